@@ -1,13 +1,44 @@
 // CLI parsing and command routing 
 
-use clap::{Command, Arg};
+use clap::{ArgMatches, Command, Arg, ArgAction};
+use std::env;
 use std::io;
+use std::path::{Path, PathBuf};
 use crate::cobra::commands;
+use crate::cobra::core::diff::Algorithm;
+use crate::cobra::core::events::NoopObserver;
+use crate::cobra::core::workspace::ApplyOptions;
+use crate::cobra::messages;
+use crate::cobra::state::{CommandContext, GlobalOpts, OutputHandle};
+use crate::cobra::utils::{date, regex::Regex};
+use crate::cobra::utils::trace;
 
 pub fn run() -> io::Result<()> {
     let matches = Command::new("cobra")
         .version("1.0")
         .about("A Git-like version control system")
+        .arg(
+            Arg::new("work-tree")
+                .help("Use <path> as the working tree instead of the repository directory")
+                .long("work-tree")
+                .value_name("path")
+                .global(true)
+        )
+        .arg(
+            Arg::new("directory")
+                .help("Run as if cobra was started in <path>")
+                .short('C')
+                .value_name("path")
+                .global(true)
+        )
+        .arg(
+            Arg::new("quiet")
+                .help("Only print requested output, no progress notes")
+                .short('q')
+                .long("quiet")
+                .action(ArgAction::SetTrue)
+                .global(true)
+        )
         .subcommand(
             Command::new("init")
                 .about("Initialize a new repository")
@@ -16,19 +47,146 @@ pub fn run() -> io::Result<()> {
                         .help("Path to initialize repository in")
                         .default_value(".")
                 )
+                .arg(
+                    Arg::new("template")
+                        .help("Copy hooks and other files from this directory into the new repository")
+                        .long("template")
+                        .value_name("dir")
+                )
         )
         .subcommand(
             Command::new("add")
                 .about("Add file contents to the index")
                 .arg(
                     Arg::new("file")
-                        .help("File to add")
+                        .help("Files to add")
+                        .required_unless_present_any(["all", "renormalize"])
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("all")
+                        .help("Also stage deletions; without paths, stage the whole work tree")
+                        .short('A')
+                        .long("all")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("renormalize")
+                        .help("Add every tracked file again, applying the current text and eol attributes")
+                        .long("renormalize")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("rm")
+                .about("Remove files from the index and the work tree")
+                .arg(
+                    Arg::new("file")
+                        .help("Files to remove")
                         .required(true)
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("cached")
+                        .help("Only remove the files from the index, keeping them in the work tree")
+                        .long("cached")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("Remove files even if they have changes that are not committed")
+                        .short('f')
+                        .long("force")
+                        .action(ArgAction::SetTrue)
                 )
         )
         .subcommand(
-            Command::new("commit")
-                .about("Record changes to the repository")
+            Command::new("mv")
+                .about("Move or rename a tracked file or directory")
+                .arg(
+                    Arg::new("source")
+                        .help("Tracked file or directory to move")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("destination")
+                        .help("New path, or an existing directory to move it into")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("Replace the destination file if it exists")
+                        .short('f')
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("reset")
+                .about("Move the current branch to another commit")
+                .arg(
+                    Arg::new("commit")
+                        .help("Commit to reset to (default: HEAD)")
+                )
+                .arg(
+                    Arg::new("soft")
+                        .help("Only move the branch, keeping the index and work tree")
+                        .long("soft")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["mixed", "hard"])
+                )
+                .arg(
+                    Arg::new("mixed")
+                        .help("Also reset the index (the default)")
+                        .long("mixed")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("hard")
+                )
+                .arg(
+                    Arg::new("hard")
+                        .help("Also reset the index and the tracked files in the work tree")
+                        .long("hard")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("import-tar")
+                .about("Commit the contents of a tar archive without touching the work tree")
+                .arg(
+                    Arg::new("file")
+                        .help("Tar archive to import, optionally gzip-compressed")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("branch")
+                        .help("Branch to commit onto (default: the current branch)")
+                        .short('b')
+                        .long("branch")
+                        .value_name("name")
+                )
+                .arg(
+                    Arg::new("message")
+                        .help("Commit message")
+                        .short('m')
+                        .long("message")
+                        .required(true)
+                )
+        )
+        .subcommand(
+            Command::new("import-dir")
+                .about("Commit the contents of a directory without touching the work tree")
+                .arg(
+                    Arg::new("path")
+                        .help("Directory to import")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("branch")
+                        .help("Branch to commit onto (default: the current branch)")
+                        .short('b')
+                        .long("branch")
+                        .value_name("name")
+                )
                 .arg(
                     Arg::new("message")
                         .help("Commit message")
@@ -37,21 +195,566 @@ pub fn run() -> io::Result<()> {
                         .required(true)
                 )
         )
+        .subcommand(
+            Command::new("restore")
+                .about("Restore work tree files from the index, or index entries from HEAD")
+                .arg(
+                    Arg::new("file")
+                        .help("Files or directories to restore")
+                        .required(true)
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("staged")
+                        .help("Reset the index entries to HEAD, unstaging their changes")
+                        .short('S')
+                        .long("staged")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("worktree")
+                        .help("Restore the work tree files (the default without --staged)")
+                        .short('W')
+                        .long("worktree")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("source")
+                        .help("Restore from this commit instead of the index, or of HEAD with --staged")
+                        .short('s')
+                        .long("source")
+                )
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("Merge a branch into the current branch, or abort a merge that stopped on conflicts")
+                .arg(
+                    Arg::new("name")
+                        .help("Name of the branch to merge")
+                        .required_unless_present("abort")
+                )
+                .args(merge_args())
+                .arg(
+                    Arg::new("abort")
+                        .help("Give up on the merge in progress, restoring the work tree and index from before it")
+                        .long("abort")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["name", "ff-only", "no-ff", "signoff"])
+                )
+        )
+        .subcommand(
+            Command::new("rebase")
+                .about("Reapply the current branch's commits on top of another base")
+                .arg(
+                    Arg::new("upstream")
+                        .help("Branch or commit to rebase onto")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("interactive")
+                        .help("Edit the list of commits to replay: pick, reword, squash, fixup or drop each one")
+                        .short('i')
+                        .long("interactive")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("reflog")
+                .about("Show where a ref has pointed, newest first")
+                .arg(
+                    Arg::new("ref")
+                        .help("Ref whose log to show, such as main or refs/remotes/origin/main (default: HEAD)")
+                )
+        )
+        .subcommand(
+            Command::new("switch")
+                .about("Switch to a branch, or check out a commit with a detached HEAD")
+                .arg(
+                    Arg::new("name")
+                        .help("Branch to switch to, a commit to detach HEAD at, or - for the previous branch")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("start-point")
+                        .help("With -c, revision to start the new branch at (defaults to HEAD)")
+                        .requires("create")
+                )
+                .arg(
+                    Arg::new("create")
+                        .help("Create the branch and switch to it")
+                        .short('c')
+                        .long("create")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("Discard local changes to tracked files")
+                        .short('f')
+                        .long("force")
+                        .visible_alias("discard-changes")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("checkout")
+                .about("Switch branches, or restore files from a commit leaving HEAD alone")
+                .override_usage("cobra checkout [-b] <branch> [<start-point>]\n       cobra checkout <commit> -- <path>...")
+                .arg(
+                    Arg::new("target")
+                        .help("Branch to switch to, or the commit to take the files from")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("start-point")
+                        .help("With -b, revision to start the new branch at (defaults to HEAD)")
+                        .requires("create")
+                )
+                .arg(
+                    Arg::new("paths")
+                        .help("Files or directories to restore")
+                        .num_args(1..)
+                        .last(true)
+                )
+                .arg(
+                    Arg::new("create")
+                        .help("Create the branch and switch to it")
+                        .short('b')
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("paths")
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("Discard local changes to tracked files")
+                        .short('f')
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("paths")
+                )
+        )
+        .subcommand(
+            Command::new("ls-files")
+                .about("Show the files in the index")
+                .arg(
+                    Arg::new("eol")
+                        .help("Show the line endings of the staged and work tree files and their attributes")
+                        .long("eol")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("stage")
+                        .help("Show the mode, object name and stage number of each entry")
+                        .short('s')
+                        .long("stage")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("null")
+                        .help("End each line with NUL instead of a newline")
+                        .short('z')
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("commit")
+                .about("Record changes to the repository")
+                .arg(
+                    Arg::new("message")
+                        .help("Commit message; given several times, each is its own paragraph (otherwise the prepared message when concluding a merge, the old one with --amend, or written in the editor)")
+                        .short('m')
+                        .long("message")
+                        .action(ArgAction::Append)
+                        .conflicts_with("file")
+                )
+                .arg(
+                    Arg::new("file")
+                        .help("Take the commit message from a file, or from standard input with -")
+                        .short('F')
+                        .long("file")
+                        .value_name("file")
+                )
+                .arg(
+                    Arg::new("no-verify")
+                        .help("Skip the pre-commit hook")
+                        .short('n')
+                        .long("no-verify")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("no-validate")
+                        .help("Write the commit even if its header fields are malformed")
+                        .long("no-validate")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("signoff")
+                        .help("Add a Signed-off-by trailer for the committer")
+                        .short('s')
+                        .long("signoff")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("all")
+                        .help("Stage every modified or deleted tracked file first; untracked files are left out")
+                        .short('a')
+                        .long("all")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("amend")
+                        .help("Replace the last commit, keeping its author and, without -m, its message")
+                        .long("amend")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .help("Show the staged diff below the message in the editor, overriding commit.verbose")
+                        .short('v')
+                        .long("verbose")
+                        .action(ArgAction::SetTrue)
+                )
+        )
         .subcommand(
             Command::new("log")
                 .about("Show commit logs")
+                .arg(
+                    Arg::new("pickaxe")
+                        .help("Only show commits that add or remove occurrences of a string")
+                        .short('S')
+                        .value_name("string")
+                )
+                .arg(
+                    Arg::new("pickaxe-regex")
+                        .help("Only show commits whose added or removed lines match a regex")
+                        .short('G')
+                        .value_name("regex")
+                )
+                .args(diff_algorithm_args())
+                .arg(
+                    Arg::new("all")
+                        .help("Start from HEAD and every branch, tag and remote-tracking ref")
+                        .long("all")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("branches")
+                        .help("Start from every branch, or those matching the pattern")
+                        .long("branches")
+                        .value_name("pattern")
+                        .num_args(0..=1)
+                        .require_equals(true)
+                        .default_missing_value("*")
+                )
+                .arg(
+                    Arg::new("tags")
+                        .help("Start from every tag, or those matching the pattern")
+                        .long("tags")
+                        .value_name("pattern")
+                        .num_args(0..=1)
+                        .require_equals(true)
+                        .default_missing_value("*")
+                )
+                .args(change_format_args())
+                .arg(
+                    Arg::new("author")
+                        .help("Only show commits whose author name or email contains the pattern")
+                        .long("author")
+                        .value_name("pattern")
+                )
+                .arg(
+                    Arg::new("since")
+                        .help("Only show commits authored at or after a date (YYYY-MM-DD, a timestamp or \"2 weeks ago\")")
+                        .long("since")
+                        .value_name("date")
+                )
+                .arg(
+                    Arg::new("until")
+                        .help("Only show commits authored at or before a date")
+                        .long("until")
+                        .value_name("date")
+                )
+                .arg(
+                    Arg::new("graph")
+                        .help("Draw the branch and merge structure beside the commits")
+                        .long("graph")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("max-count")
+                        .help("Stop after showing this many commits")
+                        .short('n')
+                        .long("max-count")
+                        .value_name("count")
+                        .value_parser(clap::value_parser!(usize))
+                )
+                .arg(
+                    Arg::new("revisions")
+                        .help("Commits or ranges to show, such as A..B or A...B (defaults to HEAD)")
+                        .num_args(0..)
+                )
+                .arg(
+                    Arg::new("paths")
+                        .help("Only show commits that change these paths")
+                        .num_args(1..)
+                        .last(true)
+                )
+        )
+        .subcommand(
+            Command::new("blame")
+                .about("Show what commit last changed each line of a file")
+                .arg(
+                    Arg::new("file")
+                        .help("File to annotate, relative to the work tree root")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("rev")
+                        .help("Annotate the file as of this commit (defaults to HEAD)")
+                )
+                .arg(
+                    Arg::new("incremental")
+                        .help("Stream machine readable records as each commit is finished")
+                        .long("incremental")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("moves")
+                        .help("Follow renames and detect lines moved within the file")
+                        .short('M')
+                        .long("find-renames")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    // git spells this -C, which cobra reserves for the global --directory
+                    Arg::new("copies")
+                        .help("Detect lines moved or copied from files changed in the same commit; twice to search every file")
+                        .long("find-copies")
+                        .action(ArgAction::Count)
+                )
+                .arg(
+                    Arg::new("find-copies-harder")
+                        .help("Search every file of the parent commit for copied lines")
+                        .long("find-copies-harder")
+                        .action(ArgAction::SetTrue)
+                )
+                .args(diff_algorithm_args())
+        )
+        .subcommand(
+            Command::new("hash-object")
+                .about("Compute the object name of a file's content, optionally storing it")
+                .arg(
+                    Arg::new("file")
+                        .help("File to hash")
+                        .required_unless_present("stdin")
+                        .conflicts_with("stdin")
+                )
+                .arg(
+                    Arg::new("stdin")
+                        .help("Read the content from standard input")
+                        .long("stdin")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("type")
+                        .help("Object type: blob (default), tree or commit")
+                        .short('t')
+                        .value_name("type")
+                )
+                .arg(
+                    Arg::new("write")
+                        .help("Write the object into the object store")
+                        .short('w')
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("rev-parse")
+                .about("Print the full object name of a revision or abbreviated hash")
+                .arg(
+                    Arg::new("rev")
+                        .help("Revision, ref or object name prefix (at least 4 digits)")
+                        .required(true)
+                )
+        )
+        .subcommand(
+            Command::new("ls-tree")
+                .about("List the entries of a tree, or of a commit's tree")
+                .arg(
+                    Arg::new("tree-ish")
+                        .help("Tree or commit to list")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("recursive")
+                        .help("Recurse into subtrees, listing files by their full path")
+                        .short('r')
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("name-only")
+                        .help("Show only the paths")
+                        .long("name-only")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Show a commit with its changes, or a tree or blob")
+                .arg(
+                    Arg::new("object")
+                        .help("Commit, tree or blob to show (defaults to HEAD)")
+                )
+                .args(change_format_args())
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show unstaged changes, or staged ones with --staged")
+                .arg(
+                    Arg::new("rev")
+                        .help("Compare the working tree against this branch or commit (e.g. HEAD)")
+                )
+                .arg(
+                    Arg::new("paths")
+                        .help("Limit the diff to these paths (after `--` if the first could name a revision)")
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("cached")
+                        .help("Compare the index against HEAD or the given commit")
+                        .long("cached")
+                        .visible_alias("staged")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("ignore-all-space")
+                        .help("Ignore whitespace when comparing lines")
+                        .short('w')
+                        .long("ignore-all-space")
+                        .action(ArgAction::SetTrue)
+                )
+                .args(diff_algorithm_args())
+        )
+        .subcommand(
+            Command::new("difftool")
+                .about("Show changes in an external diff tool")
+                .arg(
+                    Arg::new("rev")
+                        .help("Compare against this branch or commit instead of the index")
+                )
+                .arg(
+                    Arg::new("paths")
+                        .help("Limit the comparison to these paths")
+                        .num_args(1..)
+                        .last(true)
+                )
+                .arg(
+                    Arg::new("cached")
+                        .help("Compare the index against HEAD or the given commit")
+                        .long("cached")
+                        .visible_alias("staged")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("dir-diff")
+                        .help("Launch the tool once on before and after directory trees")
+                        .short('d')
+                        .long("dir-diff")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("tool")
+                        .help("Tool to use instead of diff.tool")
+                        .short('t')
+                        .long("tool")
+                )
+                .arg(
+                    Arg::new("no-prompt")
+                        .help("Do not ask before launching the tool for each file")
+                        .short('y')
+                        .long("no-prompt")
+                        .action(ArgAction::SetTrue)
+                )
         )
         .subcommand(
             Command::new("status")
                 .about("Show the working tree status")
+                .arg(
+                    Arg::new("short")
+                        .help("Give the output in the short format")
+                        .short('s')
+                        .long("short")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("porcelain")
+                        .help("Machine readable output that stays stable across versions (v1 or v2)")
+                        .long("porcelain")
+                        .num_args(0..=1)
+                        .require_equals(true)
+                        .default_missing_value("v1")
+                )
+                .arg(
+                    Arg::new("branch")
+                        .help("Show branch information")
+                        .short('b')
+                        .long("branch")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("null")
+                        .help("Terminate entries with NUL instead of newline")
+                        .short('z')
+                        .action(ArgAction::SetTrue)
+                )
         )
         .subcommand(
             Command::new("branch")
                 .about("List, create, or delete branches")
+                .args_conflicts_with_subcommands(true)
+                .arg(
+                    Arg::new("move")
+                        .help("Rename the current branch")
+                        .short('m')
+                        .long("move")
+                        .value_name("new-name")
+                        .conflicts_with("force-move")
+                )
+                .arg(
+                    Arg::new("force-move")
+                        .help("Rename the current branch, replacing an existing branch with the new name")
+                        .short('M')
+                        .value_name("new-name")
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .help("List branches with the subject line of each tip commit")
+                        .short('v')
+                        .long("verbose")
+                        .action(ArgAction::SetTrue)
+                )
                 .subcommand(
                     Command::new("list")
                         .about("List all branches")
                         .alias("ls")
+                        .arg(
+                            Arg::new("verbose")
+                                .help("Show the subject line of each branch's tip commit")
+                                .short('v')
+                                .long("verbose")
+                                .action(ArgAction::SetTrue)
+                        )
+                        .arg(
+                            Arg::new("merged")
+                                .help("Only list branches whose tip HEAD already contains")
+                                .long("merged")
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with("no-merged")
+                        )
+                        .arg(
+                            Arg::new("no-merged")
+                                .help("Only list branches with commits HEAD does not contain")
+                                .long("no-merged")
+                                .action(ArgAction::SetTrue)
+                        )
                 )
                 .subcommand(
                     Command::new("create")
@@ -61,15 +764,80 @@ pub fn run() -> io::Result<()> {
                                 .help("Name of the branch to create")
                                 .required(true)
                         )
+                        .arg(
+                            Arg::new("start-point")
+                                .help("Revision to start the new branch at, e.g. a tag or HEAD~2 (defaults to HEAD)")
+                        )
                 )
                 .subcommand(
                     Command::new("checkout")
-                        .about("Switch to a branch")
+                        .about("Switch to a branch, or check out a commit with a detached HEAD")
                         .arg(
                             Arg::new("name")
-                                .help("Name of the branch to switch to")
+                                .help("Name of the branch to switch to, or a commit to detach HEAD at")
+                                .required(true)
+                        )
+                        .arg(
+                            Arg::new("start-point")
+                                .help("With -b, revision to start the new branch at (defaults to HEAD)")
+                                .requires("create")
+                        )
+                        .arg(
+                            Arg::new("create")
+                                .help("Create the branch and switch to it")
+                                .short('b')
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with("orphan")
+                        )
+                        .arg(
+                            Arg::new("orphan")
+                                .help("Start a new branch with no history; its first commit has no parents")
+                                .long("orphan")
+                                .action(ArgAction::SetTrue)
+                        )
+                        .arg(
+                            Arg::new("keep")
+                                .help("With --orphan, keep the index and work tree instead of emptying them")
+                                .long("keep")
+                                .requires("orphan")
+                                .action(ArgAction::SetTrue)
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .help("Discard local changes to tracked files")
+                                .short('f')
+                                .long("force")
+                                .visible_alias("discard-changes")
+                                .action(ArgAction::SetTrue)
+                        )
+                        .arg(
+                            Arg::new("verbose")
+                                .help("List the files and directories removed by the switch")
+                                .short('v')
+                                .long("verbose")
+                                .action(ArgAction::SetTrue)
+                        )
+                )
+                .subcommand(
+                    Command::new("rename")
+                        .about("Rename a branch")
+                        .arg(
+                            Arg::new("old")
+                                .help("Current name of the branch")
+                                .required(true)
+                        )
+                        .arg(
+                            Arg::new("new")
+                                .help("New name for the branch")
                                 .required(true)
                         )
+                        .arg(
+                            Arg::new("force")
+                                .help("Replace an existing branch with the new name")
+                                .short('f')
+                                .long("force")
+                                .action(ArgAction::SetTrue)
+                        )
                 )
                 .subcommand(
                     Command::new("delete")
@@ -88,6 +856,7 @@ pub fn run() -> io::Result<()> {
                                 .help("Name of the branch to merge")
                                 .required(true)
                         )
+                        .args(merge_args())
                 )
                 .subcommand(
                     Command::new("rebase")
@@ -99,6 +868,243 @@ pub fn run() -> io::Result<()> {
                         )
                 )
         )
+        .subcommand(
+            Command::new("show-branch")
+                .about("Show branches and the commits unique to each")
+                .arg(
+                    Arg::new("branches")
+                        .help("Branches to compare (defaults to all local branches)")
+                        .num_args(0..)
+                )
+                .arg(
+                    Arg::new("ahead-behind")
+                        .help("Show how far each branch is ahead of and behind the current branch")
+                        .long("ahead-behind")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("limit")
+                        .help("Show at most this many commits")
+                        .short('n')
+                        .long("limit")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("50")
+                )
+        )
+        .subcommand(
+            Command::new("cherry")
+                .about("Find commits not yet applied upstream")
+                .arg(
+                    Arg::new("upstream")
+                        .help("Branch the commits would be merged into")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("head")
+                        .help("Branch holding the commits (defaults to HEAD)")
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .help("Show commit subjects")
+                        .short('v')
+                        .long("verbose")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("request-pull")
+                .about("Summarize changes for a pull request")
+                .arg(
+                    Arg::new("start")
+                        .help("Commit the changes are based on")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("url")
+                        .help("Repository to fetch the changes from")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("end")
+                        .help("Last commit to include (defaults to HEAD)")
+                )
+        )
+        .subcommand(
+            Command::new("tag")
+                .about("Create, list, or delete tags")
+                .args_conflicts_with_subcommands(true)
+                .arg(
+                    Arg::new("name")
+                        .help("Tag the current HEAD commit with this name")
+                )
+                .arg(
+                    Arg::new("delete")
+                        .help("Delete the named tag")
+                        .short('d')
+                        .long("delete")
+                        .requires("name")
+                        .action(ArgAction::SetTrue)
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List tags, optionally filtered by glob patterns")
+                        .alias("ls")
+                        .arg(
+                            Arg::new("patterns")
+                                .help("Only list tags matching these patterns (e.g. 'v1.*')")
+                                .num_args(0..)
+                        )
+                        .arg(
+                            Arg::new("sort")
+                                .help("Sort by refname or version:refname, prefix with '-' to reverse")
+                                .long("sort")
+                                .value_name("key")
+                                .default_value("refname")
+                        )
+                        .arg(
+                            Arg::new("contains")
+                                .help("Only list tags whose history contains this commit")
+                                .long("contains")
+                                .value_name("commit")
+                        )
+                        .arg(
+                            Arg::new("lines")
+                                .help("Print the first <n> lines of each tag's message (default 1)")
+                                .short('n')
+                                .value_name("n")
+                                .num_args(0..=1)
+                                .default_missing_value("1")
+                                .value_parser(clap::value_parser!(usize))
+                        )
+                )
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Get, set or edit the repository or global configuration")
+                .arg(
+                    Arg::new("key")
+                        .help("The key to read or set, as section.name")
+                        .conflicts_with_all(["edit", "list"])
+                )
+                .arg(
+                    Arg::new("value")
+                        .help("Set the key to this value instead of printing it")
+                        .conflicts_with("unset")
+                )
+                .arg(
+                    Arg::new("list")
+                        .help("List every key and value")
+                        .short('l')
+                        .long("list")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("edit")
+                )
+                .arg(
+                    Arg::new("show-origin")
+                        .help("Show the file each printed value comes from")
+                        .long("show-origin")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("unset")
+                        .help("Remove the key")
+                        .long("unset")
+                        .action(ArgAction::SetTrue)
+                        .requires("key")
+                )
+                .arg(
+                    Arg::new("edit")
+                        .help("Open the config file in an editor")
+                        .short('e')
+                        .long("edit")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("global")
+                        .help("Use the per-user config (COBRA_CONFIG_GLOBAL, $XDG_CONFIG_HOME/cobra/config or ~/.cobraconfig) instead of the repository's")
+                        .long("global")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("fsck")
+                .about("Verify the object store and list dangling objects")
+                .arg(
+                    Arg::new("lost-found")
+                        .help("Write dangling objects into .cobra/lost-found")
+                        .long("lost-found")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("fix-placement")
+                        .help("Move objects stored under the wrong name to where they belong")
+                        .long("fix-placement")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("reconstruct-refs")
+                        .help("Recreate branches for history no ref points at, naming them from surviving reflogs")
+                        .long("reconstruct-refs")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("yes")
+                        .help("Write the reconstructed refs without asking")
+                        .short('y')
+                        .long("yes")
+                        .requires("reconstruct-refs")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("verify-index")
+                .about("Report index entries whose stat data no longer matches the work tree")
+                .arg(
+                    Arg::new("realign")
+                        .help("Record the current stat data of files whose content is unchanged")
+                        .long("realign")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("gc")
+                .about("Expire old reflog entries and remove unreachable objects")
+                .arg(
+                    Arg::new("prune")
+                        .help("Prune unreachable objects older than this date (e.g. \"now\", \"2 weeks ago\")")
+                        .long("prune")
+                        .value_name("date")
+                        .require_equals(true)
+                )
+        )
+        .subcommand(
+            Command::new("fetch")
+                .about("Download objects and refs from another repository")
+                .arg(
+                    Arg::new("repository")
+                        .help("URL (tcp://host[:port]/path) or name of a configured remote")
+                        .required(true)
+                )
+        )
+        .subcommand(
+            Command::new("daemon")
+                .about("Serve repositories read-only over the git protocol")
+                .arg(
+                    Arg::new("listen")
+                        .help("Address to listen on")
+                        .long("listen")
+                        .value_name("addr")
+                        .default_value("127.0.0.1:9418")
+                )
+                .arg(
+                    Arg::new("export")
+                        .help("Repository that clients may fetch from (repeatable)")
+                        .long("export")
+                        .value_name("path")
+                        .action(ArgAction::Append)
+                        .required(true)
+                )
+        )
         .subcommand(
             Command::new("stash")
                 .about("Stash changes in a dirty working directory")
@@ -124,6 +1130,12 @@ pub fn run() -> io::Result<()> {
                                 .help("Stash reference (e.g., stash@{0})")
                                 .default_value("stash@{0}")
                         )
+                        .arg(
+                            Arg::new("stat")
+                                .help("Show changed line counts per file instead of the patch")
+                                .long("stat")
+                                .action(ArgAction::SetTrue)
+                        )
                 )
                 .subcommand(
                     Command::new("apply")
@@ -133,6 +1145,27 @@ pub fn run() -> io::Result<()> {
                                 .help("Stash reference (e.g., stash@{0})")
                                 .default_value("stash@{0}")
                         )
+                        .arg(
+                            Arg::new("preserve-mtime")
+                                .help("Give restored files their modification times from when they were stashed (also core.preserveMtime)")
+                                .long("preserve-mtime")
+                                .action(ArgAction::SetTrue)
+                        )
+                )
+                .subcommand(
+                    Command::new("pop")
+                        .about("Apply a stash and remove it from the stash list")
+                        .arg(
+                            Arg::new("stash")
+                                .help("Stash reference (e.g., stash@{0})")
+                                .default_value("stash@{0}")
+                        )
+                        .arg(
+                            Arg::new("preserve-mtime")
+                                .help("Give restored files their modification times from when they were stashed (also core.preserveMtime)")
+                                .long("preserve-mtime")
+                                .action(ArgAction::SetTrue)
+                        )
                 )
                 .subcommand(
                     Command::new("drop")
@@ -146,86 +1179,577 @@ pub fn run() -> io::Result<()> {
         )
         .get_matches();
 
-    match matches.subcommand() {
+    let opts = GlobalOpts {
+        quiet: matches.get_flag("quiet"),
+        directory: matches.get_one::<String>("directory").map(PathBuf::from),
+        // The flag wins over the environment
+        work_tree: matches.get_one::<String>("work-tree")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("COBRA_WORK_TREE").map(PathBuf::from)),
+    };
+    if let Some(directory) = &opts.directory {
+        env::set_current_dir(directory)?;
+    }
+
+    let result = match matches.subcommand() {
         Some(("init", sub_matches)) => {
             let path = sub_matches.get_one::<String>("path").unwrap();
-            commands::init::run(path)
+            let template = sub_matches.get_one::<String>("template").map(|s| s.as_str());
+            commands::init::run(&mut OutputHandle::stdout(opts.quiet), path, template)
+        },
+        Some(("config", sub_matches)) => {
+            let options = commands::config::ConfigOptions {
+                edit: sub_matches.get_flag("edit"),
+                global: sub_matches.get_flag("global"),
+                list: sub_matches.get_flag("list"),
+                show_origin: sub_matches.get_flag("show-origin"),
+                unset: sub_matches.get_flag("unset"),
+                key: sub_matches.get_one::<String>("key").cloned(),
+                value: sub_matches.get_one::<String>("value").cloned(),
+            };
+            commands::config::run(&mut OutputHandle::stdout(opts.quiet), &options)
+        },
+        Some(("daemon", sub_matches)) => {
+            let listen = sub_matches.get_one::<String>("listen").unwrap();
+            let exports: Vec<PathBuf> = sub_matches.get_many::<String>("export")
+                .map(|exports| exports.map(PathBuf::from).collect())
+                .unwrap_or_default();
+            commands::daemon::run(&mut OutputHandle::stdout(opts.quiet), listen, &exports)
+        },
+        Some((name, sub_matches)) => {
+            CommandContext::open(opts).and_then(|mut ctx| {
+                for warning in &ctx.repo.warnings {
+                    eprintln!("{}", warning);
+                }
+                ctx.repo.subscribe(Box::new(NoopObserver));
+                dispatch(&mut ctx, name, sub_matches)
+            })
+        },
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, messages::missing_subcommand("cobra"))),
+    };
+
+    trace::summary();
+    result
+}
+
+/// Runs a command that works on an existing repository
+fn dispatch(ctx: &mut CommandContext, name: &str, sub_matches: &ArgMatches) -> io::Result<()> {
+    match (name, sub_matches) {
+        ("add", sub_matches) => {
+            let files: Vec<String> = sub_matches.get_many::<String>("file")
+                .map(|files| files.cloned().collect())
+                .unwrap_or_default();
+            let options = commands::add::AddOptions {
+                all: sub_matches.get_flag("all"),
+                renormalize: sub_matches.get_flag("renormalize"),
+            };
+            commands::add::run(ctx, &files, &options)
+        },
+        ("rm", sub_matches) => {
+            let files: Vec<String> = sub_matches.get_many::<String>("file").unwrap().cloned().collect();
+            let options = commands::rm::RmOptions {
+                cached: sub_matches.get_flag("cached"),
+                force: sub_matches.get_flag("force"),
+            };
+            commands::rm::run(ctx, &files, &options)
+        },
+        ("mv", sub_matches) => {
+            let options = commands::mv::MvOptions {
+                force: sub_matches.get_flag("force"),
+            };
+            commands::mv::run(
+                ctx,
+                sub_matches.get_one::<String>("source").unwrap(),
+                sub_matches.get_one::<String>("destination").unwrap(),
+                &options,
+            )
+        },
+        ("reset", sub_matches) => {
+            let mode = if sub_matches.get_flag("soft") {
+                commands::reset::ResetMode::Soft
+            } else if sub_matches.get_flag("hard") {
+                commands::reset::ResetMode::Hard
+            } else {
+                commands::reset::ResetMode::Mixed
+            };
+            let target = sub_matches.get_one::<String>("commit").map(|s| s.as_str());
+            commands::reset::run(ctx, target, &commands::reset::ResetOptions { mode })
+        },
+        ("import-tar", sub_matches) => {
+            let file = sub_matches.get_one::<String>("file").unwrap();
+            commands::import::run_tar(ctx, Path::new(file), &import_options(sub_matches))
+        },
+        ("import-dir", sub_matches) => {
+            let path = sub_matches.get_one::<String>("path").unwrap();
+            commands::import::run_dir(ctx, Path::new(path), &import_options(sub_matches))
+        },
+        ("restore", sub_matches) => {
+            let files: Vec<String> = sub_matches.get_many::<String>("file").unwrap().cloned().collect();
+            let options = commands::restore::RestoreOptions {
+                staged: sub_matches.get_flag("staged"),
+                worktree: sub_matches.get_flag("worktree"),
+                source: sub_matches.get_one::<String>("source").cloned(),
+            };
+            commands::restore::run(ctx, &files, &options)
+        },
+        ("merge", sub_matches) => {
+            if sub_matches.get_flag("abort") {
+                return commands::branch::abort_merge(ctx);
+            }
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            commands::branch::merge(ctx, name, &merge_options(sub_matches))
+        },
+        ("rebase", sub_matches) => {
+            let upstream = sub_matches.get_one::<String>("upstream").unwrap();
+            let options = commands::rebase::RebaseOptions {
+                interactive: sub_matches.get_flag("interactive"),
+                ..Default::default()
+            };
+            commands::rebase::run(ctx, upstream, &options)
+        },
+        ("reflog", sub_matches) => {
+            commands::reflog::run(ctx, sub_matches.get_one::<String>("ref").map(|s| s.as_str()))
+        },
+        ("switch", sub_matches) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            let options = commands::branch::SwitchOptions {
+                force: sub_matches.get_flag("force"),
+                ..Default::default()
+            };
+            if sub_matches.get_flag("create") {
+                let start_point = sub_matches.get_one::<String>("start-point");
+                return commands::branch::create_and_switch(ctx, name, start_point.map(|s| s.as_str()), &options);
+            }
+            commands::branch::switch(ctx, name, &options)
+        },
+        ("checkout", sub_matches) => {
+            let target = sub_matches.get_one::<String>("target").unwrap();
+            if let Some(paths) = sub_matches.get_many::<String>("paths") {
+                let files: Vec<String> = paths.cloned().collect();
+                let options = commands::restore::RestoreOptions {
+                    staged: true,
+                    worktree: true,
+                    source: Some(target.clone()),
+                };
+                return commands::restore::run(ctx, &files, &options);
+            }
+            let options = commands::branch::SwitchOptions {
+                force: sub_matches.get_flag("force"),
+                ..Default::default()
+            };
+            if sub_matches.get_flag("create") {
+                let start_point = sub_matches.get_one::<String>("start-point");
+                return commands::branch::create_and_switch(ctx, target, start_point.map(|s| s.as_str()), &options);
+            }
+            commands::branch::switch(ctx, target, &options)
         },
-        Some(("add", sub_matches)) => {
+        ("ls-files", sub_matches) => {
+            let options = commands::ls_files::LsFilesOptions {
+                eol: sub_matches.get_flag("eol"),
+                stage: sub_matches.get_flag("stage"),
+                null_terminated: sub_matches.get_flag("null"),
+            };
+            commands::ls_files::run(ctx, &options)
+        },
+        ("commit", sub_matches) => {
+            let message = match sub_matches.get_one::<String>("file") {
+                Some(file) => Some(commands::commit::read_message_file(file, &mut io::stdin().lock())?),
+                None => sub_matches.get_many::<String>("message").map(|values| {
+                    commands::commit::join_paragraphs(&values.map(String::as_str).collect::<Vec<_>>())
+                }),
+            };
+            let options = commands::commit::CommitOptions {
+                validate: !sub_matches.get_flag("no-validate"),
+                verify: !sub_matches.get_flag("no-verify"),
+                signoff: sub_matches.get_flag("signoff"),
+                amend: sub_matches.get_flag("amend"),
+                all: sub_matches.get_flag("all"),
+                verbose: sub_matches.get_flag("verbose").then_some(true),
+                ..Default::default()
+            };
+            commands::commit::run(ctx, message.as_deref(), &options)
+        },
+        ("log", sub_matches) => {
+            let options = commands::log::LogOptions {
+                author: sub_matches.get_one::<String>("author").cloned(),
+                since: sub_matches.get_one::<String>("since").map(|value| date::parse(value)).transpose()?,
+                until: sub_matches.get_one::<String>("until").map(|value| date::parse(value)).transpose()?,
+                pickaxe: sub_matches.get_one::<String>("pickaxe").cloned(),
+                pickaxe_regex: sub_matches.get_one::<String>("pickaxe-regex")
+                    .map(|pattern| Regex::new(pattern))
+                    .transpose()?,
+                algorithm: diff_algorithm(sub_matches)?,
+                revisions: sub_matches.get_many::<String>("revisions")
+                    .map(|revisions| revisions.cloned().collect())
+                    .unwrap_or_default(),
+                all: sub_matches.get_flag("all"),
+                branches: sub_matches.get_one::<String>("branches").cloned(),
+                tags: sub_matches.get_one::<String>("tags").cloned(),
+                paths: sub_matches.get_many::<String>("paths")
+                    .map(|paths| paths.map(PathBuf::from).collect())
+                    .unwrap_or_default(),
+                changes: change_format(sub_matches),
+                max_count: sub_matches.get_one::<usize>("max-count").copied(),
+                graph: sub_matches.get_flag("graph"),
+            };
+            commands::log::run(ctx, &options)
+        },
+        ("blame", sub_matches) => {
             let file = sub_matches.get_one::<String>("file").unwrap();
-            commands::add::run(file)
+            let options = commands::blame::BlameOptions {
+                rev: sub_matches.get_one::<String>("rev").cloned(),
+                incremental: sub_matches.get_flag("incremental"),
+                algorithm: diff_algorithm(sub_matches)?,
+                moves: sub_matches.get_flag("moves"),
+                copies: match sub_matches.get_flag("find-copies-harder") {
+                    true => 2,
+                    false => sub_matches.get_count("copies") as usize,
+                },
+            };
+            commands::blame::run(ctx, file, &options)
+        },
+        ("hash-object", sub_matches) => {
+            let options = commands::hash_object::HashObjectOptions {
+                kind: sub_matches.get_one::<String>("type").cloned(),
+                write: sub_matches.get_flag("write"),
+            };
+            match sub_matches.get_one::<String>("file") {
+                Some(file) => commands::hash_object::run(ctx, &mut std::fs::File::open(file)?, &options),
+                None => commands::hash_object::run(ctx, &mut io::stdin().lock(), &options),
+            }
+        },
+        ("rev-parse", sub_matches) => {
+            commands::rev_parse::run(ctx, sub_matches.get_one::<String>("rev").unwrap())
         },
-        Some(("commit", sub_matches)) => {
-            let message = sub_matches.get_one::<String>("message").unwrap();
-            commands::commit::run(message)
+        ("ls-tree", sub_matches) => {
+            let options = commands::ls_tree::LsTreeOptions {
+                recursive: sub_matches.get_flag("recursive"),
+                name_only: sub_matches.get_flag("name-only"),
+            };
+            commands::ls_tree::run(ctx, sub_matches.get_one::<String>("tree-ish").unwrap(), &options)
         },
-        Some(("log", _)) => {
-            commands::log::run()
+        ("show", sub_matches) => {
+            let object = sub_matches.get_one::<String>("object").map(|s| s.as_str());
+            let options = commands::show::ShowOptions { changes: change_format(sub_matches) };
+            commands::show::run(ctx, object, &options)
         },
-        Some(("status", _)) => {
-            commands::status::run()
+        ("diff", sub_matches) => {
+            let options = commands::diff::DiffOptions {
+                paths: sub_matches.get_many::<String>("paths")
+                    .map(|paths| paths.map(PathBuf::from).collect())
+                    .unwrap_or_default(),
+                ignore_all_space: sub_matches.get_flag("ignore-all-space"),
+                algorithm: diff_algorithm(sub_matches)?,
+                cached: sub_matches.get_flag("cached"),
+            };
+            let rev = sub_matches.get_one::<String>("rev").map(|s| s.as_str());
+            commands::diff::run(ctx, rev, options)
         },
-        Some(("branch", sub_matches)) => {
+        ("difftool", sub_matches) => {
+            let options = commands::difftool::DifftoolOptions {
+                paths: sub_matches.get_many::<String>("paths")
+                    .map(|paths| paths.map(PathBuf::from).collect())
+                    .unwrap_or_default(),
+                cached: sub_matches.get_flag("cached"),
+                dir_diff: sub_matches.get_flag("dir-diff"),
+                tool: sub_matches.get_one::<String>("tool").cloned(),
+                no_prompt: sub_matches.get_flag("no-prompt"),
+            };
+            let rev = sub_matches.get_one::<String>("rev").map(|s| s.as_str());
+            let mut launcher = commands::difftool::ShellLauncher { root: ctx.repo.root_path.clone() };
+            commands::difftool::run(ctx, rev, &options, &mut launcher)
+        },
+        ("status", sub_matches) => {
+            let options = commands::status::StatusOptions {
+                format: match sub_matches.get_one::<String>("porcelain") {
+                    Some(version) => commands::status::StatusFormat::from_porcelain(version)?,
+                    None if sub_matches.get_flag("short") => commands::status::StatusFormat::Short,
+                    None => commands::status::StatusFormat::Long,
+                },
+                branch: sub_matches.get_flag("branch"),
+                null_terminated: sub_matches.get_flag("null"),
+            };
+            commands::status::run(ctx, &options)
+        },
+        ("branch", sub_matches) => {
+            if let Some(new) = sub_matches.get_one::<String>("move") {
+                return commands::branch::rename(ctx, None, new, false);
+            }
+            if let Some(new) = sub_matches.get_one::<String>("force-move") {
+                return commands::branch::rename(ctx, None, new, true);
+            }
             match sub_matches.subcommand() {
-                Some(("list", _)) => {
-                    commands::branch::list()
+                Some(("list", sub_matches)) => {
+                    let merged = if sub_matches.get_flag("merged") {
+                        commands::branch::MergedFilter::Merged
+                    } else if sub_matches.get_flag("no-merged") {
+                        commands::branch::MergedFilter::NotMerged
+                    } else {
+                        commands::branch::MergedFilter::All
+                    };
+                    let options = commands::branch::ListOptions { verbose: sub_matches.get_flag("verbose"), merged };
+                    commands::branch::list(ctx, &options)
                 },
                 Some(("create", sub_matches)) => {
                     let name = sub_matches.get_one::<String>("name").unwrap();
-                    commands::branch::create(name)
+                    let start_point = sub_matches.get_one::<String>("start-point");
+                    commands::branch::create(ctx, name, start_point.map(|s| s.as_str()))
                 },
                 Some(("checkout", sub_matches)) => {
                     let name = sub_matches.get_one::<String>("name").unwrap();
-                    commands::branch::switch(name)
+                    if sub_matches.get_flag("orphan") {
+                        let options = commands::branch::OrphanOptions {
+                            keep: sub_matches.get_flag("keep"),
+                            force: sub_matches.get_flag("force"),
+                        };
+                        return commands::branch::orphan(ctx, name, &options);
+                    }
+                    let options = commands::branch::SwitchOptions {
+                        force: sub_matches.get_flag("force"),
+                        verbose: sub_matches.get_flag("verbose"),
+                    };
+                    if sub_matches.get_flag("create") {
+                        let start_point = sub_matches.get_one::<String>("start-point");
+                        return commands::branch::create_and_switch(ctx, name, start_point.map(|s| s.as_str()), &options);
+                    }
+                    commands::branch::switch(ctx, name, &options)
+                },
+                Some(("rename", sub_matches)) => {
+                    let old = sub_matches.get_one::<String>("old").unwrap();
+                    let new = sub_matches.get_one::<String>("new").unwrap();
+                    commands::branch::rename(ctx, Some(old), new, sub_matches.get_flag("force"))
                 },
                 Some(("delete", sub_matches)) => {
                     let name = sub_matches.get_one::<String>("name").unwrap();
-                    commands::branch::delete(name)
+                    commands::branch::delete(ctx, name)
                 },
                 Some(("merge", sub_matches)) => {
                     let name = sub_matches.get_one::<String>("name").unwrap();
-                    commands::branch::merge(name)
+                    commands::branch::merge(ctx, name, &merge_options(sub_matches))
                 },
                 Some(("rebase", sub_matches)) => {
                     let branch = sub_matches.get_one::<String>("branch").unwrap();
-                    commands::branch::rebase(branch)
+                    commands::branch::rebase(ctx, branch)
                 },
                 _ => {
                     // Default to list if no subcommand specified
-                    commands::branch::list()
+                    let options = commands::branch::ListOptions {
+                        verbose: sub_matches.get_flag("verbose"),
+                        ..Default::default()
+                    };
+                    commands::branch::list(ctx, &options)
                 }
             }
         },
-        Some(("stash", sub_matches)) => {
+        ("show-branch", sub_matches) => {
+            let options = commands::show_branch::ShowBranchOptions {
+                branches: sub_matches.get_many::<String>("branches")
+                    .map(|branches| branches.cloned().collect())
+                    .unwrap_or_default(),
+                ahead_behind: sub_matches.get_flag("ahead-behind"),
+                limit: *sub_matches.get_one::<usize>("limit").unwrap(),
+            };
+            commands::show_branch::run(ctx, &options)
+        },
+        ("cherry", sub_matches) => {
+            let options = commands::cherry::CherryOptions {
+                upstream: sub_matches.get_one::<String>("upstream").unwrap().clone(),
+                head: sub_matches.get_one::<String>("head").cloned(),
+                verbose: sub_matches.get_flag("verbose"),
+            };
+            commands::cherry::run(ctx, &options)
+        },
+        ("request-pull", sub_matches) => {
+            let options = commands::request_pull::RequestPullOptions {
+                start: sub_matches.get_one::<String>("start").unwrap().clone(),
+                url: sub_matches.get_one::<String>("url").unwrap().clone(),
+                end: sub_matches.get_one::<String>("end").cloned(),
+            };
+            commands::request_pull::run(ctx, &options)
+        },
+        ("tag", sub_matches) => {
+            match sub_matches.subcommand() {
+                Some(("list", sub_matches)) => {
+                    let options = commands::tag::TagListOptions {
+                        patterns: sub_matches.get_many::<String>("patterns")
+                            .map(|patterns| patterns.cloned().collect())
+                            .unwrap_or_default(),
+                        sort: commands::tag::TagSort::parse(sub_matches.get_one::<String>("sort").unwrap())?,
+                        contains: sub_matches.get_one::<String>("contains").cloned(),
+                        lines: sub_matches.get_one::<usize>("lines").copied(),
+                    };
+                    commands::tag::list(ctx, &options)
+                },
+                _ => match sub_matches.get_one::<String>("name") {
+                    Some(name) if sub_matches.get_flag("delete") => commands::tag::delete(ctx, name),
+                    Some(name) => commands::tag::create(ctx, name),
+                    None => commands::tag::list(ctx, &commands::tag::TagListOptions::default()),
+                },
+            }
+        },
+        ("fsck", sub_matches) => {
+            let options = commands::fsck::FsckOptions {
+                lost_found: sub_matches.get_flag("lost-found"),
+                fix_placement: sub_matches.get_flag("fix-placement"),
+                reconstruct_refs: sub_matches.get_flag("reconstruct-refs"),
+                yes: sub_matches.get_flag("yes"),
+            };
+            commands::fsck::run(ctx, &options)
+        },
+        ("fetch", sub_matches) => {
+            commands::fetch::run(ctx, sub_matches.get_one::<String>("repository").unwrap())
+        },
+        ("verify-index", sub_matches) => {
+            commands::verify_index::run(ctx, sub_matches.get_flag("realign"))
+        },
+        ("gc", sub_matches) => {
+            commands::gc::run(ctx, sub_matches.get_one::<String>("prune").map(|s| s.as_str()))
+        },
+        ("stash", sub_matches) => {
             match sub_matches.subcommand() {
                 Some(("push", sub_matches)) => {
                     let message = sub_matches.get_one::<String>("message");
-                    commands::stash::push(message)
+                    commands::stash::push(ctx, message)
                 },
                 Some(("list", _)) => {
-                    commands::stash::list()
+                    commands::stash::list(ctx)
                 },
                 Some(("show", sub_matches)) => {
                     let stash = sub_matches.get_one::<String>("stash").unwrap();
-                    commands::stash::show(stash)
+                    let options = commands::stash::ShowOptions { stat: sub_matches.get_flag("stat") };
+                    commands::stash::show(ctx, stash, &options)
                 },
                 Some(("apply", sub_matches)) => {
                     let stash = sub_matches.get_one::<String>("stash").unwrap();
-                    commands::stash::apply(stash)
+                    let options = ApplyOptions {
+                        preserve_mtime: sub_matches.get_flag("preserve-mtime"),
+                        ..Default::default()
+                    };
+                    commands::stash::apply(ctx, stash, &options)
+                },
+                Some(("pop", sub_matches)) => {
+                    let stash = sub_matches.get_one::<String>("stash").unwrap();
+                    let options = ApplyOptions {
+                        preserve_mtime: sub_matches.get_flag("preserve-mtime"),
+                        ..Default::default()
+                    };
+                    commands::stash::pop(ctx, stash, &options)
                 },
                 Some(("drop", sub_matches)) => {
                     let stash = sub_matches.get_one::<String>("stash").unwrap();
-                    commands::stash::drop(stash)
+                    commands::stash::drop(ctx, stash)
                 },
-                _ => {
-                    println!("No stash subcommand was used");
-                    Ok(())
-                }
+                _ => Err(io::Error::new(io::ErrorKind::InvalidInput, messages::missing_subcommand("cobra stash"))),
             }
         },
-        _ => {
-            println!("No subcommand was used");
-            Ok(())
-        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, messages::missing_subcommand("cobra"))),
+    }
+}
+
+/// `--branch` and `-m` shared by import-tar and import-dir
+fn import_options(matches: &ArgMatches) -> commands::import::ImportOptions {
+    commands::import::ImportOptions {
+        branch: matches.get_one::<String>("branch").cloned(),
+        message: matches.get_one::<String>("message").unwrap().clone(),
+    }
+}
+
+/// The fast-forward and sign-off flags shared by merge and branch merge
+fn merge_args() -> [Arg; 4] {
+    [
+        Arg::new("ff")
+            .help("Fast-forward when possible, overriding merge.ff")
+            .long("ff")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["ff-only", "no-ff"]),
+        Arg::new("ff-only")
+            .help("Refuse to merge unless the branch can be fast-forwarded")
+            .long("ff-only")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("no-ff"),
+        Arg::new("no-ff")
+            .help("Create a merge commit even when a fast-forward is possible")
+            .long("no-ff")
+            .action(ArgAction::SetTrue),
+        Arg::new("signoff")
+            .help("Add a Signed-off-by trailer for the committer to the merge commit")
+            .long("signoff")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+fn merge_options(matches: &ArgMatches) -> commands::branch::MergeOptions {
+    // Without a flag the default comes from merge.ff
+    let fast_forward = if matches.get_flag("ff-only") {
+        Some(commands::branch::FastForward::Only)
+    } else if matches.get_flag("no-ff") {
+        Some(commands::branch::FastForward::Never)
+    } else if matches.get_flag("ff") {
+        Some(commands::branch::FastForward::Allow)
+    } else {
+        None
+    };
+    commands::branch::MergeOptions {
+        fast_forward,
+        signoff: matches.get_flag("signoff"),
+    }
+}
+
+/// `--name-status` and `--raw`, shared by log and show
+fn change_format_args() -> [Arg; 2] {
+    [
+        Arg::new("name-status")
+            .help("List the files each commit changed with their status")
+            .long("name-status")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("raw"),
+        Arg::new("raw")
+            .help("List the files each commit changed with modes and object names")
+            .long("raw")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+/// The change list asked for instead of a patch, if any
+fn change_format(matches: &ArgMatches) -> Option<commands::log::ChangeFormat> {
+    if matches.get_flag("name-status") {
+        Some(commands::log::ChangeFormat::NameStatus)
+    } else if matches.get_flag("raw") {
+        Some(commands::log::ChangeFormat::Raw)
+    } else {
+        None
+    }
+}
+
+/// `--diff-algorithm` and its `--patience` and `--histogram` shorthands
+fn diff_algorithm_args() -> [Arg; 3] {
+    [
+        Arg::new("diff-algorithm")
+            .help("Line diff algorithm: myers, patience or histogram (defaults to diff.algorithm)")
+            .long("diff-algorithm")
+            .value_name("algorithm")
+            .conflicts_with_all(["patience", "histogram"]),
+        Arg::new("patience")
+            .help("Diff with the patience algorithm")
+            .long("patience")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("histogram"),
+        Arg::new("histogram")
+            .help("Diff with the histogram algorithm")
+            .long("histogram")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+/// The algorithm picked on the command line, if any
+fn diff_algorithm(sub_matches: &ArgMatches) -> io::Result<Option<Algorithm>> {
+    if sub_matches.get_flag("patience") {
+        return Ok(Some(Algorithm::Patience));
+    }
+    if sub_matches.get_flag("histogram") {
+        return Ok(Some(Algorithm::Histogram));
     }
-} 
\ No newline at end of file
+    sub_matches.get_one::<String>("diff-algorithm")
+        .map(|name| Algorithm::parse(name))
+        .transpose()
+}
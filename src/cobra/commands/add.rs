@@ -1,46 +1,310 @@
+use std::env;
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
 use crate::cobra::core::{
+    attributes::Attributes,
+    ignore::IgnoreRules,
     repository::Repository,
     object::Object,
     index::IndexEntry,
+    merge::MergeState,
+    workspace::is_visible,
 };
+use crate::cobra::state::CommandContext;
 
-pub fn run(path: &str) -> io::Result<()> {
-    let mut repo = Repository::open(".")?;
-    let file_path = Path::new(path);
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    /// Also remove index entries whose files are gone, and with no paths
+    /// stage the whole work tree (-A/--all)
+    pub all: bool,
+    /// Add every tracked file again, applying the current text and eol
+    /// attributes (--renormalize)
+    pub renormalize: bool,
+}
 
-    // Convert to absolute path if relative
-    let absolute_path = if file_path.is_absolute() {
-        file_path.to_path_buf()
+/// Stages every path it can. A path that fails does not stop the others;
+/// the failures are reported together once the index has been saved.
+pub fn run(ctx: &mut CommandContext, paths: &[String], options: &AddOptions) -> io::Result<()> {
+    let attributes = Attributes::load(&ctx.repo)?;
+    let mut failures = Vec::new();
+    let mut targets = Vec::new();
+    for path in paths {
+        match resolve_path(&ctx.repo, Path::new(path)).and_then(|file_path| {
+            // With -A a path that is only in the index has its deletion
+            // staged by remove_deleted below
+            if !(options.all && only_in_index(&ctx.repo, &file_path)) {
+                stage_path(&mut ctx.repo, &attributes, &file_path)?;
+            }
+            Ok(file_path)
+        }) {
+            Ok(file_path) => targets.push(file_path),
+            Err(e) => failures.push(format!("{}: {}", path, e)),
+        }
+    }
+    if options.all && paths.is_empty() {
+        stage_path(&mut ctx.repo, &attributes, Path::new(""))?;
+        targets.push(PathBuf::new());
+    }
+    if options.renormalize {
+        let tracked: Vec<PathBuf> = ctx.repo.index.entries().map(|entry| entry.path.clone()).collect();
+        for path in tracked {
+            if ctx.repo.root_path.join(&path).is_file() {
+                stage_file(&mut ctx.repo, &attributes, &path)?;
+                targets.push(path);
+            }
+        }
+    }
+    let removed = if options.all { remove_deleted(&mut ctx.repo, &targets) } else { 0 };
+    if !targets.is_empty() || removed > 0 {
+        ctx.repo.save_index()?;
+        MergeState::mark_resolved(&ctx.repo, &targets)?;
+    }
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::other(format!(
+        "Failed to add {} of {} paths:\n  {}",
+        failures.len(),
+        paths.len(),
+        failures.join("\n  "),
+    )))
+}
+
+/// True if `path` is gone from the work tree but the index still tracks it
+/// or files under it
+fn only_in_index(repo: &Repository, path: &Path) -> bool {
+    fs::symlink_metadata(repo.root_path.join(path)).is_err()
+        && repo.index.entries().any(|entry| entry.path.starts_with(path))
+}
+
+/// Drops the index entries under any of `targets` whose files no longer
+/// exist, returning how many went
+fn remove_deleted(repo: &mut Repository, targets: &[PathBuf]) -> usize {
+    let deleted: Vec<PathBuf> = repo.index.entries()
+        .map(|entry| &entry.path)
+        .filter(|path| targets.iter().any(|target| path.starts_with(target)))
+        .filter(|path| fs::symlink_metadata(repo.root_path.join(path)).is_err())
+        .cloned()
+        .collect();
+    for path in &deleted {
+        repo.index.remove_entry(path);
+    }
+    deleted.len()
+}
+
+/// Restages every tracked file whose work tree copy changed and drops the
+/// entries of deleted ones, like `add -u` over the whole work tree, so
+/// untracked files stay untracked. A file modified no earlier than the
+/// index was written is hashed even when its stat data matches, since a
+/// rewrite within the same second keeps its size and mtime. Only the index
+/// in memory changes; the caller saves it and marks the returned paths
+/// resolved.
+pub fn stage_tracked(repo: &mut Repository) -> io::Result<Vec<PathBuf>> {
+    let attributes = Attributes::load(repo)?;
+    let index_written = fs::metadata(repo.git_dir.join("index")).map(|metadata| metadata.mtime() as u64).ok();
+    let mut changed = Vec::new();
+    let mut deleted = Vec::new();
+    for entry in repo.index.entries() {
+        let racy = index_written.is_none_or(|written| entry.mtime >= written);
+        match fs::metadata(repo.root_path.join(&entry.path)) {
+            Ok(metadata) if racy || !entry.stat_matches(&metadata) => changed.push(entry.path.clone()),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => deleted.push(entry.path.clone()),
+            Err(e) => return Err(e),
+        }
+    }
+    for path in &changed {
+        stage_file(repo, &attributes, path)?;
+    }
+    for path in &deleted {
+        repo.index.remove_entry(path);
+    }
+    changed.extend(deleted);
+    Ok(changed)
+}
+
+/// Maps a command-line path to a path relative to the work tree root.
+/// Relative paths are taken from the current directory when it lies inside
+/// the work tree, and from the work tree root otherwise. A path that does
+/// not exist is resolved through its parent directory, so deleted files
+/// can still be named.
+pub fn resolve_path(repo: &Repository, path: &Path) -> io::Result<PathBuf> {
+    let root = fs::canonicalize(&repo.root_path)?;
+    let candidate = if path.is_absolute() {
+        path.to_path_buf()
     } else {
-        repo.root_path.join(file_path)
+        let cwd = env::current_dir()?;
+        if cwd.starts_with(&root) { cwd.join(path) } else { root.join(path) }
+    };
+
+    let resolved = match fs::canonicalize(&candidate) {
+        Ok(resolved) => resolved,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => match (candidate.parent(), candidate.file_name()) {
+            (Some(parent), Some(name)) => fs::canonicalize(parent)?.join(name),
+            _ => return Err(e),
+        },
+        Err(e) => return Err(e),
     };
+    resolved
+        .strip_prefix(&root)
+        .map(Path::to_path_buf)
+        .map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Path must be inside repository",
+        ))
+}
+
+/// Stages the file at `path`, relative to the work tree root
+pub fn add_file(repo: &mut Repository, path: &Path) -> io::Result<()> {
+    stage_file(repo, &Attributes::load(repo)?, path)?;
+    repo.save_index()
+}
+
+/// Stages a file, or every file under a directory the way status sees
+/// them: .cobra, hidden and ignored untracked files are skipped, empty
+/// directories ignored
+fn stage_path(repo: &mut Repository, attributes: &Attributes, path: &Path) -> io::Result<()> {
+    let absolute_path = repo.root_path.join(path);
+    if !fs::metadata(&absolute_path)?.is_dir() {
+        return stage_file(repo, attributes, path);
+    }
+
+    let root = repo.root_path.clone();
+    let ignore = IgnoreRules::load(repo)?;
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&absolute_path)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| is_visible(repo, &ignore, e))
+    {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            files.push(entry.path().strip_prefix(&root).map_err(io::Error::other)?.to_path_buf());
+        }
+    }
+    for file in files {
+        stage_file(repo, attributes, &file)?;
+    }
+    Ok(())
+}
+
+/// Like `add_file`, leaving the index to be saved by the caller
+fn stage_file(repo: &mut Repository, attributes: &Attributes, path: &Path) -> io::Result<()> {
+    let absolute_path = repo.root_path.join(path);
 
     // Read file content
     let content = fs::read(&absolute_path)?;
     let metadata = fs::metadata(&absolute_path)?;
 
-    // Create blob object
-    let blob = Object::new_blob(content);
-    let hash = blob.hash();
-    blob.write_to_objects_dir(&repo.git_dir)?;
-
-    // Create index entry with relative path
-    let relative_path = if file_path.is_absolute() {
-        file_path.strip_prefix(&repo.root_path)
-            .map_err(|_| io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Path must be inside repository",
-            ))?
-            .to_path_buf()
-    } else {
-        file_path.to_path_buf()
-    };
+    // Create blob object, with line endings as the attributes ask
+    let blob = Object::new_blob(attributes.to_index(path, content));
+    let hash = repo.write_object(&blob)?;
 
-    let entry = IndexEntry::new(relative_path, hash, metadata);
-    repo.add_to_index(entry)?;
+    // Create index entry with relative path, spelled as the index already
+    // has it when the filesystem folds names
+    let path = repo.index.tracked_path(path, &repo.folding).unwrap_or(path).to_path_buf();
+    let entry = IndexEntry::new(path, hash, metadata);
+    repo.index.add_entry(entry);
 
     Ok(())
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::commands::status::collect;
+    use crate::cobra::core::tree::{commit_tree, flatten_tree};
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_add_stages_every_path_it_can() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        fs::write(temp_dir.path().join("a.txt"), "a\n")?;
+        fs::write(temp_dir.path().join("b.txt"), "b\n")?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        let paths: Vec<String> = ["a.txt", "missing.txt", "b.txt"].map(String::from).to_vec();
+        let error = run(&mut ctx, &paths, &AddOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("Failed to add 1 of 3 paths"), "{}", error);
+        assert!(error.to_string().contains("missing.txt"), "{}", error);
+
+        // Both files made it into the index on disk
+        let repo = Repository::open(temp_dir.path().to_str().unwrap())?;
+        assert!(repo.index.contains(Path::new("a.txt")));
+        assert!(repo.index.contains(Path::new("b.txt")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_directory_stages_its_files() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        fs::create_dir_all(temp_dir.path().join("src/nested"))?;
+        fs::create_dir_all(temp_dir.path().join("src/empty"))?;
+        fs::write(temp_dir.path().join("src/lib.rs"), "lib\n")?;
+        fs::write(temp_dir.path().join("src/nested/mod.rs"), "mod\n")?;
+        fs::write(temp_dir.path().join("src/.hidden"), "hidden\n")?;
+        fs::write(temp_dir.path().join("top.txt"), "top\n")?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        run(&mut ctx, &["src".to_string()], &AddOptions::default())?;
+        let staged: Vec<PathBuf> = ctx.repo.index.entries().map(|entry| entry.path.clone()).collect();
+        assert_eq!(staged, vec![PathBuf::from("src/lib.rs"), PathBuf::from("src/nested/mod.rs")]);
+
+        // The work tree root stages everything status would show
+        let root = temp_dir.path().to_str().unwrap().to_string();
+        run(&mut ctx, &[root], &AddOptions::default())?;
+        assert_eq!(ctx.repo.index.entries().count(), 3);
+        assert!(ctx.repo.index.contains(Path::new("top.txt")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_all_stages_deletions() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        for name in ["kept.txt", "gone.txt"] {
+            fs::write(temp_dir.path().join(name), name)?;
+            add_file(&mut repo, Path::new(name))?;
+        }
+        commit(&repo, "Two files")?;
+        fs::remove_file(temp_dir.path().join("gone.txt"))?;
+        fs::write(temp_dir.path().join("kept.txt"), "changed")?;
+        fs::write(temp_dir.path().join("new.txt"), "new")?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        run(&mut ctx, &[], &AddOptions { all: true, ..Default::default() })?;
+        let staged: Vec<PathBuf> = ctx.repo.index.entries().map(|entry| entry.path.clone()).collect();
+        assert!(!staged.contains(&PathBuf::from("gone.txt")));
+        assert!(staged.contains(&PathBuf::from("new.txt")));
+        let report = collect(&ctx.repo)?;
+        assert!(report.modified().next().is_none() && report.untracked.is_empty());
+
+        let head = commit(&ctx.repo, "Update")?;
+        let tree = flatten_tree(&ctx.repo.git_dir, commit_tree(&ctx.repo.git_dir, head)?)?;
+        assert_eq!(tree.len(), 2);
+        assert!(!tree.contains_key(Path::new("gone.txt")));
+        assert!(collect(&ctx.repo)?.is_clean());
+
+        // Naming a deleted file or directory stages its removal
+        fs::create_dir(temp_dir.path().join("dir"))?;
+        fs::write(temp_dir.path().join("dir/inner.txt"), "inner")?;
+        run(&mut ctx, &["dir".to_string()], &AddOptions::default())?;
+        commit(&ctx.repo, "Add dir")?;
+        fs::remove_file(temp_dir.path().join("kept.txt"))?;
+        fs::remove_dir_all(temp_dir.path().join("dir"))?;
+        let error = run(&mut ctx, &["kept.txt".to_string()], &AddOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("kept.txt"), "{}", error);
+        let all = AddOptions { all: true, ..Default::default() };
+        run(&mut ctx, &["kept.txt".to_string(), "dir".to_string()], &all)?;
+        let staged: Vec<PathBuf> = ctx.repo.index.entries().map(|entry| entry.path.clone()).collect();
+        assert_eq!(staged, vec![PathBuf::from("new.txt")]);
+        assert!(run(&mut ctx, &["never-tracked.txt".to_string()], &all).is_err());
+        Ok(())
+    }
+}
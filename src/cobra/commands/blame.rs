@@ -0,0 +1,159 @@
+// Show which commit last changed each line of a file
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::Path;
+use crate::cobra::core::{
+    blame::{blame, BlameGroup, BlameSettings},
+    diff::{split_lines, Algorithm},
+    object::Object,
+    oid::Oid,
+};
+use crate::cobra::state::CommandContext;
+
+#[derive(Debug, Default)]
+pub struct BlameOptions {
+    /// Commit to blame from, HEAD by default
+    pub rev: Option<String>,
+    /// Stream machine readable records as commits are finished (--incremental)
+    pub incremental: bool,
+    /// Algorithm matching lines between versions; diff.algorithm when None
+    pub algorithm: Option<Algorithm>,
+    /// Follow renames and lines moved within the file (-M)
+    pub moves: bool,
+    /// Look for lines copied from other files (-C); 2 and above search every
+    /// file of the parent instead of only the ones the commit changed
+    pub copies: usize,
+}
+
+pub fn run(ctx: &mut CommandContext, path: &str, options: &BlameOptions) -> io::Result<()> {
+    let start = ctx.refs.resolve_commit(options.rev.as_deref().unwrap_or("HEAD"))?;
+    let path = Path::new(path);
+    let settings = BlameSettings {
+        algorithm: match options.algorithm {
+            Some(algorithm) => algorithm,
+            None => ctx.repo.diff_algorithm()?,
+        },
+        moves: options.moves,
+        copies: options.copies,
+    };
+
+    if options.incremental {
+        let out = &mut ctx.out;
+        let mut described = HashSet::new();
+        blame(&ctx.repo, start, path, &settings, |group, commit| {
+            write_incremental(out, group, commit, &mut described)?;
+            out.flush()
+        })?;
+        return Ok(());
+    }
+
+    let result = blame(&ctx.repo, start, path, &settings, |_, _| Ok(()))?;
+    let lines = split_lines(&result.content);
+    let mut authors = Vec::new();
+    for group in &result.groups {
+        let author = match Object::read_from_objects_dir(&ctx.repo.git_dir, group.commit)? {
+            Object::Commit { author, .. } => author,
+            _ => continue,
+        };
+        authors.push((group, author));
+    }
+
+    let name_width = authors.iter().map(|(_, author)| author.name.len()).max().unwrap_or(0);
+    let number_width = lines.len().to_string().len();
+    // Like git, only show origin paths when some lines came from another file
+    let path_width = authors.iter()
+        .any(|(group, _)| group.path != path)
+        .then(|| authors.iter().map(|(group, _)| group.path.display().to_string().len()).max().unwrap_or(0));
+    for (group, author) in authors {
+        for offset in 0..group.count {
+            let line = group.final_start + offset;
+            let text = String::from_utf8_lossy(lines[line]);
+            let origin = match path_width {
+                Some(width) => format!(" {:<width$}", group.path.display().to_string()),
+                None => String::new(),
+            };
+            writeln!(
+                ctx.out,
+                "{}{} ({:<name_width$} {} {} {:>number_width$}) {}",
+                ctx.repo.short_id(group.commit),
+                origin,
+                author.name,
+                author.timestamp,
+                author.timezone,
+                line + 1,
+                text.trim_end_matches('\n'),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes one group in git's incremental format: a header with the commit,
+/// the 1-based source and final line and the line count, the commit's
+/// metadata the first time that commit appears, then the file name
+fn write_incremental(out: &mut dyn Write, group: &BlameGroup, commit: &Object, described: &mut HashSet<Oid>) -> io::Result<()> {
+    writeln!(out, "{} {} {} {}", group.commit, group.source_start + 1, group.final_start + 1, group.count)?;
+    if described.insert(group.commit) {
+        if let Object::Commit { author, committer, message, .. } = commit {
+            writeln!(out, "author {}", author.name)?;
+            writeln!(out, "author-mail <{}>", author.email)?;
+            writeln!(out, "author-time {}", author.timestamp)?;
+            writeln!(out, "author-tz {}", author.timezone)?;
+            writeln!(out, "committer {}", committer.name)?;
+            writeln!(out, "committer-mail <{}>", committer.email)?;
+            writeln!(out, "committer-time {}", committer.timestamp)?;
+            writeln!(out, "committer-tz {}", committer.timezone)?;
+            writeln!(out, "summary {}", message.lines().next().unwrap_or(""))?;
+        }
+    }
+    writeln!(out, "filename {}", group.path.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_incremental_matches_full_blame() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let file = temp_dir.path().join("file.txt");
+
+        for content in ["a\nb\nc\n", "a\nB\nc\nd\n", "x\na\nB\nc\nd\ny\n"] {
+            fs::write(&file, content)?;
+            add_file(&mut repo, Path::new("file.txt"))?;
+            commit(&repo, "Update file")?;
+        }
+        let expected = blame(&repo, repo.ref_store().resolve_commit("HEAD")?, Path::new("file.txt"), &BlameSettings::default(), |_, _| Ok(()))?;
+
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        run(&mut ctx, "file.txt", &BlameOptions { incremental: true, ..BlameOptions::default() })?;
+
+        // Rebuild the per-line attribution from the stream
+        let output = ctx.out.captured();
+        let mut lines = vec![String::new(); expected.line_commits().len()];
+        let mut headers = 0;
+        for record in output.lines() {
+            let fields: Vec<&str> = record.split(' ').collect();
+            if fields.len() == 4 && fields[0].len() == 40 {
+                let final_start: usize = fields[2].parse().unwrap();
+                let count: usize = fields[3].parse().unwrap();
+                for line in &mut lines[final_start - 1..final_start - 1 + count] {
+                    *line = fields[0].to_string();
+                }
+                headers += 1;
+            }
+        }
+
+        assert_eq!(lines, expected.line_commits());
+        assert_eq!(headers, expected.groups.len());
+        // Metadata appears once per commit, the file name once per group
+        assert_eq!(output.lines().filter(|line| *line == "summary Update file").count(), 3);
+        assert_eq!(output.lines().filter(|line| *line == "filename file.txt").count(), headers);
+
+        Ok(())
+    }
+}
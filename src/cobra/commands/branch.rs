@@ -1,89 +1,508 @@
 // Branch management commands
-use std::io;
-use crate::cobra::core::repository::Repository;
+use std::io::{self, Write};
+use crate::cobra::state::{CommandContext, OutputHandle};
+use crate::cobra::core::ancestry::{ancestors, is_ancestor};
+use crate::cobra::core::case_fold::collision_warning;
+use crate::cobra::core::checkout::{checkout_tree, CheckoutOptions, CheckoutReport};
+use crate::cobra::core::config::Config;
+use crate::cobra::core::diffstat::{diffstat, write_diffstat};
+use crate::cobra::core::merge::{self, MergeState, TreeMerge, Unmerged};
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::ref_store::{check_ref_format, RefStore};
+use crate::cobra::core::revision;
+use crate::cobra::core::signature::Signature;
+use crate::cobra::core::signoff::add_signoff;
+use crate::cobra::core::tree::{commit_tree, Tree};
+use crate::cobra::utils::progress::CounterProgress;
+use crate::cobra::messages;
 
-pub fn list() -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
+/// Which branches to list by whether HEAD already contains them
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MergedFilter {
+    #[default]
+    All,
+    /// Branches whose tip is reachable from HEAD (--merged)
+    Merged,
+    /// Branches with commits HEAD does not have (--no-merged)
+    NotMerged,
+}
+
+#[derive(Debug, Default)]
+pub struct ListOptions {
+    /// Show the subject line of each branch's tip commit (-v)
+    pub verbose: bool,
+    pub merged: MergedFilter,
+}
+
+/// Lists the branches sorted by name, the checked out one (or a detached
+/// HEAD) marked with `*`, each with its abbreviated tip or `(no commits)`.
+/// Branches without commits count as merged.
+pub fn list(ctx: &mut CommandContext, options: &ListOptions) -> io::Result<()> {
+    let ref_store = &ctx.refs;
     
     let branches = ref_store.list_branches()?;
     
     if branches.is_empty() {
-        println!("No branches found");
+        writeln!(ctx.out, "{}", messages::no_branches())?;
         return Ok(());
     }
     
-    // Get current branch name
-    let head_content = ref_store.read_head()?;
-    let current_branch = if let Some(content) = head_content {
-        if content.starts_with("ref: ") {
-            let branch_ref = content.strip_prefix("ref: ").unwrap().trim();
-            branch_ref.strip_prefix("refs/heads/").unwrap_or(branch_ref).to_string()
-        } else {
-            "".to_string()
+    let current_branch = ref_store.current_branch()?;
+    let mut rows = Vec::new();
+    if current_branch.is_none() {
+        if let Ok(head) = ref_store.resolve_commit("HEAD") {
+            rows.push((true, messages::branch_list_detached(&ctx.repo.short_id(head)), Some(head)));
         }
-    } else {
-        "".to_string()
-    };
-    
+    }
     for (name, hash) in branches {
-        let current_marker = if name == current_branch { " *" } else { "" };
-        println!("{}{} {}", name, current_marker, &hash[..7]);
+        rows.push((current_branch.as_ref() == Some(&name), name, hash));
+    }
+    
+    if options.merged != MergedFilter::All {
+        // A branch without commits has nothing HEAD could be missing
+        let contained = match ref_store.resolve_commit("HEAD") {
+            Ok(head) => ancestors(&ctx.repo, head)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(e),
+        };
+        let merged = |hash: &Option<Oid>| hash.is_none_or(|oid| contained.contains(&oid));
+        let wanted = options.merged == MergedFilter::Merged;
+        rows.retain(|(_, _, hash)| merged(hash) == wanted);
+    }
+
+    let width = rows.iter().map(|(_, name, _)| name.chars().count()).max().unwrap_or(0);
+    for (current, name, hash) in rows {
+        let marker = if current { '*' } else { ' ' };
+        let Some(hash) = hash else {
+            writeln!(ctx.out, "{} {:width$} {}", marker, name, messages::branch_no_commits(), width = width)?;
+            continue;
+        };
+        let mut line = format!("{} {:width$} {}", marker, name, ctx.repo.short_id(hash), width = width);
+        if options.verbose {
+            if let Object::Commit { message, .. } = ctx.repo.read_object(hash)? {
+                line = format!("{} {}", line, message.lines().next().unwrap_or(""));
+            }
+        }
+        writeln!(ctx.out, "{}", line)?;
     }
     
     Ok(())
 }
 
-pub fn create(name: &str) -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
+pub fn create(ctx: &mut CommandContext, name: &str, start_point: Option<&str>) -> io::Result<()> {
+    let ref_store = &ctx.refs;
     
-    ref_store.create_branch(name)?;
-    println!("Created branch '{}'", name);
+    ref_store.create_branch_at(name, start_point)?;
+    ctx.out.note(format_args!("{}", messages::branch_created(name)))?;
     
     Ok(())
 }
 
-pub fn switch(name: &str) -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
+#[derive(Debug, Default)]
+pub struct SwitchOptions {
+    /// Discard local changes to tracked files (--force)
+    pub force: bool,
+    /// List the files and directories the switch removed (--verbose)
+    pub verbose: bool,
+}
+
+/// Switches to the branch `name`. A name that is not a branch but names a
+/// commit, such as an abbreviated hash or `main~2`, detaches HEAD at it,
+/// and `-` goes back to where the previous checkout came from.
+pub fn switch(ctx: &mut CommandContext, name: &str, options: &SwitchOptions) -> io::Result<()> {
+    let previous;
+    let name = match name {
+        "-" => {
+            previous = ctx.refs.previous_checkout()?.ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                messages::no_previous_checkout(),
+            ))?;
+            previous.as_str()
+        }
+        name => name,
+    };
+    let branch_ref = format!("refs/heads/{}", name);
+    if ctx.refs.read_ref_value(&branch_ref)?.is_none() {
+        return detach(ctx, name, options);
+    }
+
+    // Update the working tree and index unless the branch has no commits yet
+    let from = HeadPosition::read(&ctx.refs)?;
+    if let Some(target_commit) = ctx.refs.read_ref(&branch_ref)? {
+        checkout_commit(ctx, target_commit, options)?;
+    }
     
-    ref_store.switch_branch(name)?;
-    println!("Switched to branch '{}'", name);
+    ctx.refs.switch_branch(name, &from.checkout_reason(name))?;
+    ctx.out.note(format_args!("{}", messages::switched_to_branch(name)))?;
     
     Ok(())
 }
 
-pub fn delete(name: &str) -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
-    
-    ref_store.delete_branch(name)?;
-    println!("Deleted branch '{}'", name);
-    
+/// Where HEAD was before a checkout, for its reflog entry
+struct HeadPosition {
+    /// The branch, or the commit when detached
+    name: String,
+}
+
+impl HeadPosition {
+    fn read(refs: &RefStore) -> io::Result<HeadPosition> {
+        let name = match refs.current_branch()? {
+            Some(branch) => branch,
+            None => refs.read_head()?.unwrap_or_default(),
+        };
+        Ok(HeadPosition { name })
+    }
+
+    /// The reflog reason for moving to `to`, worded the way git does,
+    /// which is what `switch -` reads back
+    fn checkout_reason(&self, to: &str) -> String {
+        format!("checkout: moving from {} to {}", self.name, to)
+    }
+}
+
+/// Checks out the commit `spec` names with HEAD holding the commit itself
+/// rather than a branch, until a branch is checked out again
+pub fn detach(ctx: &mut CommandContext, spec: &str, options: &SwitchOptions) -> io::Result<()> {
+    let commit = revision::resolve_revision(&ctx.refs, spec).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::NotFound, messages::not_branch_or_commit(spec)),
+        _ => e,
+    })?;
+    let subject = match ctx.repo.read_object(commit)? {
+        Object::Commit { message, .. } => message.lines().next().unwrap_or("").to_string(),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, messages::not_branch_or_commit(spec))),
+    };
+
+    let from = HeadPosition::read(&ctx.refs)?;
+    checkout_commit(ctx, commit, options)?;
+    ctx.refs.update_head(commit, &from.checkout_reason(spec))?;
+    ctx.out.note(format_args!("{}", messages::head_now_at(&ctx.repo.short_id(commit), &subject)))
+}
+
+/// Creates `name` at `start_point`, or at HEAD when none is given, and
+/// switches to it (checkout -b). The branch is only created once the work
+/// tree has been updated, so an existing branch or local changes in the
+/// way leave everything as it was.
+pub fn create_and_switch(ctx: &mut CommandContext, name: &str, start_point: Option<&str>, options: &SwitchOptions) -> io::Result<()> {
+    let branch_ref = format!("refs/heads/{}", name);
+    check_ref_format(&branch_ref)?;
+    if ctx.refs.read_ref_value(&branch_ref)?.is_some() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, messages::branch_exists(name)));
+    }
+
+    let start_commit = match start_point {
+        Some(start_point) => Some(revision::resolve_revision(&ctx.refs, start_point)?),
+        None => match ctx.refs.resolve_commit("HEAD") {
+            Ok(commit) => Some(commit),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        },
+    };
+    let from = HeadPosition::read(&ctx.refs)?;
+    if let Some(commit) = start_commit {
+        checkout_commit(ctx, commit, options)?;
+    }
+
+    ctx.refs.create_branch_at(name, start_commit.map(|commit| commit.to_string()).as_deref())?;
+    ctx.refs.switch_branch(name, &from.checkout_reason(name))?;
+    ctx.out.note(format_args!("{}", messages::switched_to_new_branch(name)))
+}
+
+/// Makes the index and work tree match `commit`, refusing to overwrite
+/// local changes unless forced
+fn checkout_commit(ctx: &mut CommandContext, commit: Oid, options: &SwitchOptions) -> io::Result<()> {
+    let tree = commit_tree(&ctx.repo.git_dir, commit)?;
+    let checkout = CheckoutOptions { force: options.force };
+    let report = checkout_tree(&mut ctx.repo, tree, &checkout, &mut CounterProgress::new("Updating files"))?;
+    report_checkout(&mut ctx.out, &report)?;
+    if options.verbose {
+        for path in &report.removed {
+            ctx.out.note(format_args!("{}", messages::checkout_removed_file(path.display())))?;
+        }
+        for dir in report.pruned.iter().rev() {
+            ctx.out.note(format_args!("{}", messages::checkout_removed_dir(dir.display())))?;
+        }
+    }
     Ok(())
 }
 
-pub fn merge(name: &str) -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
+#[derive(Debug, Default)]
+pub struct OrphanOptions {
+    /// Keep the index and work tree, so the first commit starts from them (--keep)
+    pub keep: bool,
+    /// Discard local changes to tracked files when removing them (--force)
+    pub force: bool,
+}
+
+/// Starts a branch with no history: HEAD names `name`, which does not exist
+/// until the first commit on it, and that commit has no parents. Unless
+/// kept, the tracked files leave the index and the work tree; untracked
+/// files stay where they are.
+pub fn orphan(ctx: &mut CommandContext, name: &str, options: &OrphanOptions) -> io::Result<()> {
+    let branch_ref = format!("refs/heads/{}", name);
+    check_ref_format(&branch_ref)?;
+    if ctx.refs.read_ref_value(&branch_ref)?.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            messages::branch_exists(name),
+        ));
+    }
+
+    let from = HeadPosition::read(&ctx.refs)?;
+    if !options.keep {
+        let empty_tree = ctx.repo.write_object(&Tree::new().to_object())?;
+        let checkout = CheckoutOptions { force: options.force };
+        let report = checkout_tree(&mut ctx.repo, empty_tree, &checkout, &mut CounterProgress::new("Removing files"))?;
+        report_checkout(&mut ctx.out, &report)?;
+    }
+
+    ctx.refs.attach_head(&branch_ref, &from.checkout_reason(name))?;
+    ctx.out.note(format_args!("{}", messages::switched_to_orphan(name)))
+}
+
+/// Renames the branch `old`, or the checked out branch when it is None
+pub fn rename(ctx: &mut CommandContext, old: Option<&str>, new: &str, force: bool) -> io::Result<()> {
+    let old = match old {
+        Some(old) => old.to_string(),
+        None => ctx.refs.current_branch()?.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            messages::rename_detached(),
+        ))?,
+    };
+    ctx.refs.rename_branch(&old, new, force)?;
+    ctx.out.note(format_args!("{}", messages::branch_renamed(&old, new)))
+}
+
+pub fn delete(ctx: &mut CommandContext, name: &str) -> io::Result<()> {
+    let ref_store = &ctx.refs;
     
-    ref_store.merge_branch(name)?;
-    println!("Merged branch '{}' into current branch", name);
+    let tip = ref_store.delete_branch(name)?;
+    let short_tip = tip.map(|tip| ctx.repo.short_id(tip)).unwrap_or_default();
+    ctx.out.note(format_args!("{}", messages::branch_deleted(name, &short_tip)))?;
     
     Ok(())
 }
 
-pub fn rebase(branch: &str) -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
+/// Whether merge may just move the branch when the other branch already
+/// contains all of its history
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FastForward {
+    /// Fast-forward when possible, merge otherwise
+    #[default]
+    Allow,
+    /// Fast-forward or fail (--ff-only)
+    Only,
+    /// Always create a merge commit (--no-ff)
+    Never,
+}
+
+impl FastForward {
+    /// The default from `merge.ff`: a boolean, or "only"; Allow when unset
+    pub fn from_config(config: &Config) -> io::Result<FastForward> {
+        match config.get("merge.ff") {
+            None => Ok(FastForward::Allow),
+            Some(value) if value.eq_ignore_ascii_case("only") => Ok(FastForward::Only),
+            Some(value) => match config.get_bool("merge.ff") {
+                Some(true) => Ok(FastForward::Allow),
+                Some(false) => Ok(FastForward::Never),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("bad merge.ff value '{}', expected true, false or only", value),
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// --ff, --ff-only or --no-ff; merge.ff when None
+    pub fast_forward: Option<FastForward>,
+    /// Add the committer's Signed-off-by trailer to a merge commit (--signoff)
+    pub signoff: bool,
+}
+
+pub fn merge(ctx: &mut CommandContext, name: &str, options: &MergeOptions) -> io::Result<()> {
+    if MergeState::in_progress(&ctx.repo) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, messages::merge_in_progress()));
+    }
+    let fast_forward = match options.fast_forward {
+        Some(mode) => mode,
+        None => FastForward::from_config(&Config::load(&ctx.repo.git_dir)?)?,
+    };
+    let target = ctx.refs.read_ref(&format!("refs/heads/{}", name))?
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Branch '{}' does not exist or has no commits", name),
+        ))?;
+    let current = match ctx.refs.resolve_commit("HEAD") {
+        Ok(hash) => Some(hash),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    let current = match current {
+        // Nothing to merge into yet: the branch simply starts at the target
+        None => return fast_forward_to(ctx, name, None, target),
+        Some(current) => current,
+    };
+    if is_ancestor(&ctx.repo, target, current)? {
+        return ctx.out.note(format_args!("{}", messages::already_up_to_date()));
+    }
+
+    if !is_ancestor(&ctx.repo, current, target)? {
+        if fast_forward == FastForward::Only {
+            return Err(io::Error::other("Not possible to fast-forward, aborting."));
+        }
+        let (message, committer) = merge_message(ctx, name, options)?;
+        let mut transaction = ctx.repo.transaction();
+        let labels = merge::Labels { ours: "HEAD", theirs: name };
+        let merged = merge::merge_commits(&ctx.repo.git_dir, &mut transaction, current, target, labels)?;
+        transaction.commit()?;
+        if !merged.conflicts.is_empty() {
+            return stop_on_conflicts(ctx, current, target, &merged, message);
+        }
+        return commit_merge(ctx, name, current, target, merged.tree, message, committer);
+    }
+
+    if fast_forward != FastForward::Never {
+        return fast_forward_to(ctx, name, Some(current), target);
+    }
+
+    // --no-ff: the merge result is exactly the target's tree, recorded under a merge commit
+    let (message, committer) = merge_message(ctx, name, options)?;
+    let tree = commit_tree(&ctx.repo.git_dir, target)?;
+    commit_merge(ctx, name, current, target, tree, message, committer)
+}
+
+/// Leaves a merge that hit conflicts for the user to finish. The merged
+/// tree is checked out with our side of each conflicted path, those files
+/// get their conflict markers, and the merge state waits for a commit or
+/// `merge --abort`, which goes back to the snapshot taken here.
+fn stop_on_conflicts(ctx: &mut CommandContext, current: Oid, target: Oid, merged: &TreeMerge, message: String) -> io::Result<()> {
+    let in_the_way = merge::locally_changed(&ctx.repo, &merged.conflicts)?;
+    if !in_the_way.is_empty() {
+        let paths: Vec<String> = in_the_way.iter().map(|path| format!("\t{}", path.display())).collect();
+        return Err(io::Error::other(messages::merge_would_overwrite(paths.join("\n"))));
+    }
+
+    let snapshot = merge::save_snapshot(&ctx.repo, current)?;
+    let report = checkout_tree(&mut ctx.repo, merged.tree, &CheckoutOptions::default(), &mut CounterProgress::new("Updating files"))?;
+    report_checkout(&mut ctx.out, &report)?;
+    merge::write_conflicts(&ctx.repo, &merged.conflicts)?;
+    let unmerged = merged.conflicts.iter()
+        .map(|conflict| Unmerged { path: conflict.path.clone(), kind: conflict.kind })
+        .collect();
+    MergeState { head: target, message, snapshot, unmerged }.write(&ctx.repo)?;
+
+    for conflict in &merged.conflicts {
+        ctx.out.note(format_args!("{}", messages::merge_conflict_in(conflict.path.display())))?;
+    }
+    Err(io::Error::other(messages::merge_stopped()))
+}
+
+/// Gives up on a merge that stopped on conflicts, putting back the work
+/// tree and index from before it
+pub fn abort_merge(ctx: &mut CommandContext) -> io::Result<()> {
+    let state = MergeState::read(&ctx.repo)?.ok_or_else(|| io::Error::new(
+        io::ErrorKind::NotFound,
+        messages::no_merge_in_progress(),
+    ))?;
+    merge::restore_snapshot(&mut ctx.repo, state.snapshot)?;
+    MergeState::clear(&ctx.repo)
+}
+
+/// Checks out the merged `tree` and records it in a merge commit with
+/// `current` and `target`, the tip of branch `name`, as parents
+fn commit_merge(
+    ctx: &mut CommandContext,
+    name: &str,
+    current: Oid,
+    target: Oid,
+    tree: Oid,
+    message: String,
+    committer: Signature,
+) -> io::Result<()> {
+    let merge_commit = Object::new_commit(
+        tree,
+        vec![current, target],
+        Signature::author(&ctx.repo.git_dir)?,
+        committer,
+        message,
+    );
+    // Staging the commit checks it, so a refused one leaves the work tree alone
+    let mut transaction = ctx.repo.transaction();
+    let merge_hash = transaction.add(merge_commit)?;
+    let report = checkout_tree(&mut ctx.repo, tree, &CheckoutOptions::default(), &mut CounterProgress::new("Updating files"))?;
+    report_checkout(&mut ctx.out, &report)?;
+    transaction.update_head(Some(current), merge_hash, &format!("merge {}: Merge made", name))?;
+    transaction.commit()?;
+
+    ctx.out.note(format_args!("{}", messages::merge_commit_made()))?;
+    let stats = diffstat(&ctx.repo, Some(commit_tree(&ctx.repo.git_dir, current)?), Some(tree))?;
+    write_diffstat(&mut ctx.out, &stats)
+}
+
+/// The message and committer of a merge commit
+fn merge_message(ctx: &CommandContext, name: &str, options: &MergeOptions) -> io::Result<(String, Signature)> {
+    let committer = Signature::committer(&ctx.repo.git_dir)?;
+    let message = format!("Merge branch '{}'\n", name);
+    let message = if options.signoff { add_signoff(&message, &committer) } else { message };
+    Ok((message, committer))
+}
+
+/// Checks out `target`, the tip of branch `name`, and moves the current
+/// branch (or a detached HEAD) to it. The checkout refuses to overwrite
+/// local changes, so nothing moves if the work tree is in the way.
+fn fast_forward_to(ctx: &mut CommandContext, name: &str, current: Option<Oid>, target: Oid) -> io::Result<()> {
+    let tree = commit_tree(&ctx.repo.git_dir, target)?;
+    let report = checkout_tree(&mut ctx.repo, tree, &CheckoutOptions::default(), &mut CounterProgress::new("Updating files"))?;
+    report_checkout(&mut ctx.out, &report)?;
+    move_head(ctx, target, &format!("merge {}: Fast-forward", name))?;
+
+    let old_tree = match current {
+        Some(current) => {
+            ctx.out.note(format_args!("{}", messages::fast_forward_range(&ctx.repo.short_id(current), &ctx.repo.short_id(target))))?;
+            Some(commit_tree(&ctx.repo.git_dir, current)?)
+        }
+        None => None,
+    };
+    ctx.out.note(format_args!("{}", messages::fast_forward()))?;
+    let stats = diffstat(&ctx.repo, old_tree, Some(tree))?;
+    write_diffstat(&mut ctx.out, &stats)
+}
+
+/// Points the checked out branch, or HEAD itself when detached, at `commit`
+fn move_head(ctx: &CommandContext, commit: Oid, reason: &str) -> io::Result<()> {
+    let head = ctx.refs.read_head()?.unwrap_or_default();
+    match head.strip_prefix("ref: ") {
+        Some(branch_ref) => ctx.refs.update_ref(branch_ref.trim(), commit, reason),
+        None => ctx.refs.update_head(commit, reason),
+    }
+}
+
+/// Mentions what a checkout threw away or could not represent
+pub fn report_checkout(out: &mut OutputHandle, report: &CheckoutReport) -> io::Result<()> {
+    for path in &report.discarded {
+        out.note(format_args!("{}", messages::checkout_discarded(path.display())))?;
+    }
+    if !report.collisions.is_empty() {
+        out.note(format_args!("{}", collision_warning(&report.collisions)))?;
+    }
+    Ok(())
+}
+
+/// Puts the current branch's changes since it forked from `branch` on top
+/// of `branch` as a single commit, checking out the result
+pub fn rebase(ctx: &mut CommandContext, branch: &str) -> io::Result<()> {
+    let ref_store = &ctx.refs;
     
     // Check if target branch exists
     let branch_ref = format!("refs/heads/{}", branch);
     let target_commit = ref_store.read_ref(&branch_ref)?
         .ok_or_else(|| io::Error::new(
             io::ErrorKind::NotFound,
-            format!("Branch '{}' does not exist", branch),
+            messages::branch_not_found(branch),
         ))?;
 
     // Get current branch commit
@@ -93,15 +512,14 @@ pub fn rebase(branch: &str) -> io::Result<()> {
             "HEAD reference not found",
         ))?;
 
-    let current_commit = if head_content.starts_with("ref: ") {
-        let current_branch_ref = &head_content[5..];
+    let current_commit = if let Some(current_branch_ref) = head_content.strip_prefix("ref: ") {
         ref_store.read_ref(current_branch_ref)?
             .ok_or_else(|| io::Error::new(
                 io::ErrorKind::NotFound,
                 "Current branch reference not found",
             ))?
     } else {
-        head_content.clone()
+        head_content.parse()?
     };
 
     // Check if we're trying to rebase onto the same branch
@@ -112,51 +530,169 @@ pub fn rebase(branch: &str) -> io::Result<()> {
         ));
     }
 
-    // Create a new commit with the target branch as parent
-    let author = crate::cobra::core::signature::Signature::new(
-        "Your Name".to_string(),
-        "you@example.com".to_string(),
-    );
-    let committer = author.clone();
+    // Apply the branch's changes since the merge base to the target's tree
+    let mut transaction = ctx.repo.transaction();
+    let labels = merge::Labels { ours: branch, theirs: "HEAD" };
+    let tree = merge::merge_commits(&ctx.repo.git_dir, &mut transaction, target_commit, current_commit, labels)?.into_tree()?;
+    transaction.commit()?;
 
-    let rebase_commit = crate::cobra::core::object::Object::new_commit(
-        current_commit.clone(), // Use current tree (simplified)
+    // Create a new commit with the target branch as parent, checked before
+    // the work tree changes
+    let reason = format!("rebase (finish): {} onto {}", head_content.strip_prefix("ref: ").unwrap_or("HEAD"), target_commit);
+    let rebase_commit = Object::new_commit(
+        tree,
         vec![target_commit],
-        author,
-        committer,
+        Signature::author(&ctx.repo.git_dir)?,
+        Signature::committer(&ctx.repo.git_dir)?,
         format!("Rebase onto {}", branch),
     );
+    let mut transaction = ctx.repo.transaction();
+    let rebase_hash = transaction.add(rebase_commit)?;
+    let report = checkout_tree(&mut ctx.repo, tree, &CheckoutOptions::default(), &mut CounterProgress::new("Updating files"))?;
+    report_checkout(&mut ctx.out, &report)?;
+    transaction.update_head(Some(current_commit), rebase_hash, &reason)?;
+    transaction.commit()?;
 
-    // Write rebase commit
-    let rebase_hash = rebase_commit.hash();
-    rebase_commit.write_to_objects_dir(&repo.git_dir)?;
-
-    // Update current branch to point to rebase commit
-    if head_content.starts_with("ref: ") {
-        let current_branch_ref = &head_content[5..];
-        ref_store.update_ref(current_branch_ref, &rebase_hash)?;
-    } else {
-        ref_store.update_head(&rebase_hash)?;
-    }
-
-    println!("Rebased current branch onto '{}'", branch);
+    ctx.out.note(format_args!("{}", messages::rebased_onto(branch)))?;
     Ok(())
 }
 
 // Legacy function for backward compatibility
-pub fn run(name: &str) -> io::Result<()> {
-    create(name)
+pub fn run(ctx: &mut CommandContext, name: &str) -> io::Result<()> {
+    create(ctx, name, None)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use crate::cobra::commands::{commit::commit, status};
+    use crate::cobra::core::signoff;
+    use crate::cobra::core::tree::flatten_tree;
+    use crate::cobra::state::GlobalOpts;
+    use crate::cobra::test_support::{commit_file, temp_repo};
     use tempfile::TempDir;
 
+    /// main and feature share "Base"; feature adds a commit, and main adds
+    /// one of its own when `diverged`. Returns the main and feature tips.
+    fn merge_fixture(diverged: bool) -> io::Result<(TempDir, CommandContext, Oid, Oid)> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let mut main = commit_file(&mut ctx, "file.txt", "base\n", "Base")?;
+
+        create(&mut ctx, "feature", None)?;
+        switch(&mut ctx, "feature", &SwitchOptions::default())?;
+        commit_file(&mut ctx, "file.txt", "feature\n", "Feature edit")?;
+        let feature = commit_file(&mut ctx, "feature.txt", "new\n", "Feature file")?;
+        switch(&mut ctx, "main", &SwitchOptions::default())?;
+
+        if diverged {
+            main = commit_file(&mut ctx, "main.txt", "main\n", "Main work")?;
+        }
+        Ok((temp_dir, ctx, main, feature))
+    }
+
+    fn parents(ctx: &CommandContext, hash: Oid) -> io::Result<Vec<Oid>> {
+        match Object::read_from_objects_dir(&ctx.repo.git_dir, hash)? {
+            Object::Commit { parents, .. } => Ok(parents),
+            _ => Err(io::Error::other("not a commit")),
+        }
+    }
+
+    #[test]
+    fn test_merge_fast_forwards() -> io::Result<()> {
+        for mode in [FastForward::Allow, FastForward::Only] {
+            let (temp_dir, mut ctx, main, feature) = merge_fixture(false)?;
+            merge(&mut ctx, "feature", &MergeOptions { fast_forward: Some(mode), ..Default::default() })?;
+
+            assert_eq!(ctx.refs.resolve_commit("HEAD")?, feature);
+            assert_eq!(fs::read_to_string(temp_dir.path().join("file.txt"))?, "feature\n");
+            assert_eq!(fs::read_to_string(temp_dir.path().join("feature.txt"))?, "new\n");
+            let output = ctx.out.captured();
+            assert!(output.contains(&format!("Updating {}..{}\nFast-forward\n", main.abbrev(7), feature.abbrev(7))), "{}", output);
+            assert!(output.ends_with(" feature.txt | 1 +\n file.txt    | 2 +-\n 2 files changed, 2 insertions(+), 1 deletion(-)\n"), "{}", output);
+
+            // Merging again has nothing to do
+            merge(&mut ctx, "feature", &MergeOptions { fast_forward: Some(mode), ..Default::default() })?;
+            assert!(ctx.out.captured().ends_with("Already up to date.\n"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_no_ff_creates_merge_commit() -> io::Result<()> {
+        let (temp_dir, mut ctx, main, feature) = merge_fixture(false)?;
+        merge(&mut ctx, "feature", &MergeOptions { fast_forward: Some(FastForward::Never), ..Default::default() })?;
+
+        let head = ctx.refs.resolve_commit("HEAD")?;
+        assert_eq!(parents(&ctx, head)?, vec![main, feature]);
+        assert_eq!(commit_tree(&ctx.repo.git_dir, head)?, commit_tree(&ctx.repo.git_dir, feature)?);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("feature.txt"))?, "new\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_diverged_histories() -> io::Result<()> {
+        let (_temp_dir, mut ctx, main, _feature) = merge_fixture(true)?;
+        let error = merge(&mut ctx, "feature", &MergeOptions { fast_forward: Some(FastForward::Only), ..Default::default() }).unwrap_err();
+        assert!(error.to_string().contains("Not possible to fast-forward"));
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, main);
+
+        for mode in [FastForward::Allow, FastForward::Never] {
+            let (_temp_dir, mut ctx, main, feature) = merge_fixture(true)?;
+            merge(&mut ctx, "feature", &MergeOptions { fast_forward: Some(mode), ..Default::default() })?;
+            let head = ctx.refs.resolve_commit("HEAD")?;
+            assert_eq!(parents(&ctx, head)?, vec![main, feature]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_commits_honor_require_signoff() -> io::Result<()> {
+        let (temp_dir, mut ctx, main, _feature) = merge_fixture(false)?;
+        let config = ctx.repo.git_dir.join("config");
+        fs::write(&config, fs::read_to_string(&config)? + "[commit]\n\trequireSignoff = true\n")?;
+
+        let no_ff = MergeOptions { fast_forward: Some(FastForward::Never), ..Default::default() };
+        let error = merge(&mut ctx, "feature", &no_ff).unwrap_err();
+        assert!(error.to_string().contains("requireSignoff"), "{}", error);
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, main);
+        assert!(!temp_dir.path().join("feature.txt").exists());
+
+        // Rebasing and merging through the ref store create commits too
+        let error = rebase(&mut ctx, "feature").unwrap_err();
+        assert!(error.to_string().contains("requireSignoff"), "{}", error);
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, main);
+        assert!(!temp_dir.path().join("feature.txt").exists());
+        let error = ctx.refs.merge_branch("feature").unwrap_err();
+        assert!(error.to_string().contains("requireSignoff"), "{}", error);
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, main);
+
+        merge(&mut ctx, "feature", &MergeOptions { signoff: true, ..no_ff })?;
+        let head = ctx.refs.resolve_commit("HEAD")?;
+        let Object::Commit { message, .. } = ctx.repo.read_object(head)? else {
+            panic!("not a commit");
+        };
+        assert_eq!(signoff::signoffs(&message).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fast_forward_protects_local_changes() -> io::Result<()> {
+        let (temp_dir, mut ctx, main, _feature) = merge_fixture(false)?;
+        fs::write(temp_dir.path().join("file.txt"), "local edit\n")?;
+
+        let error = merge(&mut ctx, "feature", &MergeOptions { fast_forward: Some(FastForward::Only), ..Default::default() }).unwrap_err();
+        assert!(error.to_string().contains("would be overwritten"));
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, main);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file.txt"))?, "local edit\n");
+        Ok(())
+    }
+
     #[test]
     fn test_create_and_list_branches() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (_temp_dir, repo) = temp_repo()?;
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
         
         // Create branches
@@ -178,15 +714,14 @@ mod tests {
 
     #[test]
     fn test_switch_branch_success() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (_temp_dir, repo) = temp_repo()?;
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
         
         // Create a branch
         ref_store.create_branch("feature")?;
         
         // Switch to the branch
-        ref_store.switch_branch("feature")?;
+        ref_store.switch_branch("feature", "test")?;
         
         // Verify HEAD points to the branch
         let head_content = ref_store.read_head()?;
@@ -197,12 +732,11 @@ mod tests {
 
     #[test]
     fn test_switch_branch_not_found() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (_temp_dir, repo) = temp_repo()?;
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
         
         // Try to switch to non-existent branch
-        let result = ref_store.switch_branch("nonexistent");
+        let result = ref_store.switch_branch("nonexistent", "test");
         assert!(result.is_err());
         
         match result {
@@ -218,8 +752,7 @@ mod tests {
 
     #[test]
     fn test_delete_branch_command() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (_temp_dir, repo) = temp_repo()?;
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
         
         // Create a branch
@@ -243,13 +776,12 @@ mod tests {
 
     #[test]
     fn test_delete_current_branch_command() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (_temp_dir, repo) = temp_repo()?;
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
         
         // Create a branch and switch to it
         ref_store.create_branch("current")?;
-        ref_store.update_head("ref: refs/heads/current")?;
+        ref_store.attach_head("refs/heads/current", "test")?;
         
         // Try to delete the current branch
         let result = ref_store.delete_branch("current");
@@ -267,33 +799,50 @@ mod tests {
     }
 
     #[test]
-    fn test_merge_branch_command() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
-        let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
-        
-        // Create a branch
+    fn test_recover_deleted_branch_from_printed_hash() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = repo.ref_store();
+
+        let tip = crate::cobra::commands::commit::commit(&repo, "Initial commit")?;
         ref_store.create_branch("feature")?;
-        
-        // Set some commits (simplified for testing)
-        ref_store.update_ref("refs/heads/main", "main_commit")?;
-        ref_store.update_ref("refs/heads/feature", "feature_commit")?;
+
+        let deleted = ref_store.delete_branch("feature")?;
+        assert_eq!(deleted, Some(tip));
+        let message = messages::branch_deleted("feature", &repo.short_id(tip));
+        assert_eq!(message, format!("Deleted branch feature (was {}).", tip.abbrev(7)));
+
+        // The deletion is recorded in the branch's reflog, which outlives the ref
+        let entries = crate::cobra::core::reflog::read(&repo.git_dir, "refs/heads/feature")?;
+        assert_eq!(entries.last().map(|entry| entry.old.clone()), Some(tip.to_string()));
+
+        // Restore it from the abbreviated hash in the message
+        let short = message.trim_end_matches(").").rsplit(' ').next().unwrap();
+        ref_store.create_branch_at("restored", Some(short))?;
+        assert_eq!(ref_store.read_ref("refs/heads/restored")?, Some(tip));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_branch_command() -> io::Result<()> {
+        let (_temp_dir, ctx, main, feature) = merge_fixture(true)?;
         
         // Merge feature into main
-        ref_store.merge_branch("feature")?;
+        ctx.refs.merge_branch("feature")?;
         
-        // Verify the merge created a new commit
-        let main_commit = ref_store.read_ref("refs/heads/main")?;
-        assert!(main_commit.is_some());
-        assert_ne!(main_commit.unwrap(), "main_commit"); // Should be different after merge
+        // The merge commit holds the files of both branches
+        let head = ctx.refs.read_ref("refs/heads/main")?.unwrap();
+        assert_eq!(parents(&ctx, head)?, vec![main, feature]);
+        let tree = commit_tree(&ctx.repo.git_dir, head)?;
+        let paths: Vec<_> = flatten_tree(&ctx.repo.git_dir, tree)?.into_keys().collect();
+        assert_eq!(paths, vec![PathBuf::from("feature.txt"), PathBuf::from("file.txt"), PathBuf::from("main.txt")]);
         
         Ok(())
     }
 
     #[test]
     fn test_merge_nonexistent_branch_command() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (_temp_dir, repo) = temp_repo()?;
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
         
         // Try to merge a non-existent branch
@@ -310,4 +859,409 @@ mod tests {
         
         Ok(())
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_create_branch_from_revisions() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let first = commit_file(&mut ctx, "file.txt", "1\n", "First")?;
+        let second = commit_file(&mut ctx, "file.txt", "2\n", "Second")?;
+        commit_file(&mut ctx, "file.txt", "3\n", "Third")?;
+        ctx.refs.update_ref("refs/tags/v1", second, "test")?;
+
+        create(&mut ctx, "from-tag", Some("v1"))?;
+        create(&mut ctx, "two-back", Some("HEAD~2"))?;
+        create(&mut ctx, "tag-parent", Some("v1^"))?;
+        assert_eq!(ctx.refs.read_ref("refs/heads/from-tag")?, Some(second));
+        assert_eq!(ctx.refs.read_ref("refs/heads/two-back")?, Some(first));
+        assert_eq!(ctx.refs.read_ref("refs/heads/tag-parent")?, Some(first));
+
+        let error = create(&mut ctx, "too-far", Some("HEAD~3")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(ctx.refs.read_ref("refs/heads/too-far")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_orphan_branch_starts_new_history() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        commit_file(&mut ctx, "main.txt", "main\n", "Main work")?;
+        fs::write(temp_dir.path().join("notes.txt"), "untracked\n")?;
+
+        orphan(&mut ctx, "gh-pages", &OrphanOptions::default())?;
+        assert_eq!(ctx.refs.read_head()?, Some("ref: refs/heads/gh-pages".to_string()));
+        assert!(ctx.refs.read_ref("refs/heads/gh-pages")?.is_none());
+        assert!(ctx.repo.index.entries().next().is_none());
+        assert!(!temp_dir.path().join("main.txt").exists());
+        assert!(temp_dir.path().join("notes.txt").exists());
+
+        let page = commit_file(&mut ctx, "index.html", "<html></html>\n", "Pages")?;
+        assert!(parents(&ctx, page)?.is_empty());
+        assert_eq!(ctx.refs.read_ref("refs/heads/gh-pages")?, Some(page));
+
+        // --keep starts from what is staged
+        orphan(&mut ctx, "kept", &OrphanOptions { keep: true, ..Default::default() })?;
+        let kept = commit(&ctx.repo, "Kept")?;
+        assert!(parents(&ctx, kept)?.is_empty());
+        assert!(ctx.repo.index.contains(Path::new("index.html")));
+
+        let error = orphan(&mut ctx, "main", &OrphanOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        let error = orphan(&mut ctx, "../HEAD", &OrphanOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(ctx.refs.read_head()?, Some("ref: refs/heads/kept".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_and_switch() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let first = commit_file(&mut ctx, "file.txt", "1\n", "First")?;
+        let second = commit_file(&mut ctx, "file.txt", "2\n", "Second")?;
+
+        create_and_switch(&mut ctx, "fix", Some("HEAD~1"), &SwitchOptions::default())?;
+        assert_eq!(ctx.refs.read_head()?, Some("ref: refs/heads/fix".to_string()));
+        assert_eq!(ctx.refs.read_ref("refs/heads/fix")?, Some(first));
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file.txt"))?, "1\n");
+        assert!(ctx.out.captured().contains("Switched to a new branch 'fix'"));
+
+        // An existing branch or local changes in the way leave everything as it was
+        let error = create_and_switch(&mut ctx, "main", None, &SwitchOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(ctx.refs.read_ref("refs/heads/main")?, Some(second));
+        fs::write(temp_dir.path().join("file.txt"), "local\n")?;
+        assert!(create_and_switch(&mut ctx, "other", Some("main"), &SwitchOptions::default()).is_err());
+        assert!(ctx.refs.read_ref("refs/heads/other")?.is_none());
+        assert_eq!(ctx.refs.current_branch()?, Some("fix".to_string()));
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file.txt"))?, "local\n");
+
+        // Starting from HEAD keeps local changes
+        create_and_switch(&mut ctx, "here", None, &SwitchOptions::default())?;
+        assert_eq!(ctx.refs.read_ref("refs/heads/here")?, Some(first));
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file.txt"))?, "local\n");
+
+        // A name that would leave refs/heads is refused before anything moves
+        let error = create_and_switch(&mut ctx, "../../../evil", None, &SwitchOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(ctx.refs.current_branch()?, Some("here".to_string()));
+        assert!(!temp_dir.path().join("evil").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_switch_updates_work_tree_and_index() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let root = temp_dir.path().to_path_buf();
+        commit_file(&mut ctx, "file.txt", "main\n", "Main")?;
+        create(&mut ctx, "feature", None)?;
+        switch(&mut ctx, "feature", &SwitchOptions::default())?;
+        commit_file(&mut ctx, "file.txt", "feature\n", "Feature edit")?;
+        commit_file(&mut ctx, "extra.txt", "extra\n", "Feature file")?;
+
+        switch(&mut ctx, "main", &SwitchOptions::default())?;
+        assert_eq!(fs::read_to_string(root.join("file.txt"))?, "main\n");
+        assert!(!root.join("extra.txt").exists());
+        assert!(status::collect(&ctx.repo)?.is_clean());
+
+        // A commit right after switching only carries the branch's own content
+        let main = commit_file(&mut ctx, "main.txt", "main only\n", "Main file")?;
+        let tree = commit_tree(&ctx.repo.git_dir, main)?;
+        let paths: Vec<_> = flatten_tree(&ctx.repo.git_dir, tree)?.into_keys().collect();
+        assert_eq!(paths, vec![PathBuf::from("file.txt"), PathBuf::from("main.txt")]);
+
+        // Local changes to a file that differs between the branches block the switch
+        fs::write(root.join("file.txt"), "local\n")?;
+        let error = switch(&mut ctx, "feature", &SwitchOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("would be overwritten"), "{}", error);
+        assert_eq!(ctx.refs.current_branch()?, Some("main".to_string()));
+        assert_eq!(fs::read_to_string(root.join("file.txt"))?, "local\n");
+
+        switch(&mut ctx, "feature", &SwitchOptions { force: true, ..Default::default() })?;
+        assert_eq!(fs::read_to_string(root.join("file.txt"))?, "feature\n");
+        assert_eq!(fs::read_to_string(root.join("extra.txt"))?, "extra\n");
+        assert!(!root.join("main.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_detached_checkout_and_reattach() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let root = temp_dir.path().to_path_buf();
+        let first = commit_file(&mut ctx, "file.txt", "1\n", "First")?;
+        let second = commit_file(&mut ctx, "file.txt", "2\n", "Second")?;
+
+        switch(&mut ctx, &first.abbrev(7), &SwitchOptions::default())?;
+        assert_eq!(ctx.refs.read_head()?, Some(first.to_string()));
+        assert_eq!(ctx.refs.current_branch()?, None);
+        assert_eq!(fs::read_to_string(root.join("file.txt"))?, "1\n");
+        assert!(ctx.out.captured().contains(&format!("HEAD is now at {} First", ctx.repo.short_id(first))));
+
+        ctx.out = OutputHandle::buffer(false);
+        list(&mut ctx, &ListOptions::default())?;
+        let detached = format!("(HEAD detached at {})", ctx.repo.short_id(first));
+        assert_eq!(
+            ctx.out.captured(),
+            format!(
+                "* {} {}\n  {:width$} {}\n",
+                detached, ctx.repo.short_id(first), "main", ctx.repo.short_id(second), width = detached.len(),
+            ),
+        );
+
+        // Committing moves HEAD alone
+        let detached = commit_file(&mut ctx, "file.txt", "detached\n", "Detached work")?;
+        assert_eq!(ctx.refs.read_head()?, Some(detached.to_string()));
+        assert_eq!(ctx.refs.read_ref("refs/heads/main")?, Some(second));
+
+        switch(&mut ctx, "main", &SwitchOptions::default())?;
+        assert_eq!(ctx.refs.read_head()?, Some("ref: refs/heads/main".to_string()));
+        assert_eq!(fs::read_to_string(root.join("file.txt"))?, "2\n");
+
+        let error = switch(&mut ctx, "nowhere", &SwitchOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains("neither a branch nor a commit"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_marks_current_branch_and_empty_refs() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        // Right after init main has no commits
+        list(&mut ctx, &ListOptions::default())?;
+        assert_eq!(ctx.out.captured(), "* main (no commits)\n");
+
+        let first = commit_file(&mut ctx, "file.txt", "1\n", "First commit\n\nWith a body")?;
+        // A branch without commits is an empty ref file
+        fs::write(ctx.repo.git_dir.join("refs/heads/zeta"), "")?;
+        create(&mut ctx, "feature/long-name", None)?;
+        let second = commit_file(&mut ctx, "file.txt", "2\n", "Second commit")?;
+
+        ctx.out = OutputHandle::buffer(false);
+        list(&mut ctx, &ListOptions { verbose: true, ..Default::default() })?;
+        let (first, second) = (ctx.repo.short_id(first), ctx.repo.short_id(second));
+        assert_eq!(ctx.out.captured(), format!(concat!(
+            "  feature/long-name {} First commit\n",
+            "* main              {} Second commit\n",
+            "  zeta              (no commits)\n",
+        ), first, second));
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_merged_and_not_merged() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        commit_file(&mut ctx, "file.txt", "1\n", "First")?;
+        create(&mut ctx, "old", None)?;
+        create_and_switch(&mut ctx, "topic", None, &SwitchOptions::default())?;
+        commit_file(&mut ctx, "topic.txt", "topic\n", "Topic work")?;
+        switch(&mut ctx, "main", &SwitchOptions::default())?;
+        commit_file(&mut ctx, "file.txt", "2\n", "Second")?;
+        fs::write(ctx.repo.git_dir.join("refs/heads/unborn"), "")?;
+
+        let names = |ctx: &mut CommandContext, merged: MergedFilter| -> io::Result<Vec<String>> {
+            ctx.out = OutputHandle::buffer(false);
+            list(ctx, &ListOptions { merged, ..Default::default() })?;
+            Ok(ctx.out.captured().lines().map(|line| line[2..].split(' ').next().unwrap().to_string()).collect())
+        };
+        assert_eq!(names(&mut ctx, MergedFilter::Merged)?, ["main", "old", "unborn"]);
+        assert_eq!(names(&mut ctx, MergedFilter::NotMerged)?, ["topic"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_switch_back_to_previous_checkout() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let first = commit_file(&mut ctx, "file.txt", "one\n", "First")?;
+        commit_file(&mut ctx, "file.txt", "two\n", "Second")?;
+
+        let error = switch(&mut ctx, "-", &SwitchOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        create_and_switch(&mut ctx, "feature", None, &SwitchOptions::default())?;
+        switch(&mut ctx, "-", &SwitchOptions::default())?;
+        assert_eq!(ctx.refs.current_branch()?, Some("main".to_string()));
+        switch(&mut ctx, "-", &SwitchOptions::default())?;
+        assert_eq!(ctx.refs.current_branch()?, Some("feature".to_string()));
+
+        // Leaving a detached HEAD goes back to the commit it was at
+        switch(&mut ctx, &first.to_string(), &SwitchOptions::default())?;
+        switch(&mut ctx, "main", &SwitchOptions::default())?;
+        switch(&mut ctx, "-", &SwitchOptions::default())?;
+        assert_eq!(ctx.refs.current_branch()?, None);
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, first);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_combines_changes_from_both_sides() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let root = temp_dir.path().to_path_buf();
+        commit_file(&mut ctx, "file.txt", "1\n2\n3\n4\n5\n", "Base")?;
+        create_and_switch(&mut ctx, "feature", None, &SwitchOptions::default())?;
+        commit_file(&mut ctx, "file.txt", "one\n2\n3\n4\n5\n", "Feature edit")?;
+        let feature = commit_file(&mut ctx, "feature.txt", "feature\n", "Feature file")?;
+        switch(&mut ctx, "main", &SwitchOptions::default())?;
+        commit_file(&mut ctx, "file.txt", "1\n2\n3\n4\nfive\n", "Main edit")?;
+        let main = commit_file(&mut ctx, "main.txt", "main\n", "Main file")?;
+
+        merge(&mut ctx, "feature", &MergeOptions::default())?;
+        let head = ctx.refs.resolve_commit("HEAD")?;
+        assert_eq!(parents(&ctx, head)?, vec![main, feature]);
+        assert_eq!(fs::read_to_string(root.join("file.txt"))?, "one\n2\n3\n4\nfive\n");
+        assert_eq!(fs::read_to_string(root.join("feature.txt"))?, "feature\n");
+        assert_eq!(fs::read_to_string(root.join("main.txt"))?, "main\n");
+        assert!(status::collect(&ctx.repo)?.is_clean());
+        assert!(ctx.out.captured().contains("file.txt    | 2 +-"), "{}", ctx.out.captured());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_conflicts_stop_until_committed_or_aborted() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let root = temp_dir.path().to_path_buf();
+        commit_file(&mut ctx, "file.txt", "1\n2\n3\n", "Base")?;
+        create_and_switch(&mut ctx, "feature", None, &SwitchOptions::default())?;
+        commit_file(&mut ctx, "file.txt", "feature\n2\n3\n", "Feature edit")?;
+        let feature = commit_file(&mut ctx, "feature.txt", "feature\n", "Feature file")?;
+        switch(&mut ctx, "main", &SwitchOptions::default())?;
+        let main = commit_file(&mut ctx, "file.txt", "main\n2\n3\n", "Main edit")?;
+        fs::write(root.join("notes.txt"), "untracked\n")?;
+
+        let error = merge(&mut ctx, "feature", &MergeOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("Automatic merge failed"), "{}", error);
+        assert!(ctx.out.captured().contains("CONFLICT: Merge conflict in file.txt\n"), "{}", ctx.out.captured());
+        assert_eq!(fs::read_to_string(root.join("file.txt"))?, "<<<<<<< HEAD\nmain\n=======\nfeature\n>>>>>>> feature\n2\n3\n");
+        assert_eq!(fs::read_to_string(root.join("feature.txt"))?, "feature\n");
+        assert_eq!(fs::read_to_string(ctx.repo.git_dir.join("MERGE_HEAD"))?, format!("{}\n", feature));
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, main);
+        let error = merge(&mut ctx, "feature", &MergeOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+
+        // --abort puts back the work tree and index from before the merge
+        abort_merge(&mut ctx)?;
+        assert_eq!(fs::read_to_string(root.join("file.txt"))?, "main\n2\n3\n");
+        assert!(!root.join("feature.txt").exists());
+        assert_eq!(fs::read_to_string(root.join("notes.txt"))?, "untracked\n");
+        assert!(!ctx.repo.git_dir.join("MERGE_HEAD").exists());
+        let report = status::collect(&ctx.repo)?;
+        assert_eq!(report.untracked, vec![PathBuf::from("notes.txt")]);
+        assert!(report.entries.is_empty());
+        assert_eq!(abort_merge(&mut ctx).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        // Committing the resolved files concludes the merge with both parents
+        merge(&mut ctx, "feature", &MergeOptions::default()).unwrap_err();
+        fs::write(root.join("file.txt"), "both\n2\n3\n")?;
+        crate::cobra::commands::add::run(&mut ctx, &["file.txt".to_string()], &Default::default())?;
+        crate::cobra::commands::commit::run(&mut ctx, None, &Default::default())?;
+        let head = ctx.refs.resolve_commit("HEAD")?;
+        assert_eq!(parents(&ctx, head)?, vec![main, feature]);
+        let Object::Commit { message, .. } = ctx.repo.read_object(head)? else {
+            panic!("not a commit");
+        };
+        assert_eq!(message, "Merge branch 'feature'\n");
+        assert!(!ctx.repo.git_dir.join("MERGE_HEAD").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicted_paths_stay_unmerged_until_added() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let root = temp_dir.path().to_path_buf();
+        commit_file(&mut ctx, "file.txt", "1\n2\n3\n", "Base")?;
+        commit_file(&mut ctx, "other.txt", "1\n", "Other")?;
+        create_and_switch(&mut ctx, "feature", None, &SwitchOptions::default())?;
+        commit_file(&mut ctx, "file.txt", "1\nfeature\n3\n", "Feature edit")?;
+        commit_file(&mut ctx, "other.txt", "feature\n", "Feature other")?;
+        switch(&mut ctx, "main", &SwitchOptions::default())?;
+        commit_file(&mut ctx, "file.txt", "1\nmain\n3\n", "Main edit")?;
+        commit_file(&mut ctx, "other.txt", "main\n", "Main other")?;
+        merge(&mut ctx, "feature", &MergeOptions::default()).unwrap_err();
+
+        let report = status::collect(&ctx.repo)?;
+        assert!(report.merging);
+        let mut long = Vec::new();
+        status::write_long(&report, &mut long)?;
+        let long = String::from_utf8_lossy(&long);
+        assert!(long.contains("You have unmerged paths."), "{}", long);
+        assert!(long.contains("Unmerged paths:\n  (use \"cobra add <file>...\" to mark resolution)\n\tboth modified:   file.txt\n\tboth modified:   other.txt\n"), "{}", long);
+        assert!(!long.contains("Changes not staged"), "{}", long);
+        let mut short = Vec::new();
+        status::write_short(&report, &Default::default(), &mut short)?;
+        assert_eq!(String::from_utf8_lossy(&short), "UU file.txt\nUU other.txt\n");
+
+        // Commit refuses while any path is unmerged, naming the ones left
+        crate::cobra::commands::add::run(&mut ctx, &["file.txt".to_string()], &Default::default())?;
+        let error = crate::cobra::commands::commit::run(&mut ctx, None, &Default::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("unmerged files: other.txt\n"), "{}", error);
+
+        fs::write(root.join("other.txt"), "both\n")?;
+        crate::cobra::commands::add::run(&mut ctx, &["other.txt".to_string()], &Default::default())?;
+        let report = status::collect(&ctx.repo)?;
+        assert!(report.unmerged.is_empty());
+        let mut long = Vec::new();
+        status::write_long(&report, &mut long)?;
+        assert!(String::from_utf8_lossy(&long).contains("All conflicts fixed but you are still merging."));
+        crate::cobra::commands::commit::run(&mut ctx, None, &Default::default())?;
+        assert!(!ctx.repo.git_dir.join("MERGE_CONFLICTS").exists());
+        assert!(status::collect(&ctx.repo)?.is_clean());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_ff_config_sets_the_default() -> io::Result<()> {
+        for (value, merge_commit) in [("false", true), ("only", false), ("true", false)] {
+            let (_temp_dir, mut ctx, main, feature) = merge_fixture(false)?;
+            let config = ctx.repo.git_dir.join("config");
+            fs::write(&config, fs::read_to_string(&config)? + &format!("[merge]\n\tff = {}\n", value))?;
+            merge(&mut ctx, "feature", &MergeOptions::default())?;
+            let head = ctx.refs.resolve_commit("HEAD")?;
+            assert_eq!(head != feature, merge_commit, "merge.ff = {}", value);
+            if merge_commit {
+                assert_eq!(parents(&ctx, head)?, vec![main, feature]);
+            }
+        }
+
+        // merge.ff = only refuses diverged histories unless a flag overrides it
+        let (_temp_dir, mut ctx, main, _feature) = merge_fixture(true)?;
+        let config = ctx.repo.git_dir.join("config");
+        fs::write(&config, fs::read_to_string(&config)? + "[merge]\n\tff = only\n")?;
+        let error = merge(&mut ctx, "feature", &MergeOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("Not possible to fast-forward"), "{}", error);
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, main);
+        merge(&mut ctx, "feature", &MergeOptions { fast_forward: Some(FastForward::Allow), ..Default::default() })?;
+        assert_ne!(ctx.refs.resolve_commit("HEAD")?, main);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebase_commit_records_a_real_tree() -> io::Result<()> {
+        let (temp_dir, mut ctx, main, feature) = merge_fixture(true)?;
+        switch(&mut ctx, "feature", &SwitchOptions::default())?;
+        rebase(&mut ctx, "main")?;
+
+        let head = ctx.refs.resolve_commit("HEAD")?;
+        assert_ne!(head, feature);
+        assert_eq!(parents(&ctx, head)?, vec![main]);
+        let tree = commit_tree(&ctx.repo.git_dir, head)?;
+        assert!(matches!(ctx.repo.read_object(tree)?, Object::Tree(_)));
+
+        // The result holds both sides' changes and is checked out
+        let files: Vec<PathBuf> = flatten_tree(&ctx.repo.git_dir, tree)?.into_keys().collect();
+        assert_eq!(files, vec![PathBuf::from("feature.txt"), PathBuf::from("file.txt"), PathBuf::from("main.txt")]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file.txt"))?, "feature\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("main.txt"))?, "main\n");
+        assert!(status::collect(&ctx.repo)?.is_clean());
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+// Find commits not yet applied upstream
+use std::collections::HashSet;
+use std::io::{self, Write};
+use crate::cobra::core::{
+    ancestry::commits_between,
+    object::Object,
+    patch_id::commit_patch_id,
+};
+use crate::cobra::state::CommandContext;
+
+#[derive(Debug, Default)]
+pub struct CherryOptions {
+    /// Branch the commits would be merged into
+    pub upstream: String,
+    /// Branch holding the commits, HEAD by default
+    pub head: Option<String>,
+    /// Show each commit's subject (-v)
+    pub verbose: bool,
+}
+
+/// Lists the commits in head but not in upstream, oldest first. Commits
+/// whose change upstream already has under another name (cherry-picked or
+/// rebased) are marked `-`, the rest `+`. Merges are skipped.
+pub fn run(ctx: &mut CommandContext, options: &CherryOptions) -> io::Result<()> {
+    let upstream = ctx.refs.resolve_commit(&options.upstream)?;
+    let head = ctx.refs.resolve_commit(options.head.as_deref().unwrap_or("HEAD"))?;
+
+    let mut applied = HashSet::new();
+    for commit in commits_between(&ctx.repo, head, upstream)? {
+        if let Some(id) = commit_patch_id(&ctx.repo, commit)? {
+            applied.insert(id);
+        }
+    }
+
+    for hash in commits_between(&ctx.repo, upstream, head)? {
+        let (parents, message) = match Object::read_from_objects_dir(&ctx.repo.git_dir, hash)? {
+            Object::Commit { parents, message, .. } => (parents, message),
+            _ => continue,
+        };
+        if parents.len() > 1 {
+            continue;
+        }
+
+        let mark = match commit_patch_id(&ctx.repo, hash)? {
+            Some(id) if applied.contains(&id) => '-',
+            _ => '+',
+        };
+        if options.verbose {
+            writeln!(ctx.out, "{} {} {}", mark, hash, message.lines().next().unwrap_or(""))?;
+        } else {
+            writeln!(ctx.out, "{} {}", mark, hash)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_cherry_marks_picked_commits() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+
+        fs::write(root.join("a.txt"), "a\n")?;
+        fs::write(root.join("b.txt"), "b\n")?;
+        add_file(&mut repo, Path::new("a.txt"))?;
+        add_file(&mut repo, Path::new("b.txt"))?;
+        commit(&repo, "Base")?;
+        let refs = repo.ref_store();
+        refs.create_branch("topic")?;
+
+        // main gets the same a.txt change the topic will make, then its own
+        fs::write(root.join("a.txt"), "a\nfix\n")?;
+        add_file(&mut repo, Path::new("a.txt"))?;
+        commit(&repo, "Fix a (picked)")?;
+        fs::write(root.join("c.txt"), "c\n")?;
+        add_file(&mut repo, Path::new("c.txt"))?;
+        commit(&repo, "Add c")?;
+
+        // topic: the same fix on the old base, then something new
+        refs.attach_head("refs/heads/topic", "test")?;
+        repo.index = crate::cobra::core::index::Index::new();
+        fs::remove_file(root.join("c.txt"))?;
+        add_file(&mut repo, Path::new("a.txt"))?;
+        add_file(&mut repo, Path::new("b.txt"))?;
+        let fix = commit(&repo, "Fix a")?;
+        fs::write(root.join("b.txt"), "b\nnew\n")?;
+        add_file(&mut repo, Path::new("b.txt"))?;
+        let new = commit(&repo, "Extend b")?;
+
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        run(&mut ctx, &CherryOptions { upstream: "main".to_string(), head: None, verbose: true })?;
+        assert_eq!(ctx.out.captured(), format!("- {} Fix a\n+ {} Extend b\n", fix, new));
+        Ok(())
+    }
+}
@@ -1,67 +1,553 @@
-use std::io;
+use std::fs;
+use std::io::{self, Read};
 use crate::cobra::core::{
+    config::Config,
     repository::Repository,
     object::Object,
-    ref_store::RefStore,
-    tree::build_tree_from_index,
+    oid::Oid,
+    tree::build_tree_from_index_in,
     signature::Signature,
+    hooks::run_hook,
+    merge::MergeState,
+    signoff::add_signoff,
 };
+use crate::cobra::commands::{add::stage_tracked, diff::{diff_staged, DiffOptions}, status};
+use crate::cobra::state::CommandContext;
+use crate::cobra::utils::editor::{edit_file, editor_command};
+use crate::cobra::messages;
 
-pub fn run(message: &str) -> io::Result<()> {
-    // Open repository
-    let repo = Repository::open(".")?;
-    let ref_store = RefStore::new(repo.git_dir.clone());
+/// Settings for recording a commit
+#[derive(Debug, Clone)]
+pub struct CommitOptions {
+    /// Refuse commits whose header fields would be malformed (disable with --no-validate)
+    pub validate: bool,
+    /// Run the pre-commit hook (disable with --no-verify)
+    pub verify: bool,
+    /// Add the committer's Signed-off-by trailer (-s)
+    pub signoff: bool,
+    /// Replace HEAD's commit instead of adding one on top of it (--amend)
+    pub amend: bool,
+    /// Stage changed and deleted tracked files first (-a/--all)
+    pub all: bool,
+    /// Editor for writing the message; COBRA_EDITOR, core.editor, VISUAL or
+    /// EDITOR when None
+    pub editor: Option<String>,
+    /// Show the staged diff below the message in the editor (-v);
+    /// commit.verbose when None
+    pub verbose: Option<bool>,
+}
 
-    // Build tree from index
-    let tree = build_tree_from_index(&repo)?;
-    let tree_hash = tree.hash();
-    tree.write_to_objects_dir(&repo.git_dir)?;
+impl Default for CommitOptions {
+    fn default() -> Self {
+        CommitOptions { validate: true, verify: true, signoff: false, amend: false, all: false, editor: None, verbose: None }
+    }
+}
+
+/// The message file the editor is opened on, as in git
+pub const COMMIT_EDITMSG: &str = "COMMIT_EDITMSG";
+
+/// Everything below this line of an edited message is ignored, as in git
+const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+/// Commits the index with `message`. Without one, --amend keeps the
+/// amended commit's message, a merge that stopped on conflicts is
+/// concluded with the message it prepared, and otherwise the message is
+/// written in the editor. An empty message aborts the commit. With -a the
+/// tracked files are staged first, but the index is only saved once the
+/// message has been accepted.
+pub fn run(ctx: &mut CommandContext, message: Option<&str>, options: &CommitOptions) -> io::Result<()> {
+    let mut staged = Vec::new();
+    let unstaged_index = match options.all {
+        true => {
+            let index = ctx.repo.index.clone();
+            staged = stage_tracked(&mut ctx.repo)?;
+            Some(index)
+        }
+        false => None,
+    };
+    let message = match commit_message(ctx, message, options) {
+        Ok(message) => message,
+        Err(e) => {
+            if let Some(index) = unstaged_index {
+                ctx.repo.index = index;
+            }
+            return Err(e);
+        }
+    };
+    if !staged.is_empty() {
+        ctx.repo.save_index()?;
+        MergeState::mark_resolved(&ctx.repo, &staged)?;
+    }
+    let commit_hash = commit_with_options(&ctx.repo, &message, options)?;
+
+    let subject = message.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+    ctx.out.note(format_args!("{}", messages::committed(&ctx.repo.short_id(commit_hash), subject)))?;
+
+    Ok(())
+}
+
+/// The message given, or else the amended commit's, the merge's or one
+/// written in the editor. Empty messages are an error.
+fn commit_message(ctx: &CommandContext, message: Option<&str>, options: &CommitOptions) -> io::Result<String> {
+    let message = match message {
+        Some(message) => message.to_string(),
+        None => match options.amend {
+            true => amended_commit(&ctx.repo)?.message,
+            false => match MergeState::read(&ctx.repo)? {
+                Some(state) => state.message,
+                None => edit_message(ctx, options)?,
+            },
+        },
+    };
+    if message.trim().is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, messages::empty_commit_message()));
+    }
+    Ok(message)
+}
+
+/// Has the user write the message in the editor, starting from a template
+/// that summarizes the status in comments, followed in verbose mode by the
+/// staged diff below a scissors line. Comment lines, surrounding blank
+/// lines and everything from the scissors line on are dropped from the result.
+fn edit_message(ctx: &CommandContext, options: &CommitOptions) -> io::Result<String> {
+    let config = Config::load(&ctx.repo.git_dir)?;
+    let mut summary = Vec::new();
+    status::write_long(&status::collect(&ctx.repo)?, &mut summary)?;
+    let mut template = format!(
+        "\n{}#\n{}",
+        comment_lines(&messages::commit_message_help()),
+        comment_lines(&String::from_utf8_lossy(&summary)),
+    );
+    if options.verbose.or_else(|| config.get_bool("commit.verbose")).unwrap_or(false) {
+        let mut diff = Vec::new();
+        diff_staged(&ctx.repo, None, &DiffOptions::default(), &mut diff)?;
+        template.push_str(&format!("{}\n{}", SCISSORS, comment_lines(&messages::commit_scissors_help())));
+        template.push_str(&String::from_utf8_lossy(&diff));
+    }
+    let path = ctx.repo.git_dir.join(COMMIT_EDITMSG);
+    fs::write(&path, template)?;
+
+    let editor = match &options.editor {
+        Some(editor) => editor.clone(),
+        None => editor_command(&config),
+    };
+    edit_file(&editor, &path)?;
+    Ok(cleanup_message(&fs::read_to_string(&path)?, true))
+}
+
+/// Joins the values of several -m options into one message, each its own
+/// paragraph
+pub fn join_paragraphs(paragraphs: &[&str]) -> String {
+    paragraphs.iter()
+        .map(|paragraph| paragraph.trim_end_matches('\n'))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Reads the message given with -F, from standard input when `path` is "-"
+pub fn read_message_file(path: &str, stdin: &mut dyn Read) -> io::Result<String> {
+    let mut text = String::new();
+    let read = match path {
+        "-" => stdin.read_to_string(&mut text).map(|_| ()),
+        _ => fs::read_to_string(path).map(|content| text = content),
+    };
+    read.map_err(|e| io::Error::new(e.kind(), format!("Could not read message file '{}': {}", path, e)))?;
+    Ok(cleanup_message(&text, false))
+}
+
+/// Tidies a message the way git does before committing it: trailing
+/// whitespace goes, runs of blank lines become one, and leading and
+/// trailing blank lines are dropped. With `strip_comments`, lines starting
+/// with `#` go too, and so does everything from a scissors line on.
+pub fn cleanup_message(text: &str, strip_comments: bool) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        if strip_comments && line == SCISSORS {
+            break;
+        }
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+/// Turns `text` into `#` comment lines for a file the user edits
+pub fn comment_lines(text: &str) -> String {
+    text.lines()
+        .map(|line| match line {
+            "" => "#\n".to_string(),
+            line if line.starts_with('\t') => format!("#{}\n", line),
+            line => format!("# {}\n", line),
+        })
+        .collect()
+}
+
+/// Records the current index as a new commit on HEAD and returns its hash
+pub fn commit(repo: &Repository, message: &str) -> io::Result<Oid> {
+    commit_with_options(repo, message, &CommitOptions::default())
+}
+
+pub fn commit_with_options(repo: &Repository, message: &str, options: &CommitOptions) -> io::Result<Oid> {
+    let ref_store = repo.ref_store();
+
+    let unmerged = MergeState::unmerged(repo)?;
+    if !unmerged.is_empty() {
+        let paths: Vec<String> = unmerged.iter().map(|unmerged| unmerged.path.display().to_string()).collect();
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, messages::unmerged_files(paths.join(", "))));
+    }
+
+    if options.verify {
+        run_hook(repo, "pre-commit", &[])?;
+    }
+
+    // An amended commit keeps its author; only the committer is new
+    let amended = if options.amend { Some(amended_commit(repo)?) } else { None };
+    let author = match &amended {
+        Some(commit) => commit.author.clone(),
+        None => Signature::author(&repo.git_dir)?,
+    };
+    let committer = Signature::committer(&repo.git_dir)?;
+    let (author, committer) = if options.validate {
+        (author.sanitized(), committer.sanitized())
+    } else {
+        (author, committer)
+    };
+
+    // Like git, store the message with a final newline
+    let message = if options.signoff {
+        add_signoff(message, &committer)
+    } else if message.ends_with('\n') {
+        message.to_string()
+    } else {
+        format!("{}\n", message)
+    };
+
+    // Nothing is written until the transaction commits, after validation
+    let mut transaction = repo.transaction().with_validation(options.validate);
+    let tree = build_tree_from_index_in(repo, &mut transaction)?;
+    let tree_hash = transaction.add(tree)?;
 
     // Get parent commit hash from HEAD
     let parent_hash = ref_store.read_head()?
         .and_then(|head_ref| {
-            if head_ref.starts_with("ref: ") {
+            if let Some(branch_ref) = head_ref.strip_prefix("ref: ") {
                 // HEAD points to a branch
-                let branch_ref = &head_ref[5..];
                 ref_store.read_ref(branch_ref).ok().flatten()
             } else {
                 // HEAD points directly to a commit
-                Some(head_ref)
+                head_ref.parse().ok()
             }
-        })
-        .unwrap_or_default();
+        });
 
-    // Create author and committer signatures
-    let author = Signature::new("Your Name".to_string(), "you@example.com".to_string());
-    let committer = author.clone();
+    // Concluding a merge that stopped on conflicts records its other tip
+    // too, and amending takes over the amended commit's parents
+    let merge_state = MergeState::read(repo)?;
+    let kind = if amended.is_some() {
+        " (amend)"
+    } else if merge_state.is_some() {
+        " (merge)"
+    } else if parent_hash.is_none() {
+        " (initial)"
+    } else {
+        ""
+    };
+    let reason = format!("commit{}: {}", kind, message.lines().next().unwrap_or_default());
+    let parents = match amended {
+        Some(commit) => commit.parents,
+        None => {
+            let mut parents: Vec<Oid> = parent_hash.into_iter().collect();
+            parents.extend(merge_state.iter().map(|state| state.head));
+            parents
+        }
+    };
 
     // Create commit object
     let commit = Object::new_commit(
-        tree_hash.clone(),
-        if parent_hash.is_empty() { vec![] } else { vec![parent_hash.clone()] },
+        tree_hash,
+        parents,
         author,
         committer,
-        message.to_string(),
+        message,
     );
 
-    // Write commit object
-    let commit_hash = commit.hash();
-    commit.write_to_objects_dir(&repo.git_dir)?;
+    // Write the objects, then move HEAD's branch (or a detached HEAD) unless
+    // another process committed in the meantime
+    let commit_hash = transaction.add(commit)?;
+    transaction.update_head(parent_hash, commit_hash, &reason)?;
+    transaction.commit()?;
+    if merge_state.is_some() {
+        MergeState::clear(repo)?;
+    }
 
-    // Update HEAD
-    let head_ref = ref_store.read_head()?
-        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HEAD reference not found"))?;
+    Ok(commit_hash)
+}
 
-    if head_ref.starts_with("ref: ") {
-        // HEAD points to a branch, update the branch
-        let branch_ref = &head_ref[5..];
-        ref_store.update_ref(branch_ref, &commit_hash)?;
-    } else {
-        // HEAD points directly to a commit, update HEAD
-        ref_store.update_head(&commit_hash)?;
+/// The parts of HEAD's commit that --amend carries over
+struct AmendedCommit {
+    parents: Vec<Oid>,
+    author: Signature,
+    message: String,
+}
+
+/// Reads the commit --amend replaces. Fails on an unborn branch and while
+/// a merge is waiting to be concluded.
+fn amended_commit(repo: &Repository) -> io::Result<AmendedCommit> {
+    if MergeState::in_progress(repo) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, messages::amend_during_merge()));
     }
+    let head = match repo.ref_store().resolve_commit("HEAD") {
+        Ok(head) => head,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(io::Error::new(io::ErrorKind::NotFound, messages::nothing_to_amend()));
+        }
+        Err(e) => return Err(e),
+    };
+    match repo.read_object(head)? {
+        Object::Commit { parents, author, message, .. } => Ok(AmendedCommit { parents, author, message }),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("HEAD {} is not a commit", head))),
+    }
+}
 
-    println!("[{}] {}", &commit_hash[..7], message);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use crate::cobra::commands::add::add_file;
+    use crate::cobra::commands::branch::{self, MergeOptions, SwitchOptions};
+    use crate::cobra::core::config::ConfigDocument;
+    use crate::cobra::core::tree::{commit_tree, flatten_tree};
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::{commit_file, init_repo, temp_repo};
+    use tempfile::TempDir;
 
-    Ok(())
-} 
\ No newline at end of file
+    fn read_commit(repo: &Repository, hash: Oid) -> io::Result<(Vec<Oid>, Signature, String)> {
+        match repo.read_object(hash)? {
+            Object::Commit { parents, author, message, .. } => Ok((parents, author, message)),
+            _ => Err(io::Error::other("not a commit")),
+        }
+    }
+
+    #[test]
+    fn test_amend_replaces_the_last_commit() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let amend = CommitOptions { amend: true, ..Default::default() };
+        let error = run(&mut ctx, Some("First"), &amend).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains("nothing to amend"), "{}", error);
+
+        let first = commit_file(&mut ctx, "file.txt", "one\n", "First")?;
+        // Someone else wrote the commit being amended, long ago
+        fs::write(temp_dir.path().join("file.txt"), "two\n")?;
+        add_file(&mut ctx.repo, Path::new("file.txt"))?;
+        let mut transaction = ctx.repo.transaction();
+        let tree = build_tree_from_index_in(&ctx.repo, &mut transaction)?;
+        let tree = transaction.add(tree)?;
+        let mut old_author = Signature::new("Ada".to_string(), "ada@example.com".to_string());
+        old_author.timestamp = 1_000_000_000;
+        let second = transaction.add(Object::new_commit(tree, vec![first], old_author.clone(), Signature::committer(&ctx.repo.git_dir)?, "Second".to_string()))?;
+        transaction.update_head(Some(first), second, "commit: Second")?;
+        transaction.commit()?;
+        fs::write(temp_dir.path().join("extra.txt"), "extra\n")?;
+        add_file(&mut ctx.repo, Path::new("extra.txt"))?;
+
+        // Without -m the old message is kept; the index becomes the new tree
+        run(&mut ctx, None, &amend)?;
+        let amended = ctx.refs.resolve_commit("HEAD")?;
+        assert_ne!(amended, second);
+        let (parents, author, message) = read_commit(&ctx.repo, amended)?;
+        assert_eq!(parents, vec![first]);
+        assert_eq!((author.name, author.email, author.timestamp), (old_author.name, old_author.email, old_author.timestamp));
+        assert_eq!(message, "Second\n");
+        let files = flatten_tree(&ctx.repo.git_dir, commit_tree(&ctx.repo.git_dir, amended)?)?;
+        assert!(files.contains_key(Path::new("extra.txt")));
+
+        run(&mut ctx, Some("Second, reworded"), &amend)?;
+        let (parents, _, message) = read_commit(&ctx.repo, ctx.refs.resolve_commit("HEAD")?)?;
+        assert_eq!(parents, vec![first]);
+        assert_eq!(message, "Second, reworded\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_amend_keeps_both_parents_of_a_merge() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        commit_file(&mut ctx, "file.txt", "base\n", "Base")?;
+        branch::create_and_switch(&mut ctx, "feature", None, &SwitchOptions::default())?;
+        let feature = commit_file(&mut ctx, "feature.txt", "feature\n", "Feature")?;
+        branch::switch(&mut ctx, "main", &SwitchOptions::default())?;
+        let main = commit_file(&mut ctx, "main.txt", "main\n", "Main")?;
+        branch::merge(&mut ctx, "feature", &MergeOptions::default())?;
+
+        run(&mut ctx, Some("Merge feature"), &CommitOptions { amend: true, ..Default::default() })?;
+        let (parents, _, message) = read_commit(&ctx.repo, ctx.refs.resolve_commit("HEAD")?)?;
+        assert_eq!(parents, vec![main, feature]);
+        assert_eq!(message, "Merge feature\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_all_stages_tracked_changes_only() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        commit_file(&mut ctx, "kept.txt", "kept\n", "Kept")?;
+        commit_file(&mut ctx, "edited.txt", "before\n", "Edited")?;
+        commit_file(&mut ctx, "deleted.txt", "deleted\n", "Deleted")?;
+        fs::write(temp_dir.path().join("edited.txt"), "after the edit\n")?;
+        fs::remove_file(temp_dir.path().join("deleted.txt"))?;
+        fs::write(temp_dir.path().join("untracked.txt"), "new\n")?;
+
+        // Nothing is staged when no message is accepted
+        let aborted = CommitOptions { all: true, editor: Some("false".to_string()), ..Default::default() };
+        assert!(run(&mut ctx, None, &aborted).is_err());
+        for repo in [&ctx.repo, &Repository::open(temp_dir.path().to_str().unwrap())?] {
+            assert!(repo.index.contains(Path::new("deleted.txt")));
+            let report = status::collect(repo)?;
+            assert!(report.entries.iter().all(|entry| entry.staged_code() == '.'), "{:?}", report.entries);
+        }
+
+        run(&mut ctx, Some("Update"), &CommitOptions { all: true, ..Default::default() })?;
+        let head = ctx.refs.resolve_commit("HEAD")?;
+        let files = flatten_tree(&ctx.repo.git_dir, commit_tree(&ctx.repo.git_dir, head)?)?;
+        let paths: Vec<&Path> = files.keys().map(|path| path.as_path()).collect();
+        assert_eq!(paths, [Path::new("edited.txt"), Path::new("kept.txt")]);
+        let Object::Blob(content) = ctx.repo.read_object(files[Path::new("edited.txt")].hash)? else {
+            panic!("not a blob");
+        };
+        assert_eq!(content, b"after the edit\n");
+        assert!(!ctx.repo.index.contains(Path::new("untracked.txt")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_all_stages_a_rewrite_in_the_same_second() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        commit_file(&mut ctx, "file.txt", "one\n", "One")?;
+        let path = temp_dir.path().join("file.txt");
+        let staged = fs::metadata(&path)?.modified()?;
+
+        // Same size, same inode and, pinned here, the same mtime as the
+        // index entry and the index itself: only the content tells
+        fs::write(&path, "two\n")?;
+        fs::File::options().write(true).open(&path)?.set_modified(staged)?;
+        fs::File::options().write(true).open(ctx.repo.git_dir.join("index"))?.set_modified(staged)?;
+        assert!(ctx.repo.index.get_entry(Path::new("file.txt")).unwrap().stat_matches(&fs::metadata(&path)?));
+
+        run(&mut ctx, Some("Two"), &CommitOptions { all: true, ..Default::default() })?;
+        let head = ctx.refs.resolve_commit("HEAD")?;
+        let files = flatten_tree(&ctx.repo.git_dir, commit_tree(&ctx.repo.git_dir, head)?)?;
+        let Object::Blob(content) = ctx.repo.read_object(files[Path::new("file.txt")].hash)? else {
+            panic!("not a blob");
+        };
+        assert_eq!(content, b"two\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_message_written_in_the_editor() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scripts = TempDir::new()?;
+        let repo = init_repo(temp_dir.path())?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        fs::write(temp_dir.path().join("file.txt"), "one\n")?;
+        add_file(&mut ctx.repo, Path::new("file.txt"))?;
+
+        // The editor keeps the template it was given and writes a message
+        // with stray whitespace and comments around it
+        let script = scripts.path().join("editor.sh");
+        fs::write(&script, format!(
+            "cp \"$1\" {}/template\nprintf 'Subject  \\n\\n\\n# dropped\\nBody\\n\\n' > \"$1\"\n",
+            scripts.path().display(),
+        ))?;
+        let options = CommitOptions { editor: Some(format!("sh {}", script.display())), ..Default::default() };
+        run(&mut ctx, None, &options)?;
+        let (_, _, message) = read_commit(&ctx.repo, ctx.refs.resolve_commit("HEAD")?)?;
+        assert_eq!(message, "Subject\n\nBody\n");
+        let template = fs::read_to_string(scripts.path().join("template"))?;
+        assert!(template.starts_with("\n# Please enter the commit message"), "{}", template);
+        assert!(template.contains("# On branch main\n"), "{}", template);
+        assert!(template.contains("#\tnew file:   file.txt\n"), "{}", template);
+
+        // An editor that fails, or leaves only comments, commits nothing
+        let head = ctx.refs.resolve_commit("HEAD")?;
+        fs::write(temp_dir.path().join("file.txt"), "two\n")?;
+        add_file(&mut ctx.repo, Path::new("file.txt"))?;
+        let failing = CommitOptions { editor: Some("false".to_string()), ..Default::default() };
+        assert!(run(&mut ctx, None, &failing).unwrap_err().to_string().contains("exited with"));
+        let unchanged = CommitOptions { editor: Some("true".to_string()), ..Default::default() };
+        let error = run(&mut ctx, None, &unchanged).unwrap_err();
+        assert_eq!(error.to_string(), "Aborting commit due to empty commit message.");
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, head);
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_verbose_shows_the_staged_diff() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scripts = TempDir::new()?;
+        let repo = init_repo(temp_dir.path())?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        commit_file(&mut ctx, "file.txt", "one\n", "First")?;
+        fs::write(temp_dir.path().join("file.txt"), "two\n")?;
+        add_file(&mut ctx.repo, Path::new("file.txt"))?;
+        let mut config = ConfigDocument::load(&ctx.repo.git_dir.join("config"))?;
+        config.set("commit.verbose", "true")?;
+        config.save(&ctx.repo.git_dir.join("config"))?;
+
+        // The message goes on top; the diff below the scissors is not part of it
+        let script = scripts.path().join("editor.sh");
+        fs::write(&script, format!(
+            "cp \"$1\" {}/template\n{{ echo Second; cat \"$1\"; }} > \"$1.new\" && mv \"$1.new\" \"$1\"\n",
+            scripts.path().display(),
+        ))?;
+        let options = CommitOptions { editor: Some(format!("sh {}", script.display())), ..Default::default() };
+        run(&mut ctx, None, &options)?;
+        let (_, _, message) = read_commit(&ctx.repo, ctx.refs.resolve_commit("HEAD")?)?;
+        assert_eq!(message, "Second\n");
+        let template = fs::read_to_string(scripts.path().join("template"))?;
+        let (_, diff) = template.split_once(&format!("{}\n", SCISSORS)).expect(&template);
+        assert!(diff.contains("-one\n+two\n"), "{}", diff);
+        Ok(())
+    }
+
+    #[test]
+    fn test_several_messages_become_paragraphs() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        fs::write(temp_dir.path().join("file.txt"), "one\n")?;
+        add_file(&mut ctx.repo, Path::new("file.txt"))?;
+
+        let message = join_paragraphs(&["Subject", "Body line one\nline two\n", "Signed-off-by: Ada <ada@example.com>"]);
+        assert_eq!(message, "Subject\n\nBody line one\nline two\n\nSigned-off-by: Ada <ada@example.com>");
+        run(&mut ctx, Some(&message), &CommitOptions::default())?;
+        let head = ctx.refs.resolve_commit("HEAD")?;
+        assert_eq!(read_commit(&ctx.repo, head)?.2, format!("{}\n", message));
+        assert!(ctx.out.captured().ends_with("] Subject\n"), "{}", ctx.out.captured());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_message_file() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("message");
+        fs::write(&path, "\nFrom a file  \n\n# kept, nobody is editing\n")?;
+        assert_eq!(read_message_file(path.to_str().unwrap(), &mut io::empty())?, "From a file\n\n# kept, nobody is editing");
+        assert_eq!(read_message_file("-", &mut io::Cursor::new("From stdin\n"))?, "From stdin");
+        let missing = temp_dir.path().join("missing");
+        let error = read_message_file(missing.to_str().unwrap(), &mut io::empty()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+}
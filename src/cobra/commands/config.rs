@@ -0,0 +1,262 @@
+// Read, set and edit the repository or per-user configuration
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::cobra::core::config::{Config, ConfigDocument, ConfigEntry};
+use crate::cobra::core::repository::Repository;
+use crate::cobra::state::OutputHandle;
+use crate::cobra::utils::editor::{edit_file, editor_command};
+use crate::cobra::utils::tempfiles::{tmp_dir, TempFile};
+use crate::cobra::messages;
+
+#[derive(Debug, Default)]
+pub struct ConfigOptions {
+    /// Open the config file in an editor (--edit)
+    pub edit: bool,
+    /// Work on the per-user config instead of the repository's (--global)
+    pub global: bool,
+    /// Print every key and value (-l/--list)
+    pub list: bool,
+    /// Prefix printed values with the file they come from (--show-origin)
+    pub show_origin: bool,
+    /// Remove `key` (--unset)
+    pub unset: bool,
+    /// The `section.key` to read, set or unset
+    pub key: Option<String>,
+    /// The value to give `key`; it is printed when None
+    pub value: Option<String>,
+}
+
+/// The config file a command works on
+#[derive(Debug)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    /// The repository's metadata directory, for conditional includes; None
+    /// for the per-user config
+    pub git_dir: Option<PathBuf>,
+    /// The per-user config, read underneath `path` but never written; None
+    /// when `path` is the per-user config itself
+    pub global: Option<PathBuf>,
+    /// Where an edit is staged before it replaces the file
+    pub scratch_dir: PathBuf,
+}
+
+/// Runs outside a CommandContext so that `--global` works without a
+/// repository, and so that a config value that stops the repository from
+/// opening can still be fixed
+pub fn run(out: &mut OutputHandle, options: &ConfigOptions) -> io::Result<()> {
+    let file = if options.global {
+        let path = Config::global_path().ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            "Cannot locate the global config: neither XDG_CONFIG_HOME nor HOME is set",
+        ))?;
+        let scratch_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        ConfigFile { path, git_dir: None, global: None, scratch_dir }
+    } else {
+        let git_dir = Repository::find_git_dir(Path::new("."))?;
+        ConfigFile {
+            path: git_dir.join("config"),
+            scratch_dir: tmp_dir(&git_dir),
+            git_dir: Some(git_dir),
+            global: Config::global_path(),
+        }
+    };
+    run_on(out, &file, options)
+}
+
+/// Carries out `options` on `file`: --edit, --list, --unset, setting a
+/// value or printing one. Writes only touch `file.path`, while reads see the
+/// per-user values it does not override. Reading a key that is not set is a
+/// NotFound error.
+pub fn run_on(out: &mut OutputHandle, file: &ConfigFile, options: &ConfigOptions) -> io::Result<()> {
+    let config = || {
+        let mut paths: Vec<&Path> = file.global.iter().map(PathBuf::as_path).collect();
+        paths.push(&file.path);
+        Config::load_files(&paths, file.git_dir.as_deref())
+    };
+    let origin = |entry: &ConfigEntry| match (&entry.origin, options.show_origin) {
+        (Some(path), true) => format!("file:{}\t", path.display()),
+        _ => String::new(),
+    };
+    if options.edit {
+        edit_config(&file.path, &file.scratch_dir, &editor_command(&config()?))?;
+        return out.note(format_args!("{}", messages::config_updated(file.path.display())));
+    }
+    if options.list {
+        for entry in config()?.entries() {
+            writeln!(out, "{}{}={}", origin(entry), entry.key, entry.value)?;
+        }
+        return Ok(());
+    }
+
+    let key = options.key.as_deref().ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Nothing to do, give a key or use --list or --edit",
+    ))?;
+    let not_set = || io::Error::new(io::ErrorKind::NotFound, messages::config_key_not_set(key));
+    if options.unset {
+        let mut document = ConfigDocument::load(&file.path)?;
+        if !document.unset(key)? {
+            return Err(not_set());
+        }
+        return document.save(&file.path);
+    }
+    match &options.value {
+        Some(value) => {
+            let mut document = ConfigDocument::load(&file.path)?;
+            document.set(key, value)?;
+            document.save(&file.path)
+        }
+        None => {
+            let config = config()?;
+            let entry = config.get_entry(key).ok_or_else(not_set)?;
+            writeln!(out, "{}{}", origin(entry), entry.value)
+        }
+    }
+}
+
+/// Lets the user edit a copy of `path`, replacing the file only if the
+/// result parses. A rejected edit stays in `scratch_dir` so that it is not
+/// lost, and the error names the offending line and the kept copy.
+pub fn edit_config(path: &Path, scratch_dir: &Path, editor: &str) -> io::Result<()> {
+    let original = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let staged = TempFile::in_dir(scratch_dir, "config-edit")?.keep_on_failure(true);
+    fs::write(staged.path(), &original)?;
+
+    edit_file(editor, staged.path())?;
+
+    let edited = fs::read_to_string(staged.path())?;
+    if let Err(e) = ConfigDocument::parse(&edited) {
+        return Err(io::Error::new(
+            e.kind(),
+            format!("{}; {} was left unchanged, your edit is saved in {}", e, path.display(), staged.path().display()),
+        ));
+    }
+    if edited.as_bytes() == original.as_slice() {
+        return staged.finish();
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    staged.persist(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_edit_replaces_valid_config() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("config");
+        let scratch = temp_dir.path().join("tmp");
+        fs::write(&path, "# shared settings\n[core]\n\tabbrev = 9\n")?;
+
+        edit_config(&path, &scratch, "sed -i 's/abbrev = 9/abbrev = 12/'")?;
+        assert_eq!(fs::read_to_string(&path)?, "# shared settings\n[core]\n\tabbrev = 12\n");
+        assert_eq!(fs::read_dir(&scratch)?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_rejects_syntax_errors() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("config");
+        let scratch = temp_dir.path().join("tmp");
+        let original = "[core]\n\tabbrev = 9\n";
+        fs::write(&path, original)?;
+
+        let error = edit_config(&path, &scratch, "printf '[user\\n' >>").unwrap_err();
+        assert!(error.to_string().contains("Bad config line 3"), "{}", error);
+        assert_eq!(fs::read_to_string(&path)?, original);
+
+        // The rejected text is kept for the user to fix
+        let kept: Vec<_> = fs::read_dir(&scratch)?.collect::<io::Result<_>>()?;
+        assert_eq!(kept.len(), 1);
+        assert_eq!(fs::read_to_string(kept[0].path())?, "[core]\n\tabbrev = 9\n[user\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_set_unset_and_list() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file = ConfigFile {
+            path: temp_dir.path().join("config"),
+            git_dir: Some(temp_dir.path().to_path_buf()),
+            global: None,
+            scratch_dir: temp_dir.path().join("tmp"),
+        };
+        let key = |key: &str, value: Option<&str>| ConfigOptions {
+            key: Some(key.to_string()),
+            value: value.map(str::to_string),
+            ..Default::default()
+        };
+        let mut out = OutputHandle::buffer(false);
+        run_on(&mut out, &file, &key("user.name", Some("Ada Lovelace")))?;
+        run_on(&mut out, &file, &key("user.email", Some("ada@example.com")))?;
+        run_on(&mut out, &file, &key("merge.ff", Some("only")))?;
+        run_on(&mut out, &file, &key("user.Name", None))?;
+        assert_eq!(out.captured(), "Ada Lovelace\n");
+
+        let mut out = OutputHandle::buffer(false);
+        run_on(&mut out, &file, &ConfigOptions { unset: true, ..key("merge.ff", None) })?;
+        run_on(&mut out, &file, &ConfigOptions { list: true, ..Default::default() })?;
+        assert_eq!(out.captured(), "user.name=Ada Lovelace\nuser.email=ada@example.com\n");
+        assert_eq!(fs::read_to_string(&file.path)?, "[user]\n\tname = Ada Lovelace\n\temail = ada@example.com\n[merge]\n");
+
+        // Unset keys are reported, and keys must have a section
+        let error = run_on(&mut out, &file, &key("merge.ff", None)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        let error = run_on(&mut out, &file, &ConfigOptions { unset: true, ..key("merge.ff", None) }).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(run_on(&mut out, &file, &key("name", Some("x"))).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_global_values_show_through_with_their_origin() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let git_dir = temp_dir.path().join(".cobra");
+        let home = temp_dir.path().join("home");
+        let global = ConfigFile {
+            path: home.join(".cobraconfig"),
+            git_dir: None,
+            global: None,
+            scratch_dir: home.clone(),
+        };
+        let local = ConfigFile {
+            path: git_dir.join("config"),
+            git_dir: Some(git_dir.clone()),
+            global: Some(global.path.clone()),
+            scratch_dir: git_dir.join("tmp"),
+        };
+        let set = |key: &str, value: &str| ConfigOptions {
+            key: Some(key.to_string()),
+            value: Some(value.to_string()),
+            ..Default::default()
+        };
+        let mut out = OutputHandle::buffer(false);
+        run_on(&mut out, &global, &set("user.name", "Me"))?;
+        run_on(&mut out, &global, &set("user.email", "me@example.com"))?;
+        run_on(&mut out, &local, &set("user.email", "me@work.example"))?;
+        assert_eq!(fs::read_to_string(&global.path)?, "[user]\n\tname = Me\n\temail = me@example.com\n");
+
+        let list = ConfigOptions { list: true, show_origin: true, ..Default::default() };
+        run_on(&mut out, &local, &list)?;
+        run_on(&mut out, &local, &ConfigOptions { key: Some("user.email".to_string()), show_origin: true, ..Default::default() })?;
+        run_on(&mut out, &global, &ConfigOptions { key: Some("user.email".to_string()), ..Default::default() })?;
+        assert_eq!(out.captured(), format!(
+            "file:{global}\tuser.name=Me\nfile:{global}\tuser.email=me@example.com\nfile:{local}\tuser.email=me@work.example\n\
+             file:{local}\tme@work.example\nme@example.com\n",
+            global = global.path.display(),
+            local = local.path.display(),
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+// Serve repositories to `cobra fetch` and `git fetch` over the git protocol
+use std::io;
+use std::path::PathBuf;
+use crate::cobra::core::daemon::Daemon;
+use crate::cobra::state::OutputHandle;
+use crate::cobra::messages;
+
+/// Runs until interrupted. `listen` is "host:port"; only the `exports` can be fetched.
+pub fn run(out: &mut OutputHandle, listen: &str, exports: &[PathBuf]) -> io::Result<()> {
+    let daemon = Daemon::bind(listen, exports)?;
+    out.note(format_args!("{}", messages::daemon_listening(daemon.local_addr()?)))?;
+    for export in exports {
+        out.note(format_args!("{}", messages::daemon_exporting(export.display())))?;
+    }
+    daemon.serve()
+}
@@ -0,0 +1,468 @@
+// Show changes between the working tree, the index and commits
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use crate::cobra::core::{
+    attributes::Attributes,
+    repository::Repository,
+    object::{Object, TreeEntry},
+    index::normalize_mode,
+    oid::{HashAlgorithm, Oid},
+    diff::{hunks, split_lines, write_unified, Algorithm, CONTEXT_LINES},
+    tree::{commit_tree, flatten_tree, matches_paths, TreeChange},
+};
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+#[derive(Debug, Default)]
+pub struct DiffOptions {
+    /// Only show files equal to or below these paths, relative to the work tree root
+    pub paths: Vec<PathBuf>,
+    /// Ignore all whitespace when comparing lines (-w)
+    pub ignore_all_space: bool,
+    /// Line diff algorithm (--diff-algorithm); diff.algorithm when None
+    pub algorithm: Option<Algorithm>,
+    /// Compare the index instead of the work tree (--staged/--cached)
+    pub cached: bool,
+}
+
+/// One side of a file pair
+struct Side {
+    hash: Oid,
+    mode: u32,
+    content: Vec<u8>,
+}
+
+/// One side of a changed file, without its content
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version {
+    pub hash: Oid,
+    pub mode: u32,
+    /// The content is the work tree file rather than a stored blob
+    pub work_tree: bool,
+}
+
+/// A file that differs between the two sides being compared. A side is
+/// None where the file does not exist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePair {
+    pub path: PathBuf,
+    pub old: Option<Version>,
+    pub new: Option<Version>,
+}
+
+pub fn run(ctx: &mut CommandContext, rev: Option<&str>, options: DiffOptions) -> io::Result<()> {
+    let repo = &ctx.repo;
+    let mut options = options;
+    if options.algorithm.is_none() {
+        options.algorithm = Some(repo.diff_algorithm()?);
+    }
+    let rev = rev_or_path(ctx, rev, &mut options.paths);
+
+    match rev {
+        _ if options.cached => diff_staged(repo, rev, &options, &mut ctx.out),
+        Some(rev) => diff_worktree_against(repo, rev, &options, &mut ctx.out),
+        None => diff_worktree(repo, &options, &mut ctx.out),
+    }
+}
+
+/// `cobra diff <file>` is a path limit, not a revision: an argument that
+/// names no commit but an existing or tracked file moves to `paths`
+pub fn rev_or_path<'a>(ctx: &CommandContext, rev: Option<&'a str>, paths: &mut Vec<PathBuf>) -> Option<&'a str> {
+    let is_path = |rev: &str| ctx.repo.root_path.join(rev).exists() || ctx.repo.index.contains(Path::new(rev));
+    match rev {
+        Some(rev) if ctx.refs.resolve_commit(rev).is_err() && is_path(rev) => {
+            paths.push(PathBuf::from(rev));
+            None
+        }
+        rev => rev,
+    }
+}
+
+/// Lists the files that differ, in path order. Without `cached` the new
+/// side is the tracked work tree files, compared against the index or, if
+/// given, `rev`'s tree. With `cached` it is the index, compared against
+/// `rev` or HEAD (an unborn HEAD being an empty tree).
+pub fn changed_files(repo: &Repository, rev: Option<&str>, cached: bool, paths: &[PathBuf]) -> io::Result<Vec<FilePair>> {
+    let attributes = Attributes::load(repo)?;
+    let mut pairs = Vec::new();
+    if rev.is_none() && !cached {
+        let mut entries: Vec<_> = repo.index.entries()
+            .filter(|entry| matches_paths(&entry.path, paths))
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        for entry in entries {
+            let new = worktree_version(repo, &attributes, &entry.path)?;
+            if new.as_ref().is_some_and(|new| new.hash == entry.hash) {
+                continue;
+            }
+            let old = Version { hash: entry.hash, mode: normalize_mode(entry.mode), work_tree: false };
+            pairs.push(FilePair { path: entry.path.clone(), old: Some(old), new });
+        }
+        return Ok(pairs);
+    }
+
+    let commit = match repo.ref_store().resolve_commit(rev.unwrap_or("HEAD")) {
+        Ok(commit) => Some(commit),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && rev.is_none() => None,
+        Err(e) => return Err(e),
+    };
+    let tree = match commit {
+        Some(commit) => flatten_tree(&repo.git_dir, commit_tree(&repo.git_dir, commit)?)?,
+        None => Default::default(),
+    };
+
+    let all_paths: BTreeSet<&PathBuf> = tree.keys()
+        .chain(repo.index.entries().map(|entry| &entry.path))
+        .filter(|path| matches_paths(path, paths))
+        .collect();
+
+    for path in all_paths {
+        let new = match repo.index.get_entry(path) {
+            Some(entry) if cached => Some(Version { hash: entry.hash, mode: normalize_mode(entry.mode), work_tree: false }),
+            Some(_) => worktree_version(repo, &attributes, path)?,
+            // A file removed from the index counts as deleted even if it is still on disk
+            None => None,
+        };
+        let old = tree.get(path).map(|entry| Version { hash: entry.hash, mode: normalize_mode(entry.mode), work_tree: false });
+        match (&old, &new) {
+            (Some(old), Some(new)) if old.hash == new.hash => continue,
+            (None, None) => continue,
+            _ => pairs.push(FilePair { path: path.clone(), old, new }),
+        }
+    }
+    Ok(pairs)
+}
+
+/// Shows unstaged changes: tracked work tree files against the index
+pub fn diff_worktree(repo: &Repository, options: &DiffOptions, out: &mut dyn Write) -> io::Result<()> {
+    write_pairs(repo, changed_files(repo, None, false, &options.paths)?, options, out)
+}
+
+/// Shows all uncommitted changes relative to `rev`: the tracked work tree
+/// files against that commit's tree, including files only on one side
+pub fn diff_worktree_against(repo: &Repository, rev: &str, options: &DiffOptions, out: &mut dyn Write) -> io::Result<()> {
+    write_pairs(repo, changed_files(repo, Some(rev), false, &options.paths)?, options, out)
+}
+
+/// Shows staged changes: the index against `rev`'s tree, or HEAD's. On an
+/// unborn branch every staged file is new.
+pub fn diff_staged(repo: &Repository, rev: Option<&str>, options: &DiffOptions, out: &mut dyn Write) -> io::Result<()> {
+    write_pairs(repo, changed_files(repo, rev, true, &options.paths)?, options, out)
+}
+
+/// Shows the files changed between two trees, such as a commit and its parent
+pub fn diff_tree_changes(repo: &Repository, changes: Vec<TreeChange>, options: &DiffOptions, out: &mut dyn Write) -> io::Result<()> {
+    let version = |entry: TreeEntry| Version { hash: entry.hash, mode: normalize_mode(entry.mode), work_tree: false };
+    let pairs = changes.into_iter()
+        .filter(|change| matches_paths(&change.path, &options.paths))
+        .map(|change| FilePair { path: change.path, old: change.old.map(version), new: change.new.map(version) })
+        .collect();
+    write_pairs(repo, pairs, options, out)
+}
+
+fn write_pairs(repo: &Repository, pairs: Vec<FilePair>, options: &DiffOptions, out: &mut dyn Write) -> io::Result<()> {
+    let reads_work_tree = pairs.iter().any(|pair| pair.new.as_ref().is_some_and(|new| new.work_tree));
+    let attributes = if reads_work_tree { Attributes::load(repo)? } else { Attributes::default() };
+    for pair in pairs {
+        let old = pair.old.map(|version| load_side(repo, &attributes, &pair.path, version)).transpose()?;
+        let new = pair.new.map(|version| load_side(repo, &attributes, &pair.path, version)).transpose()?;
+        write_file_diff(repo, out, &pair.path, old, new, options)?;
+    }
+    Ok(())
+}
+
+/// Loads a side's content. Work tree files are converted the way `add`
+/// would store them, so line endings alone never show up as changes.
+fn load_side(repo: &Repository, attributes: &Attributes, path: &Path, version: Version) -> io::Result<Side> {
+    if version.work_tree {
+        let content = attributes.to_index(path, fs::read(repo.root_path.join(path))?);
+        Ok(Side { hash: version.hash, mode: version.mode, content })
+    } else {
+        blob_side(repo, version.hash, version.mode)
+    }
+}
+
+fn blob_side(repo: &Repository, hash: Oid, mode: u32) -> io::Result<Side> {
+    let content = match Object::read_from_objects_dir(&repo.git_dir, hash)? {
+        Object::Blob(content) => content,
+        _ => return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            messages::not_a_blob(&hash.to_string()),
+        )),
+    };
+    Ok(Side { hash, mode: normalize_mode(mode), content })
+}
+
+/// Hashes a work tree file as it would be staged, or returns None if it no
+/// longer exists
+fn worktree_version(repo: &Repository, attributes: &Attributes, path: &Path) -> io::Result<Option<Version>> {
+    let full_path = repo.root_path.join(path);
+    let metadata = match fs::metadata(&full_path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let hash = Object::new_blob(attributes.to_index(path, fs::read(&full_path)?)).hash();
+    Ok(Some(Version { hash, mode: normalize_mode(metadata.permissions().mode()), work_tree: true }))
+}
+
+fn short_hash(repo: &Repository, side: &Option<Side>) -> String {
+    repo.short_id(side.as_ref().map_or(Oid::null(HashAlgorithm::Sha1), |side| side.hash))
+}
+
+fn write_file_diff(repo: &Repository, out: &mut dyn Write, path: &Path, old: Option<Side>, new: Option<Side>, options: &DiffOptions) -> io::Result<()> {
+    let name = path.display();
+    let empty = Vec::new();
+    let old_content = old.as_ref().map_or(&empty, |side| &side.content);
+    let new_content = new.as_ref().map_or(&empty, |side| &side.content);
+
+    let old_lines = split_lines(old_content);
+    let new_lines = split_lines(new_content);
+    let algorithm = options.algorithm.unwrap_or_default();
+    let edits = if options.ignore_all_space {
+        let strip = |lines: &[&[u8]]| -> Vec<Vec<u8>> {
+            lines.iter()
+                .map(|line| line.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect())
+                .collect()
+        };
+        let old_stripped = strip(&old_lines);
+        let new_stripped = strip(&new_lines);
+        let old_refs: Vec<&[u8]> = old_stripped.iter().map(|line| line.as_slice()).collect();
+        let new_refs: Vec<&[u8]> = new_stripped.iter().map(|line| line.as_slice()).collect();
+        algorithm.diff_lines(&old_refs, &new_refs)
+    } else {
+        algorithm.diff_lines(&old_lines, &new_lines)
+    };
+    let hunks = hunks(&edits, CONTEXT_LINES);
+    let binary = old_content.contains(&0) || new_content.contains(&0);
+    if hunks.is_empty() && !binary && old.is_some() && new.is_some() {
+        // Only whitespace changed and -w is on
+        return Ok(());
+    }
+
+    writeln!(out, "diff --git a/{} b/{}", name, name)?;
+    match (&old, &new) {
+        (None, Some(new)) => writeln!(out, "new file mode {:06o}", new.mode)?,
+        (Some(old), None) => writeln!(out, "deleted file mode {:06o}", old.mode)?,
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            writeln!(out, "old mode {:06o}", old.mode)?;
+            writeln!(out, "new mode {:06o}", new.mode)?;
+        }
+        _ => {}
+    }
+    match (&old, &new) {
+        (Some(old_side), Some(new_side)) if old_side.mode == new_side.mode => {
+            writeln!(out, "index {}..{} {:06o}", short_hash(repo, &old), short_hash(repo, &new), old_side.mode)?
+        }
+        _ => writeln!(out, "index {}..{}", short_hash(repo, &old), short_hash(repo, &new))?,
+    }
+
+    let old_name = if old.is_some() { format!("a/{}", name) } else { "/dev/null".to_string() };
+    let new_name = if new.is_some() { format!("b/{}", name) } else { "/dev/null".to_string() };
+    if binary {
+        writeln!(out, "Binary files {} and {} differ", old_name, new_name)?;
+        return Ok(());
+    }
+    writeln!(out, "--- {}", old_name)?;
+    writeln!(out, "+++ {}", new_name)?;
+    write_unified(out, &old_lines, &new_lines, &hunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    fn render(result: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> io::Result<String> {
+        let mut out = Vec::new();
+        result(&mut out)?;
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    #[test]
+    fn test_diff_head_includes_staged_changes() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+
+        fs::write(root.join("staged.txt"), "one\n")?;
+        fs::write(root.join("unstaged.txt"), "two\n")?;
+        add_file(&mut repo, Path::new("staged.txt"))?;
+        add_file(&mut repo, Path::new("unstaged.txt"))?;
+        commit(&repo, "Initial commit")?;
+
+        fs::write(root.join("staged.txt"), "one\nmore\n")?;
+        add_file(&mut repo, Path::new("staged.txt"))?;
+        fs::write(root.join("unstaged.txt"), "two changed\n")?;
+
+        let options = DiffOptions::default();
+        let unstaged = render(|out| diff_worktree(&repo, &options, out))?;
+        assert!(unstaged.contains("diff --git a/unstaged.txt b/unstaged.txt"));
+        assert!(unstaged.contains("-two\n+two changed\n"));
+        assert!(!unstaged.contains("staged.txt b/staged.txt"));
+
+        let against_head = render(|out| diff_worktree_against(&repo, "HEAD", &options, out))?;
+        assert!(against_head.contains("diff --git a/staged.txt b/staged.txt"));
+        assert!(against_head.contains("@@ -1,1 +1,2 @@\n one\n+more\n"));
+        assert!(against_head.contains("diff --git a/unstaged.txt b/unstaged.txt"));
+
+        // Path limits apply to both modes
+        let limited = DiffOptions { paths: vec![PathBuf::from("staged.txt")], ..DiffOptions::default() };
+        let limited_head = render(|out| diff_worktree_against(&repo, "HEAD", &limited, out))?;
+        assert!(!limited_head.contains("unstaged.txt"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_work_tree_side_is_normalized_like_add() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+
+        fs::write(root.join(crate::cobra::core::attributes::ATTRIBUTES_FILE), "*.txt text\n")?;
+        fs::write(root.join("notes.txt"), "one\ntwo\n")?;
+        add_file(&mut repo, Path::new("notes.txt"))?;
+        commit(&repo, "Initial commit")?;
+
+        // Only the line endings differ from what add would stage
+        fs::write(root.join("notes.txt"), "one\r\ntwo\r\n")?;
+        let options = DiffOptions::default();
+        assert_eq!(render(|out| diff_worktree(&repo, &options, out))?, "");
+        assert_eq!(render(|out| diff_worktree_against(&repo, "HEAD", &options, out))?, "");
+
+        fs::write(root.join("notes.txt"), "one\r\nthree\r\n")?;
+        let output = render(|out| diff_worktree(&repo, &options, out))?;
+        assert!(output.ends_with("@@ -1,2 +1,2 @@\n one\n-two\n+three\n"), "{}", output);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_against_branch_shows_one_sided_files() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+
+        fs::write(root.join("old.txt"), "gone\n")?;
+        add_file(&mut repo, Path::new("old.txt"))?;
+        commit(&repo, "Initial commit")?;
+
+        // Track a new file and drop the old one from the index
+        fs::write(root.join("new.txt"), "fresh\n")?;
+        add_file(&mut repo, Path::new("new.txt"))?;
+        repo.index = crate::cobra::core::index::Index::new();
+        add_file(&mut repo, Path::new("new.txt"))?;
+
+        let output = render(|out| diff_worktree_against(&repo, "main", &DiffOptions::default(), out))?;
+        assert!(output.contains("new file mode 100644\nindex 0000000.."));
+        assert!(output.contains("--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,1 @@\n+fresh\n"));
+        assert!(output.contains("deleted file mode 100644"));
+        assert!(output.contains("--- a/old.txt\n+++ /dev/null\n@@ -1,1 +0,0 @@\n-gone\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unstaged_deletions_binaries_and_path_limits() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+
+        for name in ["gone.txt", "data.bin", "kept.txt"] {
+            fs::write(root.join(name), format!("{}\n", name))?;
+            add_file(&mut repo, Path::new(name))?;
+        }
+        commit(&repo, "Initial commit")?;
+
+        fs::remove_file(root.join("gone.txt"))?;
+        fs::write(root.join("data.bin"), b"raw\0bytes")?;
+        fs::write(root.join("untracked.txt"), "new\n")?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        run(&mut ctx, None, DiffOptions::default())?;
+        let output = ctx.out.captured();
+        assert!(output.contains("deleted file mode 100644"));
+        assert!(output.contains("--- a/gone.txt\n+++ /dev/null\n@@ -1,1 +0,0 @@\n-gone.txt\n"));
+        assert!(output.contains("Binary files a/data.bin and b/data.bin differ\n"));
+        assert!(!output.contains("kept.txt"));
+        assert!(!output.contains("untracked.txt"));
+
+        // A deleted file given without `--` is still a path, not a revision
+        ctx.out = OutputHandle::buffer(false);
+        run(&mut ctx, Some("gone.txt"), DiffOptions::default())?;
+        let limited = ctx.out.captured();
+        assert!(limited.contains("diff --git a/gone.txt b/gone.txt"));
+        assert!(!limited.contains("data.bin"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_staged_compares_index_with_head() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+        fs::create_dir(root.join("src"))?;
+        fs::write(root.join("src/lib.rs"), "one\n")?;
+        fs::write(root.join("old.txt"), "old\n")?;
+        add_file(&mut repo, Path::new("src/lib.rs"))?;
+        add_file(&mut repo, Path::new("old.txt"))?;
+
+        // Unborn branch: everything staged is new
+        let unborn = render(|out| diff_staged(&repo, None, &DiffOptions::default(), out))?;
+        assert!(unborn.contains("--- /dev/null\n+++ b/old.txt\n"));
+        assert!(unborn.contains("--- /dev/null\n+++ b/src/lib.rs\n"));
+        commit(&repo, "Initial commit")?;
+
+        fs::write(root.join("src/lib.rs"), "one\ntwo\n")?;
+        add_file(&mut repo, Path::new("src/lib.rs"))?;
+        fs::write(root.join("src/lib.rs"), "one\ntwo\nunstaged\n")?;
+        fs::write(root.join("added.txt"), "added\n")?;
+        add_file(&mut repo, Path::new("added.txt"))?;
+        repo.index.remove_entry(Path::new("old.txt"));
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        run(&mut ctx, None, DiffOptions { cached: true, ..DiffOptions::default() })?;
+        let staged = ctx.out.captured();
+        assert!(staged.contains("--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,1 +1,2 @@\n one\n+two\n"));
+        assert!(!staged.contains("unstaged"));
+        assert!(staged.contains("new file mode 100644\nindex 0000000.."));
+        assert!(staged.contains("--- /dev/null\n+++ b/added.txt\n"));
+        assert!(staged.contains("deleted file mode 100644"));
+        assert!(staged.contains("--- a/old.txt\n+++ /dev/null\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_algorithm_from_config_and_options() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+
+        // A function replaced next to one that is kept: only patience keeps helper() whole
+        let old = "int compute(int x)\n{\n    if (x > 10)\n    {\n        return x * 2;\n    }\n    else\n    {\n        return 0;\n    }\n}\n\nint helper(void)\n{\n    int base = 40;\n    base += 2;\n    return base;\n}\n";
+        let new = "int helper(void)\n{\n    int base = 40;\n    base += 2;\n    return base;\n}\n\nvoid log_value(int v)\n{\n    if (v)\n    {\n        printf(\"%d\", v);\n    }\n    else\n    {\n        puts(\"none\");\n    }\n}\n";
+        fs::write(root.join("code.c"), old)?;
+        add_file(&mut repo, Path::new("code.c"))?;
+        fs::write(root.join("code.c"), new)?;
+        let git_dir = repo.git_dir.clone();
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        run(&mut ctx, None, DiffOptions::default())?;
+        assert!(ctx.out.captured().contains("\n+int helper(void)\n"));
+
+        fs::write(git_dir.join("config"), "[diff]\n\talgorithm = patience\n")?;
+        ctx.out = OutputHandle::buffer(false);
+        run(&mut ctx, None, DiffOptions::default())?;
+        assert!(ctx.out.captured().contains("\n int helper(void)\n"));
+
+        // The command line wins over the config
+        ctx.out = OutputHandle::buffer(false);
+        run(&mut ctx, None, DiffOptions { algorithm: Some(Algorithm::Myers), ..DiffOptions::default() })?;
+        assert!(ctx.out.captured().contains("\n+int helper(void)\n"));
+
+        fs::write(git_dir.join("config"), "[diff]\n\talgorithm = fastest\n")?;
+        let error = run(&mut ctx, None, DiffOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("unknown diff algorithm 'fastest'"));
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+// Show changes in an external diff tool
+//
+// The tool is named by --tool or diff.tool, and difftool.<name>.cmd holds
+// its command line, run through the shell with $LOCAL and $REMOTE naming
+// the old and new files. Files are picked exactly as `cobra diff` picks
+// them. Stored versions are streamed into a scratch directory under
+// .cobra/tmp, keeping their paths so tools can tell the file types, and
+// removed once the tool exits. A work tree version is handed over as the
+// file itself, so edits made in the tool are kept, except with --dir-diff,
+// where both sides are copied into before and after trees and the tool
+// runs once on the two directories.
+
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::cobra::commands::diff::{changed_files, rev_or_path, FilePair, Version};
+use crate::cobra::core::{
+    config::Config,
+    object::Object,
+    repository::Repository,
+};
+use crate::cobra::state::CommandContext;
+use crate::cobra::utils::tempfiles::TempDirectory;
+
+/// What a missing side is shown as
+const NULL_FILE: &str = "/dev/null";
+
+#[derive(Debug, Default)]
+pub struct DifftoolOptions {
+    /// Only show files equal to or below these paths
+    pub paths: Vec<PathBuf>,
+    /// Compare the index instead of the work tree (--cached)
+    pub cached: bool,
+    /// Launch the tool once on whole before and after trees (-d/--dir-diff)
+    pub dir_diff: bool,
+    /// The tool to use instead of diff.tool (-t/--tool)
+    pub tool: Option<String>,
+    /// Launch the tool for every file without asking (-y/--no-prompt)
+    pub no_prompt: bool,
+}
+
+/// A tool invocation: the configured command line and the files or
+/// directories it compares
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCommand {
+    pub template: String,
+    pub local: PathBuf,
+    pub remote: PathBuf,
+}
+
+impl ToolCommand {
+    /// The command line with $LOCAL and $REMOTE replaced by the paths, as
+    /// the shell will see it
+    pub fn command_line(&self) -> String {
+        self.template
+            .replace("$LOCAL", &self.local.to_string_lossy())
+            .replace("$REMOTE", &self.remote.to_string_lossy())
+    }
+}
+
+/// Starts the diff tool; tests record the invocations instead
+pub trait ToolLauncher {
+    /// Asks whether to view the next file; false skips it
+    fn confirm(&mut self, prompt: &str) -> io::Result<bool>;
+    /// Runs the tool and waits for it to exit
+    fn launch(&mut self, command: &ToolCommand) -> io::Result<()>;
+}
+
+/// Runs tools through `sh -c` from the work tree root, with LOCAL and
+/// REMOTE set in the environment, and prompts on the terminal
+pub struct ShellLauncher {
+    pub root: PathBuf,
+}
+
+impl ToolLauncher for ShellLauncher {
+    fn confirm(&mut self, prompt: &str) -> io::Result<bool> {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return Ok(true);
+        }
+        eprint!("{} [Y/n]? ", prompt);
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        stdin.lock().read_line(&mut answer)?;
+        Ok(!matches!(answer.trim().to_ascii_lowercase().as_str(), "n" | "no"))
+    }
+
+    fn launch(&mut self, command: &ToolCommand) -> io::Result<()> {
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&command.template)
+            .env("LOCAL", &command.local)
+            .env("REMOTE", &command.remote)
+            .current_dir(&self.root)
+            .status()
+            .map_err(|e| io::Error::new(e.kind(), format!("Could not launch '{}': {}", command.command_line(), e)))?;
+        if !status.success() {
+            return Err(io::Error::other(format!("'{}' exited with {}", command.command_line(), status)));
+        }
+        Ok(())
+    }
+}
+
+/// Opens every changed file in the configured tool, one at a time or, with
+/// --dir-diff, all at once
+pub fn run(ctx: &mut CommandContext, rev: Option<&str>, options: &DifftoolOptions, launcher: &mut dyn ToolLauncher) -> io::Result<()> {
+    let mut paths = options.paths.clone();
+    let rev = rev_or_path(ctx, rev, &mut paths);
+    let repo = &ctx.repo;
+    let config = Config::load(&repo.git_dir)?;
+    let (tool, template) = tool_command(&config, options.tool.as_deref())?;
+    let pairs = changed_files(repo, rev, options.cached, &paths)?;
+    if pairs.is_empty() {
+        return Ok(());
+    }
+
+    if options.dir_diff {
+        let scratch = TempDirectory::new(&repo.git_dir, "difftool")?;
+        let local = scratch.path().join("left");
+        let remote = scratch.path().join("right");
+        fs::create_dir(&local)?;
+        fs::create_dir(&remote)?;
+        for pair in &pairs {
+            if let Some(old) = &pair.old {
+                materialize(repo, &pair.path, old, &local.join(&pair.path))?;
+            }
+            if let Some(new) = &pair.new {
+                materialize(repo, &pair.path, new, &remote.join(&pair.path))?;
+            }
+        }
+        return launcher.launch(&ToolCommand { template, local, remote });
+    }
+
+    let prompt = !options.no_prompt && config.get_bool("difftool.prompt").unwrap_or(true);
+    for (number, pair) in pairs.iter().enumerate() {
+        if prompt {
+            let question = format!("Viewing ({}/{}): '{}'\nLaunch '{}'", number + 1, pairs.len(), pair.path.display(), tool);
+            if !launcher.confirm(&question)? {
+                continue;
+            }
+        }
+        // Dropped, and so removed, as soon as the tool is done with the file
+        let scratch = TempDirectory::new(&repo.git_dir, "difftool")?;
+        let local = file_side(repo, pair, pair.old.as_ref(), &scratch.path().join("left"))?;
+        let remote = file_side(repo, pair, pair.new.as_ref(), &scratch.path().join("right"))?;
+        launcher.launch(&ToolCommand { template: template.clone(), local, remote })?;
+    }
+    Ok(())
+}
+
+/// The tool's name and its command line from difftool.<name>.cmd
+fn tool_command(config: &Config, tool: Option<&str>) -> io::Result<(String, String)> {
+    let tool = tool.or_else(|| config.get("diff.tool")).ok_or_else(|| io::Error::new(
+        io::ErrorKind::NotFound,
+        "No diff tool configured: set diff.tool or pass --tool",
+    ))?;
+    let key = format!("difftool.{}.cmd", tool);
+    let template = config.get(&key).ok_or_else(|| io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("Unknown diff tool '{}': set {}", tool, key),
+    ))?;
+    Ok((tool.to_string(), template.to_string()))
+}
+
+/// The path handed to the tool for one side of a single file: the work
+/// tree file itself, a stored version written below `dir`, or the null
+/// file when the side does not exist
+fn file_side(repo: &Repository, pair: &FilePair, version: Option<&Version>, dir: &Path) -> io::Result<PathBuf> {
+    match version {
+        None => Ok(PathBuf::from(NULL_FILE)),
+        Some(version) if version.work_tree => Ok(repo.root_path.join(&pair.path)),
+        Some(version) => {
+            let destination = dir.join(&pair.path);
+            materialize(repo, &pair.path, version, &destination)?;
+            Ok(destination)
+        }
+    }
+}
+
+/// Writes one version of `path` to `destination` without reading it into
+/// memory
+fn materialize(repo: &Repository, path: &Path, version: &Version, destination: &Path) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if version.work_tree {
+        fs::copy(repo.root_path.join(path), destination)?;
+    } else {
+        let mut file = fs::File::create(destination)?;
+        Object::copy_blob(&repo.git_dir, version.hash, &mut file)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::add::add_file;
+    use crate::cobra::utils::tempfiles::tmp_dir;
+    use crate::cobra::test_support::repo_with_files;
+    use tempfile::TempDir;
+
+    /// Records each invocation with the contents of both sides at the time
+    #[derive(Default)]
+    struct Recorder {
+        prompts: Vec<String>,
+        answer_no: bool,
+        launches: Vec<(String, PathBuf, PathBuf)>,
+        contents: Vec<(String, String)>,
+    }
+
+    impl ToolLauncher for Recorder {
+        fn confirm(&mut self, prompt: &str) -> io::Result<bool> {
+            self.prompts.push(prompt.to_string());
+            Ok(!self.answer_no)
+        }
+
+        fn launch(&mut self, command: &ToolCommand) -> io::Result<()> {
+            let read = |path: &Path| -> io::Result<String> {
+                if path.is_dir() {
+                    let mut files: Vec<String> = walkdir::WalkDir::new(path).into_iter()
+                        .filter_map(Result::ok)
+                        .filter(|entry| entry.file_type().is_file())
+                        .map(|entry| {
+                            let relative = entry.path().strip_prefix(path).unwrap().display().to_string();
+                            format!("{}={}", relative, fs::read_to_string(entry.path()).unwrap())
+                        })
+                        .collect();
+                    files.sort();
+                    Ok(files.concat())
+                } else {
+                    fs::read_to_string(path)
+                }
+            };
+            self.contents.push((read(&command.local)?, read(&command.remote)?));
+            self.launches.push((command.command_line(), command.local.clone(), command.remote.clone()));
+            Ok(())
+        }
+    }
+
+    fn setup() -> io::Result<(TempDir, CommandContext)> {
+        let (temp_dir, mut ctx) = repo_with_files(&[("a.txt", "old a.txt\n"), ("src/b.rs", "old src/b.rs\n"), ("same.txt", "old same.txt\n")])?;
+        fs::write(ctx.repo.git_dir.join("config"), "[diff]\n\ttool = mytool\n[difftool \"mytool\"]\n\tcmd = mytool --diff \"$LOCAL\" \"$REMOTE\" --wait\n")?;
+
+        // A two-file change: one staged, one only in the work tree
+        fs::write(temp_dir.path().join("a.txt"), "new a.txt, staged\n")?;
+        add_file(&mut ctx.repo, Path::new("a.txt"))?;
+        fs::write(temp_dir.path().join("src/b.rs"), "new src/b.rs\n")?;
+        Ok((temp_dir, ctx))
+    }
+
+    #[test]
+    fn test_difftool_launches_once_per_changed_file() -> io::Result<()> {
+        let (temp_dir, mut ctx) = setup()?;
+        let root = temp_dir.path().to_path_buf();
+        let git_dir = ctx.repo.git_dir.clone();
+
+        // Against HEAD: both files, stored sides streamed out, work tree sides as they are
+        let mut recorder = Recorder::default();
+        run(&mut ctx, Some("HEAD"), &DifftoolOptions::default(), &mut recorder)?;
+        assert_eq!(recorder.prompts, [
+            "Viewing (1/2): 'a.txt'\nLaunch 'mytool'",
+            "Viewing (2/2): 'src/b.rs'\nLaunch 'mytool'",
+        ]);
+        assert_eq!(recorder.contents, [
+            ("old a.txt\n".to_string(), "new a.txt, staged\n".to_string()),
+            ("old src/b.rs\n".to_string(), "new src/b.rs\n".to_string()),
+        ]);
+        for ((command_line, local, remote), name) in recorder.launches.iter().zip(["a.txt", "src/b.rs"]) {
+            assert!(local.starts_with(tmp_dir(&git_dir)) && local.ends_with(Path::new("left").join(name)), "{}", local.display());
+            assert_eq!(remote, &root.join(name));
+            assert_eq!(command_line, &format!("mytool --diff \"{}\" \"{}\" --wait", local.display(), remote.display()));
+        }
+        // The scratch copies are gone
+        assert!(recorder.launches.iter().all(|(_, local, _)| !local.exists()));
+        assert_eq!(fs::read_dir(tmp_dir(&git_dir))?.count(), 0);
+
+        // --cached compares HEAD and the index: only the staged file, both sides stored
+        let mut recorder = Recorder { answer_no: true, ..Default::default() };
+        run(&mut ctx, None, &DifftoolOptions { cached: true, no_prompt: true, ..Default::default() }, &mut recorder)?;
+        assert!(recorder.prompts.is_empty());
+        assert_eq!(recorder.contents, [("old a.txt\n".to_string(), "new a.txt, staged\n".to_string())]);
+        assert!(recorder.launches[0].2.ends_with("right/a.txt"));
+
+        // Declining a prompt skips the file
+        let mut recorder = Recorder { answer_no: true, ..Default::default() };
+        run(&mut ctx, None, &DifftoolOptions::default(), &mut recorder)?;
+        assert_eq!(recorder.prompts.len(), 1);
+        assert!(recorder.launches.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_and_tool_selection() -> io::Result<()> {
+        let (temp_dir, mut ctx) = setup()?;
+        fs::remove_file(temp_dir.path().join("same.txt"))?;
+        fs::write(ctx.repo.git_dir.join("config"), "[difftool \"other\"]\n\tcmd = other $LOCAL $REMOTE\n")?;
+
+        let error = run(&mut ctx, None, &DifftoolOptions::default(), &mut Recorder::default()).unwrap_err();
+        assert!(error.to_string().contains("No diff tool configured"), "{}", error);
+        let missing = DifftoolOptions { tool: Some("absent".to_string()), ..Default::default() };
+        let error = run(&mut ctx, None, &missing, &mut Recorder::default()).unwrap_err();
+        assert!(error.to_string().contains("difftool.absent.cmd"), "{}", error);
+
+        // One launch on two trees; a deleted file is only on the left
+        let mut recorder = Recorder::default();
+        let options = DifftoolOptions { tool: Some("other".to_string()), dir_diff: true, ..Default::default() };
+        run(&mut ctx, Some("HEAD"), &options, &mut recorder)?;
+        assert!(recorder.prompts.is_empty());
+        assert_eq!(recorder.contents, [(
+            "a.txt=old a.txt\nsame.txt=old same.txt\nsrc/b.rs=old src/b.rs\n".to_string(),
+            "a.txt=new a.txt, staged\nsrc/b.rs=new src/b.rs\n".to_string(),
+        )]);
+        let (command_line, local, remote) = &recorder.launches[0];
+        assert_eq!(command_line, &format!("other {} {}", local.display(), remote.display()));
+        assert!(!local.exists() && !remote.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+// Download objects and refs from another repository
+use std::io::{self, Write};
+use crate::cobra::core::config::Config;
+use crate::cobra::core::transport::{fetch, TcpTransport};
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+/// `repository` is a URL or the name of a remote configured with
+/// remote.<name>.url. Only named remotes get remote-tracking refs.
+pub fn run(ctx: &mut CommandContext, repository: &str) -> io::Result<()> {
+    let (url, remote) = if repository.contains("://") {
+        (repository.to_string(), None)
+    } else {
+        let config = Config::load(&ctx.repo.git_dir)?;
+        let url = config.get(&format!("remote.{}.url", repository))
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("'{}' is neither a URL nor a configured remote", repository),
+            ))?;
+        (url.to_string(), Some(repository))
+    };
+
+    let mut transport = TcpTransport::new(&url)?;
+    let report = fetch(&ctx.repo, &mut transport, &url, remote)?;
+
+    if !report.updated.is_empty() {
+        ctx.out.note(format_args!("{}", messages::fetching_from(&url)))?;
+    }
+    for change in &report.updated {
+        let short = change.name.strip_prefix("refs/remotes/")
+            .or_else(|| change.name.strip_prefix("refs/tags/"))
+            .unwrap_or(&change.name);
+        let source = short.split_once('/')
+            .filter(|_| change.name.starts_with("refs/remotes/"))
+            .map_or(short, |(_, branch)| branch);
+        let summary = match &change.old {
+            None if change.name.starts_with("refs/tags/") => "* [new tag]".to_string(),
+            None => "* [new branch]".to_string(),
+            Some(old) => format!("  {}..{}", ctx.repo.short_id(*old), ctx.repo.short_id(change.new)),
+        };
+        writeln!(ctx.out, " {:<17} {} -> {}", summary, source, short)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// Verify the object store and report lost objects
+use std::io::{self, BufRead, IsTerminal, Write};
+use crate::cobra::core::fsck::{
+    check, fix_placement, plan_ref_reconstruction, reconstruct_refs, write_lost_found,
+};
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages::{self, machine};
+
+#[derive(Debug, Default)]
+pub struct FsckOptions {
+    /// Save dangling objects under .cobra/lost-found (--lost-found)
+    pub lost_found: bool,
+    /// Move misfiled objects to the path their hash calls for (--fix-placement)
+    pub fix_placement: bool,
+    /// Recreate refs for history nothing points at (--reconstruct-refs)
+    pub reconstruct_refs: bool,
+    /// Write the reconstructed refs without asking (--yes)
+    pub yes: bool,
+}
+
+pub fn run(ctx: &mut CommandContext, options: &FsckOptions) -> io::Result<()> {
+    if options.reconstruct_refs {
+        return run_reconstruct_refs(ctx, options.yes);
+    }
+    let mut report = check(&ctx.repo)?;
+    if options.fix_placement && !report.misplaced.is_empty() {
+        fix_placement(&ctx.repo, &report.misplaced)?;
+        for object in &report.misplaced {
+            ctx.out.note(format_args!("{}", messages::fsck_moved(object.kind, &object.hash.to_string(), &object.stored_as.to_string())))?;
+        }
+        // Objects that were unreadable by name may make others reachable again
+        report = check(&ctx.repo)?;
+    }
+
+    for object in &report.misplaced {
+        writeln!(ctx.out, "{} {} {} stored as {}", machine::MISPLACED, object.kind, object.hash, object.stored_as)?;
+    }
+    for object in &report.dangling {
+        writeln!(ctx.out, "{} {} {}", machine::DANGLING, object.kind, object.hash)?;
+    }
+    if options.lost_found {
+        write_lost_found(&ctx.repo, &report.dangling)?;
+    }
+    Ok(())
+}
+
+/// Lists the refs that would bring lost history back, then writes them
+/// once confirmed
+fn run_reconstruct_refs(ctx: &mut CommandContext, yes: bool) -> io::Result<()> {
+    let plan = plan_ref_reconstruction(&ctx.repo)?;
+    if plan.refs.is_empty() && plan.head.is_none() {
+        ctx.out.note(format_args!("{}", messages::fsck_nothing_lost()))?;
+        return Ok(());
+    }
+    for recovered in &plan.refs {
+        let origin = if recovered.from_reflog { "from reflog" } else { "no reflog" };
+        writeln!(ctx.out, "{} {} ({})", recovered.hash, recovered.name, origin)?;
+    }
+    if let Some(head) = &plan.head {
+        writeln!(ctx.out, "HEAD -> {}", head)?;
+    }
+
+    if !yes && !confirm("Create these refs?")? {
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "No refs written; run with --yes to write them without asking",
+        ));
+    }
+    reconstruct_refs(&ctx.repo, &plan)?;
+    ctx.out.note(format_args!("{}", messages::fsck_recovered(plan.refs.len())))
+}
+
+/// Asks a yes/no question on the terminal. Without one there is nobody to
+/// answer, which counts as no.
+fn confirm(question: &str) -> io::Result<bool> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return Ok(false);
+    }
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    stdin.lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
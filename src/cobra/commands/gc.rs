@@ -0,0 +1,28 @@
+// Garbage collection command
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::cobra::core::gc::collect_garbage;
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+/// `prune` is the --prune expiry, overriding gc.pruneExpire
+pub fn run(ctx: &mut CommandContext, prune: Option<&str>) -> io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(io::Error::other)?
+        .as_secs();
+
+    let report = collect_garbage(&ctx.repo, now, prune)?;
+    if report.expired_entries > 0 {
+        ctx.out.note(format_args!("{}", messages::gc_expired(report.expired_entries)))?;
+    }
+    ctx.out.note(format_args!("{}", messages::gc_removed(report.pruned.len())))?;
+    if report.kept_recent > 0 {
+        ctx.out.note(format_args!("{}", messages::gc_kept_recent(report.kept_recent)))?;
+    }
+    if report.bitmapped_commits > 0 {
+        ctx.out.note(format_args!("{}", messages::gc_wrote_bitmaps(report.bitmapped_commits)))?;
+    }
+
+    Ok(())
+}
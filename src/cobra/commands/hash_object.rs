@@ -0,0 +1,107 @@
+// Compute object names for arbitrary content, optionally storing it
+use std::io::{self, Read, Write};
+use crate::cobra::core::object::Object;
+use crate::cobra::core::pack::RawObject;
+use crate::cobra::state::CommandContext;
+
+#[derive(Debug, Default)]
+pub struct HashObjectOptions {
+    /// Object type of the content (-t): blob, tree or commit; blob when None
+    pub kind: Option<String>,
+    /// Also write the object into the object store (-w)
+    pub write: bool,
+}
+
+/// Reads the object content from `input` and prints its name. Trees and
+/// commits must be well formed, and are hashed and stored byte for byte.
+pub fn run(ctx: &mut CommandContext, input: &mut dyn Read, options: &HashObjectOptions) -> io::Result<()> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    let kind = match options.kind.as_deref().unwrap_or("blob") {
+        "blob" => "blob",
+        "tree" => "tree",
+        "commit" => "commit",
+        kind => return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown object type '{}', expected blob, tree or commit", kind),
+        )),
+    };
+
+    let hash = if kind == "blob" {
+        let blob = Object::new_blob(data);
+        match options.write {
+            true => ctx.repo.write_object(&blob)?,
+            false => blob.hash(),
+        }
+    } else {
+        // Parsing only checks the content; what is stored is the input itself
+        Object::parse(kind, &data)?;
+        let raw = RawObject { kind, data };
+        match options.write {
+            true => {
+                let mut transaction = ctx.repo.transaction();
+                let hash = transaction.add_raw(&raw)?;
+                transaction.commit()?;
+                hash
+            }
+            false => raw.hash(),
+        }
+    };
+    writeln!(ctx.out, "{}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    fn hash(ctx: &mut CommandContext, data: &[u8], options: &HashObjectOptions) -> io::Result<String> {
+        ctx.out = OutputHandle::buffer(false);
+        run(ctx, &mut &data[..], options)?;
+        Ok(ctx.out.captured().trim_end().to_string())
+    }
+
+    #[test]
+    fn test_hash_blob_matches_git_and_writes_on_request() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        // `printf 'hello\n' | git hash-object --stdin`
+        let hello = "ce013625030ba8dba906f756967f9e9ca394464a";
+        assert_eq!(hash(&mut ctx, b"hello\n", &HashObjectOptions::default())?, hello);
+        assert!(ctx.repo.read_object(hello.parse()?).is_err());
+
+        let write = HashObjectOptions { write: true, ..HashObjectOptions::default() };
+        assert_eq!(hash(&mut ctx, b"hello\n", &write)?, hello);
+        assert_eq!(ctx.repo.read_object(hello.parse()?)?.serialize(), b"hello\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_other_types() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let tree_options = |write| HashObjectOptions { kind: Some("tree".to_string()), write };
+
+        let blob = "ce013625030ba8dba906f756967f9e9ca394464a";
+        let tree = Object::new_tree_from_entries(vec![("hello.txt".to_string(), 0o100644, blob.parse()?)]);
+        let tree_hash = hash(&mut ctx, &tree.serialize(), &tree_options(true))?;
+        assert_eq!(tree_hash, tree.hash());
+        assert_eq!(ctx.repo.read_object(tree_hash.parse()?)?.serialize(), tree.serialize());
+
+        // Commits are hashed exactly as given, trailing newline included
+        let commit = format!("tree {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nMessage\n", tree_hash);
+        let commit_options = HashObjectOptions { kind: Some("commit".to_string()), ..HashObjectOptions::default() };
+        assert_eq!(
+            hash(&mut ctx, commit.as_bytes(), &commit_options)?,
+            RawObject { kind: "commit", data: commit.clone().into_bytes() }.hash(),
+        );
+
+        assert!(hash(&mut ctx, b"not a tree", &tree_options(false)).is_err());
+        let tag = HashObjectOptions { kind: Some("tag".to_string()), ..HashObjectOptions::default() };
+        assert_eq!(hash(&mut ctx, b"", &tag).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+}
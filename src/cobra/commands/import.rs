@@ -0,0 +1,82 @@
+// Commit a tarball or directory snapshot onto a branch
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use crate::cobra::core::import::{commit_snapshot, read_directory, read_tar, Snapshot};
+use crate::cobra::core::transaction::ObjectTransaction;
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+#[derive(Debug, Default)]
+pub struct ImportOptions {
+    /// Branch to commit onto (--branch), the checked out one by default
+    pub branch: Option<String>,
+    pub message: String,
+}
+
+/// Imports a tar archive, plain or gzip-compressed
+pub fn run_tar(ctx: &mut CommandContext, archive: &Path, options: &ImportOptions) -> io::Result<()> {
+    let mut transaction = ctx.repo.transaction();
+    let snapshot = read_tar(File::open(archive)?, &mut transaction)?;
+    finish(ctx, transaction, snapshot, options)
+}
+
+pub fn run_dir(ctx: &mut CommandContext, dir: &Path, options: &ImportOptions) -> io::Result<()> {
+    if !dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("'{}' is not a directory", dir.display()),
+        ));
+    }
+    let mut transaction = ctx.repo.transaction();
+    let snapshot = read_directory(dir, &mut transaction)?;
+    finish(ctx, transaction, snapshot, options)
+}
+
+/// Commits the snapshot and prints the new commit's hash
+fn finish(ctx: &mut CommandContext, transaction: ObjectTransaction, snapshot: Snapshot, options: &ImportOptions) -> io::Result<()> {
+    let branch = match &options.branch {
+        Some(branch) => branch.clone(),
+        None => ctx.refs.head_branch()?
+            .and_then(|head| head.strip_prefix("refs/heads/").map(str::to_string))
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "HEAD is detached; name the branch to import onto with --branch",
+            ))?,
+    };
+    for path in &snapshot.skipped {
+        ctx.out.note(format_args!("{}", messages::import_skipped(path.display())))?;
+    }
+    let commit = commit_snapshot(&ctx.repo, transaction, &snapshot, &branch, &options.message)?;
+    writeln!(ctx.out, "{}", commit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::init_repo;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_import_dir_defaults_to_the_current_branch() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = init_repo(&temp_dir.path().join("repo"))?;
+        let source = temp_dir.path().join("source");
+        fs::create_dir(&source)?;
+        fs::write(source.join("file.txt"), "content\n")?;
+        std::os::unix::fs::symlink("file.txt", source.join("alias"))?;
+
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let options = ImportOptions { message: "Import".to_string(), ..Default::default() };
+        run_dir(&mut ctx, &source, &options)?;
+        let head = ctx.refs.resolve_commit("main")?;
+        assert_eq!(ctx.out.captured(), format!("{}\n{}\n", messages::import_skipped("alias"), head));
+
+        ctx.refs.update_head(head, "test")?;
+        let error = run_dir(&mut ctx, &source, &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+}
@@ -1,9 +1,81 @@
 // Initialize new repository
+use std::env;
 use std::io;
+use std::path::PathBuf;
 use crate::cobra::core::repository::Repository;
+use crate::cobra::core::config::Config;
+use crate::cobra::state::OutputHandle;
+use crate::cobra::messages;
 
-pub fn run(path: &str) -> io::Result<()> {
-    Repository::init(path)?;
-    println!("Initialized empty Cobra repository in {}", path);
+pub fn run(out: &mut OutputHandle, path: &str, template: Option<&str>) -> io::Result<()> {
+    let repo = Repository::init(path)?;
+
+    // --template wins over COBRA_TEMPLATE_DIR, which wins over init.templateDir
+    let template_dir = match template {
+        Some(template) => Some(PathBuf::from(template)),
+        None => match env::var_os("COBRA_TEMPLATE_DIR") {
+            Some(dir) => Some(PathBuf::from(dir)),
+            None => Config::load_global()?.get("init.templateDir").map(PathBuf::from),
+        },
+    };
+    if let Some(template_dir) = template_dir {
+        repo.apply_template(&template_dir)?;
+    }
+
+    out.note(format_args!("{}", messages::initialized(path)))?;
     Ok(())
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use crate::cobra::commands::commit::{commit, commit_with_options, CommitOptions};
+    use crate::cobra::test_support::init_repo;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_init_with_template() -> io::Result<()> {
+        let template_dir = TempDir::new()?;
+        let template = template_dir.path();
+        fs::create_dir_all(template.join("hooks"))?;
+        fs::create_dir_all(template.join("info"))?;
+        fs::write(template.join("info/exclude"), "*.log\n")?;
+        fs::write(template.join("HEAD"), "ref: refs/heads/template\n")?;
+        let hook = template.join("hooks/pre-commit");
+        fs::write(&hook, "#!/bin/sh\necho 'blocked by template hook' >&2\nexit 1\n")?;
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755))?;
+
+        let repo_dir = TempDir::new()?;
+        let repo = init_repo(repo_dir.path())?;
+        repo.apply_template(template)?;
+
+        // Template files are copied, but never replace what init wrote
+        assert_eq!(fs::read_to_string(repo.git_dir.join("info/exclude"))?, "*.log\n");
+        assert_eq!(fs::read_to_string(repo.git_dir.join("HEAD"))?, "ref: refs/heads/main\n");
+        let mode = fs::metadata(repo.git_dir.join("hooks/pre-commit"))?.permissions().mode();
+        assert_ne!(mode & 0o111, 0);
+
+        // The copied exclude file and hook are live
+        fs::write(repo_dir.path().join("debug.log"), "noise\n")?;
+        assert!(crate::cobra::commands::status::collect(&repo)?.untracked.is_empty());
+        let error = commit(&repo, "Blocked").unwrap_err();
+        assert!(error.to_string().contains("pre-commit hook failed"));
+        assert_eq!(repo.ref_store().read_ref_value("refs/heads/main")?, Some(String::new()));
+
+        let options = CommitOptions { verify: false, ..CommitOptions::default() };
+        commit_with_options(&repo, "Allowed", &options)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_template_is_an_error() -> io::Result<()> {
+        let repo_dir = TempDir::new()?;
+        let repo = init_repo(repo_dir.path())?;
+        let result = repo.apply_template(&repo_dir.path().join("no-such-dir"));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+}
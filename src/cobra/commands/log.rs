@@ -1,48 +1,790 @@
-use std::io;
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::PathBuf;
 use crate::cobra::core::{
     repository::Repository,
-    object::Object,
+    object::{Object, TreeEntry},
+    diff::{split_lines, Algorithm, Edit},
+    graph::Graph,
+    ancestry::ancestors,
+    oid::Oid,
+    revision::{resolve_revision, topo_sort, RevWalk},
     ref_store::RefStore,
+    tree::{commit_tree, diff_trees_within, TreeChange},
 };
+use crate::cobra::state::CommandContext;
+use crate::cobra::utils::{glob::glob_match, regex::Regex};
+use crate::cobra::messages;
 
-pub fn run() -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = RefStore::new(repo.git_dir.clone());
-
-    // Get current commit hash from HEAD
-    let mut current_hash = ref_store.read_head()?
-        .and_then(|head_ref| {
-            if head_ref.starts_with("ref: ") {
-                // HEAD points to a branch
-                let branch_ref = &head_ref[5..];
-                ref_store.read_ref(branch_ref).ok().flatten()
+/// Filters applied while walking the history
+#[derive(Debug, Default)]
+pub struct LogOptions {
+    /// Only show commits whose author name or email contains this,
+    /// ignoring case (--author)
+    pub author: Option<String>,
+    /// Only show commits authored at or after this time (--since)
+    pub since: Option<u64>,
+    /// Only show commits authored at or before this time (--until)
+    pub until: Option<u64>,
+    /// Only show commits that change the number of occurrences of this string (-S)
+    pub pickaxe: Option<String>,
+    /// Only show commits whose added or removed lines match this pattern (-G)
+    pub pickaxe_regex: Option<Regex>,
+    /// Algorithm deciding which lines -G sees as changed; diff.algorithm when None
+    pub algorithm: Option<Algorithm>,
+    /// Revisions and ranges to walk (`A`, `^A`, `A..B`, `A...B`); HEAD when
+    /// they include nothing and no ref selection is given
+    pub revisions: Vec<String>,
+    /// Also start from HEAD and every branch, tag and remote-tracking ref (--all)
+    pub all: bool,
+    /// Also start from the branches matching this pattern (--branches[=pattern])
+    pub branches: Option<String>,
+    /// Also start from the tags matching this pattern (--tags[=pattern])
+    pub tags: Option<String>,
+    /// Only show commits that change files at or below these paths, and
+    /// only those files in the change list (`-- <path>...`)
+    pub paths: Vec<PathBuf>,
+    /// List the files each commit changed, compared to its first parent
+    pub changes: Option<ChangeFormat>,
+    /// Stop after this many commits have passed the filters (-n, --max-count)
+    pub max_count: Option<usize>,
+    /// Draw the history as a graph beside the commits, which come in
+    /// topological order (--graph)
+    pub graph: bool,
+}
+
+/// How the files a commit changed are listed after its message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeFormat {
+    /// A status letter and the path (--name-status)
+    NameStatus,
+    /// Modes, abbreviated hashes, status and path (--raw)
+    Raw,
+}
+
+pub fn run(ctx: &mut CommandContext, options: &LogOptions) -> io::Result<()> {
+    let out = &mut ctx.out;
+    let repo = &ctx.repo;
+    let mut graph = options.graph.then(Graph::new);
+    let missing = walk_all(repo, options, |hash, commit, changes| {
+        let parents = match commit {
+            Object::Commit { parents, .. } => parents.as_slice(),
+            _ => &[],
+        };
+        match (&mut graph, changes) {
+            (None, Some(changes)) => write_entry(repo, out, hash, commit, changes, options),
+            (None, None) => Ok(()),
+            (Some(graph), Some(changes)) => {
+                let mut text = Vec::new();
+                write_entry(repo, &mut text, hash, commit, changes, options)?;
+                graph.commit(hash, parents).write(out, &String::from_utf8_lossy(&text))
+            }
+            (Some(graph), None) => {
+                graph.skip(hash, parents);
+                Ok(())
+            }
+        }
+    })?;
+    for hash in missing {
+        out.note(format_args!("{}", messages::history_truncated(&hash.to_string())))?;
+    }
+    Ok(())
+}
+
+/// Writes a commit and, if asked for, the files it changed
+fn write_entry(
+    repo: &Repository,
+    out: &mut dyn Write,
+    hash: Oid,
+    commit: &Object,
+    changes: &[TreeChange],
+    options: &LogOptions,
+) -> io::Result<()> {
+    write_commit(out, hash, commit)?;
+    if let Some(format) = options.changes {
+        write_changes(repo, out, changes, format)?;
+        if !changes.is_empty() {
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a commit's header and indented message, followed by a blank line
+pub fn write_commit(out: &mut dyn Write, hash: Oid, commit: &Object) -> io::Result<()> {
+    if let Object::Commit { author, message, .. } = commit {
+        writeln!(out, "commit {}", hash)?;
+        writeln!(out, "Author: {} <{}>", author.name, author.email)?;
+        writeln!(out, "Date:   {} {}", author.timestamp, author.timezone)?;
+        writeln!(out)?;
+        for line in message.lines() {
+            writeln!(out, "    {}", line)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Lists `changes` one line per file in `format`
+pub fn write_changes(repo: &Repository, out: &mut dyn Write, changes: &[TreeChange], format: ChangeFormat) -> io::Result<()> {
+    for change in changes {
+        match format {
+            ChangeFormat::NameStatus => writeln!(out, "{}\t{}", change.status(), change.path.display())?,
+            ChangeFormat::Raw => {
+                let side = |entry: Option<&TreeEntry>| match entry {
+                    Some(entry) => (entry.mode, repo.short_id(entry.hash)),
+                    None => (0, "0".repeat(repo.abbrev)),
+                };
+                let (old_mode, old_hash) = side(change.old.as_ref());
+                let (new_mode, new_hash) = side(change.new.as_ref());
+                writeln!(
+                    out,
+                    ":{:06o} {:06o} {} {} {}\t{}",
+                    old_mode, new_mode, old_hash, new_hash, change.status(), change.path.display(),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Walks the history selected by `options`, newest commit first, calling
+/// `visit` once for every commit that passes the filters. Commits are only
+/// diffed against their first parent when a filter or the change list needs
+/// it; `visit` gets the changes within `options.paths` then, and none otherwise.
+/// The walk ends early once `options.max_count` commits have been visited.
+/// Returns the commits whose objects are missing, where history had to stop
+/// short, unless the walk ended before it got there.
+pub fn walk<F>(repo: &Repository, options: &LogOptions, mut visit: F) -> io::Result<Vec<Oid>>
+where
+    F: FnMut(Oid, &Object, &[TreeChange]) -> io::Result<()>,
+{
+    walk_all(repo, options, |hash, commit, changes| match changes {
+        Some(changes) => visit(hash, commit, changes),
+        None => Ok(()),
+    })
+}
+
+/// Like `walk`, but also calls `visit` for the commits the filters leave
+/// out, without changes, so that a graph can follow their lanes
+fn walk_all<F>(repo: &Repository, options: &LogOptions, mut visit: F) -> io::Result<Vec<Oid>>
+where
+    F: FnMut(Oid, &Object, Option<&[TreeChange]>) -> io::Result<()>,
+{
+    let algorithm = match options.algorithm {
+        Some(algorithm) => algorithm,
+        None => repo.diff_algorithm()?,
+    };
+    let pickaxe = options.pickaxe.is_some() || options.pickaxe_regex.is_some();
+    let needs_changes = pickaxe || options.changes.is_some() || !options.paths.is_empty();
+    let author = options.author.as_deref().map(str::to_lowercase);
+    let mut walk = revisions(repo, options)?.iter(repo)?;
+    let commits: Box<dyn Iterator<Item = io::Result<(Oid, Object)>>> = match options.graph {
+        true => Box::new(topo_sort(walk.by_ref().collect::<io::Result<_>>()?).into_iter().map(Ok)),
+        false => Box::new(walk.by_ref()),
+    };
+    let mut remaining = options.max_count.unwrap_or(usize::MAX);
+    for commit in commits {
+        if remaining == 0 {
+            break;
+        }
+        let (hash, commit) = commit?;
+        let changes = 'filters: {
+            if author.as_deref().is_some_and(|pattern| !matches_author(&commit, pattern)) {
+                break 'filters None;
+            }
+            if !authored_within(&commit, options.since, options.until) {
+                break 'filters None;
+            }
+            let changes = match needs_changes {
+                true => commit_changes(repo, &commit, &options.paths)?,
+                false => Vec::new(),
+            };
+            if !options.paths.is_empty() && changes.is_empty() {
+                break 'filters None;
+            }
+            if pickaxe && !matches_pickaxe(repo, &changes, options, algorithm)? {
+                break 'filters None;
+            }
+            Some(changes)
+        };
+        // Skipped commits still lead the walk to their parents
+        visit(hash, &commit, changes.as_deref())?;
+        if changes.is_some() {
+            remaining -= 1;
+        }
+    }
+    // History missing past the last commit asked for does not matter
+    match remaining {
+        0 => Ok(Vec::new()),
+        _ => Ok(walk.missing().to_vec()),
+    }
+}
+
+/// Whether the commit's author name or email contains the lowercase `pattern`
+fn matches_author(commit: &Object, pattern: &str) -> bool {
+    match commit {
+        Object::Commit { author, .. } => {
+            author.name.to_lowercase().contains(pattern) || author.email.to_lowercase().contains(pattern)
+        }
+        _ => false,
+    }
+}
+
+/// Whether the commit's author date lies in the `--since`/`--until` window
+fn authored_within(commit: &Object, since: Option<u64>, until: Option<u64>) -> bool {
+    match commit {
+        Object::Commit { author, .. } => {
+            since.is_none_or(|since| author.timestamp >= since) && until.is_none_or(|until| author.timestamp <= until)
+        }
+        _ => false,
+    }
+}
+
+/// The files within `paths` that `commit` changed compared to its first
+/// parent. A root commit is compared to the empty tree, so it adds everything.
+fn commit_changes(repo: &Repository, commit: &Object, paths: &[PathBuf]) -> io::Result<Vec<TreeChange>> {
+    let Object::Commit { tree, parents, .. } = commit else {
+        return Ok(Vec::new());
+    };
+    let parent_tree = match parents.first() {
+        Some(parent) => Some(commit_tree(&repo.git_dir, *parent)?),
+        None => None,
+    };
+    diff_trees_within(&repo.git_dir, parent_tree, Some(*tree), paths)
+}
+
+/// A set of commits described by revision arguments: plain revisions
+/// (`A`), exclusions (`^A`), ranges (`A..B`, commits in B but not in A) and
+/// symmetric differences (`A...B`, commits in either but not in both).
+/// Endpoints are resolved while parsing, so a typo fails before any walking.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct RevRange {
+    include: Vec<Oid>,
+    exclude: Vec<Oid>,
+    /// Endpoints of `A...B`, whose common history is excluded
+    symmetric: Vec<(Oid, Oid)>,
+}
+
+impl RevRange {
+    /// Parses revision arguments. An empty side of `..` or `...` means HEAD,
+    /// surrounding whitespace is ignored and endpoints may be abbreviated
+    /// hashes. An argument that does not resolve fails with
+    /// "unknown revision '<arg>'".
+    fn parse<S: AsRef<str>>(refs: &RefStore, args: &[S]) -> io::Result<RevRange> {
+        let mut range = RevRange::default();
+        for arg in args {
+            let arg = arg.as_ref().trim();
+            if arg.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty revision"));
+            }
+
+            if let Some((left, right)) = arg.split_once("...") {
+                let left = resolve(refs, left)?;
+                let right = resolve(refs, right)?;
+                range.include.push(left);
+                range.include.push(right);
+                range.symmetric.push((left, right));
+            } else if let Some((left, right)) = arg.split_once("..") {
+                range.exclude.push(resolve(refs, left)?);
+                range.include.push(resolve(refs, right)?);
+            } else if let Some(excluded) = arg.strip_prefix('^') {
+                range.exclude.push(resolve(refs, excluded)?);
             } else {
-                // HEAD points directly to a commit
-                Some(head_ref)
+                range.include.push(resolve(refs, arg)?);
             }
-        })
-        .unwrap_or_default();
+        }
+        Ok(range)
+    }
+
+    /// Commits whose history is walked
+    fn include(&self) -> &[Oid] {
+        &self.include
+    }
+
+    /// Adds more commits whose history is walked
+    fn extend(&mut self, commits: impl IntoIterator<Item = Oid>) {
+        self.include.extend(commits);
+    }
+
+    /// Walks the commits in the range, newest first
+    fn iter<'a>(&self, repo: &'a Repository) -> io::Result<RevWalk<'a>> {
+        let mut hidden = HashSet::new();
+        for &hash in &self.exclude {
+            hidden.extend(ancestors(repo, hash)?);
+        }
+        for &(left, right) in &self.symmetric {
+            let left = ancestors(repo, left)?;
+            hidden.extend(ancestors(repo, right)?.into_iter().filter(|oid| left.contains(oid)));
+        }
+        RevWalk::new(repo, self.include.iter().copied(), hidden)
+    }
+}
 
-    // Print commit history
-    while !current_hash.is_empty() {
-        let commit = Object::read_from_objects_dir(&repo.git_dir, &current_hash)?;
-        match commit {
-            Object::Commit { tree: _, parents, author, committer: _, message } => {
-                println!("commit {}", current_hash);
-                println!("Author: {} <{}>", author.name, author.email);
-                println!("Date:   {} {}", author.timestamp, author.timezone);
-                println!();
-                for line in message.lines() {
-                    println!("    {}", line);
-                }
-                println!();
+/// Resolves one endpoint, HEAD when it is empty
+fn resolve(refs: &RefStore, spec: &str) -> io::Result<Oid> {
+    let spec = match spec.trim() {
+        "" => "HEAD",
+        spec => spec,
+    };
+    resolve_revision(refs, spec).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("unknown revision '{}'", spec),
+        ),
+        _ => e,
+    })
+}
 
-                // Move to parent commit
-                current_hash = parents.first().cloned().unwrap_or_default();
+/// Builds the range to walk from the positional revisions and ref selections
+fn revisions(repo: &Repository, options: &LogOptions) -> io::Result<RevRange> {
+    let refs = repo.ref_store();
+    let mut range = RevRange::parse(&refs, &options.revisions)?;
+
+    let select_refs = options.all || options.branches.is_some() || options.tags.is_some();
+    if options.all || (range.include().is_empty() && !select_refs) {
+        match refs.resolve_commit("HEAD") {
+            Ok(hash) => range.extend([hash]),
+            // An unborn branch has no history, but other refs still might
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let all = options.all.then_some("*");
+    let namespaces = [
+        ("refs/heads", all.or(options.branches.as_deref())),
+        ("refs/tags", all.or(options.tags.as_deref())),
+        ("refs/remotes", all),
+    ];
+    for (namespace, pattern) in namespaces {
+        let Some(pattern) = pattern else { continue };
+        let matching: Vec<Oid> = refs.list_refs(namespace)?
+            .into_iter()
+            .filter(|(name, _)| ref_matches(pattern, name))
+            .filter_map(|(_, hash)| hash)
+            .collect();
+        range.extend(matching);
+    }
+    Ok(range)
+}
+
+/// Matches a ref name against a `--branches`/`--tags` pattern. Like git, a
+/// pattern without wildcards selects that ref and everything below it.
+fn ref_matches(pattern: &str, name: &str) -> bool {
+    if pattern.contains(['*', '?', '[']) {
+        glob_match(pattern, name)
+    } else {
+        name == pattern || name.strip_prefix(pattern).is_some_and(|rest| rest.starts_with('/'))
+    }
+}
+
+/// Checks the -S/-G filters against the files the commit changed
+fn matches_pickaxe(repo: &Repository, changes: &[TreeChange], options: &LogOptions, algorithm: Algorithm) -> io::Result<bool> {
+    // Blobs are loaded one file pair at a time and dropped before the next
+    for change in changes {
+        let old = change.old.as_ref().map_or(Ok(Vec::new()), |entry| Object::read_blob(&repo.git_dir, entry.hash))?;
+        let new = change.new.as_ref().map_or(Ok(Vec::new()), |entry| Object::read_blob(&repo.git_dir, entry.hash))?;
+
+        if let Some(needle) = &options.pickaxe {
+            if count_occurrences(&old, needle.as_bytes()) != count_occurrences(&new, needle.as_bytes()) {
+                return Ok(true);
+            }
+        }
+
+        if let Some(regex) = &options.pickaxe_regex {
+            let old_lines = split_lines(&old);
+            let new_lines = split_lines(&new);
+            let changed = algorithm.diff_lines(&old_lines, &new_lines).into_iter().any(|edit| {
+                let line = match edit {
+                    Edit::Delete { old } => old_lines[old],
+                    Edit::Insert { new } => new_lines[new],
+                    Edit::Equal { .. } => return false,
+                };
+                regex.is_match(String::from_utf8_lossy(line).trim_end_matches('\n'))
+            });
+            if changed {
+                return Ok(true);
             }
-            _ => break,
         }
     }
 
-    Ok(())
-} 
\ No newline at end of file
+    Ok(false)
+}
+
+/// Counts non-overlapping occurrences of `needle` in `haystack`
+fn count_occurrences(haystack: &[u8], needle: &[u8]) -> usize {
+    if needle.is_empty() {
+        return 0;
+    }
+    let mut count = 0;
+    let mut i = 0;
+    while i + needle.len() <= haystack.len() {
+        if &haystack[i..i + needle.len()] == needle {
+            count += 1;
+            i += needle.len();
+        } else {
+            i += 1;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::core::index::IndexEntry;
+    use crate::cobra::core::signature::Signature;
+    use crate::cobra::test_support::{commit_at, forked_history, temp_repo};
+    use crate::cobra::utils::date;
+
+    fn commit_file(repo: &mut Repository, name: &str, content: &str, message: &str) -> io::Result<Oid> {
+        let path = repo.root_path.join(name);
+        fs::write(&path, content)?;
+        let hash = repo.write_object(&Object::new_blob(content.as_bytes().to_vec()))?;
+        repo.add_to_index(IndexEntry::new(PathBuf::from(name), hash, fs::metadata(&path)?))?;
+        commit(repo, message)
+    }
+
+    fn collect(repo: &Repository, options: &LogOptions) -> io::Result<Vec<Oid>> {
+        let mut hashes = Vec::new();
+        walk(repo, options, |hash, _, _| {
+            hashes.push(hash);
+            Ok(())
+        })?;
+        Ok(hashes)
+    }
+
+    #[test]
+    fn test_pickaxe_finds_add_and_remove() -> io::Result<()> {
+        let (_temp_dir, mut repo) = temp_repo()?;
+
+        let added = commit_file(&mut repo, "lib.rs", "fn a() {}\nfn marker() {}\n", "Add marker")?;
+        let _edited = commit_file(&mut repo, "lib.rs", "fn a() { 1 }\nfn marker() {}\n", "Edit a")?;
+        let removed = commit_file(&mut repo, "lib.rs", "fn a() { 1 }\n", "Remove marker")?;
+
+        let options = LogOptions { pickaxe: Some("marker".to_string()), ..LogOptions::default() };
+        assert_eq!(collect(&repo, &options)?, vec![removed, added]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pickaxe_regex_matches_changed_lines() -> io::Result<()> {
+        let (_temp_dir, mut repo) = temp_repo()?;
+
+        let _first = commit_file(&mut repo, "lib.rs", "fn a() {}\nfn marker() {}\n", "First")?;
+        let edited = commit_file(&mut repo, "lib.rs", "fn a() { 1 }\nfn marker() {}\n", "Edit a")?;
+
+        // The edit commit moves "marker" lines only as context, so -G does not see it
+        let options = LogOptions { pickaxe_regex: Some(Regex::new("fn a\\(\\) \\{ [0-9]")?), ..LogOptions::default() };
+        assert_eq!(collect(&repo, &options)?, vec![edited]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_from_several_starting_points() -> io::Result<()> {
+        let (_temp_dir, mut repo) = temp_repo()?;
+        let base = commit_file(&mut repo, "file.txt", "base\n", "Base")?;
+        let main = commit_file(&mut repo, "file.txt", "main\n", "Main")?;
+
+        // A side branch that is never checked out, one second newer than main
+        let refs = repo.ref_store();
+        let (tree, mut signature) = match Object::read_from_objects_dir(&repo.git_dir, main)? {
+            Object::Commit { tree, committer, .. } => (tree, committer),
+            _ => unreachable!(),
+        };
+        signature.timestamp += 1;
+        let side = repo.write_object(&Object::new_commit(tree, vec![base], signature.clone(), signature, "Side".to_string()))?;
+        refs.update_ref("refs/heads/feature/side", side, "test")?;
+        refs.update_ref("refs/tags/v1", base, "test")?;
+
+        assert_eq!(collect(&repo, &LogOptions::default())?, vec![main, base]);
+
+        let all = LogOptions { all: true, ..LogOptions::default() };
+        assert_eq!(collect(&repo, &all)?, vec![side, main, base]);
+
+        for pattern in ["feature", "feature/*", "*side"] {
+            let branches = LogOptions { branches: Some(pattern.to_string()), ..LogOptions::default() };
+            assert_eq!(collect(&repo, &branches)?, vec![side, base], "{}", pattern);
+        }
+        let no_match = LogOptions { branches: Some("feat".to_string()), ..LogOptions::default() };
+        assert!(collect(&repo, &no_match)?.is_empty());
+
+        let tags = LogOptions { tags: Some("*".to_string()), ..LogOptions::default() };
+        assert_eq!(collect(&repo, &tags)?, vec![base]);
+
+        // Shared history is listed once however many starting points reach it
+        let revisions = LogOptions {
+            revisions: vec!["main".to_string(), "feature/side".to_string(), "v1".to_string(), "main".to_string()],
+            ..LogOptions::default()
+        };
+        assert_eq!(collect(&repo, &revisions)?, vec![side, main, base]);
+
+        let range = LogOptions { revisions: vec!["main..feature/side".to_string()], ..LogOptions::default() };
+        assert_eq!(collect(&repo, &range)?, vec![side]);
+        let excluded = LogOptions { revisions: vec!["^main".to_string()], all: true, ..LogOptions::default() };
+        assert_eq!(collect(&repo, &excluded)?, vec![side]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_count_limits_printed_commits() -> io::Result<()> {
+        let (_temp_dir, mut repo) = temp_repo()?;
+        let first = commit_file(&mut repo, "lib.rs", "fn marker() {}\n", "Add marker")?;
+        let _second = commit_file(&mut repo, "other.rs", "fn other() {}\n", "Add other")?;
+        let third = commit_file(&mut repo, "lib.rs", "fn marker() { 1 }\n", "Edit marker")?;
+        let fourth = commit_file(&mut repo, "other.rs", "fn other() { 1 }\n", "Edit other")?;
+
+        let limited = |max_count| LogOptions { max_count: Some(max_count), ..LogOptions::default() };
+        assert_eq!(collect(&repo, &limited(2))?, vec![fourth, third]);
+        assert_eq!(collect(&repo, &limited(10))?.len(), 4);
+        assert!(collect(&repo, &limited(0))?.is_empty());
+
+        // Only commits that pass the filters count towards the limit
+        let options = LogOptions { paths: vec![PathBuf::from("lib.rs")], ..limited(2) };
+        assert_eq!(collect(&repo, &options)?, vec![third, first]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_author_filter_keeps_walking_past_other_authors() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let tree = repo.write_object(&Object::new_tree())?;
+        let mut parents = Vec::new();
+        let mut commit_by = |name: &str, email: &str| -> io::Result<Oid> {
+            let author = Signature::new(name.to_string(), email.to_string());
+            let commit = Object::new_commit(tree, parents.clone(), author.clone(), author, name.to_string());
+            let hash = repo.write_object(&commit)?;
+            parents = vec![hash];
+            Ok(hash)
+        };
+        let first = commit_by("Ada Lovelace", "ada@example.com")?;
+        let _second = commit_by("Grace Hopper", "grace@navy.example")?;
+        let third = commit_by("Ada Lovelace", "ada@example.com")?;
+
+        let by = |author: &str| LogOptions {
+            author: Some(author.to_string()),
+            revisions: vec![third.to_string()],
+            ..LogOptions::default()
+        };
+        assert_eq!(collect(&repo, &by("lovelace"))?, vec![third, first]);
+        assert_eq!(collect(&repo, &by("NAVY"))?.len(), 1);
+        assert!(collect(&repo, &by("nobody"))?.is_empty());
+
+        // The limit counts only commits that pass the filter
+        let options = LogOptions { max_count: Some(2), ..by("ada@") };
+        assert_eq!(collect(&repo, &options)?, vec![third, first]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_window_skips_commits_outside_it() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let tree = repo.write_object(&Object::new_tree())?;
+        let mut parents = Vec::new();
+        let mut commit_on = |date: &str| -> io::Result<Oid> {
+            let mut author = Signature::new("Ada".to_string(), "ada@example.com".to_string());
+            author.timestamp = date::parse(date)?;
+            let commit = Object::new_commit(tree, parents.clone(), author.clone(), author, date.to_string());
+            let hash = repo.write_object(&commit)?;
+            parents = vec![hash];
+            Ok(hash)
+        };
+        let january = commit_on("2024-01-15")?;
+        let march = commit_on("2024-03-15")?;
+        // Out of order on purpose, the window is not a cut-off for the walk
+        let february = commit_on("2024-02-15")?;
+        let may = commit_on("2024-05-15")?;
+
+        let window = |since: Option<&str>, until: Option<&str>| -> io::Result<LogOptions> {
+            Ok(LogOptions {
+                since: since.map(date::parse).transpose()?,
+                until: until.map(date::parse).transpose()?,
+                revisions: vec![may.to_string()],
+                ..LogOptions::default()
+            })
+        };
+        assert_eq!(collect(&repo, &window(Some("2024-02-01"), None)?)?, vec![may, february, march]);
+        assert_eq!(collect(&repo, &window(None, Some("2024-02-15"))?)?, vec![february, january]);
+        assert_eq!(collect(&repo, &window(Some("2024-02-01"), Some("2024-03-31"))?)?, vec![february, march]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_corrupt_and_looping_history() -> io::Result<()> {
+        use crate::cobra::state::{GlobalOpts, OutputHandle};
+
+        let (temp_dir, repo) = temp_repo()?;
+        let tree = repo.write_object(&Object::new_tree())?;
+        let commit_with = |parents: Vec<Oid>, message: &str| {
+            let author = Signature::new("Ada".to_string(), "ada@example.com".to_string());
+            repo.write_object(&Object::new_commit(tree, parents, author.clone(), author, message.to_string()))
+        };
+        let loose = |hash: Oid| hash.loose_path(&repo.git_dir);
+        let run_log = |revisions: &[Oid]| -> io::Result<String> {
+            let repo = Repository::open(temp_dir.path().to_str().unwrap())?;
+            let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+            let options = LogOptions { revisions: revisions.iter().map(|rev| rev.to_string()).collect(), ..LogOptions::default() };
+            run(&mut ctx, &options)?;
+            Ok(ctx.out.captured())
+        };
+
+        // A parent that was never written ends the history with a notice
+        let dangling: Oid = "ab".repeat(20).parse()?;
+        let orphaned = commit_with(vec![dangling], "orphaned")?;
+        let output = run_log(&[orphaned])?;
+        assert!(output.contains(&format!("commit {}", orphaned)), "{}", output);
+        assert!(output.ends_with(&format!("error: commit {} not found, history truncated\n", dangling)), "{}", output);
+        // ... unless the walk stops before it gets there
+        let mut ctx = CommandContext::new(Repository::open(temp_dir.path().to_str().unwrap())?, OutputHandle::buffer(false), GlobalOpts::default());
+        run(&mut ctx, &LogOptions { revisions: vec![orphaned.to_string()], max_count: Some(1), ..LogOptions::default() })?;
+        assert!(!ctx.out.captured().contains("not found"));
+
+        // A parent chain looping back on itself is walked once
+        let forged: Oid = "cd".repeat(20).parse()?;
+        let first = commit_with(vec![forged], "first")?;
+        let loops_back = commit_with(vec![first], "loops back")?;
+        fs::create_dir_all(loose(forged).parent().unwrap())?;
+        fs::copy(loose(loops_back), loose(forged))?;
+        let output = run_log(&[first])?;
+        assert_eq!(output.matches("commit ").count(), 2, "{}", output);
+
+        // A damaged object is an error naming it
+        let damaged: Oid = "ef".repeat(20).parse()?;
+        let above_damage = commit_with(vec![damaged], "above damage")?;
+        fs::create_dir_all(loose(damaged).parent().unwrap())?;
+        fs::write(loose(damaged), b"not zlib")?;
+        let error = run_log(&[above_damage]).unwrap_err();
+        assert!(error.to_string().contains(&damaged.to_string()), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_occurrences() {
+        assert_eq!(count_occurrences(b"aaaa", b"aa"), 2);
+        assert_eq!(count_occurrences(b"abc", b"x"), 0);
+        assert_eq!(count_occurrences(b"abc", b""), 0);
+    }
+
+    #[test]
+    fn test_name_status_and_raw_list_changes() -> io::Result<()> {
+        use crate::cobra::core::index::Index;
+        use crate::cobra::state::{GlobalOpts, OutputHandle};
+
+        let (temp_dir, mut repo) = temp_repo()?;
+        fs::create_dir(temp_dir.path().join("src"))?;
+        commit_file(&mut repo, "old.txt", "old\n", "Add old")?;
+        commit_file(&mut repo, "src/lib.rs", "fn a() {}\n", "Add lib")?;
+
+        // Drop old.txt, edit lib.rs and add main.rs in one commit
+        repo.index = Index::new();
+        for (name, content) in [("src/lib.rs", "fn b() {}\n"), ("src/main.rs", "fn main() {}\n")] {
+            let path = repo.root_path.join(name);
+            fs::write(&path, content)?;
+            let hash = repo.write_object(&Object::new_blob(content.as_bytes().to_vec()))?;
+            repo.add_to_index(IndexEntry::new(PathBuf::from(name), hash, fs::metadata(&path)?))?;
+        }
+        commit(&repo, "Rework")?;
+
+        let short = |content: &str| repo.short_id(Object::new_blob(content.as_bytes().to_vec()).hash());
+        let zeros = "0".repeat(repo.abbrev);
+        let run_log = |options: LogOptions| -> io::Result<Vec<String>> {
+            let repo = Repository::open(temp_dir.path().to_str().unwrap())?;
+            let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+            run(&mut ctx, &options)?;
+            // Keep only the change lines, with a marker between commits
+            Ok(ctx.out.captured().lines()
+                .filter(|line| line.starts_with("commit ") || line.contains('\t'))
+                .map(|line| if line.starts_with("commit ") { "--".to_string() } else { line.to_string() })
+                .collect())
+        };
+
+        let name_status = run_log(LogOptions { changes: Some(ChangeFormat::NameStatus), ..LogOptions::default() })?;
+        assert_eq!(name_status, vec![
+            "--", "D\told.txt", "M\tsrc/lib.rs", "A\tsrc/main.rs",
+            "--", "A\tsrc/lib.rs",
+            "--", "A\told.txt",
+        ]);
+
+        let raw = run_log(LogOptions { changes: Some(ChangeFormat::Raw), ..LogOptions::default() })?;
+        assert_eq!(raw[..4], [
+            "--".to_string(),
+            format!(":100644 000000 {} {} D\told.txt", short("old\n"), zeros),
+            format!(":100644 100644 {} {} M\tsrc/lib.rs", short("fn a() {}\n"), short("fn b() {}\n")),
+            format!(":000000 100644 {} {} A\tsrc/main.rs", zeros, short("fn main() {}\n")),
+        ]);
+
+        // Path limiting drops commits and files outside src/
+        let limited = run_log(LogOptions {
+            changes: Some(ChangeFormat::NameStatus),
+            paths: vec![PathBuf::from("src/")],
+            ..LogOptions::default()
+        })?;
+        assert_eq!(limited, vec!["--", "M\tsrc/lib.rs", "A\tsrc/main.rs", "--", "A\tsrc/lib.rs"]);
+        Ok(())
+    }
+
+    fn walk_range(repo: &Repository, args: &[&str]) -> io::Result<Vec<Oid>> {
+        RevRange::parse(&repo.ref_store(), args)?
+            .iter(repo)?
+            .map(|commit| commit.map(|(hash, _)| hash))
+            .collect()
+    }
+
+    #[test]
+    fn test_range_syntaxes() -> io::Result<()> {
+        let (_temp_dir, repo, [base, m1, f1, f2]) = forked_history()?;
+
+        assert_eq!(walk_range(&repo, &["feature"])?, vec![f2, f1, base]);
+        assert_eq!(walk_range(&repo, &["main", "feature"])?, vec![f2, f1, m1, base]);
+        assert_eq!(walk_range(&repo, &["main..feature"])?, vec![f2, f1]);
+        assert_eq!(walk_range(&repo, &["^main", "feature"])?, vec![f2, f1]);
+        assert_eq!(walk_range(&repo, &["feature..main"])?, vec![m1]);
+        assert_eq!(walk_range(&repo, &["main...feature"])?, vec![f2, f1, m1]);
+        assert!(walk_range(&repo, &["feature..feature"])?.is_empty());
+
+        // An empty side is HEAD, which is main
+        assert_eq!(walk_range(&repo, &["..feature"])?, vec![f2, f1]);
+        assert_eq!(walk_range(&repo, &["feature.."])?, vec![m1]);
+
+        // Endpoints may carry ancestry suffixes
+        let merge = commit_at(&repo, &[m1, f2], "merge", 5000)?;
+        repo.ref_store().update_ref("refs/heads/main", merge, "test")?;
+        assert_eq!(walk_range(&repo, &["main^2~1..feature"])?, vec![f2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_whitespace_and_abbreviations() -> io::Result<()> {
+        let (_temp_dir, repo, [_base, m1, f1, f2]) = forked_history()?;
+
+        assert_eq!(walk_range(&repo, &["  main .. feature\n"])?, vec![f2, f1]);
+        let short = format!("{}..{}", m1.abbrev(7), f2.abbrev(4));
+        assert_eq!(walk_range(&repo, &[short.as_str()])?, vec![f2, f1]);
+        let range = RevRange::parse(&repo.ref_store(), &[f2.to_string().to_uppercase()])?;
+        assert_eq!(range.include().to_vec(), vec![f2]);
+
+        assert!(RevRange::parse(&repo.ref_store(), &["   "]).is_err());
+        // Too short to be taken for a hash
+        let error = RevRange::parse(&repo.ref_store(), &[f2.abbrev(3)]).unwrap_err();
+        assert_eq!(error.to_string(), format!("unknown revision '{}'", f2.abbrev(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_revisions() -> io::Result<()> {
+        let (_temp_dir, repo, _) = forked_history()?;
+        for (args, name) in [("foo", "foo"), ("main..foo", "foo"), ("foo...main", "foo"), ("^foo", "foo")] {
+            let error = RevRange::parse(&repo.ref_store(), &[args]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::NotFound);
+            assert_eq!(error.to_string(), format!("unknown revision '{}'", name));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+// List the files in the index
+use std::fs;
+use std::io::{self, Write};
+use crate::cobra::core::attributes::{classify, classify_bytes, Attributes};
+use crate::cobra::core::index::normalize_mode;
+use crate::cobra::core::object::Object;
+use crate::cobra::state::CommandContext;
+
+#[derive(Debug, Default)]
+pub struct LsFilesOptions {
+    /// Show the line endings of the staged and work tree content and the
+    /// text/eol attributes that apply (--eol)
+    pub eol: bool,
+    /// Show the mode, object name and stage number of each entry (--stage)
+    pub stage: bool,
+    /// End each line with NUL instead of a newline (-z)
+    pub null_terminated: bool,
+}
+
+/// Prints the tracked paths sorted by name. As in git, --stage starts each
+/// line with `<mode> <object> <stage>` and --eol with `i/<index>
+/// w/<work tree> attr/<attributes>`, each followed by a tab.
+pub fn run(ctx: &mut CommandContext, options: &LsFilesOptions) -> io::Result<()> {
+    let mut entries: Vec<_> = ctx.repo.index.entries().cloned().collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let attributes = if options.eol { Attributes::load(&ctx.repo)? } else { Attributes::default() };
+    let terminator = if options.null_terminated { '\0' } else { '\n' };
+
+    for entry in entries {
+        if options.stage {
+            // The index holds no conflict stages, so every entry is stage 0
+            write!(ctx.out, "{:06o} {} 0\t", normalize_mode(entry.mode), entry.hash)?;
+        }
+        if !options.eol {
+            write!(ctx.out, "{}{}", entry.path.display(), terminator)?;
+            continue;
+        }
+        let staged = match ctx.repo.read_object(entry.hash)? {
+            Object::Blob(content) => classify_bytes(&content).to_string(),
+            _ => String::new(),
+        };
+        // A file missing from the work tree leaves its column empty
+        let worktree = match fs::File::open(ctx.repo.root_path.join(&entry.path)) {
+            Ok(mut file) => classify(&mut file)?.to_string(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let attr = attributes.text_setting(&entry.path).to_string();
+        write!(ctx.out, "i/{:<6} w/{:<6} attr/{:<17}\t{}{}", staged, worktree, attr, entry.path.display(), terminator)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::cobra::commands::add::{self, AddOptions};
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    fn blob(ctx: &CommandContext, path: &str) -> io::Result<Vec<u8>> {
+        let hash = ctx.repo.index.get_entry(Path::new(path)).unwrap().hash;
+        match ctx.repo.read_object(hash)? {
+            Object::Blob(content) => Ok(content),
+            _ => Err(io::Error::other("not a blob")),
+        }
+    }
+
+    #[test]
+    fn test_eol_audit_and_renormalize() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let files: [(&str, &[u8]); 4] = [
+            ("crlf.txt", b"one\r\ntwo\r\n"),
+            ("lf.txt", b"one\ntwo\n"),
+            ("mixed.txt", b"one\r\ntwo\n"),
+            ("image.bin", b"\x89PNG\r\n\0\r\n"),
+        ];
+        for (name, content) in files {
+            fs::write(temp_dir.path().join(name), content)?;
+        }
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        // Staged before any attributes exist, so stored as they are
+        add::run(&mut ctx, &[], &AddOptions { all: true, ..Default::default() })?;
+
+        run(&mut ctx, &LsFilesOptions { eol: true, ..Default::default() })?;
+        assert_eq!(ctx.out.captured(), [
+            "i/crlf   w/crlf   attr/                 \tcrlf.txt\n",
+            "i/binary w/binary attr/                 \timage.bin\n",
+            "i/lf     w/lf     attr/                 \tlf.txt\n",
+            "i/mixed  w/mixed  attr/                 \tmixed.txt\n",
+        ].concat());
+
+        fs::write(temp_dir.path().join(".cobraattributes"), "* text=auto\n")?;
+        add::run(&mut ctx, &[], &AddOptions { renormalize: true, ..Default::default() })?;
+        assert_eq!(blob(&ctx, "crlf.txt")?, b"one\ntwo\n");
+        assert_eq!(blob(&ctx, "mixed.txt")?, b"one\ntwo\n");
+        assert_eq!(blob(&ctx, "image.bin")?, b"\x89PNG\r\n\0\r\n");
+
+        let mut ctx = CommandContext::new(ctx.repo, OutputHandle::buffer(false), GlobalOpts::default());
+        run(&mut ctx, &LsFilesOptions { eol: true, ..Default::default() })?;
+        let output = ctx.out.captured();
+        assert!(output.contains("i/lf     w/crlf   attr/text=auto        \tcrlf.txt\n"), "{}", output);
+        assert!(output.contains("i/binary w/binary attr/text=auto        \timage.bin\n"), "{}", output);
+
+        // The converted files are not modified as far as status can tell
+        let report = crate::cobra::commands::status::collect(&ctx.repo)?;
+        assert!(report.modified().next().is_none(), "{:?}", report.entries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stage_and_null_terminated_output() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        // An empty index prints nothing
+        run(&mut ctx, &LsFilesOptions::default())?;
+        assert_eq!(ctx.out.captured(), "");
+
+        for name in ["zeta.txt", "alpha.txt", "dir/mid.txt"] {
+            fs::create_dir_all(temp_dir.path().join(name).parent().unwrap())?;
+            fs::write(temp_dir.path().join(name), "hello\n")?;
+            add::run(&mut ctx, &[name.to_string()], &AddOptions::default())?;
+        }
+        ctx.out = OutputHandle::buffer(false);
+        run(&mut ctx, &LsFilesOptions::default())?;
+        assert_eq!(ctx.out.captured(), "alpha.txt\ndir/mid.txt\nzeta.txt\n");
+
+        ctx.out = OutputHandle::buffer(false);
+        run(&mut ctx, &LsFilesOptions { stage: true, null_terminated: true, ..Default::default() })?;
+        let hello = "ce013625030ba8dba906f756967f9e9ca394464a";
+        assert_eq!(ctx.out.captured(), format!(
+            "100644 {0} 0\talpha.txt\x00100644 {0} 0\tdir/mid.txt\x00100644 {0} 0\tzeta.txt\x00",
+            hello,
+        ));
+        Ok(())
+    }
+}
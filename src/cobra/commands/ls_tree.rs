@@ -0,0 +1,105 @@
+// List the entries of a tree
+use std::io::{self, Write};
+use std::path::Path;
+use crate::cobra::core::{
+    object::{Object, TreeEntry},
+    oid::Oid,
+    pack::GITLINK_MODE,
+    revision::resolve_object,
+};
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+#[derive(Debug, Default)]
+pub struct LsTreeOptions {
+    /// Descend into subtrees, listing files by their full path (-r)
+    pub recursive: bool,
+    /// Print only the paths (--name-only)
+    pub name_only: bool,
+}
+
+/// Lists the tree `spec` names, or the tree of the commit it names, as
+/// `<mode> <type> <object>\t<path>` lines
+pub fn run(ctx: &mut CommandContext, spec: &str, options: &LsTreeOptions) -> io::Result<()> {
+    let entries = match resolve_object(&ctx.refs, spec)? {
+        (_, Object::Tree(entries)) => entries,
+        (_, Object::Commit { tree, .. }) => match ctx.refs.read_object(tree)? {
+            Object::Tree(entries) => entries,
+            _ => return Err(not_a_tree(tree)),
+        },
+        (hash, Object::Blob(_)) => return Err(not_a_tree(hash)),
+    };
+    write_entries(ctx, &entries, Path::new(""), options)
+}
+
+fn write_entries(ctx: &mut CommandContext, entries: &[TreeEntry], prefix: &Path, options: &LsTreeOptions) -> io::Result<()> {
+    for entry in entries {
+        let path = prefix.join(&entry.name);
+        let kind = match entry.mode {
+            0o40000 => "tree",
+            GITLINK_MODE => "commit",
+            _ => "blob",
+        };
+        if kind == "tree" && options.recursive {
+            match ctx.refs.read_object(entry.hash)? {
+                Object::Tree(children) => write_entries(ctx, &children, &path, options)?,
+                _ => return Err(not_a_tree(entry.hash)),
+            }
+            continue;
+        }
+        match options.name_only {
+            true => writeln!(ctx.out, "{}", path.display())?,
+            false => writeln!(ctx.out, "{:06o} {} {}\t{}", entry.mode, kind, entry.hash, path.display())?,
+        }
+    }
+    Ok(())
+}
+
+fn not_a_tree(hash: Oid) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, messages::not_a_tree(&hash.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::core::tree::commit_tree;
+    use crate::cobra::state::OutputHandle;
+    use crate::cobra::test_support::repo_with_files;
+    use tempfile::TempDir;
+
+    fn setup() -> io::Result<(TempDir, CommandContext)> {
+        repo_with_files(&[("README", "hello\n"), ("src/lib.rs", "fn lib() {}\n"), ("src/bin/main.rs", "fn main() {}\n")])
+    }
+
+    #[test]
+    fn test_ls_tree_top_level_and_recursive() -> io::Result<()> {
+        let (_temp_dir, mut ctx) = setup()?;
+        let readme = Object::new_blob(b"hello\n".to_vec()).hash();
+
+        run(&mut ctx, "HEAD", &LsTreeOptions::default())?;
+        let lines: Vec<String> = ctx.out.captured().lines().map(str::to_string).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], format!("100644 blob {}\tREADME", readme));
+        assert!(lines[1].starts_with("040000 tree ") && lines[1].ends_with("\tsrc"), "{}", lines[1]);
+
+        // A tree name works as well as a commit
+        let tree = commit_tree(&ctx.repo.git_dir, ctx.refs.resolve_commit("HEAD")?)?;
+        ctx.out = OutputHandle::buffer(false);
+        run(&mut ctx, &tree.to_string(), &LsTreeOptions { recursive: true, name_only: true })?;
+        assert_eq!(ctx.out.captured(), "README\nsrc/bin/main.rs\nsrc/lib.rs\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ls_tree_rejects_blobs_and_unknown_names() -> io::Result<()> {
+        let (_temp_dir, mut ctx) = setup()?;
+        let readme = Object::new_blob(b"hello\n".to_vec()).hash();
+
+        let error = run(&mut ctx, &readme.to_string(), &LsTreeOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), messages::not_a_tree(&readme.to_string()));
+        assert!(run(&mut ctx, "no-such-branch", &LsTreeOptions::default()).is_err());
+        assert!(run(&mut ctx, &"0".repeat(40), &LsTreeOptions::default()).is_err());
+        Ok(())
+    }
+}
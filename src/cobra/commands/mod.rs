@@ -4,4 +4,29 @@ pub mod commit;
 pub mod log;
 pub mod status;
 pub mod branch;
-pub mod stash; 
\ No newline at end of file
+pub mod stash;
+pub mod gc;
+pub mod diff;
+pub mod tag;
+pub mod blame;
+pub mod show_branch;
+pub mod cherry;
+pub mod request_pull;
+pub mod fsck;
+pub mod config;
+pub mod daemon;
+pub mod fetch;
+pub mod verify_index;
+pub mod ls_files;
+pub mod rm;
+pub mod mv;
+pub mod restore;
+pub mod difftool;
+pub mod reset;
+pub mod import;
+pub mod show;
+pub mod hash_object;
+pub mod ls_tree;
+pub mod rev_parse;
+pub mod rebase;
+pub mod reflog;
@@ -0,0 +1,151 @@
+// Move or rename tracked files
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::cobra::commands::add::resolve_path;
+use crate::cobra::core::index::IndexEntry;
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+#[derive(Debug, Default)]
+pub struct MvOptions {
+    /// Replace an existing destination file (-f/--force)
+    pub force: bool,
+}
+
+/// Moves `source` to `destination` in the work tree and the index. The
+/// source is a tracked file, or a directory whose tracked files all move
+/// with it. A destination that is an existing directory receives the
+/// source under its own name. Staged content moves as it is: the entries
+/// keep their blobs, and only files still holding them get their stat data
+/// recorded, so unstaged edits stay visible.
+pub fn run(ctx: &mut CommandContext, source: &str, destination: &str, options: &MvOptions) -> io::Result<()> {
+    let repo = &mut ctx.repo;
+    let from = resolve_path(repo, Path::new(source))?;
+    let mut to = resolve_path(repo, Path::new(destination))?;
+    let source_is_dir = repo.root_path.join(&from).is_dir();
+    if repo.root_path.join(&to).is_dir() {
+        if let Some(name) = from.file_name() {
+            to = to.join(name);
+        }
+    }
+
+    let moved: Vec<IndexEntry> = repo.index.entries()
+        .filter(|entry| if source_is_dir { entry.path.starts_with(&from) } else { entry.path == from })
+        .cloned()
+        .collect();
+    if moved.is_empty() || from.as_os_str().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("'{}' is not under version control", source),
+        ));
+    }
+    if to.starts_with(&from) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Cannot move '{}' into itself", source),
+        ));
+    }
+    let target = repo.root_path.join(&to);
+    if !target.parent().is_some_and(Path::is_dir) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Destination directory for '{}' does not exist", to.display()),
+        ));
+    }
+    if fs::symlink_metadata(&target).is_ok() && (source_is_dir || !options.force) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Destination '{}' already exists{}", to.display(), if source_is_dir { "" } else { "; use -f to replace it" }),
+        ));
+    }
+
+    fs::rename(repo.root_path.join(&from), &target)?;
+    // A replaced destination is no longer tracked under its old content
+    repo.index.remove_entry(&to);
+    for entry in moved {
+        let relative = entry.path.strip_prefix(&from).map_err(io::Error::other)?;
+        let path = if relative.as_os_str().is_empty() { to.clone() } else { to.join(relative) };
+        let moved_entry = IndexEntry::from_tree_blob(repo, &path, entry.mode, entry.hash)?;
+        repo.index.remove_entry(&entry.path);
+        repo.index.add_entry(moved_entry);
+    }
+    repo.save_index()?;
+    ctx.out.note(format_args!("{}", messages::renamed(from.display(), to.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::commands::status::collect;
+    use crate::cobra::test_support::repo_with_files;
+    use tempfile::TempDir;
+
+    fn setup() -> io::Result<(TempDir, CommandContext)> {
+        repo_with_files(&[
+            ("a.txt", "a.txt\n"),
+            ("b.txt", "b.txt\n"),
+            ("src/lib.rs", "src/lib.rs\n"),
+            ("src/nested/mod.rs", "src/nested/mod.rs\n"),
+        ])
+    }
+
+    fn tracked(ctx: &CommandContext) -> Vec<String> {
+        let mut paths: Vec<String> = ctx.repo.index.entries().map(|entry| entry.path.display().to_string()).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_mv_file_and_directory() -> io::Result<()> {
+        let (temp_dir, mut ctx) = setup()?;
+        run(&mut ctx, "a.txt", "renamed.txt", &MvOptions::default())?;
+        run(&mut ctx, "src", "lib", &MvOptions::default())?;
+        assert_eq!(tracked(&ctx), ["b.txt", "lib/lib.rs", "lib/nested/mod.rs", "renamed.txt"]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("lib/nested/mod.rs"))?, "src/nested/mod.rs\n");
+        assert!(!temp_dir.path().join("src").exists());
+
+        // Only staged renames remain: nothing modified, nothing untracked
+        let report = collect(&ctx.repo)?;
+        assert!(report.untracked.is_empty(), "{:?}", report.untracked);
+        assert!(report.entries.iter().all(|entry| entry.unstaged_code() == '.'), "{:?}", report.entries);
+        commit(&ctx.repo, "Move things")?;
+        assert!(collect(&ctx.repo)?.is_clean());
+
+        // Into an existing directory, under the source's name
+        run(&mut ctx, "b.txt", "lib", &MvOptions::default())?;
+        assert_eq!(tracked(&ctx), ["lib/b.txt", "lib/lib.rs", "lib/nested/mod.rs", "renamed.txt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mv_onto_tracked_file_needs_force() -> io::Result<()> {
+        let (temp_dir, mut ctx) = setup()?;
+        let error = run(&mut ctx, "a.txt", "b.txt", &MvOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("b.txt"))?, "b.txt\n");
+
+        run(&mut ctx, "a.txt", "b.txt", &MvOptions { force: true })?;
+        assert_eq!(tracked(&ctx), ["b.txt", "src/lib.rs", "src/nested/mod.rs"]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("b.txt"))?, "a.txt\n");
+        assert!(collect(&ctx.repo)?.entries.iter().all(|entry| entry.unstaged_code() == '.'));
+
+        fs::write(temp_dir.path().join("untracked.txt"), "new\n")?;
+        let error = run(&mut ctx, "untracked.txt", "elsewhere.txt", &MvOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("not under version control"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mv_keeps_unstaged_edits_visible() -> io::Result<()> {
+        let (temp_dir, mut ctx) = setup()?;
+        fs::write(temp_dir.path().join("a.txt"), "A.TXT\n")?;
+        run(&mut ctx, "a.txt", "moved.txt", &MvOptions::default())?;
+
+        let report = collect(&ctx.repo)?;
+        let moved = report.entries.iter().find(|entry| entry.path == Path::new("moved.txt")).unwrap();
+        assert_eq!(moved.unstaged_code(), 'M', "{:?}", report.entries);
+        Ok(())
+    }
+}
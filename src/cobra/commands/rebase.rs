@@ -0,0 +1,344 @@
+// Reapply commits on top of another base, optionally as an edited todo list
+//
+// `rebase -i <base>` writes one `pick <commit> <subject>` line per commit
+// since the base to .cobra/rebase-merge/git-rebase-todo and opens the editor
+// on it. The edited list is parsed in full before anything happens, so a
+// malformed line aborts with the branch untouched. Each step then applies
+// its commit's changes to the tip built so far with a three-way merge, and
+// the branch only moves once every step has succeeded.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::cobra::commands::{branch, status};
+use crate::cobra::commands::commit::{cleanup_message, comment_lines};
+use crate::cobra::core::ancestry::commits_between;
+use crate::cobra::core::checkout::{checkout_tree, CheckoutOptions};
+use crate::cobra::core::config::Config;
+use crate::cobra::core::merge::{self, MergeState};
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::revision;
+use crate::cobra::core::signature::Signature;
+use crate::cobra::core::tree::commit_tree;
+use crate::cobra::state::CommandContext;
+use crate::cobra::utils::editor::{edit_file, editor_command};
+use crate::cobra::utils::progress::CounterProgress;
+use crate::cobra::messages;
+
+/// Directory holding the todo list while an interactive rebase runs
+pub const REBASE_MERGE_DIR: &str = "rebase-merge";
+const TODO_FILE: &str = "git-rebase-todo";
+const MESSAGE_FILE: &str = "message";
+
+#[derive(Debug, Default)]
+pub struct RebaseOptions {
+    /// Edit the list of commits before replaying them (-i/--interactive)
+    pub interactive: bool,
+    /// Editor command; COBRA_EDITOR, core.editor, VISUAL or EDITOR when None
+    pub editor: Option<String>,
+}
+
+/// What a todo line does with its commit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TodoAction {
+    /// Use the commit as it is
+    Pick,
+    /// Use the commit, editing its message
+    Reword,
+    /// Fold the commit into the previous one, keeping both messages
+    Squash,
+    /// Fold the commit into the previous one, keeping only the previous message
+    Fixup,
+    /// Leave the commit out
+    Drop,
+}
+
+impl TodoAction {
+    /// Parses a todo command, in full or by its one-letter abbreviation
+    pub fn parse(word: &str) -> Option<TodoAction> {
+        match word {
+            "pick" | "p" => Some(TodoAction::Pick),
+            "reword" | "r" => Some(TodoAction::Reword),
+            "squash" | "s" => Some(TodoAction::Squash),
+            "fixup" | "f" => Some(TodoAction::Fixup),
+            "drop" | "d" => Some(TodoAction::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// One line of the todo list
+#[derive(Debug, Clone, PartialEq)]
+pub struct TodoItem {
+    pub action: TodoAction,
+    /// The commit the line names
+    pub commit: Oid,
+}
+
+/// The commit built so far, which the next step applies its changes to
+struct Tip {
+    hash: Oid,
+    tree: Oid,
+    parents: Vec<Oid>,
+    author: Signature,
+    message: String,
+}
+
+pub fn run(ctx: &mut CommandContext, upstream: &str, options: &RebaseOptions) -> io::Result<()> {
+    if !options.interactive {
+        return branch::rebase(ctx, upstream);
+    }
+    let editor = match &options.editor {
+        Some(editor) => editor.clone(),
+        None => editor_command(&Config::load(&ctx.repo.git_dir)?),
+    };
+    interactive(ctx, upstream, &editor)
+}
+
+/// Lets the user edit the list of commits since `base`, then replays it
+/// on top of `base`. Merge commits are left out of the list.
+pub fn interactive(ctx: &mut CommandContext, base: &str, editor: &str) -> io::Result<()> {
+    let state_dir = ctx.repo.git_dir.join(REBASE_MERGE_DIR);
+    if state_dir.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, messages::rebase_in_progress(state_dir.display())));
+    }
+    if MergeState::in_progress(&ctx.repo) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, messages::merge_in_progress()));
+    }
+    if !status::collect(&ctx.repo)?.entries.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, messages::rebase_local_changes()));
+    }
+    let onto = revision::resolve_revision(&ctx.refs, base)?;
+    let head = ctx.refs.resolve_commit("HEAD")?;
+
+    fs::create_dir_all(&state_dir)?;
+    let result = edit_and_replay(ctx, &state_dir, onto, head, editor);
+    fs::remove_dir_all(&state_dir)?;
+    result
+}
+
+fn edit_and_replay(ctx: &mut CommandContext, state_dir: &Path, onto: Oid, head: Oid, editor: &str) -> io::Result<()> {
+    let mut todo = String::new();
+    let mut count = 0;
+    for hash in commits_between(&ctx.repo, onto, head)? {
+        let Object::Commit { parents, message, .. } = ctx.repo.read_object(hash)? else {
+            continue;
+        };
+        if parents.len() > 1 {
+            continue;
+        }
+        todo.push_str(&format!("pick {} {}\n", ctx.repo.short_id(hash), message.lines().next().unwrap_or("")));
+        count += 1;
+    }
+    let range = format!("{}..{}", ctx.repo.short_id(onto), ctx.repo.short_id(head));
+    todo.push('\n');
+    todo.push_str(&comment_lines(&messages::rebase_todo_help(range, ctx.repo.short_id(onto), count)));
+
+    let todo_path = state_dir.join(TODO_FILE);
+    fs::write(&todo_path, todo)?;
+    edit_file(editor, &todo_path)?;
+    let items = parse_todo(ctx, &fs::read_to_string(&todo_path)?)?;
+    if items.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, messages::rebase_nothing_to_do()));
+    }
+
+    let tip = replay(ctx, state_dir, onto, &items, editor)?;
+    let report = checkout_tree(&mut ctx.repo, tip.tree, &CheckoutOptions::default(), &mut CounterProgress::new("Updating files"))?;
+    branch::report_checkout(&mut ctx.out, &report)?;
+    let updated = match ctx.refs.current_branch()? {
+        Some(name) => format!("refs/heads/{}", name),
+        None => "HEAD".to_string(),
+    };
+    if tip.hash != head {
+        let mut transaction = ctx.repo.transaction();
+        transaction.update_head(Some(head), tip.hash, &format!("rebase -i (finish): {} onto {}", updated, onto))?;
+        transaction.commit()?;
+    }
+    ctx.out.note(format_args!("{}", messages::rebase_succeeded(updated)))
+}
+
+/// Parses an edited todo list. Blank lines and `#` comments are ignored;
+/// anything else must be a known command and a commit, or the whole list
+/// is rejected naming the line.
+pub fn parse_todo(ctx: &CommandContext, text: &str) -> io::Result<Vec<TodoItem>> {
+    let mut items: Vec<TodoItem> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidInput, messages::rebase_bad_todo_line(number + 1, reason));
+        let mut words = line.split_whitespace();
+        let word = words.next().unwrap_or_default();
+        let action = TodoAction::parse(word).ok_or_else(|| invalid(format!("unknown command '{}'", word)))?;
+        let name = words.next().ok_or_else(|| invalid(format!("'{}' needs a commit", word)))?;
+        let commit = ctx.refs.resolve_commit(name).map_err(|e| invalid(e.to_string()))?;
+        let folds = matches!(action, TodoAction::Squash | TodoAction::Fixup);
+        if folds && !items.iter().any(|item| item.action != TodoAction::Drop) {
+            return Err(invalid(format!("cannot '{}' without a previous commit", word)));
+        }
+        items.push(TodoItem { action, commit });
+    }
+    Ok(items)
+}
+
+/// Applies every item on top of `onto`, writing the new commits but
+/// moving no refs, and returns the last one
+fn replay(ctx: &CommandContext, state_dir: &Path, onto: Oid, items: &[TodoItem], editor: &str) -> io::Result<Tip> {
+    let Object::Commit { parents, author, message, .. } = ctx.repo.read_object(onto)? else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a commit", onto)));
+    };
+    let mut tip = Tip { hash: onto, tree: commit_tree(&ctx.repo.git_dir, onto)?, parents, author, message };
+
+    for item in items.iter().filter(|item| item.action != TodoAction::Drop) {
+        let Object::Commit { tree, parents, author, message, .. } = ctx.repo.read_object(item.commit)? else {
+            continue;
+        };
+        // A commit that already sits on the tip is kept as it is
+        if item.action == TodoAction::Pick && parents.first() == Some(&tip.hash) {
+            tip = Tip { hash: item.commit, tree, parents, author, message };
+            continue;
+        }
+
+        let mut transaction = ctx.repo.transaction();
+        let base = match parents.first() {
+            Some(parent) => Some(commit_tree(&ctx.repo.git_dir, *parent)?),
+            None => None,
+        };
+        let short = ctx.repo.short_id(item.commit);
+        let labels = merge::Labels { ours: "HEAD", theirs: &short };
+        let merged = merge::merge_trees(&ctx.repo.git_dir, &mut transaction, base, tip.tree, tree, labels)?;
+        if !merged.conflicts.is_empty() {
+            let paths: Vec<String> = merged.conflicts.iter().map(|conflict| conflict.path.display().to_string()).collect();
+            return Err(io::Error::other(messages::rebase_could_not_apply(&short, message.lines().next().unwrap_or(""), paths.join(", "))));
+        }
+
+        let (parents, author, message) = match item.action {
+            TodoAction::Squash => (tip.parents.clone(), tip.author.clone(), format!("{}\n\n{}", tip.message.trim_end_matches('\n'), message)),
+            TodoAction::Fixup => (tip.parents.clone(), tip.author.clone(), tip.message.clone()),
+            TodoAction::Reword => (vec![tip.hash], author, edit_message(state_dir, &message, editor)?),
+            _ => (vec![tip.hash], author, message),
+        };
+        let commit = Object::new_commit(merged.tree, parents.clone(), author.clone(), Signature::committer(&ctx.repo.git_dir)?, message.clone());
+        let hash = transaction.add(commit)?;
+        transaction.commit()?;
+        tip = Tip { hash, tree: merged.tree, parents, author, message };
+    }
+    Ok(tip)
+}
+
+/// Opens `message` in the editor for a reword. `#` lines are dropped and
+/// an empty result aborts the rebase.
+fn edit_message(state_dir: &Path, message: &str, editor: &str) -> io::Result<String> {
+    let path = state_dir.join(MESSAGE_FILE);
+    fs::write(&path, format!("{}\n\n{}", message, comment_lines(&messages::commit_message_help())))?;
+    edit_file(editor, &path)?;
+    let edited = cleanup_message(&fs::read_to_string(&path)?, true);
+    if edited.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, messages::empty_commit_message()));
+    }
+    Ok(edited)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::core::tree::flatten_tree;
+    use std::path::PathBuf;
+    use crate::cobra::test_support::{context, temp_repo};
+    use tempfile::TempDir;
+
+    fn setup() -> io::Result<(TempDir, CommandContext, Vec<Oid>)> {
+        let (temp_dir, repo) = temp_repo()?;
+        let mut ctx = context(repo);
+        let mut commits = Vec::new();
+        for (name, content, message) in [
+            ("base.txt", "base\n", "Base"),
+            ("one.txt", "one\n", "One"),
+            ("two.txt", "two\n", "Two"),
+            ("three.txt", "three\n", "Three"),
+            ("four.txt", "four\n", "Four"),
+        ] {
+            fs::write(temp_dir.path().join(name), content)?;
+            add_file(&mut ctx.repo, Path::new(name))?;
+            commits.push(commit(&ctx.repo, message)?);
+        }
+        Ok((temp_dir, ctx, commits))
+    }
+
+    /// An editor that replaces the todo list with `todo` and any commit
+    /// message with "Reworded", keeping a copy of the list it was given in
+    /// `temp_dir`, outside the work tree
+    fn scripted_editor(temp_dir: &TempDir, todo: &str) -> io::Result<String> {
+        let todo_path = temp_dir.path().join("todo.in");
+        fs::write(&todo_path, todo)?;
+        let script = temp_dir.path().join("editor.sh");
+        fs::write(&script, format!(
+            "case \"$1\" in\n*git-rebase-todo) cp \"$1\" {0}/todo.orig; cat {1} > \"$1\" ;;\n*) printf 'Reworded\\n' > \"$1\" ;;\nesac\n",
+            temp_dir.path().display(),
+            todo_path.display(),
+        ))?;
+        Ok(format!("sh {}", script.display()))
+    }
+
+    /// Messages along the first-parent history of HEAD, newest first
+    fn history(ctx: &CommandContext) -> io::Result<Vec<String>> {
+        let mut messages = Vec::new();
+        let mut next = Some(ctx.refs.resolve_commit("HEAD")?);
+        while let Some(hash) = next {
+            let Object::Commit { parents, message, .. } = ctx.repo.read_object(hash)? else {
+                panic!("not a commit");
+            };
+            messages.push(message);
+            next = parents.first().cloned();
+        }
+        Ok(messages)
+    }
+
+    #[test]
+    fn test_interactive_rebase_follows_the_edited_todo() -> io::Result<()> {
+        let (temp_dir, mut ctx, commits) = setup()?;
+        let scripts = TempDir::new()?;
+        let short: Vec<String> = commits.iter().map(|commit| ctx.repo.short_id(*commit)).collect();
+        let todo = format!("reword {}\nsquash {}\n# a comment\n\nf {}\ndrop {}\n", short[1], short[2], short[3], short[4]);
+        let editor = scripted_editor(&scripts, &todo)?;
+
+        interactive(&mut ctx, "HEAD~4", &editor)?;
+        assert_eq!(history(&ctx)?, vec!["Reworded\n\nTwo\n".to_string(), "Base\n".to_string()]);
+        let files: Vec<_> = flatten_tree(&ctx.repo.git_dir, commit_tree(&ctx.repo.git_dir, ctx.refs.resolve_commit("HEAD")?)?)?
+            .into_keys()
+            .collect();
+        assert_eq!(files, ["base.txt", "one.txt", "three.txt", "two.txt"].map(PathBuf::from));
+        assert!(!temp_dir.path().join("four.txt").exists());
+        assert!(status::collect(&ctx.repo)?.is_clean());
+        assert!(ctx.out.captured().contains("Successfully rebased and updated refs/heads/main."));
+        assert!(!ctx.repo.git_dir.join(REBASE_MERGE_DIR).exists());
+
+        // The list offered lists every commit since the base, oldest first
+        let offered = fs::read_to_string(scripts.path().join("todo.orig"))?;
+        assert!(offered.starts_with(&format!("pick {} One\npick {} Two\npick {} Three\npick {} Four\n\n# Rebase ", short[1], short[2], short[3], short[4])), "{}", offered);
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_todo_aborts_before_rewriting() -> io::Result<()> {
+        let (_temp_dir, mut ctx, commits) = setup()?;
+        let scripts = TempDir::new()?;
+        let todo = format!("pick {}\nedit-ish {}\n", ctx.repo.short_id(commits[3]), ctx.repo.short_id(commits[4]));
+        let editor = scripted_editor(&scripts, &todo)?;
+
+        let error = interactive(&mut ctx, "HEAD~2", &editor).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("line 2: unknown command 'edit-ish'"), "{}", error);
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, commits[4]);
+        assert!(!ctx.repo.git_dir.join(REBASE_MERGE_DIR).exists());
+
+        let editor = scripted_editor(&scripts, &format!("fixup {}\n", ctx.repo.short_id(commits[3])))?;
+        let error = interactive(&mut ctx, "HEAD~2", &editor).unwrap_err();
+        assert!(error.to_string().contains("cannot 'fixup' without a previous commit"), "{}", error);
+        assert_eq!(ctx.refs.resolve_commit("HEAD")?, commits[4]);
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+// Show where a ref has been
+use std::io::{self, Write};
+use crate::cobra::core::reflog;
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+/// Prints the reflog of `name`, HEAD when None, newest entry first as
+/// `<short hash> <name>@{<n>}: <reason>`. Branches and remote-tracking
+/// branches can be named without their `refs/...` prefix.
+pub fn run(ctx: &mut CommandContext, name: Option<&str>) -> io::Result<()> {
+    let name = name.unwrap_or("HEAD");
+    let ref_name = logged_ref(ctx, name)?;
+    let entries = reflog::read(&ctx.repo.git_dir, &ref_name)?;
+    for (index, entry) in entries.iter().rev().enumerate() {
+        writeln!(ctx.out, "{} {}@{{{}}}: {}", ctx.repo.short_id(entry.new.parse()?), name, index, entry.message)?;
+    }
+    Ok(())
+}
+
+/// The full name of the ref `name` stands for: the first candidate that
+/// has a reflog or exists, the way git looks names up
+fn logged_ref(ctx: &CommandContext, name: &str) -> io::Result<String> {
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+    ];
+    for candidate in candidates.into_iter().filter(|candidate| candidate == "HEAD" || candidate.starts_with("refs/")) {
+        if !reflog::read(&ctx.repo.git_dir, &candidate)?.is_empty() || ctx.refs.read_ref(&candidate)?.is_some() {
+            return Ok(candidate);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, messages::unknown_ref(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use crate::cobra::commands::add::add_file;
+    use crate::cobra::commands::branch::{self, SwitchOptions};
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::commands::reset::{self, ResetMode, ResetOptions};
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_reflog_lists_ref_movements_newest_first() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        fs::write(temp_dir.path().join("file.txt"), "one\n")?;
+        add_file(&mut repo, Path::new("file.txt"))?;
+        let first = commit(&repo, "First")?;
+        fs::write(temp_dir.path().join("file.txt"), "two\n")?;
+        add_file(&mut repo, Path::new("file.txt"))?;
+        let second = commit(&repo, "Second")?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(true), GlobalOpts::default());
+        ctx.refs.create_branch("topic")?;
+        branch::switch(&mut ctx, "topic", &SwitchOptions::default())?;
+        reset::run(&mut ctx, Some(&first.to_string()), &ResetOptions { mode: ResetMode::Hard })?;
+
+        let (first, second) = (ctx.repo.short_id(first), ctx.repo.short_id(second));
+        run(&mut ctx, None)?;
+        run(&mut ctx, Some("main"))?;
+        run(&mut ctx, Some("refs/heads/topic"))?;
+        assert_eq!(ctx.out.captured(), format!("\
+{first} HEAD@{{0}}: reset: moving to {first_full}
+{second} HEAD@{{1}}: checkout: moving from main to topic
+{second} HEAD@{{2}}: commit: Second
+{first} HEAD@{{3}}: commit (initial): First
+{second} main@{{0}}: commit: Second
+{first} main@{{1}}: commit (initial): First
+{first} refs/heads/topic@{{0}}: reset: moving to {first_full}
+{second} refs/heads/topic@{{1}}: branch: Created from HEAD
+",
+            first_full = ctx.refs.resolve_commit("topic")?,
+        ));
+
+        let error = run(&mut ctx, Some("missing")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+// Summarize changes for a pull request message
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use crate::cobra::core::{
+    ancestry::{commits_between, is_ancestor},
+    diffstat::{diffstat, write_diffstat},
+    object::Object,
+    oid::Oid,
+    repository::Repository,
+    signature::Signature,
+    tree::commit_tree,
+};
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+#[derive(Debug, Default)]
+pub struct RequestPullOptions {
+    /// Commit the changes are based on, usually the upstream branch
+    pub start: String,
+    /// Repository the changes can be fetched from
+    pub url: String,
+    /// Last commit to include, HEAD by default
+    pub end: Option<String>,
+}
+
+/// Prints a message asking to pull `start..end` from `url`: where the
+/// changes start and end, a changelog grouped by author and a diffstat.
+/// `start` must be an ancestor of `end`.
+pub fn run(ctx: &mut CommandContext, options: &RequestPullOptions) -> io::Result<()> {
+    let end_spec = options.end.as_deref().unwrap_or("HEAD");
+    let start = ctx.refs.resolve_commit(&options.start)?;
+    let end = ctx.refs.resolve_commit(end_spec)?;
+    if !is_ancestor(&ctx.repo, start, end)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not an ancestor of '{}'", options.start, end_spec),
+        ));
+    }
+
+    // Name the ref to fetch; HEAD stands for the current branch
+    let head = ctx.refs.read_head()?.unwrap_or_default();
+    let ref_name = match end_spec {
+        "HEAD" => head.strip_prefix("ref: refs/heads/").map(str::to_string),
+        spec => Some(spec.to_string()),
+    };
+
+    let (_, start_committer, start_message) = commit_summary(&ctx.repo, start)?;
+    let (_, end_committer, end_message) = commit_summary(&ctx.repo, end)?;
+    writeln!(ctx.out, "{}", messages::pull_since(&start.to_string()))?;
+    writeln!(ctx.out)?;
+    writeln!(ctx.out, "  {} ({})", subject(&start_message), start_committer.short_date())?;
+    writeln!(ctx.out)?;
+    writeln!(ctx.out, "{}", messages::pull_available())?;
+    writeln!(ctx.out)?;
+    match ref_name {
+        Some(ref_name) => writeln!(ctx.out, "  {} {}", options.url, ref_name)?,
+        None => writeln!(ctx.out, "  {}", options.url)?,
+    }
+    writeln!(ctx.out)?;
+    writeln!(ctx.out, "{}", messages::pull_up_to(&end.to_string()))?;
+    writeln!(ctx.out)?;
+    writeln!(ctx.out, "  {} ({})", subject(&end_message), end_committer.short_date())?;
+    writeln!(ctx.out)?;
+    writeln!(ctx.out, "{}", "-".repeat(64))?;
+
+    write_shortlog(ctx, start, end)?;
+    let old_tree = commit_tree(&ctx.repo.git_dir, start)?;
+    let new_tree = commit_tree(&ctx.repo.git_dir, end)?;
+    let stats = diffstat(&ctx.repo, Some(old_tree), Some(new_tree))?;
+    write_diffstat(&mut ctx.out, &stats)
+}
+
+fn commit_summary(repo: &Repository, hash: Oid) -> io::Result<(Signature, Signature, String)> {
+    match Object::read_from_objects_dir(&repo.git_dir, hash)? {
+        Object::Commit { author, committer, message, .. } => Ok((author, committer, message)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            messages::not_a_commit(&hash.to_string()),
+        )),
+    }
+}
+
+fn subject(message: &str) -> &str {
+    message.lines().next().unwrap_or("")
+}
+
+/// Lists commit subjects under their author's name, authors alphabetically
+/// and each author's commits oldest first
+fn write_shortlog(ctx: &mut CommandContext, start: Oid, end: Oid) -> io::Result<()> {
+    let mut by_author: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for hash in commits_between(&ctx.repo, start, end)? {
+        let (author, _, message) = commit_summary(&ctx.repo, hash)?;
+        by_author.entry(author.name).or_default().push(subject(&message).to_string());
+    }
+
+    for (author, subjects) in by_author {
+        writeln!(ctx.out, "{} ({}):", author, subjects.len())?;
+        for subject in subjects {
+            writeln!(ctx.out, "      {}", subject)?;
+        }
+        writeln!(ctx.out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_request_pull_summary() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+
+        fs::write(root.join("README"), "hello\n")?;
+        add_file(&mut repo, Path::new("README"))?;
+        let start = commit(&repo, "Initial commit")?;
+        fs::write(root.join("README"), "hello\nworld\n")?;
+        fs::write(root.join("lib.rs"), "fn main() {}\n")?;
+        add_file(&mut repo, Path::new("README"))?;
+        add_file(&mut repo, Path::new("lib.rs"))?;
+        commit(&repo, "Add lib")?;
+        fs::write(root.join("README"), "hi\nworld\n")?;
+        add_file(&mut repo, Path::new("README"))?;
+        let end = commit(&repo, "Reword greeting")?;
+
+        let (author, committer, _) = commit_summary(&repo, end)?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let options = RequestPullOptions { start: start.to_string(), url: "https://example.com/repo".to_string(), end: None };
+        run(&mut ctx, &options)?;
+
+        let output = ctx.out.captured();
+        assert!(output.starts_with(&format!("The following changes since commit {}:\n\n  Initial commit (", start)));
+        assert!(output.contains("  https://example.com/repo main\n"));
+        assert!(output.contains(&format!("up to {}:\n\n  Reword greeting ({})\n", end, committer.short_date())));
+        assert!(output.ends_with(&format!("{}\n\
+{} (2):
+      Add lib
+      Reword greeting
+
+ README | 3 ++-
+ lib.rs | 1 +
+ 2 files changed, 3 insertions(+), 1 deletion(-)
+", "-".repeat(64), author.name)));
+
+        // The range has to make sense
+        let backwards = RequestPullOptions { start: end.to_string(), url: "x".to_string(), end: Some(start.to_string()) };
+        assert!(run(&mut ctx, &backwards).is_err());
+        Ok(())
+    }
+}
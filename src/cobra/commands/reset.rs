@@ -0,0 +1,168 @@
+// Move the current branch to another commit
+use std::io;
+use crate::cobra::core::{
+    index::{Index, IndexEntry},
+    object::Object,
+    revision::resolve_revision,
+    tree::{commit_tree, flatten_tree},
+    workspace::{ApplyOptions, WorkspaceState},
+};
+use crate::cobra::state::CommandContext;
+use crate::cobra::utils::progress::NoProgress;
+use crate::cobra::messages;
+
+/// Where a reset leaves the previous HEAD commit, so it can be undone
+pub const ORIG_HEAD: &str = "ORIG_HEAD";
+
+/// How much besides the branch a reset rewrites
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ResetMode {
+    /// Only the branch ref (--soft)
+    Soft,
+    /// The branch and the index (--mixed)
+    #[default]
+    Mixed,
+    /// The branch, the index and the tracked work tree files (--hard)
+    Hard,
+}
+
+#[derive(Debug, Default)]
+pub struct ResetOptions {
+    pub mode: ResetMode,
+}
+
+/// Moves the checked out branch, or a detached HEAD, to `target` (HEAD
+/// when None), saving the old commit in ORIG_HEAD. The target and its
+/// tree are read before anything changes, so a commit missing from the
+/// object store leaves every ref as it was. Untracked files survive even
+/// --hard.
+pub fn run(ctx: &mut CommandContext, target: Option<&str>, options: &ResetOptions) -> io::Result<()> {
+    let commit = resolve_revision(&ctx.refs, target.unwrap_or("HEAD"))?;
+    let tree_hash = commit_tree(&ctx.repo.git_dir, commit)?;
+    let tree = flatten_tree(&ctx.repo.git_dir, tree_hash)?;
+    let workspace = match options.mode {
+        ResetMode::Hard => Some(WorkspaceState::from_tree(&ctx.repo, tree_hash)?),
+        _ => None,
+    };
+
+    let repo = &mut ctx.repo;
+    let old = match repo.ref_store().resolve_commit("HEAD") {
+        Ok(old) => Some(old),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    if let Some(old) = old {
+        repo.ref_store().update_ref(ORIG_HEAD, old, "reset")?;
+    }
+    let mut transaction = repo.transaction();
+    transaction.update_head(old, commit, &format!("reset: moving to {}", target.unwrap_or("HEAD")))?;
+    transaction.commit()?;
+
+    if let Some(workspace) = workspace {
+        let options = ApplyOptions { keep_untracked: true, ..Default::default() };
+        workspace.apply_to_workspace(repo, &options, &mut NoProgress)?;
+    }
+    if options.mode != ResetMode::Soft {
+        let mut index = Index::new();
+        for (path, entry) in &tree {
+            index.add_entry(IndexEntry::from_tree_blob(repo, path, entry.mode, entry.hash)?);
+        }
+        repo.index = index;
+        repo.save_index()?;
+    }
+
+    let summary = match repo.read_object(commit)? {
+        Object::Commit { message, .. } => message.lines().next().unwrap_or("").to_string(),
+        _ => String::new(),
+    };
+    ctx.out.note(format_args!("{}", messages::head_now_at(&repo.short_id(commit), &summary)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use crate::cobra::commands::add::add_file;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::commands::status::collect;
+    use crate::cobra::core::oid::Oid;
+    use crate::cobra::test_support::{context, temp_repo};
+    use tempfile::TempDir;
+
+    /// Three commits, each rewriting a.txt; the third also adds b.txt
+    fn setup() -> io::Result<(TempDir, CommandContext, Vec<Oid>)> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let mut commits = Vec::new();
+        for number in 1..=3 {
+            fs::write(temp_dir.path().join("a.txt"), "version\n".repeat(number))?;
+            add_file(&mut repo, Path::new("a.txt"))?;
+            if number == 3 {
+                fs::write(temp_dir.path().join("b.txt"), "added last\n")?;
+                add_file(&mut repo, Path::new("b.txt"))?;
+            }
+            commits.push(commit(&repo, &format!("Commit {}", number))?);
+        }
+        Ok((temp_dir, context(repo), commits))
+    }
+
+    fn staged_hash(ctx: &CommandContext, path: &Path) -> Option<Oid> {
+        ctx.repo.index.get_entry(path).map(|entry| entry.hash)
+    }
+
+    fn head(ctx: &CommandContext) -> io::Result<Oid> {
+        ctx.repo.ref_store().resolve_commit("HEAD")
+    }
+
+    #[test]
+    fn test_reset_modes() -> io::Result<()> {
+        let (temp_dir, mut ctx, commits) = setup()?;
+        let third_a = staged_hash(&ctx, Path::new("a.txt"));
+
+        // --soft only moves the branch: the third commit's changes are staged
+        run(&mut ctx, Some("HEAD~1"), &ResetOptions { mode: ResetMode::Soft })?;
+        assert_eq!(head(&ctx)?, commits[1]);
+        assert_eq!(ctx.repo.ref_store().read_ref(ORIG_HEAD)?, Some(commits[2]));
+        assert_eq!(staged_hash(&ctx, Path::new("a.txt")), third_a);
+        let report = collect(&ctx.repo)?;
+        assert!(report.entries.iter().all(|entry| entry.unstaged_code() == '.'), "{:?}", report.entries);
+        assert_eq!(report.entries.len(), 2);
+
+        // --mixed also resets the index, leaving the changes in the work tree
+        run(&mut ctx, Some(&commits[0].to_string()), &ResetOptions::default())?;
+        assert_eq!(head(&ctx)?, commits[0]);
+        assert_eq!(ctx.repo.ref_store().read_ref(ORIG_HEAD)?, Some(commits[1]));
+        let report = collect(&ctx.repo)?;
+        assert_eq!(report.untracked, vec![Path::new("b.txt").to_path_buf()]);
+        assert_eq!(report.modified().collect::<Vec<_>>(), vec![Path::new("a.txt")]);
+        assert!(report.entries.iter().all(|entry| entry.staged_code() == '.'), "{:?}", report.entries);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.txt"))?, "version\n".repeat(3));
+        assert!(ctx.out.captured().ends_with("Commit 1\n"), "{}", ctx.out.captured());
+
+        // --hard rewrites tracked files; ORIG_HEAD undoes the reset
+        fs::write(temp_dir.path().join("untracked.txt"), "keep me\n")?;
+        run(&mut ctx, Some(ORIG_HEAD), &ResetOptions { mode: ResetMode::Hard })?;
+        assert_eq!(head(&ctx)?, commits[1]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.txt"))?, "version\n".repeat(2));
+        fs::remove_file(temp_dir.path().join("b.txt"))?;
+        run(&mut ctx, Some(&commits[2].to_string()), &ResetOptions { mode: ResetMode::Hard })?;
+        assert_eq!(fs::read_to_string(temp_dir.path().join("b.txt"))?, "added last\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("untracked.txt"))?, "keep me\n");
+        let report = collect(&ctx.repo)?;
+        assert_eq!(report.untracked, vec![Path::new("untracked.txt").to_path_buf()]);
+        assert!(report.entries.is_empty(), "{:?}", report.entries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_to_missing_commit_changes_nothing() -> io::Result<()> {
+        let (temp_dir, mut ctx, commits) = setup()?;
+        let missing = "0123456789abcdef0123456789abcdef01234567";
+        let error = run(&mut ctx, Some(missing), &ResetOptions { mode: ResetMode::Hard }).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(head(&ctx)?, commits[2]);
+        assert_eq!(ctx.repo.ref_store().read_ref(ORIG_HEAD)?, None);
+        assert!(temp_dir.path().join("b.txt").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+// Restore work tree files from the index, or index entries from HEAD or
+// another commit
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::cobra::commands::add::resolve_path;
+use crate::cobra::core::{
+    checkout::restore_file_from_index,
+    index::IndexEntry,
+    revision,
+    tree::{commit_tree, flatten_tree},
+};
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// Reset the index entries to HEAD or the source commit (--staged)
+    pub staged: bool,
+    /// Write the files in the work tree; the default unless --staged is
+    /// given (--worktree)
+    pub worktree: bool,
+    /// Take the files from this commit rather than from the index, or from
+    /// HEAD with --staged (--source). `checkout <commit> -- <path>` passes
+    /// it with both --staged and --worktree.
+    pub source: Option<String>,
+}
+
+/// Restores the files at or under each path. With --staged the index
+/// entries are reset to HEAD first, dropping those HEAD does not have; the
+/// work tree is then rewritten from the index if asked to, which with
+/// both flags makes the files match HEAD. Every path must be known to the
+/// index, or with --staged to HEAD, before anything changes.
+///
+/// With a source commit, the files under each path are set to that
+/// commit's, in the work tree, the index or both as the flags say, and the
+/// path must exist there. Tracked files the commit does not have are left
+/// alone.
+pub fn run(ctx: &mut CommandContext, paths: &[String], options: &RestoreOptions) -> io::Result<()> {
+    let staged = options.staged;
+    let worktree = options.worktree || !options.staged;
+    let repo = &mut ctx.repo;
+    let source_commit = match &options.source {
+        Some(spec) => Some(revision::resolve_revision(&repo.ref_store(), spec)?),
+        None => match repo.ref_store().resolve_commit("HEAD") {
+            Ok(commit) => Some(commit),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        },
+    };
+    let source_tree = match &source_commit {
+        Some(commit) if staged || options.source.is_some() => flatten_tree(&repo.git_dir, commit_tree(&repo.git_dir, *commit)?)?,
+        _ => Default::default(),
+    };
+
+    let mut targets = Vec::new();
+    for path in paths {
+        let resolved = resolve_path(repo, Path::new(path))?;
+        let in_index = repo.index.entries().any(|entry| entry.path.starts_with(&resolved));
+        let in_source = source_tree.keys().any(|source_path| source_path.starts_with(&resolved));
+        if let Some(spec) = options.source.as_deref().filter(|_| !in_source) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                messages::path_not_in_commit(path, spec),
+            ));
+        }
+        if !in_index && !in_source {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                messages::pathspec_no_match(path),
+            ));
+        }
+        targets.push(resolved);
+    }
+    let selected = |path: &Path| {
+        targets.iter().any(|target| path.starts_with(target))
+            && (options.source.is_none() || source_tree.contains_key(path))
+    };
+
+    let mut unstaged = Vec::new();
+    if staged {
+        let dropped: Vec<PathBuf> = repo.index.entries()
+            .filter(|entry| selected(&entry.path) && !source_tree.contains_key(&entry.path))
+            .map(|entry| entry.path.clone())
+            .collect();
+        for path in dropped {
+            repo.index.remove_entry(&path);
+            unstaged.push(path);
+        }
+        for (path, source) in source_tree.iter().filter(|(path, _)| selected(path)) {
+            if repo.index.get_entry(path).is_some_and(|entry| entry.hash == source.hash) {
+                continue;
+            }
+            repo.index.add_entry(IndexEntry::from_tree_blob(repo, path, source.mode, source.hash)?);
+        }
+    }
+
+    if worktree && options.source.is_some() && !staged {
+        // Straight from the commit; the index keeps what it has
+        for (path, source) in source_tree.iter().filter(|(path, _)| selected(path)) {
+            let entry = IndexEntry::from_tree_blob(repo, path, source.mode, source.hash)?;
+            restore_file_from_index(repo, &entry)?;
+        }
+    } else if worktree {
+        let restored: Vec<IndexEntry> = repo.index.entries()
+            .filter(|entry| selected(&entry.path))
+            .cloned()
+            .collect();
+        for entry in restored {
+            let refreshed = restore_file_from_index(repo, &entry)?;
+            repo.index.add_entry(refreshed);
+        }
+        // HEAD does not have these files, and neither does the index now
+        for path in &unstaged {
+            match fs::remove_file(repo.root_path.join(path)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+    }
+    repo.save_index()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::add::add_file;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::commands::status::collect;
+    use crate::cobra::test_support::repo_with_files;
+    use tempfile::TempDir;
+
+    fn setup() -> io::Result<(TempDir, CommandContext)> {
+        repo_with_files(&[("a.txt", "committed\n"), ("b.txt", "committed\n"), ("dir/c.txt", "committed\n")])
+    }
+
+    fn paths(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_restore_worktree_from_index() -> io::Result<()> {
+        let (temp_dir, mut ctx) = setup()?;
+        fs::write(temp_dir.path().join("a.txt"), "staged edit\n")?;
+        add_file(&mut ctx.repo, Path::new("a.txt"))?;
+        fs::write(temp_dir.path().join("a.txt"), "unstaged edit, longer\n")?;
+        fs::remove_file(temp_dir.path().join("dir/c.txt"))?;
+
+        run(&mut ctx, &paths(&["a.txt", "dir"]), &RestoreOptions::default())?;
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.txt"))?, "staged edit\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("dir/c.txt"))?, "committed\n");
+        // Only the staged change is left
+        let report = collect(&ctx.repo)?;
+        assert_eq!(report.entries.len(), 1);
+        assert_eq!((report.entries[0].staged_code(), report.entries[0].unstaged_code()), ('M', '.'));
+
+        let error = run(&mut ctx, &paths(&["nowhere.txt"]), &RestoreOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains("did not match any file known to cobra"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_staged_and_both() -> io::Result<()> {
+        let (temp_dir, mut ctx) = setup()?;
+        fs::write(temp_dir.path().join("a.txt"), "staged edit\n")?;
+        fs::write(temp_dir.path().join("new.txt"), "new\n")?;
+        add_file(&mut ctx.repo, Path::new("a.txt"))?;
+        add_file(&mut ctx.repo, Path::new("new.txt"))?;
+        ctx.repo.index.remove_entry(Path::new("b.txt"));
+        ctx.repo.save_index()?;
+
+        // Unstaging keeps the work tree as it is
+        let staged = RestoreOptions { staged: true, ..Default::default() };
+        run(&mut ctx, &paths(&["a.txt", "b.txt", "new.txt"]), &staged)?;
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.txt"))?, "staged edit\n");
+        assert!(!ctx.repo.index.contains(Path::new("new.txt")));
+        assert!(ctx.repo.index.contains(Path::new("b.txt")));
+        let report = collect(&ctx.repo)?;
+        assert_eq!(report.untracked, vec![PathBuf::from("new.txt")]);
+        assert_eq!(report.modified().collect::<Vec<_>>(), vec![Path::new("a.txt")]);
+        assert!(report.entries.iter().all(|entry| entry.staged_code() == '.'), "{:?}", report.entries);
+
+        // Both flags make the files match HEAD again
+        add_file(&mut ctx.repo, Path::new("a.txt"))?;
+        add_file(&mut ctx.repo, Path::new("new.txt"))?;
+        let both = RestoreOptions { staged: true, worktree: true, ..Default::default() };
+        run(&mut ctx, &paths(&["."]), &both)?;
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.txt"))?, "committed\n");
+        assert!(!temp_dir.path().join("new.txt").exists());
+        assert!(collect(&ctx.repo)?.is_clean());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_from_commit() -> io::Result<()> {
+        let (temp_dir, mut ctx) = setup()?;
+        let root = temp_dir.path();
+        let first = ctx.repo.ref_store().resolve_commit("HEAD")?;
+        for name in ["a.txt", "dir/c.txt"] {
+            fs::write(root.join(name), "second\n")?;
+            add_file(&mut ctx.repo, Path::new(name))?;
+        }
+        fs::write(root.join("dir/d.txt"), "new\n")?;
+        add_file(&mut ctx.repo, Path::new("dir/d.txt"))?;
+        let second = commit(&ctx.repo, "Second commit")?;
+        fs::write(root.join("dir/d.txt"), "local edit\n")?;
+
+        // A file and a directory; files the commit lacks stay as they are,
+        // and only the work tree changes
+        let from_first = RestoreOptions { source: Some(first.abbrev(8)), ..Default::default() };
+        run(&mut ctx, &paths(&["a.txt", "dir"]), &from_first)?;
+        assert_eq!(fs::read_to_string(root.join("a.txt"))?, "committed\n");
+        assert_eq!(fs::read_to_string(root.join("dir/c.txt"))?, "committed\n");
+        assert_eq!(fs::read_to_string(root.join("dir/d.txt"))?, "local edit\n");
+        assert_eq!(ctx.repo.ref_store().resolve_commit("HEAD")?, second);
+        let codes = |ctx: &CommandContext| -> io::Result<Vec<(String, char, char)>> {
+            Ok(collect(&ctx.repo)?.entries.iter()
+                .map(|entry| (entry.path.display().to_string(), entry.staged_code(), entry.unstaged_code()))
+                .collect())
+        };
+        assert_eq!(codes(&ctx)?, vec![
+            ("a.txt".to_string(), '.', 'M'),
+            ("dir/c.txt".to_string(), '.', 'M'),
+            ("dir/d.txt".to_string(), '.', 'M'),
+        ]);
+
+        // With --staged only the index changes; both, as checkout does, update both
+        let staged = RestoreOptions { staged: true, ..from_first.clone() };
+        run(&mut ctx, &paths(&["dir/c.txt"]), &staged)?;
+        let both = RestoreOptions { staged: true, worktree: true, ..from_first.clone() };
+        run(&mut ctx, &paths(&["a.txt"]), &both)?;
+        assert_eq!(codes(&ctx)?, vec![
+            ("a.txt".to_string(), 'M', '.'),
+            ("dir/c.txt".to_string(), 'M', '.'),
+            ("dir/d.txt".to_string(), '.', 'M'),
+        ]);
+
+        let error = run(&mut ctx, &paths(&["dir/d.txt"]), &from_first).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(error.to_string(), format!("path 'dir/d.txt' does not exist in '{}'", first.abbrev(8)));
+        Ok(())
+    }
+}
@@ -0,0 +1,10 @@
+// Print the object name a revision resolves to
+use std::io::{self, Write};
+use crate::cobra::core::revparse;
+use crate::cobra::state::CommandContext;
+
+/// Prints the full object name `spec` resolves to
+pub fn run(ctx: &mut CommandContext, spec: &str) -> io::Result<()> {
+    let hash = revparse::resolve_in(&ctx.refs, spec)?;
+    writeln!(ctx.out, "{}", hash)
+}
@@ -0,0 +1,195 @@
+// Remove files from the index and the work tree
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::cobra::commands::add::resolve_path;
+use crate::cobra::core::{
+    attributes::Attributes,
+    merge::MergeState,
+    object::Object,
+    repository::Repository,
+    tree::{commit_tree, flatten_tree},
+};
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+#[derive(Debug, Default)]
+pub struct RmOptions {
+    /// Only untrack the files, leaving them in the work tree (--cached)
+    pub cached: bool,
+    /// Skip the checks that protect uncommitted content (-f/--force)
+    pub force: bool,
+}
+
+/// Untracks every path and, without --cached, deletes its file. Nothing is
+/// removed unless every path is tracked and safe to remove: like git, a
+/// file whose content would be lost needs -f.
+pub fn run(ctx: &mut CommandContext, paths: &[String], options: &RmOptions) -> io::Result<()> {
+    let mut targets = Vec::new();
+    for path in paths {
+        let resolved = resolve_path(&ctx.repo, Path::new(path))?;
+        let tracked = ctx.repo.index.tracked_path(&resolved, &ctx.repo.folding).ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("pathspec '{}' did not match any tracked file", path),
+        ))?;
+        targets.push(tracked.to_path_buf());
+    }
+
+    if !options.force {
+        let problems = unsafe_removals(&ctx.repo, &targets, options.cached)?;
+        if !problems.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Refusing to remove:\n  {}\nUse -f to remove them anyway", problems.join("\n  ")),
+            ));
+        }
+    }
+
+    for path in &targets {
+        ctx.repo.index.remove_entry(path);
+    }
+    ctx.repo.save_index()?;
+    MergeState::mark_resolved(&ctx.repo, &targets)?;
+
+    for path in &targets {
+        if !options.cached {
+            remove_from_work_tree(&ctx.repo, path)?;
+        }
+        ctx.out.note(format_args!("{}", messages::removed_from_index(path.display())))?;
+    }
+    Ok(())
+}
+
+/// Describes each target whose removal would lose content that is not
+/// committed: local modifications when the file is deleted, staged changes
+/// when the file is deleted too, and with --cached staged content found
+/// neither in HEAD nor in the file
+fn unsafe_removals(repo: &Repository, targets: &[PathBuf], cached: bool) -> io::Result<Vec<String>> {
+    let head_tree = match repo.ref_store().resolve_commit("HEAD") {
+        Ok(commit) => flatten_tree(&repo.git_dir, commit_tree(&repo.git_dir, commit)?)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Default::default(),
+        Err(e) => return Err(e),
+    };
+    let attributes = Attributes::load(repo)?;
+
+    let mut problems = Vec::new();
+    for path in targets {
+        let Some(entry) = repo.index.get_entry(path) else {
+            continue;
+        };
+        let staged = head_tree.get(path).is_none_or(|head| head.hash != entry.hash);
+        let modified = match fs::read(repo.root_path.join(path)) {
+            Ok(content) => Object::new_blob(attributes.to_index(path, content)).hash() != entry.hash,
+            // A deleted file has nothing left to lose
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        let problem = if cached {
+            (staged && modified).then_some("has staged content different from both the file and HEAD")
+        } else if modified {
+            Some("has local modifications")
+        } else if staged {
+            Some("has changes staged in the index")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            problems.push(format!("{}: {}", path.display(), problem));
+        }
+    }
+    Ok(problems)
+}
+
+/// Deletes the file and then any directories it leaves empty
+fn remove_from_work_tree(repo: &Repository, path: &Path) -> io::Result<()> {
+    match fs::remove_file(repo.root_path.join(path)) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let mut dir = path.parent();
+    while let Some(parent) = dir.filter(|parent| !parent.as_os_str().is_empty()) {
+        if fs::remove_dir(repo.root_path.join(parent)).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::add::add_file;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::commands::status::collect;
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    fn paths(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_rm_untracks_and_deletes() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        fs::create_dir(temp_dir.path().join("docs"))?;
+        for name in ["a.txt", "b.txt", "docs/c.txt"] {
+            fs::write(temp_dir.path().join(name), format!("{}\n", name))?;
+            add_file(&mut repo, Path::new(name))?;
+        }
+        commit(&repo, "Initial commit")?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        run(&mut ctx, &paths(&["a.txt", "docs/c.txt"]), &RmOptions::default())?;
+        assert!(!temp_dir.path().join("a.txt").exists());
+        assert!(!temp_dir.path().join("docs").exists(), "an emptied directory goes too");
+        assert_eq!(ctx.out.captured(), "rm 'a.txt'\nrm 'docs/c.txt'\n");
+
+        // --cached keeps the file, which then shows up as untracked
+        run(&mut ctx, &paths(&["b.txt"]), &RmOptions { cached: true, ..Default::default() })?;
+        assert!(temp_dir.path().join("b.txt").exists());
+        let report = collect(&ctx.repo)?;
+        assert_eq!(report.untracked, vec![PathBuf::from("b.txt")]);
+        assert_eq!(report.entries.len(), 3, "{:?}", report.entries);
+        assert!(ctx.repo.index.entries().next().is_none());
+
+        let error = run(&mut ctx, &paths(&["missing.txt"]), &RmOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains("did not match any tracked file"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rm_refuses_to_lose_changes_without_force() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        for name in ["clean.txt", "edited.txt"] {
+            fs::write(temp_dir.path().join(name), "committed\n")?;
+            add_file(&mut repo, Path::new(name))?;
+        }
+        commit(&repo, "Initial commit")?;
+        fs::write(temp_dir.path().join("edited.txt"), "not committed anywhere\n")?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        // Nothing is removed when one of the paths is unsafe
+        let error = run(&mut ctx, &paths(&["clean.txt", "edited.txt"]), &RmOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("edited.txt: has local modifications"), "{}", error);
+        assert!(ctx.repo.index.contains(Path::new("clean.txt")));
+        assert!(temp_dir.path().join("clean.txt").exists());
+
+        // The file keeps the only copy of the change, so --cached is fine
+        run(&mut ctx, &paths(&["edited.txt"]), &RmOptions { cached: true, ..Default::default() })?;
+        assert!(temp_dir.path().join("edited.txt").exists());
+
+        // Staged changes would be lost along with the file
+        fs::write(temp_dir.path().join("clean.txt"), "staged\n")?;
+        add_file(&mut ctx.repo, Path::new("clean.txt"))?;
+        let error = run(&mut ctx, &paths(&["clean.txt"]), &RmOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("has changes staged in the index"), "{}", error);
+
+        run(&mut ctx, &paths(&["clean.txt"]), &RmOptions { force: true, ..Default::default() })?;
+        assert!(!temp_dir.path().join("clean.txt").exists());
+        assert!(ctx.repo.index.entries().next().is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+// Show a single object: a commit with its patch, or a tree or blob as is
+use std::io::{self, Write};
+use crate::cobra::commands::{
+    diff::{diff_tree_changes, DiffOptions},
+    log::{write_changes, write_commit, ChangeFormat},
+};
+use crate::cobra::core::{
+    object::Object,
+    revision::resolve_object,
+    tree::{commit_tree, diff_trees},
+};
+use crate::cobra::state::CommandContext;
+
+#[derive(Debug, Default)]
+pub struct ShowOptions {
+    /// List the files a commit changed instead of its patch
+    /// (--name-status, --raw)
+    pub changes: Option<ChangeFormat>,
+}
+
+/// Shows `rev`, HEAD when None. A commit is printed as in `log`, followed
+/// by its diff against its first parent (the empty tree for a root commit).
+pub fn run(ctx: &mut CommandContext, rev: Option<&str>, options: &ShowOptions) -> io::Result<()> {
+    let spec = rev.unwrap_or("HEAD");
+    let (hash, object) = resolve_object(&ctx.refs, spec)?;
+    let repo = &ctx.repo;
+    let out = &mut ctx.out;
+
+    match &object {
+        Object::Commit { tree, parents, .. } => {
+            write_commit(out, hash, &object)?;
+            let parent_tree = match parents.first() {
+                Some(parent) => Some(commit_tree(&repo.git_dir, *parent)?),
+                None => None,
+            };
+            let changes = diff_trees(&repo.git_dir, parent_tree, Some(*tree))?;
+            if let Some(format) = options.changes {
+                return write_changes(repo, out, &changes, format);
+            }
+            let options = DiffOptions { algorithm: Some(repo.diff_algorithm()?), ..DiffOptions::default() };
+            diff_tree_changes(repo, changes, &options, out)
+        }
+        Object::Tree(entries) => {
+            writeln!(out, "tree {}", spec)?;
+            writeln!(out)?;
+            for entry in entries {
+                let slash = if entry.mode == 0o40000 { "/" } else { "" };
+                writeln!(out, "{}{}", entry.name, slash)?;
+            }
+            Ok(())
+        }
+        Object::Blob(content) => out.write_all(content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::init_repo;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_show_commit_against_parent_and_root() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let mut repo = init_repo(root)?;
+        fs::write(root.join("a.txt"), "one\n")?;
+        add_file(&mut repo, Path::new("a.txt"))?;
+        let first = commit(&repo, "First")?;
+        fs::write(root.join("a.txt"), "one\ntwo\n")?;
+        add_file(&mut repo, Path::new("a.txt"))?;
+        let second = commit(&repo, "Second\n\nWith a body")?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        run(&mut ctx, None, &ShowOptions::default())?;
+        let head = ctx.out.captured();
+        assert!(head.starts_with(&format!("commit {}\nAuthor: ", second)));
+        assert!(head.contains("\n    Second\n    \n    With a body\n\ndiff --git a/a.txt b/a.txt\n"));
+        assert!(head.ends_with("--- a/a.txt\n+++ b/a.txt\n@@ -1,1 +1,2 @@\n one\n+two\n"));
+
+        // The root commit adds everything
+        ctx.out = OutputHandle::buffer(false);
+        run(&mut ctx, Some(&first.to_string()), &ShowOptions::default())?;
+        assert!(ctx.out.captured().contains("new file mode 100644\n"));
+        assert!(ctx.out.captured().ends_with("--- /dev/null\n+++ b/a.txt\n@@ -0,0 +1,1 @@\n+one\n"));
+
+        ctx.out = OutputHandle::buffer(false);
+        run(&mut ctx, Some("HEAD~1"), &ShowOptions::default())?;
+        assert!(ctx.out.captured().starts_with(&format!("commit {}\n", first)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_name_status_and_raw() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let mut repo = init_repo(root)?;
+        fs::write(root.join("a.txt"), "one\n")?;
+        fs::write(root.join("old.txt"), "old\n")?;
+        add_file(&mut repo, Path::new("a.txt"))?;
+        add_file(&mut repo, Path::new("old.txt"))?;
+        commit(&repo, "First")?;
+
+        // Modify a.txt, delete old.txt and add new.txt in one commit
+        fs::write(root.join("a.txt"), "one\ntwo\n")?;
+        fs::write(root.join("new.txt"), "new\n")?;
+        add_file(&mut repo, Path::new("a.txt"))?;
+        add_file(&mut repo, Path::new("new.txt"))?;
+        repo.index.remove_entry(Path::new("old.txt"));
+        repo.save_index()?;
+        commit(&repo, "Second")?;
+
+        let short = |content: &str| repo.short_id(Object::new_blob(content.as_bytes().to_vec()).hash());
+        let raw = format!(
+            "    Second\n\n:100644 100644 {} {} M\ta.txt\n:000000 100644 {} {} A\tnew.txt\n:100644 000000 {} {} D\told.txt\n",
+            short("one\n"), short("one\ntwo\n"), "0".repeat(repo.abbrev), short("new\n"), short("old\n"), "0".repeat(repo.abbrev),
+        );
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        run(&mut ctx, None, &ShowOptions { changes: Some(ChangeFormat::NameStatus) })?;
+        let name_status = ctx.out.captured();
+        assert!(name_status.ends_with("    Second\n\nM\ta.txt\nA\tnew.txt\nD\told.txt\n"), "{}", name_status);
+        assert!(!name_status.contains("diff --git"));
+
+        ctx.out = OutputHandle::buffer(false);
+        run(&mut ctx, None, &ShowOptions { changes: Some(ChangeFormat::Raw) })?;
+        assert!(ctx.out.captured().ends_with(&raw), "{}", ctx.out.captured());
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_tree_and_blob() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let mut repo = init_repo(root)?;
+        fs::create_dir(root.join("src"))?;
+        fs::write(root.join("src/lib.rs"), "fn lib() {}\n")?;
+        fs::write(root.join("README"), "hello\n")?;
+        add_file(&mut repo, Path::new("src/lib.rs"))?;
+        add_file(&mut repo, Path::new("README"))?;
+        let head = commit(&repo, "Initial commit")?;
+        let tree = commit_tree(&repo.git_dir, head)?;
+        let blob = Object::new_blob(b"hello\n".to_vec()).hash();
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        run(&mut ctx, Some(&tree.to_string()), &ShowOptions::default())?;
+        assert_eq!(ctx.out.captured(), format!("tree {}\n\nREADME\nsrc/\n", tree));
+
+        ctx.out = OutputHandle::buffer(false);
+        run(&mut ctx, Some(&blob.to_string()), &ShowOptions::default())?;
+        assert_eq!(ctx.out.captured(), "hello\n");
+        Ok(())
+    }
+}
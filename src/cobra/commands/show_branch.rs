@@ -0,0 +1,275 @@
+// Compare several branches side by side
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::io::{self, Write};
+use crate::cobra::core::{
+    ancestry::{ahead_behind, ancestors},
+    object::Object,
+    oid::Oid,
+};
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages;
+
+#[derive(Debug)]
+pub struct ShowBranchOptions {
+    /// Branches to compare; all local branches when empty
+    pub branches: Vec<String>,
+    /// Print an ahead/behind table instead of the commit matrix
+    pub ahead_behind: bool,
+    /// Maximum number of commits shown in the matrix
+    pub limit: usize,
+}
+
+impl Default for ShowBranchOptions {
+    fn default() -> Self {
+        ShowBranchOptions { branches: Vec::new(), ahead_behind: false, limit: 50 }
+    }
+}
+
+struct Column {
+    name: String,
+    tip: Oid,
+    current: bool,
+    reach: HashSet<Oid>,
+}
+
+pub fn run(ctx: &mut CommandContext, options: &ShowBranchOptions) -> io::Result<()> {
+    let head = ctx.refs.read_head()?.unwrap_or_default();
+    let current = head.strip_prefix("ref: refs/heads/").unwrap_or("");
+
+    let mut branches = ctx.refs.list_branches()?;
+    branches.sort();
+    let selected: Vec<(String, Oid)> = if options.branches.is_empty() {
+        // Branches without commits have nothing to compare
+        branches.into_iter().filter_map(|(name, tip)| Some((name, tip?))).collect()
+    } else {
+        let mut selected = Vec::new();
+        for name in &options.branches {
+            match branches.iter().find(|(branch, _)| branch == name).and_then(|(_, tip)| *tip) {
+                Some(tip) => selected.push((name.clone(), tip)),
+                None => return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Branch '{}' does not exist or has no commits", name),
+                )),
+            }
+        }
+        selected
+    };
+
+    let mut columns = Vec::new();
+    for (name, tip) in selected {
+        let reach = ancestors(&ctx.repo, tip)?;
+        columns.push(Column { current: name == current, name, tip, reach });
+    }
+    if columns.is_empty() {
+        return Ok(());
+    }
+
+    if options.ahead_behind {
+        write_ahead_behind(ctx, &columns)
+    } else {
+        write_matrix(ctx, &columns, options.limit)
+    }
+}
+
+/// Commit metadata needed for display
+struct CommitInfo {
+    parents: Vec<Oid>,
+    timestamp: u64,
+    subject: String,
+}
+
+fn commit_info(ctx: &CommandContext, hash: Oid) -> io::Result<CommitInfo> {
+    match Object::read_from_objects_dir(&ctx.repo.git_dir, hash)? {
+        Object::Commit { parents, committer, message, .. } => Ok(CommitInfo {
+            parents,
+            timestamp: committer.timestamp,
+            subject: message.lines().next().unwrap_or("").to_string(),
+        }),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            messages::not_a_commit(&hash.to_string()),
+        )),
+    }
+}
+
+/// Prints how far each branch is ahead of and behind the current branch
+/// (or the first one listed if the current branch is not among them)
+fn write_ahead_behind(ctx: &mut CommandContext, columns: &[Column]) -> io::Result<()> {
+    let base = columns.iter().find(|column| column.current).unwrap_or(&columns[0]);
+    let width = columns.iter().map(|column| column.name.len()).max().unwrap_or(0);
+
+    writeln!(ctx.out, "{}", messages::compared_with(&base.name))?;
+    for column in columns.iter().filter(|column| column.name != base.name) {
+        let (ahead, behind) = ahead_behind(&ctx.repo, column.tip, base.tip)?;
+        let subject = commit_info(ctx, column.tip)?.subject;
+        writeln!(ctx.out, "  {:<width$}  ahead {}, behind {}  {}", column.name, ahead, behind, subject)?;
+    }
+    Ok(())
+}
+
+/// Prints git's show-branch layout: one header line per branch, then every
+/// commit not shared by all branches (newest first) with a mark in each
+/// column that reaches it, ending with the newest commit they all share
+fn write_matrix(ctx: &mut CommandContext, columns: &[Column], limit: usize) -> io::Result<()> {
+    let width = columns.len();
+    for (i, column) in columns.iter().enumerate() {
+        let mark = if column.current { '*' } else { '!' };
+        let subject = commit_info(ctx, column.tip)?.subject;
+        writeln!(ctx.out, "{}{}{} [{}] {}", " ".repeat(i), mark, " ".repeat(width - i - 1), column.name, subject)?;
+    }
+    writeln!(ctx.out, "{}", "-".repeat(width))?;
+
+    // Name commits after the first branch whose first-parent chain reaches them
+    let mut names: HashMap<Oid, (usize, usize)> = HashMap::new();
+    let mut infos: HashMap<Oid, CommitInfo> = HashMap::new();
+    for (i, column) in columns.iter().enumerate() {
+        let mut hash = Some(column.tip);
+        let mut depth = 0;
+        while let Some(current) = hash {
+            names.entry(current).or_insert((i, depth));
+            if let Entry::Vacant(entry) = infos.entry(current) {
+                entry.insert(commit_info(ctx, current)?);
+            }
+            hash = infos[&current].parents.first().copied();
+            depth += 1;
+        }
+    }
+
+    let common: HashSet<Oid> = columns.iter()
+        .skip(1)
+        .fold(columns[0].reach.iter().copied().collect(), |common, column| {
+            common.into_iter().filter(|hash| column.reach.contains(hash)).collect()
+        });
+    let rows: HashSet<Oid> = columns.iter()
+        .flat_map(|column| column.reach.iter().copied())
+        .filter(|hash| !common.contains(hash))
+        .collect();
+    for &hash in &rows {
+        if let Entry::Vacant(entry) = infos.entry(hash) {
+            entry.insert(commit_info(ctx, hash)?);
+        }
+    }
+
+    // Children before parents; among the commits that are ready, newest first
+    let mut pending_children: HashMap<Oid, usize> = HashMap::new();
+    for hash in &rows {
+        for parent in &infos[hash].parents {
+            if rows.contains(parent) {
+                *pending_children.entry(*parent).or_insert(0) += 1;
+            }
+        }
+    }
+    let sort_key = |hash: &Oid| {
+        let (column, depth) = names.get(hash).copied().unwrap_or((usize::MAX, usize::MAX));
+        (std::cmp::Reverse(infos[hash].timestamp), column, depth)
+    };
+    let mut ready: Vec<Oid> = rows.iter()
+        .filter(|hash| !pending_children.contains_key(*hash))
+        .copied()
+        .collect();
+    let mut shown = 0;
+    while !ready.is_empty() && shown < limit {
+        ready.sort_by_key(|hash| std::cmp::Reverse(sort_key(hash)));
+        let hash = ready.pop().unwrap();
+        write_row(ctx, columns, &names, &infos[&hash], hash)?;
+        shown += 1;
+
+        for parent in &infos[&hash].parents {
+            if let Some(count) = pending_children.get_mut(parent) {
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(*parent);
+                }
+            }
+        }
+    }
+
+    // Finish with the newest shared commit on the first branch's first-parent chain
+    let mut hash = Some(columns[0].tip);
+    while let Some(current) = hash {
+        if common.contains(&current) {
+            return write_row(ctx, columns, &names, &infos[&current], current);
+        }
+        hash = infos[&current].parents.first().copied();
+    }
+    Ok(())
+}
+
+fn write_row(
+    ctx: &mut CommandContext,
+    columns: &[Column],
+    names: &HashMap<Oid, (usize, usize)>,
+    info: &CommitInfo,
+    hash: Oid,
+) -> io::Result<()> {
+    let marks: String = columns.iter()
+        .map(|column| match column.reach.contains(&hash) {
+            false => ' ',
+            true if info.parents.len() > 1 => '-',
+            true if column.current => '*',
+            true => '+',
+        })
+        .collect();
+    let name = match names.get(&hash) {
+        Some(&(column, 0)) => columns[column].name.clone(),
+        Some(&(column, depth)) => format!("{}~{}", columns[column].name, depth),
+        None => ctx.repo.short_id(hash),
+    };
+    writeln!(ctx.out, "{} [{}] {}", marks, name, info.subject)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::{commit_at, temp_repo};
+    use tempfile::TempDir;
+
+    /// main: c1 - c2, feature: c1 - f1 - f2, topic: c2 - t1
+    fn three_branches() -> io::Result<(TempDir, CommandContext)> {
+        let (temp_dir, repo) = temp_repo()?;
+        let c1 = commit_at(&repo, &[], "c1", 1000)?;
+        let c2 = commit_at(&repo, &[c1], "c2", 2000)?;
+        let f1 = commit_at(&repo, &[c1], "f1", 3000)?;
+        let f2 = commit_at(&repo, &[f1], "f2", 4000)?;
+        let t1 = commit_at(&repo, &[c2], "t1", 5000)?;
+        let refs = repo.ref_store();
+        refs.update_ref("refs/heads/main", c2, "test")?;
+        refs.update_ref("refs/heads/feature", f2, "test")?;
+        refs.update_ref("refs/heads/topic", t1, "test")?;
+
+        let ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        Ok((temp_dir, ctx))
+    }
+
+    #[test]
+    fn test_show_branch_matrix() -> io::Result<()> {
+        let (_temp_dir, mut ctx) = three_branches()?;
+        run(&mut ctx, &ShowBranchOptions::default())?;
+        assert_eq!(ctx.out.captured(), "\
+!   [feature] f2
+ *  [main] c2
+  ! [topic] t1
+---
+  + [topic] t1
++   [feature] f2
++   [feature~1] f1
+ *+ [main] c2
++*+ [feature~2] c1
+");
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_branch_ahead_behind() -> io::Result<()> {
+        let (_temp_dir, mut ctx) = three_branches()?;
+        let options = ShowBranchOptions { ahead_behind: true, ..ShowBranchOptions::default() };
+        run(&mut ctx, &options)?;
+        assert_eq!(ctx.out.captured(), "\
+Compared with main:
+  feature  ahead 2, behind 1  f2
+  topic    ahead 1, behind 0  t1
+");
+        Ok(())
+    }
+}
@@ -1,71 +1,86 @@
 // Stash management commands
-use std::io;
-use std::fs;
-use std::os::unix::fs::PermissionsExt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::path::Path;
+use crate::cobra::core::config::Config;
+use crate::cobra::core::diff::{hunks, split_lines, write_unified, Edit, CONTEXT_LINES};
+use crate::cobra::core::diffstat::{write_diffstat, FileStat};
+use crate::cobra::core::index::normalize_mode;
+use crate::cobra::core::object::{Object, TreeEntry};
+use crate::cobra::core::oid::{HashAlgorithm, Oid};
 use crate::cobra::core::repository::Repository;
+use crate::cobra::core::tree::{commit_tree, flatten_tree};
+use crate::cobra::core::workspace::{ApplyOptions, WorkspaceState, KEEP_FILE, MTIMES_FILE};
+use crate::cobra::state::CommandContext;
+use crate::cobra::utils::progress::CounterProgress;
+use crate::cobra::messages;
 
-pub fn push(message: Option<&String>) -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
+pub fn push(ctx: &mut CommandContext, message: Option<&String>) -> io::Result<()> {
+    let repo = &ctx.repo;
+    let ref_store = &ctx.refs;
     
-    let stash_hash = ref_store.create_stash(message.map(|s| s.as_str()))?;
-    println!("Saved working directory and index state WIP on current branch: {}", &stash_hash[..7]);
+    let stash_hash = ref_store.create_stash(repo, message.map(|s| s.as_str()))?;
+    ctx.out.note(format_args!("{}", messages::stash_saved(&ctx.repo.short_id(stash_hash))))?;
     
     Ok(())
 }
 
-pub fn list() -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
+pub fn list(ctx: &mut CommandContext) -> io::Result<()> {
+    let repo = &ctx.repo;
+    let ref_store = &ctx.refs;
     
     let stashes = ref_store.list_stashes()?;
     
     if stashes.is_empty() {
-        println!("No stashes found");
+        writeln!(ctx.out, "{}", messages::no_stashes())?;
         return Ok(());
     }
     
     for (stash_ref, hash) in stashes {
         // Try to get the stash commit to show the message
         if let Ok(Some(stash_commit)) = ref_store.get_stash(&stash_ref) {
-            if let Ok(commit_obj) = crate::cobra::core::object::Object::read_from_objects_dir(&repo.git_dir, &stash_commit) {
-                if let crate::cobra::core::object::Object::Commit { message, .. } = commit_obj {
-                    println!("{}: {}", stash_ref, message.lines().next().unwrap_or(""));
-                }
+            if let Ok(Object::Commit { message, .. }) = Object::read_from_objects_dir(&repo.git_dir, stash_commit) {
+                writeln!(ctx.out, "{}: {}", stash_ref, message.lines().next().unwrap_or(""))?;
             }
         } else {
-            println!("{}: {}", stash_ref, &hash[..7]);
+            writeln!(ctx.out, "{}: {}", stash_ref, repo.short_id(hash))?;
         }
     }
     
     Ok(())
 }
 
-pub fn show(stash_ref: &str) -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
+#[derive(Debug, Default)]
+pub struct ShowOptions {
+    /// Print a diffstat instead of the patch (--stat)
+    pub stat: bool,
+}
+
+pub fn show(ctx: &mut CommandContext, stash_ref: &str, options: &ShowOptions) -> io::Result<()> {
+    let repo = &ctx.repo;
+    let ref_store = &ctx.refs;
     
     let stash_hash = ref_store.get_stash(stash_ref)?
         .ok_or_else(|| io::Error::new(
             io::ErrorKind::NotFound,
-            format!("Stash '{}' does not exist", stash_ref),
+            messages::stash_not_found(stash_ref),
         ))?;
     
     // Read and display the stash commit
-    let stash_commit = crate::cobra::core::object::Object::read_from_objects_dir(&repo.git_dir, &stash_hash)?;
+    let stash_commit = Object::read_from_objects_dir(&repo.git_dir, stash_hash)?;
     
     match stash_commit {
-        crate::cobra::core::object::Object::Commit { tree, parents, author, committer, message } => {
-            println!("commit {}", stash_hash);
-            println!("Author: {}", author.format());
-            println!("Date:   {}", committer.format());
-            println!();
-            println!("{}", message);
-            println!();
+        Object::Commit { tree, parents, author, committer, message } => {
+            writeln!(ctx.out, "commit {}", stash_hash)?;
+            writeln!(ctx.out, "Author: {}", author.format())?;
+            writeln!(ctx.out, "Date:   {}", committer.format())?;
+            writeln!(ctx.out)?;
+            writeln!(ctx.out, "{}", message.trim_end_matches('\n'))?;
+            writeln!(ctx.out)?;
             
             // Show the actual diff by comparing with parent
             if let Some(parent_hash) = parents.first() {
-                show_diff(&repo, parent_hash, &tree)?;
+                show_diff(repo, &mut ctx.out, *parent_hash, tree, options)?;
             }
         }
         _ => {
@@ -79,177 +94,189 @@ pub fn show(stash_ref: &str) -> io::Result<()> {
     Ok(())
 }
 
-pub fn apply(stash_ref: &str) -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
+/// Writes a stash back to the working directory. Modification times are
+/// restored when `options` or core.preserveMtime ask for it.
+pub fn apply(ctx: &mut CommandContext, stash_ref: &str, options: &ApplyOptions) -> io::Result<()> {
+    let repo = &ctx.repo;
+    let ref_store = &ctx.refs;
     
     let stash_hash = ref_store.get_stash(stash_ref)?
         .ok_or_else(|| io::Error::new(
             io::ErrorKind::NotFound,
-            format!("Stash '{}' does not exist", stash_ref),
+            messages::stash_not_found(stash_ref),
         ))?;
     
-    // Read the stash commit
-    let stash_commit = crate::cobra::core::object::Object::read_from_objects_dir(&repo.git_dir, &stash_hash)?;
-    
-    match stash_commit {
-        crate::cobra::core::object::Object::Commit { tree, .. } => {
-            // Read the stash tree
-            let stash_tree_obj = crate::cobra::core::object::Object::read_from_objects_dir(&repo.git_dir, &tree)?;
-            
-            match stash_tree_obj {
-                crate::cobra::core::object::Object::Tree(entries) => {
-                    // Create a workspace state from the stash tree
-                    let mut workspace_state = crate::cobra::core::workspace::WorkspaceState {
-                        files: std::collections::HashMap::new(),
-                        metadata: std::collections::HashMap::new(),
-                    };
-                    
-                    // Convert tree entries to workspace state
-                    for entry in entries {
-                        let path = std::path::PathBuf::from(&entry.name);
-                        workspace_state.files.insert(path.clone(), entry.hash);
-                        
-                        // Create basic metadata
-                        let mut metadata = fs::metadata(".")?; // Use current dir as template
-                        let mut perms = metadata.permissions();
-                        perms.set_mode(entry.mode);
-                        metadata = fs::metadata(".")?; // Re-read after permission change
-                        workspace_state.metadata.insert(path, metadata);
-                    }
-                    
-                    // Check for conflicts
-                    let conflicts = workspace_state.check_conflicts(&repo)?;
-                    if !conflicts.is_empty() {
-                        println!("Conflicts detected when applying stash:");
-                        for conflict in &conflicts {
-                            println!("  {}", conflict.display());
-                        }
-                        return Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            "Cannot apply stash due to conflicts",
-                        ));
-                    }
-                    
-                    // Apply the workspace state
-                    workspace_state.apply_to_workspace(&repo)?;
-                    println!("Applied stash '{}'", stash_ref);
-                }
-                _ => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Stash tree is not a valid tree object",
-                    ));
-                }
-            }
-        }
-        _ => {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Stash does not point to a commit",
-            ));
+    let workspace_state = WorkspaceState::from_tree(repo, commit_tree(&repo.git_dir, stash_hash)?)?;
+
+    // Check for conflicts
+    let conflicts = workspace_state.check_conflicts(repo)?;
+    if !conflicts.is_empty() {
+        writeln!(ctx.out, "{}", messages::stash_conflicts())?;
+        for conflict in &conflicts {
+            writeln!(ctx.out, "  {}", conflict.display())?;
         }
+        return Err(io::Error::other(
+            "Cannot apply stash due to conflicts",
+        ));
     }
+
+    let options = ApplyOptions {
+        preserve_mtime: options.preserve_mtime
+            || Config::load(&repo.git_dir)?.get_bool("core.preserveMtime").unwrap_or(false),
+        ..*options
+    };
+    workspace_state.apply_to_workspace(repo, &options, &mut CounterProgress::new("Applying stash"))?;
+    ctx.out.note(format_args!("{}", messages::stash_applied(stash_ref)))?;
     
     Ok(())
 }
 
-pub fn drop(stash_ref: &str) -> io::Result<()> {
-    let repo = Repository::open(".")?;
-    let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
+/// Applies a stash and drops it from the list. A stash that could not be
+/// applied is kept, and the output says so.
+pub fn pop(ctx: &mut CommandContext, stash_ref: &str, options: &ApplyOptions) -> io::Result<()> {
+    if ctx.refs.list_stashes()?.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, messages::no_stash_entries()));
+    }
+    if let Err(err) = apply(ctx, stash_ref, options) {
+        if err.kind() != io::ErrorKind::NotFound {
+            writeln!(ctx.out, "{}", messages::stash_kept())?;
+        }
+        return Err(err);
+    }
+    drop(ctx, stash_ref)
+}
+
+pub fn drop(ctx: &mut CommandContext, stash_ref: &str) -> io::Result<()> {
+    let ref_store = &ctx.refs;
     
+    // Name the commit so the stash can be recovered from fsck's dangling list
+    let hash = ref_store.get_stash(stash_ref)?;
     ref_store.drop_stash(stash_ref)?;
-    println!("Dropped stash '{}'", stash_ref);
+    let short = hash.map(|hash| ctx.repo.short_id(hash)).unwrap_or_default();
+    ctx.out.note(format_args!("{}", messages::stash_dropped(stash_ref, &short)))?;
     
     Ok(())
 }
 
-/// Shows a diff between two trees
-fn show_diff(repo: &Repository, parent_hash: &str, stash_tree: &str) -> io::Result<()> {
-    // Read parent tree
-    let parent_commit = crate::cobra::core::object::Object::read_from_objects_dir(&repo.git_dir, parent_hash)?;
-    let parent_tree = match parent_commit {
-        crate::cobra::core::object::Object::Commit { tree, .. } => tree,
+/// The files of a tree by path with their mode and hash, leaving out the
+/// entries that only hold stash metadata. Subtrees are flattened.
+fn saved_files(repo: &Repository, tree: Oid) -> io::Result<BTreeMap<String, (u32, Oid)>> {
+    let is_metadata = |path: &Path, entry: &TreeEntry| {
+        path == Path::new(MTIMES_FILE) || entry.name == KEEP_FILE
+    };
+    Ok(flatten_tree(&repo.git_dir, tree)?
+        .into_iter()
+        .filter(|(path, entry)| !is_metadata(path, entry))
+        .map(|(path, entry)| (path.display().to_string(), (normalize_mode(entry.mode), entry.hash)))
+        .collect())
+}
+
+/// One side of a file the stash changed
+struct Side {
+    mode: u32,
+    hash: Oid,
+    content: Vec<u8>,
+}
+
+/// Shows the files that differ between the stash's parent commit and the
+/// stash, as a patch or as a diffstat
+fn show_diff(repo: &Repository, out: &mut dyn Write, parent_hash: Oid, stash_tree: Oid, options: &ShowOptions) -> io::Result<()> {
+    let parent_tree = match Object::read_from_objects_dir(&repo.git_dir, parent_hash)? {
+        Object::Commit { tree, .. } => tree,
         _ => return Ok(()),
     };
-    
-    // Read both trees
-    let parent_tree_obj = crate::cobra::core::object::Object::read_from_objects_dir(&repo.git_dir, &parent_tree)?;
-    let stash_tree_obj = crate::cobra::core::object::Object::read_from_objects_dir(&repo.git_dir, stash_tree)?;
-    
-    match (parent_tree_obj, stash_tree_obj) {
-        (crate::cobra::core::object::Object::Tree(parent_entries), crate::cobra::core::object::Object::Tree(stash_entries)) => {
-            // Create maps for easy lookup
-            let mut parent_map = std::collections::HashMap::new();
-            for entry in parent_entries {
-                parent_map.insert(entry.name.clone(), entry.hash);
-            }
-            
-            let mut stash_map = std::collections::HashMap::new();
-            for entry in stash_entries {
-                stash_map.insert(entry.name.clone(), entry.hash);
-            }
-            
-            // Show differences
-            for (name, stash_hash) in &stash_map {
-                if let Some(parent_hash) = parent_map.get(name) {
-                    if parent_hash != stash_hash {
-                        println!("diff --git a/{} b/{}", name, name);
-                        println!("index {}..{}", &parent_hash[..7], &stash_hash[..7]);
-                        println!("--- a/{}", name);
-                        println!("+++ b/{}", name);
-                        println!();
-                    }
-                } else {
-                    println!("diff --git a/{} b/{}", name, name);
-                    println!("new file mode 100644");
-                    println!("index 0000000..{}", &stash_hash[..7]);
-                    println!("--- /dev/null");
-                    println!("+++ b/{}", name);
-                    println!();
-                }
+    let parent_map = saved_files(repo, parent_tree)?;
+    let stash_map = saved_files(repo, stash_tree)?;
+    let names: BTreeSet<&String> = parent_map.keys().chain(stash_map.keys()).collect();
+
+    let algorithm = repo.diff_algorithm()?;
+    let mut stats = Vec::new();
+    for name in names {
+        let (old, new) = (parent_map.get(name), stash_map.get(name));
+        if old == new {
+            continue;
+        }
+        let old = old.map(|(mode, hash)| load_side(repo, *mode, *hash)).transpose()?;
+        let new = new.map(|(mode, hash)| load_side(repo, *mode, *hash)).transpose()?;
+        let empty = Vec::new();
+        let old_content = old.as_ref().map_or(&empty, |side| &side.content);
+        let new_content = new.as_ref().map_or(&empty, |side| &side.content);
+        let binary = old_content.contains(&0) || new_content.contains(&0);
+        let old_lines = split_lines(old_content);
+        let new_lines = split_lines(new_content);
+        let edits = if binary { Vec::new() } else { algorithm.diff_lines(&old_lines, &new_lines) };
+
+        if options.stat {
+            stats.push(FileStat {
+                path: name.clone(),
+                insertions: edits.iter().filter(|edit| matches!(edit, Edit::Insert { .. })).count(),
+                deletions: edits.iter().filter(|edit| matches!(edit, Edit::Delete { .. })).count(),
+                binary: binary.then_some((old_content.len(), new_content.len())),
+            });
+            continue;
+        }
+
+        writeln!(out, "diff --git a/{} b/{}", name, name)?;
+        match (&old, &new) {
+            (None, Some(new)) => writeln!(out, "new file mode {:06o}", new.mode)?,
+            (Some(old), None) => writeln!(out, "deleted file mode {:06o}", old.mode)?,
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                writeln!(out, "old mode {:06o}", old.mode)?;
+                writeln!(out, "new mode {:06o}", new.mode)?;
             }
-            
-            // Show deleted files
-            for (name, _) in &parent_map {
-                if !stash_map.contains_key(name) {
-                    println!("diff --git a/{} b/{}", name, name);
-                    println!("deleted file mode 100644");
-                    println!("index {}..0000000", &parent_map[name][..7]);
-                    println!("--- a/{}", name);
-                    println!("+++ /dev/null");
-                    println!();
-                }
+            _ => {}
+        }
+        let short = |side: &Option<Side>| repo.short_id(side.as_ref().map_or(Oid::null(HashAlgorithm::Sha1), |side| side.hash));
+        match (&old, &new) {
+            (Some(old_side), Some(new_side)) if old_side.mode == new_side.mode => {
+                writeln!(out, "index {}..{} {:06o}", short(&old), short(&new), old_side.mode)?
             }
+            _ => writeln!(out, "index {}..{}", short(&old), short(&new))?,
         }
-        _ => {
-            println!("diff --git a/ b/");
-            println!("index {}..{}", &parent_tree[..7], &stash_tree[..7]);
-            println!("--- a/");
-            println!("+++ b/");
+
+        let old_name = if old.is_some() { format!("a/{}", name) } else { "/dev/null".to_string() };
+        let new_name = if new.is_some() { format!("b/{}", name) } else { "/dev/null".to_string() };
+        if binary {
+            writeln!(out, "Binary files {} and {} differ", old_name, new_name)?;
+            continue;
         }
+        writeln!(out, "--- {}", old_name)?;
+        writeln!(out, "+++ {}", new_name)?;
+        write_unified(out, &old_lines, &new_lines, &hunks(&edits, CONTEXT_LINES))?;
+    }
+
+    if options.stat {
+        write_diffstat(out, &stats)?;
     }
-    
     Ok(())
 }
 
+fn load_side(repo: &Repository, mode: u32, hash: Oid) -> io::Result<Side> {
+    let content = Object::read_blob(&repo.git_dir, hash)?;
+    Ok(Side { mode, hash, content })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::{init_repo, temp_repo, MAIN_COMMIT};
     use tempfile::TempDir;
 
     #[test]
     fn test_stash_push() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (_temp_dir, repo) = temp_repo()?;
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
         
         // Set up a commit
-        ref_store.update_ref("refs/heads/main", "main_commit")?;
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
         
         // Test stash push
-        let stash_hash = ref_store.create_stash(Some("Test stash"))?;
-        assert!(!stash_hash.is_empty());
+        let stash_hash = ref_store.create_stash(&repo, Some("Test stash"))?;
+        assert!(!stash_hash.is_null());
         
         // Verify stash was created
         let stashes = ref_store.list_stashes()?;
@@ -260,16 +287,15 @@ mod tests {
 
     #[test]
     fn test_stash_list() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (_temp_dir, repo) = temp_repo()?;
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
         
         // Set up a commit
-        ref_store.update_ref("refs/heads/main", "main_commit")?;
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
         
         // Create stashes
-        ref_store.create_stash(Some("First stash"))?;
-        ref_store.create_stash(Some("Second stash"))?;
+        ref_store.create_stash(&repo, Some("First stash"))?;
+        ref_store.create_stash(&repo, Some("Second stash"))?;
         
         // Test list functionality
         let stashes = ref_store.list_stashes()?;
@@ -282,15 +308,14 @@ mod tests {
 
     #[test]
     fn test_stash_show() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (_temp_dir, repo) = temp_repo()?;
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
         
         // Set up a commit
-        ref_store.update_ref("refs/heads/main", "main_commit")?;
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
         
         // Create a stash
-        let stash_hash = ref_store.create_stash(Some("Test stash message"))?;
+        let stash_hash = ref_store.create_stash(&repo, Some("Test stash message"))?;
         
         // Test show functionality
         let retrieved_hash = ref_store.get_stash("stash@{0}")?;
@@ -301,16 +326,15 @@ mod tests {
 
     #[test]
     fn test_stash_drop() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (_temp_dir, repo) = temp_repo()?;
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
         
         // Set up a commit
-        ref_store.update_ref("refs/heads/main", "main_commit")?;
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
         
         // Create stashes
-        ref_store.create_stash(Some("First stash"))?;
-        ref_store.create_stash(Some("Second stash"))?;
+        ref_store.create_stash(&repo, Some("First stash"))?;
+        ref_store.create_stash(&repo, Some("Second stash"))?;
         
         // Verify we have 2 stashes
         let stashes = ref_store.list_stashes()?;
@@ -326,10 +350,32 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_recover_dropped_stash_from_fsck() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
+
+        let older = ref_store.create_stash(&repo, Some("Keep me"))?;
+        let newer = ref_store.create_stash(&repo, Some("Still here"))?;
+        ref_store.drop_stash("stash@{1}")?;
+        assert_eq!(ref_store.list_stashes()?, vec![("stash@{0}".to_string(), newer)]);
+
+        // The dropped stash is now a dangling commit and can be pushed back
+        let dangling = crate::cobra::core::fsck::check(&repo)?.dangling;
+        assert_eq!(dangling.len(), 1);
+        assert_eq!((dangling[0].kind, &dangling[0].hash), ("commit", &older));
+        ref_store.push_stash(dangling[0].hash, "Keep me")?;
+        assert_eq!(ref_store.get_stash("stash@{0}")?, Some(older));
+        assert!(crate::cobra::core::fsck::check(&repo)?.dangling.is_empty());
+
+        Ok(())
+    }
+
     #[test]
     fn test_stash_drop_nonexistent() {
         let temp_dir = TempDir::new().unwrap();
-        let repo = Repository::init(temp_dir.path().to_str().unwrap()).unwrap();
+        let repo = init_repo(temp_dir.path()).unwrap();
         let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir);
         
         // Try to drop non-existent stash
@@ -344,4 +390,118 @@ mod tests {
             _ => panic!("Expected error"),
         }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_apply_restores_empty_dirs_and_mtimes() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let mut repo = init_repo(root)?;
+        fs::write(root.join("README"), "readme\n")?;
+        add_file(&mut repo, Path::new("README"))?;
+        commit(&repo, "Initial commit")?;
+
+        fs::create_dir_all(root.join("build/cache"))?;
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/lib.rs"), "pub fn lib() {}\n")?;
+        let stashed_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        fs::File::options().write(true).open(root.join("src/lib.rs"))?.set_modified(stashed_at)?;
+        repo.ref_store().create_stash(&repo, Some("With an empty dir"))?;
+
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let mtime = || fs::metadata(root.join("src/lib.rs"))?.modified();
+
+        fs::remove_dir_all(root.join("build"))?;
+        fs::remove_file(root.join("src/lib.rs"))?;
+        apply(&mut ctx, "stash@{0}", &ApplyOptions::default())?;
+        assert!(root.join("build/cache").is_dir());
+        assert_eq!(fs::read_to_string(root.join("src/lib.rs"))?, "pub fn lib() {}\n");
+        assert_ne!(mtime()?, stashed_at);
+        // Only real files show up in the stash's diff
+        show(&mut ctx, "stash@{0}", &ShowOptions::default())?;
+        assert!(!ctx.out.captured().contains(".cobra-"));
+
+        apply(&mut ctx, "stash@{0}", &ApplyOptions { preserve_mtime: true, ..Default::default() })?;
+        assert_eq!(mtime()?, stashed_at);
+
+        fs::write(ctx.repo.git_dir.join("config"), "[core]\n\tpreserveMtime = true\n")?;
+        fs::File::options().write(true).open(root.join("src/lib.rs"))?.set_modified(SystemTime::now())?;
+        apply(&mut ctx, "stash@{0}", &ApplyOptions::default())?;
+        assert_eq!(mtime()?, stashed_at);
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_prints_patch_and_stat() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let mut repo = init_repo(root)?;
+        fs::write(root.join("notes.txt"), "one\nthree\n")?;
+        fs::write(root.join("gone.txt"), "bye\nnow\n")?;
+        add_file(&mut repo, Path::new("notes.txt"))?;
+        add_file(&mut repo, Path::new("gone.txt"))?;
+        commit(&repo, "Initial commit")?;
+
+        fs::write(root.join("notes.txt"), "one\ntwo\nthree")?;
+        fs::remove_file(root.join("gone.txt"))?;
+        fs::create_dir_all(root.join("src/deep"))?;
+        fs::write(root.join("src/deep/new.rs"), "fn new() {}\n")?;
+        repo.ref_store().create_stash(&repo, Some("Edit notes"))?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        show(&mut ctx, "stash@{0}", &ShowOptions::default())?;
+        let patch = ctx.out.captured();
+        assert!(patch.contains("\
+--- a/notes.txt
++++ b/notes.txt
+@@ -1,2 +1,3 @@
+ one
+-three
++two
++three
+\\ No newline at end of file
+"));
+        assert!(patch.contains("deleted file mode 100644\nindex "));
+        assert!(patch.contains("--- a/gone.txt\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-bye\n-now\n"));
+        assert!(patch.contains("new file mode 100644\nindex 0000000.."));
+        assert!(patch.contains("--- /dev/null\n+++ b/src/deep/new.rs\n@@ -0,0 +1,1 @@\n+fn new() {}\n"));
+
+        ctx.out = OutputHandle::buffer(false);
+        show(&mut ctx, "stash@{0}", &ShowOptions { stat: true })?;
+        assert!(ctx.out.captured().ends_with("\
+ gone.txt        | 2 --
+ notes.txt       | 3 ++-
+ src/deep/new.rs | 1 +
+ 3 files changed, 3 insertions(+), 3 deletions(-)
+"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pop_keeps_the_stash_on_conflict() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let mut repo = init_repo(root)?;
+        fs::write(root.join("notes.txt"), "committed\n")?;
+        add_file(&mut repo, Path::new("notes.txt"))?;
+        commit(&repo, "Initial commit")?;
+        fs::write(root.join("notes.txt"), "stashed\n")?;
+        repo.ref_store().create_stash(&repo, Some("Edit notes"))?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+
+        fs::write(root.join("notes.txt"), "local\n")?;
+        assert!(pop(&mut ctx, "stash@{0}", &ApplyOptions::default()).is_err());
+        assert!(ctx.out.captured().contains(&messages::stash_kept()));
+        assert_eq!(ctx.refs.list_stashes()?.len(), 1);
+        assert_eq!(fs::read_to_string(root.join("notes.txt"))?, "local\n");
+
+        fs::write(root.join("notes.txt"), "stashed\n")?;
+        pop(&mut ctx, "stash@{0}", &ApplyOptions::default())?;
+        assert!(ctx.out.captured().contains("Dropped stash@{0}"));
+        assert!(ctx.refs.list_stashes()?.is_empty());
+
+        let err = pop(&mut ctx, "stash@{0}", &ApplyOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "No stash entries found");
+        Ok(())
+    }
+}
@@ -1,29 +1,190 @@
-use std::io;
+use std::io::{self, Write};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::os::unix::fs::MetadataExt;
 use walkdir::WalkDir;
 use crate::cobra::core::{
+    attributes::Attributes,
+    config::Config,
+    ignore::IgnoreRules,
     repository::Repository,
-    index::IndexEntry,
-    object::Object,
-    ref_store::RefStore,
+    index::{normalize_mode, IndexEntry},
+    ancestry::merge_base,
+    merge::{ConflictKind, MergeState},
+    object::{Object, TreeEntry},
+    oid::Oid,
+    reflog::ZERO_HASH,
+    tree::{commit_tree, flatten_tree},
+    verify_index::{self, StaleFile},
+    workspace::is_visible,
 };
+use crate::cobra::state::CommandContext;
+use crate::cobra::utils::trace::{self, Stream};
+use crate::cobra::messages::{self, machine};
 
-fn get_workspace_files(repo_root: &Path) -> io::Result<HashSet<PathBuf>> {
+/// How `cobra status` prints its results
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum StatusFormat {
+    /// Human readable sections
+    #[default]
+    Long,
+    /// Two-letter codes and paths (-s, --short)
+    Short,
+    /// Same records as Short, frozen for scripts, `--porcelain[=v1]`
+    PorcelainV1,
+    /// Machine readable records, `--porcelain=v2`
+    PorcelainV2,
+}
+
+impl StatusFormat {
+    /// Parses the value given to `--porcelain`
+    pub fn from_porcelain(version: &str) -> io::Result<StatusFormat> {
+        match version {
+            "v2" | "2" => Ok(StatusFormat::PorcelainV2),
+            "v1" | "1" => Ok(StatusFormat::PorcelainV1),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported porcelain version '{}'", version),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StatusOptions {
+    pub format: StatusFormat,
+    /// Print the `# branch.*` header lines (-b)
+    pub branch: bool,
+    /// Terminate records with NUL and leave paths unquoted (-z)
+    pub null_terminated: bool,
+}
+
+/// Mode and object hash of a path on one side of the comparison
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryState {
+    pub mode: u32,
+    pub hash: Oid,
+}
+
+/// A tracked path that differs between HEAD, the index and the work tree.
+/// A side is None when the path does not exist there.
+#[derive(Debug, Clone)]
+pub struct StatusEntry {
+    pub path: PathBuf,
+    pub head: Option<EntryState>,
+    pub index: Option<EntryState>,
+    pub worktree: Option<EntryState>,
+}
+
+impl StatusEntry {
+    /// The staged half of the XY code: HEAD against the index
+    pub fn staged_code(&self) -> char {
+        change_code(self.head.as_ref(), self.index.as_ref())
+    }
+
+    /// The unstaged half of the XY code: the index against the work tree
+    pub fn unstaged_code(&self) -> char {
+        match self.index {
+            // A path removed from the index has nothing to compare
+            None => '.',
+            Some(_) => change_code(self.index.as_ref(), self.worktree.as_ref()),
+        }
+    }
+}
+
+fn change_code(old: Option<&EntryState>, new: Option<&EntryState>) -> char {
+    match (old, new) {
+        (None, None) => '.',
+        (None, Some(_)) => 'A',
+        (Some(_), None) => 'D',
+        (Some(old), Some(new)) if old == new => '.',
+        (Some(_), Some(_)) => 'M',
+    }
+}
+
+/// Everything `cobra status` reports, shared by all output formats
+#[derive(Debug, Default)]
+pub struct StatusReport {
+    /// The checked out branch, or None when HEAD is detached
+    pub branch: Option<String>,
+    /// The commit HEAD points to, or None before the first commit
+    pub head_oid: Option<Oid>,
+    /// HEAD's commit abbreviated for display, None before the first commit
+    pub head_short: Option<String>,
+    /// Changed tracked paths, sorted by path
+    pub entries: Vec<StatusEntry>,
+    /// Files in the work tree that are not in the index, sorted
+    pub untracked: Vec<PathBuf>,
+    /// Tracked files that had to be hashed only to find them unchanged
+    pub stale: Vec<StaleFile>,
+    /// Whether a merge stopped on conflicts and has not been concluded
+    pub merging: bool,
+    /// Conflicted paths not yet marked resolved with `cobra add`
+    pub unmerged: Vec<UnmergedEntry>,
+}
+
+/// A conflicted path with the versions git keeps in index stages 1 (the
+/// merge base), 2 (ours) and 3 (theirs). A stage is None when the path does
+/// not exist on that side.
+#[derive(Debug, Clone)]
+pub struct UnmergedEntry {
+    pub path: PathBuf,
+    pub kind: ConflictKind,
+    pub base: Option<EntryState>,
+    pub ours: Option<EntryState>,
+    pub theirs: Option<EntryState>,
+    /// Mode of the conflicted file in the work tree, 0 when there is none
+    pub worktree_mode: u32,
+}
+
+impl StatusReport {
+    /// Entries whose index side differs from HEAD, what the next commit
+    /// records; unmerged paths are reported on their own
+    pub fn staged(&self) -> impl Iterator<Item = &StatusEntry> {
+        self.entries.iter().filter(|entry| entry.staged_code() != '.' && self.unmerged_kind(&entry.path).is_none())
+    }
+
+    /// Entries whose work tree side differs from the index, including
+    /// files deleted from the work tree; unmerged paths are reported on
+    /// their own
+    pub fn unstaged(&self) -> impl Iterator<Item = &StatusEntry> {
+        self.entries.iter().filter(|entry| entry.unstaged_code() != '.' && self.unmerged_kind(&entry.path).is_none())
+    }
+
+    /// How the path conflicted, if it is still unmerged
+    pub fn unmerged_kind(&self, path: &Path) -> Option<ConflictKind> {
+        self.unmerged.iter().find(|unmerged| unmerged.path == path).map(|unmerged| unmerged.kind)
+    }
+
+    /// Tracked files deleted from the work tree but still in the index
+    pub fn deleted(&self) -> impl Iterator<Item = &Path> {
+        self.entries.iter()
+            .filter(|entry| entry.unstaged_code() == 'D')
+            .map(|entry| entry.path.as_path())
+    }
+
+    /// Tracked files whose work tree content differs from the index
+    pub fn modified(&self) -> impl Iterator<Item = &Path> {
+        self.entries.iter()
+            .filter(|entry| entry.unstaged_code() == 'M')
+            .map(|entry| entry.path.as_path())
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.entries.is_empty() && self.untracked.is_empty() && self.unmerged.is_empty()
+    }
+}
+
+fn get_workspace_files(repo: &Repository) -> io::Result<HashSet<PathBuf>> {
     let mut files = HashSet::new();
-    let cobra_dir = repo_root.join(".cobra");
+    let repo_root = &repo.root_path;
+    let ignore = IgnoreRules::load(repo)?;
 
     for entry in WalkDir::new(repo_root)
         .min_depth(1)  // Skip root directory
         .into_iter()
-        .filter_entry(|e| {
-            // Skip .cobra directory and hidden files
-            !e.path().starts_with(&cobra_dir) && 
-            !e.path().to_string_lossy().contains("/.") &&
-            !e.path().file_name().map_or(false, |n| n.to_string_lossy().starts_with("."))
-        })
+        .filter_entry(|e| is_visible(repo, &ignore, e))
     {
         let entry = entry?;
         if entry.file_type().is_file() {
@@ -35,79 +196,786 @@ fn get_workspace_files(repo_root: &Path) -> io::Result<HashSet<PathBuf>> {
     Ok(files)
 }
 
-fn is_file_modified(repo: &Repository, path: &Path, index_entry: &IndexEntry) -> io::Result<bool> {
+/// The base, our and their version of every path the merge left conflicted
+fn unmerged_entries(
+    repo: &Repository,
+    head_oid: Option<Oid>,
+    head_tree: &BTreeMap<PathBuf, TreeEntry>,
+    merge: MergeState,
+) -> io::Result<Vec<UnmergedEntry>> {
+    let tree_of = |commit: Option<Oid>| -> io::Result<BTreeMap<PathBuf, TreeEntry>> {
+        match commit {
+            Some(commit) => flatten_tree(&repo.git_dir, commit_tree(&repo.git_dir, commit)?),
+            None => Ok(BTreeMap::new()),
+        }
+    };
+    let base = match head_oid {
+        Some(head) => merge_base(repo, head, merge.head)?,
+        None => None,
+    };
+    let base_tree = tree_of(base)?;
+    let their_tree = tree_of(Some(merge.head))?;
+
+    let state = |tree: &BTreeMap<PathBuf, TreeEntry>, path: &PathBuf| tree.get(path)
+        .map(|entry| EntryState { mode: normalize_mode(entry.mode), hash: entry.hash });
+    let mut entries = Vec::new();
+    for unmerged in merge.unmerged {
+        let worktree_mode = match fs::symlink_metadata(repo.root_path.join(&unmerged.path)) {
+            Ok(metadata) => normalize_mode(metadata.mode()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        entries.push(UnmergedEntry {
+            base: state(&base_tree, &unmerged.path),
+            // Ours is HEAD, which does not move while the merge is pending
+            ours: state(head_tree, &unmerged.path),
+            theirs: state(&their_tree, &unmerged.path),
+            worktree_mode,
+            path: unmerged.path,
+            kind: unmerged.kind,
+        });
+    }
+    Ok(entries)
+}
+
+/// Reads the state of a tracked file in the work tree, or None if it is gone.
+/// Records files whose stat data was out of date but content was not in `stale`.
+fn worktree_state(
+    repo: &Repository,
+    attributes: &Attributes,
+    path: &Path,
+    index_entry: &IndexEntry,
+    stale: &mut Vec<StaleFile>,
+) -> io::Result<Option<EntryState>> {
     let full_path = repo.root_path.join(path);
-    let metadata = fs::metadata(&full_path)?;
-    
-    println!("Checking file: {}", path.display());
-    println!("  Current size: {}, Index size: {}", metadata.len(), index_entry.size);
-    println!("  Current mtime: {}, Index mtime: {}", metadata.mtime(), index_entry.mtime);
-    
-    // Quick check: if mtime and size match, assume content is the same
-    if metadata.len() == index_entry.size && 
-       metadata.mtime() as u64 == index_entry.mtime {
-        return Ok(false);
+    let metadata = match fs::metadata(&full_path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mode = normalize_mode(metadata.mode());
+
+    trace::event(Stream::Main, || format!(
+        "status check {}: size {} (index {}), mtime {} (index {})",
+        path.display(), metadata.len(), index_entry.size, metadata.mtime(), index_entry.mtime,
+    ));
+
+    // Quick check: if the stat data matches, assume content is the same
+    if index_entry.stat_matches(&metadata) {
+        return Ok(Some(EntryState { mode, hash: index_entry.hash }));
     }
 
-    // Content check: hash the current file and compare with index
+    // Content check: hash the current file as add would store it
     let content = fs::read(&full_path)?;
-    let blob = Object::new_blob(content);
-    let current_hash = blob.hash();
-    println!("  Current hash: {}, Index hash: {}", current_hash, index_entry.hash);
-    Ok(current_hash != index_entry.hash)
-}
-
-pub fn run() -> io::Result<()> {
-    // Open repository
-    let repo = Repository::open(".")?;
-    let _ref_store = RefStore::new(repo.git_dir.clone());
-
-    // Get all files in workspace
-    let workspace_files = get_workspace_files(&repo.root_path)?;
-    
-    // Get all files in index
-    let index_files: HashSet<_> = repo.index.entries()
-        .map(|entry| entry.path.clone())
+    let hash = Object::new_blob(attributes.to_index(path, content)).hash();
+    if hash == index_entry.hash && mode == normalize_mode(index_entry.mode) {
+        stale.push(StaleFile { path: index_entry.path.clone(), hash, metadata });
+    }
+    Ok(Some(EntryState { mode, hash }))
+}
+
+/// Compares HEAD, the index and the work tree
+pub fn collect(repo: &Repository) -> io::Result<StatusReport> {
+    let ref_store = repo.ref_store();
+    let branch = ref_store.current_branch()?;
+    let head_oid = match ref_store.resolve_commit("HEAD") {
+        Ok(hash) => Some(hash),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    let head_tree = match head_oid {
+        Some(commit) => flatten_tree(&repo.git_dir, commit_tree(&repo.git_dir, commit)?)?,
+        None => Default::default(),
+    };
+
+    let workspace_files = trace::timed(Stream::Main, "worktree walk", || get_workspace_files(repo), |files| {
+        match files {
+            Ok(files) => format!("{} files", files.len()),
+            Err(e) => format!("failed: {}", e),
+        }
+    })?;
+
+    // On a case-insensitive or normalizing filesystem a tracked file may sit
+    // in the work tree under another spelling of its name
+    let folded_files: HashMap<String, &PathBuf> = if repo.folding.is_exact() {
+        HashMap::new()
+    } else {
+        workspace_files.iter().map(|path| (repo.folding.key(path), path)).collect()
+    };
+
+    let paths: BTreeSet<&PathBuf> = head_tree.keys()
+        .chain(repo.index.entries().map(|entry| &entry.path))
         .collect();
 
-    // Find untracked files (in workspace but not in index)
-    let mut untracked: Vec<_> = workspace_files.difference(&index_files)
+    let attributes = Attributes::load(repo)?;
+    let mut entries = Vec::new();
+    let mut stale = Vec::new();
+    for path in paths {
+        let head = head_tree.get(path)
+            .map(|entry| EntryState { mode: normalize_mode(entry.mode), hash: entry.hash });
+        let index_entry = repo.index.get_entry(path);
+        let index = index_entry
+            .map(|entry| EntryState { mode: normalize_mode(entry.mode), hash: entry.hash });
+        let worktree = match index_entry {
+            Some(entry) => match worktree_state(repo, &attributes, path, entry, &mut stale)? {
+                None => match folded_files.get(&repo.folding.key(path)) {
+                    Some(actual) => worktree_state(repo, &attributes, actual, entry, &mut stale)?,
+                    None => None,
+                },
+                state => state,
+            },
+            None => None,
+        };
+
+        let entry = StatusEntry { path: path.clone(), head, index, worktree };
+        if entry.staged_code() != '.' || entry.unstaged_code() != '.' {
+            entries.push(entry);
+        }
+    }
+
+    let folded_index: HashSet<String> = if repo.folding.is_exact() {
+        HashSet::new()
+    } else {
+        repo.index.entries().map(|entry| repo.folding.key(&entry.path)).collect()
+    };
+    // A path deleted on our side is not in the index, but its conflicted
+    // content in the work tree is not untracked either
+    let merge = MergeState::read(repo)?;
+    let merging = merge.is_some();
+    let unmerged = match merge {
+        Some(state) => unmerged_entries(repo, head_oid, &head_tree, state)?,
+        None => Vec::new(),
+    };
+    let mut untracked: Vec<_> = workspace_files.iter()
+        .filter(|path| !repo.index.contains(path) && !folded_index.contains(&repo.folding.key(path)))
+        .filter(|path| !unmerged.iter().any(|unmerged| &unmerged.path == *path))
+        .cloned()
         .collect();
     untracked.sort(); // Sort for consistent output
 
-    // Find modified files (in both but content differs)
-    let mut modified = Vec::new();
-    for path in workspace_files.intersection(&index_files) {
-        if let Some(index_entry) = repo.index.entries().find(|e| e.path == *path) {
-            if is_file_modified(&repo, path, index_entry)? {
-                modified.push(path);
-            }
+    let head_short = head_oid.map(|hash| repo.short_id(hash));
+    Ok(StatusReport { branch, head_oid, head_short, entries, untracked, stale, merging, unmerged })
+}
+
+pub fn run(ctx: &mut CommandContext, options: &StatusOptions) -> io::Result<()> {
+    let report = trace::timed(Stream::Main, "status", || collect(&ctx.repo), |report| match report {
+        Ok(report) => format!("{} changed, {} untracked", report.entries.len(), report.untracked.len()),
+        Err(e) => format!("failed: {}", e),
+    })?;
+    if !report.stale.is_empty() && !ctx.repo.index.is_foreign() && !realign_if_drifted(ctx, &report, options)? {
+        refresh_stat_data(&mut ctx.repo, &report.stale);
+    }
+
+    match options.format {
+        StatusFormat::Long => write_long(&report, &mut ctx.out),
+        StatusFormat::Short | StatusFormat::PorcelainV1 => write_short(&report, options, &mut ctx.out),
+        StatusFormat::PorcelainV2 => write_porcelain_v2(&report, options, &mut ctx.out),
+    }
+}
+
+/// When every file's stat data is from another filesystem, refreshes the
+/// index if index.autoRealign allows it, and otherwise suggests doing so.
+/// Returns whether there was such drift.
+fn realign_if_drifted(ctx: &mut CommandContext, report: &StatusReport, options: &StatusOptions) -> io::Result<bool> {
+    let drift = verify_index::wholesale_drift(&ctx.repo)?;
+    if drift.is_empty() {
+        return Ok(false);
+    }
+    let config = Config::load(&ctx.repo.git_dir)?;
+    if config.get_bool("index.autoRealign") == Some(true) {
+        let refreshed = verify_index::realign(&mut ctx.repo)?;
+        trace::event(Stream::Main, || format!("status: realigned {} index entries ({})", refreshed.len(), drift.join(", ")));
+    } else if options.format == StatusFormat::Long {
+        ctx.out.note(format_args!("{}", messages::status_realign_hint(&drift.join("/"), report.stale.len())))?;
+    }
+    Ok(true)
+}
+
+/// Records the current stat data of files status had to hash only to find
+/// them unchanged, so the next run trusts them without reading them; see
+/// `verify_index::refresh_stat_data` for the files left alone. This is only
+/// a cache, so nothing is refreshed when another process holds the index
+/// lock or the index cannot be read or written. Returns the paths refreshed.
+pub fn refresh_stat_data(repo: &mut Repository, stale: &[StaleFile]) -> Vec<PathBuf> {
+    match verify_index::refresh_stat_data(repo, stale) {
+        Ok(refreshed) => refreshed,
+        Err(e) => {
+            trace::event(Stream::Main, || format!("status: stat data not refreshed: {}", e));
+            Vec::new()
         }
     }
-    modified.sort(); // Sort for consistent output
+}
 
-    // Print status
-    if !modified.is_empty() {
-        println!("Changes not staged for commit:");
-        println!("  (use \"cobra add <file>...\" to update what will be committed)");
-        for path in &modified {
-            println!("\tmodified:   {}", path.display());
+/// Writes the human-readable report: where HEAD is and any merge in
+/// progress, staged changes, then unmerged paths, unstaged changes and
+/// untracked files, each section with a hint on what to do next
+pub fn write_long(report: &StatusReport, out: &mut dyn Write) -> io::Result<()> {
+    match (&report.branch, &report.head_short) {
+        (Some(branch), _) => writeln!(out, "{}", messages::status_on_branch(branch))?,
+        (None, Some(commit)) => writeln!(out, "{}", messages::status_detached_at(commit))?,
+        (None, None) => {}
+    }
+    if report.head_oid.is_none() {
+        writeln!(out, "\n{}\n", messages::status_no_commits())?;
+    }
+    if report.merging {
+        match report.unmerged.is_empty() {
+            false => writeln!(out, "{}\n", messages::status_merging_unmerged())?,
+            true => writeln!(out, "{}\n", messages::status_merging_fixed())?,
         }
-        println!();
     }
 
-    if !untracked.is_empty() {
-        println!("Untracked files:");
-        println!("  (use \"cobra add <file>...\" to include in what will be committed)");
-        for path in &untracked {
-            println!("\t{}", path.display());
+    let staged: Vec<_> = report.staged().collect();
+    if !staged.is_empty() {
+        writeln!(out, "{}", messages::status_staged())?;
+        writeln!(out, "{}", messages::status_staged_hint())?;
+        for entry in &staged {
+            let path = entry.path.display();
+            let line = match entry.staged_code() {
+                'A' => messages::status_new_file(path),
+                'D' => messages::status_deleted(path),
+                _ => messages::status_modified(path),
+            };
+            writeln!(out, "{}", line)?;
         }
-        println!();
+        writeln!(out)?;
     }
 
-    if modified.is_empty() && untracked.is_empty() {
-        println!("nothing to commit, working tree clean");
+    if !report.unmerged.is_empty() {
+        writeln!(out, "{}", messages::status_unmerged())?;
+        writeln!(out, "{}", messages::status_unmerged_hint())?;
+        for unmerged in &report.unmerged {
+            let path = unmerged.path.display();
+            let line = match unmerged.kind {
+                ConflictKind::BothModified => messages::status_both_modified(path),
+                ConflictKind::BothAdded => messages::status_both_added(path),
+                ConflictKind::DeletedByUs => messages::status_deleted_by_us(path),
+                ConflictKind::DeletedByThem => messages::status_deleted_by_them(path),
+            };
+            writeln!(out, "{}", line)?;
+        }
+        writeln!(out)?;
+    }
+
+    let unstaged: Vec<_> = report.unstaged().collect();
+    if !unstaged.is_empty() {
+        writeln!(out, "{}", messages::status_not_staged())?;
+        writeln!(out, "{}", messages::status_not_staged_hint())?;
+        for entry in &unstaged {
+            let path = entry.path.display();
+            let line = match entry.unstaged_code() {
+                'D' => messages::status_deleted(path),
+                _ => messages::status_modified(path),
+            };
+            writeln!(out, "{}", line)?;
+        }
+        writeln!(out)?;
+    }
+
+    if !report.untracked.is_empty() {
+        writeln!(out, "{}", messages::status_untracked())?;
+        writeln!(out, "{}", messages::status_untracked_hint())?;
+        for path in &report.untracked {
+            writeln!(out, "\t{}", path.display())?;
+        }
+        writeln!(out)?;
+    }
+
+    if staged.is_empty() && unstaged.is_empty() && report.untracked.is_empty() && report.unmerged.is_empty() {
+        writeln!(out, "{}", messages::status_clean())?;
     }
 
     Ok(())
-} 
\ No newline at end of file
+}
+
+/// Writes `XY path` records as `--short` and `--porcelain=v1` print them:
+/// X is the staged change, Y the unstaged one, a space meaning unchanged,
+/// untracked files get `??` and unmerged ones their conflict code, such as
+/// `UU`. With -b a `## <branch>` line comes first.
+/// The porcelain format is frozen, so any future cosmetic change to the
+/// short format has to leave PorcelainV1 as it is.
+pub fn write_short(report: &StatusReport, options: &StatusOptions, out: &mut dyn Write) -> io::Result<()> {
+    let terminator = if options.null_terminated { "\0" } else { "\n" };
+    let path = |path: &Path| if options.null_terminated {
+        path.to_string_lossy().into_owned()
+    } else {
+        quote_path(path)
+    };
+
+    if options.branch {
+        let branch = report.branch.as_deref().unwrap_or(machine::NO_BRANCH);
+        match report.head_oid {
+            Some(_) => write!(out, "## {}{}", branch, terminator)?,
+            None => write!(out, "## {} {}{}", machine::NO_COMMITS_YET_ON, branch, terminator)?,
+        }
+    }
+
+    let code = |code: char| if code == '.' { ' ' } else { code };
+    let mut records: Vec<(&Path, String)> = report.entries.iter()
+        .filter(|entry| report.unmerged_kind(&entry.path).is_none())
+        .map(|entry| (entry.path.as_path(), format!("{}{}", code(entry.staged_code()), code(entry.unstaged_code()))))
+        .chain(report.unmerged.iter().map(|unmerged| (unmerged.path.as_path(), unmerged.kind.code().to_string())))
+        .collect();
+    records.sort();
+    for (entry_path, code) in records {
+        write!(out, "{} {}{}", code, path(entry_path), terminator)?;
+    }
+    for untracked in &report.untracked {
+        write!(out, "?? {}{}", path(untracked), terminator)?;
+    }
+    Ok(())
+}
+
+/// Writes `--porcelain=v2` records. Ordinary changes use `1 XY N... mH mI mW
+/// hH hI path` and unmerged paths `u XY N... m1 m2 m3 mW h1 h2 h3 path`,
+/// both in path order; sides that do not exist get a zero mode and hash.
+/// There are no submodules, so the submodule field is always `N...`, and
+/// without rename detection there are no `2` records.
+pub fn write_porcelain_v2(report: &StatusReport, options: &StatusOptions, out: &mut dyn Write) -> io::Result<()> {
+    let terminator = if options.null_terminated { "\0" } else { "\n" };
+    let path = |path: &Path| if options.null_terminated {
+        path.to_string_lossy().into_owned()
+    } else {
+        quote_path(path)
+    };
+
+    if options.branch {
+        write!(out, "# branch.oid {}{}", report.head_oid.map_or(machine::INITIAL_OID.to_string(), |oid| oid.to_string()), terminator)?;
+        write!(out, "# branch.head {}{}", report.branch.as_deref().unwrap_or(machine::DETACHED_HEAD), terminator)?;
+        // Branches have no upstream yet, so there are no branch.upstream or branch.ab lines
+    }
+
+    let mode = |state: &Option<EntryState>| state.as_ref().map_or(0, |state| state.mode);
+    let hash = |state: &Option<EntryState>| state.as_ref().map_or(ZERO_HASH.to_string(), |state| state.hash.to_string());
+    let mut records: Vec<(&Path, String)> = report.entries.iter()
+        .filter(|entry| report.unmerged_kind(&entry.path).is_none())
+        .map(|entry| (entry.path.as_path(), format!(
+            "1 {}{} N... {:06o} {:06o} {:06o} {} {}",
+            entry.staged_code(),
+            entry.unstaged_code(),
+            mode(&entry.head),
+            mode(&entry.index),
+            mode(&entry.worktree),
+            hash(&entry.head),
+            hash(&entry.index),
+        )))
+        .chain(report.unmerged.iter().map(|unmerged| (unmerged.path.as_path(), format!(
+            "u {} N... {:06o} {:06o} {:06o} {:06o} {} {} {}",
+            unmerged.kind.code(),
+            mode(&unmerged.base),
+            mode(&unmerged.ours),
+            mode(&unmerged.theirs),
+            unmerged.worktree_mode,
+            hash(&unmerged.base),
+            hash(&unmerged.ours),
+            hash(&unmerged.theirs),
+        ))))
+        .collect();
+    records.sort();
+    for (entry_path, record) in records {
+        write!(out, "{} {}{}", record, path(entry_path), terminator)?;
+    }
+
+    for untracked in &report.untracked {
+        write!(out, "? {}{}", path(untracked), terminator)?;
+    }
+    Ok(())
+}
+
+/// Quotes a path C-style when it contains quotes, backslashes, control
+/// characters or non-ASCII bytes; other paths are returned as they are
+pub fn quote_path(path: &Path) -> String {
+    let bytes = path.as_os_str().as_encoded_bytes();
+    if !bytes.iter().any(|&b| b == b'"' || b == b'\\' || !(0x20..0x7f).contains(&b)) {
+        return path.to_string_lossy().into_owned();
+    }
+
+    let mut quoted = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            0x20..=0x7e => quoted.push(b as char),
+            _ => quoted.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::core::case_fold::PathFolding;
+    use crate::cobra::core::index::Index;
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::{init_repo, temp_repo};
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_case_folded_names_are_not_untracked() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        fs::write(temp_dir.path().join("README.md"), "readme\n")?;
+        add_file(&mut repo, Path::new("README.md"))?;
+        commit(&repo, "Add readme")?;
+
+        // A case-insensitive filesystem may hand the file back under another case
+        fs::rename(temp_dir.path().join("README.md"), temp_dir.path().join("readme.md"))?;
+        let report = collect(&repo)?;
+        assert_eq!(report.entries.len(), 1);
+        assert_eq!(report.entries[0].unstaged_code(), 'D');
+        assert_eq!(report.untracked, vec![PathBuf::from("readme.md")]);
+
+        repo.folding = PathFolding { ignore_case: true, precompose_unicode: false };
+        let report = collect(&repo)?;
+        assert!(report.is_clean(), "{:?}", report);
+        Ok(())
+    }
+
+    #[test]
+    fn test_separate_work_tree() -> io::Result<()> {
+        let meta_dir = TempDir::new()?;
+        let work_dir = TempDir::new()?;
+        init_repo(meta_dir.path())?;
+        let mut repo = Repository::open_at(meta_dir.path().join(".cobra"), work_dir.path().to_path_buf())?;
+
+        fs::create_dir(work_dir.path().join("src"))?;
+        fs::write(work_dir.path().join("src/main.rs"), "fn main() {}\n")?;
+        fs::write(work_dir.path().join("notes.txt"), "todo\n")?;
+
+        add_file(&mut repo, Path::new("src/main.rs"))?;
+        commit(&repo, "Add main")?;
+
+        let report = collect(&repo)?;
+        assert!(report.entries.is_empty());
+        assert_eq!(report.untracked, vec![PathBuf::from("notes.txt")]);
+
+        // Nothing was written next to the metadata, nothing leaked into the work tree
+        assert!(!meta_dir.path().join("src").exists());
+        assert!(!work_dir.path().join(".cobra").exists());
+
+        fs::write(work_dir.path().join("src/main.rs"), "fn main() { run() }\n")?;
+        let report = collect(&repo)?;
+        assert_eq!(report.modified().collect::<Vec<_>>(), vec![Path::new("src/main.rs")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_porcelain_v2_golden() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+        let blob = |content: &str| Object::new_blob(content.as_bytes().to_vec()).hash();
+
+        fs::write(root.join("staged.txt"), "one\n")?;
+        fs::write(root.join("unstaged.txt"), "two\n")?;
+        fs::write(root.join("removed.txt"), "three\n")?;
+        for name in ["staged.txt", "unstaged.txt", "removed.txt"] {
+            add_file(&mut repo, Path::new(name))?;
+        }
+        let head = commit(&repo, "Initial commit")?;
+
+        // Drop removed.txt from the index, keeping everything else
+        let kept: Vec<_> = repo.index.entries()
+            .filter(|entry| entry.path != Path::new("removed.txt"))
+            .cloned()
+            .collect();
+        repo.index = Index::new();
+        for entry in kept {
+            repo.index.add_entry(entry);
+        }
+        fs::remove_file(root.join("removed.txt"))?;
+
+        fs::write(root.join("staged.txt"), "one\nmore\n")?;
+        add_file(&mut repo, Path::new("staged.txt"))?;
+        fs::write(root.join("added.txt"), "new\n")?;
+        add_file(&mut repo, Path::new("added.txt"))?;
+        fs::write(root.join("unstaged.txt"), "two changed\n")?;
+        fs::write(root.join("odd \"name\".txt"), "?\n")?;
+
+        let report = collect(&repo)?;
+        let options = StatusOptions { format: StatusFormat::PorcelainV2, branch: true, null_terminated: false };
+        let mut out = Vec::new();
+        write_porcelain_v2(&report, &options, &mut out)?;
+
+        let expected = format!(
+            "# branch.oid {head}\n\
+             # branch.head main\n\
+             1 A. N... 000000 100644 100644 {zero} {added} added.txt\n\
+             1 D. N... 100644 000000 000000 {removed} {zero} removed.txt\n\
+             1 M. N... 100644 100644 100644 {one} {one_more} staged.txt\n\
+             1 .M N... 100644 100644 100644 {two} {two} unstaged.txt\n\
+             ? \"odd \\\"name\\\".txt\"\n",
+            head = head,
+            zero = ZERO_HASH,
+            added = blob("new\n"),
+            removed = blob("three\n"),
+            one = blob("one\n"),
+            one_more = blob("one\nmore\n"),
+            two = blob("two\n"),
+        );
+        assert_eq!(String::from_utf8_lossy(&out), expected);
+
+        // -z leaves paths unquoted and ends every record with NUL
+        let options = StatusOptions { null_terminated: true, branch: false, ..options };
+        let mut out = Vec::new();
+        write_porcelain_v2(&report, &options, &mut out)?;
+        let output = String::from_utf8_lossy(&out);
+        assert!(output.starts_with("1 A. N... "));
+        assert!(output.ends_with("? odd \"name\".txt\0"));
+        assert_eq!(output.matches('\0').count(), 5);
+        assert!(!output.contains('\n'));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_porcelain_v2_golden_with_conflicts() -> io::Result<()> {
+        use crate::cobra::commands::branch::{create_and_switch, merge, switch, MergeOptions, SwitchOptions};
+        use crate::cobra::commands::rm;
+        use crate::cobra::test_support::{commit_file, context};
+
+        let (temp_dir, repo) = temp_repo()?;
+        let root = temp_dir.path();
+        let blob = |content: &str| Object::new_blob(content.as_bytes().to_vec()).hash();
+        let mut ctx = context(repo);
+        commit_file(&mut ctx, "both.txt", "1\n2\n3\n", "Base")?;
+        commit_file(&mut ctx, "gone.txt", "keep\n", "Gone")?;
+        commit_file(&mut ctx, "staged.txt", "one\n", "Staged")?;
+        commit_file(&mut ctx, "unstaged.txt", "two\n", "Unstaged")?;
+        create_and_switch(&mut ctx, "feature", None, &SwitchOptions::default())?;
+        commit_file(&mut ctx, "both.txt", "1\nfeature\n3\n", "Feature edit")?;
+        commit_file(&mut ctx, "gone.txt", "edited\n", "Feature keeps gone.txt")?;
+        switch(&mut ctx, "main", &SwitchOptions::default())?;
+        commit_file(&mut ctx, "both.txt", "1\nmain\n3\n", "Main edit")?;
+        rm::run(&mut ctx, &["gone.txt".to_string()], &rm::RmOptions::default())?;
+        let head = commit(&ctx.repo, "Main drops gone.txt")?;
+        merge(&mut ctx, "feature", &MergeOptions::default()).unwrap_err();
+
+        // Staged, unstaged and untracked changes next to the conflicts
+        fs::write(root.join("staged.txt"), "one\nmore\n")?;
+        add_file(&mut ctx.repo, Path::new("staged.txt"))?;
+        fs::write(root.join("unstaged.txt"), "two changed\n")?;
+        fs::write(root.join("tab\there.txt"), "?\n")?;
+
+        let report = collect(&ctx.repo)?;
+        let options = StatusOptions { format: StatusFormat::PorcelainV2, branch: true, null_terminated: false };
+        let mut out = Vec::new();
+        write_porcelain_v2(&report, &options, &mut out)?;
+
+        let expected = format!(
+            "# branch.oid {head}\n\
+             # branch.head main\n\
+             u UU N... 100644 100644 100644 100644 {base} {ours} {theirs} both.txt\n\
+             u DU N... 100644 000000 100644 100644 {keep} {zero} {edited} gone.txt\n\
+             1 M. N... 100644 100644 100644 {one} {one_more} staged.txt\n\
+             1 .M N... 100644 100644 100644 {two} {two} unstaged.txt\n\
+             ? \"tab\\there.txt\"\n",
+            head = head,
+            zero = ZERO_HASH,
+            base = blob("1\n2\n3\n"),
+            ours = blob("1\nmain\n3\n"),
+            theirs = blob("1\nfeature\n3\n"),
+            keep = blob("keep\n"),
+            edited = blob("edited\n"),
+            one = blob("one\n"),
+            one_more = blob("one\nmore\n"),
+            two = blob("two\n"),
+        );
+        assert_eq!(String::from_utf8_lossy(&out), expected);
+
+        // -z keeps the same records, NUL-terminated and with raw paths
+        let options = StatusOptions { null_terminated: true, ..options };
+        let mut out = Vec::new();
+        write_porcelain_v2(&report, &options, &mut out)?;
+        let expected = expected.replace("\"tab\\there.txt\"", "tab\there.txt").replace('\n', "\0");
+        assert_eq!(String::from_utf8_lossy(&out), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_and_porcelain_v1() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+        let options = StatusOptions { format: StatusFormat::PorcelainV1, branch: true, null_terminated: false };
+        let short = |repo: &Repository, options: &StatusOptions| -> io::Result<String> {
+            let mut out = Vec::new();
+            write_short(&collect(repo)?, options, &mut out)?;
+            Ok(String::from_utf8_lossy(&out).into_owned())
+        };
+
+        fs::write(root.join("kept.txt"), "kept\n")?;
+        fs::write(root.join("edited.txt"), "one\n")?;
+        add_file(&mut repo, Path::new("kept.txt"))?;
+        add_file(&mut repo, Path::new("edited.txt"))?;
+        assert_eq!(short(&repo, &options)?, "## No commits yet on main\nA  edited.txt\nA  kept.txt\n");
+        commit(&repo, "Initial commit")?;
+
+        fs::write(root.join("edited.txt"), "two\n")?;
+        add_file(&mut repo, Path::new("edited.txt"))?;
+        fs::write(root.join("edited.txt"), "three\n")?;
+        fs::remove_file(root.join("kept.txt"))?;
+        fs::write(root.join("new file.txt"), "new\n")?;
+        assert_eq!(short(&repo, &options)?, "## main\nMM edited.txt\n D kept.txt\n?? new file.txt\n");
+
+        let options = StatusOptions { format: StatusFormat::Short, branch: false, null_terminated: true };
+        assert_eq!(short(&repo, &options)?, "MM edited.txt\0 D kept.txt\0?? new file.txt\0");
+        assert_eq!(StatusFormat::from_porcelain("v1")?, StatusFormat::PorcelainV1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_format_lists_staged_and_unstaged_changes() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+        let long = |repo: &Repository| -> io::Result<String> {
+            let mut out = Vec::new();
+            write_long(&collect(repo)?, &mut out)?;
+            Ok(String::from_utf8_lossy(&out).into_owned())
+        };
+
+        // Before the first commit everything in the index is new
+        fs::write(root.join("edited.txt"), "one\n")?;
+        fs::write(root.join("gone.txt"), "gone\n")?;
+        add_file(&mut repo, Path::new("edited.txt"))?;
+        add_file(&mut repo, Path::new("gone.txt"))?;
+        let output = long(&repo)?;
+        assert!(output.starts_with("On branch main\n\nNo commits yet\n\nChanges to be committed:\n"), "{}", output);
+        assert!(output.contains("\tnew file:   edited.txt\n\tnew file:   gone.txt\n"), "{}", output);
+        commit(&repo, "Initial commit")?;
+        assert_eq!(long(&repo)?, "On branch main\nnothing to commit, working tree clean\n");
+
+        // A file with staged and unstaged edits shows up in both sections
+        fs::write(root.join("edited.txt"), "two\n")?;
+        add_file(&mut repo, Path::new("edited.txt"))?;
+        fs::write(root.join("edited.txt"), "three\n")?;
+        repo.index.remove_entry(Path::new("gone.txt"));
+        repo.save_index()?;
+        fs::remove_file(root.join("gone.txt"))?;
+        fs::write(root.join("added.txt"), "new\n")?;
+        add_file(&mut repo, Path::new("added.txt"))?;
+
+        let output = long(&repo)?;
+        let staged = "On branch main\n\
+                      Changes to be committed:\n\
+                      \x20 (use \"cobra restore --staged <file>...\" to unstage)\n\
+                      \tnew file:   added.txt\n\
+                      \tmodified:   edited.txt\n\
+                      \tdeleted:    gone.txt\n\n";
+        assert!(output.starts_with(staged), "{}", output);
+        assert!(output[staged.len()..].starts_with("Changes not staged for commit:\n"), "{}", output);
+        assert!(output.ends_with("\tmodified:   edited.txt\n\n"), "{}", output);
+
+        // A detached HEAD is named by its abbreviated commit
+        let head = repo.ref_store().resolve_commit("HEAD")?;
+        repo.ref_store().update_head(head, "test")?;
+        let report = collect(&repo)?;
+        assert_eq!(report.branch, None);
+        let mut out = Vec::new();
+        write_long(&report, &mut out)?;
+        let header = format!("HEAD detached at {}\nChanges to be committed:\n", repo.short_id(head));
+        assert!(String::from_utf8_lossy(&out).starts_with(&header), "{}", String::from_utf8_lossy(&out));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deleted_files_are_reported() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+        fs::write(root.join("kept.txt"), "kept\n")?;
+        fs::write(root.join("removed.txt"), "removed\n")?;
+        add_file(&mut repo, Path::new("kept.txt"))?;
+        add_file(&mut repo, Path::new("removed.txt"))?;
+        commit(&repo, "Initial commit")?;
+
+        fs::remove_file(root.join("removed.txt"))?;
+        let report = collect(&repo)?;
+        assert_eq!(report.deleted().collect::<Vec<_>>(), vec![Path::new("removed.txt")]);
+        assert!(report.modified().next().is_none());
+
+        let mut long = Vec::new();
+        write_long(&report, &mut long)?;
+        let long = String::from_utf8_lossy(&long);
+        assert!(long.starts_with("On branch main\nChanges not staged for commit:\n"), "{}", long);
+        assert!(long.contains("\tdeleted:    removed.txt\n"), "{}", long);
+        assert!(!long.contains("working tree clean"), "{}", long);
+
+        let mut short = Vec::new();
+        write_short(&report, &StatusOptions::default(), &mut short)?;
+        assert_eq!(String::from_utf8_lossy(&short), " D removed.txt\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_refreshes_stat_data_of_unchanged_files() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+        for name in ["old.txt", "recent.txt", "edited.txt"] {
+            fs::write(root.join(name), format!("{}\n", name))?;
+            add_file(&mut repo, Path::new(name))?;
+        }
+        commit(&repo, "Initial commit")?;
+
+        // Touched long ago, just now, and rewritten, so all three get hashed
+        let set_mtime = |name: &str, mtime: SystemTime| {
+            fs::File::options().write(true).open(root.join(name))?.set_modified(mtime)
+        };
+        set_mtime("old.txt", SystemTime::now() - Duration::from_secs(100))?;
+        set_mtime("recent.txt", SystemTime::now() + Duration::from_secs(5))?;
+        fs::write(root.join("edited.txt"), "other content\n")?;
+        set_mtime("edited.txt", SystemTime::now() - Duration::from_secs(100))?;
+        let stale: Vec<_> = collect(&repo)?.stale.into_iter().map(|file| file.path).collect();
+        assert_eq!(stale, vec![PathBuf::from("old.txt"), PathBuf::from("recent.txt")]);
+
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        run(&mut ctx, &StatusOptions::default())?;
+        assert!(!ctx.repo.git_dir.join("index.lock").exists());
+
+        // Only the file that cannot have been rewritten unnoticed is trusted
+        // from now on, and the modified one keeps its staged hash
+        let reopened = Repository::open(root.to_str().unwrap())?;
+        let report = collect(&reopened)?;
+        let stale: Vec<_> = report.stale.iter().map(|file| file.path.as_path()).collect();
+        assert_eq!(stale, vec![Path::new("recent.txt")]);
+        assert_eq!(report.modified().collect::<Vec<_>>(), vec![Path::new("edited.txt")]);
+        assert_eq!(
+            reopened.index.get_entry(Path::new("edited.txt")).unwrap().hash,
+            Object::new_blob(b"edited.txt\n".to_vec()).hash(),
+        );
+
+        // A failure only costs the refresh, never the status itself
+        set_mtime("recent.txt", SystemTime::now() - Duration::from_secs(100))?;
+        let mut repo = Repository::open(root.to_str().unwrap())?;
+        let stale = collect(&repo)?.stale;
+        fs::write(repo.git_dir.join("index"), "garbage")?;
+        assert!(refresh_stat_data(&mut repo, &stale).is_empty());
+        fs::write(repo.git_dir.join("index.lock"), "")?;
+        assert!(refresh_stat_data(&mut repo, &stale).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_porcelain_v2_initial_and_detached() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let options = StatusOptions { format: StatusFormat::PorcelainV2, branch: true, null_terminated: false };
+
+        let mut out = Vec::new();
+        write_porcelain_v2(&collect(&repo)?, &options, &mut out)?;
+        assert_eq!(String::from_utf8_lossy(&out), "# branch.oid (initial)\n# branch.head main\n");
+
+        let head = commit(&repo, "Initial commit")?;
+        repo.ref_store().update_head(head, "test")?;
+        let mut out = Vec::new();
+        write_porcelain_v2(&collect(&repo)?, &options, &mut out)?;
+        assert_eq!(String::from_utf8_lossy(&out), format!("# branch.oid {}\n# branch.head (detached)\n", head));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+// Tag creation, deletion and listing
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use crate::cobra::core::{
+    ancestry::is_ancestor,
+    object::Object,
+};
+use crate::cobra::state::CommandContext;
+use crate::cobra::utils::{glob::glob_match, natsort::compare_versions};
+use crate::cobra::messages;
+
+/// Ordering of listed tags (--sort)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TagSort {
+    /// Compare numeric parts by value (`version:refname`)
+    pub version: bool,
+    /// Reverse the order (a leading `-`)
+    pub reverse: bool,
+}
+
+impl TagSort {
+    /// Parses `refname`, `version:refname` or `v:refname`, optionally prefixed with `-`
+    pub fn parse(key: &str) -> io::Result<TagSort> {
+        let (reverse, key) = match key.strip_prefix('-') {
+            Some(key) => (true, key),
+            None => (false, key),
+        };
+        let version = match key {
+            "refname" => false,
+            "version:refname" | "v:refname" => true,
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported sort key '{}'", key),
+            )),
+        };
+        Ok(TagSort { version, reverse })
+    }
+
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        let ordering = if self.version { compare_versions(a, b) } else { a.cmp(b) };
+        if self.reverse { ordering.reverse() } else { ordering }
+    }
+}
+
+/// Tags the current HEAD commit
+pub fn create(ctx: &mut CommandContext, name: &str) -> io::Result<()> {
+    ctx.refs.create_tag(name)
+}
+
+pub fn delete(ctx: &mut CommandContext, name: &str) -> io::Result<()> {
+    let target = ctx.refs.delete_tag(name)?;
+    writeln!(ctx.out, "{}", messages::tag_deleted(name, &target.map(|target| target.abbrev(7)).unwrap_or_default()))
+}
+
+#[derive(Debug, Default)]
+pub struct TagListOptions {
+    /// Only list tags matching one of these globs
+    pub patterns: Vec<String>,
+    pub sort: TagSort,
+    /// Only list tags whose history contains this commit (--contains)
+    pub contains: Option<String>,
+    /// Print up to this many lines of each tag's message (-n)
+    pub lines: Option<usize>,
+}
+
+pub fn list(ctx: &mut CommandContext, options: &TagListOptions) -> io::Result<()> {
+    let contains = match &options.contains {
+        Some(spec) => Some(ctx.refs.resolve_commit(spec)?),
+        None => None,
+    };
+
+    let mut tags = Vec::new();
+    for (name, hash) in ctx.refs.list_tags()? {
+        if !options.patterns.is_empty() && !options.patterns.iter().any(|pattern| glob_match(pattern, &name)) {
+            continue;
+        }
+        if let Some(commit) = contains {
+            match hash {
+                Some(hash) if is_ancestor(&ctx.repo, commit, hash)? => {}
+                _ => continue,
+            }
+        }
+        tags.push((name, hash));
+    }
+    tags.sort_by(|(a, _), (b, _)| options.sort.compare(a, b));
+
+    for (name, hash) in tags {
+        match options.lines {
+            None => writeln!(ctx.out, "{}", name)?,
+            Some(count) => {
+                // Tags are lightweight, so the message is the tagged commit's
+                let message = match hash.map(|hash| Object::read_from_objects_dir(&ctx.repo.git_dir, hash)).transpose()? {
+                    Some(Object::Commit { message, .. }) => message,
+                    _ => String::new(),
+                };
+                let mut lines = message.lines().take(count);
+                writeln!(ctx.out, "{:<15} {}", name, lines.next().unwrap_or(""))?;
+                for line in lines {
+                    writeln!(ctx.out, "{}", format!("    {}", line).trim_end())?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::state::{GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_tag_list_filters_and_sorts() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let first = commit(&repo, "First release\nWith notes\nMore notes")?;
+        let second = commit(&repo, "Second release")?;
+        let refs = repo.ref_store();
+        refs.update_ref("refs/tags/v1.2.9", first, "test")?;
+        refs.update_ref("refs/tags/v1.2.10", second, "test")?;
+        refs.update_ref("refs/tags/v2.0", second, "test")?;
+
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let options = TagListOptions {
+            patterns: vec!["v1.*".to_string()],
+            sort: TagSort::parse("version:refname")?,
+            ..TagListOptions::default()
+        };
+        list(&mut ctx, &options)?;
+        assert_eq!(ctx.out.captured(), "v1.2.9\nv1.2.10\n");
+
+        let mut ctx = CommandContext::new(ctx.repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let options = TagListOptions {
+            contains: Some(second.to_string()),
+            sort: TagSort::parse("-v:refname")?,
+            ..TagListOptions::default()
+        };
+        list(&mut ctx, &options)?;
+        assert_eq!(ctx.out.captured(), "v2.0\nv1.2.10\n");
+
+        let mut ctx = CommandContext::new(ctx.repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let options = TagListOptions {
+            patterns: vec!["v1.2.9".to_string()],
+            lines: Some(2),
+            ..TagListOptions::default()
+        };
+        list(&mut ctx, &options)?;
+        assert_eq!(ctx.out.captured(), "v1.2.9          First release\n    With notes\n");
+
+        assert!(TagSort::parse("creatordate").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+// Report index entries whose stat data no longer matches the work tree
+use std::io::{self, Write};
+use crate::cobra::core::verify_index::{realign, verify};
+use crate::cobra::state::CommandContext;
+use crate::cobra::messages::{self, machine};
+
+/// `realign` refreshes the stat data of entries whose content still matches
+pub fn run(ctx: &mut CommandContext, realign_entries: bool) -> io::Result<()> {
+    let report = verify(&ctx.repo)?;
+    if !report.wholesale.is_empty() {
+        ctx.out.note(format_args!("{}", messages::index_foreign(&report.wholesale.join(", "), report.present)))?;
+    }
+    for stale in &report.stale {
+        writeln!(ctx.out, "{} {} ({})", machine::STALE, stale.path.display(), stale.fields.join(", "))?;
+    }
+    for path in &report.modified {
+        writeln!(ctx.out, "{} {}", machine::MODIFIED, path.display())?;
+    }
+    for path in &report.missing {
+        writeln!(ctx.out, "{} {}", machine::MISSING, path.display())?;
+    }
+
+    if realign_entries && !report.stale.is_empty() {
+        let refreshed = realign(&mut ctx.repo)?;
+        ctx.out.note(format_args!("{}", messages::index_refreshed(refreshed.len())))?;
+    }
+    Ok(())
+}
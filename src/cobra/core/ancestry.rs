@@ -0,0 +1,162 @@
+// Commit ancestry queries
+
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::repository::Repository;
+
+/// Returns true if `ancestor` is `descendant` itself or is reachable from it
+/// through any parent links. Anything that is not a commit, such as a tag
+/// pointing at a tree, has no history and contains only itself.
+pub fn is_ancestor(repo: &Repository, ancestor: Oid, descendant: Oid) -> io::Result<bool> {
+    let mut pending = vec![descendant];
+    let mut seen = HashSet::new();
+
+    while let Some(oid) = pending.pop() {
+        if oid == ancestor {
+            return Ok(true);
+        }
+        if !seen.insert(oid) {
+            continue;
+        }
+        pending.extend(parent_oids(&repo.git_dir, oid)?);
+    }
+    Ok(false)
+}
+
+/// Returns every commit reachable from `tip`, including `tip` itself
+pub fn ancestors(repo: &Repository, tip: Oid) -> io::Result<HashSet<Oid>> {
+    ancestors_in(&repo.git_dir, tip)
+}
+
+/// Like `ancestors`, reading the commits straight from `git_dir`
+pub fn ancestors_in(git_dir: &Path, tip: Oid) -> io::Result<HashSet<Oid>> {
+    let mut pending = vec![tip];
+    let mut seen = HashSet::new();
+
+    while let Some(oid) = pending.pop() {
+        if !seen.insert(oid) {
+            continue;
+        }
+        pending.extend(parent_oids(git_dir, oid)?);
+    }
+    Ok(seen)
+}
+
+/// Returns the best common ancestor of `a` and `b`, the one no other common
+/// ancestor descends from, or None when the histories share no commit.
+/// Criss-cross histories have several; the lowest hash is picked so the
+/// answer is at least stable.
+pub fn merge_base(repo: &Repository, a: Oid, b: Oid) -> io::Result<Option<Oid>> {
+    merge_base_in(&repo.git_dir, a, b)
+}
+
+/// Like `merge_base`, reading the commits straight from `git_dir`
+pub fn merge_base_in(git_dir: &Path, a: Oid, b: Oid) -> io::Result<Option<Oid>> {
+    let b_ancestors = ancestors_in(git_dir, b)?;
+    let common: HashSet<Oid> = ancestors_in(git_dir, a)?
+        .into_iter()
+        .filter(|oid| b_ancestors.contains(oid))
+        .collect();
+
+    // Everything behind a common ancestor is an older, worse base
+    let mut pending = Vec::new();
+    for &oid in &common {
+        pending.extend(parent_oids(git_dir, oid)?);
+    }
+    let mut older = HashSet::new();
+    while let Some(oid) = pending.pop() {
+        if older.insert(oid) {
+            pending.extend(parent_oids(git_dir, oid)?);
+        }
+    }
+    Ok(common.difference(&older).min().copied())
+}
+
+/// The parents of a commit; anything else has none
+fn parent_oids(git_dir: &Path, oid: Oid) -> io::Result<Vec<Oid>> {
+    match Object::read_from_objects_dir(git_dir, oid)? {
+        Object::Commit { parents, .. } => Ok(parents),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Counts the commits reachable from `tip` but not from `base` (ahead) and
+/// the other way round (behind)
+pub fn ahead_behind(repo: &Repository, tip: Oid, base: Oid) -> io::Result<(usize, usize)> {
+    let tip_ancestors = ancestors(repo, tip)?;
+    let base_ancestors = ancestors(repo, base)?;
+    Ok((
+        tip_ancestors.difference(&base_ancestors).count(),
+        base_ancestors.difference(&tip_ancestors).count(),
+    ))
+}
+
+/// Lists the commits reachable from `tip` but not from `base`, parents
+/// before their children (oldest first along each line of history)
+pub fn commits_between(repo: &Repository, base: Oid, tip: Oid) -> io::Result<Vec<Oid>> {
+    let excluded = ancestors(repo, base)?;
+    let mut ordered = Vec::new();
+    let mut seen = HashSet::new();
+    // (commit, whether its parents have been pushed already)
+    let mut pending = vec![(tip, false)];
+
+    while let Some((oid, expanded)) = pending.pop() {
+        if expanded {
+            ordered.push(oid);
+            continue;
+        }
+        if excluded.contains(&oid) || !seen.insert(oid) {
+            continue;
+        }
+        pending.push((oid, true));
+        pending.extend(parent_oids(&repo.git_dir, oid)?.into_iter().rev().map(|parent| (parent, false)));
+    }
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::test_support::temp_repo;
+    use std::fs;
+
+    #[test]
+    fn test_is_ancestor() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let first = commit(&repo, "First")?;
+        let second = commit(&repo, "Second")?;
+
+        assert!(is_ancestor(&repo, first, second)?);
+        assert!(is_ancestor(&repo, second, second)?);
+        assert!(!is_ancestor(&repo, second, first)?);
+        assert_eq!(ancestors(&repo, second)?.len(), 2);
+        assert_eq!(ahead_behind(&repo, second, first)?, (1, 0));
+        assert_eq!(ahead_behind(&repo, first, second)?, (0, 1));
+
+        let third = commit(&repo, "Third")?;
+        assert_eq!(commits_between(&repo, first, third)?, vec![second, third]);
+        assert!(commits_between(&repo, third, first)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_base() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let first = commit(&repo, "First")?;
+        let second = commit(&repo, "Second")?;
+        let third = commit(&repo, "Third")?;
+        assert_eq!(merge_base(&repo, third, second)?, Some(second));
+
+        // A side line starting at the second commit meets the main line there
+        fs::write(repo.git_dir.join("refs/heads/main"), format!("{}\n", second))?;
+        let side = commit(&repo, "Side")?;
+        assert_eq!(merge_base(&repo, third, side)?, Some(second));
+        assert_eq!(merge_base(&repo, side, first)?, Some(first));
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+// Path attributes and line-ending conversion
+//
+// Attributes come from `.cobraattributes` at the top of the work tree and
+// `.cobra/info/attributes`, the latter taking precedence. Each line is a
+// pattern followed by attributes: `name` sets one, `-name` unsets it,
+// `!name` makes it unspecified again and `name=value` gives it a value.
+// Patterns without a slash match the file name at any depth; the others
+// match the whole path. When several lines match, the last one wins.
+//
+// Only `text` and `eol` mean anything so far. A file that is text has its
+// CRLF line endings turned into LF when it is added; `text=auto` does so
+// only for files that do not look binary. Files are written to the work
+// tree as they are stored.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::utils::glob::glob_match;
+
+/// Name of the attributes file in the work tree
+pub const ATTRIBUTES_FILE: &str = ".cobraattributes";
+
+/// How many bytes of a file are inspected to classify its line endings
+pub const EOL_SCAN_LIMIT: usize = 1 << 20;
+
+/// Like git, a NUL byte this early makes a file binary
+const BINARY_SCAN_LIMIT: usize = 8000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttrValue {
+    Set,
+    Unset,
+    Value(String),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    attrs: Vec<(String, Option<AttrValue>)>,
+}
+
+impl Rule {
+    fn matches(&self, path: &str) -> bool {
+        let pattern = self.pattern.trim_start_matches('/');
+        if self.pattern.contains('/') {
+            glob_match(pattern, path)
+        } else {
+            glob_match(pattern, path.rsplit('/').next().unwrap_or(path))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Attributes {
+    rules: Vec<Rule>,
+}
+
+impl Attributes {
+    /// Reads the repository's attribute files; missing files add nothing
+    pub fn load(repo: &Repository) -> io::Result<Attributes> {
+        let mut attributes = Attributes::default();
+        for path in [repo.root_path.join(ATTRIBUTES_FILE), repo.git_dir.join("info/attributes")] {
+            match fs::read_to_string(&path) {
+                Ok(text) => attributes.rules.extend(Attributes::parse(&text).rules),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(attributes)
+    }
+
+    pub fn parse(text: &str) -> Attributes {
+        let rules = text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let pattern = fields.next()?.to_string();
+                let attrs = fields.map(|field| {
+                    if let Some(name) = field.strip_prefix('-') {
+                        (name.to_string(), Some(AttrValue::Unset))
+                    } else if let Some(name) = field.strip_prefix('!') {
+                        (name.to_string(), None)
+                    } else if let Some((name, value)) = field.split_once('=') {
+                        (name.to_string(), Some(AttrValue::Value(value.to_string())))
+                    } else {
+                        (field.to_string(), Some(AttrValue::Set))
+                    }
+                }).collect();
+                Some(Rule { pattern, attrs })
+            })
+            .collect();
+        Attributes { rules }
+    }
+
+    /// The value of attribute `name` for `path`, None when unspecified
+    pub fn get(&self, path: &Path, name: &str) -> Option<AttrValue> {
+        let path = path.to_string_lossy();
+        self.rules.iter().rev()
+            .filter(|rule| rule.matches(&path))
+            .find_map(|rule| rule.attrs.iter().rev().find(|(attr, _)| attr == name))
+            .and_then(|(_, value)| value.clone())
+    }
+
+    /// The text and eol attributes for `path`
+    pub fn text_setting(&self, path: &Path) -> TextSetting {
+        let eol = match self.get(path, "eol") {
+            Some(AttrValue::Value(eol)) => Some(eol),
+            _ => None,
+        };
+        match self.get(path, "text") {
+            Some(AttrValue::Set) => TextSetting::Text { eol },
+            Some(AttrValue::Unset) => TextSetting::Binary,
+            Some(AttrValue::Value(value)) if value == "auto" => TextSetting::Auto { eol },
+            // An eol attribute alone makes the file text
+            _ if eol.is_some() => TextSetting::Text { eol },
+            _ => TextSetting::Unspecified,
+        }
+    }
+
+    /// The content to store for `path` given its work tree content
+    pub fn to_index(&self, path: &Path, content: Vec<u8>) -> Vec<u8> {
+        let convert = match self.text_setting(path) {
+            TextSetting::Text { .. } => true,
+            TextSetting::Auto { .. } => classify_bytes(&content) != LineEndings::Binary,
+            TextSetting::Binary | TextSetting::Unspecified => false,
+        };
+        if convert && content.windows(2).any(|pair| pair == b"\r\n") {
+            crlf_to_lf(&content)
+        } else {
+            content
+        }
+    }
+}
+
+/// What the text and eol attributes say about a path
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextSetting {
+    Unspecified,
+    /// `-text`
+    Binary,
+    /// `text`, or just `eol=...`
+    Text { eol: Option<String> },
+    /// `text=auto`
+    Auto { eol: Option<String> },
+}
+
+/// Shown the way `git ls-files --eol` shows the attribute column
+impl fmt::Display for TextSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (text, eol) = match self {
+            TextSetting::Unspecified => return Ok(()),
+            TextSetting::Binary => return write!(f, "-text"),
+            TextSetting::Text { eol } => ("text", eol),
+            TextSetting::Auto { eol } => ("text=auto", eol),
+        };
+        match eol {
+            Some(eol) if text == "text" => write!(f, "text eol={}", eol),
+            Some(eol) => write!(f, "{} eol={}", text, eol),
+            None => write!(f, "{}", text),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEndings {
+    /// No line breaks at all, including empty files
+    None,
+    Lf,
+    Crlf,
+    Mixed,
+    Binary,
+}
+
+impl fmt::Display for LineEndings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LineEndings::None => "none",
+            LineEndings::Lf => "lf",
+            LineEndings::Crlf => "crlf",
+            LineEndings::Mixed => "mixed",
+            LineEndings::Binary => "binary",
+        })
+    }
+}
+
+/// Classifies the line endings in the first EOL_SCAN_LIMIT bytes of
+/// `input`, reading it a block at a time
+pub fn classify(input: &mut dyn Read) -> io::Result<LineEndings> {
+    let mut buffer = [0u8; 8192];
+    let (mut lf, mut crlf) = (0usize, 0usize);
+    let mut previous = 0u8;
+    let mut scanned = 0;
+    while scanned < EOL_SCAN_LIMIT {
+        let read = match input.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read.min(EOL_SCAN_LIMIT - scanned),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for (i, &byte) in buffer[..read].iter().enumerate() {
+            match byte {
+                0 if scanned + i < BINARY_SCAN_LIMIT => return Ok(LineEndings::Binary),
+                b'\n' if previous == b'\r' => crlf += 1,
+                b'\n' => lf += 1,
+                _ => {}
+            }
+            previous = byte;
+        }
+        scanned += read;
+    }
+    Ok(match (lf, crlf) {
+        (0, 0) => LineEndings::None,
+        (_, 0) => LineEndings::Lf,
+        (0, _) => LineEndings::Crlf,
+        _ => LineEndings::Mixed,
+    })
+}
+
+pub fn classify_bytes(mut content: &[u8]) -> LineEndings {
+    // Reading from a slice cannot fail
+    classify(&mut content).unwrap_or(LineEndings::Binary)
+}
+
+fn crlf_to_lf(content: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(content.len());
+    for (i, &byte) in content.iter().enumerate() {
+        if byte == b'\r' && content.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        converted.push(byte);
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_line_endings() {
+        assert_eq!(classify_bytes(b""), LineEndings::None);
+        assert_eq!(classify_bytes(b"a\nb\n"), LineEndings::Lf);
+        assert_eq!(classify_bytes(b"a\r\nb\r\n"), LineEndings::Crlf);
+        assert_eq!(classify_bytes(b"a\r\nb\n"), LineEndings::Mixed);
+        assert_eq!(classify_bytes(b"a\0\r\n"), LineEndings::Binary);
+
+        // CR and LF split across blocks still count as one CRLF
+        let mut content = vec![b'x'; 8191];
+        content.extend_from_slice(b"\r\n");
+        assert_eq!(classify_bytes(&content), LineEndings::Crlf);
+
+        // Nothing past the limit is looked at
+        let mut content = vec![b'x'; EOL_SCAN_LIMIT];
+        content.extend_from_slice(b"\r\n");
+        assert_eq!(classify_bytes(&content), LineEndings::None);
+    }
+
+    #[test]
+    fn test_attribute_rules() {
+        let attributes = Attributes::parse(
+            "# comment\n* text=auto\n*.png -text\ndocs/*.txt text eol=crlf\nkeep.txt !text\n",
+        );
+        assert_eq!(attributes.text_setting(Path::new("src/main.rs")), TextSetting::Auto { eol: None });
+        assert_eq!(attributes.text_setting(Path::new("img/logo.png")), TextSetting::Binary);
+        assert_eq!(attributes.text_setting(Path::new("docs/a.txt")).to_string(), "text eol=crlf");
+        assert_eq!(attributes.text_setting(Path::new("keep.txt")), TextSetting::Unspecified);
+
+        assert_eq!(attributes.to_index(Path::new("a.c"), b"x\r\ny\n".to_vec()), b"x\ny\n");
+        assert_eq!(attributes.to_index(Path::new("a.png"), b"x\r\n".to_vec()), b"x\r\n");
+        assert_eq!(attributes.to_index(Path::new("a.bin"), b"\0x\r\n".to_vec()), b"\0x\r\n");
+    }
+}
@@ -0,0 +1,559 @@
+// Reachability bitmaps: cached closures of selected commits
+//
+// Finding everything reachable from a set of refs means parsing every commit
+// and tree in their history. `objects/info/bitmaps` caches that work. It
+// lists the loose objects present when it was written, sorted, as an object
+// index, and for selected commits (the ref tips, and every BITMAP_SPACING-th
+// commit below them) holds a bitmap over that index marking the commit's
+// whole closure. A closure query unions the bitmaps of the covered commits
+// it meets and only walks what they do not cover, such as commits made
+// since the file was written.
+//
+// A commit's closure never changes, so new objects cannot make a bitmap
+// wrong; only removing objects can. gc therefore deletes the file before it
+// prunes anything, and writes a fresh one afterwards when gc.writeBitmaps is
+// set. Readers that find no file, or one that fails its checksum, walk
+// everything as before.
+//
+// The file is "CBMP", a version (1), the object count and that many raw
+// 20-byte hashes in order, the bitmap count and for each bitmap the
+// commit's position in the object index, the length of the encoded bitmap
+// and the bitmap itself, then the SHA-1 of everything before. A bitmap is
+// encoded as a varint count of runs followed by varint run lengths,
+// alternating between clear and set bits and starting with clear ones.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use sha1::{Digest, Sha1};
+use crate::cobra::core::gc::loose_objects;
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::pack::GITLINK_MODE;
+use crate::cobra::utils::tempfiles::TempFile;
+use crate::cobra::utils::trace::{self, Stream};
+
+/// Location of the bitmap file in the git directory
+pub const BITMAP_FILE: &str = "objects/info/bitmaps";
+
+/// Besides the tips, one commit in this many gets a bitmap
+pub const BITMAP_SPACING: usize = 100;
+
+const SIGNATURE: &[u8; 4] = b"CBMP";
+const VERSION: u32 = 1;
+
+fn corrupt(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Corrupt reachability bitmaps: {}", what))
+}
+
+/// A set of positions in an object index
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    pub fn new() -> Bitmap {
+        Bitmap::default()
+    }
+
+    pub fn set(&mut self, bit: usize) {
+        let word = bit / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (bit % 64);
+    }
+
+    pub fn contains(&self, bit: usize) -> bool {
+        self.words.get(bit / 64).is_some_and(|word| word & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn union_with(&mut self, other: &Bitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    /// Number of positions set
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    /// The positions set, in increasing order
+    pub fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64).filter(move |bit| word & (1 << bit) != 0).map(move |bit| index * 64 + bit)
+        })
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut runs = Vec::new();
+        let mut position = 0;
+        let mut ones = self.ones().peekable();
+        while let Some(start) = ones.next() {
+            let mut end = start + 1;
+            while ones.peek() == Some(&end) {
+                ones.next();
+                end += 1;
+            }
+            runs.push(start - position);
+            runs.push(end - start);
+            position = end;
+        }
+        write_varint(out, runs.len());
+        for run in runs {
+            write_varint(out, run);
+        }
+    }
+
+    /// Decodes a bitmap whose positions must all be below `limit`
+    fn decode(data: &[u8], limit: usize) -> io::Result<Bitmap> {
+        let mut pos = 0;
+        let count = read_varint(data, &mut pos)?;
+        let mut bitmap = Bitmap::new();
+        let mut position = 0usize;
+        for run in 0..count {
+            let length = read_varint(data, &mut pos)?;
+            let end = position.checked_add(length).filter(|&end| end <= limit)
+                .ok_or_else(|| corrupt("bitmap runs past the object index"))?;
+            if run % 2 == 1 {
+                (position..end).for_each(|bit| bitmap.set(bit));
+            }
+            position = end;
+        }
+        if pos != data.len() {
+            return Err(corrupt("trailing bytes after a bitmap"));
+        }
+        Ok(bitmap)
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> io::Result<usize> {
+    let mut value = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos).ok_or_else(|| corrupt("truncated number"))?;
+        *pos += 1;
+        if shift > 56 {
+            return Err(corrupt("number too large"));
+        }
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+/// The bitmap file: an object index and the closures of selected commits
+#[derive(Debug, Default)]
+pub struct ReachabilityBitmaps {
+    /// Object hashes, sorted; a bitmap's positions index into this
+    objects: Vec<Oid>,
+    positions: HashMap<Oid, usize>,
+    commits: HashMap<Oid, Bitmap>,
+}
+
+impl ReachabilityBitmaps {
+    fn with_objects(objects: Vec<Oid>) -> ReachabilityBitmaps {
+        let positions = objects.iter().enumerate().map(|(position, &hash)| (hash, position)).collect();
+        ReachabilityBitmaps { objects, positions, commits: HashMap::new() }
+    }
+
+    /// Reads the repository's bitmap file, or None if there is none
+    pub fn load(git_dir: &Path) -> io::Result<Option<ReachabilityBitmaps>> {
+        match fs::read(git_dir.join(BITMAP_FILE)) {
+            Ok(data) => ReachabilityBitmaps::parse(&data).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like `load`, but a file that cannot be used is only reported in the
+    /// trace: callers then walk everything, as without bitmaps
+    pub fn open(git_dir: &Path) -> Option<ReachabilityBitmaps> {
+        match ReachabilityBitmaps::load(git_dir) {
+            Ok(bitmaps) => bitmaps,
+            Err(e) => {
+                trace::event(Stream::Main, || format!("ignoring reachability bitmaps: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Deletes the bitmap file, as anything that removes objects must
+    pub fn remove(git_dir: &Path) -> io::Result<()> {
+        match fs::remove_file(git_dir.join(BITMAP_FILE)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces the bitmap file
+    pub fn write(&self, git_dir: &Path) -> io::Result<()> {
+        let path = git_dir.join(BITMAP_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let staged = TempFile::new(git_dir, "bitmaps")?;
+        fs::write(staged.path(), self.to_bytes())?;
+        staged.persist(&path)
+    }
+
+    /// Number of commits with a bitmap
+    pub fn commit_count(&self) -> usize {
+        self.commits.len()
+    }
+
+    /// Number of objects in the object index
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Whether `commit`'s closure is cached
+    pub fn covers(&self, commit: Oid) -> bool {
+        self.commits.contains_key(&commit)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(SIGNATURE);
+        out.extend_from_slice(&VERSION.to_be_bytes());
+        out.extend_from_slice(&(self.objects.len() as u32).to_be_bytes());
+        for hash in &self.objects {
+            out.extend_from_slice(hash.as_bytes());
+        }
+        let mut commits: Vec<(usize, &Bitmap)> = self.commits.iter()
+            .filter_map(|(commit, bitmap)| Some((*self.positions.get(commit)?, bitmap)))
+            .collect();
+        commits.sort_by_key(|(position, _)| *position);
+        out.extend_from_slice(&(commits.len() as u32).to_be_bytes());
+        for (position, bitmap) in commits {
+            let mut encoded = Vec::new();
+            bitmap.encode(&mut encoded);
+            out.extend_from_slice(&(position as u32).to_be_bytes());
+            out.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+            out.extend(encoded);
+        }
+        let checksum = Sha1::digest(&out);
+        out.extend_from_slice(&checksum);
+        out
+    }
+
+    fn parse(data: &[u8]) -> io::Result<ReachabilityBitmaps> {
+        let body_len = data.len().checked_sub(20).ok_or_else(|| corrupt("truncated file"))?;
+        let (body, checksum) = data.split_at(body_len);
+        if Sha1::digest(body).as_slice() != checksum {
+            return Err(corrupt("checksum mismatch"));
+        }
+        if !body.starts_with(SIGNATURE) {
+            return Err(corrupt("bad signature"));
+        }
+        let mut pos = SIGNATURE.len();
+        let read_u32 = |pos: &mut usize| -> io::Result<usize> {
+            let bytes = body.get(*pos..*pos + 4).ok_or_else(|| corrupt("truncated file"))?;
+            *pos += 4;
+            Ok(u32::from_be_bytes(bytes.try_into().unwrap_or_default()) as usize)
+        };
+        let version = read_u32(&mut pos)?;
+        if version != VERSION as usize {
+            return Err(corrupt(&format!("unsupported version {}", version)));
+        }
+
+        let object_count = read_u32(&mut pos)?;
+        let hashes = body.get(pos..pos + object_count * 20).ok_or_else(|| corrupt("truncated object index"))?;
+        pos += object_count * 20;
+        let objects = hashes.chunks(20).map(Oid::from_bytes).collect::<io::Result<_>>()?;
+        let mut bitmaps = ReachabilityBitmaps::with_objects(objects);
+
+        let commit_count = read_u32(&mut pos)?;
+        for _ in 0..commit_count {
+            let position = read_u32(&mut pos)?;
+            let length = read_u32(&mut pos)?;
+            let commit = bitmaps.objects.get(position).copied().ok_or_else(|| corrupt("commit outside the object index"))?;
+            let encoded = body.get(pos..pos + length).ok_or_else(|| corrupt("truncated bitmap"))?;
+            pos += length;
+            bitmaps.commits.insert(commit, Bitmap::decode(encoded, object_count)?);
+        }
+        if pos != body.len() {
+            return Err(corrupt("trailing bytes"));
+        }
+        Ok(bitmaps)
+    }
+
+    /// Maps a bitmap over another object index onto this one, or None if
+    /// one of its objects is no longer in this index
+    fn translate(&self, from: &ReachabilityBitmaps, bitmap: &Bitmap) -> Option<Bitmap> {
+        let mut translated = Bitmap::new();
+        for position in bitmap.ones() {
+            translated.set(*self.positions.get(&from.objects[position])?);
+        }
+        Some(translated)
+    }
+}
+
+/// Everything reachable from some roots, and what it took to find out
+#[derive(Debug, Default)]
+pub struct Closure {
+    pub objects: HashSet<Oid>,
+    /// Objects read from the store and parsed
+    pub objects_read: usize,
+    /// How many of those were trees
+    pub trees_parsed: usize,
+    /// Cached closures unioned in instead of walking
+    pub bitmaps_used: usize,
+}
+
+/// Collects every object reachable from `roots`, walking only what
+/// `bitmaps` do not cover. Missing objects, such as a ref pointing nowhere,
+/// are skipped.
+pub fn reachable_from(git_dir: &Path, roots: &[Oid], bitmaps: Option<&ReachabilityBitmaps>) -> io::Result<Closure> {
+    let mut closure = Closure::default();
+    let mut covered = Bitmap::new();
+    let mut pending: Vec<Oid> = roots.iter().rev().copied().collect();
+    while let Some(hash) = pending.pop() {
+        if hash.is_null() || closure.objects.contains(&hash) {
+            continue;
+        }
+        if let Some(bitmaps) = bitmaps {
+            if bitmaps.positions.get(&hash).is_some_and(|&position| covered.contains(position)) {
+                continue;
+            }
+            if let Some(bitmap) = bitmaps.commits.get(&hash) {
+                covered.union_with(bitmap);
+                closure.bitmaps_used += 1;
+                continue;
+            }
+        }
+        let object = match Object::read_from_objects_dir(git_dir, hash) {
+            Ok(object) => object,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        closure.objects_read += 1;
+        match object {
+            Object::Commit { tree, parents, .. } => {
+                pending.extend(parents);
+                pending.push(tree);
+            }
+            Object::Tree(entries) => {
+                closure.trees_parsed += 1;
+                pending.extend(entries.into_iter()
+                    .filter(|entry| entry.mode != GITLINK_MODE)
+                    .map(|entry| entry.hash));
+            }
+            Object::Blob(_) => {}
+        }
+        closure.objects.insert(hash);
+    }
+    if let Some(bitmaps) = bitmaps {
+        closure.objects.extend(covered.ones().map(|position| bitmaps.objects[position]));
+    }
+    Ok(closure)
+}
+
+/// Computes bitmaps over the loose objects now in the store for `tips`
+/// and every BITMAP_SPACING-th commit of their history. Ancestors come
+/// first, so each closure only walks down to the previous bitmap; those
+/// already in `previous` are carried over without walking at all.
+pub fn generate(git_dir: &Path, tips: &[Oid], previous: Option<&ReachabilityBitmaps>) -> io::Result<ReachabilityBitmaps> {
+    let mut bitmaps = ReachabilityBitmaps::with_objects(loose_objects(git_dir)?);
+    for commit in select_commits(git_dir, tips)? {
+        if bitmaps.covers(commit) || !bitmaps.positions.contains_key(&commit) {
+            continue;
+        }
+        let carried = previous.and_then(|previous| {
+            bitmaps.translate(previous, previous.commits.get(&commit)?)
+        });
+        let bitmap = match carried {
+            Some(bitmap) => bitmap,
+            None => {
+                let mut bitmap = Bitmap::new();
+                for hash in reachable_from(git_dir, std::slice::from_ref(&commit), Some(&bitmaps))?.objects {
+                    if let Some(&position) = bitmaps.positions.get(&hash) {
+                        bitmap.set(position);
+                    }
+                }
+                bitmap
+            }
+        };
+        bitmaps.commits.insert(commit, bitmap);
+    }
+    Ok(bitmaps)
+}
+
+/// The commits that get a bitmap, each after its ancestors
+fn select_commits(git_dir: &Path, tips: &[Oid]) -> io::Result<Vec<Oid>> {
+    let mut visited = HashSet::new();
+    let mut order = Vec::new();
+    let mut stack: Vec<(Oid, bool)> = tips.iter().rev().map(|&tip| (tip, false)).collect();
+    while let Some((hash, expanded)) = stack.pop() {
+        if expanded {
+            order.push(hash);
+            continue;
+        }
+        if !visited.insert(hash) {
+            continue;
+        }
+        let parents = match Object::read_from_objects_dir(git_dir, hash) {
+            Ok(Object::Commit { parents, .. }) => parents,
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        stack.push((hash, true));
+        stack.extend(parents.into_iter().rev()
+            .filter(|parent| !visited.contains(parent))
+            .map(|parent| (parent, false)));
+    }
+    Ok(order.into_iter().enumerate()
+        .filter(|(index, hash)| (index + 1) % BITMAP_SPACING == 0 || tips.contains(hash))
+        .map(|(_, hash)| hash)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::core::signature::Signature;
+    use crate::cobra::test_support::temp_repo;
+
+    /// `data` in zlib framing as stored deflate blocks, which skips the
+    /// encoder setup that dominates writing thousands of objects in a debug
+    /// build
+    fn stored_zlib(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x78, 0x01];
+        let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+        for (index, block) in blocks.iter().enumerate() {
+            out.push((index + 1 == blocks.len()) as u8);
+            let len = block.len() as u16;
+            out.extend(len.to_le_bytes());
+            out.extend((!len).to_le_bytes());
+            out.extend(*block);
+        }
+        let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+            let a = (a + byte as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        out.extend(((b << 16) | a).to_be_bytes());
+        out
+    }
+
+    /// A linear history of `count` commits, each with its own tree: a
+    /// directory every commit shares, and a file renamed every time. Returns
+    /// the commits, oldest first.
+    fn synthetic_history(git_dir: &Path, count: usize) -> io::Result<Vec<Oid>> {
+        // Written straight to their final place, uncompressed: the test is
+        // about walking, not writing
+        let write = |object: Object| -> io::Result<Oid> {
+            let hash = object.hash();
+            let path = hash.loose_path(git_dir);
+            fs::create_dir_all(path.parent().unwrap())?;
+            let mut loose = format!("{} {}\0", object.type_str(), object.len()).into_bytes();
+            loose.extend(object.serialize());
+            fs::write(path, stored_zlib(&loose))?;
+            Ok(hash)
+        };
+        let shared_blob = write(Object::new_blob(b"shared\n".to_vec()))?;
+        let shared_dir = write(Object::new_tree_from_entries(vec![("lib.rs".to_string(), 0o100644, shared_blob)]))?;
+        let mut signature = Signature::new("Tester".to_string(), "tester@example.com".to_string());
+        signature.timestamp = 1_700_000_000;
+        signature.timezone = "+0000".to_string();
+
+        let mut commits: Vec<Oid> = Vec::new();
+        for number in 0..count {
+            let tree = write(Object::new_tree_from_entries(vec![
+                ("lib".to_string(), 0o040000, shared_dir),
+                (format!("v{}.txt", number), 0o100644, shared_blob),
+            ]))?;
+            let parents = commits.last().cloned().into_iter().collect();
+            commits.push(write(Object::new_commit(tree, parents, signature.clone(), signature.clone(), format!("Commit {}\n", number)))?);
+        }
+        Ok(commits)
+    }
+
+    #[test]
+    fn test_bitmap_encoding_round_trips() -> io::Result<()> {
+        let mut bitmap = Bitmap::new();
+        for bit in [0, 1, 2, 63, 64, 65, 200, 1000] {
+            bitmap.set(bit);
+        }
+        let mut encoded = Vec::new();
+        bitmap.encode(&mut encoded);
+        assert_eq!(Bitmap::decode(&encoded, 1001)?, bitmap);
+        assert_eq!(bitmap.ones().collect::<Vec<_>>(), [0, 1, 2, 63, 64, 65, 200, 1000]);
+        assert!(Bitmap::decode(&encoded, 1000).is_err(), "positions must stay inside the index");
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitmaps_replace_the_walk() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let git_dir = &repo.git_dir;
+        let commits = synthetic_history(git_dir, 5000)?;
+        let tip = *commits.last().unwrap();
+
+        let walked = reachable_from(git_dir, std::slice::from_ref(&tip), None)?;
+        assert_eq!(walked.objects.len(), 2 * 5000 + 2);
+        assert_eq!(walked.trees_parsed, 5000 + 1);
+
+        generate(git_dir, std::slice::from_ref(&tip), None)?.write(git_dir)?;
+        let bitmaps = ReachabilityBitmaps::load(git_dir)?.expect("bitmaps were written");
+        assert_eq!(bitmaps.commit_count(), 5000 / BITMAP_SPACING);
+        assert!(bitmaps.covers(tip));
+
+        // The tip's closure is one bitmap: nothing is parsed
+        let cached = reachable_from(git_dir, std::slice::from_ref(&tip), Some(&bitmaps))?;
+        assert_eq!(cached.objects, walked.objects);
+        assert_eq!((cached.objects_read, cached.trees_parsed, cached.bitmaps_used), (0, 0, 1));
+
+        // A commit between bitmaps walks down to the nearest one
+        let middle = &commits[2549];
+        let expected = reachable_from(git_dir, std::slice::from_ref(middle), None)?;
+        let cached = reachable_from(git_dir, std::slice::from_ref(middle), Some(&bitmaps))?;
+        assert_eq!(cached.objects, expected.objects);
+        assert!(cached.trees_parsed < BITMAP_SPACING, "{} trees parsed", cached.trees_parsed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_damaged_file_falls_back_to_walking() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let commits = synthetic_history(&repo.git_dir, 3)?;
+        assert!(ReachabilityBitmaps::open(&repo.git_dir).is_none());
+
+        generate(&repo.git_dir, &commits[2..], None)?.write(&repo.git_dir)?;
+        let path = repo.git_dir.join(BITMAP_FILE);
+        let mut data = fs::read(&path)?;
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&path, data)?;
+        assert!(ReachabilityBitmaps::load(&repo.git_dir).is_err());
+        assert!(ReachabilityBitmaps::open(&repo.git_dir).is_none());
+
+        ReachabilityBitmaps::remove(&repo.git_dir)?;
+        assert!(!path.exists());
+        Ok(())
+    }
+}
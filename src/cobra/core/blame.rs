@@ -0,0 +1,486 @@
+// Line-by-line attribution of a file to the commits that introduced each line
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::cobra::core::diff::{split_lines, Algorithm, Edit};
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::core::similarity::{similarity, RENAME_THRESHOLD};
+use crate::cobra::core::tree::{commit_tree, diff_trees, flatten_tree, tree_entry};
+use crate::cobra::utils::trace::{self, Stream};
+
+/// A run of consecutive lines attributed to one commit. Line numbers are
+/// 0-based: `source_start` in the commit's version of the file,
+/// `final_start` in the blamed version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlameGroup {
+    pub commit: Oid,
+    pub path: PathBuf,
+    pub source_start: usize,
+    pub final_start: usize,
+    pub count: usize,
+}
+
+/// The complete attribution of a file
+#[derive(Debug)]
+pub struct Blame {
+    /// Content of the blamed version
+    pub content: Vec<u8>,
+    /// Groups ordered by their position in the blamed version
+    pub groups: Vec<BlameGroup>,
+}
+
+impl Blame {
+    /// The commit each line of the blamed version came from
+    pub fn line_commits(&self) -> Vec<Oid> {
+        self.groups.iter().flat_map(|group| std::iter::repeat_n(group.commit, group.count)).collect()
+    }
+}
+
+/// Where `blame` looks for the origin of lines a commit did not inherit
+/// unchanged from a parent's version of the same file
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BlameSettings {
+    pub algorithm: Algorithm,
+    /// Follow renames and find lines moved within the file (-M)
+    pub moves: bool,
+    /// Find lines copied or moved from other files (-C): 1 searches the
+    /// files the same commit changed, 2 or more every file of the parent
+    pub copies: usize,
+}
+
+/// Alphanumeric characters a run of lines must have before it counts as
+/// moved within a file, or copied from another one, as in git
+const MOVE_SCORE: usize = 20;
+const COPY_SCORE: usize = 40;
+
+/// Lines of the blamed file still looking for their origin, currently
+/// suspected to come from `path` in `commit`. Each pair is (line in this
+/// commit's version, line in the blamed version).
+struct Suspect {
+    commit: Oid,
+    path: PathBuf,
+    blob: Oid,
+    lines: Vec<(usize, usize)>,
+}
+
+/// Objects, blob lookups and line mappings computed during one blame run.
+/// Histories often bring the same pair of blobs together more than once, so
+/// each pair is only diffed the first time.
+#[derive(Default)]
+struct BlameCache {
+    algorithm: Algorithm,
+    commits: HashMap<Oid, Rc<Object>>,
+    blobs: HashMap<(Oid, PathBuf), Option<Oid>>,
+    contents: HashMap<Oid, Rc<Vec<u8>>>,
+    line_maps: HashMap<(Oid, Oid), Rc<Vec<Option<usize>>>>,
+    diffs_computed: usize,
+    diffs_reused: usize,
+}
+
+impl BlameCache {
+    fn commit(&mut self, repo: &Repository, hash: Oid) -> io::Result<Rc<Object>> {
+        if let Some(commit) = self.commits.get(&hash) {
+            return Ok(commit.clone());
+        }
+        let commit = Rc::new(Object::read_from_objects_dir(&repo.git_dir, hash)?);
+        self.commits.insert(hash, commit.clone());
+        Ok(commit)
+    }
+
+    /// The blob holding `path` in `commit`, if the file exists there
+    fn blob(&mut self, repo: &Repository, commit: Oid, path: &Path) -> io::Result<Option<Oid>> {
+        let key = (commit, path.to_path_buf());
+        if let Some(&blob) = self.blobs.get(&key) {
+            return Ok(blob);
+        }
+        let tree = commit_tree(&repo.git_dir, commit)?;
+        let blob = tree_entry(&repo.git_dir, tree, path)?
+            .filter(|entry| entry.mode != 0o040000)
+            .map(|entry| entry.hash);
+        self.blobs.insert(key, blob);
+        Ok(blob)
+    }
+
+    fn content(&mut self, repo: &Repository, blob: Oid) -> io::Result<Rc<Vec<u8>>> {
+        if let Some(content) = self.contents.get(&blob) {
+            return Ok(content.clone());
+        }
+        let content = Rc::new(Object::read_blob(&repo.git_dir, blob)?);
+        self.contents.insert(blob, content.clone());
+        Ok(content)
+    }
+
+    /// Maps each line of `new_blob` to the identical line of `old_blob`, if any
+    fn line_map(&mut self, repo: &Repository, old_blob: Oid, new_blob: Oid) -> io::Result<Rc<Vec<Option<usize>>>> {
+        let key = (old_blob, new_blob);
+        if let Some(map) = self.line_maps.get(&key) {
+            self.diffs_reused += 1;
+            return Ok(map.clone());
+        }
+
+        let old = self.content(repo, old_blob)?;
+        let new = self.content(repo, new_blob)?;
+        let new_lines = split_lines(&new);
+        let mut map = vec![None; new_lines.len()];
+        for edit in self.algorithm.diff_lines(&split_lines(&old), &new_lines) {
+            if let Edit::Equal { old, new } = edit {
+                map[new] = Some(old);
+            }
+        }
+
+        let map = Rc::new(map);
+        self.diffs_computed += 1;
+        self.line_maps.insert(key, map.clone());
+        Ok(map)
+    }
+}
+
+fn commit_time(commit: &Object) -> u64 {
+    match commit {
+        Object::Commit { committer, .. } => committer.timestamp,
+        _ => 0,
+    }
+}
+
+/// Attributes every line of `path` as of commit `start`. Lines are handed
+/// from a commit to its parents as long as the parents have them unchanged;
+/// whatever is left is blamed on the commit. Commits are visited newest
+/// first, and `emit` is called with each group as soon as its commit is
+/// finished, so callers can stream results.
+pub fn blame<F>(repo: &Repository, start: Oid, path: &Path, settings: &BlameSettings, mut emit: F) -> io::Result<Blame>
+where
+    F: FnMut(&BlameGroup, &Object) -> io::Result<()>,
+{
+    let mut cache = BlameCache { algorithm: settings.algorithm, ..BlameCache::default() };
+    let blob = cache.blob(repo, start, path)?.ok_or_else(|| io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no such path '{}' in {}", path.display(), start),
+    ))?;
+    let content = Object::read_blob(&repo.git_dir, blob)?;
+    let line_count = split_lines(&content).len();
+
+    let mut pending = vec![Suspect {
+        commit: start,
+        path: path.to_path_buf(),
+        blob,
+        lines: (0..line_count).map(|line| (line, line)).collect(),
+    }];
+    let mut groups = Vec::new();
+
+    while !pending.is_empty() {
+        // Newest first, so a commit has heard from all its children before it is processed
+        let mut newest = 0;
+        let mut newest_time = 0;
+        for (i, suspect) in pending.iter().enumerate() {
+            let time = commit_time(&*cache.commit(repo, suspect.commit)?);
+            if i == 0 || time > newest_time {
+                newest = i;
+                newest_time = time;
+            }
+        }
+        let suspect = pending.swap_remove(newest);
+        let commit = cache.commit(repo, suspect.commit)?;
+        let parents = match commit.as_ref() {
+            Object::Commit { parents, .. } => parents.clone(),
+            _ => Vec::new(),
+        };
+
+        let mut remaining = suspect.lines.clone();
+        for &parent in &parents {
+            if remaining.is_empty() {
+                break;
+            }
+            let parent_file = match cache.blob(repo, parent, &suspect.path)? {
+                Some(blob) => Some((suspect.path.clone(), blob)),
+                None if settings.moves || settings.copies > 0 => find_rename(repo, &mut cache, &suspect, parent)?,
+                None => None,
+            };
+            let Some((parent_path, parent_blob)) = parent_file else {
+                continue;
+            };
+
+            let mut passed = Vec::new();
+            if parent_blob == suspect.blob {
+                passed = std::mem::take(&mut remaining);
+            } else {
+                let map = cache.line_map(repo, parent_blob, suspect.blob)?;
+                remaining.retain(|&(source, result)| match map[source] {
+                    Some(old) => {
+                        passed.push((old, result));
+                        false
+                    }
+                    None => true,
+                });
+                if settings.moves || settings.copies > 0 {
+                    let ours = cache.content(repo, suspect.blob)?;
+                    let theirs = cache.content(repo, parent_blob)?;
+                    passed.extend(take_copied(&split_lines(&ours), &split_lines(&theirs), &mut remaining, MOVE_SCORE));
+                }
+            }
+            hand_over(&mut pending, parent, &parent_path, parent_blob, passed);
+        }
+
+        if settings.copies > 0 {
+            for &parent in &parents {
+                if remaining.is_empty() {
+                    break;
+                }
+                let ours = cache.content(repo, suspect.blob)?;
+                for (other_path, other_blob) in copy_sources(repo, &suspect, parent, settings.copies)? {
+                    let theirs = cache.content(repo, other_blob)?;
+                    let passed = take_copied(&split_lines(&ours), &split_lines(&theirs), &mut remaining, COPY_SCORE);
+                    hand_over(&mut pending, parent, &other_path, other_blob, passed);
+                }
+            }
+        }
+
+        remaining.sort_by_key(|&(_, result)| result);
+        for group in group_lines(suspect.commit, &suspect.path, &remaining) {
+            emit(&group, &commit)?;
+            groups.push(group);
+        }
+    }
+
+    trace::event(Stream::Main, || format!(
+        "blame {}: {} diffs computed, {} reused",
+        path.display(), cache.diffs_computed, cache.diffs_reused,
+    ));
+    groups.sort_by_key(|group| group.final_start);
+    Ok(Blame { content, groups })
+}
+
+/// Passes lines on to `path` in `parent`, joining a suspect already waiting there
+fn hand_over(pending: &mut Vec<Suspect>, parent: Oid, path: &Path, blob: Oid, lines: Vec<(usize, usize)>) {
+    if lines.is_empty() {
+        return;
+    }
+    match pending.iter_mut().find(|other| other.commit == parent && other.path == path) {
+        Some(other) => other.lines.extend(lines),
+        None => pending.push(Suspect {
+            commit: parent,
+            path: path.to_path_buf(),
+            blob,
+            lines,
+        }),
+    }
+}
+
+/// The file in `parent` that the suspect's file was renamed from: the most
+/// similar of the files the suspect's commit deleted
+fn find_rename(repo: &Repository, cache: &mut BlameCache, suspect: &Suspect, parent: Oid) -> io::Result<Option<(PathBuf, Oid)>> {
+    let parent_tree = commit_tree(&repo.git_dir, parent)?;
+    let tree = commit_tree(&repo.git_dir, suspect.commit)?;
+    let content = cache.content(repo, suspect.blob)?;
+
+    let mut best: Option<(usize, PathBuf, Oid)> = None;
+    for change in diff_trees(&repo.git_dir, Some(parent_tree), Some(tree))? {
+        let (Some(old), None) = (&change.old, &change.new) else {
+            continue;
+        };
+        let score = similarity(&cache.content(repo, old.hash)?, &content);
+        if score >= RENAME_THRESHOLD && best.as_ref().is_none_or(|(best_score, _, _)| score > *best_score) {
+            best = Some((score, change.path, old.hash));
+        }
+    }
+    Ok(best.map(|(_, path, blob)| (path, blob)))
+}
+
+/// Other files of `parent` that lines may have been copied from: the ones
+/// the suspect's commit changed or deleted, or at `level` 2 and above every
+/// file. Sorted by path, so results do not depend on the search order.
+fn copy_sources(repo: &Repository, suspect: &Suspect, parent: Oid, level: usize) -> io::Result<Vec<(PathBuf, Oid)>> {
+    let parent_tree = commit_tree(&repo.git_dir, parent)?;
+    let sources: Vec<(PathBuf, Oid)> = if level >= 2 {
+        flatten_tree(&repo.git_dir, parent_tree)?
+            .into_iter()
+            .filter(|(_, entry)| entry.mode != 0o040000)
+            .map(|(path, entry)| (path, entry.hash))
+            .collect()
+    } else {
+        let tree = commit_tree(&repo.git_dir, suspect.commit)?;
+        diff_trees(&repo.git_dir, Some(parent_tree), Some(tree))?
+            .into_iter()
+            .filter_map(|change| change.old.map(|old| (change.path, old.hash)))
+            .collect()
+    };
+    Ok(sources.into_iter().filter(|(path, _)| *path != suspect.path).collect())
+}
+
+/// Moves the `remaining` lines that also appear in `other` out of it and
+/// returns them as (line in `other`, final line). Only runs of consecutive
+/// lines worth at least `min_score` alphanumeric characters count, so that
+/// lone braces or blank lines are not taken for copies.
+fn take_copied(ours: &[&[u8]], other: &[&[u8]], remaining: &mut Vec<(usize, usize)>, min_score: usize) -> Vec<(usize, usize)> {
+    remaining.sort_unstable();
+    let mut found = Vec::new();
+    let mut taken = vec![false; remaining.len()];
+
+    let mut run_start = 0;
+    while run_start < remaining.len() {
+        // Lines consecutive in our version form a run that may have moved as a whole
+        let mut run_end = run_start + 1;
+        while run_end < remaining.len() && remaining[run_end].0 == remaining[run_end - 1].0 + 1 {
+            run_end += 1;
+        }
+
+        let mut i = run_start;
+        while i < run_end {
+            let (mut best_start, mut best_len) = (0, 0);
+            for j in 0..other.len() {
+                let len = (0..run_end - i)
+                    .take_while(|&k| j + k < other.len() && ours[remaining[i + k].0] == other[j + k])
+                    .count();
+                if len > best_len {
+                    (best_start, best_len) = (j, len);
+                }
+            }
+            let score: usize = (0..best_len)
+                .map(|k| ours[remaining[i + k].0].iter().filter(|b| b.is_ascii_alphanumeric()).count())
+                .sum();
+            if best_len > 0 && score >= min_score {
+                for k in 0..best_len {
+                    found.push((best_start + k, remaining[i + k].1));
+                    taken[i + k] = true;
+                }
+                i += best_len;
+            } else {
+                i += 1;
+            }
+        }
+        run_start = run_end;
+    }
+
+    let mut index = 0;
+    remaining.retain(|_| {
+        index += 1;
+        !taken[index - 1]
+    });
+    found
+}
+
+/// Splits lines sorted by their final position into runs that are
+/// consecutive on both sides
+fn group_lines(commit: Oid, path: &Path, lines: &[(usize, usize)]) -> Vec<BlameGroup> {
+    let mut groups: Vec<BlameGroup> = Vec::new();
+    for &(source, result) in lines {
+        if let Some(last) = groups.last_mut() {
+            if last.source_start + last.count == source && last.final_start + last.count == result {
+                last.count += 1;
+                continue;
+            }
+        }
+        groups.push(BlameGroup {
+            commit,
+            path: path.to_path_buf(),
+            source_start: source,
+            final_start: result,
+            count: 1,
+        });
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::core::index::Index;
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_blame_attributes_lines() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let file = temp_dir.path().join("file.txt");
+
+        fs::write(&file, "one\ntwo\nthree\n")?;
+        add_file(&mut repo, Path::new("file.txt"))?;
+        let first = commit(&repo, "First")?;
+
+        fs::write(&file, "one\n2\nthree\nfour\n")?;
+        add_file(&mut repo, Path::new("file.txt"))?;
+        let second = commit(&repo, "Second")?;
+
+        let mut emitted = Vec::new();
+        let blame = blame(&repo, second, Path::new("file.txt"), &BlameSettings::default(), |group, _| {
+            emitted.push(group.clone());
+            Ok(())
+        })?;
+
+        assert_eq!(blame.line_commits(), vec![first, second, first, second]);
+        // The newer commit is finished first
+        assert_eq!(emitted[0].commit, second);
+        assert_eq!(blame.groups[2], BlameGroup {
+            commit: first,
+            path: PathBuf::from("file.txt"),
+            source_start: 2,
+            final_start: 2,
+            count: 1,
+        });
+
+        assert!(super::blame(&repo, second, Path::new("missing.txt"), &BlameSettings::default(), |_, _| Ok(())).is_err());
+        Ok(())
+    }
+
+    const MOVED: &str = "fn scaled(value: usize) -> usize {\n    value * FACTOR + offset(value)\n}\n";
+
+    #[test]
+    fn test_copies_find_function_moved_between_files() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let (a, b) = (temp_dir.path().join("a.rs"), temp_dir.path().join("b.rs"));
+
+        fs::write(&a, format!("fn keep() {{}}\n\n{}", MOVED))?;
+        fs::write(&b, "fn other() {}\n")?;
+        add_file(&mut repo, Path::new("a.rs"))?;
+        add_file(&mut repo, Path::new("b.rs"))?;
+        let first = commit(&repo, "Add scaled to a.rs")?;
+
+        fs::write(&a, "fn keep() {}\n")?;
+        fs::write(&b, format!("fn other() {{}}\n\n{}", MOVED))?;
+        add_file(&mut repo, Path::new("a.rs"))?;
+        add_file(&mut repo, Path::new("b.rs"))?;
+        let second = commit(&repo, "Move scaled to b.rs")?;
+
+        let plain = blame(&repo, second, Path::new("b.rs"), &BlameSettings::default(), |_, _| Ok(()))?;
+        assert_eq!(plain.line_commits()[2..], [second; 3]);
+
+        let settings = BlameSettings { copies: 1, ..BlameSettings::default() };
+        let copied = blame(&repo, second, Path::new("b.rs"), &settings, |_, _| Ok(()))?;
+        assert_eq!(copied.line_commits()[2..], [first; 3]);
+        let group = copied.groups.iter().find(|group| group.final_start <= 2 && group.final_start + group.count > 2).unwrap();
+        assert_eq!(group.path, PathBuf::from("a.rs"));
+        assert_eq!(group.source_start + 2 - group.final_start, 2);
+        // The first line never moved
+        assert_eq!(copied.groups[0].path, PathBuf::from("b.rs"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_moves_follow_renames() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+
+        fs::write(temp_dir.path().join("old.rs"), MOVED)?;
+        add_file(&mut repo, Path::new("old.rs"))?;
+        let first = commit(&repo, "Add old.rs")?;
+
+        fs::remove_file(temp_dir.path().join("old.rs"))?;
+        fs::write(temp_dir.path().join("new.rs"), MOVED.replace("FACTOR", "2"))?;
+        repo.index = Index::new();
+        add_file(&mut repo, Path::new("new.rs"))?;
+        let second = commit(&repo, "Rename old.rs to new.rs")?;
+
+        let plain = blame(&repo, second, Path::new("new.rs"), &BlameSettings::default(), |_, _| Ok(()))?;
+        assert_eq!(plain.line_commits(), vec![second; 3]);
+
+        let settings = BlameSettings { moves: true, ..BlameSettings::default() };
+        let renamed = blame(&repo, second, Path::new("new.rs"), &settings, |_, _| Ok(()))?;
+        assert_eq!(renamed.line_commits(), vec![first, second, first]);
+        assert_eq!(renamed.groups[0].path, PathBuf::from("old.rs"));
+        assert_eq!(renamed.groups[1].path, PathBuf::from("new.rs"));
+        Ok(())
+    }
+}
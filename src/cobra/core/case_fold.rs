@@ -0,0 +1,179 @@
+// Path comparison on case-insensitive and Unicode-normalizing filesystems
+//
+// Trees and the index are always exact, but some filesystems treat
+// `README.md` and `ReadMe.md` (or a precomposed and a decomposed `é`) as the
+// same file. The repository probes its filesystem once and records the result
+// as core.ignoreCase and core.precomposeUnicode; PathFolding turns a path into
+// the key under which that filesystem considers two names equal.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::cobra::core::config::Config;
+use crate::cobra::utils::tempfiles::TempFile;
+
+/// Combining marks with the Latin-1 letters they compose with, and the result
+const COMPOSITIONS: &[(char, &str, &str)] = &[
+    ('\u{300}', "AEIOUaeiou", "ÀÈÌÒÙàèìòù"),
+    ('\u{301}', "AEIOUYaeiouy", "ÁÉÍÓÚÝáéíóúý"),
+    ('\u{302}', "AEIOUaeiou", "ÂÊÎÔÛâêîôû"),
+    ('\u{303}', "ANOano", "ÃÑÕãñõ"),
+    ('\u{308}', "AEIOUaeiouy", "ÄËÏÖÜäëïöüÿ"),
+    ('\u{30a}', "Aa", "Åå"),
+    ('\u{327}', "Cc", "Çç"),
+];
+
+/// How the filesystem folds names before comparing them
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PathFolding {
+    /// Names differing only in case refer to the same file
+    pub ignore_case: bool,
+    /// Precomposed and decomposed accented letters refer to the same file
+    pub precompose_unicode: bool,
+}
+
+impl PathFolding {
+    /// Byte-for-byte comparison, as on a typical Linux filesystem
+    pub const EXACT: PathFolding = PathFolding { ignore_case: false, precompose_unicode: false };
+
+    /// Reads core.ignoreCase and core.precomposeUnicode, or None if the
+    /// filesystem has not been probed yet
+    pub fn from_config(config: &Config) -> Option<PathFolding> {
+        Some(PathFolding {
+            ignore_case: config.get_bool("core.ignorecase")?,
+            precompose_unicode: config.get_bool("core.precomposeunicode").unwrap_or(false),
+        })
+    }
+
+    pub fn is_exact(&self) -> bool {
+        *self == PathFolding::EXACT
+    }
+
+    /// The key two paths share exactly when this filesystem treats them as one
+    pub fn key(&self, path: &Path) -> String {
+        let mut key = path.to_string_lossy().into_owned();
+        if self.precompose_unicode {
+            key = precompose(&key);
+        }
+        if self.ignore_case {
+            key = key.to_lowercase();
+        }
+        key
+    }
+
+    /// Groups of paths that this filesystem cannot store side by side,
+    /// each sorted, ordered by their first path
+    pub fn collisions<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) -> Vec<Vec<PathBuf>> {
+        if self.is_exact() {
+            return Vec::new();
+        }
+        let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for path in paths {
+            groups.entry(self.key(path)).or_default().push(path.to_path_buf());
+        }
+        let mut collisions: Vec<Vec<PathBuf>> = groups.into_values()
+            .filter(|group| group.len() > 1)
+            .map(|mut group| {
+                group.sort();
+                group
+            })
+            .collect();
+        collisions.sort();
+        collisions
+    }
+}
+
+/// Composes the accented Latin-1 letters that appear in decomposed form
+fn precompose(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        let composed = COMPOSITIONS.iter()
+            .find(|(mark, _, _)| *mark == c)
+            .and_then(|(_, bases, composed)| {
+                let base = result.chars().last()?;
+                let position = bases.chars().position(|b| b == base)?;
+                composed.chars().nth(position)
+            });
+        match composed {
+            Some(composed) => {
+                result.pop();
+                result.push(composed);
+            }
+            None => result.push(c),
+        }
+    }
+    result
+}
+
+/// Finds out how the filesystem holding `dir` compares names by creating a
+/// file there and looking it up under a different case and normalization
+pub fn probe(dir: &Path) -> io::Result<PathFolding> {
+    let probe = TempFile::in_dir(dir, "Probe\u{e9}")?.keep_on_failure(false);
+    let name = probe.path().file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let exists = |other: String| fs::symlink_metadata(dir.join(other)).is_ok();
+
+    let folding = PathFolding {
+        ignore_case: exists(name.to_lowercase()),
+        precompose_unicode: exists(name.replace('\u{e9}', "e\u{301}")),
+    };
+    probe.finish()?;
+    Ok(folding)
+}
+
+/// Formats git's warning about tree paths that collided in the work tree
+pub fn collision_warning(collisions: &[Vec<PathBuf>]) -> String {
+    let mut warning = String::from(
+        "warning: the following paths have collided (e.g. case-sensitive paths\n\
+         on a case-insensitive filesystem) and only one from the same\n\
+         colliding group is in the working tree:\n",
+    );
+    for path in collisions.iter().flatten() {
+        warning.push_str(&format!("\n  '{}'", path.display()));
+    }
+    warning
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_keys_and_collisions() {
+        let folding = PathFolding { ignore_case: true, precompose_unicode: true };
+        assert_eq!(folding.key(Path::new("Docs/README.md")), "docs/readme.md");
+        assert_eq!(folding.key(Path::new("cafe\u{301}.txt")), folding.key(Path::new("Caf\u{e9}.txt")));
+        assert_ne!(folding.key(Path::new("cafe.txt")), folding.key(Path::new("caf\u{e9}.txt")));
+
+        let paths = ["README.md", "src/main.rs", "ReadMe.md", "readme.md", "SRC/main.rs", "notes.txt"];
+        let paths: Vec<&Path> = paths.iter().map(Path::new).collect();
+        assert_eq!(folding.collisions(paths.iter().copied()), vec![
+            vec![PathBuf::from("README.md"), PathBuf::from("ReadMe.md"), PathBuf::from("readme.md")],
+            vec![PathBuf::from("SRC/main.rs"), PathBuf::from("src/main.rs")],
+        ]);
+        assert!(PathFolding::EXACT.collisions(paths.iter().copied()).is_empty());
+    }
+
+    #[test]
+    fn test_collision_warning() {
+        let warning = collision_warning(&[vec![PathBuf::from("README.md"), PathBuf::from("readme.md")]]);
+        assert!(warning.starts_with("warning: the following paths have collided"));
+        assert!(warning.ends_with("working tree:\n\n  'README.md'\n  'readme.md'"));
+    }
+
+    #[test]
+    fn test_probe_and_config() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        // Whatever the filesystem does, the probe cleans up after itself
+        probe(temp_dir.path())?;
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), 0);
+
+        assert_eq!(PathFolding::from_config(&Config::default()), None);
+        let config = Config::parse("[core]\n\tignoreCase = true\n")?;
+        assert_eq!(PathFolding::from_config(&config), Some(PathFolding { ignore_case: true, precompose_unicode: false }));
+        let config = Config::parse("[core]\n\tignoreCase = TRUE\n\tprecomposeUnicode = Yes\n")?;
+        assert_eq!(PathFolding::from_config(&config), Some(PathFolding { ignore_case: true, precompose_unicode: true }));
+        Ok(())
+    }
+}
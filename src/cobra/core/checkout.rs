@@ -0,0 +1,454 @@
+// Materializing trees into the working directory and index
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use crate::cobra::core::{
+    events::RepositoryEvent,
+    index::{normalize_mode, Index, IndexEntry},
+    object::{Object, TreeEntry},
+    oid::Oid,
+    repository::Repository,
+    tree::flatten_tree,
+};
+use crate::cobra::utils::progress::Progress;
+use walkdir::WalkDir;
+
+/// Options controlling how a checkout treats local modifications
+#[derive(Debug, Default, Clone)]
+pub struct CheckoutOptions {
+    /// Overwrite locally modified or deleted tracked files instead of refusing
+    pub force: bool,
+}
+
+/// Summary of the changes a checkout made to the working directory
+#[derive(Debug, Default)]
+pub struct CheckoutReport {
+    /// Paths written from the target tree
+    pub updated: Vec<PathBuf>,
+    /// Tracked paths removed because the target tree does not contain them
+    pub removed: Vec<PathBuf>,
+    /// Directories removed because the removals left them empty
+    pub pruned: Vec<PathBuf>,
+    /// Paths whose local changes were thrown away (only with `force`)
+    pub discarded: Vec<PathBuf>,
+    /// Groups of target paths the filesystem folds to the same name; only
+    /// the last written of each group survives in the working directory
+    pub collisions: Vec<Vec<PathBuf>>,
+}
+
+/// State of a tracked file in the working directory relative to its index entry
+#[derive(Debug, PartialEq)]
+enum LocalState {
+    Clean,
+    Modified,
+    Deleted,
+}
+
+fn local_state(repo: &Repository, entry: &IndexEntry) -> io::Result<LocalState> {
+    let full_path = repo.root_path.join(&entry.path);
+    let metadata = match fs::metadata(&full_path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(LocalState::Deleted),
+        Err(e) => return Err(e),
+    };
+
+    // Quick check: if mtime and size match, assume content is the same
+    if metadata.len() == entry.size && metadata.mtime() as u64 == entry.mtime {
+        return Ok(LocalState::Clean);
+    }
+
+    if worktree_hash(&full_path)? == entry.hash {
+        Ok(LocalState::Clean)
+    } else {
+        Ok(LocalState::Modified)
+    }
+}
+
+fn worktree_hash(full_path: &Path) -> io::Result<Oid> {
+    Ok(Object::new_blob(fs::read(full_path)?).hash())
+}
+
+/// Replaces the working directory and index contents with the given tree.
+///
+/// Tracked files that differ between the index and the target are rewritten,
+/// tracked files missing from the target are removed once the writes are done,
+/// along with the directories that leaves empty, and untracked files are
+/// never touched. Without `force`, local modifications to any path the checkout
+/// would change abort the operation before anything is written.
+pub fn checkout_tree(
+    repo: &mut Repository,
+    tree_hash: Oid,
+    options: &CheckoutOptions,
+    progress: &mut dyn Progress,
+) -> io::Result<CheckoutReport> {
+    let target = flatten_tree(&repo.git_dir, tree_hash)?;
+    let current: HashMap<PathBuf, IndexEntry> = repo.index.entries()
+        .map(|entry| (entry.path.clone(), entry.clone()))
+        .collect();
+
+    let mut to_write: Vec<(&PathBuf, &TreeEntry)> = Vec::new();
+    let mut to_remove: Vec<&PathBuf> = Vec::new();
+    let mut dirty = Vec::new();
+    let mut untracked = Vec::new();
+
+    for (path, entry) in &target {
+        match current.get(path) {
+            Some(index_entry) => {
+                let changed = index_entry.hash != entry.hash || index_entry.mode != entry.mode;
+                let state = local_state(repo, index_entry)?;
+                if state != LocalState::Clean {
+                    // Local edits that already match the target are not in the way
+                    let matches_target = state == LocalState::Modified
+                        && worktree_hash(&repo.root_path.join(path))? == entry.hash;
+                    if !matches_target && (changed || options.force) {
+                        dirty.push(path.clone());
+                        to_write.push((path, entry));
+                        continue;
+                    }
+                }
+                if changed {
+                    to_write.push((path, entry));
+                }
+            }
+            None => {
+                untracked.extend(in_the_way(repo, path, &current)?);
+                to_write.push((path, entry));
+            }
+        }
+    }
+
+    for (path, index_entry) in &current {
+        if !target.contains_key(path) {
+            if local_state(repo, index_entry)? == LocalState::Modified {
+                dirty.push(path.clone());
+            }
+            to_remove.push(path);
+        }
+    }
+
+    if !untracked.is_empty() {
+        untracked.sort();
+        return Err(io::Error::other(format!(
+            "The following untracked working tree files would be overwritten by checkout:\n{}\nPlease move or remove them before you switch branches.",
+            format_paths(&untracked),
+        )));
+    }
+
+    dirty.sort();
+    if !dirty.is_empty() && !options.force {
+        return Err(io::Error::other(format!(
+            "Your local changes to the following files would be overwritten by checkout:\n{}\nPlease commit your changes or stash them before you switch branches.",
+            format_paths(&dirty),
+        )));
+    }
+
+    let mut report = CheckoutReport {
+        discarded: dirty,
+        collisions: repo.folding.collisions(target.keys().map(PathBuf::as_path)),
+        ..CheckoutReport::default()
+    };
+
+    progress.start(to_write.len() + to_remove.len());
+
+    // Deletions wait until every new file is written, except the ones a
+    // write depends on: a file where the target needs a directory, or the
+    // contents of a directory the target replaces with a file
+    let (blocking, deferred): (Vec<&PathBuf>, Vec<&PathBuf>) = to_remove.iter().partition(|removed| {
+        to_write.iter().any(|(written, _)| written.starts_with(removed) || removed.starts_with(written))
+    });
+    for path in blocking {
+        remove_tracked(repo, path, &mut report)?;
+        progress.tick(path);
+    }
+
+    for (path, entry) in &to_write {
+        write_blob(repo, path, entry)?;
+        report.updated.push((*path).clone());
+        progress.tick(path);
+    }
+
+    for path in deferred {
+        remove_tracked(repo, path, &mut report)?;
+        progress.tick(path);
+    }
+
+    progress.finish();
+
+    let mut changed_paths: Vec<PathBuf> = report.updated.iter().chain(report.removed.iter()).cloned().collect();
+    changed_paths.sort();
+    repo.events.emit(RepositoryEvent::WorktreeFilesChanged { paths: changed_paths });
+
+    let written: HashSet<&PathBuf> = to_write.iter().map(|(path, _)| *path).collect();
+
+    // Rebuild the index: keep stat data for untouched paths, refresh the rest
+    let mut index = Index::new();
+    for (path, entry) in &target {
+        match current.get(path) {
+            Some(index_entry) if !written.contains(path) => {
+                index.add_entry(index_entry.clone());
+            }
+            _ => {
+                let metadata = fs::metadata(repo.root_path.join(path))?;
+                index.add_entry(IndexEntry::new(path.clone(), entry.hash, metadata));
+            }
+        }
+    }
+    repo.index = index;
+    repo.save_index()?;
+
+    report.updated.sort();
+    report.removed.sort();
+    report.pruned.sort();
+    Ok(report)
+}
+
+/// Untracked files that block writing `path`: the file itself, or when the
+/// target replaces a directory of tracked files, anything else inside it
+fn in_the_way(repo: &Repository, path: &Path, current: &HashMap<PathBuf, IndexEntry>) -> io::Result<Vec<PathBuf>> {
+    let full_path = repo.root_path.join(path);
+    if !full_path.is_dir() || !current.keys().any(|tracked| tracked.starts_with(path)) {
+        return Ok(if full_path.exists() { vec![path.to_path_buf()] } else { Vec::new() });
+    }
+
+    let mut blocking = Vec::new();
+    for entry in WalkDir::new(&full_path).min_depth(1) {
+        let entry = entry.map_err(io::Error::from)?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(&repo.root_path).unwrap_or(entry.path());
+        if !current.contains_key(relative) {
+            blocking.push(relative.to_path_buf());
+        }
+    }
+    Ok(blocking)
+}
+
+/// Deletes a tracked file, then each parent directory the deletion left
+/// empty, bottom-up. Pruning stops at the first directory that still holds
+/// anything, so untracked and ignored files keep their directories.
+fn remove_tracked(repo: &Repository, path: &Path, report: &mut CheckoutReport) -> io::Result<()> {
+    match fs::remove_file(repo.root_path.join(path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    report.removed.push(path.to_path_buf());
+
+    for parent in path.ancestors().skip(1).filter(|parent| !parent.as_os_str().is_empty()) {
+        if fs::remove_dir(repo.root_path.join(parent)).is_err() {
+            break;
+        }
+        report.pruned.push(parent.to_path_buf());
+    }
+    Ok(())
+}
+
+/// Writes the staged content of `entry` over its work tree file and returns
+/// the entry with the stat data of the file written
+pub fn restore_file_from_index(repo: &Repository, entry: &IndexEntry) -> io::Result<IndexEntry> {
+    let tree_entry = TreeEntry { mode: normalize_mode(entry.mode), name: String::new(), hash: entry.hash };
+    write_blob(repo, &entry.path, &tree_entry)?;
+    let metadata = fs::metadata(repo.root_path.join(&entry.path))?;
+    Ok(IndexEntry::new(entry.path.clone(), entry.hash, metadata))
+}
+
+fn write_blob(repo: &Repository, path: &Path, entry: &TreeEntry) -> io::Result<()> {
+    let full_path = repo.root_path.join(path);
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match Object::read_from_objects_dir(&repo.git_dir, entry.hash)? {
+        Object::Blob(content) => {
+            fs::write(&full_path, content)?;
+            let mut perms = fs::metadata(&full_path)?.permissions();
+            perms.set_mode(entry.mode & 0o777);
+            fs::set_permissions(&full_path, perms)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Tree entry '{}' does not point to a blob", path.display()),
+        )),
+    }
+}
+
+fn format_paths(paths: &[PathBuf]) -> String {
+    paths.iter()
+        .map(|path| format!("\t{}", path.display()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::core::case_fold::PathFolding;
+    use crate::cobra::core::tree::{build_tree, build_tree_from_index};
+    use crate::cobra::utils::progress::NoProgress;
+    use crate::cobra::test_support::temp_repo;
+
+    fn stage(repo: &mut Repository, name: &str, content: &str) -> io::Result<()> {
+        let path = repo.root_path.join(name);
+        fs::write(&path, content)?;
+        let blob = Object::new_blob(content.as_bytes().to_vec());
+        repo.write_object(&blob)?;
+        let entry = IndexEntry::new(PathBuf::from(name), blob.hash(), fs::metadata(&path)?);
+        repo.add_to_index(entry)
+    }
+
+    fn snapshot(repo: &Repository) -> io::Result<Oid> {
+        let tree = build_tree_from_index(repo)?;
+        repo.write_object(&tree)?;
+        Ok(tree.hash())
+    }
+
+    #[test]
+    fn test_checkout_switches_file_contents() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+
+        stage(&mut repo, "file.txt", "main version\n")?;
+        stage(&mut repo, "only-main.txt", "main only\n")?;
+
+        let blob = Object::new_blob(b"feature version\n".to_vec());
+        repo.write_object(&blob)?;
+        let feature_tree = build_tree(&repo, &[(PathBuf::from("file.txt"), 0o100644, blob.hash())])?;
+        repo.write_object(&feature_tree)?;
+
+        let report = checkout_tree(&mut repo, feature_tree.hash(), &CheckoutOptions::default(), &mut NoProgress)?;
+        assert_eq!(report.updated, vec![PathBuf::from("file.txt")]);
+        assert_eq!(report.removed, vec![PathBuf::from("only-main.txt")]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file.txt"))?, "feature version\n");
+        assert!(!temp_dir.path().join("only-main.txt").exists());
+        assert_eq!(repo.index.entries().count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_refuses_dirty_file() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+
+        stage(&mut repo, "file.txt", "one\n")?;
+        let first = snapshot(&repo)?;
+        stage(&mut repo, "file.txt", "two\n")?;
+        let second = snapshot(&repo)?;
+        checkout_tree(&mut repo, first, &CheckoutOptions::default(), &mut NoProgress)?;
+
+        fs::write(temp_dir.path().join("file.txt"), "local edit\n")?;
+        let result = checkout_tree(&mut repo, second, &CheckoutOptions::default(), &mut NoProgress);
+        match result {
+            Err(e) => assert!(e.to_string().contains("would be overwritten")),
+            _ => panic!("Expected error"),
+        }
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file.txt"))?, "local edit\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_forced_switch_keeps_untracked_files() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+
+        stage(&mut repo, "file.txt", "main\n")?;
+        let main_tree = snapshot(&repo)?;
+        stage(&mut repo, "file.txt", "feature\n")?;
+        let feature_tree = snapshot(&repo)?;
+        checkout_tree(&mut repo, main_tree, &CheckoutOptions::default(), &mut NoProgress)?;
+
+        // Dirty tracked file plus an untracked neighbor
+        fs::write(temp_dir.path().join("file.txt"), "dirty\n")?;
+        fs::write(temp_dir.path().join("notes.txt"), "untracked\n")?;
+
+        let report = checkout_tree(&mut repo, feature_tree, &CheckoutOptions { force: true }, &mut NoProgress)?;
+        assert_eq!(report.discarded, vec![PathBuf::from("file.txt")]);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("file.txt"))?, "feature\n");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("notes.txt"))?, "untracked\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_removals_prune_empty_directories() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let root = temp_dir.path();
+
+        fs::create_dir_all(root.join("src"))?;
+        fs::create_dir_all(root.join("docs/api/v1"))?;
+        stage(&mut repo, "src/main.rs", "fn main() {}\n")?;
+        stage(&mut repo, "docs/guide.md", "guide\n")?;
+        stage(&mut repo, "docs/api/v1/index.md", "api\n")?;
+        let with_docs = snapshot(&repo)?;
+
+        let blob = repo.write_object(&Object::new_blob(b"fn main() {}\n".to_vec()))?;
+        let lib = repo.write_object(&Object::new_blob(b"pub fn lib() {}\n".to_vec()))?;
+        let without_docs = build_tree(&repo, &[
+            (PathBuf::from("src/main.rs"), 0o100644, blob),
+            (PathBuf::from("lib/lib.rs"), 0o100644, lib),
+        ])?;
+        repo.write_object(&without_docs)?;
+
+        // An untracked file keeps its directory alive, nothing above it is touched
+        fs::write(root.join("docs/notes.txt"), "mine\n")?;
+        let report = checkout_tree(&mut repo, without_docs.hash(), &CheckoutOptions::default(), &mut NoProgress)?;
+        assert_eq!(report.removed, vec![PathBuf::from("docs/api/v1/index.md"), PathBuf::from("docs/guide.md")]);
+        assert_eq!(report.pruned, vec![PathBuf::from("docs/api"), PathBuf::from("docs/api/v1")]);
+        assert_eq!(fs::read_to_string(root.join("docs/notes.txt"))?, "mine\n");
+        assert!(!root.join("docs/api").exists());
+        assert!(root.join("lib/lib.rs").is_file());
+
+        // Back and forth without the untracked file removes the directory entirely
+        checkout_tree(&mut repo, with_docs, &CheckoutOptions::default(), &mut NoProgress)?;
+        assert_eq!(fs::read_to_string(root.join("docs/api/v1/index.md"))?, "api\n");
+        assert!(!root.join("lib").exists());
+        fs::remove_file(root.join("docs/notes.txt"))?;
+        let report = checkout_tree(&mut repo, without_docs.hash(), &CheckoutOptions::default(), &mut NoProgress)?;
+        assert!(report.pruned.contains(&PathBuf::from("docs")));
+        assert!(!root.join("docs").exists());
+        assert!(root.is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_replaced_by_directory() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        stage(&mut repo, "build", "a file\n")?;
+        let as_file = snapshot(&repo)?;
+
+        let blob = repo.write_object(&Object::new_blob(b"output\n".to_vec()))?;
+        let as_dir = build_tree(&repo, &[(PathBuf::from("build/out.txt"), 0o100644, blob)])?;
+        repo.write_object(&as_dir)?;
+
+        checkout_tree(&mut repo, as_dir.hash(), &CheckoutOptions::default(), &mut NoProgress)?;
+        assert_eq!(fs::read_to_string(temp_dir.path().join("build/out.txt"))?, "output\n");
+        checkout_tree(&mut repo, as_file, &CheckoutOptions::default(), &mut NoProgress)?;
+        assert_eq!(fs::read_to_string(temp_dir.path().join("build"))?, "a file\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_reports_folded_collisions() -> io::Result<()> {
+        let (_temp_dir, mut repo) = temp_repo()?;
+
+        let blob = Object::new_blob(b"readme\n".to_vec());
+        repo.write_object(&blob)?;
+        let tree = build_tree(&repo, &[
+            (PathBuf::from("README.md"), 0o100644, blob.hash()),
+            (PathBuf::from("ReadMe.md"), 0o100644, blob.hash()),
+            (PathBuf::from("other.txt"), 0o100644, blob.hash()),
+        ])?;
+        repo.write_object(&tree)?;
+
+        let report = checkout_tree(&mut repo, tree.hash(), &CheckoutOptions::default(), &mut NoProgress)?;
+        assert!(report.collisions.is_empty());
+
+        // Pretend the work tree lives on a case-insensitive filesystem
+        repo.folding = PathFolding { ignore_case: true, precompose_unicode: false };
+        let report = checkout_tree(&mut repo, tree.hash(), &CheckoutOptions::default(), &mut NoProgress)?;
+        assert_eq!(report.collisions, vec![vec![PathBuf::from("README.md"), PathBuf::from("ReadMe.md")]]);
+
+        Ok(())
+    }
+}
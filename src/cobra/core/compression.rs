@@ -0,0 +1,299 @@
+// How objects are compressed on disk
+//
+// Every compressed stream cobra writes is created here, at the level
+// core.compression asks for: 1 is fastest, 9 smallest, and 0 stores the data
+// in zlib framing without compressing it. Packs are always zlib, as git
+// requires.
+//
+// Built with the `zstd` cargo feature, loose objects can be written as zstd
+// frames instead (core.looseObjectFormat = zstd). Such files start with
+// ZSTD_MAGIC, which can never begin a zlib stream, so reads tell the formats
+// apart by their first bytes and repositories may mix both. Git cannot read
+// zstd objects: this is an experiment for repositories only cobra touches.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use crate::cobra::core::config::Config;
+use crate::cobra::messages;
+
+/// zlib's own default, used when core.compression is unset or -1
+pub const DEFAULT_LEVEL: u32 = 6;
+
+/// Marks a zstd loose object. A zlib stream's first byte always has 8 in
+/// its low nibble, which 0xff does not.
+pub const ZSTD_MAGIC: &[u8; 4] = b"\xffzst";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionSettings {
+    /// 0 (store) to 9 (smallest)
+    pub level: u32,
+    /// Write loose objects as zstd instead of zlib
+    pub zstd: bool,
+}
+
+impl Default for CompressionSettings {
+    fn default() -> Self {
+        CompressionSettings { level: DEFAULT_LEVEL, zstd: false }
+    }
+}
+
+impl CompressionSettings {
+    /// Reads core.compression and core.looseObjectFormat
+    pub fn from_config(config: &Config) -> io::Result<CompressionSettings> {
+        Ok(CompressionSettings { level: level_from(config)?, zstd: zstd_from(config)? })
+    }
+
+    /// Like `from_config`, but a value cobra cannot use is reported through
+    /// `warn` and its default taken instead, so that a bad setting does not
+    /// stop the repository from opening
+    pub fn from_config_or_default(config: &Config, mut warn: impl FnMut(&io::Error)) -> CompressionSettings {
+        let defaults = CompressionSettings::default();
+        CompressionSettings {
+            level: level_from(config).unwrap_or_else(|e| { warn(&e); defaults.level }),
+            zstd: zstd_from(config).unwrap_or_else(|e| { warn(&e); defaults.zstd }),
+        }
+    }
+
+    /// The settings in the config of the repository at `git_dir`, and a
+    /// warning for each bad value that was replaced by its default. Failing
+    /// instead would leave the repository unusable until the config is
+    /// fixed by hand; the caller decides where the warnings go.
+    pub fn load(git_dir: &Path) -> io::Result<(CompressionSettings, Vec<String>)> {
+        let mut warnings = Vec::new();
+        let settings = CompressionSettings::from_config_or_default(&Config::load(git_dir)?, |e| {
+            warnings.push(messages::config_value_ignored(e));
+        });
+        Ok((settings, warnings))
+    }
+}
+
+fn level_from(config: &Config) -> io::Result<u32> {
+    match config.get("core.compression") {
+        Some(value) => parse_level(value),
+        None => Ok(DEFAULT_LEVEL),
+    }
+}
+
+fn zstd_from(config: &Config) -> io::Result<bool> {
+    match config.get("core.looseObjectFormat").map(str::to_ascii_lowercase).as_deref() {
+        None | Some("zlib") => Ok(false),
+        Some("zstd") if cfg!(feature = "zstd") => Ok(true),
+        Some("zstd") => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "core.looseObjectFormat is zstd, but cobra was built without the zstd feature",
+        )),
+        Some(other) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid core.looseObjectFormat '{}': expected zlib or zstd", other),
+        )),
+    }
+}
+
+/// Parses a compression level: 0 to 9, or -1 for the default
+pub fn parse_level(value: &str) -> io::Result<u32> {
+    match value.trim().parse::<i32>() {
+        Ok(-1) => Ok(DEFAULT_LEVEL),
+        Ok(level @ 0..=9) => Ok(level as u32),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid compression level '{}': expected -1 or 0 to 9", value),
+        )),
+    }
+}
+
+/// A zlib stream at `level`, for pack entries and anything else git reads
+pub fn zlib_encoder<W: Write>(out: W, level: u32) -> ZlibEncoder<W> {
+    ZlibEncoder::new(out, Compression::new(level.min(9)))
+}
+
+/// Compresses a loose object in the format `settings` asks for
+pub enum LooseEncoder<W: Write> {
+    Zlib(ZlibEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+pub fn loose_encoder<W: Write>(mut out: W, settings: CompressionSettings) -> io::Result<LooseEncoder<W>> {
+    if settings.zstd {
+        out.write_all(ZSTD_MAGIC)?;
+        #[cfg(feature = "zstd")]
+        return Ok(LooseEncoder::Zstd(zstd::stream::write::Encoder::new(out, settings.level as i32)?));
+        #[cfg(not(feature = "zstd"))]
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "cobra was built without the zstd feature"));
+    }
+    Ok(LooseEncoder::Zlib(zlib_encoder(out, settings.level)))
+}
+
+impl<W: Write> LooseEncoder<W> {
+    /// Ends the stream and returns the underlying writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            LooseEncoder::Zlib(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            LooseEncoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for LooseEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            LooseEncoder::Zlib(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            LooseEncoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            LooseEncoder::Zlib(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            LooseEncoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Decompresses a loose object file in either format
+pub fn decompress_loose(compressed: &[u8]) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    loose_decoder(compressed)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Decompresses a loose object as it is read from `input`, in either
+/// format, so large objects never have to fit in memory
+pub fn loose_decoder<'a, R: Read + 'a>(mut input: R) -> io::Result<Box<dyn Read + 'a>> {
+    let mut prefix = Vec::with_capacity(ZSTD_MAGIC.len());
+    input.by_ref().take(ZSTD_MAGIC.len() as u64).read_to_end(&mut prefix)?;
+    if prefix != ZSTD_MAGIC {
+        return Ok(Box::new(ZlibDecoder::new(io::Cursor::new(prefix).chain(input))));
+    }
+    #[cfg(feature = "zstd")]
+    return Ok(Box::new(zstd::stream::read::Decoder::new(input)?));
+    #[cfg(not(feature = "zstd"))]
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Object is zstd-compressed, but cobra was built without the zstd feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Instant;
+    use crate::cobra::core::object::Object;
+    use crate::cobra::core::repository::Repository;
+    use crate::cobra::test_support::temp_repo;
+
+    fn compress(data: &[u8], settings: CompressionSettings) -> io::Result<Vec<u8>> {
+        let mut encoder = loose_encoder(Vec::new(), settings)?;
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+
+    /// Source-like text: repetitive enough to compress, varied enough that
+    /// the levels differ
+    fn generated_data() -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut seed: u32 = 12345;
+        for i in 0..20_000 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let word = ["let", "fn", "match", "value", "count", "items", "return"][(seed >> 16) as usize % 7];
+            data.extend_from_slice(format!("{} {}_{} = {};\n", word, word, seed % 97, i % 1000).as_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_levels_trade_time_for_size() -> io::Result<()> {
+        let data = generated_data();
+        let mut results = Vec::new();
+        for level in [0, 1, 9] {
+            let started = Instant::now();
+            let compressed = compress(&data, CompressionSettings { level, zstd: false })?;
+            let elapsed = started.elapsed();
+            assert_eq!(decompress_loose(&compressed)?, data);
+            results.push((level, compressed.len(), elapsed));
+        }
+        let report = format!("{:?}", results);
+        let size = |level: u32| results.iter().find(|result| result.0 == level).unwrap().1;
+        // Level 0 only frames the data
+        assert!(size(0) > data.len(), "{}", report);
+        assert!(size(9) < size(1), "{}", report);
+        Ok(())
+    }
+
+    #[test]
+    fn test_settings_from_config() -> io::Result<()> {
+        let config = |text: &str| Config::parse(text);
+        assert_eq!(CompressionSettings::from_config(&config("")?)?, CompressionSettings::default());
+        assert_eq!(CompressionSettings::from_config(&config("[core]\n\tcompression = 1\n")?)?.level, 1);
+        assert_eq!(CompressionSettings::from_config(&config("[core]\n\tcompression = -1\n")?)?.level, DEFAULT_LEVEL);
+        assert!(CompressionSettings::from_config(&config("[core]\n\tcompression = 10\n")?).is_err());
+        assert!(CompressionSettings::from_config(&config("[core]\n\tlooseObjectFormat = lz4\n")?).is_err());
+        let zstd = CompressionSettings::from_config(&config("[core]\n\tlooseObjectFormat = zstd\n")?);
+        assert_eq!(zstd.is_ok(), cfg!(feature = "zstd"));
+
+        // Leniently, each bad value falls back on its own
+        let mut warnings = Vec::new();
+        let settings = CompressionSettings::from_config_or_default(
+            &config("[core]\n\tcompression = fastest\n\tlooseObjectFormat = zlib\n")?,
+            |e| warnings.push(e.to_string()),
+        );
+        assert_eq!(settings, CompressionSettings::default());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("'fastest'"), "{:?}", warnings);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repository_writes_at_configured_level() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        let blob = Object::Blob(generated_data());
+        let hash = repo.write_object(&blob)?;
+        let path = hash.loose_path(&repo.git_dir);
+        let compressed = fs::metadata(&path)?.len();
+
+        fs::write(repo.git_dir.join("config"), "[core]\n\tcompression = 0\n")?;
+        repo = Repository::open(temp_dir.path().to_str().unwrap())?;
+        assert_eq!(repo.compression.level, 0);
+        fs::remove_file(&path)?;
+        repo.write_object(&blob)?;
+        assert!(fs::metadata(&path)?.len() > compressed);
+        assert_eq!(repo.read_object(hash)?.serialize(), blob.serialize());
+
+        fs::write(repo.git_dir.join("config"), "[core]\n\tcompression = 11\n")?;
+        repo = Repository::open(temp_dir.path().to_str().unwrap())?;
+        assert_eq!(repo.compression.level, DEFAULT_LEVEL);
+        assert_eq!(repo.warnings.len(), 1);
+        assert!(repo.warnings[0].starts_with("warning: Invalid compression level '11'"), "{:?}", repo.warnings);
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_and_zlib_objects_are_read_alike() -> io::Result<()> {
+        let data = generated_data();
+        let zstd = compress(&data, CompressionSettings { level: 3, zstd: true })?;
+        assert!(zstd.starts_with(ZSTD_MAGIC));
+        let zlib = compress(&data, CompressionSettings::default())?;
+        assert_eq!(decompress_loose(&zstd)?, data);
+        assert_eq!(decompress_loose(&zlib)?, data);
+
+        // A repository switched to zstd keeps reading its zlib objects
+        let (temp_dir, repo) = temp_repo()?;
+        let old = repo.write_object(&Object::Blob(b"zlib\n".to_vec()))?;
+        fs::write(repo.git_dir.join("config"), "[core]\n\tlooseObjectFormat = zstd\n")?;
+        let repo = Repository::open(temp_dir.path().to_str().unwrap())?;
+        let new = repo.write_object(&Object::Blob(b"zstd\n".to_vec()))?;
+        let stored = fs::read(repo.git_dir.join("objects").join(&new[..2]).join(&new[2..]))?;
+        assert!(stored.starts_with(ZSTD_MAGIC));
+        assert_eq!(repo.read_object(&old)?.serialize(), b"zlib\n");
+        assert_eq!(repo.read_object(&new)?.serialize(), b"zstd\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,608 @@
+// Repository configuration (.cobra/config) on top of the per-user config
+//
+// Reading flattens the per-user file, the repository's file and everything
+// they include into one list of key/value pairs, so that repository values
+// override per-user ones key by key. Writing goes through ConfigDocument instead, which keeps
+// every line as it was read so that changing one key leaves comments,
+// ordering and all other sections byte-for-byte intact.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::cobra::utils::glob::glob_match;
+use crate::cobra::utils::tempfiles::TempFile;
+
+/// Includes nested deeper than this are rejected, which also stops include cycles
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Values read from INI-style config files. Keys are addressed as
+/// `section.key` or `section.subsection.key`; section and key names are
+/// case-insensitive, later assignments win.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    values: Vec<ConfigEntry>,
+}
+
+/// One assignment and the file it was read from
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigEntry {
+    /// The normalized key
+    pub key: String,
+    pub value: String,
+    /// None for text parsed on its own
+    pub origin: Option<PathBuf>,
+}
+
+impl Config {
+    /// Loads the per-user config and then `<git_dir>/config`, with the
+    /// files they include. Missing files contribute nothing.
+    pub fn load(git_dir: &Path) -> io::Result<Config> {
+        let global = Config::global_path();
+        let local = git_dir.join("config");
+        let mut paths: Vec<&Path> = global.iter().map(PathBuf::as_path).collect();
+        paths.push(&local);
+        Config::load_files(&paths, Some(git_dir))
+    }
+
+    /// Path of the per-user config: COBRA_CONFIG_GLOBAL when set, so that
+    /// scripts and tests can point it elsewhere, then
+    /// `$XDG_CONFIG_HOME/cobra/config` when XDG_CONFIG_HOME is set, and
+    /// `~/.cobraconfig` otherwise. Unit tests never see one, so that they do
+    /// not depend on the settings of whoever runs them; tests of per-user
+    /// values pass the file's path explicitly.
+    pub fn global_path() -> Option<PathBuf> {
+        if cfg!(test) {
+            return None;
+        }
+        if let Some(path) = env::var_os("COBRA_CONFIG_GLOBAL").filter(|value| !value.is_empty()) {
+            return Some(PathBuf::from(path));
+        }
+        if let Some(xdg) = env::var_os("XDG_CONFIG_HOME").filter(|value| !value.is_empty()) {
+            return Some(PathBuf::from(xdg).join("cobra").join("config"));
+        }
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".cobraconfig"))
+    }
+
+    /// Loads the per-user config. A missing file yields an empty config.
+    pub fn load_global() -> io::Result<Config> {
+        match Config::global_path() {
+            Some(path) => Config::load_file(&path, None),
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// Loads a config file, splicing in `[include]` files where they are
+    /// named and `[includeIf "gitdir:..."]` files whose condition matches
+    /// `git_dir`. Included paths are relative to the including file.
+    /// Missing files, including missing includes, contribute nothing.
+    pub fn load_file(path: &Path, git_dir: Option<&Path>) -> io::Result<Config> {
+        Config::load_files(&[path], git_dir)
+    }
+
+    /// Loads each of `paths` in turn as load_file does, so that values in
+    /// later files override those in earlier ones
+    pub fn load_files(paths: &[&Path], git_dir: Option<&Path>) -> io::Result<Config> {
+        let mut values = Vec::new();
+        for path in paths {
+            read_into(path, git_dir, 0, &mut values)?;
+        }
+        Ok(Config { values })
+    }
+
+    /// Parses config text on its own; includes are not followed
+    pub fn parse(text: &str) -> io::Result<Config> {
+        let document = ConfigDocument::parse(text)?;
+        Ok(Config {
+            values: document.entries()
+                .map(|(key, value)| ConfigEntry { key: key.to_string(), value: value.to_string(), origin: None })
+                .collect(),
+        })
+    }
+
+    /// Returns the last value assigned to `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_entry(key).map(|entry| entry.value.as_str())
+    }
+
+    /// Returns the last assignment of `key`, with the file it came from
+    pub fn get_entry(&self, key: &str) -> Option<&ConfigEntry> {
+        let key = normalize_key(key);
+        self.values.iter().rev().find(|entry| entry.key == key)
+    }
+
+    /// Every assignment in the order it was read, included files in place
+    /// of their include
+    pub fn entries(&self) -> impl Iterator<Item = &ConfigEntry> {
+        self.values.iter()
+    }
+
+    /// Reads `key` as a boolean the way git does ("true", "yes", "on", "1"
+    /// and their opposites); None if it is unset or not a boolean
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)?.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(true),
+            "false" | "no" | "off" | "0" | "" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+fn read_into(path: &Path, git_dir: Option<&Path>, depth: usize, values: &mut Vec<ConfigEntry>) -> io::Result<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Exceeded maximum include depth ({}) while including {}", MAX_INCLUDE_DEPTH, path.display()),
+        ));
+    }
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let document = ConfigDocument::parse(&text).map_err(|e| io::Error::new(
+        e.kind(),
+        format!("{} in {}", e, path.display()),
+    ))?;
+
+    for (key, value) in document.entries() {
+        values.push(ConfigEntry { key: key.to_string(), value: value.to_string(), origin: Some(path.to_path_buf()) });
+        if let Some(include) = include_target(key, value, path, git_dir) {
+            read_into(&include, git_dir, depth + 1, values)?;
+        }
+    }
+    Ok(())
+}
+
+/// The file an `include.path` or `includeIf.<condition>.path` entry names,
+/// if it applies
+fn include_target(key: &str, value: &str, including_file: &Path, git_dir: Option<&Path>) -> Option<PathBuf> {
+    if key == "include.path" {
+        return Some(resolve_include(value, including_file));
+    }
+    let condition = key.strip_prefix("includeif.")?.strip_suffix(".path")?;
+    condition_matches(condition, including_file, git_dir?).then(|| resolve_include(value, including_file))
+}
+
+fn resolve_include(value: &str, including_file: &Path) -> PathBuf {
+    if let Some(rest) = value.strip_prefix("~/") {
+        if let Some(home) = env::var_os("HOME") {
+            return PathBuf::from(home).join(rest);
+        }
+    }
+    let base = including_file.parent().unwrap_or(Path::new(""));
+    base.join(value)
+}
+
+/// Checks a `gitdir:` (or case-insensitive `gitdir/i:`) condition: the
+/// pattern is a glob over the repository's metadata directory, a trailing
+/// `/` matches everything below it and a relative pattern may match at any depth
+fn condition_matches(condition: &str, including_file: &Path, git_dir: &Path) -> bool {
+    let (pattern, ignore_case) = match (condition.strip_prefix("gitdir:"), condition.strip_prefix("gitdir/i:")) {
+        (Some(pattern), _) => (pattern, false),
+        (_, Some(pattern)) => (pattern, true),
+        _ => return false,
+    };
+
+    let mut pattern = if let Some(rest) = pattern.strip_prefix("~/") {
+        match env::var_os("HOME") {
+            Some(home) => format!("{}/{}", PathBuf::from(home).display(), rest),
+            None => return false,
+        }
+    } else if let Some(rest) = pattern.strip_prefix("./") {
+        let base = including_file.parent().unwrap_or(Path::new(""));
+        format!("{}/{}", base.display(), rest)
+    } else if pattern.starts_with('/') {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+
+    let git_dir = fs::canonicalize(git_dir).unwrap_or_else(|_| git_dir.to_path_buf());
+    let git_dir = git_dir.to_string_lossy();
+    if ignore_case {
+        glob_match(&pattern.to_lowercase(), &git_dir.to_lowercase())
+    } else {
+        glob_match(&pattern, &git_dir)
+    }
+}
+
+/// Lowercases the section and key parts, leaving a subsection untouched
+fn normalize_key(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first), Some(last)) if first != last => format!(
+            "{}{}{}",
+            key[..first].to_lowercase(),
+            &key[first..last],
+            key[last..].to_lowercase(),
+        ),
+        _ => key.to_lowercase(),
+    }
+}
+
+/// One line of a config file
+#[derive(Debug, Clone)]
+struct Line {
+    /// The line exactly as read, including its line terminator
+    raw: String,
+    kind: LineKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum LineKind {
+    /// A section header, as the key prefix it introduces ("core", "remote.origin")
+    Section(String),
+    /// An assignment with its full normalized key
+    Entry { key: String, value: String },
+    /// Blank lines and comments
+    Other,
+}
+
+/// A config file as a list of lines, for writes that change only the lines
+/// they are about
+#[derive(Debug, Clone, Default)]
+pub struct ConfigDocument {
+    lines: Vec<Line>,
+}
+
+impl ConfigDocument {
+    /// Parses config text, reporting the first malformed line by number
+    pub fn parse(text: &str) -> io::Result<ConfigDocument> {
+        let mut lines = Vec::new();
+        let mut section: Option<String> = None;
+
+        for (number, raw) in text.split_inclusive('\n').enumerate() {
+            let line = raw.trim();
+            let bad_line = |reason: &str| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Bad config line {}: {}", number + 1, reason),
+            );
+
+            let kind = if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                LineKind::Other
+            } else if let Some(header) = line.strip_prefix('[') {
+                let header = header.strip_suffix(']').ok_or_else(|| bad_line(line))?;
+                let name = match header.split_once(' ') {
+                    // [remote "origin"] keeps the subsection's case
+                    Some((name, sub)) => format!("{}.{}", name.to_lowercase(), sub.trim().trim_matches('"')),
+                    None => header.to_lowercase(),
+                };
+                section = Some(name.clone());
+                LineKind::Section(name)
+            } else {
+                let section = section.as_ref().ok_or_else(|| bad_line("key outside of a section"))?;
+                let (key, value) = match line.split_once('=') {
+                    Some((key, value)) => (key.trim(), value.trim().trim_matches('"')),
+                    // A bare key is a boolean true
+                    None => (line, "true"),
+                };
+                if key.is_empty() {
+                    return Err(bad_line(line));
+                }
+                LineKind::Entry { key: format!("{}.{}", section, key.to_lowercase()), value: value.to_string() }
+            };
+            lines.push(Line { raw: raw.to_string(), kind });
+        }
+
+        Ok(ConfigDocument { lines })
+    }
+
+    /// Reads a config file for editing; a missing file is an empty document
+    pub fn load(path: &Path) -> io::Result<ConfigDocument> {
+        match fs::read_to_string(path) {
+            Ok(text) => ConfigDocument::parse(&text).map_err(|e| io::Error::new(
+                e.kind(),
+                format!("{} in {}", e, path.display()),
+            )),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ConfigDocument::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replaces `path` with this document in one rename
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let staged = TempFile::in_dir(dir, ".config")?;
+        fs::write(staged.path(), self.to_string())?;
+        staged.persist(path)
+    }
+
+    /// Every assignment in file order as (normalized key, value)
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(|line| match &line.kind {
+            LineKind::Entry { key, value } => Some((key.as_str(), value.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Sets `key` to `value`. The last existing assignment is rewritten in
+    /// place; otherwise the key is added at the end of the last matching
+    /// section, or in a new section at the end of the file.
+    pub fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        let (section, name) = split_key(key)?;
+        let normalized = normalize_key(key);
+        let entry_kind = LineKind::Entry { key: normalized.clone(), value: value.to_string() };
+
+        let existing = self.lines.iter().rposition(|line| matches!(&line.kind, LineKind::Entry { key, .. } if *key == normalized));
+        if let Some(index) = existing {
+            let line = &mut self.lines[index];
+            let indent: String = line.raw.chars().take_while(|c| *c == ' ' || *c == '\t').collect();
+            let newline = if line.raw.ends_with('\n') { "\n" } else { "" };
+            line.raw = format!("{}{} = {}{}", indent, name, quote(value), newline);
+            line.kind = entry_kind;
+            return Ok(());
+        }
+
+        let raw = format!("\t{} = {}\n", name, quote(value));
+        let normalized_section = normalize_key(&format!("{}.x", section));
+        let normalized_section = &normalized_section[..normalized_section.len() - 2];
+        let header = self.lines.iter().rposition(|line| line.kind == LineKind::Section(normalized_section.to_string()));
+        match header {
+            Some(header) => {
+                // After the section's last assignment, before any trailing comments
+                let end = self.lines[header + 1..].iter()
+                    .position(|line| matches!(line.kind, LineKind::Section(_)))
+                    .map_or(self.lines.len(), |offset| header + 1 + offset);
+                let last_entry = self.lines[header + 1..end].iter()
+                    .rposition(|line| matches!(line.kind, LineKind::Entry { .. }))
+                    .map_or(header, |offset| header + 1 + offset);
+                self.terminate_line(last_entry);
+                self.lines.insert(last_entry + 1, Line { raw, kind: entry_kind });
+            }
+            None => {
+                if let Some(last) = self.lines.len().checked_sub(1) {
+                    self.terminate_line(last);
+                }
+                let header_raw = match section.split_once('.') {
+                    Some((name, sub)) => format!("[{} \"{}\"]\n", name, sub),
+                    None => format!("[{}]\n", section),
+                };
+                self.lines.push(Line { raw: header_raw, kind: LineKind::Section(normalized_section.to_string()) });
+                self.lines.push(Line { raw, kind: entry_kind });
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the assignment of `key`. Returns false if the key is not set;
+    /// a key with several values is an error, since it is unclear which to drop.
+    pub fn unset(&mut self, key: &str) -> io::Result<bool> {
+        let normalized = normalize_key(key);
+        let matches: Vec<usize> = self.lines.iter()
+            .enumerate()
+            .filter(|(_, line)| matches!(&line.kind, LineKind::Entry { key, .. } if *key == normalized))
+            .map(|(index, _)| index)
+            .collect();
+        match matches.as_slice() {
+            [] => Ok(false),
+            [index] => {
+                self.lines.remove(*index);
+                Ok(true)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has multiple values", key),
+            )),
+        }
+    }
+
+    /// Makes sure the line at `index` ends with a newline before another is added after it
+    fn terminate_line(&mut self, index: usize) {
+        if !self.lines[index].raw.ends_with('\n') {
+            self.lines[index].raw.push('\n');
+        }
+    }
+}
+
+impl fmt::Display for ConfigDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            f.write_str(&line.raw)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits `section[.subsection].name` into the section part and the name
+fn split_key(key: &str) -> io::Result<(&str, &str)> {
+    match key.rsplit_once('.') {
+        Some((section, name))
+            if !section.is_empty()
+                && !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+        {
+            Ok((section, name))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid config key '{}'", key),
+        )),
+    }
+}
+
+/// Quotes values that would not survive parsing unquoted
+fn quote(value: &str) -> String {
+    if value.is_empty() || value.trim() != value || value.contains(['#', ';']) {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_sections_and_keys() -> io::Result<()> {
+        let config = Config::parse(
+            "# comment\n[core]\n\tbare = false\n[gc]\nreflogExpire = \"30 days\"\n[remote \"Origin\"]\nurl = x\n[core]\nbare = true\n",
+        )?;
+        assert_eq!(config.get("core.bare"), Some("true"));
+        assert_eq!(config.get("gc.reflogexpire"), Some("30 days"));
+        assert_eq!(config.get("GC.reflogExpire"), Some("30 days"));
+        assert_eq!(config.get("remote.Origin.url"), Some("x"));
+        assert_eq!(config.get("user.name"), None);
+        assert_eq!(config.get_bool("core.bare"), Some(true));
+        assert_eq!(config.get_bool("gc.reflogExpire"), None);
+        assert_eq!(config.get_bool("user.name"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_outside_section_is_an_error() {
+        assert!(Config::parse("name = x\n").is_err());
+        let error = Config::parse("[core]\n\tbare = true\n[broken\n").unwrap_err();
+        assert!(error.to_string().contains("line 3"));
+    }
+
+    #[test]
+    fn test_set_and_unset_preserve_other_lines() -> io::Result<()> {
+        let original = "\
+# Team settings, do not reorder
+[core]
+    abbrev = 9
+\t; editor is set per user
+[remote \"origin\"]
+\turl = https://example.com/repo
+# trailing comment";
+        let mut document = ConfigDocument::parse(original)?;
+        assert_eq!(document.to_string(), original);
+
+        // A new key goes after the last assignment of its section
+        document.set("core.bare", "false")?;
+        let with_bare = document.to_string();
+        assert_eq!(with_bare, original.replace("abbrev = 9\n", "abbrev = 9\n\tbare = false\n"));
+
+        // Removing it again restores the file exactly
+        assert!(document.unset("core.bare")?);
+        assert_eq!(document.to_string(), original);
+        assert!(!document.unset("core.bare")?);
+
+        // Changing a value touches only its own line, keeping the indentation
+        document.set("remote.origin.url", "git@example.com:repo")?;
+        assert_eq!(document.to_string(), original.replace("https://example.com/repo", "git@example.com:repo"));
+
+        // A new section is appended at the end
+        document.set("user.name", "Ada Lovelace")?;
+        assert!(document.to_string().ends_with("# trailing comment\n[user]\n\tname = Ada Lovelace\n"));
+        document.set("remote.upstream.url", " padded ")?;
+        assert!(document.to_string().ends_with("[remote \"upstream\"]\n\turl = \" padded \"\n"));
+
+        let config = Config::parse(&document.to_string())?;
+        assert_eq!(config.get("remote.upstream.url"), Some(" padded "));
+        assert_eq!(config.get("core.abbrev"), Some("9"));
+        assert!(document.set("nosection", "x").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unset_rejects_multiple_values() -> io::Result<()> {
+        let mut document = ConfigDocument::parse("[remote \"origin\"]\n\tfetch = a\n\tfetch = b\n")?;
+        assert!(document.unset("remote.origin.fetch").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_includes() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        let work = root.join("work").join("project").join(".cobra");
+        let other = root.join("other").join(".cobra");
+        fs::create_dir_all(&work)?;
+        fs::create_dir_all(&other)?;
+
+        fs::write(root.join("shared.conf"), "[core]\n\tabbrev = 10\n[user]\n\tname = Shared\n")?;
+        fs::write(root.join("work.conf"), "[user]\n\temail = dev@work.example\n")?;
+        let main = format!(
+            "[user]\n\tname = Local\n[include]\n\tpath = ../../../shared.conf\n[includeIf \"gitdir:{}/work/\"]\n\tpath = {}\n",
+            root.canonicalize()?.display(),
+            root.join("work.conf").display(),
+        );
+        fs::write(work.join("config"), &main)?;
+        fs::write(other.join("config"), main.replace("../../../", "../../"))?;
+
+        // The include comes after the local name, so it wins
+        let config = Config::load(&work)?;
+        assert_eq!(config.get("user.name"), Some("Shared"));
+        assert_eq!(config.get("core.abbrev"), Some("10"));
+        assert_eq!(config.get("user.email"), Some("dev@work.example"));
+
+        // The conditional include only applies below work/
+        let config = Config::load(&other)?;
+        assert_eq!(config.get("core.abbrev"), Some("10"));
+        assert_eq!(config.get("user.email"), None);
+
+        // A file including itself gives up instead of looping
+        fs::write(root.join("loop.conf"), "[include]\n\tpath = loop.conf\n")?;
+        assert!(Config::load_file(&root.join("loop.conf"), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_keys_in_one_section_round_trip() -> io::Result<()> {
+        let mut document = ConfigDocument::default();
+        document.set("user.name", "Ada Lovelace")?;
+        document.set("user.email", "ada@example.com")?;
+        document.set("commit.verbose", "true")?;
+        document.set("user.signingKey", "#1")?;
+        assert_eq!(
+            document.to_string(),
+            "[user]\n\tname = Ada Lovelace\n\temail = ada@example.com\n\tsigningKey = \"#1\"\n[commit]\n\tverbose = true\n",
+        );
+
+        // Reparsing yields the same lines and the same values
+        let reparsed = ConfigDocument::parse(&document.to_string())?;
+        assert_eq!(reparsed.to_string(), document.to_string());
+        let config = Config::parse(&document.to_string())?;
+        let entries: Vec<_> = config.entries().map(|entry| (entry.key.as_str(), entry.value.as_str())).collect();
+        assert_eq!(entries, [
+            ("user.name", "Ada Lovelace"),
+            ("user.email", "ada@example.com"),
+            ("user.signingkey", "#1"),
+            ("commit.verbose", "true"),
+        ]);
+        assert_eq!(config.get_bool("commit.verbose"), Some(true));
+
+        // Changing one key of the section leaves its neighbours alone
+        let mut document = reparsed;
+        document.set("user.email", "ada@work.example")?;
+        assert!(document.unset("user.name")?);
+        let config = Config::parse(&document.to_string())?;
+        assert_eq!(config.get("user.name"), None);
+        assert_eq!(config.get("user.email"), Some("ada@work.example"));
+        assert_eq!(config.get("user.signingKey"), Some("#1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repository_values_override_global_ones() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let global = temp_dir.path().join("home").join(".cobraconfig");
+        let git_dir = temp_dir.path().join(".cobra");
+        fs::create_dir_all(global.parent().unwrap())?;
+        fs::create_dir_all(&git_dir)?;
+        let local = git_dir.join("config");
+
+        // A missing per-user file is simply empty
+        fs::write(&local, "[user]\n\tname = Local\n")?;
+        let config = Config::load_files(&[&global, &local], Some(&git_dir))?;
+        assert_eq!(config.get("user.name"), Some("Local"));
+
+        // Each key comes from the last file that sets it
+        fs::write(&global, "[user]\n\tname = Global\n\temail = me@example.com\n")?;
+        let config = Config::load_files(&[&global, &local], Some(&git_dir))?;
+        assert_eq!(config.get("user.name"), Some("Local"));
+        assert_eq!(config.get_entry("user.name").and_then(|entry| entry.origin.clone()), Some(local.clone()));
+        assert_eq!(config.get("user.email"), Some("me@example.com"));
+        assert_eq!(config.get_entry("user.email").and_then(|entry| entry.origin.clone()), Some(global.clone()));
+        let origins: Vec<_> = config.entries().map(|entry| entry.origin.as_deref()).collect();
+        assert_eq!(origins, [Some(global.as_path()), Some(global.as_path()), Some(local.as_path())]);
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+// A read-only server for the git protocol
+//
+// `cobra daemon` listens on a TCP port and answers fetches for the
+// repositories it was told to export, and nothing else: any other path is
+// refused, as is every attempt to push. Each connection gets its own thread.
+
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use crate::cobra::core::protocol::{read_pkt, upload_pack, write_error};
+use crate::cobra::utils::trace::{self, Stream};
+
+/// Port git uses for its daemon
+pub const DEFAULT_PORT: u16 = 9418;
+
+/// How long a connection may sit idle before it is dropped
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A repository clients may fetch from
+#[derive(Debug, Clone)]
+struct Export {
+    /// The path as given, canonicalized; clients name the repository by it
+    root: PathBuf,
+    git_dir: PathBuf,
+}
+
+pub struct Daemon {
+    listener: TcpListener,
+    exports: Arc<Vec<Export>>,
+}
+
+impl Daemon {
+    /// Listens on `addr` ("host:port", port 0 picks a free one) and exports
+    /// the repositories at `exports`, each a work tree or a bare repository
+    pub fn bind(addr: &str, exports: &[PathBuf]) -> io::Result<Daemon> {
+        if exports.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Nothing to export; pass at least one --export"));
+        }
+        let exports = exports.iter()
+            .map(|path| {
+                let root = fs::canonicalize(path).map_err(|e| io::Error::new(
+                    e.kind(),
+                    format!("Cannot export '{}': {}", path.display(), e),
+                ))?;
+                let git_dir = find_git_dir(&root).ok_or_else(|| io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Cannot export '{}': not a cobra repository", path.display()),
+                ))?;
+                Ok(Export { root, git_dir })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let listener = TcpListener::bind(addr)?;
+        Ok(Daemon { listener, exports: Arc::new(exports) })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections until the listener fails
+    pub fn serve(&self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let exports = Arc::clone(&self.exports);
+            thread::spawn(move || {
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                if let Err(e) = handle_connection(stream, &exports) {
+                    trace::event(Stream::Main, || format!("daemon: {}: {}", peer, e));
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn find_git_dir(root: &Path) -> Option<PathBuf> {
+    if root.join(".cobra").is_dir() {
+        Some(root.join(".cobra"))
+    } else if root.join("HEAD").is_file() && root.join("objects").is_dir() {
+        Some(root.to_path_buf())
+    } else {
+        None
+    }
+}
+
+fn handle_connection(stream: TcpStream, exports: &[Export]) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut out = BufWriter::new(stream);
+
+    // "git-upload-pack /path\0host=example.com\0", possibly followed by
+    // extra parameters we ignore, such as a request for protocol version 2
+    let request = read_pkt(&mut input)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Expected a request, got a flush"))?;
+    let request = String::from_utf8_lossy(&request).into_owned();
+    let command = request.split('\0').next().unwrap_or_default().trim_end();
+    let (service, path) = command.split_once(' ').unwrap_or((command, ""));
+    trace::event(Stream::Main, || format!("daemon: {} {}", service, path));
+
+    match service {
+        "git-upload-pack" => {}
+        "git-receive-pack" => {
+            write_error(&mut out, "this daemon is read-only; pushing is not allowed")?;
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Refused a push"));
+        }
+        _ => {
+            write_error(&mut out, &format!("unsupported service '{}'", service))?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unsupported service '{}'", service)));
+        }
+    }
+
+    // Only paths that resolve to an export are served, so "..", symlinks and
+    // the like cannot reach anything else
+    let requested = fs::canonicalize(path).ok();
+    let export = exports.iter()
+        .find(|export| requested.as_ref().is_some_and(|path| *path == export.root || *path == export.git_dir));
+    let Some(export) = export else {
+        write_error(&mut out, &format!("'{}': repository not exported", path))?;
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("'{}' is not exported", path)));
+    };
+
+    upload_pack(&export.git_dir, &mut input, &mut out)?;
+    out.flush()
+}
@@ -0,0 +1,824 @@
+// Line-level diff engine
+//
+// Lines are interned to integers and the common prefix and suffix are split
+// off before a DiffAlgorithm sees them. Myers finds a shortest edit script
+// and is the default; patience and histogram anchor on lines that are rare
+// on both sides, which keeps moved or rewritten blocks in readable hunks.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+/// A single step of an edit script turning the old lines into the new ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit {
+    /// Line `old` of the old side is kept as line `new` of the new side
+    Equal { old: usize, new: usize },
+    /// Line `old` of the old side is removed
+    Delete { old: usize },
+    /// Line `new` of the new side is added
+    Insert { new: usize },
+}
+
+/// Splits content into lines, keeping the trailing newline on each line
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        if byte == b'\n' {
+            lines.push(&data[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < data.len() {
+        lines.push(&data[start..]);
+    }
+    lines
+}
+
+/// Turns one sequence of interned lines into another. Equal lines share an
+/// id, and line numbers in the result are offsets into `old` and `new`.
+pub trait DiffAlgorithm {
+    fn diff(&self, old: &[usize], new: &[usize]) -> Vec<Edit>;
+}
+
+/// The algorithms selectable with `--diff-algorithm` and `diff.algorithm`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    Myers,
+    Patience,
+    Histogram,
+}
+
+impl Algorithm {
+    /// Parses an algorithm name; "default" and "minimal" are Myers
+    pub fn parse(name: &str) -> io::Result<Algorithm> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "myers" | "default" | "minimal" => Ok(Algorithm::Myers),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Ok(Algorithm::Histogram),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown diff algorithm '{}', expected myers, patience or histogram", name.trim()),
+            )),
+        }
+    }
+
+    pub fn engine(&self) -> &'static dyn DiffAlgorithm {
+        match self {
+            Algorithm::Myers => &Myers,
+            Algorithm::Patience => &Patience,
+            Algorithm::Histogram => &Histogram,
+        }
+    }
+
+    /// Computes an edit script between two sequences of lines with this algorithm
+    pub fn diff_lines<'a>(&self, old: &[&'a [u8]], new: &[&'a [u8]]) -> Vec<Edit> {
+        diff_lines_with(self.engine(), old, new)
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Algorithm::Myers => "myers",
+            Algorithm::Patience => "patience",
+            Algorithm::Histogram => "histogram",
+        })
+    }
+}
+
+/// Computes the shortest edit script between two sequences of lines
+pub fn diff_lines<'a>(old: &[&'a [u8]], new: &[&'a [u8]]) -> Vec<Edit> {
+    diff_lines_with(&Myers, old, new)
+}
+
+/// Computes an edit script between two sequences of lines with `algorithm`
+pub fn diff_lines_with<'a>(algorithm: &dyn DiffAlgorithm, old: &[&'a [u8]], new: &[&'a [u8]]) -> Vec<Edit> {
+    // Map lines to integers so comparisons in the main loop are cheap
+    let mut ids: HashMap<&'a [u8], usize> = HashMap::new();
+    let mut intern = |line: &'a [u8]| {
+        let next = ids.len();
+        *ids.entry(line).or_insert(next)
+    };
+    let old_ids: Vec<usize> = old.iter().map(|line| intern(line)).collect();
+    let new_ids: Vec<usize> = new.iter().map(|line| intern(line)).collect();
+
+    let mut edits = Vec::with_capacity(old.len().max(new.len()));
+    diff_trimmed(algorithm, &old_ids, &new_ids, 0, 0, &mut edits);
+    edits
+}
+
+/// Appends the edits for `a` against `b`, whose first lines are `a_offset`
+/// and `b_offset`. The common prefix and suffix never take part in the search.
+fn diff_trimmed(algorithm: &dyn DiffAlgorithm, a: &[usize], b: &[usize], a_offset: usize, b_offset: usize, edits: &mut Vec<Edit>) {
+    let prefix = a.iter()
+        .zip(b.iter())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let suffix = a[prefix..].iter().rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    for i in 0..prefix {
+        edits.push(Edit::Equal { old: a_offset + i, new: b_offset + i });
+    }
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    for edit in algorithm.diff(a_mid, b_mid) {
+        edits.push(shift(edit, a_offset + prefix, b_offset + prefix));
+    }
+
+    for i in 0..suffix {
+        edits.push(Edit::Equal {
+            old: a_offset + a.len() - suffix + i,
+            new: b_offset + b.len() - suffix + i,
+        });
+    }
+}
+
+fn shift(edit: Edit, a_offset: usize, b_offset: usize) -> Edit {
+    match edit {
+        Edit::Equal { old, new } => Edit::Equal { old: old + a_offset, new: new + b_offset },
+        Edit::Delete { old } => Edit::Delete { old: old + a_offset },
+        Edit::Insert { new } => Edit::Insert { new: new + b_offset },
+    }
+}
+
+/// Unchanged lines kept around each change in unified diffs and patch ids
+pub const CONTEXT_LINES: usize = 3;
+
+/// A group of nearby changes together with their surrounding context.
+/// Starts are 0-based line offsets into the old and new sides.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub edits: Vec<Edit>,
+}
+
+/// Groups an edit script into hunks with up to `context` unchanged lines
+/// around each change. Changes separated by at most `2 * context` unchanged
+/// lines share a hunk.
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Hunk> {
+    let changes: Vec<usize> = edits.iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal { .. }))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        match groups.last_mut() {
+            Some((_, end)) if i - *end <= 2 * context + 1 => *end = i,
+            _ => groups.push((i, i)),
+        }
+    }
+
+    groups.into_iter().map(|(first, last)| {
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(edits.len());
+        let slice = edits[start..end].to_vec();
+
+        // Line offsets of the first edit in the hunk on each side
+        let (old_start, new_start) = edits[..start].iter().fold((0, 0), |(old, new), edit| match edit {
+            Edit::Equal { .. } => (old + 1, new + 1),
+            Edit::Delete { .. } => (old + 1, new),
+            Edit::Insert { .. } => (old, new + 1),
+        });
+        let old_len = slice.iter().filter(|edit| !matches!(edit, Edit::Insert { .. })).count();
+        let new_len = slice.iter().filter(|edit| !matches!(edit, Edit::Delete { .. })).count();
+
+        Hunk { old_start, old_len, new_start, new_len, edits: slice }
+    }).collect()
+}
+
+/// Renders hunks as unified diff text (without the file headers)
+pub fn write_unified(out: &mut dyn Write, old: &[&[u8]], new: &[&[u8]], hunks: &[Hunk]) -> io::Result<()> {
+    // An empty range is addressed by the line before it
+    let range = |start: usize, len: usize| if len == 0 { start } else { start + 1 };
+
+    for hunk in hunks {
+        writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            range(hunk.old_start, hunk.old_len),
+            hunk.old_len,
+            range(hunk.new_start, hunk.new_len),
+            hunk.new_len,
+        )?;
+        for edit in &hunk.edits {
+            let (prefix, line) = match *edit {
+                Edit::Equal { old: i, .. } => (b' ', old[i]),
+                Edit::Delete { old: i } => (b'-', old[i]),
+                Edit::Insert { new: j } => (b'+', new[j]),
+            };
+            out.write_all(&[prefix])?;
+            out.write_all(line)?;
+            if !line.ends_with(b"\n") {
+                out.write_all(b"\n\\ No newline at end of file\n")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Myers' O(ND) greedy algorithm: the shortest edit script
+pub struct Myers;
+
+impl DiffAlgorithm for Myers {
+    fn diff(&self, old: &[usize], new: &[usize]) -> Vec<Edit> {
+        myers(old, new)
+    }
+}
+
+/// Patience diff: lines occurring exactly once on each side are matched in
+/// their longest common order, and the gaps between them are diffed
+/// recursively. Gaps without such lines fall back to Myers.
+pub struct Patience;
+
+impl DiffAlgorithm for Patience {
+    fn diff(&self, old: &[usize], new: &[usize]) -> Vec<Edit> {
+        let anchors = unique_anchors(old, new);
+        if anchors.is_empty() {
+            return myers(old, new);
+        }
+
+        let mut edits = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        for (anchor_i, anchor_j) in anchors {
+            diff_trimmed(self, &old[i..anchor_i], &new[j..anchor_j], i, j, &mut edits);
+            edits.push(Edit::Equal { old: anchor_i, new: anchor_j });
+            i = anchor_i + 1;
+            j = anchor_j + 1;
+        }
+        diff_trimmed(self, &old[i..], &new[j..], i, j, &mut edits);
+        edits
+    }
+}
+
+/// Pairs of positions of the lines unique to both sides, reduced to the
+/// longest run that is increasing on both sides
+fn unique_anchors(a: &[usize], b: &[usize]) -> Vec<(usize, usize)> {
+    // Per line: occurrences in a, its position in a, occurrences in b, its position in b
+    let mut counts: HashMap<usize, (usize, usize, usize, usize)> = HashMap::new();
+    for (i, line) in a.iter().enumerate() {
+        let count = counts.entry(*line).or_default();
+        count.0 += 1;
+        count.1 = i;
+    }
+    for (j, line) in b.iter().enumerate() {
+        if let Some(count) = counts.get_mut(line) {
+            count.2 += 1;
+            count.3 = j;
+        }
+    }
+    let mut pairs: Vec<(usize, usize)> = counts.into_values()
+        .filter(|&(in_a, _, in_b, _)| in_a == 1 && in_b == 1)
+        .map(|(_, i, _, j)| (i, j))
+        .collect();
+    pairs.sort_by_key(|&(_, j)| j);
+
+    // Patience sorting: piles hold the index of their top pair, and each pair
+    // remembers the top of the pile to its left when it was placed
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = Vec::with_capacity(pairs.len());
+    for (index, &(i, _)) in pairs.iter().enumerate() {
+        let pile = piles.partition_point(|&top| pairs[top].0 < i);
+        previous.push(pile.checked_sub(1).map(|left| piles[left]));
+        if pile == piles.len() {
+            piles.push(index);
+        } else {
+            piles[pile] = index;
+        }
+    }
+
+    let mut anchors = Vec::with_capacity(piles.len());
+    let mut next = piles.last().copied();
+    while let Some(index) = next {
+        anchors.push(pairs[index]);
+        next = previous[index];
+    }
+    anchors.reverse();
+    anchors
+}
+
+/// Lines occurring more often than this are never used as histogram anchors
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// Line comparisons the histogram search may spend per input line before the
+/// rest of the diff falls back to Myers
+const HISTOGRAM_WORK_PER_LINE: usize = 32;
+
+/// Histogram diff: like patience, but anchors on the matching run of lines
+/// that are least frequent in the old side, so repeated lines such as
+/// braces can still anchor when no line is unique. Falls back to Myers when
+/// the sides share no line that is rare enough.
+pub struct Histogram;
+
+impl DiffAlgorithm for Histogram {
+    fn diff(&self, old: &[usize], new: &[usize]) -> Vec<Edit> {
+        let budget = (old.len() + new.len()).saturating_mul(HISTOGRAM_WORK_PER_LINE);
+        BoundedHistogram { budget: Cell::new(budget) }.diff(old, new)
+    }
+}
+
+/// Histogram diff over a shared work budget. Every anchor search scans both
+/// sides, and anchors that split off a line at a time would make that
+/// quadratic, so searches stop once the budget is spent and whatever is left
+/// is diffed with Myers.
+struct BoundedHistogram {
+    budget: Cell<usize>,
+}
+
+impl DiffAlgorithm for BoundedHistogram {
+    fn diff(&self, old: &[usize], new: &[usize]) -> Vec<Edit> {
+        let mut budget = self.budget.get();
+        let run = rarest_run(old, new, &mut budget);
+        self.budget.set(budget);
+        let Some((i, j, len)) = run else {
+            return myers(old, new);
+        };
+
+        let mut edits = Vec::new();
+        diff_trimmed(self, &old[..i], &new[..j], 0, 0, &mut edits);
+        for k in 0..len {
+            edits.push(Edit::Equal { old: i + k, new: j + k });
+        }
+        diff_trimmed(self, &old[i + len..], &new[j + len..], i + len, j + len, &mut edits);
+        edits
+    }
+}
+
+/// The common run (start in a, start in b, length) around the line of b
+/// that occurs least often in a, preferring longer runs and then earlier ones.
+/// Every line visited is charged to `budget`; None once it runs out.
+fn rarest_run(a: &[usize], b: &[usize], budget: &mut usize) -> Option<(usize, usize, usize)> {
+    let mut charge = |cost: usize| -> Option<()> {
+        *budget = budget.checked_sub(cost)?;
+        Some(())
+    };
+    charge(a.len() + b.len())?;
+    let mut positions: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (i, line) in a.iter().enumerate() {
+        positions.entry(*line).or_default().push(i);
+    }
+
+    // Occurrences in a, start in a, start in b and length of the best run so far
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let mut j = 0;
+    while j < b.len() {
+        let Some(occurrences) = positions.get(&b[j]).filter(|p| p.len() <= MAX_CHAIN_LENGTH) else {
+            j += 1;
+            continue;
+        };
+        let mut skip_to = j + 1;
+        for &i in occurrences {
+            let mut start = 0;
+            while start < i.min(j) && a[i - start - 1] == b[j - start - 1] {
+                start += 1;
+            }
+            let mut len = start;
+            while i - start + len < a.len() && j - start + len < b.len() && a[i - start + len] == b[j - start + len] {
+                len += 1;
+            }
+            charge(start + len + 1)?;
+            let better = match best {
+                None => true,
+                Some((count, _, _, best_len)) => occurrences.len() < count || (occurrences.len() == count && len > best_len),
+            };
+            if better {
+                best = Some((occurrences.len(), i - start, j - start, len));
+            }
+            skip_to = skip_to.max(j - start + len);
+        }
+        // Lines inside a run already found would only find it again
+        j = if occurrences.len() == 1 { skip_to } else { j + 1 };
+    }
+    best.map(|(_, i, j, len)| (i, j, len))
+}
+
+/// Myers' O(ND) algorithm in linear space: find the middle snake of an
+/// optimal path, then solve the two halves on either side of it
+fn myers(a: &[usize], b: &[usize]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(a.len().max(b.len()));
+    myers_into(a, b, 0, 0, &mut edits);
+    // Halves meet in no particular order; show removals before additions
+    // within each run of changes
+    for run in edits.split_mut(|edit| matches!(edit, Edit::Equal { .. })) {
+        run.sort_by_key(|edit| matches!(edit, Edit::Insert { .. }));
+    }
+    edits
+}
+
+fn myers_into(a: &[usize], b: &[usize], a_offset: usize, b_offset: usize, edits: &mut Vec<Edit>) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    for i in 0..prefix {
+        edits.push(Edit::Equal { old: a_offset + i, new: b_offset + i });
+    }
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    let (a_start, b_start) = (a_offset + prefix, b_offset + prefix);
+    if a_mid.is_empty() {
+        edits.extend((0..b_mid.len()).map(|j| Edit::Insert { new: b_start + j }));
+    } else if b_mid.is_empty() {
+        edits.extend((0..a_mid.len()).map(|i| Edit::Delete { old: a_start + i }));
+    } else {
+        // Both sides differ at their first and last lines, so the snake
+        // always leaves each half with fewer edits than the whole
+        let (x, y, u, v) = middle_snake(a_mid, b_mid);
+        myers_into(&a_mid[..x], &b_mid[..y], a_start, b_start, edits);
+        for i in 0..u - x {
+            edits.push(Edit::Equal { old: a_start + x + i, new: b_start + y + i });
+        }
+        myers_into(&a_mid[u..], &b_mid[v..], a_start + u, b_start + v, edits);
+    }
+
+    for i in 0..suffix {
+        edits.push(Edit::Equal {
+            old: a_offset + a.len() - suffix + i,
+            new: b_offset + b.len() - suffix + i,
+        });
+    }
+}
+
+/// Searches forwards from the start and backwards from the end at once and
+/// returns the snake `(x, y)..(u, v)` where the two searches meet. Past
+/// `max_cost` rounds, as with very different inputs, it settles for the
+/// point the forward search got furthest to, giving up minimality to keep
+/// the work close to linear.
+fn middle_snake(a: &[usize], b: &[usize]) -> (usize, usize, usize, usize) {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max_cost = ((n + m + 3) as f64).sqrt().max(256.0) as isize;
+    let max_d = ((n + m + 1) / 2).min(max_cost);
+    let offset = max_d + 1;
+    // Furthest x on each diagonal k = x - y, forwards and (in reversed coordinates) backwards
+    let mut forward = vec![0isize; (2 * offset + 1) as usize];
+    let mut backward = vec![0isize; (2 * offset + 1) as usize];
+    let at = |k: isize| (k + offset) as usize;
+
+    for d in 0..=max_d {
+        for k in (-d..=d).rev().step_by(2) {
+            let start_x = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                forward[at(k + 1)]
+            } else {
+                forward[at(k - 1)] + 1
+            };
+            let (mut x, mut y) = (start_x, start_x - k);
+            let start_y = y;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[at(k)] = x;
+            let reverse_k = delta - k;
+            if odd && reverse_k.abs() < d && x + backward[at(reverse_k)] >= n {
+                return (start_x as usize, start_y as usize, x as usize, y as usize);
+            }
+        }
+
+        for k in (-d..=d).rev().step_by(2) {
+            let start_x = if k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) {
+                backward[at(k + 1)]
+            } else {
+                backward[at(k - 1)] + 1
+            };
+            let (mut x, mut y) = (start_x, start_x - k);
+            let start_y = y;
+            while x < n && y < m && a[(n - 1 - x) as usize] == b[(m - 1 - y) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[at(k)] = x;
+            let forward_k = delta - k;
+            if !odd && forward_k.abs() <= d && x + forward[at(forward_k)] >= n {
+                return ((n - x) as usize, (m - y) as usize, (n - start_x) as usize, (m - start_y) as usize);
+            }
+        }
+    }
+
+    // Too expensive: split where the forward search made the most progress
+    let (x, y) = (-max_d..=max_d).step_by(2)
+        .map(|k| (forward[at(k)], forward[at(k)] - k))
+        .filter(|&(x, y)| x <= n && (0..=m).contains(&y))
+        .max_by_key(|&(x, y)| x + y)
+        .expect("at least one diagonal");
+    (x as usize, y as usize, x as usize, y as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(old: &[&[u8]], new: &[&[u8]], edits: &[Edit]) -> Vec<Vec<u8>> {
+        let mut result = Vec::new();
+        for edit in edits {
+            match *edit {
+                Edit::Equal { old: i, .. } => result.push(old[i].to_vec()),
+                Edit::Insert { new: j } => result.push(new[j].to_vec()),
+                Edit::Delete { .. } => {}
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_split_lines() {
+        assert_eq!(split_lines(b"a\nb\n"), vec![&b"a\n"[..], &b"b\n"[..]]);
+        assert_eq!(split_lines(b"a\nb"), vec![&b"a\n"[..], &b"b"[..]]);
+        assert!(split_lines(b"").is_empty());
+    }
+
+    #[test]
+    fn test_diff_lines_roundtrip() {
+        let old_data = b"a\nb\nc\nd\ne\n";
+        let new_data = b"a\nc\nd\nx\ne\nf\n";
+        let old = split_lines(old_data);
+        let new = split_lines(new_data);
+        let edits = diff_lines(&old, &new);
+
+        let rebuilt: Vec<u8> = apply(&old, &new, &edits).concat();
+        assert_eq!(rebuilt, new_data.to_vec());
+
+        let deletes = edits.iter().filter(|e| matches!(e, Edit::Delete { .. })).count();
+        let inserts = edits.iter().filter(|e| matches!(e, Edit::Insert { .. })).count();
+        assert_eq!(deletes, 1);
+        assert_eq!(inserts, 2);
+    }
+
+    #[test]
+    fn test_hunks_and_unified_output() -> io::Result<()> {
+        let old_data = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new_data = b"1\n2\nthree\n4\n5\n6\n7\n8\n9\n10";
+        let old = split_lines(old_data);
+        let new = split_lines(new_data);
+        let hunks = hunks(&diff_lines(&old, &new), 1);
+        assert_eq!(hunks.len(), 2);
+
+        let mut out = Vec::new();
+        write_unified(&mut out, &old, &new, &hunks)?;
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+@@ -2,3 +2,3 @@
+ 2
+-3
++three
+ 4
+@@ -9,2 +9,2 @@
+ 9
+-10
++10
+\\ No newline at end of file
+");
+        Ok(())
+    }
+
+    #[test]
+    fn test_hunks_for_new_file() {
+        let new = split_lines(b"a\nb\n");
+        let hunks = hunks(&diff_lines(&[], &new), 3);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].old_len, hunks[0].new_start, hunks[0].new_len), (0, 0, 0, 2));
+    }
+
+    fn unified(algorithm: Algorithm, old_data: &[u8], new_data: &[u8]) -> io::Result<String> {
+        let old = split_lines(old_data);
+        let new = split_lines(new_data);
+        let mut out = Vec::new();
+        write_unified(&mut out, &old, &new, &hunks(&algorithm.diff_lines(&old, &new), 3))?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    /// A function replaced by one with the same brace structure, next to
+    /// one that survives unchanged
+    const REFACTOR_OLD: &[u8] = b"\
+int compute(int x)
+{
+    if (x > 10)
+    {
+        return x * 2;
+    }
+    else
+    {
+        return 0;
+    }
+}
+
+int helper(void)
+{
+    int base = 40;
+    base += 2;
+    return base;
+}
+";
+
+    const REFACTOR_NEW: &[u8] = b"\
+int helper(void)
+{
+    int base = 40;
+    base += 2;
+    return base;
+}
+
+void log_value(int v)
+{
+    if (v)
+    {
+        printf(\"%d\", v);
+    }
+    else
+    {
+        puts(\"none\");
+    }
+}
+";
+
+    #[test]
+    fn test_golden_refactor_myers() -> io::Result<()> {
+        // The shortest script matches braces and keeps helper() as a rewrite
+        assert_eq!(unified(Algorithm::Myers, REFACTOR_OLD, REFACTOR_NEW)?, "\
+@@ -1,18 +1,18 @@
+-int compute(int x)
++int helper(void)
+ {
+-    if (x > 10)
++    int base = 40;
++    base += 2;
++    return base;
++}
++
++void log_value(int v)
++{
++    if (v)
+     {
+-        return x * 2;
++        printf(\"%d\", v);
+     }
+     else
+     {
+-        return 0;
++        puts(\"none\");
+     }
+-}
+-
+-int helper(void)
+-{
+-    int base = 40;
+-    base += 2;
+-    return base;
+ }
+");
+        Ok(())
+    }
+
+    #[test]
+    fn test_golden_refactor_patience() -> io::Result<()> {
+        let expected = "\
+@@ -1,18 +1,18 @@
+-int compute(int x)
+-{
+-    if (x > 10)
+-    {
+-        return x * 2;
+-    }
+-    else
+-    {
+-        return 0;
+-    }
+-}
+-
+ int helper(void)
+ {
+     int base = 40;
+     base += 2;
+     return base;
++}
++
++void log_value(int v)
++{
++    if (v)
++    {
++        printf(\"%d\", v);
++    }
++    else
++    {
++        puts(\"none\");
++    }
+ }
+";
+        assert_eq!(unified(Algorithm::Patience, REFACTOR_OLD, REFACTOR_NEW)?, expected);
+        assert_eq!(unified(Algorithm::Histogram, REFACTOR_OLD, REFACTOR_NEW)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_algorithms_roundtrip() {
+        let cases: [(&[u8], &[u8]); 6] = [
+            (b"a\nb\nc\nd\ne\n", b"a\nc\nd\nx\ne\nf\n"),
+            (b"}\n}\n}\n", b"}\nx\n}\n"),
+            (b"a\nb\na\nb\n", b"b\na\nb\na\n"),
+            (b"x\ny\nz\n", b"z\ny\nx\n"),
+            (b"", b"new\n"),
+            (REFACTOR_OLD, REFACTOR_NEW),
+        ];
+        for algorithm in [Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram] {
+            for (old_data, new_data) in cases {
+                let old = split_lines(old_data);
+                let new = split_lines(new_data);
+                let edits = algorithm.diff_lines(&old, &new);
+                assert_eq!(apply(&old, &new, &edits).concat(), new_data.to_vec(), "{} on {:?}", algorithm, old_data);
+                // Every old line is accounted for exactly once, in order
+                let olds: Vec<usize> = edits.iter().filter_map(|edit| match *edit {
+                    Edit::Equal { old, .. } | Edit::Delete { old } => Some(old),
+                    Edit::Insert { .. } => None,
+                }).collect();
+                assert_eq!(olds, (0..old.len()).collect::<Vec<_>>(), "{}", algorithm);
+            }
+        }
+    }
+
+    #[test]
+    fn test_myers_is_minimal_and_scales() {
+        let changes = |edits: &[Edit]| edits.iter().filter(|edit| !matches!(edit, Edit::Equal { .. })).count();
+        // Small pseudo-random inputs over a tiny alphabet, checked against the LCS
+        let mut seed = 7u64;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+        for _ in 0..200 {
+            let a: Vec<usize> = (0..next() % 12).map(|_| next() % 4).collect();
+            let b: Vec<usize> = (0..next() % 12).map(|_| next() % 4).collect();
+            let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+            for i in (0..a.len()).rev() {
+                for j in (0..b.len()).rev() {
+                    lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+                }
+            }
+            assert_eq!(changes(&myers(&a, &b)), a.len() + b.len() - 2 * lcs[0][0], "{:?} {:?}", a, b);
+        }
+
+        // Tens of thousands of lines with scattered edits, and two unrelated files
+        let old: Vec<Vec<u8>> = (0..30_000).map(|i| format!("line {}\n", i).into_bytes()).collect();
+        let mut new = old.clone();
+        for i in (0..30_000).step_by(97) {
+            new[i] = format!("changed {}\n", i).into_bytes();
+        }
+        new.truncate(29_000);
+        let old: Vec<&[u8]> = old.iter().map(Vec::as_slice).collect();
+        let new: Vec<&[u8]> = new.iter().map(Vec::as_slice).collect();
+        let edits = diff_lines(&old, &new);
+        assert_eq!(apply(&old, &new, &edits).concat(), new.concat());
+        assert_eq!(changes(&edits), 2 * 299 + 1000);
+
+        let other: Vec<Vec<u8>> = (0..20_000).map(|i| format!("other {}\n", i).into_bytes()).collect();
+        let other: Vec<&[u8]> = other.iter().map(Vec::as_slice).collect();
+        let edits = diff_lines(&old[..20_000], &other);
+        assert_eq!(apply(&old[..20_000], &other, &edits).concat(), other.concat());
+    }
+
+    #[test]
+    fn test_histogram_scales() {
+        // Every other line changed: each anchor search would split off a
+        // single line, so the search has to give up and leave it to Myers
+        let old: Vec<Vec<u8>> = (0..20_000).map(|i| format!("line {}\n", i).into_bytes()).collect();
+        let new: Vec<Vec<u8>> = (0..20_000)
+            .map(|i| if i % 2 == 0 { format!("line {}\n", i) } else { format!("changed {}\n", i) }.into_bytes())
+            .collect();
+        let old: Vec<&[u8]> = old.iter().map(Vec::as_slice).collect();
+        let new: Vec<&[u8]> = new.iter().map(Vec::as_slice).collect();
+        let edits = Algorithm::Histogram.diff_lines(&old, &new);
+        assert_eq!(apply(&old, &new, &edits).concat(), new.concat());
+        assert_eq!(edits.iter().filter(|edit| !matches!(edit, Edit::Equal { .. })).count(), 20_000);
+    }
+
+    #[test]
+    fn test_parse_algorithm() -> io::Result<()> {
+        assert_eq!(Algorithm::parse("Patience")?, Algorithm::Patience);
+        assert_eq!(Algorithm::parse("default")?, Algorithm::Myers);
+        assert_eq!(Algorithm::parse(" histogram ")?.to_string(), "histogram");
+        assert_eq!(Algorithm::parse("fast").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_lines_empty_sides() {
+        let lines = split_lines(b"one\ntwo\n");
+        assert_eq!(diff_lines(&[], &lines), vec![Edit::Insert { new: 0 }, Edit::Insert { new: 1 }]);
+        assert_eq!(diff_lines(&lines, &[]), vec![Edit::Delete { old: 0 }, Edit::Delete { old: 1 }]);
+        assert!(diff_lines(&[], &[]).is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+// Per-file line counts between two trees, as printed under a merge or pull request
+
+use std::io::{self, Write};
+use crate::cobra::core::{
+    diff::{diff_lines, split_lines, Edit},
+    object::Object,
+    oid::Oid,
+    repository::Repository,
+    tree::diff_trees,
+};
+use crate::cobra::messages;
+
+/// Widest diffstat graph, in characters
+const GRAPH_WIDTH: usize = 50;
+
+/// How much one file changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStat {
+    pub path: String,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// Old and new size in bytes when either side is binary
+    pub binary: Option<(usize, usize)>,
+}
+
+/// Counts inserted and deleted lines for every path that differs between
+/// two trees. A missing tree counts as empty.
+pub fn diffstat(repo: &Repository, old_tree: Option<Oid>, new_tree: Option<Oid>) -> io::Result<Vec<FileStat>> {
+    let mut stats = Vec::new();
+    for change in diff_trees(&repo.git_dir, old_tree, new_tree)? {
+        let old = match &change.old {
+            Some(entry) => Object::read_blob(&repo.git_dir, entry.hash)?,
+            None => Vec::new(),
+        };
+        let new = match &change.new {
+            Some(entry) => Object::read_blob(&repo.git_dir, entry.hash)?,
+            None => Vec::new(),
+        };
+        let path = change.path.display().to_string();
+        if old.contains(&0) || new.contains(&0) {
+            stats.push(FileStat { path, insertions: 0, deletions: 0, binary: Some((old.len(), new.len())) });
+            continue;
+        }
+        let edits = diff_lines(&split_lines(&old), &split_lines(&new));
+        stats.push(FileStat {
+            path,
+            insertions: edits.iter().filter(|edit| matches!(edit, Edit::Insert { .. })).count(),
+            deletions: edits.iter().filter(|edit| matches!(edit, Edit::Delete { .. })).count(),
+            binary: None,
+        });
+    }
+    Ok(stats)
+}
+
+/// Prints one line per changed file with a +/- graph, then the totals
+pub fn write_diffstat(out: &mut dyn Write, stats: &[FileStat]) -> io::Result<()> {
+    let name_width = stats.iter().map(|stat| stat.path.chars().count()).max().unwrap_or(0);
+    let most = stats.iter().map(|stat| stat.insertions + stat.deletions).max().unwrap_or(0);
+    let count_width = most.to_string().len();
+    for stat in stats {
+        if let Some((old_size, new_size)) = stat.binary {
+            writeln!(out, " {:<name_width$} | Bin {} -> {} bytes", stat.path, old_size, new_size)?;
+            continue;
+        }
+        let changed = stat.insertions + stat.deletions;
+        // Scale the graph down when the largest change does not fit
+        let (plus, minus) = if most > GRAPH_WIDTH {
+            let scale = |count: usize| if count == 0 { 0 } else { (count * GRAPH_WIDTH / most).max(1) };
+            (scale(stat.insertions), scale(stat.deletions))
+        } else {
+            (stat.insertions, stat.deletions)
+        };
+        writeln!(
+            out,
+            " {:<name_width$} | {:>count_width$} {}{}",
+            stat.path, changed, "+".repeat(plus), "-".repeat(minus),
+        )?;
+    }
+
+    let insertions = stats.iter().map(|stat| stat.insertions).sum();
+    let deletions = stats.iter().map(|stat| stat.deletions).sum();
+    writeln!(out, " {}", messages::diffstat_summary(stats.len(), insertions, deletions))
+}
@@ -0,0 +1,183 @@
+// Repository change notifications for embedding applications
+
+use std::cell::RefCell;
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+use crate::cobra::core::oid::Oid;
+
+/// A change to repository state, emitted after it has been written to disk
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepositoryEvent {
+    /// A reference (branch, HEAD, tag, stash) now points somewhere else;
+    /// `new` is ZERO_HASH when the ref was deleted
+    RefUpdated {
+        name: String,
+        old: Option<String>,
+        new: String,
+    },
+    /// The index file was rewritten
+    IndexChanged,
+    /// A new object was added to the object store
+    ObjectWritten {
+        hash: Oid,
+        kind: &'static str,
+    },
+    /// Files in the working directory were created, rewritten or removed
+    WorktreeFilesChanged {
+        paths: Vec<PathBuf>,
+    },
+    /// An ObjectTransaction finished: its objects are durable and its refs
+    /// moved. Sent after the individual ObjectWritten and RefUpdated events.
+    TransactionCommitted {
+        /// Objects that were new to the store
+        objects: Vec<Oid>,
+        refs: Vec<String>,
+    },
+}
+
+/// Receives repository events. Implementations use interior mutability
+/// if they need to record state.
+pub trait RepositoryObserver {
+    fn on_event(&self, event: &RepositoryEvent);
+}
+
+/// An observer that ignores every event
+pub struct NoopObserver;
+
+impl RepositoryObserver for NoopObserver {
+    fn on_event(&self, _event: &RepositoryEvent) {}
+}
+
+/// A shared list of observers. Cloning the bus shares the subscriptions,
+/// so a `RefStore` created from a `Repository` reports to the same observers.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    observers: Rc<RefCell<Vec<Box<dyn RepositoryObserver>>>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus::default()
+    }
+
+    /// Registers an observer for all future events
+    pub fn subscribe(&self, observer: Box<dyn RepositoryObserver>) {
+        self.observers.borrow_mut().push(observer);
+    }
+
+    /// Delivers an event to every registered observer in registration order
+    pub fn emit(&self, event: RepositoryEvent) {
+        for observer in self.observers.borrow().iter() {
+            observer.on_event(&event);
+        }
+    }
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("observers", &self.observers.borrow().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::core::index::IndexEntry;
+    use crate::cobra::core::object::Object;
+    use crate::cobra::test_support::temp_repo;
+
+    struct Recorder {
+        events: Rc<RefCell<Vec<RepositoryEvent>>>,
+    }
+
+    impl RepositoryObserver for Recorder {
+        fn on_event(&self, event: &RepositoryEvent) {
+            self.events.borrow_mut().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn test_commit_event_sequence() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        repo.subscribe(Box::new(Recorder { events: events.clone() }));
+
+        // Stage a file
+        let file_path = temp_dir.path().join("test.txt");
+        fs::write(&file_path, "test content")?;
+        let blob = Object::new_blob(b"test content".to_vec());
+        let blob_hash = repo.write_object(&blob)?;
+        repo.add_to_index(IndexEntry::new("test.txt".into(), blob_hash, fs::metadata(&file_path)?))?;
+
+        let commit_hash = commit(&repo, "Initial commit")?;
+        let tree_hash = match Object::read_from_objects_dir(&repo.git_dir, commit_hash)? {
+            Object::Commit { tree, .. } => tree,
+            _ => panic!("Expected commit object"),
+        };
+
+        assert_eq!(*events.borrow(), vec![
+            RepositoryEvent::ObjectWritten { hash: blob_hash, kind: "blob" },
+            RepositoryEvent::IndexChanged,
+            RepositoryEvent::ObjectWritten { hash: tree_hash, kind: "tree" },
+            RepositoryEvent::ObjectWritten { hash: commit_hash, kind: "commit" },
+            RepositoryEvent::RefUpdated {
+                name: "refs/heads/main".to_string(),
+                old: None,
+                new: commit_hash.to_string(),
+            },
+            RepositoryEvent::TransactionCommitted {
+                objects: vec![tree_hash, commit_hash],
+                refs: vec!["refs/heads/main".to_string()],
+            },
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ref_deletions_are_reported() -> io::Result<()> {
+        use crate::cobra::core::reflog::ZERO_HASH;
+
+        let (_temp_dir, repo) = temp_repo()?;
+        let head = commit(&repo, "Initial commit")?;
+        let ref_store = repo.ref_store();
+        ref_store.create_branch("feature")?;
+        ref_store.create_tag("v1")?;
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        repo.subscribe(Box::new(Recorder { events: events.clone() }));
+        ref_store.delete_branch("feature")?;
+        ref_store.delete_tag("v1")?;
+
+        let deleted = |name: &str| RepositoryEvent::RefUpdated {
+            name: name.to_string(),
+            old: Some(head.to_string()),
+            new: ZERO_HASH.to_string(),
+        };
+        assert_eq!(*events.borrow(), vec![deleted("refs/heads/feature"), deleted("refs/tags/v1")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_existing_objects_are_not_reported() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        repo.subscribe(Box::new(Recorder { events: events.clone() }));
+
+        let blob = Object::new_blob(b"same".to_vec());
+        repo.write_object(&blob)?;
+        repo.write_object(&blob)?;
+
+        assert_eq!(events.borrow().len(), 1);
+
+        Ok(())
+    }
+}
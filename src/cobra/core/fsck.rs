@@ -0,0 +1,391 @@
+// Object store consistency checks
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use crate::cobra::core::gc::{reachable_objects, scan_loose_objects};
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::ref_store::{RefUpdate, STASH_REF};
+use crate::cobra::core::reflog;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::utils::hash::hash_object;
+
+/// An unreachable object that no other unreachable object refers to
+/// either, i.e. the tip of some lost piece of history
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dangling {
+    pub hash: Oid,
+    pub kind: &'static str,
+}
+
+/// A loose object stored under a name that is not the hash of its contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct Misplaced {
+    /// The name its path gives it
+    pub stored_as: Oid,
+    /// The hash of its contents, and so the name it should have
+    pub hash: Oid,
+    pub kind: &'static str,
+}
+
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// Dangling objects sorted by hash
+    pub dangling: Vec<Dangling>,
+    /// Misfiled objects sorted by where they are stored
+    pub misplaced: Vec<Misplaced>,
+}
+
+/// Hashes every loose object to make sure it is stored where its name says,
+/// then finds the objects that nothing reaches. Refs, the index, reflogs
+/// (including the stash log) and merge or rebase state count as roots, as
+/// in gc. Only the tips of unreachable chains are reported: a lost commit
+/// is dangling, its tree and parents are not. Misplaced objects cannot be
+/// found by name, so anything only they refer to may show up as dangling
+/// until they are moved back with `fix_placement`.
+pub fn check(repo: &Repository) -> io::Result<FsckReport> {
+    // Cached closures would hide missing objects, so fsck walks everything
+    let reachable = reachable_objects(repo, None)?;
+    let mut unreachable = HashMap::new();
+    let mut misplaced = Vec::new();
+    scan_loose_objects(&repo.git_dir, |stored_as, content| {
+        let object = Object::parse_loose(&content)?;
+        let hash: Oid = hash_object(&content).parse()?;
+        if hash != stored_as {
+            misplaced.push(Misplaced { stored_as, hash, kind: object.type_str() });
+        } else if !reachable.contains(&hash) {
+            unreachable.insert(hash, object);
+        }
+        Ok(())
+    })?;
+
+    let mut referenced = HashSet::new();
+    for object in unreachable.values() {
+        match object {
+            Object::Commit { tree, parents, .. } => {
+                referenced.insert(*tree);
+                referenced.extend(parents.iter().copied());
+            }
+            Object::Tree(entries) => referenced.extend(entries.iter().map(|entry| entry.hash)),
+            Object::Blob(_) => {}
+        }
+    }
+
+    let mut dangling: Vec<Dangling> = unreachable.iter()
+        .filter(|(hash, _)| !referenced.contains(*hash))
+        .map(|(&hash, object)| Dangling { hash, kind: object.type_str() })
+        .collect();
+    dangling.sort_by_key(|object| object.hash);
+    Ok(FsckReport { dangling, misplaced })
+}
+
+/// Moves misplaced objects to the path their contents call for. When an
+/// object already exists there, the misfiled copy is simply removed.
+pub fn fix_placement(repo: &Repository, misplaced: &[Misplaced]) -> io::Result<()> {
+    for object in misplaced {
+        let from = object.stored_as.loose_path(&repo.git_dir);
+        let to = object.hash.loose_path(&repo.git_dir);
+        if to.exists() {
+            fs::remove_file(&from)?;
+            continue;
+        }
+        if let Some(dir) = to.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::rename(&from, &to)?;
+    }
+    Ok(())
+}
+
+/// Saves dangling objects under `<git_dir>/lost-found` like git does:
+/// commits as `commit/<hash>` holding the hash, blobs as `other/<hash>`
+/// holding their contents and trees as `other/<hash>` holding the hash.
+/// Returns the files written.
+pub fn write_lost_found(repo: &Repository, dangling: &[Dangling]) -> io::Result<Vec<PathBuf>> {
+    let lost_found = repo.git_dir.join("lost-found");
+    let mut written = Vec::new();
+    for object in dangling {
+        let dir = lost_found.join(if object.kind == "commit" { "commit" } else { "other" });
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(object.hash.to_string());
+        match Object::read_from_objects_dir(&repo.git_dir, object.hash)? {
+            Object::Blob(content) => fs::write(&path, content)?,
+            _ => fs::write(&path, format!("{}\n", object.hash))?,
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Branches made up for lost history that no reflog can name
+pub const RECOVERED_PREFIX: &str = "refs/heads/recovered/";
+
+/// A ref `reconstruct_refs` writes
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveredRef {
+    /// Full name, e.g. "refs/heads/main"
+    pub name: String,
+    pub hash: Oid,
+    /// True when the name comes from a surviving reflog
+    pub from_reflog: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct RefReconstruction {
+    /// Refs to create, those named by reflogs first, then the made-up ones
+    /// newest first
+    pub refs: Vec<RecoveredRef>,
+    /// The ref HEAD is to point at, None when HEAD is fine as it is
+    pub head: Option<String>,
+}
+
+/// Works out refs for history that no ref leads to any more, as when
+/// `.cobra/refs` is deleted. Branches and tags whose reflog survives get
+/// back the value it last recorded. Every commit in the store is then read
+/// and its parent links inverted: a commit that is no other commit's parent
+/// is the tip of some history, and each tip nothing points at gets a
+/// `recovered/<short hash>` branch. Stashes are left out, being nobody's
+/// history. HEAD is pointed at the newest tip unless it already names a
+/// ref that exists or is recovered.
+pub fn plan_ref_reconstruction(repo: &Repository) -> io::Result<RefReconstruction> {
+    // Commit hash -> (parents, commit time)
+    let mut commits: HashMap<Oid, (Vec<Oid>, u64)> = HashMap::new();
+    scan_loose_objects(&repo.git_dir, |hash, content| {
+        if let Object::Commit { parents, committer, .. } = Object::parse_loose(&content)? {
+            commits.insert(hash, (parents, committer.timestamp));
+        }
+        Ok(())
+    })?;
+    let stashes: HashSet<Oid> = reflog::read(&repo.git_dir, STASH_REF)?.into_iter()
+        .filter_map(|entry| Oid::from_hex(&entry.new).ok())
+        .collect();
+
+    let ref_store = repo.ref_store();
+    let mut pointed_at: HashMap<Oid, Vec<String>> = HashMap::new();
+    for namespace in ["refs/heads", "refs/tags"] {
+        for (name, hash) in ref_store.list_refs(namespace)? {
+            if let Some(hash) = hash {
+                pointed_at.entry(hash).or_default().push(format!("{}/{}", namespace, name));
+            }
+        }
+    }
+
+    let mut refs = Vec::new();
+    for name in reflog::list(&repo.git_dir)? {
+        if !(name.starts_with("refs/heads/") || name.starts_with("refs/tags/")) || ref_store.read_ref(&name)?.is_some() {
+            continue;
+        }
+        // A log ending in the zero hash belongs to a deleted ref
+        let Some(last) = reflog::read(&repo.git_dir, &name)?.pop() else {
+            continue;
+        };
+        let Ok(hash) = Oid::from_hex(&last.new) else {
+            continue;
+        };
+        if !hash.is_null() && commits.contains_key(&hash) {
+            pointed_at.entry(hash).or_default().push(name.clone());
+            refs.push(RecoveredRef { name, hash, from_reflog: true });
+        }
+    }
+    refs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let parents: HashSet<&Oid> = commits.iter()
+        .filter(|(hash, _)| !stashes.contains(*hash))
+        .flat_map(|(_, (parents, _))| parents)
+        .collect();
+    let mut tips: Vec<(&Oid, u64)> = commits.iter()
+        .filter(|(hash, _)| !parents.contains(hash) && !stashes.contains(*hash))
+        .map(|(hash, (_, time))| (hash, *time))
+        .collect();
+    // Newest first, ties broken by hash so the plan is stable
+    tips.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    for &(&hash, _) in &tips {
+        if !pointed_at.contains_key(&hash) {
+            let name = format!("{}{}", RECOVERED_PREFIX, repo.short_id(hash));
+            pointed_at.entry(hash).or_default().push(name.clone());
+            refs.push(RecoveredRef { name, hash, from_reflog: false });
+        }
+    }
+
+    let head_is_fine = match ref_store.read_head()? {
+        Some(head) => match head.strip_prefix("ref: ") {
+            Some(target) => pointed_at.values().flatten().any(|name| name == target),
+            None => Oid::from_hex(&head).is_ok_and(|head| commits.contains_key(&head)),
+        },
+        None => false,
+    };
+    let head = if head_is_fine {
+        None
+    } else {
+        tips.first()
+            .and_then(|(hash, _)| pointed_at.get(*hash))
+            .and_then(|names| names.iter().find(|name| name.starts_with("refs/heads/")))
+            .cloned()
+    };
+    Ok(RefReconstruction { refs, head })
+}
+
+/// Writes the refs of `plan`, all or none, then points HEAD where it says.
+/// A ref that has appeared since the plan was made is not overwritten.
+pub fn reconstruct_refs(repo: &Repository, plan: &RefReconstruction) -> io::Result<()> {
+    let ref_store = repo.ref_store();
+    let updates: Vec<RefUpdate> = plan.refs.iter()
+        .map(|recovered| RefUpdate {
+            name: recovered.name.clone(),
+            expected: None,
+            new: recovered.hash,
+            reason: "fsck: recovered".to_string(),
+        })
+        .collect();
+    ref_store.update_refs(&updates)?;
+    if let Some(head) = &plan.head {
+        ref_store.attach_head(head, "fsck: recovered")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::commands::log::{self, LogOptions};
+    use crate::cobra::state::{CommandContext, GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_deleted_branch_tip_is_dangling() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = repo.ref_store();
+        commit(&repo, "Initial commit")?;
+        assert!(check(&repo)?.dangling.is_empty());
+
+        ref_store.create_branch("feature")?;
+        ref_store.attach_head("refs/heads/feature", "checkout: moving from main to feature")?;
+        let tip = commit(&repo, "Feature work")?;
+        ref_store.attach_head("refs/heads/main", "checkout: moving from feature to main")?;
+        ref_store.delete_branch("feature")?;
+
+        // The branch's reflog, and HEAD's, still hold the tip until they are expired
+        assert!(check(&repo)?.dangling.is_empty());
+        crate::cobra::core::reflog::write(&repo.git_dir, "refs/heads/feature", &[])?;
+        crate::cobra::core::reflog::write(&repo.git_dir, "HEAD", &[])?;
+        assert_eq!(check(&repo)?.dangling, vec![Dangling { hash: tip, kind: "commit" }]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lost_found_recovers_deleted_branch() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = repo.ref_store();
+        commit(&repo, "Initial commit")?;
+        ref_store.create_branch("feature")?;
+        ref_store.attach_head("refs/heads/feature", "checkout: moving from main to feature")?;
+        let tip = commit(&repo, "Work worth keeping")?;
+        ref_store.attach_head("refs/heads/main", "checkout: moving from feature to main")?;
+        ref_store.delete_branch("feature")?;
+        crate::cobra::core::reflog::write(&repo.git_dir, "refs/heads/feature", &[])?;
+        crate::cobra::core::reflog::write(&repo.git_dir, "HEAD", &[])?;
+        let lost_blob = repo.write_object(&Object::new_blob(b"lost contents\n".to_vec()))?;
+
+        let report = check(&repo)?;
+        let mut written = write_lost_found(&repo, &report.dangling)?;
+        let mut expected = vec![
+            repo.git_dir.join("lost-found/commit").join(tip.to_string()),
+            repo.git_dir.join("lost-found/other").join(lost_blob.to_string()),
+        ];
+        expected.sort();
+        written.sort();
+        assert_eq!(written, expected);
+        assert_eq!(fs::read_to_string(repo.git_dir.join("lost-found/other").join(lost_blob.to_string()))?, "lost contents\n");
+
+        // The saved hash leads back to the lost commit and its message
+        let saved = fs::read_to_string(repo.git_dir.join("lost-found/commit").join(tip.to_string()))?;
+        match Object::read_from_objects_dir(&repo.git_dir, saved.trim().parse()?)? {
+            Object::Commit { message, .. } => assert_eq!(message.trim_end(), "Work worth keeping"),
+            _ => panic!("lost-found entry is not a commit"),
+        }
+        ref_store.create_branch_at("recovered", Some(saved.trim()))?;
+        assert!(check(&repo)?.dangling.iter().all(|object| object.kind == "blob"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fix_placement_moves_misfiled_objects() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let tip = commit(&repo, "Initial commit")?;
+
+        // Misfile the commit under another prefix
+        let hex = tip.to_string();
+        let wrong: Oid = format!("{}{}", if hex.starts_with("00") { "ff" } else { "00" }, &hex[2..]).parse()?;
+        fs::create_dir_all(wrong.loose_path(&repo.git_dir).parent().unwrap())?;
+        fs::rename(tip.loose_path(&repo.git_dir), wrong.loose_path(&repo.git_dir))?;
+
+        let report = check(&repo)?;
+        assert_eq!(report.misplaced, vec![Misplaced { stored_as: wrong, hash: tip, kind: "commit" }]);
+
+        fix_placement(&repo, &report.misplaced)?;
+        assert!(tip.loose_path(&repo.git_dir).is_file());
+        assert!(!wrong.loose_path(&repo.git_dir).exists());
+        let report = check(&repo)?;
+        assert!(report.misplaced.is_empty());
+        assert!(report.dangling.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconstruct_refs_after_refs_are_deleted() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = repo.ref_store();
+        let base = commit(&repo, "Initial commit")?;
+        let main = commit(&repo, "Second commit")?;
+        // A side branch off the first commit, a little newer than main,
+        // whose reflog is gone
+        let Object::Commit { tree, mut committer, .. } = repo.read_object(base)? else {
+            panic!("not a commit");
+        };
+        committer.timestamp += 10;
+        let side = repo.write_object(&Object::new_commit(tree, vec![base], committer.clone(), committer, "Side".to_string()))?;
+        ref_store.update_ref("refs/heads/side", side, "branch: Created from HEAD")?;
+        reflog::write(&repo.git_dir, "refs/heads/side", &[])?;
+        ref_store.create_stash(&repo, Some("Saved work"))?;
+
+        fs::remove_dir_all(repo.git_dir.join("refs"))?;
+        assert!(ref_store.resolve_commit("HEAD").is_err());
+
+        // main is named by its reflog, side only by its hash; HEAD still
+        // names main and is left alone
+        let plan = plan_ref_reconstruction(&repo)?;
+        assert_eq!(plan.refs, vec![
+            RecoveredRef { name: "refs/heads/main".to_string(), hash: main, from_reflog: true },
+            RecoveredRef { name: format!("{}{}", RECOVERED_PREFIX, side.abbrev(7)), hash: side, from_reflog: false },
+        ]);
+        assert_eq!(plan.head, None);
+        reconstruct_refs(&repo, &plan)?;
+        assert_eq!(ref_store.resolve_commit("HEAD")?, main);
+
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        let options = LogOptions { revisions: vec![format!("recovered/{}", side.abbrev(7))], ..LogOptions::default() };
+        log::run(&mut ctx, &options)?;
+        let output = ctx.out.captured();
+        assert!(output.contains(&format!("commit {}", side)) && output.contains(&format!("commit {}", base)), "{}", output);
+        assert!(!output.contains(&main.to_string()), "{}", output);
+
+        // Without branch reflogs or a HEAD worth keeping, HEAD goes to the
+        // newest tip
+        let repo = ctx.repo;
+        fs::remove_dir_all(repo.git_dir.join("refs"))?;
+        fs::remove_dir_all(repo.git_dir.join("logs/refs/heads"))?;
+        ref_store.attach_head("refs/heads/gone", "test")?;
+        let plan = plan_ref_reconstruction(&repo)?;
+        let names: Vec<&str> = plan.refs.iter().map(|recovered| recovered.name.as_str()).collect();
+        let side_branch = format!("{}{}", RECOVERED_PREFIX, side.abbrev(7));
+        assert_eq!(names, vec![side_branch.as_str(), &format!("{}{}", RECOVERED_PREFIX, main.abbrev(7))]);
+        assert_eq!(plan.head.as_deref(), Some(side_branch.as_str()));
+        reconstruct_refs(&repo, &plan)?;
+        assert_eq!(ref_store.resolve_commit("HEAD")?, side);
+        assert!(plan_ref_reconstruction(&repo)?.refs.is_empty());
+        Ok(())
+    }
+}
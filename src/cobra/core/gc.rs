@@ -0,0 +1,458 @@
+// Garbage collection of unreachable loose objects
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use crate::cobra::core::bitmap::{generate, reachable_from, ReachabilityBitmaps};
+use crate::cobra::core::config::Config;
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::reflog;
+use crate::cobra::core::repository::Repository;
+
+/// How long reflog entries (and the commits they mention) are kept by default
+pub const DEFAULT_REFLOG_EXPIRE: &str = "90 days ago";
+
+/// How old an unreachable loose object must be before it is pruned by default
+pub const DEFAULT_PRUNE_EXPIRE: &str = "2 weeks ago";
+
+/// Files holding commits that an interrupted merge or rebase still needs
+const STATE_ANCHORS: &[&str] = &[
+    "MERGE_HEAD",
+    "MERGE_SNAPSHOT",
+    "ORIG_HEAD",
+    "CHERRY_PICK_HEAD",
+    "rebase-merge/orig-head",
+    "rebase-merge/onto",
+    "rebase-apply/orig-head",
+    "rebase-apply/onto",
+];
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Hashes of the loose objects that were deleted
+    pub pruned: Vec<Oid>,
+    /// Number of reflog entries dropped because they were past the expiry
+    pub expired_entries: usize,
+    /// Unreachable objects kept because they are younger than the prune expiry
+    pub kept_recent: usize,
+    /// Commits given a bitmap in the rewritten bitmap file; 0 unless
+    /// gc.writeBitmaps is set
+    pub bitmapped_commits: usize,
+}
+
+/// Parses an expiry such as "90 days ago", "2.weeks.ago", "now" or "never"
+/// into a number of seconds. `never` yields None.
+pub fn parse_expiry(value: &str) -> io::Result<Option<u64>> {
+    let invalid = || io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid expiry date '{}'", value),
+    );
+
+    let normalized = value.trim().to_lowercase().replace('.', " ");
+    match normalized.as_str() {
+        "never" | "false" => return Ok(None),
+        "now" | "all" => return Ok(Some(0)),
+        _ => {}
+    }
+
+    let words: Vec<&str> = normalized.split_whitespace()
+        .filter(|word| *word != "ago")
+        .collect();
+    let (count, unit) = match words.as_slice() {
+        [count] => (*count, "seconds"),
+        [count, unit] => (*count, *unit),
+        _ => return Err(invalid()),
+    };
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let unit_seconds = match unit.trim_end_matches('s') {
+        "second" | "sec" => 1,
+        "minute" | "min" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    Ok(Some(count * unit_seconds))
+}
+
+/// Returns the timestamp before which reflog entries expire, from `gc.reflogExpire`
+fn reflog_cutoff(config: &Config, now: u64) -> io::Result<Option<u64>> {
+    let expiry = config.get("gc.reflogExpire").unwrap_or(DEFAULT_REFLOG_EXPIRE);
+    Ok(parse_expiry(expiry)?.map(|seconds| now.saturating_sub(seconds)))
+}
+
+/// Returns the time before which unreachable objects may be pruned, from
+/// `prune` (the --prune option) or `gc.pruneExpire`
+fn prune_cutoff(config: &Config, prune: Option<&str>, now: u64) -> io::Result<Option<u64>> {
+    let expiry = prune
+        .or_else(|| config.get("gc.pruneExpire"))
+        .unwrap_or(DEFAULT_PRUNE_EXPIRE);
+    Ok(parse_expiry(expiry)?.map(|seconds| now.saturating_sub(seconds)))
+}
+
+/// Drops reflog entries older than `cutoff`. Logs of deleted refs disappear
+/// once their last entry expires.
+pub fn expire_reflogs(git_dir: &Path, cutoff: Option<u64>) -> io::Result<usize> {
+    let cutoff = match cutoff {
+        Some(cutoff) => cutoff,
+        None => return Ok(0),
+    };
+
+    let mut expired = 0;
+    for ref_name in reflog::list(git_dir)? {
+        let entries = reflog::read(git_dir, &ref_name)?;
+        let kept: Vec<_> = entries.iter()
+            .filter(|entry| entry.committer.timestamp >= cutoff)
+            .cloned()
+            .collect();
+        if kept.len() != entries.len() {
+            expired += entries.len() - kept.len();
+            reflog::write(git_dir, &ref_name, &kept)?;
+        }
+    }
+    Ok(expired)
+}
+
+/// Reads the commits named by merge and rebase state files
+fn state_anchors(git_dir: &Path) -> io::Result<Vec<Oid>> {
+    let mut anchors = Vec::new();
+    for name in STATE_ANCHORS {
+        match fs::read_to_string(git_dir.join(name)) {
+            // MERGE_HEAD lists one commit per line for octopus merges
+            Ok(text) => anchors.extend(text.lines().filter_map(|line| Oid::from_hex(line.trim()).ok())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(anchors)
+}
+
+/// The commits at the tips of branches, remote-tracking branches and tags,
+/// a detached HEAD and the stash list. Tags are lightweight, so a tag names
+/// its object directly.
+fn ref_tips(repo: &Repository) -> io::Result<Vec<Oid>> {
+    let ref_store = repo.ref_store();
+    let mut tips = Vec::new();
+    for namespace in ["refs/heads", "refs/remotes", "refs/tags"] {
+        tips.extend(ref_store.list_refs(namespace)?.into_iter().filter_map(|(_, hash)| hash));
+    }
+    if let Some(head) = ref_store.read_ref("HEAD")? {
+        tips.push(head);
+    }
+    for (_, hash) in ref_store.list_stashes()? {
+        tips.push(hash);
+    }
+    Ok(tips)
+}
+
+/// Collects every object reachable from branches, remote-tracking branches,
+/// tags, a detached HEAD, the stash list, the index, the (unexpired) reflogs and in-progress merges or
+/// rebases, taking closures from `bitmaps` where they cover them
+pub fn reachable_objects(repo: &Repository, bitmaps: Option<&ReachabilityBitmaps>) -> io::Result<HashSet<Oid>> {
+    let mut roots = ref_tips(repo)?;
+    for entry in repo.index.entries() {
+        roots.push(entry.hash);
+    }
+    for ref_name in reflog::list(&repo.git_dir)? {
+        for entry in reflog::read(&repo.git_dir, &ref_name)? {
+            roots.extend([&entry.old, &entry.new].into_iter().filter_map(|hash| Oid::from_hex(hash).ok()));
+        }
+    }
+    roots.extend(state_anchors(&repo.git_dir)?);
+    Ok(reachable_from(&repo.git_dir, &roots, bitmaps)?.objects)
+}
+
+/// Lists the hashes of all loose objects in the object store
+pub fn loose_objects(git_dir: &Path) -> io::Result<Vec<Oid>> {
+    let objects_dir = git_dir.join("objects");
+    let mut hashes = Vec::new();
+    if !objects_dir.is_dir() {
+        return Ok(hashes);
+    }
+
+    for dir in fs::read_dir(&objects_dir)? {
+        let dir = dir?;
+        let prefix = dir.file_name().to_string_lossy().to_string();
+        if prefix.len() != 2 || !dir.file_type()?.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(dir.path())? {
+            let name = file?.file_name().to_string_lossy().to_string();
+            if name.len() == 38 {
+                if let Ok(hash) = Oid::from_hex(&format!("{}{}", prefix, name)) {
+                    hashes.push(hash);
+                }
+            }
+        }
+    }
+    hashes.sort();
+    Ok(hashes)
+}
+
+/// Reads every loose object, passing the name it is stored under and its
+/// decompressed contents (header included) to `visit`
+pub fn scan_loose_objects(
+    git_dir: &Path,
+    mut visit: impl FnMut(Oid, Vec<u8>) -> io::Result<()>,
+) -> io::Result<()> {
+    for hash in loose_objects(git_dir)? {
+        let path = hash.loose_path(git_dir);
+        let content = Object::read_loose_file(&path)?;
+        visit(hash, content)?;
+    }
+    Ok(())
+}
+
+/// Modification time of a loose object in seconds since the epoch
+fn object_mtime(path: &Path) -> io::Result<u64> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(modified.duration_since(UNIX_EPOCH).map_or(0, |age| age.as_secs()))
+}
+
+/// Expires old reflog entries, then deletes loose objects nothing refers to.
+/// Unreachable objects newer than the prune expiry are kept, since a command
+/// running concurrently may have written them and not updated a ref yet.
+/// `prune` overrides `gc.pruneExpire`. The reachability bitmaps speed up
+/// the walk; they are deleted before the first object goes, and rewritten
+/// at the end when gc.writeBitmaps is set.
+pub fn collect_garbage(repo: &Repository, now: u64, prune: Option<&str>) -> io::Result<GcReport> {
+    let config = Config::load(&repo.git_dir)?;
+    let expired_entries = expire_reflogs(&repo.git_dir, reflog_cutoff(&config, now)?)?;
+    let cutoff = prune_cutoff(&config, prune, now)?;
+
+    let bitmaps = ReachabilityBitmaps::open(&repo.git_dir);
+    let reachable = reachable_objects(repo, bitmaps.as_ref())?;
+    let mut pruned = Vec::new();
+    let mut kept_recent = 0;
+    for hash in loose_objects(&repo.git_dir)? {
+        if reachable.contains(&hash) {
+            continue;
+        }
+        let path = hash.loose_path(&repo.git_dir);
+        if !cutoff.is_some_and(|cutoff| object_mtime(&path).is_ok_and(|mtime| mtime <= cutoff)) {
+            kept_recent += 1;
+            continue;
+        }
+        if pruned.is_empty() {
+            ReachabilityBitmaps::remove(&repo.git_dir)?;
+        }
+        fs::remove_file(&path)?;
+        // Leave no empty fan-out directories behind
+        if let Some(dir) = path.parent().filter(|dir| fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none())) {
+            fs::remove_dir(dir)?;
+        }
+        pruned.push(hash);
+    }
+
+    let mut bitmapped_commits = 0;
+    if config.get_bool("gc.writeBitmaps").unwrap_or(false) {
+        let written = generate(&repo.git_dir, &ref_tips(repo)?, bitmaps.as_ref())?;
+        written.write(&repo.git_dir)?;
+        bitmapped_commits = written.commit_count();
+    }
+
+    Ok(GcReport { pruned, expired_entries, kept_recent, bitmapped_commits })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::test_support::temp_repo;
+
+    /// Commits once on main, then once more on a branch that gets deleted.
+    /// Returns the deleted branch's tip.
+    fn deleted_branch_repo(repo: &Repository) -> io::Result<Oid> {
+        let ref_store = repo.ref_store();
+        commit(repo, "Initial commit")?;
+        ref_store.create_branch("feature")?;
+        ref_store.attach_head("refs/heads/feature", "checkout: moving from main to feature")?;
+        let tip = commit(repo, "Feature work")?;
+        ref_store.attach_head("refs/heads/main", "checkout: moving from feature to main")?;
+        ref_store.delete_branch("feature")?;
+        Ok(tip)
+    }
+
+    #[test]
+    fn test_parse_expiry() -> io::Result<()> {
+        assert_eq!(parse_expiry("90 days ago")?, Some(90 * 24 * 60 * 60));
+        assert_eq!(parse_expiry("2.weeks.ago")?, Some(14 * 24 * 60 * 60));
+        assert_eq!(parse_expiry("1 hour")?, Some(3600));
+        assert_eq!(parse_expiry("now")?, Some(0));
+        assert_eq!(parse_expiry("never")?, None);
+        assert!(parse_expiry("soon").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_keeps_deleted_branch_within_window() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let tip = deleted_branch_repo(&repo)?;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let report = collect_garbage(&repo, now, None)?;
+
+        assert!(report.pruned.is_empty());
+        assert_eq!(report.expired_entries, 0);
+        assert!(Object::read_from_objects_dir(&repo.git_dir, tip).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_prunes_deleted_branch_after_expiry() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let tip = deleted_branch_repo(&repo)?;
+        fs::write(repo.git_dir.join("config"), "[gc]\n\treflogExpire = 1 day\n\tpruneExpire = 1 day\n")?;
+        let logged = reflog::list(&repo.git_dir)?.iter()
+            .map(|name| reflog::read(&repo.git_dir, name).map(|entries| entries.len()))
+            .sum::<io::Result<usize>>()?;
+
+        // Two days later every entry, the deletion included, is past the window
+        let later = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() + 2 * 24 * 60 * 60;
+        let report = collect_garbage(&repo, later, None)?;
+
+        assert_eq!(report.expired_entries, logged);
+        assert_eq!(report.pruned, vec![tip]);
+        assert!(Object::read_from_objects_dir(&repo.git_dir, tip).is_err());
+        assert!(reflog::list(&repo.git_dir)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_keeps_commits_reachable_only_from_tags_and_remotes() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = repo.ref_store();
+        commit(&repo, "Initial commit")?;
+        ref_store.create_branch("feature")?;
+        ref_store.attach_head("refs/heads/feature", "checkout: moving from main to feature")?;
+        let tip = commit(&repo, "Feature work")?;
+        ref_store.create_tag("v1")?;
+        ref_store.attach_head("refs/heads/main", "checkout: moving from feature to main")?;
+        ref_store.delete_branch("feature")?;
+        fs::write(repo.git_dir.join("config"), "[gc]\n\treflogExpire = now\n")?;
+
+        let report = collect_garbage(&repo, now() + 60, Some("now"))?;
+        assert!(report.pruned.is_empty(), "{:?}", report.pruned);
+        assert!(Object::read_from_objects_dir(&repo.git_dir, tip).is_ok());
+        assert_eq!(ref_store.resolve_commit("v1")?, tip);
+
+        // Fetched history is kept by its remote-tracking branch alone
+        ref_store.update_ref("refs/remotes/origin/feature", tip, "fetch")?;
+        ref_store.delete_tag("v1")?;
+        let report = collect_garbage(&repo, now() + 60, Some("now"))?;
+        assert!(report.pruned.is_empty(), "{:?}", report.pruned);
+        Ok(())
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// Backdates a loose object's mtime by `seconds`
+    fn age(repo: &Repository, hash: Oid, seconds: u64) -> io::Result<()> {
+        let then = std::time::SystemTime::now() - std::time::Duration::from_secs(seconds);
+        fs::File::options()
+            .append(true)
+            .open(hash.loose_path(&repo.git_dir))?
+            .set_modified(then)
+    }
+
+    #[test]
+    fn test_gc_keeps_recent_unreachable_objects() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        commit(&repo, "Initial commit")?;
+
+        let old = Object::new_blob(b"old\n".to_vec());
+        let fresh = Object::new_blob(b"fresh\n".to_vec());
+        repo.write_object(&old)?;
+        repo.write_object(&fresh)?;
+        age(&repo, old.hash(), 3 * 7 * 24 * 60 * 60)?;
+
+        let report = collect_garbage(&repo, now(), None)?;
+        assert_eq!(report.pruned, vec![old.hash()]);
+        assert_eq!(report.kept_recent, 1);
+        assert!(fresh.hash().loose_path(&repo.git_dir).exists());
+
+        // --prune=now drops everything unreachable, --prune=never nothing
+        let report = collect_garbage(&repo, now(), Some("never"))?;
+        assert!(report.pruned.is_empty());
+        let report = collect_garbage(&repo, now(), Some("now"))?;
+        assert_eq!(report.pruned, vec![fresh.hash()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewriting_an_object_freshens_it() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+
+        let blob = Object::new_blob(b"content\n".to_vec());
+        repo.write_object(&blob)?;
+        age(&repo, blob.hash(), 30 * 24 * 60 * 60)?;
+
+        // Writing the same content again reports no new object but resets the clock
+        assert!(!blob.write_to_store(&*repo.vfs, &repo.git_dir, repo.compression)?);
+        let report = collect_garbage(&repo, now(), None)?;
+        assert!(report.pruned.is_empty());
+        assert_eq!(report.kept_recent, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_head_anchors_objects() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let tip = deleted_branch_repo(&repo)?;
+        fs::write(repo.git_dir.join("config"), "[gc]\n\treflogExpire = now\n")?;
+        fs::write(repo.git_dir.join("MERGE_HEAD"), format!("{}\n", tip))?;
+
+        let report = collect_garbage(&repo, now() + 60, Some("now"))?;
+        assert!(report.expired_entries > 0);
+        assert!(report.pruned.is_empty());
+        assert!(Object::read_from_objects_dir(&repo.git_dir, tip).is_ok());
+
+        fs::remove_file(repo.git_dir.join("MERGE_HEAD"))?;
+        let report = collect_garbage(&repo, now() + 60, Some("now"))?;
+        assert!(report.pruned.contains(&tip));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_writes_bitmaps_and_drops_them_when_pruning() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let tip = deleted_branch_repo(&repo)?;
+        let main = repo.ref_store().resolve_commit("main")?;
+        fs::write(repo.git_dir.join("config"), "[gc]\n\twriteBitmaps = true\n")?;
+
+        let report = collect_garbage(&repo, now(), Some("never"))?;
+        assert_eq!(report.bitmapped_commits, 1);
+        let bitmaps = ReachabilityBitmaps::load(&repo.git_dir)?.expect("gc.writeBitmaps is set");
+        assert!(bitmaps.covers(main));
+        assert_eq!(reachable_objects(&repo, Some(&bitmaps))?, reachable_objects(&repo, None)?);
+
+        // Pruning invalidates the file even when no new one is wanted
+        fs::write(repo.git_dir.join("config"), "[gc]\n\treflogExpire = now\n")?;
+        let report = collect_garbage(&repo, now() + 60, Some("now"))?;
+        assert!(report.pruned.contains(&tip));
+        assert_eq!(report.bitmapped_commits, 0);
+        assert!(ReachabilityBitmaps::load(&repo.git_dir)?.is_none());
+        Ok(())
+    }
+}
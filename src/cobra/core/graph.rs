@@ -0,0 +1,259 @@
+// ASCII history graph for log --graph
+//
+// Every column ("lane") waits for one commit. A commit is drawn as `*` in
+// its lane, which then waits for the commit's first parent; further parents
+// get new lanes to its right. Lanes waiting for the same commit are merged
+// into the leftmost one. Lanes move at most one column per row, drawn as
+// `\` and `/` between the `|` of the columns, so an octopus merge or a
+// collapse across several columns takes several rows.
+
+use std::io::{self, Write};
+use crate::cobra::core::oid::Oid;
+
+/// The lanes of the graph between two commits
+#[derive(Debug, Default)]
+pub struct Graph {
+    columns: Vec<Oid>,
+}
+
+/// The rows drawn for one commit
+#[derive(Debug)]
+pub struct GraphRows {
+    /// The row with the commit's `*`
+    pub commit: String,
+    /// Rows moving the lanes into their new columns
+    pub transitions: Vec<String>,
+    /// What the lanes look like once the commit is done
+    pub padding: String,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph::default()
+    }
+
+    /// Draws `hash` and moves its lane on to `parents`. Commits should come
+    /// in topological order, children before their parents.
+    pub fn commit(&mut self, hash: Oid, parents: &[Oid]) -> GraphRows {
+        let column = self.column_of(hash);
+        let commit = draw(self.columns.len(), |cells| {
+            for position in 0..self.columns.len() {
+                cells[2 * position] = if position == column { '*' } else { '|' };
+            }
+        });
+
+        let mut lanes = self.advance(column, parents);
+        let mut transitions = Vec::new();
+        while lanes.iter().any(|lane| lane.position != lane.target) {
+            let width = lanes.iter().map(|lane| lane.position.max(lane.target) + 1).max().unwrap_or(0);
+            transitions.push(draw(width, |cells| {
+                for lane in &mut lanes {
+                    if lane.position < lane.target {
+                        cells[2 * lane.position + 1] = '\\';
+                        lane.position += 1;
+                    } else if lane.position > lane.target {
+                        cells[2 * lane.position - 1] = '/';
+                        lane.position -= 1;
+                    } else {
+                        cells[2 * lane.position] = '|';
+                    }
+                }
+            }));
+        }
+
+        let padding = draw(self.columns.len(), |cells| {
+            for position in 0..self.columns.len() {
+                cells[2 * position] = '|';
+            }
+        });
+        GraphRows { commit, transitions, padding }
+    }
+
+    /// Moves the lane of a commit that is not shown on to its parents
+    /// without drawing anything
+    pub fn skip(&mut self, hash: Oid, parents: &[Oid]) {
+        let column = self.column_of(hash);
+        self.advance(column, parents);
+    }
+
+    /// The lane waiting for `hash`, a new one on the right if none is
+    fn column_of(&mut self, hash: Oid) -> usize {
+        match self.columns.iter().position(|waiting| *waiting == hash) {
+            Some(column) => column,
+            None => {
+                self.columns.push(hash);
+                self.columns.len() - 1
+            }
+        }
+    }
+
+    /// Replaces the lane at `column` with one per parent and merges lanes
+    /// waiting for the same commit. Returns where each lane was and where
+    /// it ends up.
+    fn advance(&mut self, column: usize, parents: &[Oid]) -> Vec<Lane> {
+        let mut waiting: Vec<(usize, Oid)> = Vec::new();
+        for (position, hash) in self.columns.iter().enumerate() {
+            if position == column {
+                waiting.extend(parents.iter().map(|parent| (column, *parent)));
+            } else {
+                waiting.push((position, *hash));
+            }
+        }
+
+        self.columns.clear();
+        let mut lanes = Vec::new();
+        for (position, hash) in waiting {
+            let target = match self.columns.iter().position(|existing| *existing == hash) {
+                Some(target) => target,
+                None => {
+                    self.columns.push(hash);
+                    self.columns.len() - 1
+                }
+            };
+            lanes.push(Lane { position, target });
+        }
+        lanes
+    }
+}
+
+impl GraphRows {
+    /// Writes `text` beside the rows: its first line next to the commit,
+    /// the next ones next to the transitions, and the rest next to the
+    /// padding. All lines are indented to the same column.
+    pub fn write(&self, out: &mut dyn Write, text: &str) -> io::Result<()> {
+        let width = self.transitions.iter()
+            .chain([&self.commit, &self.padding])
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut rows = std::iter::once(&self.commit).chain(&self.transitions);
+        let mut lines = text.lines();
+        loop {
+            let (row, line) = match (rows.next(), lines.next()) {
+                (None, None) => return Ok(()),
+                (row, line) => (row.unwrap_or(&self.padding), line.unwrap_or("")),
+            };
+            let line = format!("{:width$} {}", row, line, width = width);
+            writeln!(out, "{}", line.trim_end())?;
+        }
+    }
+}
+
+/// A lane moving from one column to another
+#[derive(Debug)]
+struct Lane {
+    position: usize,
+    target: usize,
+}
+
+/// A row `columns` wide, filled in by `fill`, without trailing spaces
+fn draw(columns: usize, fill: impl FnOnce(&mut [char])) -> String {
+    let mut cells = vec![' '; 2 * columns];
+    fill(&mut cells);
+    cells.into_iter().collect::<String>().trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stand-in id for the commit called `name`
+    fn id(name: &str) -> Oid {
+        Oid::for_object("commit", name.as_bytes())
+    }
+
+    /// Draws commits given as (name, parents), one line of text each
+    fn render(commits: &[(&str, &[&str])]) -> io::Result<String> {
+        let mut graph = Graph::new();
+        let mut out = Vec::new();
+        for (name, parents) in commits {
+            let parents: Vec<Oid> = parents.iter().map(|parent| id(parent)).collect();
+            graph.commit(id(name), &parents).write(&mut out, name)?;
+        }
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_linear_history_and_merges() -> io::Result<()> {
+        assert_eq!(render(&[("c", &["b"]), ("b", &["a"]), ("a", &[])])?, "* c\n* b\n* a\n");
+
+        // One merge, second parent first as topological order gives it
+        let merge = render(&[
+            ("merge", &["m1", "f2"]),
+            ("f2", &["f1"]),
+            ("f1", &["base"]),
+            ("m1", &["base"]),
+            ("base", &[]),
+        ])?;
+        assert_eq!(merge, concat!(
+            "*   merge\n",
+            "|\\\n",
+            "| * f2\n",
+            "| * f1\n",
+            "* | m1\n",
+            "|/\n",
+            "* base\n",
+        ));
+
+        // Two branches running at the same time, merged back one after the other
+        let concurrent = render(&[
+            ("merge-b", &["merge-a", "b2"]),
+            ("b2", &["b1"]),
+            ("merge-a", &["m1", "a1"]),
+            ("a1", &["base"]),
+            ("b1", &["base"]),
+            ("m1", &["base"]),
+            ("base", &[]),
+        ])?;
+        assert_eq!(concurrent, concat!(
+            "*   merge-b\n",
+            "|\\\n",
+            "| * b2\n",
+            "* |   merge-a\n",
+            "|\\ \\\n",
+            "| * | a1\n",
+            "| | * b1\n",
+            "| |/\n",
+            "* | m1\n",
+            "|/\n",
+            "* base\n",
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_alignment_and_octopus_merges() -> io::Result<()> {
+        let mut graph = Graph::new();
+        let mut out = Vec::new();
+        let parents = |names: &[&str]| names.iter().map(|name| id(name)).collect::<Vec<_>>();
+
+        // Every line of a commit's text lines up, padded by the lanes
+        graph.commit(id("side"), &parents(&["base"])).write(&mut out, "side\nbody")?;
+        graph.commit(id("octopus"), &parents(&["a", "b", "c"])).write(&mut out, "octopus\n\nbody\nmore")?;
+        for name in ["c", "b", "a"] {
+            graph.commit(id(name), &parents(&["base"])).write(&mut out, name)?;
+        }
+        graph.commit(id("base"), &[]).write(&mut out, "base\nroot")?;
+        assert_eq!(String::from_utf8(out).unwrap(), concat!(
+            "* side\n",
+            "| body\n",
+            "| *     octopus\n",
+            "| |\\\n",
+            "| | |\\  body\n",
+            "| | | | more\n",
+            // Lanes waiting for the same commit meet one column per row
+            "| | | * c\n",
+            "| | |/\n",
+            "| |/|\n",
+            "|/| |\n",
+            "| | * b\n",
+            "| |/\n",
+            "|/|\n",
+            "| * a\n",
+            "|/\n",
+            "* base\n",
+            "  root\n",
+        ));
+        Ok(())
+    }
+}
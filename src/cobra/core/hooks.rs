@@ -0,0 +1,28 @@
+// Client-side hooks (.cobra/hooks/<name>)
+
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+use crate::cobra::core::repository::Repository;
+
+/// Runs the hook `name` from the work tree root if it exists and is
+/// executable. A hook that exits non-zero aborts the operation.
+pub fn run_hook(repo: &Repository, name: &str, args: &[&str]) -> io::Result<()> {
+    let path = repo.git_dir.join("hooks").join(name);
+    let executable = path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false);
+    if !executable {
+        return Ok(());
+    }
+
+    let status = Command::new(&path)
+        .args(args)
+        .current_dir(&repo.root_path)
+        .env("COBRA_DIR", &repo.git_dir)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} hook failed ({})", name, status)));
+    }
+    Ok(())
+}
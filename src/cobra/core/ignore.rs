@@ -0,0 +1,228 @@
+// Untracked files the work tree walkers leave out
+//
+// `.cobraignore` at the top of the work tree lists gitignore-style patterns,
+// one per line. Blank lines and lines starting with `#` are skipped. A
+// leading `!` re-includes what an earlier pattern excluded, and a trailing
+// `/` makes a pattern match directories only. A pattern with a slash
+// anywhere else is anchored: it matches the whole path from the top of the
+// work tree, with `**` standing for any number of directories. Other
+// patterns match a file or directory name at any depth. The last pattern
+// that matches decides.
+//
+// `.cobra/info/exclude` holds more patterns in the same format, for a single
+// clone. They are read first, so `.cobraignore` wins when both match.
+//
+// Ignoring only ever applies to untracked files: a file in the index keeps
+// showing its modifications wherever it lies.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::cobra::core::index::Index;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::utils::glob::glob_match;
+
+/// Name of the ignore file in the work tree
+pub const IGNORE_FILE: &str = ".cobraignore";
+
+/// Per-repository patterns, relative to the git directory
+pub const EXCLUDE_FILE: &str = "info/exclude";
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    /// The pattern split at slashes, without any leading `!`, `/` or
+    /// trailing `/`
+    segments: Vec<String>,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Pattern> {
+        let (negated, rest) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            // A backslash keeps a leading `!` or `#` literal
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, rest) = match rest.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let anchored = rest.contains('/');
+        let rest = rest.trim_start_matches('/');
+        if rest.is_empty() {
+            return None;
+        }
+        Some(Pattern {
+            segments: rest.split('/').map(str::to_string).collect(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, components: &[&str], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            match_segments(&self.segments, components)
+        } else {
+            components.last().is_some_and(|name| glob_match(&self.segments[0], name))
+        }
+    }
+}
+
+/// Matches path components against pattern segments, where a `**` segment
+/// matches zero or more components and `*` never crosses a slash
+fn match_segments(segments: &[String], components: &[&str]) -> bool {
+    match segments.split_first() {
+        None => components.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=components.len()).any(|skip| match_segments(rest, &components[skip..]))
+        }
+        Some((first, rest)) => components.split_first()
+            .is_some_and(|(name, remaining)| glob_match(first, name) && match_segments(rest, remaining)),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    /// Reads info/exclude and then the work tree's ignore file; without
+    /// either nothing is ignored
+    pub fn load(repo: &Repository) -> io::Result<IgnoreRules> {
+        let mut rules = match repo.vfs.read(&repo.git_dir.join(EXCLUDE_FILE)) {
+            Ok(content) => IgnoreRules::parse(&String::from_utf8_lossy(&content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => IgnoreRules::default(),
+            Err(e) => return Err(e),
+        };
+        match fs::read_to_string(repo.root_path.join(IGNORE_FILE)) {
+            Ok(text) => rules.patterns.extend(IgnoreRules::parse(&text).patterns),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(rules)
+    }
+
+    pub fn parse(text: &str) -> IgnoreRules {
+        let patterns = text.lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(Pattern::parse)
+            .collect();
+        IgnoreRules { patterns }
+    }
+
+    /// True when `path`, relative to the work tree root, is ignored. Like
+    /// git, nothing inside an ignored directory can be re-included.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+        let path = path.to_string_lossy();
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        (1..components.len()).any(|depth| self.decide(&components[..depth], true))
+            || self.decide(&components, is_dir)
+    }
+
+    fn decide(&self, components: &[&str], is_dir: bool) -> bool {
+        self.patterns.iter().rev()
+            .find(|pattern| pattern.matches(components, is_dir))
+            .is_some_and(|pattern| !pattern.negated)
+    }
+
+    /// Whether a walk of the work tree should leave `path` out: it is
+    /// ignored, not tracked, and (for a directory) holds no tracked files
+    pub fn excludes(&self, index: &Index, path: &Path, is_dir: bool) -> bool {
+        if !self.is_ignored(path, is_dir) {
+            return false;
+        }
+        if is_dir {
+            !index.entries().any(|entry| entry.path.starts_with(path))
+        } else {
+            !index.contains(path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::add::add_file;
+    use crate::cobra::commands::status::collect;
+    use crate::cobra::test_support::temp_repo;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_ignore_patterns() {
+        let rules = IgnoreRules::parse(
+            "# build output\ntarget/\n*.log\n!keep.log\n/root.txt\ndocs/*.html\nsrc/**/gen.rs\n",
+        );
+        let ignored = |path: &str, is_dir: bool| rules.is_ignored(Path::new(path), is_dir);
+
+        // Directory patterns match directories at any depth, and what they hold
+        assert!(ignored("target", true));
+        assert!(ignored("crates/a/target", true));
+        assert!(ignored("target/debug/app", false));
+        assert!(!ignored("target", false));
+
+        // Negation, except below an ignored directory
+        assert!(ignored("debug.log", false));
+        assert!(ignored("logs/debug.log", false));
+        assert!(!ignored("keep.log", false));
+        assert!(!ignored("logs/keep.log", false));
+        assert!(ignored("target/keep.log", false));
+
+        // Anchored patterns match from the top only; * stays in one directory
+        assert!(ignored("root.txt", false));
+        assert!(!ignored("sub/root.txt", false));
+        assert!(ignored("docs/index.html", false));
+        assert!(!ignored("docs/api/index.html", false));
+        assert!(!ignored("other/docs/index.html", false));
+        assert!(ignored("src/gen.rs", false));
+        assert!(ignored("src/a/b/gen.rs", false));
+        assert!(!ignored("gen.rs", false));
+    }
+
+    #[test]
+    fn test_status_skips_ignored_untracked_files_only() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        fs::write(temp_dir.path().join(IGNORE_FILE), "target/\n*.log\n")?;
+        fs::create_dir_all(temp_dir.path().join("target/debug"))?;
+        fs::write(temp_dir.path().join("target/debug/app"), "binary\n")?;
+        fs::write(temp_dir.path().join("tracked.log"), "one\n")?;
+        fs::write(temp_dir.path().join("build.log"), "noise\n")?;
+        fs::write(temp_dir.path().join("main.rs"), "fn main() {}\n")?;
+        add_file(&mut repo, Path::new("tracked.log"))?;
+
+        let report = collect(&repo)?;
+        assert_eq!(report.untracked, vec![PathBuf::from("main.rs")]);
+
+        // A tracked file matching a pattern still shows its changes
+        fs::write(temp_dir.path().join("tracked.log"), "one\ntwo\n")?;
+        let report = collect(&repo)?;
+        assert_eq!(report.modified().collect::<Vec<_>>(), vec![Path::new("tracked.log")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_info_exclude_applies_before_the_ignore_file() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        fs::create_dir_all(repo.git_dir.join("info"))?;
+        fs::write(repo.git_dir.join(EXCLUDE_FILE), "*.tmp\n*.bak")?;
+        fs::write(temp_dir.path().join("scratch.tmp"), "x\n")?;
+        fs::write(temp_dir.path().join("old.bak"), "x\n")?;
+        fs::write(temp_dir.path().join("main.rs"), "fn main() {}\n")?;
+        assert_eq!(collect(&repo)?.untracked, vec![PathBuf::from("main.rs")]);
+
+        // The work tree's file is read last, so it can re-include
+        fs::write(temp_dir.path().join(IGNORE_FILE), "!old.bak\n")?;
+        assert_eq!(collect(&repo)?.untracked, vec![PathBuf::from("main.rs"), PathBuf::from("old.bak")]);
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+// Snapshot imports: commits built straight from a tarball or a directory
+//
+// The tree is assembled from the archive or directory itself. The index and
+// the work tree are never read or written, so imports work in bare
+// repositories and leave a checked out branch's files alone. Ignore rules do
+// not apply: everything in the snapshot is imported, except `.cobra`
+// directories and entries trees cannot hold here (symlinks, devices and
+// fifos), which are reported as skipped.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use flate2::read::GzDecoder;
+use walkdir::WalkDir;
+use crate::cobra::core::{
+    index::normalize_mode,
+    object::Object,
+    oid::Oid,
+    repository::Repository,
+    signature::Signature,
+    transaction::ObjectTransaction,
+    tree::build_tree_in,
+};
+
+const BLOCK: usize = 512;
+
+/// Files read from an archive or directory, with their blobs staged
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// Mode and blob hash of every imported file, by path
+    pub files: BTreeMap<PathBuf, (u32, Oid)>,
+    /// Entries left out because trees here cannot hold them
+    pub skipped: Vec<PathBuf>,
+}
+
+impl Snapshot {
+    /// A path that is both a file and a directory cannot become a tree
+    fn check_paths(&self) -> io::Result<()> {
+        // Children sort directly after their parent
+        let mut paths = self.files.keys().peekable();
+        while let Some(path) = paths.next() {
+            if paths.peek().is_some_and(|next| next.starts_with(path)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("'{}' is both a file and a directory", path.display()),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Turns an archive member name into a path inside the tree, or None for
+/// the archive root and anything inside a `.cobra` directory
+fn tree_path(name: &str) -> io::Result<Option<PathBuf>> {
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(part) if part == ".cobra" => return Ok(None),
+            Component::Normal(part) => path.push(part),
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Refusing to import '{}': it points outside the archive", name),
+            )),
+        }
+    }
+    Ok(Some(path).filter(|path| !path.as_os_str().is_empty()))
+}
+
+/// A NUL-terminated header field
+fn field(header: &[u8]) -> &[u8] {
+    let end = header.iter().position(|&b| b == 0).unwrap_or(header.len());
+    &header[..end]
+}
+
+/// An octal header number, or a big-endian one when the top bit of the
+/// first byte is set (GNU's encoding for sizes that do not fit)
+fn number(header: &[u8]) -> io::Result<u64> {
+    if header.first().is_some_and(|&b| b & 0x80 != 0) {
+        let mut value = u64::from(header[0] & 0x7f);
+        for &b in &header[1..] {
+            value = value.checked_shl(8).ok_or_else(|| invalid_tar("number out of range"))? | u64::from(b);
+        }
+        return Ok(value);
+    }
+    let text = String::from_utf8_lossy(field(header));
+    let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid_tar("bad number in header"))
+}
+
+fn invalid_tar(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Not a valid tar archive: {}", reason))
+}
+
+fn read_block(reader: &mut dyn Read, block: &mut [u8; BLOCK]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < BLOCK {
+        match reader.read(&mut block[filled..])? {
+            0 if filled == 0 => return Ok(false),
+            0 => return Err(invalid_tar("truncated header")),
+            n => filled += n,
+        }
+    }
+    Ok(true)
+}
+
+/// Reads a member's data and skips the padding after it
+fn read_data(reader: &mut dyn Read, size: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(size).read_to_end(&mut data)?;
+    if (data.len() as u64) < size {
+        return Err(invalid_tar("truncated member"));
+    }
+    let padding = (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64;
+    io::copy(&mut reader.take(padding), &mut io::sink())?;
+    Ok(data)
+}
+
+/// The `path` and `linkpath` records of a pax extended header
+fn pax_records(data: &[u8]) -> BTreeMap<String, String> {
+    let mut records = BTreeMap::new();
+    let mut rest = data;
+    while let Some(space) = rest.iter().position(|&b| b == b' ') {
+        let length: usize = match String::from_utf8_lossy(&rest[..space]).parse() {
+            Ok(length) if length > space && length <= rest.len() => length,
+            _ => break,
+        };
+        let record = String::from_utf8_lossy(&rest[space + 1..length]);
+        if let Some((key, value)) = record.trim_end_matches('\n').split_once('=') {
+            records.insert(key.to_string(), value.to_string());
+        }
+        rest = &rest[length..];
+    }
+    records
+}
+
+/// Reads a tar archive, gzip-compressed or not, staging a blob for every
+/// regular file. Modes come from the headers; hard links get the content of
+/// the file they link to.
+pub fn read_tar(reader: impl Read, transaction: &mut ObjectTransaction) -> io::Result<Snapshot> {
+    let mut reader = BufReader::new(reader);
+    let mut reader: Box<dyn Read> = if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+
+    let mut snapshot = Snapshot::default();
+    let mut long_name: Option<String> = None;
+    let mut long_link: Option<String> = None;
+    let mut block = [0u8; BLOCK];
+    while read_block(&mut reader, &mut block)? {
+        if block.iter().all(|&b| b == 0) {
+            break;
+        }
+        let checksum = number(&block[148..156])?;
+        let actual: u64 = block.iter().enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { u64::from(b' ') } else { u64::from(b) })
+            .sum();
+        if checksum != actual {
+            return Err(invalid_tar("header checksum mismatch"));
+        }
+
+        let size = number(&block[124..136])?;
+        let kind = block[156];
+        let mut name = String::from_utf8_lossy(field(&block[..100])).into_owned();
+        if &block[257..262] == b"ustar" {
+            let prefix = field(&block[345..500]);
+            if !prefix.is_empty() {
+                name = format!("{}/{}", String::from_utf8_lossy(prefix), name);
+            }
+        }
+        let name = long_name.take().unwrap_or(name);
+        let link = long_link.take()
+            .unwrap_or_else(|| String::from_utf8_lossy(field(&block[157..257])).into_owned());
+
+        match kind {
+            // GNU long names and pax headers describe the member that follows
+            b'L' => long_name = Some(String::from_utf8_lossy(field(&read_data(&mut reader, size)?)).into_owned()),
+            b'K' => long_link = Some(String::from_utf8_lossy(field(&read_data(&mut reader, size)?)).into_owned()),
+            b'x' => {
+                let mut records = pax_records(&read_data(&mut reader, size)?);
+                long_name = records.remove("path");
+                long_link = records.remove("linkpath");
+            }
+            b'0' | b'\0' | b'7' => {
+                let content = read_data(&mut reader, size)?;
+                if let Some(path) = tree_path(&name)? {
+                    let hash = transaction.add(Object::new_blob(content))?;
+                    snapshot.files.insert(path, (normalize_mode(number(&block[100..108])? as u32), hash));
+                }
+            }
+            b'1' => {
+                read_data(&mut reader, size)?;
+                if let Some(path) = tree_path(&name)? {
+                    let target = tree_path(&link)?.and_then(|target| snapshot.files.get(&target).copied());
+                    let (mode, hash) = target.ok_or_else(|| invalid_tar(&format!("'{}' links to a missing file", name)))?;
+                    snapshot.files.insert(path, (mode, hash));
+                }
+            }
+            b'5' | b'g' => {
+                read_data(&mut reader, size)?;
+            }
+            _ => {
+                read_data(&mut reader, size)?;
+                if let Some(path) = tree_path(&name)? {
+                    snapshot.skipped.push(path);
+                }
+            }
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Reads every file below `root`, staging a blob for each. Modes come from
+/// the file permissions; symlinks are not followed.
+pub fn read_directory(root: &Path, transaction: &mut ObjectTransaction) -> io::Result<Snapshot> {
+    let mut snapshot = Snapshot::default();
+    let walk = WalkDir::new(root)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".cobra");
+    for entry in walk {
+        let entry = entry?;
+        let path = entry.path().strip_prefix(root).map_err(io::Error::other)?.to_path_buf();
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            continue;
+        }
+        if !file_type.is_file() {
+            snapshot.skipped.push(path);
+            continue;
+        }
+        let mode = normalize_mode(entry.metadata().map_err(io::Error::other)?.permissions().mode());
+        let hash = transaction.add(Object::new_blob(fs::read(entry.path())?))?;
+        snapshot.files.insert(path, (mode, hash));
+    }
+    Ok(snapshot)
+}
+
+/// Commits the snapshot on `branch`, on top of its current tip (or as a root
+/// commit when the branch does not exist or has no commits yet), and returns
+/// the new commit's hash
+pub fn commit_snapshot(
+    repo: &Repository,
+    mut transaction: ObjectTransaction,
+    snapshot: &Snapshot,
+    branch: &str,
+    message: &str,
+) -> io::Result<Oid> {
+    snapshot.check_paths()?;
+    let branch_ref = format!("refs/heads/{}", branch);
+    let parent = repo.ref_store().read_ref(&branch_ref)?;
+
+    let entries: Vec<_> = snapshot.files.iter()
+        .map(|(path, &(mode, hash))| (path.clone(), mode, hash))
+        .collect();
+    let tree = build_tree_in(&mut transaction, &entries)?;
+    let tree_hash = transaction.add(tree)?;
+
+    let reason = format!("import: {}", message.lines().next().unwrap_or_default());
+    let message = if message.ends_with('\n') { message.to_string() } else { format!("{}\n", message) };
+    let commit = Object::new_commit(
+        tree_hash,
+        parent.into_iter().collect(),
+        Signature::author(&repo.git_dir)?.sanitized(),
+        Signature::committer(&repo.git_dir)?.sanitized(),
+        message,
+    );
+    let commit_hash = transaction.add(commit)?;
+    transaction.update_ref(&branch_ref, parent, commit_hash, &reason);
+    transaction.commit()?;
+    Ok(commit_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use crate::cobra::core::tree::{commit_tree, diff_trees, flatten_tree};
+    use crate::cobra::test_support::{init_repo, temp_repo};
+    use tempfile::TempDir;
+
+    /// A ustar archive of (name, mode, type, content) members. The content
+    /// of hard links and symlinks is their target.
+    fn tar(members: &[(&str, u32, u8, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for &(name, mode, kind, content) in members {
+            let mut header = [0u8; BLOCK];
+            let content = if kind == b'1' || kind == b'2' {
+                header[157..157 + content.len()].copy_from_slice(content);
+                &[]
+            } else {
+                content
+            };
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..108].copy_from_slice(format!("{:07o}\0", mode).as_bytes());
+            header[108..116].copy_from_slice(b"0000000\0");
+            header[116..124].copy_from_slice(b"0000000\0");
+            header[124..136].copy_from_slice(format!("{:011o}\0", content.len()).as_bytes());
+            header[136..148].copy_from_slice(b"00000000000\0");
+            header[148..156].copy_from_slice(b"        ");
+            header[156] = kind;
+            header[257..265].copy_from_slice(b"ustar\x0000");
+            let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+            header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+            archive.extend_from_slice(&header);
+            archive.extend_from_slice(content);
+            archive.resize(archive.len().div_ceil(BLOCK) * BLOCK, 0);
+        }
+        archive.resize(archive.len() + 2 * BLOCK, 0);
+        archive
+    }
+
+    fn import(repo: &Repository, archive: &[u8], message: &str) -> io::Result<Oid> {
+        let mut transaction = repo.transaction();
+        let snapshot = read_tar(archive, &mut transaction)?;
+        commit_snapshot(repo, transaction, &snapshot, "vendor", message)
+    }
+
+    #[test]
+    fn test_successive_tarballs_build_history() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+
+        let first = import(&repo, &tar(&[
+            ("lib-1.0/", 0o755, b'5', b""),
+            ("lib-1.0/README", 0o644, b'0', b"version 1\n"),
+            ("lib-1.0/src/old.c", 0o644, b'0', b"int old;\n"),
+            ("lib-1.0/configure", 0o755, b'0', b"#!/bin/sh\n"),
+            ("lib-1.0/link", 0o777, b'2', b"README"),
+        ]), "Import lib 1.0")?;
+
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(&tar(&[
+            ("./lib-1.0/README", 0o644, b'0', b"version 2\n"),
+            ("./lib-1.0/configure", 0o755, b'0', b"#!/bin/sh\n"),
+            ("./lib-1.0/src/new.c", 0o644, b'0', b"int new;\n"),
+            ("./lib-1.0/COPYING", 0o644, b'1', b"./lib-1.0/README"),
+        ]))?;
+        let second = import(&repo, &gzipped.finish()?, "Import lib 2.0")?;
+
+        match repo.read_object(second)? {
+            Object::Commit { parents, message, .. } => {
+                assert_eq!(parents, vec![first]);
+                assert_eq!(message, "Import lib 2.0\n");
+            }
+            _ => panic!("expected a commit"),
+        }
+        assert_eq!(repo.ref_store().read_ref("refs/heads/vendor")?, Some(second));
+
+        let old_tree = commit_tree(&repo.git_dir, first)?;
+        let files = flatten_tree(&repo.git_dir, old_tree)?;
+        assert_eq!(files[Path::new("lib-1.0/configure")].mode, 0o100755);
+        assert!(!files.contains_key(Path::new("lib-1.0/link")));
+
+        let changes: Vec<_> = diff_trees(&repo.git_dir, Some(old_tree), Some(commit_tree(&repo.git_dir, second)?))?
+            .iter()
+            .map(|change| (change.status(), change.path.to_string_lossy().into_owned()))
+            .collect();
+        assert_eq!(changes, vec![
+            ('A', "lib-1.0/COPYING".to_string()),
+            ('M', "lib-1.0/README".to_string()),
+            ('A', "lib-1.0/src/new.c".to_string()),
+            ('D', "lib-1.0/src/old.c".to_string()),
+        ]);
+
+        // Neither the index, the work tree nor HEAD's branch were touched
+        assert_eq!(repo.index.entries().count(), 0);
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), 1);
+        assert_eq!(repo.ref_store().read_ref_value("refs/heads/main")?, Some(String::new()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_directory_import_and_unsafe_archives() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = init_repo(&temp_dir.path().join("repo"))?;
+        let source = temp_dir.path().join("source");
+        fs::create_dir_all(source.join("bin"))?;
+        fs::create_dir_all(source.join(".cobra"))?;
+        fs::write(source.join(".cobra/HEAD"), "ref: refs/heads/main\n")?;
+        fs::write(source.join(".gitignore"), "*.o\n")?;
+        fs::write(source.join("main.o"), "object\n")?;
+        fs::write(source.join("bin/run"), "#!/bin/sh\n")?;
+        fs::set_permissions(source.join("bin/run"), fs::Permissions::from_mode(0o755))?;
+        std::os::unix::fs::symlink("bin/run", source.join("run"))?;
+
+        let mut transaction = repo.transaction();
+        let snapshot = read_directory(&source, &mut transaction)?;
+        assert_eq!(snapshot.skipped, vec![PathBuf::from("run")]);
+        let commit = commit_snapshot(&repo, transaction, &snapshot, "upstream", "Snapshot")?;
+        let files = flatten_tree(&repo.git_dir, commit_tree(&repo.git_dir, commit)?)?;
+        // Ignore rules do not apply to explicit imports
+        assert_eq!(files.keys().collect::<Vec<_>>(), [".gitignore", "bin/run", "main.o"].map(Path::new));
+        assert_eq!(files[Path::new("bin/run")].mode, 0o100755);
+
+        for name in ["../escape", "/etc/passwd", "a/../../b"] {
+            let error = import(&repo, &tar(&[(name, 0o644, b'0', b"x")]), "Bad").unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+        let error = import(&repo, &tar(&[("a", 0o644, b'0', b"x"), ("a/b", 0o644, b'0', b"y")]), "Bad").unwrap_err();
+        assert!(error.to_string().contains("both a file and a directory"), "{}", error);
+        assert!(import(&repo, b"not a tarball at all", "Bad").is_err());
+        assert_eq!(repo.ref_store().read_ref("refs/heads/vendor")?, None);
+        Ok(())
+    }
+}
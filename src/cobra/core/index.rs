@@ -4,13 +4,23 @@ use std::fs;
 use std::io::{self, Write, Read};
 use byteorder::{BigEndian, WriteBytesExt, ReadBytesExt};
 
+use super::object::Object;
 use super::repository::Repository;
+use crate::cobra::core::case_fold::PathFolding;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::utils::fs::Vfs;
+use crate::cobra::utils::trace::{self, Stream};
 
 #[allow(dead_code)]
 const SIGNATURE: &[u8; 4] = b"COBA"; // Our index signature
 #[allow(dead_code)]
 const VERSION: u32 = 1; // Index format version
 
+/// Reduces a file's st_mode to one of the two regular file modes stored in trees
+pub fn normalize_mode(mode: u32) -> u32 {
+    if mode & 0o111 != 0 { 0o100755 } else { 0o100644 }
+}
+
 /// Represents a single entry in the index
 #[derive(Debug, Clone)]
 pub struct IndexEntry {
@@ -30,21 +40,45 @@ pub struct IndexEntry {
     pub gid: u32,
     /// The file size
     pub size: u64,
-    /// The SHA-1 hash of the file content
-    pub hash: String,
+    /// The id of the blob holding the file content
+    pub hash: Oid,
     /// The path of the file relative to repository root
     pub path: PathBuf,
 }
 
 impl IndexEntry {
+    /// An entry staging the blob `hash` from a tree. The work tree file's
+    /// stat data is only recorded when the file holds that same content;
+    /// otherwise the entry gets none, so status compares the file by content.
+    pub fn from_tree_blob(repo: &Repository, path: &Path, mode: u32, hash: Oid) -> io::Result<IndexEntry> {
+        let full_path = repo.root_path.join(path);
+        if let Ok(content) = fs::read(&full_path) {
+            if Object::new_blob(content).hash() == hash {
+                return Ok(IndexEntry::new(path.to_path_buf(), hash, fs::metadata(&full_path)?));
+            }
+        }
+        Ok(IndexEntry {
+            ctime: 0,
+            mtime: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash,
+            path: path.to_path_buf(),
+        })
+    }
+
     /// Creates a new index entry from a file
-    pub fn new(path: PathBuf, hash: String, metadata: fs::Metadata) -> IndexEntry {
+    pub fn new(path: PathBuf, hash: Oid, metadata: fs::Metadata) -> IndexEntry {
         IndexEntry {
             ctime: metadata.ctime() as u64,
             mtime: metadata.mtime() as u64,
             dev: metadata.dev() as u32,
             ino: metadata.ino() as u32,
-            mode: metadata.mode() as u32,
+            mode: metadata.mode(),
             uid: metadata.uid(),
             gid: metadata.gid(),
             size: metadata.len(),
@@ -53,6 +87,30 @@ impl IndexEntry {
         }
     }
 
+    /// Names the stat fields recorded for the file that `metadata` no longer
+    /// agrees with. The content may still be the same.
+    pub fn stat_differences(&self, metadata: &fs::Metadata) -> Vec<&'static str> {
+        let fields = [
+            ("dev", self.dev == metadata.dev() as u32),
+            ("ino", self.ino == metadata.ino() as u32),
+            ("uid", self.uid == metadata.uid()),
+            ("gid", self.gid == metadata.gid()),
+            ("size", self.size == metadata.len()),
+            ("mtime", self.mtime == metadata.mtime() as u64),
+            ("ctime", self.ctime == metadata.ctime() as u64),
+        ];
+        fields.into_iter().filter(|(_, same)| !same).map(|(field, _)| field).collect()
+    }
+
+    /// True if the file looks untouched since it was staged: same size,
+    /// mtime, inode and device. Anything else means hashing it to be sure.
+    pub fn stat_matches(&self, metadata: &fs::Metadata) -> bool {
+        self.size == metadata.len()
+            && self.mtime == metadata.mtime() as u64
+            && self.ino == metadata.ino() as u32
+            && self.dev == metadata.dev() as u32
+    }
+
     /// Write entry to a binary format
     fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         // Write fixed-length fields
@@ -66,7 +124,7 @@ impl IndexEntry {
         writer.write_u64::<BigEndian>(self.size)?;
 
         // Write hash
-        writer.write_all(self.hash.as_bytes())?;
+        writer.write_all(self.hash.to_string().as_bytes())?;
         writer.write_u8(0)?; // Null terminator
 
         // Write path
@@ -98,8 +156,10 @@ impl IndexEntry {
             }
             hash.push(byte);
         }
-        let hash = String::from_utf8(hash)
+        let hash = std::str::from_utf8(&hash)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // A damaged name would otherwise only fail once something looks the object up
+        let hash = Oid::from_hex(hash).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
         // Read path (null-terminated string)
         let mut path = Vec::new();
@@ -129,28 +189,50 @@ impl IndexEntry {
 }
 
 /// Represents the index (staging area)
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Index {
     /// Map of paths to index entries
     entries: Vec<IndexEntry>,
+    /// The file on disk is git's index, which cobra cannot parse or update
+    foreign: bool,
 }
 
+/// Signature at the start of an index written by git
+const GIT_INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
+
 impl Index {
     /// Creates a new empty index
     pub fn new() -> Index {
         Index {
             entries: Vec::new(),
+            foreign: false,
         }
     }
 
     /// Loads the index from the repository
     pub fn load(repo: &Repository) -> io::Result<Index> {
-        let index_path = repo.git_dir.join("index");
-        if index_path.exists() {
-            Index::read_from_file(&index_path)
-        } else {
-            Ok(Index::new())
-        }
+        Index::load_from(&*repo.vfs, &repo.git_dir)
+    }
+
+    /// Loads the index of the repository at `git_dir`, empty if there is none yet
+    pub fn load_from(vfs: &dyn Vfs, git_dir: &Path) -> io::Result<Index> {
+        let index_path = git_dir.join("index");
+        trace::timed(Stream::Main, "index load", || {
+            match vfs.read(&index_path) {
+                Ok(content) => Index::from_bytes(&content, &index_path),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Index::new()),
+                Err(e) => Err(e),
+            }
+        }, |index| match index {
+            Ok(index) => format!("{} entries", index.entries.len()),
+            Err(e) => format!("failed: {}", e),
+        })
+    }
+
+    /// True if the repository's index was written by git. Such an index
+    /// loads as empty, so read-only commands work, and is never overwritten.
+    pub fn is_foreign(&self) -> bool {
+        self.foreign
     }
 
     /// Adds or updates an entry in the index
@@ -161,24 +243,50 @@ impl Index {
         self.entries.push(entry);
     }
 
+    /// Removes the entry for `path`, returning whether there was one
+    pub fn remove_entry(&mut self, path: &Path) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.path != *path);
+        self.entries.len() != before
+    }
+
     /// Gets an entry from the index by path
     pub fn get_entry(&self, path: &Path) -> Option<&IndexEntry> {
         self.entries.iter().find(|e| e.path == *path)
     }
 
+    /// Gets an entry for updating in place
+    pub fn get_entry_mut(&mut self, path: &Path) -> Option<&mut IndexEntry> {
+        self.entries.iter_mut().find(|e| e.path == *path)
+    }
+
     /// Returns true if the path exists in the index
     pub fn contains(&self, path: &Path) -> bool {
         self.entries.iter().any(|e| e.path == *path)
     }
 
+    /// The path under which the index tracks `path`: `path` itself, or on a
+    /// filesystem that folds names, an entry spelled differently that the
+    /// filesystem considers the same file
+    pub fn tracked_path(&self, path: &Path, folding: &PathFolding) -> Option<&Path> {
+        if let Some(entry) = self.get_entry(path) {
+            return Some(&entry.path);
+        }
+        if folding.is_exact() {
+            return None;
+        }
+        let key = folding.key(path);
+        self.entries.iter().find(|e| folding.key(&e.path) == key).map(|e| e.path.as_path())
+    }
+
     /// Returns an iterator over all entries
     pub fn entries(&self) -> impl Iterator<Item = &IndexEntry> {
         self.entries.iter()
     }
 
-    /// Write the index to a file
-    pub fn write_to_file(&self, path: &Path) -> io::Result<()> {
-        let mut file = fs::File::create(path)?;
+    /// Serializes the index as it is stored on disk
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut file = Vec::new();
         
         // Write number of entries
         file.write_u32::<BigEndian>(self.entries.len() as u32)?;
@@ -188,22 +296,31 @@ impl Index {
             entry.write_to(&mut file)?;
         }
 
-        Ok(())
+        Ok(file)
     }
 
     /// Read the index from a file
     pub fn read_from_file(path: &Path) -> io::Result<Index> {
-        let mut file = fs::File::open(path)?;
+        Index::from_bytes(&fs::read(path)?, path)
+    }
+
+    /// Parses a stored index; `path` is where it came from, for tracing
+    pub fn from_bytes(content: &[u8], path: &Path) -> io::Result<Index> {
+        let mut file = content;
         
         // Read number of entries
         let num_entries = file.read_u32::<BigEndian>()?;
+        if num_entries.to_be_bytes() == *GIT_INDEX_SIGNATURE {
+            trace::event(Stream::Main, || format!("index {}: written by git, not loaded", path.display()));
+            return Ok(Index { entries: Vec::new(), foreign: true });
+        }
         
-        let mut entries = Vec::with_capacity(num_entries as usize);
+        let mut entries = Vec::new();
         for _ in 0..num_entries {
             entries.push(IndexEntry::read_from(&mut file)?);
         }
 
-        Ok(Index { entries })
+        Ok(Index { entries, foreign: false })
     }
 }
 
@@ -224,18 +341,31 @@ mod tests {
         writeln!(file, "test content")?;
         
         // Create index entry
-        let entry = IndexEntry::new(PathBuf::from("test.txt"), "abcdef".to_string(), fs::metadata(&file_path)?);
+        let entry = IndexEntry::new(PathBuf::from("test.txt"), "ab".repeat(20).parse()?, fs::metadata(&file_path)?);
         
         // Verify basic properties
         assert_eq!(entry.path, PathBuf::from("test.txt"));
         assert!(entry.size > 0);
         assert!(entry.mode > 0);
         assert!(entry.mtime > 0);
-        assert!(!entry.hash.is_empty());
+        assert!(!entry.hash.is_null());
         
         Ok(())
     }
 
+    #[test]
+    fn test_git_index_is_foreign() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("index");
+        fs::write(&path, b"DIRC\0\0\0\x02\0\0\0\x05")?;
+
+        let index = Index::read_from_file(&path)?;
+        assert!(index.is_foreign());
+        assert_eq!(index.entries().count(), 0);
+        assert!(!Index::new().is_foreign());
+        Ok(())
+    }
+
     #[test]
     fn test_index_operations() {
         let mut index = Index::new();
@@ -248,7 +378,7 @@ mod tests {
             uid: 0,
             gid: 0,
             size: 100,
-            hash: "abcdef".to_string(),
+            hash: "ab".repeat(20).parse().unwrap(),
             path: PathBuf::from("test.txt"),
         };
 
@@ -258,20 +388,23 @@ mod tests {
 
         // Test getting entry
         let retrieved = index.get_entry(&PathBuf::from("test.txt")).unwrap();
-        assert_eq!(retrieved.hash, "abcdef");
+        assert_eq!(retrieved.hash, "ab".repeat(20));
         assert_eq!(retrieved.size, 100);
 
         // Test entries iterator
         let entries: Vec<_> = index.entries().collect();
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].path, PathBuf::from("test.txt"));
+
+        // Other spellings only find the entry where the filesystem folds them
+        let folding = PathFolding { ignore_case: true, precompose_unicode: false };
+        assert_eq!(index.tracked_path(Path::new("Test.TXT"), &folding), Some(Path::new("test.txt")));
+        assert_eq!(index.tracked_path(Path::new("Test.TXT"), &PathFolding::EXACT), None);
+        assert_eq!(index.tracked_path(Path::new("other.txt"), &folding), None);
     }
 
     #[test]
     fn test_index_serialization() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let index_path = temp_dir.path().join("index");
-        
         // Create an index with some entries
         let mut index = Index::new();
         index.add_entry(IndexEntry {
@@ -283,7 +416,7 @@ mod tests {
             uid: 0,
             gid: 0,
             size: 100,
-            hash: "a".repeat(40),
+            hash: "a".repeat(40).parse()?,
             path: PathBuf::from("test1.txt"),
         });
         index.add_entry(IndexEntry {
@@ -295,15 +428,12 @@ mod tests {
             uid: 0,
             gid: 0,
             size: 200,
-            hash: "b".repeat(40),
+            hash: "b".repeat(40).parse()?,
             path: PathBuf::from("test2.txt"),
         });
         
-        // Write to file
-        index.write_to_file(&index_path)?;
-        
-        // Read back
-        let read_index = Index::read_from_file(&index_path)?;
+        // Round trip through the stored form
+        let read_index = Index::from_bytes(&index.to_bytes()?, Path::new("index"))?;
         
         // Verify entries match
         assert_eq!(read_index.entries.len(), 2);
@@ -0,0 +1,584 @@
+// Three-way merging of file contents and trees, and the state of a merge
+// that stopped on conflicts
+//
+// Both sides are diffed against their merge base. Base lines that survive
+// on both sides are stable points; between them each side either kept the
+// base, in which case the other side's version wins, or both changed it
+// differently, which is a conflict.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use crate::cobra::core::{
+    ancestry::merge_base_in,
+    events::RepositoryEvent,
+    diff::{diff_lines, split_lines, Edit},
+    index::{Index, IndexEntry},
+    object::{Object, TreeEntry},
+    oid::Oid,
+    repository::Repository,
+    signature::Signature,
+    transaction::ObjectTransaction,
+    tree::{build_tree_from_index_in, build_tree_in, commit_tree, flatten_tree},
+    workspace::{ApplyOptions, WorkspaceState},
+};
+use crate::cobra::utils::progress::NoProgress;
+use crate::cobra::messages;
+
+/// The commit being merged in by a merge that stopped on conflicts
+pub const MERGE_HEAD: &str = "MERGE_HEAD";
+/// The message that merge's commit will get
+pub const MERGE_MSG: &str = "MERGE_MSG";
+/// Commit saving the work tree and index from before the merge
+pub const MERGE_SNAPSHOT: &str = "MERGE_SNAPSHOT";
+/// The conflicted paths not yet marked resolved, one `<code>\t<path>` per line
+pub const MERGE_CONFLICTS: &str = "MERGE_CONFLICTS";
+
+/// One stretch of a merged file
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeChunk<'a> {
+    /// Lines both sides agree on once their changes are combined
+    Clean(Vec<&'a [u8]>),
+    /// Lines the two sides changed in different ways
+    Conflict {
+        base: Vec<&'a [u8]>,
+        ours: Vec<&'a [u8]>,
+        theirs: Vec<&'a [u8]>,
+    },
+}
+
+/// Merges the lines of `ours` and `theirs`, two edits of `base`
+pub fn merge_lines<'a>(base: &[&'a [u8]], ours: &[&'a [u8]], theirs: &[&'a [u8]]) -> Vec<MergeChunk<'a>> {
+    let ours_match = matched_lines(base, ours);
+    let theirs_match = matched_lines(base, theirs);
+    let mut chunks = Vec::new();
+    let mut clean: Vec<&'a [u8]> = Vec::new();
+    let (mut b, mut o, mut t) = (0, 0, 0);
+
+    while b < base.len() || o < ours.len() || t < theirs.len() {
+        // The next base line both sides kept, or the end of all three
+        let stable = (b..base.len())
+            .find_map(|i| Some((i, ours_match[i]?, theirs_match[i]?)))
+            .unwrap_or((base.len(), ours.len(), theirs.len()));
+
+        if stable == (b, o, t) {
+            clean.push(base[b]);
+            (b, o, t) = (b + 1, o + 1, t + 1);
+            continue;
+        }
+
+        let (base_part, ours_part, theirs_part) = (&base[b..stable.0], &ours[o..stable.1], &theirs[t..stable.2]);
+        if ours_part == base_part || ours_part == theirs_part {
+            clean.extend_from_slice(theirs_part);
+        } else if theirs_part == base_part {
+            clean.extend_from_slice(ours_part);
+        } else {
+            if !clean.is_empty() {
+                chunks.push(MergeChunk::Clean(std::mem::take(&mut clean)));
+            }
+            chunks.push(MergeChunk::Conflict {
+                base: base_part.to_vec(),
+                ours: ours_part.to_vec(),
+                theirs: theirs_part.to_vec(),
+            });
+        }
+        (b, o, t) = stable;
+    }
+    if !clean.is_empty() {
+        chunks.push(MergeChunk::Clean(clean));
+    }
+    chunks
+}
+
+/// For each base line, the line of `side` it was kept as, if any
+fn matched_lines(base: &[&[u8]], side: &[&[u8]]) -> Vec<Option<usize>> {
+    let mut matched = vec![None; base.len()];
+    for edit in diff_lines(base, side) {
+        if let Edit::Equal { old, new } = edit {
+            matched[old] = Some(new);
+        }
+    }
+    matched
+}
+
+/// What conflict markers call the two sides
+#[derive(Debug, Clone, Copy)]
+pub struct Labels<'a> {
+    pub ours: &'a str,
+    pub theirs: &'a str,
+}
+
+/// A file merged line by line
+#[derive(Debug)]
+pub struct FileMerge {
+    pub content: Vec<u8>,
+    /// Number of hunks left between conflict markers
+    pub conflicts: usize,
+}
+
+/// Merges two edits of `base`. Hunks the sides changed in different ways
+/// are written between `<<<<<<<`, `=======` and `>>>>>>>` markers.
+pub fn merge_contents(base: &[u8], ours: &[u8], theirs: &[u8], labels: Labels) -> FileMerge {
+    let (base, ours, theirs) = (split_lines(base), split_lines(ours), split_lines(theirs));
+    let mut merged = FileMerge { content: Vec::new(), conflicts: 0 };
+    for chunk in merge_lines(&base, &ours, &theirs) {
+        match chunk {
+            MergeChunk::Clean(lines) => merged.content.extend(lines.concat()),
+            MergeChunk::Conflict { ours, theirs, .. } => {
+                merged.conflicts += 1;
+                write_marker(&mut merged.content, "<<<<<<<", labels.ours);
+                merged.content.extend(ours.concat());
+                write_marker(&mut merged.content, "=======", "");
+                merged.content.extend(theirs.concat());
+                write_marker(&mut merged.content, ">>>>>>>", labels.theirs);
+            }
+        }
+    }
+    merged
+}
+
+/// Appends a conflict marker line, ending an unterminated last line first
+fn write_marker(content: &mut Vec<u8>, marker: &str, label: &str) {
+    if content.last().is_some_and(|&byte| byte != b'\n') {
+        content.push(b'\n');
+    }
+    content.extend_from_slice(marker.as_bytes());
+    if !label.is_empty() {
+        content.push(b' ');
+        content.extend_from_slice(label.as_bytes());
+    }
+    content.push(b'\n');
+}
+
+/// How the two sides of a conflicted path differ, named like git's
+/// short status codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictKind {
+    /// UU: edited on both sides
+    BothModified,
+    /// AA: added on both sides
+    BothAdded,
+    /// DU: deleted on our side, edited on theirs
+    DeletedByUs,
+    /// UD: edited on our side, deleted on theirs
+    DeletedByThem,
+}
+
+impl ConflictKind {
+    pub fn code(self) -> &'static str {
+        match self {
+            ConflictKind::BothModified => "UU",
+            ConflictKind::BothAdded => "AA",
+            ConflictKind::DeletedByUs => "DU",
+            ConflictKind::DeletedByThem => "UD",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<ConflictKind> {
+        match code {
+            "UU" => Some(ConflictKind::BothModified),
+            "AA" => Some(ConflictKind::BothAdded),
+            "DU" => Some(ConflictKind::DeletedByUs),
+            "UD" => Some(ConflictKind::DeletedByThem),
+            _ => None,
+        }
+    }
+}
+
+/// A path the two sides changed in ways that could not be combined
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub path: PathBuf,
+    pub kind: ConflictKind,
+    /// What belongs in the work tree: the file with conflict markers, or
+    /// the edited side of a file the other side deleted
+    pub content: Vec<u8>,
+    pub mode: u32,
+}
+
+/// The outcome of merging two trees
+#[derive(Debug)]
+pub struct TreeMerge {
+    /// Hash of the merged tree, staged in the transaction. Conflicted
+    /// paths keep our side.
+    pub tree: Oid,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl TreeMerge {
+    /// The merged tree, or an error naming the conflicting paths
+    pub fn into_tree(self) -> io::Result<Oid> {
+        if self.conflicts.is_empty() {
+            return Ok(self.tree);
+        }
+        let paths: Vec<String> = self.conflicts.iter().map(|conflict| conflict.path.display().to_string()).collect();
+        Err(io::Error::other(messages::merge_conflicts(paths.join(", "))))
+    }
+}
+
+/// Merges the trees of commits `ours` and `theirs` against their merge
+/// base, staging the merged blobs and trees in `transaction`. Unrelated
+/// histories merge as if they started from an empty tree.
+pub fn merge_commits(
+    git_dir: &Path,
+    transaction: &mut ObjectTransaction,
+    ours: Oid,
+    theirs: Oid,
+    labels: Labels,
+) -> io::Result<TreeMerge> {
+    let base = match merge_base_in(git_dir, ours, theirs)? {
+        Some(base) => Some(commit_tree(git_dir, base)?),
+        None => None,
+    };
+    let ours = commit_tree(git_dir, ours)?;
+    let theirs = commit_tree(git_dir, theirs)?;
+    merge_trees(git_dir, transaction, base, ours, theirs, labels)
+}
+
+/// Merges trees `ours` and `theirs` against `base` path by path. A file
+/// only one side changed takes that side; a file both sides edited is
+/// merged line by line. A file on one side where the other side has a
+/// directory cannot be represented and fails the whole merge.
+pub fn merge_trees(
+    git_dir: &Path,
+    transaction: &mut ObjectTransaction,
+    base: Option<Oid>,
+    ours: Oid,
+    theirs: Oid,
+    labels: Labels,
+) -> io::Result<TreeMerge> {
+    let base = match base {
+        Some(base) => flatten_tree(git_dir, base)?,
+        None => BTreeMap::new(),
+    };
+    let ours = flatten_tree(git_dir, ours)?;
+    let theirs = flatten_tree(git_dir, theirs)?;
+    let paths: BTreeSet<&PathBuf> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+    let entry_of = |entry: &TreeEntry| (entry.mode, entry.hash);
+
+    let mut entries = Vec::new();
+    let mut conflicts = Vec::new();
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+        let merged = if same(o, t) || same(t, b) {
+            o.map(entry_of)
+        } else if same(o, b) {
+            t.map(entry_of)
+        } else {
+            match merge_entry(git_dir, transaction, b, o, t, labels)? {
+                Outcome::Merged(merged) => merged,
+                Outcome::Conflicted { content, mode } => {
+                    let kind = match (b, o, t) {
+                        (_, None, _) => ConflictKind::DeletedByUs,
+                        (_, _, None) => ConflictKind::DeletedByThem,
+                        (None, _, _) => ConflictKind::BothAdded,
+                        _ => ConflictKind::BothModified,
+                    };
+                    conflicts.push(Conflict { path: path.clone(), kind, content, mode });
+                    o.map(entry_of)
+                }
+            }
+        };
+        if let Some((mode, hash)) = merged {
+            entries.push((path.clone(), mode, hash));
+        }
+    }
+
+    let files: BTreeSet<&Path> = entries.iter().map(|(path, _, _)| path.as_path())
+        .chain(conflicts.iter().map(|conflict| conflict.path.as_path()))
+        .collect();
+    if let Some(file) = files.iter().find_map(|path| path.ancestors().skip(1).find(|dir| files.contains(dir))) {
+        return Err(io::Error::other(messages::merge_conflicts(file.display())));
+    }
+
+    let tree = build_tree_in(transaction, &entries)?;
+    Ok(TreeMerge { tree: transaction.add(tree)?, conflicts })
+}
+
+/// True if both sides hold the same file, or both lack it
+fn same(a: Option<&TreeEntry>, b: Option<&TreeEntry>) -> bool {
+    a.map(|entry| (entry.mode, entry.hash)) == b.map(|entry| (entry.mode, entry.hash))
+}
+
+/// How a path both sides changed turned out
+enum Outcome {
+    /// The merged mode and blob, staged; None if the file is gone
+    Merged(Option<(u32, Oid)>),
+    /// What to leave in the work tree
+    Conflicted { content: Vec<u8>, mode: u32 },
+}
+
+/// Merges a path both sides changed in different ways: its mode, and its
+/// content line by line when it is a regular file everywhere
+fn merge_entry(
+    git_dir: &Path,
+    transaction: &mut ObjectTransaction,
+    base: Option<&TreeEntry>,
+    ours: Option<&TreeEntry>,
+    theirs: Option<&TreeEntry>,
+    labels: Labels,
+) -> io::Result<Outcome> {
+    let (Some(ours), Some(theirs)) = (ours, theirs) else {
+        // Edited on one side and deleted on the other: the edit stays around
+        return match ours.or(theirs) {
+            Some(kept) => Ok(Outcome::Conflicted { content: Object::read_blob(git_dir, kept.hash)?, mode: kept.mode }),
+            None => Ok(Outcome::Merged(None)),
+        };
+    };
+
+    let base_mode = base.map(|base| base.mode);
+    let mode = if Some(ours.mode) == base_mode || ours.mode == theirs.mode {
+        Some(theirs.mode)
+    } else if Some(theirs.mode) == base_mode {
+        Some(ours.mode)
+    } else {
+        None
+    };
+    let base_hash = base.map(|base| base.hash);
+    if let Some(mode) = mode {
+        if ours.hash == theirs.hash || Some(theirs.hash) == base_hash {
+            return Ok(Outcome::Merged(Some((mode, ours.hash))));
+        }
+        if Some(ours.hash) == base_hash {
+            return Ok(Outcome::Merged(Some((mode, theirs.hash))));
+        }
+    }
+
+    let regular = |entry: &TreeEntry| matches!(entry.mode, 0o100644 | 0o100755);
+    if !(regular(ours) && regular(theirs) && base.is_none_or(regular)) {
+        return Ok(Outcome::Conflicted { content: Object::read_blob(git_dir, ours.hash)?, mode: ours.mode });
+    }
+    let base_content = match base {
+        Some(base) => Object::read_blob(git_dir, base.hash)?,
+        None => Vec::new(),
+    };
+    let merged = merge_contents(&base_content, &Object::read_blob(git_dir, ours.hash)?, &Object::read_blob(git_dir, theirs.hash)?, labels);
+    match mode {
+        Some(mode) if merged.conflicts == 0 => {
+            Ok(Outcome::Merged(Some((mode, transaction.add(Object::new_blob(merged.content))?))))
+        }
+        mode => Ok(Outcome::Conflicted { content: merged.content, mode: mode.unwrap_or(ours.mode) }),
+    }
+}
+
+/// Conflicted paths whose work tree file differs from the index, which
+/// writing the conflict out would clobber
+pub fn locally_changed(repo: &Repository, conflicts: &[Conflict]) -> io::Result<Vec<PathBuf>> {
+    let mut changed = Vec::new();
+    for conflict in conflicts {
+        let current = match fs::read(repo.root_path.join(&conflict.path)) {
+            Ok(content) => Some(Object::new_blob(content).hash()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        if current != repo.index.get_entry(&conflict.path).map(|entry| entry.hash) {
+            changed.push(conflict.path.clone());
+        }
+    }
+    Ok(changed)
+}
+
+/// Writes each conflicted file into the work tree for the user to resolve
+pub fn write_conflicts(repo: &Repository, conflicts: &[Conflict]) -> io::Result<()> {
+    for conflict in conflicts {
+        let full_path = repo.root_path.join(&conflict.path);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&full_path, &conflict.content)?;
+        fs::set_permissions(&full_path, fs::Permissions::from_mode(conflict.mode & 0o777))?;
+    }
+    let paths = conflicts.iter().map(|conflict| conflict.path.clone()).collect();
+    repo.events.emit(RepositoryEvent::WorktreeFilesChanged { paths });
+    Ok(())
+}
+
+/// A conflicted path that has not been marked resolved yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unmerged {
+    pub path: PathBuf,
+    pub kind: ConflictKind,
+}
+
+/// A merge that stopped on conflicts, waiting to be committed or aborted
+#[derive(Debug, Clone)]
+pub struct MergeState {
+    /// The commit being merged in
+    pub head: Oid,
+    pub message: String,
+    /// Commit from `save_snapshot`, which --abort goes back to
+    pub snapshot: Oid,
+    pub unmerged: Vec<Unmerged>,
+}
+
+impl MergeState {
+    /// True while MERGE_HEAD exists
+    pub fn in_progress(repo: &Repository) -> bool {
+        repo.vfs.exists(&repo.git_dir.join(MERGE_HEAD))
+    }
+
+    /// Reads the merge in progress, if there is one
+    pub fn read(repo: &Repository) -> io::Result<Option<MergeState>> {
+        if !MergeState::in_progress(repo) {
+            return Ok(None);
+        }
+        let read = |name: &str| -> io::Result<String> {
+            Ok(String::from_utf8_lossy(&repo.vfs.read(&repo.git_dir.join(name))?).into_owned())
+        };
+        Ok(Some(MergeState {
+            head: read(MERGE_HEAD)?.trim().parse()?,
+            message: read(MERGE_MSG)?,
+            snapshot: read(MERGE_SNAPSHOT)?.trim().parse()?,
+            unmerged: MergeState::unmerged(repo)?,
+        }))
+    }
+
+    /// The conflicted paths still waiting to be resolved; none when no
+    /// merge is in progress
+    pub fn unmerged(repo: &Repository) -> io::Result<Vec<Unmerged>> {
+        let content = match repo.vfs.read(&repo.git_dir.join(MERGE_CONFLICTS)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(String::from_utf8_lossy(&content)
+            .lines()
+            .filter_map(|line| {
+                let (code, path) = line.split_once('\t')?;
+                Some(Unmerged { path: PathBuf::from(path), kind: ConflictKind::from_code(code)? })
+            })
+            .collect())
+    }
+
+    /// Marks the conflicted paths at or under any of `paths` resolved,
+    /// returning them
+    pub fn mark_resolved(repo: &Repository, paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+        let (resolved, unmerged): (Vec<Unmerged>, Vec<Unmerged>) = MergeState::unmerged(repo)?
+            .into_iter()
+            .partition(|unmerged| paths.iter().any(|path| unmerged.path.starts_with(path)));
+        if !resolved.is_empty() {
+            write_unmerged(repo, &unmerged)?;
+        }
+        Ok(resolved.into_iter().map(|unmerged| unmerged.path).collect())
+    }
+
+    /// Records the merge; MERGE_HEAD goes last, so it never names a merge
+    /// whose other files are missing
+    pub fn write(&self, repo: &Repository) -> io::Result<()> {
+        write_unmerged(repo, &self.unmerged)?;
+        repo.vfs.write_atomic(&repo.git_dir.join(MERGE_MSG), self.message.as_bytes())?;
+        repo.vfs.write_atomic(&repo.git_dir.join(MERGE_SNAPSHOT), format!("{}\n", self.snapshot).as_bytes())?;
+        repo.vfs.write_atomic(&repo.git_dir.join(MERGE_HEAD), format!("{}\n", self.head).as_bytes())
+    }
+
+    /// Forgets the merge in progress, MERGE_HEAD first
+    pub fn clear(repo: &Repository) -> io::Result<()> {
+        for name in [MERGE_HEAD, MERGE_MSG, MERGE_SNAPSHOT, MERGE_CONFLICTS] {
+            match repo.vfs.remove(&repo.git_dir.join(name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_unmerged(repo: &Repository, unmerged: &[Unmerged]) -> io::Result<()> {
+    let lines: String = unmerged.iter()
+        .map(|unmerged| format!("{}\t{}\n", unmerged.kind.code(), unmerged.path.display()))
+        .collect();
+    repo.vfs.write_atomic(&repo.git_dir.join(MERGE_CONFLICTS), lines.as_bytes())
+}
+
+/// Saves the index and work tree the way git saves a stash: a commit of
+/// the index on top of `head`, and a commit of the work tree whose
+/// parents are `head` and that index commit. Returns the latter.
+pub fn save_snapshot(repo: &Repository, head: Oid) -> io::Result<Oid> {
+    let author = Signature::author(&repo.git_dir)?;
+    let committer = Signature::committer(&repo.git_dir)?;
+    let mut transaction = repo.transaction().with_signoff_policy(false);
+
+    let index_tree = build_tree_from_index_in(repo, &mut transaction)?;
+    let index_tree = transaction.add(index_tree)?;
+    let index_commit = transaction.add(Object::new_commit(
+        index_tree,
+        vec![head],
+        author.clone(),
+        committer.clone(),
+        "Index before merge\n".to_string(),
+    ))?;
+    let work_tree = WorkspaceState::from_workspace(repo)?.create_tree(&mut transaction)?;
+    let snapshot = transaction.add(Object::new_commit(
+        work_tree,
+        vec![head, index_commit],
+        author,
+        committer,
+        "Work tree before merge\n".to_string(),
+    ))?;
+    transaction.commit()?;
+    Ok(snapshot)
+}
+
+/// Puts back the work tree and index saved by `save_snapshot`
+pub fn restore_snapshot(repo: &mut Repository, snapshot: Oid) -> io::Result<()> {
+    let Object::Commit { tree, parents, .. } = repo.read_object(snapshot)? else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, messages::not_a_commit(&snapshot.to_string())));
+    };
+    let index_commit = parents.get(1).copied().ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Merge snapshot {} has no index commit", snapshot),
+    ))?;
+
+    let options = ApplyOptions { preserve_mtime: true, ..Default::default() };
+    WorkspaceState::from_tree(repo, tree)?.apply_to_workspace(repo, &options, &mut NoProgress)?;
+
+    let mut index = Index::new();
+    for (path, entry) in flatten_tree(&repo.git_dir, commit_tree(&repo.git_dir, index_commit)?)? {
+        index.add_entry(IndexEntry::from_tree_blob(repo, &path, entry.mode, entry.hash)?);
+    }
+    repo.index = index;
+    repo.save_index()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LABELS: Labels = Labels { ours: "HEAD", theirs: "feature" };
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> Option<String> {
+        let merged = merge_contents(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), LABELS);
+        (merged.conflicts == 0).then(|| String::from_utf8(merged.content).unwrap())
+    }
+
+    #[test]
+    fn test_merge_contents_combines_separate_edits() {
+        let base = "a\nb\nc\nd\ne\n";
+        assert_eq!(merge(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\n").as_deref(), Some("A\nb\nc\nd\nE\n"));
+        assert_eq!(merge(base, "a\nb\nc\nd\ne\nf\n", "a\nc\nd\ne\n").as_deref(), Some("a\nc\nd\ne\nf\n"));
+        // The same change on both sides is taken once
+        assert_eq!(merge(base, "a\nB\nc\nd\ne\n", "a\nB\nc\nd\ne\n").as_deref(), Some("a\nB\nc\nd\ne\n"));
+        assert_eq!(merge(base, base, "x\n").as_deref(), Some("x\n"));
+    }
+
+    #[test]
+    fn test_merge_lines_reports_overlapping_edits() {
+        let base = split_lines(b"a\nb\nc\n");
+        let ours = split_lines(b"a\nours\nc\n");
+        let theirs = split_lines(b"a\ntheirs\nc\n");
+        assert_eq!(merge_lines(&base, &ours, &theirs), vec![
+            MergeChunk::Clean(vec![b"a\n"]),
+            MergeChunk::Conflict { base: vec![b"b\n"], ours: vec![b"ours\n"], theirs: vec![b"theirs\n"] },
+            MergeChunk::Clean(vec![b"c\n"]),
+        ]);
+
+        let merged = merge_contents(b"a\nb\nc\n", b"a\nours\nc\n", b"a\ntheirs", LABELS);
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            String::from_utf8(merged.content).unwrap(),
+            "a\n<<<<<<< HEAD\nours\nc\n=======\ntheirs\n>>>>>>> feature\n",
+        );
+    }
+}
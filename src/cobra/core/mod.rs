@@ -4,4 +4,35 @@ pub mod index;
 pub mod ref_store;
 pub mod tree;
 pub mod workspace;
-pub mod signature; 
\ No newline at end of file
+pub mod signature;
+pub mod checkout;
+pub mod events;
+pub mod diff;
+pub mod config;
+pub mod reflog;
+pub mod gc;
+pub mod hooks;
+pub mod ancestry;
+pub mod merge;
+pub mod blame;
+pub mod patch_id;
+pub mod fsck;
+pub mod case_fold;
+pub mod diffstat;
+pub mod revision;
+pub mod transaction;
+pub mod similarity;
+pub mod pack;
+pub mod protocol;
+pub mod daemon;
+pub mod transport;
+pub mod compression;
+pub mod verify_index;
+pub mod signoff;
+pub mod attributes;
+pub mod ignore;
+pub mod bitmap;
+pub mod import;
+pub mod oid;
+pub mod revparse;
+pub mod graph;
@@ -1,13 +1,16 @@
 // Git object model (blob, tree, commit) 
 
-use std::io::{self, Write, Read};
+use std::fmt;
+use std::io::{self, BufRead, Write};
 use std::fs;
 use std::path::Path;
-use flate2::write::ZlibEncoder;
-use flate2::read::ZlibDecoder;
-use flate2::Compression;
-use sha1::{Sha1, Digest};
+use std::time::SystemTime;
+use crate::cobra::core::compression::{self, CompressionSettings};
+use crate::cobra::core::oid::{HashAlgorithm, Oid};
 use crate::cobra::core::signature::Signature;
+use crate::cobra::utils::fs::{RealFs, Vfs};
+use crate::cobra::utils::trace::{self, Counter, Stream};
+use crate::cobra::messages;
 
 /// A tree entry represents a file or directory in a tree object
 #[derive(Debug, Clone)]
@@ -16,17 +19,53 @@ pub struct TreeEntry {
     pub mode: u32,
     /// The name of the file or directory
     pub name: String,
-    /// The SHA-1 hash of the blob or tree
-    pub hash: String,
+    /// The id of the blob or tree
+    pub hash: Oid,
+}
+
+/// A reason a commit would not serialize to a well-formed object
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// A tree or parent id is not a SHA-1 id, the only kind cobra stores
+    MalformedHash { field: &'static str, value: String },
+    /// An author or committer field would break the header line
+    InvalidSignature { field: &'static str, reason: String },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::MalformedHash { field, value } => {
+                write!(f, "malformed {} hash '{}'", field, value.escape_debug())
+            }
+            ValidationError::InvalidSignature { field, reason } => {
+                write!(f, "invalid {}: {}", field, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<ValidationError> for io::Error {
+    fn from(error: ValidationError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// True for a full, lowercase hex object name
+pub fn is_object_hash(value: &str) -> bool {
+    Oid::from_hex(value).is_ok_and(|oid| oid.algorithm() == HashAlgorithm::Sha1)
 }
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Object {
     Blob(Vec<u8>),
     Tree(Vec<TreeEntry>),
     Commit {
-        tree: String,
-        parents: Vec<String>,
+        tree: Oid,
+        parents: Vec<Oid>,
         author: Signature,
         committer: Signature,
         message: String,
@@ -45,7 +84,7 @@ impl Object {
     }
 
     /// Creates a new tree object from a list of entries
-    pub fn new_tree_from_entries(entries: Vec<(String, u32, String)>) -> Object {
+    pub fn new_tree_from_entries(entries: Vec<(String, u32, Oid)>) -> Object {
         let tree_entries: Vec<TreeEntry> = entries
             .into_iter()
             .map(|(name, mode, hash)| TreeEntry { mode, name, hash })
@@ -55,8 +94,8 @@ impl Object {
 
     /// Creates a new commit object
     pub fn new_commit(
-        tree: String,
-        parents: Vec<String>,
+        tree: Oid,
+        parents: Vec<Oid>,
         author: Signature,
         committer: Signature,
         message: String,
@@ -70,6 +109,29 @@ impl Object {
         }
     }
 
+    /// Checks that a commit's header fields cannot corrupt its serialized form.
+    /// Blobs and trees are always valid.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let (tree, parents, author, committer) = match self {
+            Object::Commit { tree, parents, author, committer, .. } => (tree, parents, author, committer),
+            _ => return Ok(()),
+        };
+
+        if tree.algorithm() != HashAlgorithm::Sha1 {
+            return Err(ValidationError::MalformedHash { field: "tree", value: tree.to_string() });
+        }
+        if let Some(parent) = parents.iter().find(|parent| parent.algorithm() != HashAlgorithm::Sha1) {
+            return Err(ValidationError::MalformedHash { field: "parent", value: parent.to_string() });
+        }
+        for (field, signature) in [("author", author), ("committer", committer)] {
+            signature.check().map_err(|reason| ValidationError::InvalidSignature {
+                field,
+                reason: reason.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
     /// Returns the object type as a string
     pub fn type_str(&self) -> &'static str {
         match self {
@@ -85,16 +147,16 @@ impl Object {
         match self {
             Object::Blob(data) => data.len(),
             Object::Tree(entries) => {
-                // Each entry: mode(6) + space(1) + name + null(1) + hash(20)
+                // Each entry: mode + space(1) + name + null(1) + hash(20)
                 entries.iter().map(|entry| {
-                    6 + 1 + entry.name.len() + 1 + 20
+                    format!("{:o}", entry.mode).len() + 1 + entry.name.len() + 1 + 20
                 }).sum()
             }
             Object::Commit { tree, parents, author, committer, message } => {
                 let mut size = 0;
-                size += "tree ".len() + tree.len() + 1; // +1 for newline
+                size += "tree ".len() + tree.algorithm().hex_len() + 1; // +1 for newline
                 for parent in parents {
-                    size += "parent ".len() + parent.len() + 1;
+                    size += "parent ".len() + parent.algorithm().hex_len() + 1;
                 }
                 size += "author ".len() + author.format().len() + 1;
                 size += "committer ".len() + committer.format().len() + 1;
@@ -105,6 +167,12 @@ impl Object {
         }
     }
 
+    /// Returns true if the object's content is empty
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Serializes the object into Git's format
     pub fn serialize(&self) -> Vec<u8> {
         match self {
@@ -112,23 +180,20 @@ impl Object {
             Object::Tree(entries) => {
                 let mut result = Vec::new();
                 for entry in entries {
-                    // Format: "<mode> <name>\0<hash_bytes>"
-                    write!(result, "{:06o} {}\0", entry.mode, entry.name).unwrap();
-                    // Convert hash from hex to bytes and handle invalid hex gracefully
-                    let hash_bytes = hex::decode(&entry.hash)
-                        .unwrap_or_else(|_| vec![0; 20]); // Use zeros for invalid hex in tests
-                    result.extend_from_slice(&hash_bytes);
+                    // Format: "<mode> <name>\0<hash_bytes>", with modes unpadded as in git ("40000")
+                    write!(result, "{:o} {}\0", entry.mode, entry.name).unwrap();
+                    result.extend_from_slice(entry.hash.as_bytes());
                 }
                 result
             }
             Object::Commit { tree, parents, author, committer, message } => {
                 let mut result = Vec::new();
-                write!(result, "tree {}\n", tree).unwrap();
+                writeln!(result, "tree {}", tree).unwrap();
                 for parent in parents {
-                    write!(result, "parent {}\n", parent).unwrap();
+                    writeln!(result, "parent {}", parent).unwrap();
                 }
-                write!(result, "author {}\n", author.format()).unwrap();
-                write!(result, "committer {}\n", committer.format()).unwrap();
+                writeln!(result, "author {}", author.format()).unwrap();
+                writeln!(result, "committer {}", committer.format()).unwrap();
                 write!(result, "\n{}", message).unwrap();
                 result
             }
@@ -138,45 +203,18 @@ impl Object {
     /// Compresses the serialized object using zlib
     #[allow(dead_code)]
     pub fn compress(&self) -> io::Result<Vec<u8>> {
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let mut encoder = compression::zlib_encoder(Vec::new(), compression::DEFAULT_LEVEL);
         encoder.write_all(&self.serialize())?;
         encoder.finish()
     }
 
-    /// Returns the SHA-1 hash of the object
-    pub fn hash(&self) -> String {
-        let content = self.serialize();
-        let header = format!("{} {}", self.type_str(), content.len());
-        let mut hasher = Sha1::new();
-        hasher.update(header.as_bytes());
-        hasher.update(b"\0");
-        hasher.update(&content);
-        hex::encode(hasher.finalize())
-    }
-
-    /// Writes the object to the object store
-    pub fn write_to(&self, repo_path: &Path) -> io::Result<String> {
-        let hash = self.hash();
-        let dir_name = &hash[..2];
-        let file_name = &hash[2..];
-        
-        let object_dir = repo_path.join(".cobra/objects").join(dir_name);
-        fs::create_dir_all(&object_dir)?;
-        
-        let object_path = object_dir.join(file_name);
-        if object_path.exists() {
-            // Object already exists, no need to write it again
-            return Ok(hash);
-        }
-
-        let compressed = self.compress()?;
-        fs::write(object_path, compressed)?;
-        
-        Ok(hash)
+    /// Returns the SHA-1 id of the object
+    pub fn hash(&self) -> Oid {
+        Oid::for_object(self.type_str(), &self.serialize())
     }
 
     /// Adds an entry to a tree object
-    pub fn add_tree_entry(&mut self, name: String, mode: u32, hash: String) -> io::Result<()> {
+    pub fn add_tree_entry(&mut self, name: String, mode: u32, hash: Oid) -> io::Result<()> {
         match self {
             Object::Tree(entries) => {
                 entries.push(TreeEntry { mode, name, hash });
@@ -190,19 +228,9 @@ impl Object {
     }
 
     /// Reads and parses an object from the object store
-    pub fn read_from(repo_path: &Path, hash: &str) -> io::Result<Object> {
-        let dir_name = &hash[..2];
-        let file_name = &hash[2..];
-        
-        let object_path = repo_path
-            .join(".cobra/objects")
-            .join(dir_name)
-            .join(file_name);
-            
-        let compressed = fs::read(object_path)?;
-        let mut decoder = ZlibDecoder::new(&compressed[..]);
-        let mut data = Vec::new();
-        decoder.read_to_end(&mut data)?;
+    pub fn read_from(repo_path: &Path, hash: Oid) -> io::Result<Object> {
+        let object_path = hash.loose_path(&repo_path.join(".cobra"));
+        let data = compression::decompress_loose(&fs::read(object_path)?)?;
         
         // Parse header
         let header_end = data.iter()
@@ -221,130 +249,150 @@ impl Object {
             .parse::<usize>()
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
             
-        let data = data[header_end + 1..].to_vec();
-        
-        match obj_type {
-            "blob" => Ok(Object::Blob(data)),
-            "tree" => {
-                let mut entries = Vec::new();
-                let mut i = 0;
-                while i < data.len() {
-                    // Parse mode
-                    let mode_end = data[i..].iter()
-                        .position(|&b| b == b' ')
-                        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid tree entry"))?;
-                    let mode = std::str::from_utf8(&data[i..i+mode_end])
-                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                    let mode = u32::from_str_radix(mode, 8)
-                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                    i += mode_end + 1;
-
-                    // Parse name
-                    let name_end = data[i..].iter()
-                        .position(|&b| b == 0)
-                        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid tree entry"))?;
-                    let name = std::str::from_utf8(&data[i..i+name_end])
-                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
-                        .to_string();
-                    i += name_end + 1;
-
-                    // Parse hash
-                    let hash = hex::encode(&data[i..i+20]);
-                    i += 20;
-
-                    entries.push(TreeEntry { mode, name, hash });
-                }
-                Ok(Object::Tree(entries))
-            }
-            "commit" => {
-                let content = String::from_utf8(data)
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                let mut lines = content.lines();
-                
-                // Parse tree
-                let tree_line = lines.next()
-                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing tree line"))?;
-                if !tree_line.starts_with("tree ") {
-                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid tree line"));
-                }
-                let tree = tree_line[5..].to_string();
-                
-                // Parse parents
-                let mut parents = Vec::new();
-                while let Some(line) = lines.next() {
-                    if line.starts_with("parent ") {
-                        parents.push(line[7..].to_string());
-                    } else {
-                        // Move on to author line
-                        if !line.starts_with("author ") {
-                            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid author line"));
-                        }
-                        let author = Signature::parse(&line[7..])?;
-                        
-                        // Parse committer
-                        let committer_line = lines.next()
-                            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing committer line"))?;
-                        if !committer_line.starts_with("committer ") {
-                            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid committer line"));
-                        }
-                        let committer = Signature::parse(&committer_line[10..])?;
-                        
-                        // Skip empty line
-                        let empty_line = lines.next()
-                            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing empty line"))?;
-                        if !empty_line.is_empty() {
-                            return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected empty line"));
-                        }
-                        
-                        // Rest is commit message
-                        let message = lines.collect::<Vec<_>>().join("\n");
-                        
-                        return Ok(Object::Commit {
-                            tree,
-                            parents,
-                            author,
-                            committer,
-                            message,
-                        });
-                    }
-                }
-                
-                Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid commit object"))
-            }
-            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown object type")),
-        }
+        Object::parse(obj_type, &data[header_end + 1..])
     }
 
-    pub fn write_to_objects_dir(&self, git_dir: &Path) -> io::Result<()> {
-        let hash = self.hash();
-        let dir = git_dir.join("objects").join(&hash[..2]);
-        let file = dir.join(&hash[2..]);
+    /// Writes the object to the object store under `git_dir`, reached
+    /// through `vfs`. Returns true if the object was newly created, false if
+    /// it already existed (its mtime is refreshed instead).
+    pub fn write_to_store(&self, vfs: &dyn Vfs, git_dir: &Path, settings: CompressionSettings) -> io::Result<bool> {
+        let written = trace::timed(Stream::Perf, "object write", || self.write_loose(vfs, git_dir, settings), |written| {
+            let existing = if matches!(written, Ok(false)) { " (already present)" } else { "" };
+            format!("{} {}{}", self.type_str(), self.hash(), existing)
+        })?;
+        if written {
+            trace::increment(Counter::ObjectsWritten);
+        }
+        Ok(written)
+    }
 
-        if !dir.exists() {
-            fs::create_dir_all(&dir)?;
+    fn write_loose(&self, vfs: &dyn Vfs, git_dir: &Path, settings: CompressionSettings) -> io::Result<bool> {
+        let file = self.hash().loose_path(git_dir);
+        if let Some(dir) = file.parent().filter(|dir| !vfs.exists(dir)) {
+            vfs.create_dir_all(dir)?;
         }
 
-        if !file.exists() {
-            let content = self.serialize();
-            let header = format!("{} {}", self.type_str(), content.len());
-            let mut file = fs::File::create(file)?;
-            let mut encoder = ZlibEncoder::new(&mut file, Compression::default());
-            encoder.write_all(header.as_bytes())?;
-            encoder.write_all(b"\0")?;
-            encoder.write_all(&content)?;
-            encoder.finish()?;
+        if vfs.exists(&file) {
+            // Bump the mtime so gc treats the object as recently used
+            vfs.touch(&file)?;
+            return Ok(false);
         }
 
+        let mut content = Vec::new();
+        self.write_loose_to(&mut content, settings)?;
+        vfs.write_atomic(&file, &content)?;
+        Ok(true)
+    }
+
+    /// Writes the object in loose format (compressed header and content)
+    pub fn write_loose_to(&self, out: &mut dyn Write, settings: CompressionSettings) -> io::Result<()> {
+        let content = self.serialize();
+        let header = format!("{} {}", self.type_str(), content.len());
+        let mut encoder = compression::loose_encoder(out, settings)?;
+        encoder.write_all(header.as_bytes())?;
+        encoder.write_all(b"\0")?;
+        encoder.write_all(&content)?;
+        encoder.finish()?;
         Ok(())
     }
 
-    pub fn read_from_objects_dir(git_dir: &Path, hash: &str) -> io::Result<Object> {
-        let path = git_dir.join("objects").join(&hash[..2]).join(&hash[2..]);
-        let file = fs::File::open(path)?;
-        let mut decoder = ZlibDecoder::new(file);
-        let mut content = Vec::new();
-        decoder.read_to_end(&mut content)?;
+    pub fn read_from_objects_dir(git_dir: &Path, hash: Oid) -> io::Result<Object> {
+        Object::read_from_store(&RealFs::new(git_dir), git_dir, hash)
+    }
+
+    /// Reads the content of blob `hash`; any other kind of object is
+    /// InvalidData
+    pub fn read_blob(git_dir: &Path, hash: Oid) -> io::Result<Vec<u8>> {
+        match Object::read_from_objects_dir(git_dir, hash)? {
+            Object::Blob(content) => Ok(content),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                messages::not_a_blob(&hash.to_string()),
+            )),
+        }
+    }
+
+    /// Like `read_from_objects_dir`, for an object store reached through `vfs`
+    pub fn read_from_store(vfs: &dyn Vfs, git_dir: &Path, hash: Oid) -> io::Result<Object> {
+        let object = trace::timed(Stream::Perf, "object read", || Object::read_loose(vfs, git_dir, hash), |object| {
+            match object {
+                Ok(object) => format!("{} {}", object.type_str(), hash),
+                Err(e) => format!("{} failed: {}", hash, e),
+            }
+        })?;
+        trace::increment(Counter::ObjectsRead);
+        Ok(object)
+    }
+
+    fn read_loose(vfs: &dyn Vfs, git_dir: &Path, hash: Oid) -> io::Result<Object> {
+        let path = hash.loose_path(git_dir);
+        Object::parse_loose(&compression::decompress_loose(&vfs.read(&path)?)?)
+    }
+
+    /// Lists the names of all loose objects starting with `prefix`, in
+    /// order. The prefix needs at least the two digits naming a fan-out
+    /// directory; uppercase digits are accepted.
+    pub fn find_by_prefix(git_dir: &Path, prefix: &str) -> io::Result<Vec<Oid>> {
+        Object::find_by_prefix_in_store(&RealFs::new(git_dir), git_dir, prefix)
+    }
+
+    /// Like `find_by_prefix`, for an object store reached through `vfs`
+    pub fn find_by_prefix_in_store(vfs: &dyn Vfs, git_dir: &Path, prefix: &str) -> io::Result<Vec<Oid>> {
+        let prefix = prefix.to_ascii_lowercase();
+        if prefix.len() < 2 || prefix.len() > 40 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a valid object name prefix", prefix),
+            ));
+        }
+
+        let fan_out = git_dir.join("objects").join(&prefix[..2]);
+        if !vfs.exists(&fan_out) {
+            return Ok(Vec::new());
+        }
+        let mut hashes: Vec<Oid> = vfs.read_dir(&fan_out)?
+            .into_iter()
+            .map(|name| format!("{}{}", &prefix[..2], name.to_string_lossy()))
+            .filter(|hash| hash.starts_with(&prefix))
+            .filter_map(|hash| Oid::from_hex(&hash).ok())
+            .collect();
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    /// Streams the content of the loose blob `hash` into `out` without
+    /// holding it in memory, returning its size
+    pub fn copy_blob(git_dir: &Path, hash: Oid, out: &mut dyn Write) -> io::Result<u64> {
+        let path = hash.loose_path(git_dir);
+        let mut reader = io::BufReader::new(compression::loose_decoder(fs::File::open(&path)?)?);
+        let mut header = Vec::new();
+        reader.read_until(0, &mut header)?;
+        let size = match std::str::from_utf8(&header).ok().and_then(|header| header.strip_suffix('\0')) {
+            Some(header) => match header.strip_prefix("blob ").map(str::parse::<u64>) {
+                Some(Ok(size)) => size,
+                _ => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    messages::not_a_blob(&hash.to_string()),
+                )),
+            },
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid object format")),
+        };
+        let copied = io::copy(&mut reader, out)?;
+        if copied != size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Content size mismatch"));
+        }
+        trace::increment(Counter::ObjectsRead);
+        Ok(copied)
+    }
 
+    /// Decompresses a loose object file, returning the header and content
+    /// exactly as stored. Their SHA-1 is the object's name.
+    pub fn read_loose_file(path: &Path) -> io::Result<Vec<u8>> {
+        compression::decompress_loose(&fs::read(path)?)
+    }
+
+    /// Parses the decompressed contents of a loose object file
+    pub fn parse_loose(content: &[u8]) -> io::Result<Object> {
         // Find null byte separating header from content
         let null_pos = content.iter()
             .position(|&b| b == 0)
@@ -372,26 +420,15 @@ impl Object {
         let content = String::from_utf8(data.to_vec())
             .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid commit encoding"))?;
         
-        let mut tree = String::new();
+        let mut tree = None;
         let mut parents = Vec::new();
         let mut author = None;
         let mut committer = None;
-        let mut message = String::new();
-        let mut in_message = false;
+        // The message is everything after the first empty line, byte for byte
+        let (headers, message) = content.split_once("\n\n").unwrap_or((&content, ""));
+        let message = message.to_string();
 
-        for line in content.lines() {
-            if line.is_empty() {
-                in_message = true;
-                continue;
-            }
-
-            if in_message {
-                if !message.is_empty() {
-                    message.push('\n');
-                }
-                message.push_str(line);
-                continue;
-            }
+        for line in headers.lines() {
 
             let space_pos = line.find(' ')
                 .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid commit format"))?;
@@ -399,8 +436,8 @@ impl Object {
             let value = value.trim();
 
             match key {
-                "tree" => tree = value.to_string(),
-                "parent" => parents.push(value.to_string()),
+                "tree" => tree = Some(header_oid(value)?),
+                "parent" => parents.push(header_oid(value)?),
                 "author" => {
                     author = Some(Signature::parse(value)?);
                 }
@@ -411,6 +448,7 @@ impl Object {
             }
         }
 
+        let tree = tree.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing tree"))?;
         let author = author.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing author"))?;
         let committer = committer.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing committer"))?;
 
@@ -455,7 +493,7 @@ impl Object {
             if i + 20 > data.len() {
                 return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid tree format: incomplete hash"));
             }
-            let hash = hex::encode(&data[i..i + 20]);
+            let hash = Oid::from_bytes(&data[i..i + 20])?;
             i += 20;
 
             entries.push(TreeEntry { mode, name, hash });
@@ -473,6 +511,18 @@ impl Object {
     }
 }
 
+/// The id in a commit's tree or parent header; a malformed one means the
+/// commit itself is corrupt
+fn header_oid(value: &str) -> io::Result<Oid> {
+    Oid::from_hex(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Bumps the mtime of an existing loose object so gc treats it as recently
+/// used. Loose objects may be read-only, so the file is only opened for reading.
+pub fn freshen_loose(path: &Path) -> io::Result<()> {
+    fs::File::open(path)?.set_times(fs::FileTimes::new().set_modified(SystemTime::now()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -488,12 +538,11 @@ mod tests {
     #[test]
     fn test_tree_serialization() -> io::Result<()> {
         let mut tree = Object::new_tree();
-        // Use a valid hex string for the hash (40 chars)
-        let hash = "1234567890123456789012345678901234567890".to_string();
+        let hash: Oid = "1234567890123456789012345678901234567890".parse()?;
         tree.add_tree_entry(
             "test.txt".to_string(),
             0o100644,
-            hash.clone(),
+            hash,
         )?;
 
         let serialized = tree.serialize();
@@ -513,25 +562,27 @@ mod tests {
     }
 
     #[test]
-    fn test_commit_serialization() {
+    fn test_commit_serialization() -> io::Result<()> {
         let author = Signature::new("John Doe".to_string(), "john@example.com".to_string());
         let committer = Signature::new("Jane Doe".to_string(), "jane@example.com".to_string());
+        let tree_hash: Oid = "abcdef".repeat(7)[..40].parse()?;
+        let parent_hash: Oid = "123456".repeat(7)[..40].parse()?;
 
         let commit = Object::new_commit(
-            "abcdef".to_string(),
-            vec!["123456".to_string()],
+            tree_hash,
+            vec![parent_hash],
             author.clone(),
             committer.clone(),
             "Initial commit".to_string(),
         );
 
         let serialized = commit.serialize();
-        let parsed = Object::parse_commit(&serialized).unwrap();
+        let parsed = Object::parse_commit(&serialized)?;
 
         match parsed {
             Object::Commit { tree, parents, author, committer, message } => {
-                assert_eq!(tree, "abcdef");
-                assert_eq!(parents, vec!["123456"]);
+                assert_eq!(tree, tree_hash);
+                assert_eq!(parents, vec![parent_hash]);
                 assert_eq!(author.name, "John Doe");
                 assert_eq!(author.email, "john@example.com");
                 assert_eq!(committer.name, "Jane Doe");
@@ -540,6 +591,31 @@ mod tests {
             }
             _ => panic!("Expected commit object"),
         }
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_paragraph_commit_message_round_trip() -> io::Result<()> {
+        let signature = Signature::new("John Doe".to_string(), "john@example.com".to_string());
+        let message = "Subject line\n\nFirst paragraph\nstill the first\n\n\nSecond paragraph\n";
+        let tree = Oid::for_object("tree", b"");
+        let commit = Object::new_commit(tree, vec![], signature.clone(), signature, message.to_string());
+
+        let serialized = commit.serialize();
+        assert!(String::from_utf8_lossy(&serialized).ends_with("\n\nSubject line\n\nFirst paragraph\nstill the first\n\n\nSecond paragraph\n"));
+        let Object::Commit { message: parsed, .. } = Object::parse_commit(&serialized)? else {
+            panic!("Expected commit object");
+        };
+        // Blank lines inside the body and the final newline all survive
+        assert_eq!(parsed, message);
+
+        // Re-serializing reproduces the object, so its hash stays the same
+        for message in ["Trailing blank lines\n\n\n", "No final newline", ""] {
+            let signature = Signature::new("John Doe".to_string(), "john@example.com".to_string());
+            let commit = Object::new_commit(tree, vec![], signature.clone(), signature, message.to_string());
+            assert_eq!(Object::parse_commit(&commit.serialize())?.serialize(), commit.serialize(), "{:?}", message);
+        }
+        Ok(())
     }
 
     #[test]
@@ -581,4 +657,145 @@ mod tests {
 
         Ok(())
     }
-} 
\ No newline at end of file
+
+    fn signature(name: &str, email: &str) -> Signature {
+        Signature {
+            name: name.to_string(),
+            email: email.to_string(),
+            timestamp: 1234567890,
+            timezone: "+0000".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate_rejects_malformed_hashes() -> io::Result<()> {
+        let tree: Oid = "a".repeat(40).parse()?;
+        let sha256: Oid = "b".repeat(64).parse()?;
+        let author = signature("A U Thor", "author@example.com");
+
+        // Short, uppercase or non-hex names never become an Oid at all
+        for bad in ["", "main_commit", &"A".repeat(40)] {
+            assert!(bad.parse::<Oid>().is_err(), "{:?}", bad);
+        }
+
+        // SHA-256 ids parse, but this store writes SHA-1 commits only
+        let wide_tree = Object::new_commit(sha256, vec![], author.clone(), author.clone(), "m".into());
+        assert_eq!(
+            wide_tree.validate(),
+            Err(ValidationError::MalformedHash { field: "tree", value: "b".repeat(64) }),
+        );
+
+        let bad_parent = Object::new_commit(tree, vec![sha256], author.clone(), author.clone(), "m".into());
+        assert!(matches!(bad_parent.validate(), Err(ValidationError::MalformedHash { field: "parent", .. })));
+
+        let good = Object::new_commit(tree, vec![tree], author.clone(), author, "m".into());
+        assert_eq!(good.validate(), Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hostile_signatures_are_sanitized_or_rejected() -> io::Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let tree: Oid = "a".repeat(40).parse()?;
+        let fragments = [
+            "", "A U Thor", "<", ">", "\n", "\r\n", "\0", " ", ".", "evil> 0 +0000\ncommitter x",
+            "\"quoted\"", "\\", "ünï", "a@b", "<<>>", "\nparent 0000",
+        ];
+
+        // Deterministic pseudo-random pairs of fragments
+        let mut seed: u64 = 0x2545F4914F6CDD1D;
+        let mut pick = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            fragments[(seed % fragments.len() as u64) as usize]
+        };
+
+        for _ in 0..500 {
+            let name = format!("{}{}{}", pick(), pick(), pick());
+            let email = format!("{}{}", pick(), pick());
+            let raw = signature(&name, &email);
+            let commit = Object::new_commit(tree, vec![], raw.clone(), raw.clone(), "msg".into());
+
+            // Unsanitized input containing header-breaking characters is always rejected
+            if name.contains(['<', '>', '\n', '\r', '\0']) || email.contains(['<', '>', '\n', '\r', '\0']) {
+                assert!(commit.validate().is_err(), "accepted {:?} <{:?}>", name, email);
+            }
+
+            let clean = raw.sanitized();
+            let commit = Object::new_commit(tree, vec![], clean.clone(), clean.clone(), "msg".into());
+            match commit.validate() {
+                Ok(()) => {
+                    // Whatever is accepted must round-trip through the object store unchanged
+                    commit.write_to_store(&RealFs::new(temp_dir.path()), temp_dir.path(), CompressionSettings::default())?;
+                    match Object::read_from_objects_dir(temp_dir.path(), commit.hash())? {
+                        Object::Commit { author, committer, message, .. } => {
+                            assert_eq!((author.name, author.email), (clean.name.clone(), clean.email.clone()));
+                            assert_eq!(committer.name, clean.name);
+                            assert_eq!(message, "msg");
+                        }
+                        _ => panic!("Expected commit object"),
+                    }
+                }
+                Err(error) => {
+                    assert_eq!(error, ValidationError::InvalidSignature {
+                        field: "author",
+                        reason: "empty ident name not allowed".to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_by_prefix_scans_fan_out_directory() -> io::Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let git_dir = temp_dir.path();
+        let blob = Object::new_blob(b"content\n".to_vec());
+        blob.write_to_store(&RealFs::new(git_dir), git_dir, CompressionSettings::default())?;
+        let id = blob.hash();
+        let hash = id.to_string();
+
+        // A second object sharing the first five digits, and a stray file
+        let fan_out = git_dir.join("objects").join(&hash[..2]);
+        let mut other = hash[..5].to_string();
+        other.push(if hash.as_bytes()[5] == b'0' { '1' } else { '0' });
+        other.push_str(&hash[6..]);
+        fs::copy(fan_out.join(&hash[2..]), fan_out.join(&other[2..]))?;
+        fs::write(fan_out.join(format!("{}.lock", &hash[2..12])), "")?;
+
+        let mut both = vec![id, other.parse()?];
+        both.sort();
+        assert_eq!(Object::find_by_prefix(git_dir, &hash[..2])?, both);
+        assert_eq!(Object::find_by_prefix(git_dir, &hash[..5].to_uppercase())?, both);
+        assert_eq!(Object::find_by_prefix(git_dir, &hash[..6])?, vec![id]);
+        assert_eq!(Object::find_by_prefix(git_dir, &hash)?, vec![id]);
+
+        let missing = if hash.starts_with("ff") { "00" } else { "ff" };
+        assert!(Object::find_by_prefix(git_dir, missing)?.is_empty());
+        for bad in ["", "a", "xyz0", &format!("{}0", hash)] {
+            let error = Object::find_by_prefix(git_dir, bad).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_freshen_read_only_object() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::Duration;
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let path = temp_dir.path().join("object");
+        fs::write(&path, "content")?;
+        let old = SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+        fs::File::open(&path)?.set_modified(old)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o444))?;
+
+        freshen_loose(&path)?;
+        assert!(fs::metadata(&path)?.modified()? > old);
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+// Object ids: fixed-size hashes that are cheap to copy, compare and hash
+//
+// Objects, the object store, the index and refs all carry Oids, so short
+// or malformed names fail with an error instead of a slicing panic. Hex
+// strings appear only where ids cross a boundary: command line arguments
+// and output, the wire protocol, and the reflog and ref file formats.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use sha1::{Digest, Sha1};
+
+/// The hash function an object id was computed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Length of an id in bytes
+    pub fn size(self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+        }
+    }
+
+    /// Length of an id in hex digits
+    pub fn hex_len(self) -> usize {
+        self.size() * 2
+    }
+
+    fn from_len(len: usize) -> Option<HashAlgorithm> {
+        match len {
+            20 => Some(HashAlgorithm::Sha1),
+            32 => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// A complete object id. Ids sort by algorithm, then in the same order as
+/// their hex names.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Oid {
+    algorithm: HashAlgorithm,
+    bytes: [u8; 32],
+}
+
+impl Oid {
+    /// Parses a full lowercase hex name, 40 digits for SHA-1 or 64 for SHA-256
+    pub fn from_hex(hex: &str) -> io::Result<Oid> {
+        let invalid = || io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not a valid object name", hex),
+        );
+        let algorithm = match hex.len() % 2 {
+            0 => HashAlgorithm::from_len(hex.len() / 2).ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        };
+        let mut bytes = [0u8; 32];
+        for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digit = |c: u8| match c {
+                b'0'..=b'9' => Some(c - b'0'),
+                b'a'..=b'f' => Some(c - b'a' + 10),
+                _ => None,
+            };
+            *byte = match (digit(pair[0]), digit(pair[1])) {
+                (Some(high), Some(low)) => high << 4 | low,
+                _ => return Err(invalid()),
+            };
+        }
+        Ok(Oid { algorithm, bytes })
+    }
+
+    /// An id from its raw bytes, 20 for SHA-1 or 32 for SHA-256
+    pub fn from_bytes(raw: &[u8]) -> io::Result<Oid> {
+        let algorithm = HashAlgorithm::from_len(raw.len()).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("An object id cannot be {} bytes long", raw.len()),
+        ))?;
+        let mut bytes = [0u8; 32];
+        bytes[..raw.len()].copy_from_slice(raw);
+        Ok(Oid { algorithm, bytes })
+    }
+
+    /// The SHA-1 id of an object with this type and content
+    pub fn for_object(kind: &str, content: &[u8]) -> Oid {
+        let mut hasher = Sha1::new();
+        hasher.update(format!("{} {}\0", kind, content.len()));
+        hasher.update(content);
+        let mut bytes = [0u8; 32];
+        bytes[..20].copy_from_slice(&hasher.finalize());
+        Oid { algorithm: HashAlgorithm::Sha1, bytes }
+    }
+
+    /// The all-zero id refs and reflogs use for "nothing"
+    pub fn null(algorithm: HashAlgorithm) -> Oid {
+        Oid { algorithm, bytes: [0; 32] }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.as_bytes().iter().all(|&b| b == 0)
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.algorithm.size()]
+    }
+
+    /// The first `len` hex digits, or the whole name when it is shorter
+    pub fn abbrev(&self, len: usize) -> String {
+        let mut hex = self.to_string();
+        hex.truncate(len);
+        hex
+    }
+
+    /// Where the object is stored loose: `objects/<2 digits>/<the rest>`
+    pub fn loose_path(&self, git_dir: &Path) -> PathBuf {
+        let hex = self.to_string();
+        git_dir.join("objects").join(&hex[..2]).join(&hex[2..])
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.as_bytes() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Oid({})", self)
+    }
+}
+
+impl FromStr for Oid {
+    type Err = io::Error;
+
+    fn from_str(hex: &str) -> io::Result<Oid> {
+        Oid::from_hex(hex)
+    }
+}
+
+impl From<Oid> for String {
+    fn from(oid: Oid) -> String {
+        oid.to_string()
+    }
+}
+
+impl PartialEq<str> for Oid {
+    fn eq(&self, hex: &str) -> bool {
+        Oid::from_hex(hex).is_ok_and(|other| other == *self)
+    }
+}
+
+impl PartialEq<&str> for Oid {
+    fn eq(&self, hex: &&str) -> bool {
+        *self == **hex
+    }
+}
+
+impl PartialEq<String> for Oid {
+    fn eq(&self, hex: &String) -> bool {
+        *self == *hex.as_str()
+    }
+}
+
+impl PartialEq<Oid> for String {
+    fn eq(&self, oid: &Oid) -> bool {
+        *oid == *self.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::utils::hash::hash_raw_object;
+
+    const HELLO: &str = "ce013625030ba8dba906f756967f9e9ca394464a";
+
+    #[test]
+    fn test_hex_round_trip_and_validation() -> io::Result<()> {
+        let oid = Oid::from_hex(HELLO)?;
+        assert_eq!(oid.to_string(), HELLO);
+        assert_eq!(oid.algorithm(), HashAlgorithm::Sha1);
+        assert_eq!(oid.as_bytes().len(), 20);
+        assert_eq!(Oid::from_bytes(oid.as_bytes())?, oid);
+        assert_eq!(oid.abbrev(7), "ce01362");
+        assert_eq!(oid.abbrev(100), HELLO);
+        assert_eq!(oid, *HELLO);
+        assert_eq!(Oid::for_object("blob", b"hello\n"), oid);
+        assert_eq!(Oid::for_object("blob", b"").to_string(), hash_raw_object("blob", b""));
+        assert_eq!(oid.loose_path(Path::new(".cobra")), Path::new(".cobra/objects/ce/013625030ba8dba906f756967f9e9ca394464a"));
+
+        let sha256 = "a".repeat(64).parse::<Oid>()?;
+        assert_eq!(sha256.algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(sha256.to_string().len(), 64);
+        assert!(Oid::null(HashAlgorithm::Sha1).is_null());
+
+        for bad in ["", "ce01362", &HELLO[..39], &HELLO.to_uppercase(), &format!("{}g", &HELLO[..39]), "é"] {
+            assert_eq!(Oid::from_hex(bad).unwrap_err().kind(), io::ErrorKind::InvalidInput, "{:?}", bad);
+        }
+        assert!(Oid::from_bytes(&[0; 19]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ordering_matches_hex_order() -> io::Result<()> {
+        let mut names = vec![
+            "ff00000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000001",
+            "0f00000000000000000000000000000000000000",
+            HELLO,
+        ];
+        let mut oids: Vec<Oid> = names.iter().map(|name| name.parse()).collect::<io::Result<_>>()?;
+        names.sort();
+        oids.sort();
+        assert_eq!(oids.iter().map(Oid::to_string).collect::<Vec<_>>(), names);
+        Ok(())
+    }
+}
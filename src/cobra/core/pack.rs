@@ -0,0 +1,287 @@
+// Pack files, the format objects travel in between repositories
+//
+// A pack is "PACK", a version (2) and an object count, then each object as a
+// type-and-size header followed by its zlib-compressed content, and finally
+// the SHA-1 of everything before it. Objects are written whole; packs
+// containing deltas (as git sends when it has a base to diff against) are
+// rejected when read.
+
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use flate2::read::ZlibDecoder;
+use sha1::{Digest, Sha1};
+use crate::cobra::core::bitmap::{reachable_from, ReachabilityBitmaps};
+use crate::cobra::core::compression::{self, CompressionSettings};
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::utils::trace::{self, Stream};
+
+const SIGNATURE: &[u8; 4] = b"PACK";
+const VERSION: u32 = 2;
+
+/// Mode of tree entries that point at a commit in another repository
+pub const GITLINK_MODE: u32 = 0o160000;
+
+/// An object as stored: its type name and serialized content
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawObject {
+    pub kind: &'static str,
+    pub data: Vec<u8>,
+}
+
+impl RawObject {
+    /// Reads a loose object without parsing it, so it travels byte for byte
+    pub fn read(git_dir: &Path, hash: Oid) -> io::Result<RawObject> {
+        let content = Object::read_loose_file(&hash.loose_path(git_dir))?;
+        let header_end = content.iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid object header in {}", hash)))?;
+        let header = String::from_utf8_lossy(&content[..header_end]);
+        let kind = kind_name(type_code(header.split(' ').next().unwrap_or_default())?)?;
+        Ok(RawObject { kind, data: content[header_end + 1..].to_vec() })
+    }
+
+    pub fn hash(&self) -> Oid {
+        Oid::for_object(self.kind, &self.data)
+    }
+
+    /// Writes the object in loose format, byte for byte as received
+    pub fn write_loose_to(&self, out: &mut dyn Write, settings: CompressionSettings) -> io::Result<()> {
+        let mut encoder = compression::loose_encoder(out, settings)?;
+        write!(encoder, "{} {}\0", self.kind, self.data.len())?;
+        encoder.write_all(&self.data)?;
+        encoder.finish()?;
+        Ok(())
+    }
+}
+
+fn type_code(kind: &str) -> io::Result<u8> {
+    match kind {
+        "commit" => Ok(1),
+        "tree" => Ok(2),
+        "blob" => Ok(3),
+        "tag" => Ok(4),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown object type '{}'", kind))),
+    }
+}
+
+fn kind_name(type_code: u8) -> io::Result<&'static str> {
+    match type_code {
+        1 => Ok("commit"),
+        2 => Ok("tree"),
+        3 => Ok("blob"),
+        4 => Ok("tag"),
+        6 | 7 => Err(io::Error::new(io::ErrorKind::Unsupported, "Packs with delta objects are not supported")),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid pack: unknown object type {}", type_code))),
+    }
+}
+
+/// Objects reachable from `wants` that are not reachable from `haves`, in
+/// the order commits, then the trees and blobs they need. Haves this
+/// repository does not know are ignored. The haves' closure, usually the
+/// larger one, comes from the reachability bitmaps where they cover it.
+pub fn objects_between(git_dir: &Path, wants: &[Oid], haves: &[Oid]) -> io::Result<Vec<Oid>> {
+    let bitmaps = ReachabilityBitmaps::open(git_dir);
+    let known = reachable_from(git_dir, haves, bitmaps.as_ref())?.objects;
+    closure(git_dir, wants, &known)
+}
+
+fn closure(git_dir: &Path, roots: &[Oid], exclude: &HashSet<Oid>) -> io::Result<Vec<Oid>> {
+    let mut seen = HashSet::new();
+    let mut order = Vec::new();
+    let mut pending: Vec<Oid> = roots.iter().rev().copied().collect();
+    while let Some(hash) = pending.pop() {
+        if hash.is_null() || exclude.contains(&hash) || !seen.insert(hash) {
+            continue;
+        }
+        let object = match Object::read_from_objects_dir(git_dir, hash) {
+            Ok(object) => object,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && exclude.is_empty() => continue,
+            Err(e) => return Err(e),
+        };
+        match object {
+            Object::Commit { tree, parents, .. } => {
+                pending.extend(parents);
+                pending.push(tree);
+            }
+            Object::Tree(entries) => {
+                pending.extend(entries.into_iter()
+                    .filter(|entry| entry.mode != GITLINK_MODE)
+                    .map(|entry| entry.hash));
+            }
+            Object::Blob(_) => {}
+        }
+        order.push(hash);
+    }
+    Ok(order)
+}
+
+/// Writes the objects named by `hashes` as a pack, each compressed at `level`
+pub fn write_pack(git_dir: &Path, hashes: &[Oid], level: u32, out: &mut dyn Write) -> io::Result<()> {
+    let mut out = HashingWriter { inner: out, hasher: Sha1::new() };
+    out.write_all(SIGNATURE)?;
+    out.write_all(&VERSION.to_be_bytes())?;
+    out.write_all(&(hashes.len() as u32).to_be_bytes())?;
+
+    for &hash in hashes {
+        let object = RawObject::read(git_dir, hash)?;
+        write_entry_header(&mut out, type_code(object.kind)?, object.data.len())?;
+        let mut encoder = compression::zlib_encoder(&mut out, level);
+        encoder.write_all(&object.data)?;
+        encoder.finish()?;
+    }
+
+    let checksum = out.hasher.finalize();
+    out.inner.write_all(&checksum)?;
+    trace::event(Stream::Pack, || format!("pack write: {} objects", hashes.len()));
+    Ok(())
+}
+
+/// Type in bits 4-6 of the first byte, the size in its low four bits and
+/// then seven bits per byte while the high bit is set
+fn write_entry_header(out: &mut dyn Write, type_code: u8, size: usize) -> io::Result<()> {
+    let mut byte = (type_code << 4) | (size & 0x0f) as u8;
+    let mut rest = size >> 4;
+    while rest > 0 {
+        out.write_all(&[byte | 0x80])?;
+        byte = (rest & 0x7f) as u8;
+        rest >>= 7;
+    }
+    out.write_all(&[byte])
+}
+
+/// Parses a pack into its objects, checking the trailing checksum
+pub fn read_pack(pack: &[u8]) -> io::Result<Vec<RawObject>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid pack: {}", message));
+    if pack.len() < 32 || &pack[..4] != SIGNATURE {
+        return Err(invalid("bad signature"));
+    }
+    let (body, checksum) = pack.split_at(pack.len() - 20);
+    if Sha1::digest(body).as_slice() != checksum {
+        return Err(invalid("checksum mismatch"));
+    }
+    let version = u32::from_be_bytes(body[4..8].try_into().unwrap());
+    if version != 2 && version != 3 {
+        return Err(invalid(&format!("unsupported version {}", version)));
+    }
+    let count = u32::from_be_bytes(body[8..12].try_into().unwrap()) as usize;
+    // Every object takes at least a byte, so a larger count cannot be honest
+    if count > body.len() - 12 {
+        return Err(invalid("more objects than the pack can hold"));
+    }
+
+    let mut objects = Vec::new();
+    let mut position = 12;
+    for _ in 0..count {
+        let mut byte = *body.get(position).ok_or_else(|| invalid("truncated"))?;
+        position += 1;
+        let type_code = (byte >> 4) & 0x07;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            if shift + 7 > usize::BITS {
+                return Err(invalid("object size too large"));
+            }
+            byte = *body.get(position).ok_or_else(|| invalid("truncated"))?;
+            position += 1;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        let kind = kind_name(type_code)?;
+
+        let mut decoder = ZlibDecoder::new(&body[position..]);
+        let mut data = Vec::new();
+        decoder.read_to_end(&mut data)?;
+        if data.len() != size {
+            return Err(invalid("object size does not match its header"));
+        }
+        position += decoder.total_in() as usize;
+        objects.push(RawObject { kind, data });
+    }
+    if position != body.len() {
+        return Err(invalid("trailing data after the last object"));
+    }
+    trace::event(Stream::Pack, || format!("pack read: {} objects", objects.len()));
+    Ok(objects)
+}
+
+/// Passes writes through while hashing them for the pack trailer
+struct HashingWriter<'a> {
+    inner: &'a mut dyn Write,
+    hasher: Sha1,
+}
+
+impl Write for HashingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_pack_roundtrip_and_negotiation() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        // Large enough for a multi-byte size header
+        fs::write(temp_dir.path().join("big.txt"), "line\n".repeat(1000))?;
+        add_file(&mut repo, Path::new("big.txt"))?;
+        let first = commit(&repo, "First")?;
+        fs::write(temp_dir.path().join("small.txt"), "small\n")?;
+        add_file(&mut repo, Path::new("small.txt"))?;
+        let second = commit(&repo, "Second")?;
+
+        let everything = objects_between(&repo.git_dir, std::slice::from_ref(&second), &[])?;
+        // Two commits, two trees, two blobs
+        assert_eq!(everything.len(), 6);
+        assert_eq!(everything[0], second);
+
+        // The client already has the first commit and everything it reaches
+        let missing = objects_between(&repo.git_dir, std::slice::from_ref(&second), &[first, "f".repeat(40).parse()?])?;
+        assert_eq!(missing.len(), 3);
+
+        let mut pack = Vec::new();
+        write_pack(&repo.git_dir, &everything, compression::DEFAULT_LEVEL, &mut pack)?;
+        let objects = read_pack(&pack)?;
+        let hashes: Vec<Oid> = objects.iter().map(RawObject::hash).collect();
+        assert_eq!(hashes, everything);
+
+        let last = pack.len() - 1;
+        pack[last] ^= 1;
+        assert!(read_pack(&pack).unwrap_err().to_string().contains("checksum"));
+        Ok(())
+    }
+
+    /// A well-formed pack around `body`, which follows the header
+    fn pack_with(count: u32, body: &[u8]) -> Vec<u8> {
+        let mut pack = SIGNATURE.to_vec();
+        pack.extend_from_slice(&VERSION.to_be_bytes());
+        pack.extend_from_slice(&count.to_be_bytes());
+        pack.extend_from_slice(body);
+        let checksum = Sha1::digest(&pack);
+        pack.extend_from_slice(&checksum);
+        pack
+    }
+
+    #[test]
+    fn test_read_pack_rejects_hostile_headers() {
+        let error = read_pack(&pack_with(u32::MAX, &[0x30])).unwrap_err();
+        assert!(error.to_string().contains("more objects"), "{}", error);
+
+        // A size header that never ends would shift past the top of usize
+        let error = read_pack(&pack_with(1, &[0xff; 16])).unwrap_err();
+        assert!(error.to_string().contains("too large"), "{}", error);
+    }
+}
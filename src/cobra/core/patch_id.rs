@@ -0,0 +1,198 @@
+// Patch ids: content hashes of the change a commit makes
+//
+// Two commits get the same patch id when they make the same change, no
+// matter where it was applied. Hunk offsets and whitespace are left out of
+// the hash, and every file is hashed on its own with the per-file digests
+// added together, so neither the line numbers nor the order files appear in
+// affect the result. This is what lets `cherry` recognise commits that were
+// cherry-picked or rebased onto a different base.
+
+use std::io;
+use sha1::{Digest, Sha1};
+use crate::cobra::core::diff::{diff_lines, hunks, split_lines, Edit, CONTEXT_LINES};
+use crate::cobra::core::object::{Object, TreeEntry};
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::core::tree::{commit_tree, diff_trees, TreeChange};
+use crate::cobra::messages;
+
+/// The patch id of the change `commit` makes relative to its parent, or
+/// None for merges and commits that change nothing
+pub fn commit_patch_id(repo: &Repository, commit: Oid) -> io::Result<Option<String>> {
+    let (tree, parents) = match Object::read_from_objects_dir(&repo.git_dir, commit)? {
+        Object::Commit { tree, parents, .. } => (tree, parents),
+        _ => return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            messages::not_a_commit(&commit.to_string()),
+        )),
+    };
+    if parents.len() > 1 {
+        return Ok(None);
+    }
+    let parent_tree = match parents.first() {
+        Some(&parent) => Some(commit_tree(&repo.git_dir, parent)?),
+        None => None,
+    };
+    tree_patch_id(repo, parent_tree, Some(tree))
+}
+
+/// The patch id of the change between two trees, or None if they are equal
+pub fn tree_patch_id(repo: &Repository, old_tree: Option<Oid>, new_tree: Option<Oid>) -> io::Result<Option<String>> {
+    let changes = diff_trees(&repo.git_dir, old_tree, new_tree)?;
+    if changes.is_empty() {
+        return Ok(None);
+    }
+
+    let mut sum = [0u8; 20];
+    for change in &changes {
+        add_digest(&mut sum, &file_digest(repo, change)?);
+    }
+    Ok(Some(hex::encode(sum)))
+}
+
+/// Hashes one file's part of the patch: its name, the mode change if any,
+/// and every line of every hunk with whitespace removed
+fn file_digest(repo: &Repository, change: &TreeChange) -> io::Result<[u8; 20]> {
+    let mut hasher = Sha1::new();
+    let path = change.path.to_string_lossy();
+    hasher.update(format!("diff--gita/{}b/{}", path, path).replace(char::is_whitespace, ""));
+
+    let old_mode = change.old.as_ref().map_or(0, |entry| entry.mode);
+    let new_mode = change.new.as_ref().map_or(0, |entry| entry.mode);
+    if old_mode != new_mode {
+        hasher.update(format!("mode{:o}{:o}", old_mode, new_mode));
+    }
+
+    let old = match &change.old {
+        Some(entry) => Object::read_blob(&repo.git_dir, entry.hash)?,
+        None => Vec::new(),
+    };
+    let new = match &change.new {
+        Some(entry) => Object::read_blob(&repo.git_dir, entry.hash)?,
+        None => Vec::new(),
+    };
+
+    if old.contains(&0) || new.contains(&0) {
+        // Binary content has no lines; the blob names stand for it
+        let name = |entry: &Option<TreeEntry>| entry.as_ref().map_or(String::new(), |entry| entry.hash.to_string());
+        hasher.update(format!("binary{}{}", name(&change.old), name(&change.new)));
+        return Ok(hasher.finalize().into());
+    }
+
+    let old_lines = split_lines(&old);
+    let new_lines = split_lines(&new);
+    for hunk in hunks(&diff_lines(&old_lines, &new_lines), CONTEXT_LINES) {
+        // Only the hunk boundary is hashed, not its position
+        hasher.update(b"@@");
+        for edit in &hunk.edits {
+            let (prefix, line) = match *edit {
+                Edit::Equal { old: i, .. } => (b' ', old_lines[i]),
+                Edit::Delete { old: i } => (b'-', old_lines[i]),
+                Edit::Insert { new: j } => (b'+', new_lines[j]),
+            };
+            hasher.update([prefix]);
+            let stripped: Vec<u8> = line.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+            hasher.update(stripped);
+        }
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// Adds `digest` to `sum` as 160-bit little-endian numbers, so the result
+/// does not depend on the order the files are visited in
+fn add_digest(sum: &mut [u8; 20], digest: &[u8; 20]) {
+    let mut carry = 0u16;
+    for (total, byte) in sum.iter_mut().zip(digest) {
+        let value = *total as u16 + *byte as u16 + carry;
+        *total = value as u8;
+        carry = value >> 8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::cobra::core::{signature::Signature, tree::build_tree};
+    use crate::cobra::test_support::temp_repo;
+
+    /// Commits the given files (and nothing else) on top of `parent`
+    fn commit_files(repo: &Repository, parent: Option<Oid>, files: &[(&str, String)]) -> io::Result<Oid> {
+        let mut entries = Vec::new();
+        for (path, content) in files {
+            let blob = repo.write_object(&Object::new_blob(content.as_bytes().to_vec()))?;
+            entries.push((PathBuf::from(path), 0o100644, blob));
+        }
+        let tree = repo.write_object(&build_tree(repo, &entries)?)?;
+        let signature = Signature::new("Tester".to_string(), "tester@example.com".to_string());
+        let commit = Object::new_commit(
+            tree,
+            parent.into_iter().collect(),
+            signature.clone(),
+            signature,
+            "change".to_string(),
+        );
+        repo.write_object(&commit)
+    }
+
+    /// Twenty numbered lines with the given replacements
+    fn lines(changes: &[(usize, &str)]) -> String {
+        (1..=20)
+            .map(|n| changes.iter().find(|(line, _)| *line == n).map_or(format!("line {}\n", n), |(_, text)| format!("{}\n", text)))
+            .collect()
+    }
+
+    #[test]
+    fn test_reordered_patches_match() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let base = commit_files(&repo, None, &[("file.txt", lines(&[]))])?;
+
+        // One side changes line 2 and then line 18, the other in the opposite order
+        let top = commit_files(&repo, Some(base), &[("file.txt", lines(&[(2, "top")]))])?;
+        let both = commit_files(&repo, Some(top), &[("file.txt", lines(&[(2, "top"), (18, "bottom")]))])?;
+        let bottom = commit_files(&repo, Some(base), &[("file.txt", lines(&[(18, "bottom")]))])?;
+        let both_again = commit_files(&repo, Some(bottom), &[("file.txt", lines(&[(2, "top"), (18, "bottom")]))])?;
+
+        let id = |commit: Oid| commit_patch_id(&repo, commit).map(|id| id.unwrap());
+        assert_eq!(id(top)?, id(both_again)?);
+        assert_eq!(id(both)?, id(bottom)?);
+        assert_ne!(id(top)?, id(bottom)?);
+
+        // Whitespace-only differences do not matter, other content does
+        let spaced = commit_files(&repo, Some(base), &[("file.txt", lines(&[(2, "  top")]))])?;
+        let other = commit_files(&repo, Some(base), &[("file.txt", lines(&[(2, "elsewhere")]))])?;
+        assert_eq!(id(spaced)?, id(top)?);
+        assert_ne!(id(other)?, id(top)?);
+
+        // A commit that changes nothing has no patch id
+        let empty = commit_files(&repo, Some(base), &[("file.txt", lines(&[]))])?;
+        assert_eq!(commit_patch_id(&repo, empty)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_patch_id_ignores_offsets_and_file_order() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+
+        // The same edit to b.txt, once on a base where a.txt has more lines above the change
+        let short = commit_files(&repo, None, &[("a.txt", "a\n".to_string()), ("b.txt", lines(&[]))])?;
+        let long_b = format!("extra 1\nextra 2\nextra 3\nextra 4\nextra 5\n{}", lines(&[]));
+        let long = commit_files(&repo, None, &[("a.txt", "a\n".to_string()), ("b.txt", long_b)])?;
+
+        let edited_short = commit_files(&repo, Some(short), &[("a.txt", "a\n".to_string()), ("b.txt", lines(&[(10, "ten")]))])?;
+        let edited_long = format!("extra 1\nextra 2\nextra 3\nextra 4\nextra 5\n{}", lines(&[(10, "ten")]));
+        let edited_long = commit_files(&repo, Some(long), &[("a.txt", "a\n".to_string()), ("b.txt", edited_long)])?;
+        assert_eq!(commit_patch_id(&repo, edited_short)?, commit_patch_id(&repo, edited_long)?);
+
+        // Per-file digests are summed, so the combination is commutative
+        let mut forward = [0u8; 20];
+        let mut backward = [0u8; 20];
+        let (x, y) = ([0xff; 20], [0x01; 20]);
+        add_digest(&mut forward, &x);
+        add_digest(&mut forward, &y);
+        add_digest(&mut backward, &y);
+        add_digest(&mut backward, &x);
+        assert_eq!(forward, backward);
+        Ok(())
+    }
+}
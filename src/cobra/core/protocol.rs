@@ -0,0 +1,242 @@
+// The git wire protocol (version 0), as spoken by `git daemon`
+//
+// Every message is a pkt-line: four hex digits giving the length of the line
+// including those digits, then the payload. "0000" is a flush that ends a
+// section. A fetch starts with the server advertising its refs, the first
+// one carrying the capability list. The client names the objects it wants,
+// then the commits it has, and ends with "done"; the server acknowledges the
+// first commit they share and sends a pack of everything the client lacks.
+//
+// Only the plainest form is supported: no multi_ack, no side-band and no
+// shallow clones, so none of them are advertised and clients do not ask.
+
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use crate::cobra::core::compression::CompressionSettings;
+use crate::cobra::core::pack::{objects_between, write_pack};
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::ref_store::RefStore;
+use crate::cobra::utils::trace::{self, Stream};
+
+/// Largest pkt-line, length prefix included
+const MAX_PKT_LEN: usize = 65520;
+
+/// Value of the `agent` capability
+pub fn agent() -> String {
+    format!("cobra/{}", env!("CARGO_PKG_VERSION"))
+}
+
+pub fn write_pkt(out: &mut dyn Write, payload: &[u8]) -> io::Result<()> {
+    if payload.len() + 4 > MAX_PKT_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "pkt-line too long"));
+    }
+    write!(out, "{:04x}", payload.len() + 4)?;
+    out.write_all(payload)
+}
+
+pub fn write_flush(out: &mut dyn Write) -> io::Result<()> {
+    out.write_all(b"0000")
+}
+
+/// Reads one pkt-line, returning None for a flush
+pub fn read_pkt(input: &mut dyn BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut prefix = [0u8; 4];
+    input.read_exact(&mut prefix)?;
+    let len = std::str::from_utf8(&prefix).ok()
+        .and_then(|prefix| usize::from_str_radix(prefix, 16).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid pkt-line length"))?;
+    match len {
+        0 => Ok(None),
+        1..=3 => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unsupported pkt-line {:04x}", len))),
+        _ if len > MAX_PKT_LEN => Err(io::Error::new(io::ErrorKind::InvalidData, "pkt-line too long")),
+        _ => {
+            let mut payload = vec![0u8; len - 4];
+            input.read_exact(&mut payload)?;
+            Ok(Some(payload))
+        }
+    }
+}
+
+/// Reads a pkt-line as text without its trailing newline. An "ERR" line
+/// from the other side becomes an error.
+pub fn read_pkt_line(input: &mut dyn BufRead) -> io::Result<Option<String>> {
+    let Some(payload) = read_pkt(input)? else {
+        return Ok(None);
+    };
+    let line = String::from_utf8(payload)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "pkt-line is not UTF-8"))?;
+    let line = line.strip_suffix('\n').unwrap_or(&line).to_string();
+    match line.strip_prefix("ERR ") {
+        Some(message) => Err(io::Error::other(format!("Remote error: {}", message))),
+        None => Ok(Some(line)),
+    }
+}
+
+/// Sends an error to the client in place of the next expected line
+pub fn write_error(out: &mut dyn Write, message: &str) -> io::Result<()> {
+    write_pkt(out, format!("ERR {}\n", message).as_bytes())?;
+    out.flush()
+}
+
+/// What a remote offers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemoteRefs {
+    /// Full ref names ("refs/heads/main") with their hashes, HEAD included
+    pub refs: Vec<(String, Oid)>,
+    /// The branch HEAD points at, when the remote says
+    pub head: Option<String>,
+}
+
+/// The refs a repository offers, HEAD first. Unborn branches, which have
+/// no hash yet, are left out, so an empty repository offers nothing.
+pub fn advertised_refs(git_dir: &Path) -> io::Result<RemoteRefs> {
+    let store = RefStore::new(git_dir.to_path_buf());
+    let mut refs = Vec::new();
+    let mut head_target = None;
+    if let Some(head) = store.read_head()? {
+        match head.strip_prefix("ref: ") {
+            Some(target) => {
+                let target = target.trim().to_string();
+                if let Some(hash) = store.read_ref(&target)? {
+                    refs.push(("HEAD".to_string(), hash));
+                    head_target = Some(target);
+                }
+            }
+            None => refs.extend(Oid::from_hex(head.trim()).ok().map(|hash| ("HEAD".to_string(), hash))),
+        }
+    }
+    for namespace in ["refs/heads", "refs/tags"] {
+        for (name, hash) in store.list_refs(namespace)? {
+            if let Some(hash) = hash {
+                refs.push((format!("{}/{}", namespace, name), hash));
+            }
+        }
+    }
+    Ok(RemoteRefs { refs, head: head_target })
+}
+
+/// Serves one fetch from the repository at `git_dir` after the client's
+/// request line has been read
+pub fn upload_pack(git_dir: &Path, input: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<()> {
+    let RemoteRefs { refs, head: head_target } = advertised_refs(git_dir)?;
+    let mut capabilities = format!("agent={}", agent());
+    if let Some(target) = &head_target {
+        capabilities = format!("symref=HEAD:{} {}", target, capabilities);
+    }
+
+    if refs.is_empty() {
+        // An empty repository still has to send its capabilities
+        write_pkt(out, format!("{} capabilities^{{}}\0{}\n", "0".repeat(40), capabilities).as_bytes())?;
+    }
+    for (i, (name, hash)) in refs.iter().enumerate() {
+        let line = match i {
+            0 => format!("{} {}\0{}\n", hash, name, capabilities),
+            _ => format!("{} {}\n", hash, name),
+        };
+        write_pkt(out, line.as_bytes())?;
+    }
+    write_flush(out)?;
+    out.flush()?;
+
+    let offered: HashSet<Oid> = refs.iter().map(|(_, hash)| *hash).collect();
+    let mut wants = Vec::new();
+    loop {
+        let line = match read_pkt_line(input) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            // Clients that only list refs may hang up without a flush
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && wants.is_empty() => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let want = line.strip_prefix("want ")
+            .and_then(|rest| rest.split(' ').next())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Expected a want line, got '{}'", line)))?;
+        let Some(oid) = Oid::from_hex(want).ok().filter(|oid| offered.contains(oid)) else {
+            write_error(out, &format!("upload-pack: not our ref {}", want))?;
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("Client wants unadvertised object {}", want)));
+        };
+        wants.push(oid);
+    }
+    if wants.is_empty() {
+        return Ok(());
+    }
+
+    let mut common = Vec::new();
+    let mut acknowledged = false;
+    loop {
+        match read_pkt_line(input)? {
+            Some(line) if line == "done" => break,
+            Some(line) => {
+                let have = line.strip_prefix("have ")
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Expected a have line, got '{}'", line)))?;
+                if let Some(oid) = Oid::from_hex(have).ok().filter(|oid| oid.loose_path(git_dir).is_file()) {
+                    if !acknowledged {
+                        write_pkt(out, format!("ACK {}\n", have).as_bytes())?;
+                        acknowledged = true;
+                    }
+                    common.push(oid);
+                }
+            }
+            None => {
+                if !acknowledged {
+                    write_pkt(out, b"NAK\n")?;
+                }
+                out.flush()?;
+            }
+        }
+    }
+    if !acknowledged {
+        write_pkt(out, b"NAK\n")?;
+    }
+
+    let objects = objects_between(git_dir, &wants, &common)?;
+    trace::event(Stream::Pack, || format!(
+        "upload-pack: {} wants, {} common, sending {} objects", wants.len(), common.len(), objects.len(),
+    ));
+    // Out here stdout is the protocol stream, so config warnings are dropped
+    let (compression, _warnings) = CompressionSettings::load(git_dir)?;
+    write_pack(git_dir, &objects, compression.level, out)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_pkt_lines() -> io::Result<()> {
+        let mut buffer = Vec::new();
+        write_pkt(&mut buffer, b"want abc\n")?;
+        write_flush(&mut buffer)?;
+        write_pkt(&mut buffer, b"ERR access denied\n")?;
+        assert_eq!(&buffer[..13], b"000dwant abc\n");
+
+        let mut input = Cursor::new(buffer);
+        assert_eq!(read_pkt_line(&mut input)?, Some("want abc".to_string()));
+        assert_eq!(read_pkt_line(&mut input)?, None);
+        assert!(read_pkt_line(&mut input).unwrap_err().to_string().contains("access denied"));
+        assert_eq!(read_pkt(&mut input).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut bad = Cursor::new(b"00zz".to_vec());
+        assert_eq!(read_pkt(&mut bad).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_repository_advertises_only_capabilities() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        assert_eq!(advertised_refs(&repo.git_dir)?, RemoteRefs::default());
+
+        let mut out = Vec::new();
+        let mut input = Cursor::new(b"0000".to_vec());
+        upload_pack(&repo.git_dir, &mut input, &mut out)?;
+        let mut advertisement = Cursor::new(out);
+        let first = read_pkt_line(&mut advertisement)?.unwrap_or_default();
+        assert!(first.starts_with(&format!("{} capabilities^{{}}\0", "0".repeat(40))), "{:?}", first);
+        assert_eq!(read_pkt_line(&mut advertisement)?, None);
+        Ok(())
+    }
+}
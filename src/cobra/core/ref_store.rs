@@ -1,81 +1,511 @@
 // Reference management (branches, tags, HEAD)
-use std::fs;
+//
+// On disk a ref holds one line: a 40-character lowercase hash, or
+// "ref: <name>" for a symbolic ref such as HEAD. A branch created before
+// its first commit holds an empty line. Reads accept whatever other tools
+// leave behind (no newline, CRLF, padding, uppercase hex) and normalize it;
+// writes only ever produce the canonical form and reject anything else.
+// Callers deal in Oids: `read_ref` yields the object a ref names and the
+// update functions take one, while `read_ref_value` and `read_head` give
+// the stored text for the symbolic and unborn cases.
+use std::fmt;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::cobra::core::events::{EventBus, RepositoryEvent};
+use crate::cobra::core::merge;
+use crate::cobra::core::object::{is_object_hash, Object};
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::reflog::{self, ReflogEntry, ZERO_HASH};
+use crate::cobra::core::revision;
+use crate::cobra::core::transaction::ObjectTransaction;
+use crate::cobra::utils::fs::{walk_files, RealFs, VfsHandle};
+use crate::cobra::utils::trace::{self, Stream};
+use crate::cobra::messages;
+
+/// The ref holding the newest stash; older ones live in its reflog
+pub const STASH_REF: &str = "refs/stash";
+
+/// How long to wait for another process to release a ref lock
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Shortest abbreviated object name accepted
+pub const MIN_PREFIX_LEN: usize = 4;
+
+/// A reason a value cannot be stored in a ref
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefValueError {
+    /// Neither a hash nor a symbolic ref
+    Malformed { name: String, value: String },
+    /// A symbolic ref pointing at something that is not a ref name
+    InvalidTarget { name: String, target: String },
+    /// A name that breaks the rules of `check_ref_format`
+    InvalidName { name: String },
+}
+
+impl fmt::Display for RefValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefValueError::Malformed { name, value } => {
+                write!(f, "cannot store '{}' in ref '{}': not an object hash", value.escape_debug(), name)
+            }
+            RefValueError::InvalidTarget { name, target } => {
+                write!(f, "ref '{}' cannot point at '{}': not a ref name", name, target.escape_debug())
+            }
+            RefValueError::InvalidName { name } => {
+                write!(f, "'{}' is not a valid ref name", name.escape_debug())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RefValueError {}
+
+impl From<RefValueError> for io::Error {
+    fn from(error: RefValueError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// Reads ref file content leniently: surrounding whitespace and line
+/// endings are dropped and hashes lowercased
+fn normalize(content: &[u8]) -> String {
+    let value = String::from_utf8_lossy(content);
+    let value = value.trim();
+    if let Some(target) = value.strip_prefix("ref:") {
+        return format!("ref: {}", target.trim());
+    }
+    if value.len() == 40 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        return value.to_ascii_lowercase();
+    }
+    value.to_string()
+}
+
+/// Checks a full ref name ("refs/heads/topic") the way git check-ref-format
+/// does, so that a name can never reach outside the refs it belongs to: no
+/// empty components (which rules out a leading, trailing or doubled `/`), no
+/// component starting with `.` or ending in `.lock`, no `..` or `@{`, no
+/// trailing `.`, and no whitespace, control characters or any of `~^:?*[\`
+pub fn check_ref_format(name: &str) -> Result<(), RefValueError> {
+    let valid = name != "@"
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.ends_with('.')
+        && !name.chars().any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c))
+        && name.split('/').all(|component| {
+            !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(RefValueError::InvalidName { name: name.to_string() })
+    }
+}
+
+/// Whether moves of `ref_name` are logged, as git's core.logAllRefUpdates
+/// does by default: HEAD, branches and remote-tracking branches
+fn logs_updates(ref_name: &str) -> bool {
+    ref_name == "HEAD" || ref_name.starts_with("refs/heads/") || ref_name.starts_with("refs/remotes/")
+}
+
+/// The exact bytes `value` is stored as in ref `name`
+fn canonical(name: &str, value: &str) -> Result<String, RefValueError> {
+    let value = normalize(value.as_bytes());
+    if let Some(target) = value.strip_prefix("ref: ") {
+        if !target.starts_with("refs/") || check_ref_format(target).is_err() {
+            return Err(RefValueError::InvalidTarget { name: name.to_string(), target: target.to_string() });
+        }
+    } else if !value.is_empty() && !is_object_hash(&value) {
+        return Err(RefValueError::Malformed { name: name.to_string(), value });
+    }
+    Ok(format!("{}\n", value))
+}
+
+/// Exclusive right to update one ref, held through a `<ref>.lock` file next
+/// to it as git does. The new value is written to the lock file and renamed
+/// over the ref, so readers never see a partial write. Dropping the lock
+/// without committing leaves the ref untouched.
+pub struct RefLock {
+    vfs: VfsHandle,
+    name: String,
+    path: PathBuf,
+    lock_path: PathBuf,
+    done: bool,
+}
+
+impl RefLock {
+    /// Takes the lock on `ref_name`, retrying while another process holds it
+    pub fn acquire(git_dir: &Path, ref_name: &str) -> io::Result<RefLock> {
+        RefLock::acquire_on(RealFs::handle(git_dir), git_dir, ref_name)
+    }
+
+    /// Like `acquire`, for a repository reached through `vfs`. Names that
+    /// could reach outside the refs, such as `../config`, are refused.
+    pub fn acquire_on(vfs: VfsHandle, git_dir: &Path, ref_name: &str) -> io::Result<RefLock> {
+        check_ref_format(ref_name)?;
+        let path = git_dir.join(ref_name);
+        if let Some(parent) = path.parent() {
+            vfs.create_dir_all(parent)?;
+        }
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let started = Instant::now();
+        loop {
+            match vfs.create_new(&lock_path) {
+                Ok(()) => return Ok(RefLock { vfs, name: ref_name.to_string(), path, lock_path, done: false }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && started.elapsed() < LOCK_TIMEOUT => {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WouldBlock,
+                        format!(
+                            "Unable to create '{}': File exists. Another cobra process seems to be running; \
+                             if not, remove the lock file and try again",
+                            lock_path.display(),
+                        ),
+                    ));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Points the ref at `value` and releases the lock
+    pub fn commit(self, value: &str) -> io::Result<()> {
+        self.stage(value)?;
+        self.install()
+    }
+
+    /// Replaces the locked file with `content` as is and releases the lock,
+    /// for files other than refs such as the index
+    pub fn commit_raw(self, content: &[u8]) -> io::Result<()> {
+        self.vfs.write_atomic(&self.lock_path, content)?;
+        self.install()
+    }
+
+    /// Writes `value` into the lock file without moving the ref yet. Running
+    /// out of space or permissions, or a malformed value, shows up here,
+    /// before anything changed.
+    fn stage(&self, value: &str) -> io::Result<()> {
+        self.vfs.write_atomic(&self.lock_path, canonical(&self.name, value)?.as_bytes())
+    }
+
+    /// Renames the staged lock file over the ref
+    fn install(mut self) -> io::Result<()> {
+        self.vfs.rename(&self.lock_path, &self.path)?;
+        self.done = true;
+        Ok(())
+    }
+
+    /// Deletes the ref and releases the lock
+    pub fn remove(mut self) -> io::Result<()> {
+        match self.vfs.remove(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        self.vfs.remove(&self.lock_path)?;
+        self.done = true;
+        Ok(())
+    }
+}
+
+impl Drop for RefLock {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.vfs.remove(&self.lock_path);
+        }
+    }
+}
+
+/// A reflog entry waiting for its ref to move
+struct PendingLog {
+    /// The logs it goes to, HEAD's first when HEAD points at the ref
+    names: Vec<String>,
+    old: Option<Oid>,
+    new: Oid,
+    reason: String,
+}
+
+/// A compare-and-swap update of one ref
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefUpdate {
+    pub name: String,
+    /// Object the ref must still name, None if it must not point anywhere yet
+    pub expected: Option<Oid>,
+    pub new: Oid,
+    /// What moved the ref, for its reflog ("commit: Fix typo")
+    pub reason: String,
+}
 
 pub struct RefStore {
     git_dir: PathBuf,
+    events: EventBus,
+    vfs: VfsHandle,
 }
 
 impl RefStore {
     pub fn new(git_dir: PathBuf) -> Self {
-        RefStore { git_dir }
+        RefStore::with_events(git_dir, EventBus::new())
+    }
+
+    /// Creates a RefStore that reports ref updates to the given event bus
+    pub fn with_events(git_dir: PathBuf, events: EventBus) -> Self {
+        let vfs = RealFs::handle(&git_dir);
+        RefStore::with_vfs(git_dir, events, vfs)
+    }
+
+    /// Creates a RefStore whose refs are read and written through `vfs`
+    pub fn with_vfs(git_dir: PathBuf, events: EventBus, vfs: VfsHandle) -> Self {
+        RefStore { git_dir, events, vfs }
+    }
+
+    fn lock(&self, ref_name: &str) -> io::Result<RefLock> {
+        RefLock::acquire_on(self.vfs.clone(), &self.git_dir, ref_name)
+    }
+
+    fn transaction(&self) -> ObjectTransaction {
+        ObjectTransaction::new(self.git_dir.clone(), self.events.clone()).with_vfs(self.vfs.clone())
     }
 
     pub fn create_initial_refs(&self) -> io::Result<()> {
         // Create refs directory structure
         let refs_dir = self.git_dir.join("refs");
         let heads_dir = refs_dir.join("heads");
-        fs::create_dir_all(&heads_dir)?;
+        self.vfs.create_dir_all(&heads_dir)?;
 
-        // Create empty main branch reference
+        // Create main without a commit yet, and HEAD pointing at it
         let main_ref = heads_dir.join("main");
-        fs::write(&main_ref, "")?;
-
-        // Create HEAD pointing to main branch
+        self.vfs.write_atomic(&main_ref, canonical("refs/heads/main", "")?.as_bytes())?;
         let head_path = self.git_dir.join("HEAD");
-        fs::write(head_path, "ref: refs/heads/main\n")?;
+        self.vfs.write_atomic(&head_path, canonical("HEAD", "ref: refs/heads/main")?.as_bytes())?;
 
         Ok(())
     }
 
-    pub fn update_ref(&self, ref_name: &str, target: &str) -> io::Result<()> {
-        let ref_path = self.git_dir.join(ref_name);
-        
-        // Create parent directories if they don't exist
-        if let Some(parent) = ref_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-        
-        fs::write(ref_path, format!("{}\n", target))
+    /// Moves `ref_name` to `target`, logging the move with `reason`
+    pub fn update_ref(&self, ref_name: &str, target: Oid, reason: &str) -> io::Result<()> {
+        self.write_ref(ref_name, &target.to_string(), Some(reason))
     }
 
-    pub fn read_ref(&self, ref_name: &str) -> io::Result<Option<String>> {
-        let ref_path = self.git_dir.join(ref_name);
-        
-        if !ref_path.exists() {
+    /// Moves `ref_name` to `target` under its lock, then adds `reason` to
+    /// the reflog unless it is None
+    fn write_ref(&self, ref_name: &str, target: &str, reason: Option<&str>) -> io::Result<()> {
+        let lock = self.lock(ref_name)?;
+        let old = self.read_ref_value(ref_name)?.filter(|value| !value.is_empty());
+        let log = match reason {
+            Some(reason) => self.pending_log(ref_name, old.as_deref(), target, reason)?,
+            None => None,
+        };
+        lock.commit(target)?;
+        self.updated(ref_name, old, target);
+        self.write_log(log)
+    }
+
+    /// The reflog entry for moving `ref_name` from `old` to `new`, worked out
+    /// before the ref moves and written once it has. It goes to HEAD's log
+    /// too when HEAD points at the ref. Symbolic values are logged as the
+    /// commits they name; a move that leaves no commit is not logged.
+    fn pending_log(&self, ref_name: &str, old: Option<&str>, new: &str, reason: &str) -> io::Result<Option<PendingLog>> {
+        if !logs_updates(ref_name) {
             return Ok(None);
         }
-        
-        let content = fs::read_to_string(ref_path)?;
-        Ok(Some(content.trim().to_string()))
+        let Some(new) = self.peel(new)? else {
+            return Ok(None);
+        };
+        let old = match old {
+            Some(old) => self.peel(old)?,
+            None => None,
+        };
+        let mut names = vec![ref_name.to_string()];
+        if ref_name != "HEAD" && self.head_branch()?.as_deref() == Some(ref_name) {
+            names.insert(0, "HEAD".to_string());
+        }
+        Ok(Some(PendingLog { names, old, new, reason: reason.to_string() }))
+    }
+
+    fn write_log(&self, log: Option<PendingLog>) -> io::Result<()> {
+        let Some(log) = log else {
+            return Ok(());
+        };
+        let old = log.old.map(|old| old.to_string());
+        for name in &log.names {
+            self.append_reflog(name, old.as_deref(), &log.new.to_string(), &log.reason)?;
+        }
+        Ok(())
+    }
+
+    /// The commit a ref value names, following "ref: <name>" one level;
+    /// None for an unborn branch or anything that is not a hash
+    fn peel(&self, value: &str) -> io::Result<Option<Oid>> {
+        let value = normalize(value.as_bytes());
+        match value.strip_prefix("ref: ") {
+            Some(target) => self.read_ref(target),
+            None => Ok(Some(value).filter(|value| is_object_hash(value)).and_then(|value| Oid::from_hex(&value).ok())),
+        }
+    }
+
+    /// Applies several updates all or nothing: every ref is locked, checked
+    /// against its expected value and has its new value written to the lock
+    /// file before the first one moves. Locks are taken in name order so
+    /// concurrent transactions cannot deadlock.
+    pub fn update_refs(&self, updates: &[RefUpdate]) -> io::Result<()> {
+        let mut updates: Vec<&RefUpdate> = updates.iter().collect();
+        updates.sort_by(|a, b| a.name.cmp(&b.name));
+        if let Some(pair) = updates.windows(2).find(|pair| pair[0].name == pair[1].name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("ref '{}' is updated twice in one transaction", pair[0].name),
+            ));
+        }
+
+        let mut locked = Vec::with_capacity(updates.len());
+        for update in updates {
+            let lock = self.lock(&update.name)?;
+            let current = self.read_ref_value(&update.name)?.filter(|value| !value.is_empty());
+            let expected = update.expected.map(|expected| expected.to_string());
+            if current != expected {
+                return Err(io::Error::other(format!(
+                    "cannot lock ref '{}': is at {} but expected {}",
+                    update.name,
+                    current.as_deref().unwrap_or(ZERO_HASH),
+                    expected.as_deref().unwrap_or(ZERO_HASH),
+                )));
+            }
+            locked.push((lock, update, current, update.new.to_string()));
+        }
+
+        for (lock, _, _, new) in &locked {
+            lock.stage(new)?;
+        }
+        let mut logs = Vec::with_capacity(locked.len());
+        for (_, update, old, new) in &locked {
+            logs.push(self.pending_log(&update.name, old.as_deref(), new, &update.reason)?);
+        }
+        for (lock, update, old, new) in locked {
+            lock.install()?;
+            self.updated(&update.name, old, &new);
+        }
+        logs.into_iter().try_for_each(|log| self.write_log(log))
+    }
+
+    fn updated(&self, ref_name: &str, old: Option<String>, new: &str) {
+        trace::event(Stream::Main, || format!(
+            "ref update {}: {} -> {}",
+            ref_name,
+            old.as_deref().unwrap_or(ZERO_HASH),
+            new,
+        ));
+        self.events.emit(RepositoryEvent::RefUpdated {
+            name: ref_name.to_string(),
+            old,
+            new: new.to_string(),
+        });
+    }
+
+    /// The object `ref_name` names. None when there is no such ref, when it
+    /// is a branch without commits, and when it is symbolic or holds
+    /// anything else that is not an object id.
+    pub fn read_ref(&self, ref_name: &str) -> io::Result<Option<Oid>> {
+        Ok(self.read_ref_value(ref_name)?.and_then(|value| Oid::from_hex(&value).ok()))
+    }
+
+    /// Reads a ref as a hash, "ref: <name>" or an empty string for a
+    /// branch without commits, whatever line ending or padding it has on disk
+    pub fn read_ref_value(&self, ref_name: &str) -> io::Result<Option<String>> {
+        Ok(self.read_ref_raw(ref_name)?.map(|content| normalize(&content)))
+    }
+
+    /// The bytes of a ref file exactly as stored, for plumbing that must not
+    /// reinterpret them. A directory in the ref's place, left by refs below
+    /// that name, means there is no such ref.
+    pub fn read_ref_raw(&self, ref_name: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.vfs.read(&self.git_dir.join(ref_name)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::IsADirectory | io::ErrorKind::NotADirectory) => Ok(None),
+            Err(e) => Err(e),
+        }
     }
 
     pub fn read_head(&self) -> io::Result<Option<String>> {
-        self.read_ref("HEAD")
+        self.read_ref_value("HEAD")
+    }
+
+    /// The ref HEAD points at ("refs/heads/main"), or None when HEAD is
+    /// detached or missing
+    pub fn head_branch(&self) -> io::Result<Option<String>> {
+        Ok(self.read_head()?.and_then(|head| head.strip_prefix("ref: ").map(str::to_string)))
     }
 
-    pub fn update_head(&self, target: &str) -> io::Result<()> {
-        self.update_ref("HEAD", target)
+    /// The short name of the checked out branch ("main"), or None when
+    /// HEAD is detached or missing
+    pub fn current_branch(&self) -> io::Result<Option<String>> {
+        Ok(self.head_branch()?.map(|target| match target.strip_prefix("refs/heads/") {
+            Some(name) => name.to_string(),
+            None => target,
+        }))
+    }
+
+    /// Adds an entry to the reflog of `ref_name`
+    pub fn append_reflog(&self, ref_name: &str, old: Option<&str>, new: &str, message: &str) -> io::Result<()> {
+        reflog::append_in(&*self.vfs, &self.git_dir, ref_name, old, new, message)
+    }
+
+    /// The branch, or the commit when HEAD was detached, that the latest
+    /// checkout moved away from, as its "checkout: moving from <from> to
+    /// <to>" entry in HEAD's reflog names it
+    pub fn previous_checkout(&self) -> io::Result<Option<String>> {
+        let entries = reflog::read_in(&*self.vfs, &self.git_dir, "HEAD")?;
+        Ok(entries.iter().rev()
+            .filter_map(|entry| entry.message.strip_prefix("checkout: moving from "))
+            .find_map(|moved| moved.split_once(" to "))
+            .map(|(from, _)| from.to_string()))
+    }
+
+    /// Detaches HEAD at the commit `target`, logging the move with `reason`
+    pub fn update_head(&self, target: Oid, reason: &str) -> io::Result<()> {
+        self.update_ref("HEAD", target, reason)
+    }
+
+    /// Points HEAD at the branch `branch_ref` ("refs/heads/main"), logging
+    /// the move with `reason`
+    pub fn attach_head(&self, branch_ref: &str, reason: &str) -> io::Result<()> {
+        self.write_ref("HEAD", &format!("ref: {}", branch_ref), Some(reason))
     }
 
     pub fn create_branch(&self, branch_name: &str) -> io::Result<()> {
+        self.create_branch_at(branch_name, None)
+    }
+
+    /// Creates a branch at `start_point`, any revision the revision parser
+    /// resolves (a branch, tag or abbreviated hash, with suffixes such as
+    /// `~2`), or at the current HEAD commit when no start point is given
+    pub fn create_branch_at(&self, branch_name: &str, start_point: Option<&str>) -> io::Result<()> {
         // Check if branch already exists
         let branch_ref = format!("refs/heads/{}", branch_name);
-        if let Some(_) = self.read_ref(&branch_ref)? {
+        check_ref_format(&branch_ref)?;
+        if self.read_ref_value(&branch_ref)?.is_some() {
             return Err(io::Error::new(
                 io::ErrorKind::AlreadyExists,
-                format!("A branch named '{}' already exists", branch_name),
+                messages::branch_exists(branch_name),
             ));
         }
 
+        if let Some(start_point) = start_point {
+            let commit = revision::resolve_revision(self, start_point)?;
+            return self.update_ref(&branch_ref, commit, &format!("branch: Created from {}", start_point));
+        }
+
         // Get current HEAD commit
         let head_content = self.read_head()?;
         let current_commit = match head_content {
             Some(content) if content.starts_with("ref: ") => {
                 // HEAD points to a branch, get the commit from that branch
                 let branch_name = content.strip_prefix("ref: ").unwrap().trim();
-                self.read_ref(branch_name)?
+                self.read_ref_value(branch_name)?
                     .ok_or_else(|| io::Error::new(
                         io::ErrorKind::NotFound,
                         format!("Branch '{}' not found", branch_name),
@@ -92,65 +522,317 @@ impl RefStore {
         };
 
         // Create the new branch pointing to the current commit
-        self.update_ref(&branch_ref, &current_commit)
+        self.write_ref(&branch_ref, &current_commit, Some("branch: Created from HEAD"))
+    }
+
+    /// Lists the branches, including nested ones such as `feature/foo`,
+    /// sorted by name. A branch without commits has no commit.
+    pub fn list_branches(&self) -> io::Result<Vec<(String, Option<Oid>)>> {
+        self.list_refs("refs/heads")
+    }
+
+    /// Lists the tags under refs/tags, including nested ones such as
+    /// `release/v1`, sorted by name
+    pub fn list_tags(&self) -> io::Result<Vec<(String, Option<Oid>)>> {
+        self.list_refs("refs/tags")
+    }
+
+    /// Creates a lightweight tag at the current HEAD commit
+    pub fn create_tag(&self, tag_name: &str) -> io::Result<()> {
+        let tag_ref = format!("refs/tags/{}", tag_name);
+        check_ref_format(&tag_ref)?;
+        let lock = self.lock(&tag_ref)?;
+        if self.read_ref_value(&tag_ref)?.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Tag '{}' already exists", tag_name),
+            ));
+        }
+        let commit = self.resolve_commit("HEAD")?.to_string();
+        lock.commit(&commit)?;
+        self.updated(&tag_ref, None, &commit);
+        Ok(())
+    }
+
+    /// Deletes a tag and returns the object it pointed to, None when it
+    /// held something other than an object id
+    pub fn delete_tag(&self, tag_name: &str) -> io::Result<Option<Oid>> {
+        let tag_ref = format!("refs/tags/{}", tag_name);
+        check_ref_format(&tag_ref)?;
+        let old = self.delete_ref(&tag_ref)?.ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Tag '{}' not found", tag_name),
+        ))?;
+        Ok(Oid::from_hex(&old).ok())
+    }
+
+    /// Deletes `ref_name` under its lock and returns the value it held, or
+    /// None if there was no such ref. Observers see the deletion as a move
+    /// to ZERO_HASH.
+    fn delete_ref(&self, ref_name: &str) -> io::Result<Option<String>> {
+        let lock = self.lock(ref_name)?;
+        let Some(old) = self.read_ref_value(ref_name)? else {
+            return Ok(None);
+        };
+        lock.remove()?;
+        self.updated(ref_name, Some(old.clone()).filter(|value| !value.is_empty()), ZERO_HASH);
+        Ok(Some(old))
     }
 
-    pub fn list_branches(&self) -> io::Result<Vec<(String, String)>> {
-        let heads_dir = self.git_dir.join("refs/heads");
-        if !heads_dir.exists() {
+    /// Lists every ref below `namespace` (such as "refs/remotes") with its
+    /// name relative to the namespace and the object it names, sorted by
+    /// name. Refs without an object id, such as unborn branches, have None.
+    pub fn list_refs(&self, namespace: &str) -> io::Result<Vec<(String, Option<Oid>)>> {
+        let dir = self.git_dir.join(namespace);
+        if !self.vfs.exists(&dir) {
             return Ok(Vec::new());
         }
 
-        let mut branches = Vec::new();
-        for entry in fs::read_dir(heads_dir)? {
-            let entry = entry?;
-            if entry.file_type()?.is_file() {
-                if let Some(name) = entry.file_name().to_str() {
-                    let branch_ref = format!("refs/heads/{}", name);
-                    if let Some(hash) = self.read_ref(&branch_ref)? {
-                        branches.push((name.to_string(), hash));
-                    }
+        let mut refs = Vec::new();
+        for file in walk_files(&*self.vfs, &dir)? {
+            if file.extension().is_some_and(|ext| ext == "lock") {
+                continue;
+            }
+            let name = file.to_string_lossy().into_owned();
+            if let Some(value) = self.read_ref_value(&format!("{}/{}", namespace, name))? {
+                refs.push((name, Oid::from_hex(&value).ok()));
+            }
+        }
+        refs.sort();
+        Ok(refs)
+    }
+
+    /// Reads an object from the store the refs belong to
+    pub fn read_object(&self, hash: Oid) -> io::Result<Object> {
+        Object::read_from_store(&*self.vfs, &self.git_dir, hash)
+    }
+
+    /// Resolves HEAD, a pseudo-ref like ORIG_HEAD, a branch name, a tag or a
+    /// (possibly abbreviated) hash to a commit hash
+    pub fn resolve_commit(&self, spec: &str) -> io::Result<Oid> {
+        if spec == "HEAD" {
+            let head = self.read_head()?.unwrap_or_default();
+            return self.peel(&head)?.ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                messages::head_unborn(),
+            ));
+        }
+        // Pseudo-refs such as ORIG_HEAD sit next to HEAD
+        if spec.ends_with("_HEAD") && spec.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+            if let Some(hash) = self.read_ref(spec)? {
+                return Ok(hash);
+            }
+        }
+
+        if let Some(hash) = self.read_ref(&format!("refs/heads/{}", spec))? {
+            return Ok(hash);
+        }
+        // Tags may point at something other than a commit
+        if let Some(hash) = self.read_ref(&format!("refs/tags/{}", spec))? {
+            if let Ok(Object::Commit { .. }) = self.read_object(hash) {
+                return Ok(hash);
+            }
+        }
+
+        let not_found = || io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Not a valid branch or commit: '{}'", spec),
+        );
+        if spec.len() < MIN_PREFIX_LEN || spec.len() > 40 || !spec.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(not_found());
+        }
+
+        let hash = self.expand_prefix(spec).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => not_found(),
+            _ => e,
+        })?;
+        match self.read_object(hash)? {
+            Object::Commit { .. } => Ok(hash),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a commit", spec),
+            )),
+        }
+    }
+
+    /// Expands an abbreviated object name, of at least MIN_PREFIX_LEN hex
+    /// digits, to the one stored object it starts. An ambiguous prefix
+    /// fails with the candidates in the message.
+    pub fn expand_prefix(&self, prefix: &str) -> io::Result<Oid> {
+        let prefix = prefix.to_lowercase();
+        if prefix.len() < MIN_PREFIX_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                messages::prefix_too_short(&prefix, MIN_PREFIX_LEN),
+            ));
+        }
+        if prefix.len() > 40 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a valid object name", prefix),
+            ));
+        }
+
+        let candidates = Object::find_by_prefix_in_store(self.vfs.as_ref(), &self.git_dir, &prefix)?;
+        match candidates.as_slice() {
+            [hash] => Ok(*hash),
+            [] => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                messages::no_such_object(&prefix),
+            )),
+            _ => {
+                let mut message = messages::ambiguous_prefix(&prefix);
+                for hash in &candidates {
+                    let kind = self.read_object(*hash).map_or("bad object", |object| object.type_str());
+                    message.push_str(&format!("\n  {} {}", hash, kind));
                 }
+                Err(io::Error::new(io::ErrorKind::InvalidInput, message))
             }
         }
-        Ok(branches)
     }
 
-    pub fn delete_branch(&self, branch_name: &str) -> io::Result<()> {
-        // Check if branch exists
+    /// Deletes a branch and returns the commit it pointed to (None for a
+    /// branch without commits). The branch's reflog is kept, with a final
+    /// entry recording the deletion, so the tip stays recoverable until gc
+    /// expires the log.
+    pub fn delete_branch(&self, branch_name: &str) -> io::Result<Option<Oid>> {
         let branch_ref = format!("refs/heads/{}", branch_name);
-        if self.read_ref(&branch_ref)?.is_none() {
+        check_ref_format(&branch_ref)?;
+        if self.head_branch()?.as_deref() == Some(branch_ref.as_str()) {
             return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("Branch '{}' does not exist", branch_name),
+                io::ErrorKind::InvalidInput,
+                format!("Cannot delete the current branch '{}'", branch_name),
             ));
         }
 
-        // Check if we're trying to delete the current branch
-        let head_content = self.read_head()?;
-        if let Some(content) = head_content {
-            if content == format!("ref: {}", branch_ref) {
+        let tip = self.delete_ref(&branch_ref)?.ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            messages::branch_not_found(branch_name),
+        ))?;
+        let tip = Oid::from_hex(&tip).ok();
+        if let Some(tip) = tip {
+            reflog::append_in(&*self.vfs, &self.git_dir, &branch_ref, Some(&tip.to_string()), ZERO_HASH, "branch: deleted")?;
+        }
+        Ok(tip)
+    }
+
+    /// Renames a branch: the new ref takes over the old one's value and
+    /// reflog, and HEAD follows when it named the old branch. An existing
+    /// branch called `new_name` is only replaced when `force` is set, and
+    /// never when it is checked out.
+    pub fn rename_branch(&self, old_name: &str, new_name: &str, force: bool) -> io::Result<()> {
+        let old_ref = format!("refs/heads/{}", old_name);
+        let new_ref = format!("refs/heads/{}", new_name);
+        check_ref_format(&new_ref)?;
+        if self.read_ref_value(&old_ref)?.is_none() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, messages::branch_not_found(old_name)));
+        }
+        if old_ref == new_ref {
+            return Ok(());
+        }
+        let head_branch = self.head_branch()?;
+        if self.read_ref_value(&new_ref)?.is_some() {
+            if !force {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, messages::branch_exists(new_name)));
+            }
+            if head_branch.as_deref() == Some(new_ref.as_str()) {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
-                    format!("Cannot delete the current branch '{}'", branch_name),
+                    format!("Cannot replace the current branch '{}'", new_name),
                 ));
             }
         }
 
-        // Delete the branch file
-        let branch_path = self.git_dir.join(&branch_ref);
-        fs::remove_file(branch_path)?;
-        
+        // The old ref and its log are cleared away first so a name like
+        // `feature` can become `feature/foo` and the other way round. The
+        // log waits in RENAMED-REF until the new ref is in place.
+        let lock = self.lock(&old_ref)?;
+        let tip = self.read_ref_value(&old_ref)?.ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            messages::branch_not_found(old_name),
+        ))?;
+        let logs = self.git_dir.join("logs");
+        let old_log = logs.join(&old_ref);
+        let parked_log = logs.join("RENAMED-REF");
+        let has_log = self.vfs.exists(&old_log);
+        if has_log {
+            self.vfs.rename(&old_log, &parked_log)?;
+        }
+        if let Err(e) = lock.remove() {
+            if has_log {
+                let _ = self.vfs.rename(&parked_log, &old_log);
+            }
+            return Err(e);
+        }
+        self.prune_empty_dirs(&self.git_dir.join(&old_ref), &self.git_dir.join("refs/heads"));
+        self.prune_empty_dirs(&old_log, &logs.join("refs/heads"));
+
+        let new_log = logs.join(&new_ref);
+        let moved_log = has_log.then_some((parked_log.as_path(), new_log.as_path()));
+        if let Err(e) = self.install_renamed(&new_ref, &tip, moved_log) {
+            // Put the branch back the way it was
+            if has_log && !self.vfs.exists(&parked_log) && self.vfs.rename(&new_log, &parked_log).is_ok() {
+                self.prune_empty_dirs(&new_log, &logs.join("refs/heads"));
+            }
+            let _ = self.write_ref(&old_ref, &tip, None);
+            if has_log {
+                let _ = self.vfs.create_dir_all(old_log.parent().unwrap_or(&logs));
+                let _ = self.vfs.rename(&parked_log, &old_log);
+            }
+            return Err(e);
+        }
+        if !tip.is_empty() {
+            let message = format!("Branch: renamed {} to {}", old_ref, new_ref);
+            reflog::append_in(&*self.vfs, &self.git_dir, &new_ref, Some(&tip), &tip, &message)?;
+        }
+        if head_branch.as_deref() == Some(old_ref.as_str()) {
+            self.write_ref("HEAD", &format!("ref: {}", new_ref), None)?;
+        }
         Ok(())
     }
 
+    /// Moves the parked log from `log.0` to `log.1`, then writes the
+    /// renamed branch. The moved log gets one entry for the rename instead
+    /// of the usual entries for each ref written.
+    fn install_renamed(&self, new_ref: &str, tip: &str, log: Option<(&Path, &Path)>) -> io::Result<()> {
+        if let Some((parked, new_log)) = log {
+            if let Some(parent) = new_log.parent() {
+                self.vfs.create_dir_all(parent)?;
+            }
+            self.vfs.rename(parked, new_log)?;
+        }
+        self.write_ref(new_ref, tip, None)
+    }
+
+    /// Removes the directories between `path` and `stop` left empty once
+    /// `path` is gone, so they do not block a ref of the same name
+    fn prune_empty_dirs(&self, path: &Path, stop: &Path) {
+        for dir in path.ancestors().skip(1).take_while(|dir| *dir != stop && dir.starts_with(stop)) {
+            if self.vfs.remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+
     pub fn merge_branch(&self, branch_name: &str) -> io::Result<()> {
+        let committer = crate::cobra::core::signature::Signature::committer(&self.git_dir)?;
+        self.merge_branch_with(branch_name, &format!("Merge branch '{}'", branch_name), committer)
+    }
+
+    /// Like `merge_branch`, recording the merge commit with `message` and
+    /// `committer`
+    pub fn merge_branch_with(
+        &self,
+        branch_name: &str,
+        message: &str,
+        committer: crate::cobra::core::signature::Signature,
+    ) -> io::Result<()> {
         // Check if branch exists
         let branch_ref = format!("refs/heads/{}", branch_name);
         let branch_commit = self.read_ref(&branch_ref)?
             .ok_or_else(|| io::Error::new(
                 io::ErrorKind::NotFound,
-                format!("Branch '{}' does not exist", branch_name),
+                messages::branch_not_found(branch_name),
             ))?;
 
         // Get current branch commit
@@ -159,19 +841,7 @@ impl RefStore {
                 io::ErrorKind::NotFound,
                 "HEAD reference not found",
             ))?;
-
-        let current_commit = if head_content.starts_with("ref: ") {
-            // HEAD points to a branch, get the commit from that branch
-            let current_branch_ref = &head_content[5..];
-            self.read_ref(current_branch_ref)?
-                .ok_or_else(|| io::Error::new(
-                    io::ErrorKind::NotFound,
-                    format!("Branch '{}' not found", current_branch_ref),
-                ))?
-        } else {
-            // HEAD points directly to a commit
-            head_content.clone()
-        };
+        let current_commit = self.resolve_commit("HEAD")?;
 
         // Check if we're trying to merge the same branch
         if current_commit == branch_commit {
@@ -181,74 +851,92 @@ impl RefStore {
             ));
         }
 
-        // For now, we'll create a simple merge commit
-        // In a real implementation, you'd need to handle conflicts, etc.
-        let author = crate::cobra::core::signature::Signature::new(
-            "Your Name".to_string(),
-            "you@example.com".to_string(),
-        );
-        let committer = author.clone();
+        // Combine both sides' changes since their merge base
+        let mut transaction = self.transaction();
+        let labels = merge::Labels { ours: "HEAD", theirs: branch_name };
+        let tree = merge::merge_commits(&self.git_dir, &mut transaction, current_commit, branch_commit, labels)?.into_tree()?;
+        let author = crate::cobra::core::signature::Signature::author(&self.git_dir)?;
 
         // Create merge commit with both parents
-        let merge_commit = crate::cobra::core::object::Object::new_commit(
-            current_commit.clone(), // Use current tree (simplified)
+        let merge_commit = Object::new_commit(
+            tree,
             vec![current_commit, branch_commit],
             author,
             committer,
-            format!("Merge branch '{}'", branch_name),
+            message.to_string(),
         );
 
-        // Write merge commit
-        let merge_hash = merge_commit.hash();
-        merge_commit.write_to_objects_dir(&self.git_dir)?;
-
-        // Update current branch to point to merge commit
-        if head_content.starts_with("ref: ") {
-            let current_branch_ref = &head_content[5..];
-            self.update_ref(current_branch_ref, &merge_hash)?;
-        } else {
-            self.update_head(&merge_hash)?;
-        }
-
-        Ok(())
+        // Write the merge commit and move the current branch (or detached HEAD) to it
+        let merge_hash = transaction.add(merge_commit)?;
+        let head_ref = head_content.strip_prefix("ref: ").unwrap_or("HEAD");
+        let reason = format!("merge {}: Merge made", branch_name);
+        transaction.update_ref(head_ref, Some(current_commit), merge_hash, &reason);
+        transaction.commit()
     }
 
-    pub fn create_stash(&self, message: Option<&str>) -> io::Result<String> {
-        // Create repository instance
-        let repo = crate::cobra::core::repository::Repository::open(".")?;
-        
+    pub fn create_stash(&self, repo: &crate::cobra::core::repository::Repository, message: Option<&str>) -> io::Result<Oid> {
         // Create stash state from current workspace and index
         let stash_message = message.unwrap_or("WIP on current branch");
-        let stash_state = crate::cobra::core::workspace::StashState::create(&repo, stash_message)?;
+        let stash_state = crate::cobra::core::workspace::StashState::create(repo, stash_message)?;
         
-        // Create commit from stash state
-        let stash_hash = stash_state.create_commit(&repo)?;
-        
-        // Add to stash list
-        self.add_to_stash_list(&stash_hash)?;
+        // Create commit from stash state. refs/stash keeps its reflog under
+        // its own lock, so it moves once the objects are durable.
+        let mut transaction = repo.transaction().with_signoff_policy(false);
+        let stash_hash = stash_state.create_commit(&mut transaction)?;
+        transaction.commit()?;
+        self.push_stash(stash_hash, stash_message)?;
         
         Ok(stash_hash)
     }
 
-    pub fn list_stashes(&self) -> io::Result<Vec<(String, String)>> {
-        let stash_list_path = self.git_dir.join("refs/stash");
-        if !stash_list_path.exists() {
-            return Ok(Vec::new());
-        }
+    /// Lists the stashes newest first as (`stash@{N}`, commit) pairs
+    pub fn list_stashes(&self) -> io::Result<Vec<(String, Oid)>> {
+        self.stash_entries()?
+            .into_iter()
+            .rev()
+            .enumerate()
+            .map(|(index, entry)| Ok((format!("stash@{{{}}}", index), Oid::from_hex(&entry.new)?)))
+            .collect()
+    }
 
-        let content = fs::read_to_string(&stash_list_path)?;
-        let mut stashes = Vec::new();
-        
-        for (index, line) in content.lines().enumerate() {
-            if !line.trim().is_empty() {
-                stashes.push((format!("stash@{{{}}}", index), line.trim().to_string()));
-            }
+    /// The stash reflog, oldest first. Repositories from before the reflog
+    /// kept every stash as a line of `refs/stash`; those lines are read as
+    /// entries until the next push or drop rewrites them into the log.
+    fn stash_entries(&self) -> io::Result<Vec<ReflogEntry>> {
+        let entries = reflog::read_in(&*self.vfs, &self.git_dir, STASH_REF)?;
+        if !entries.is_empty() {
+            return Ok(entries);
+        }
+        let legacy = self.read_ref_value(STASH_REF)?.unwrap_or_default();
+        let mut previous: Option<String> = None;
+        let mut entries = Vec::new();
+        for line in legacy.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            entries.push(ReflogEntry::new(&self.git_dir, previous.as_deref(), line, "stash"));
+            previous = Some(line.to_string());
         }
+        Ok(entries)
+    }
+
+    /// Records `stash_hash` as the newest stash while holding the lock on
+    /// refs/stash, so concurrent pushes cannot lose each other's entries
+    pub fn push_stash(&self, stash_hash: Oid, message: &str) -> io::Result<()> {
+        let stash_hash = stash_hash.to_string();
+        let lock = self.lock(STASH_REF)?;
+        let mut entries = self.stash_entries()?;
+        let old = entries.last().map(|entry| entry.new.clone());
+        entries.push(ReflogEntry::new(&self.git_dir, old.as_deref(), &stash_hash, message));
+        reflog::write_in(&*self.vfs, &self.git_dir, STASH_REF, &entries)?;
+        lock.commit(&stash_hash)?;
 
-        Ok(stashes)
+        self.events.emit(RepositoryEvent::RefUpdated {
+            name: STASH_REF.to_string(),
+            old,
+            new: stash_hash,
+        });
+        Ok(())
     }
 
-    pub fn get_stash(&self, stash_ref: &str) -> io::Result<Option<String>> {
+    pub fn get_stash(&self, stash_ref: &str) -> io::Result<Option<Oid>> {
         let stashes = self.list_stashes()?;
         
         // Parse stash reference like "stash@{0}"
@@ -256,23 +944,19 @@ impl RefStore {
             let index_str = &stash_ref[7..stash_ref.len()-1];
             if let Ok(index) = index_str.parse::<usize>() {
                 if index < stashes.len() {
-                    return Ok(Some(stashes[index].1.clone()));
+                    return Ok(Some(stashes[index].1));
                 }
             }
         }
         
         // Try direct hash
-        if stash_ref.len() == 40 {
-            return Ok(Some(stash_ref.to_string()));
-        }
-
-        Ok(None)
+        Ok(Oid::from_hex(stash_ref).ok())
     }
 
+    /// Removes `stash@{N}` from the stash reflog under the lock and points
+    /// refs/stash at whatever is newest afterwards. The dropped commit is
+    /// no longer referenced anywhere and shows up as dangling in fsck.
     pub fn drop_stash(&self, stash_ref: &str) -> io::Result<()> {
-        let stashes = self.list_stashes()?;
-        let stash_list_path = self.git_dir.join("refs/stash");
-        
         // Parse stash reference
         let index = if stash_ref.starts_with("stash@{") && stash_ref.ends_with("}") {
             let index_str = &stash_ref[7..stash_ref.len()-1];
@@ -286,77 +970,73 @@ impl RefStore {
             ));
         };
 
-        if index >= stashes.len() {
+        let lock = self.lock(STASH_REF)?;
+        let mut entries = self.stash_entries()?;
+        if index >= entries.len() {
             return Err(io::Error::new(
                 io::ErrorKind::NotFound,
-                format!("Stash '{}' does not exist", stash_ref),
+                messages::stash_not_found(stash_ref),
             ));
         }
 
-        // Remove the stash from the list
-        let mut new_stashes = stashes;
-        new_stashes.remove(index);
-        
-        // Write updated stash list
-        let content = new_stashes.iter()
-            .map(|(_, hash)| hash.clone())
-            .collect::<Vec<_>>()
-            .join("\n");
-        
-        if content.is_empty() {
-            fs::remove_file(&stash_list_path)?;
-        } else {
-            fs::write(&stash_list_path, content)?;
+        // stash@{0} is the last entry of the log
+        let dropped = entries.remove(entries.len() - 1 - index);
+        // Keep the old/new chain of the log intact across the gap
+        let mut previous = ZERO_HASH.to_string();
+        for entry in &mut entries {
+            entry.old = std::mem::replace(&mut previous, entry.new.clone());
         }
+        reflog::write_in(&*self.vfs, &self.git_dir, STASH_REF, &entries)?;
 
-        Ok(())
-    }
-
-    fn add_to_stash_list(&self, stash_hash: &str) -> io::Result<()> {
-        let stash_list_path = self.git_dir.join("refs/stash");
-        
-        // Create refs directory if it doesn't exist
-        if let Some(parent) = stash_list_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-
-        // Append to stash list
-        let mut content = if stash_list_path.exists() {
-            fs::read_to_string(&stash_list_path)?
-        } else {
-            String::new()
-        };
-
-        if !content.is_empty() {
-            content.push('\n');
+        let newest = entries.last().map(|entry| entry.new.clone());
+        match &newest {
+            Some(hash) => lock.commit(hash)?,
+            None => lock.remove()?,
         }
-        content.push_str(stash_hash);
-
-        fs::write(&stash_list_path, content)?;
+        trace::event(Stream::Main, || format!("stash drop {}: {}", stash_ref, dropped.new));
+        self.events.emit(RepositoryEvent::RefUpdated {
+            name: STASH_REF.to_string(),
+            old: Some(dropped.new),
+            new: newest.unwrap_or_else(|| ZERO_HASH.to_string()),
+        });
         Ok(())
     }
 
-    pub fn switch_branch(&self, branch_name: &str) -> io::Result<()> {
+    /// Points HEAD at the existing branch `branch_name`, logging the move
+    /// with `reason`
+    pub fn switch_branch(&self, branch_name: &str, reason: &str) -> io::Result<()> {
         let branch_ref = format!("refs/heads/{}", branch_name);
-        if self.read_ref(&branch_ref)?.is_none() {
+        if self.read_ref_value(&branch_ref)?.is_none() {
             return Err(io::Error::new(
                 io::ErrorKind::NotFound, 
-                format!("Branch '{}' does not exist", branch_name)
+                messages::branch_not_found(branch_name)
             ));
         }
-        self.update_head(&format!("ref: {}", branch_ref))
+        self.attach_head(&branch_ref, reason)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cobra::commands::add::add_file;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::utils::fs::{MemoryFs, Vfs, VfsOp};
+    use crate::cobra::test_support::{temp_repo, FEATURE_COMMIT, MAIN_COMMIT};
+    use std::fs;
+    use std::sync::Arc;
     use tempfile::TempDir;
 
+    /// A RefStore kept in memory, for tests that only need refs
+    fn memory_store() -> (Arc<MemoryFs>, RefStore) {
+        let fs = MemoryFs::new();
+        let ref_store = RefStore::with_vfs(PathBuf::from("/repo/.cobra"), EventBus::new(), fs.clone());
+        (fs, ref_store)
+    }
+
     #[test]
     fn test_create_branch() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
+        let (_, ref_store) = memory_store();
         
         // Initialize refs
         ref_store.create_initial_refs()?;
@@ -365,11 +1045,11 @@ mod tests {
         ref_store.create_branch("feature")?;
         
         // Verify branch was created
-        let branch_content = ref_store.read_ref("refs/heads/feature")?;
+        let branch_content = ref_store.read_ref_value("refs/heads/feature")?;
         assert!(branch_content.is_some());
         
         // Verify it points to the same commit as main (empty in this case)
-        let main_content = ref_store.read_ref("refs/heads/main")?;
+        let main_content = ref_store.read_ref_value("refs/heads/main")?;
         assert_eq!(branch_content, main_content);
         
         Ok(())
@@ -377,30 +1057,58 @@ mod tests {
 
     #[test]
     fn test_create_branch_with_commit() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
+        let (_, ref_store) = memory_store();
         
         // Initialize refs
         ref_store.create_initial_refs()?;
         
         // Set main branch to point to a commit
-        let commit_hash = "abc123def456";
-        ref_store.update_ref("refs/heads/main", commit_hash)?;
+        let commit_hash: Oid = "abc123def4560000000000000000000000000000".parse()?;
+        ref_store.update_ref("refs/heads/main", commit_hash, "test")?;
         
         // Create a new branch
         ref_store.create_branch("feature")?;
         
         // Verify branch points to the same commit
         let branch_content = ref_store.read_ref("refs/heads/feature")?;
-        assert_eq!(branch_content, Some(commit_hash.to_string()));
+        assert_eq!(branch_content, Some(commit_hash));
         
         Ok(())
     }
 
+    #[test]
+    fn test_check_ref_format() {
+        for valid in ["refs/heads/main", "refs/heads/feature/foo", "refs/tags/v1.0", "HEAD", "refs/heads/a-b_c"] {
+            assert_eq!(check_ref_format(valid), Ok(()), "{}", valid);
+        }
+        for invalid in [
+            "refs/heads/../../../evil", "/refs/heads/x", "refs/heads/x/", "refs/heads//x", "refs/heads/.hidden",
+            "refs/heads/x.lock", "refs/heads/x.", "refs/heads/a b", "refs/heads/a\tb", "refs/heads/a~1",
+            "refs/heads/a^", "refs/heads/a:b", "refs/heads/a?", "refs/heads/a*", "refs/heads/a[b", "refs/heads/a\\b",
+            "refs/heads/a@{1}", "@", "",
+        ] {
+            assert!(check_ref_format(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_create_branch_rejects_invalid_names() -> io::Result<()> {
+        let (fs, ref_store) = memory_store();
+        ref_store.create_initial_refs()?;
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
+
+        for name in ["../../../evil", "bad/", "x.lock", "a..b"] {
+            let error = ref_store.create_branch(name).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", name);
+        }
+        assert!(!fs.exists(Path::new("/evil")));
+        assert_eq!(ref_store.list_branches()?, vec![("main".to_string(), Some(MAIN_COMMIT.parse()?))]);
+        Ok(())
+    }
+
     #[test]
     fn test_create_duplicate_branch() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
+        let (_, ref_store) = memory_store();
         
         // Initialize refs
         ref_store.create_initial_refs()?;
@@ -423,10 +1131,42 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_create_and_delete_tag() -> io::Result<()> {
+        let (_, ref_store) = memory_store();
+        ref_store.create_initial_refs()?;
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
+
+        ref_store.create_tag("v1.0")?;
+        assert_eq!(ref_store.list_tags()?, vec![("v1.0".to_string(), Some(MAIN_COMMIT.parse()?))]);
+        let err = ref_store.create_tag("v1.0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        assert_eq!(ref_store.delete_tag("v1.0")?, Some(MAIN_COMMIT.parse()?));
+        assert!(ref_store.list_tags()?.is_empty());
+        assert_eq!(ref_store.delete_tag("v1.0").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        for name in ["../../HEAD", "v1.0.lock", "bad name", "v1/"] {
+            assert_eq!(ref_store.create_tag(name).unwrap_err().kind(), io::ErrorKind::InvalidInput, "{}", name);
+        }
+        assert!(ref_store.list_tags()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_tag_on_unborn_branch() -> io::Result<()> {
+        let (_, ref_store) = memory_store();
+        ref_store.create_initial_refs()?;
+
+        let err = ref_store.create_tag("v1.0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(ref_store.list_tags()?.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_list_branches() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
+        let (_, ref_store) = memory_store();
         
         // Initialize refs
         ref_store.create_initial_refs()?;
@@ -444,14 +1184,18 @@ mod tests {
         assert!(branch_names.contains(&"feature1".to_string()));
         assert!(branch_names.contains(&"feature2".to_string()));
         assert_eq!(branches.len(), 3);
+
+        // Nested names are listed too, and everything comes sorted
+        ref_store.create_branch("feature/nested")?;
+        let branch_names: Vec<String> = ref_store.list_branches()?.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(branch_names, ["feature/nested", "feature1", "feature2", "main"]);
         
         Ok(())
     }
 
     #[test]
     fn test_list_branches_empty() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
+        let (_, ref_store) = memory_store();
         
         // Don't initialize refs, so no branches exist
         let branches = ref_store.list_branches()?;
@@ -462,8 +1206,7 @@ mod tests {
 
     #[test]
     fn test_delete_branch() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
+        let (fs, ref_store) = memory_store();
         
         // Initialize refs
         ref_store.create_initial_refs()?;
@@ -483,14 +1226,80 @@ mod tests {
         let branches_after = ref_store.list_branches()?;
         let branch_names_after: Vec<String> = branches_after.iter().map(|(name, _)| name.clone()).collect();
         assert!(!branch_names_after.contains(&"feature".to_string()));
-        
+
+        // Names that would reach outside refs/heads are refused
+        fs.write_atomic(Path::new("/repo/.cobra/config"), b"[core]\n")?;
+        for name in ["../../config", "../tags/v1", "feature.lock"] {
+            assert_eq!(ref_store.delete_branch(name).unwrap_err().kind(), io::ErrorKind::InvalidInput, "{}", name);
+        }
+        assert!(fs.exists(Path::new("/repo/.cobra/config")));
+        assert_eq!(ref_store.delete_tag("../heads/main").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_branch() -> io::Result<()> {
+        let (_, ref_store) = memory_store();
+        ref_store.create_initial_refs()?;
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "commit (initial): First")?;
+        ref_store.update_ref("refs/heads/other", FEATURE_COMMIT.parse()?, "test")?;
+
+        // The checked out branch, into a name that needs a new directory
+        ref_store.rename_branch("main", "feature/main", false)?;
+        assert_eq!(ref_store.read_ref("refs/heads/main")?, None);
+        assert_eq!(ref_store.read_ref("refs/heads/feature/main")?, Some(MAIN_COMMIT.parse()?));
+        assert_eq!(ref_store.current_branch()?, Some("feature/main".to_string()));
+        let log = reflog::read_in(&*ref_store.vfs, &ref_store.git_dir, "refs/heads/feature/main")?;
+        let messages: Vec<&str> = log.iter().map(|entry| entry.message.as_str()).collect();
+        assert_eq!(messages, ["commit (initial): First", "Branch: renamed refs/heads/main to refs/heads/feature/main"]);
+        assert!(reflog::read_in(&*ref_store.vfs, &ref_store.git_dir, "refs/heads/main")?.is_empty());
+
+        let error = ref_store.rename_branch("missing", "found", false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        // An existing target needs force, and the checked out branch is never replaced
+        let error = ref_store.rename_branch("other", "feature/main", false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        let error = ref_store.rename_branch("other", "feature/main", true).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        ref_store.switch_branch("other", "test")?;
+        ref_store.rename_branch("feature/main", "other", true).unwrap_err();
+        ref_store.rename_branch("other", "feature/main", true)?;
+        assert_eq!(ref_store.read_ref("refs/heads/feature/main")?, Some(FEATURE_COMMIT.parse()?));
+        assert_eq!(ref_store.read_ref("refs/heads/other")?, None);
+        assert_eq!(ref_store.current_branch()?, Some("feature/main".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_branch_keeps_the_old_one_on_failure() -> io::Result<()> {
+        let (fs, ref_store) = memory_store();
+        ref_store.create_initial_refs()?;
+        ref_store.update_ref("refs/heads/a/b", MAIN_COMMIT.parse()?, "branch: Created from main")?;
+
+        let error = ref_store.rename_branch("a/b", "bad/", false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(ref_store.read_ref("refs/heads/a/b")?, Some(MAIN_COMMIT.parse()?));
+
+        // The emptied directory makes way for a branch of its name
+        ref_store.rename_branch("a/b", "a", false)?;
+        assert_eq!(ref_store.read_ref("refs/heads/a")?, Some(MAIN_COMMIT.parse()?));
+        assert_eq!(reflog::read_in(&*ref_store.vfs, &ref_store.git_dir, "refs/heads/a")?.len(), 2);
+
+        fs.fail(VfsOp::Write, "/repo/.cobra/refs/heads/c.lock", io::ErrorKind::StorageFull);
+        let error = ref_store.rename_branch("a", "c", false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+        assert_eq!(ref_store.read_ref("refs/heads/a")?, Some(MAIN_COMMIT.parse()?));
+        assert_eq!(ref_store.read_ref("refs/heads/c")?, None);
+        assert_eq!(reflog::read_in(&*ref_store.vfs, &ref_store.git_dir, "refs/heads/a")?.len(), 2);
+        assert!(!fs.exists(Path::new("/repo/.cobra/logs/RENAMED-REF")));
         Ok(())
     }
 
     #[test]
     fn test_delete_nonexistent_branch() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
+        let (_, ref_store) = memory_store();
         
         // Initialize refs
         ref_store.create_initial_refs()?;
@@ -512,8 +1321,7 @@ mod tests {
 
     #[test]
     fn test_delete_current_branch() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
+        let (_, ref_store) = memory_store();
         
         // Initialize refs
         ref_store.create_initial_refs()?;
@@ -522,7 +1330,7 @@ mod tests {
         ref_store.create_branch("feature")?;
         
         // Switch to the branch
-        ref_store.update_head("ref: refs/heads/feature")?;
+        ref_store.attach_head("refs/heads/feature", "test")?;
         
         // Try to delete the current branch
         let result = ref_store.delete_branch("feature");
@@ -541,37 +1349,44 @@ mod tests {
 
     #[test]
     fn test_merge_branch() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
-        
-        // Initialize refs
-        ref_store.create_initial_refs()?;
-        
-        // Create a branch
+        let (temp_dir, mut repo) = temp_repo()?;
+        let ref_store = RefStore::new(repo.git_dir.clone());
+        let mut commit_file = |name: &str, content: &str, message: &str| -> io::Result<Oid> {
+            fs::write(temp_dir.path().join(name), content)?;
+            add_file(&mut repo, Path::new(name))?;
+            commit(&repo, message)
+        };
+        commit_file("shared.txt", "one\ntwo\nthree\n", "Base")?;
+
+        // Each branch edits its own end of the shared file
         ref_store.create_branch("feature")?;
-        
-        // Set some commits (simplified for testing)
-        ref_store.update_ref("refs/heads/main", "main_commit")?;
-        ref_store.update_ref("refs/heads/feature", "feature_commit")?;
-        
-        // Merge feature into main
+        ref_store.switch_branch("feature", "test")?;
+        let feature = commit_file("shared.txt", "one\ntwo\nTHREE\n", "Feature")?;
+        ref_store.switch_branch("main", "test")?;
+        let main = commit_file("shared.txt", "ONE\ntwo\nthree\n", "Main")?;
+
         ref_store.merge_branch("feature")?;
-        
-        // Verify the merge created a new commit
-        let main_commit = ref_store.read_ref("refs/heads/main")?;
-        assert!(main_commit.is_some());
-        assert_ne!(main_commit.unwrap(), "main_commit"); // Should be different after merge
+
+        // The merge commit has both tips as parents and combines both edits
+        let merged = ref_store.read_ref("refs/heads/main")?.unwrap();
+        let Object::Commit { tree, parents, .. } = Object::read_from_objects_dir(&ref_store.git_dir, merged)? else {
+            panic!("not a commit");
+        };
+        assert_eq!(parents, vec![main, feature]);
+        assert!(matches!(Object::read_from_objects_dir(&ref_store.git_dir, tree)?, Object::Tree(_)));
+        let files = crate::cobra::core::tree::flatten_tree(&ref_store.git_dir, tree)?;
+        let Object::Blob(content) = Object::read_from_objects_dir(&ref_store.git_dir, files[Path::new("shared.txt")].hash)? else {
+            panic!("not a blob");
+        };
+        assert_eq!(content, b"ONE\ntwo\nTHREE\n");
         
         Ok(())
     }
 
     #[test]
     fn test_merge_nonexistent_branch() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
-        
-        // Initialize refs
-        ref_store.create_initial_refs()?;
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = RefStore::new(repo.git_dir.clone());
         
         // Try to merge a non-existent branch
         let result = ref_store.merge_branch("nonexistent");
@@ -590,15 +1405,12 @@ mod tests {
 
     #[test]
     fn test_merge_same_branch() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
-        
-        // Initialize refs
-        ref_store.create_initial_refs()?;
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = RefStore::new(repo.git_dir.clone());
         
         // Set same commit for both branches
-        ref_store.update_ref("refs/heads/main", "same_commit")?;
-        ref_store.update_ref("refs/heads/feature", "same_commit")?;
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
+        ref_store.update_ref("refs/heads/feature", MAIN_COMMIT.parse()?, "test")?;
         
         // Try to merge the same branch
         let result = ref_store.merge_branch("feature");
@@ -617,19 +1429,15 @@ mod tests {
 
     #[test]
     fn test_create_stash() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
-        
-        // Initialize refs and create repository structure
-        ref_store.create_initial_refs()?;
-        ref_store.update_ref("refs/heads/main", "main_commit")?;
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = RefStore::new(repo.git_dir.clone());
         
-        // Create objects directory for stash creation
-        fs::create_dir_all(temp_dir.path().join(".cobra/objects"))?;
+        // Set main branch to point to a commit
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
         
         // Create a stash
-        let stash_hash = ref_store.create_stash(Some("Test stash"))?;
-        assert!(!stash_hash.is_empty());
+        let stash_hash = ref_store.create_stash(&repo, Some("Test stash"))?;
+        assert!(!stash_hash.is_null());
         
         // Verify stash was added to list
         let stashes = ref_store.list_stashes()?;
@@ -642,43 +1450,77 @@ mod tests {
 
     #[test]
     fn test_list_stashes() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = RefStore::new(repo.git_dir.clone());
         
-        // Initialize refs and create repository structure
-        ref_store.create_initial_refs()?;
-        ref_store.update_ref("refs/heads/main", "main_commit")?;
-        
-        // Create objects directory for stash creation
-        fs::create_dir_all(temp_dir.path().join(".cobra/objects"))?;
+        // Set main branch to point to a commit
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
         
         // Create multiple stashes
-        ref_store.create_stash(Some("First stash"))?;
-        ref_store.create_stash(Some("Second stash"))?;
+        let first = ref_store.create_stash(&repo, Some("First stash"))?;
+        let second = ref_store.create_stash(&repo, Some("Second stash"))?;
         
-        // List stashes
+        // List stashes, newest first
         let stashes = ref_store.list_stashes()?;
-        assert_eq!(stashes.len(), 2);
-        assert_eq!(stashes[0].0, "stash@{0}");
-        assert_eq!(stashes[1].0, "stash@{1}");
+        assert_eq!(stashes, vec![
+            ("stash@{0}".to_string(), second),
+            ("stash@{1}".to_string(), first),
+        ]);
+
+        // refs/stash holds the newest stash, the reflog holds all of them
+        assert_eq!(ref_store.read_ref("refs/stash")?, Some(second));
+        let entries = reflog::read(&repo.git_dir, "refs/stash")?;
+        assert_eq!(entries.iter().map(|entry| entry.message.as_str()).collect::<Vec<_>>(), vec!["First stash", "Second stash"]);
         
         Ok(())
     }
 
     #[test]
-    fn test_get_stash() -> io::Result<()> {
+    fn test_concurrent_stash_pushes_keep_every_entry() -> io::Result<()> {
         let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
-        
-        // Initialize refs and create repository structure
-        ref_store.create_initial_refs()?;
-        ref_store.update_ref("refs/heads/main", "main_commit")?;
+        let git_dir = temp_dir.path().to_path_buf();
+
+        // Each thread stands in for a separate process with its own RefStore
+        let workers: Vec<_> = (0..8).map(|worker| {
+            let git_dir = git_dir.clone();
+            thread::spawn(move || -> io::Result<()> {
+                let ref_store = RefStore::new(git_dir);
+                for n in 0..5 {
+                    ref_store.push_stash(format!("{:040x}", worker * 100 + n).parse()?, "WIP")?;
+                }
+                Ok(())
+            })
+        }).collect();
+        for worker in workers {
+            worker.join().unwrap()?;
+        }
+
+        let ref_store = RefStore::new(git_dir.clone());
+        let mut hashes: Vec<Oid> = ref_store.list_stashes()?.into_iter().map(|(_, hash)| hash).collect();
+        assert_eq!(hashes.len(), 40);
+        assert_eq!(ref_store.read_ref("refs/stash")?.as_ref(), hashes.first());
+        hashes.sort();
+        hashes.dedup();
+        assert_eq!(hashes.len(), 40);
+
+        // No lock is left behind, and a held lock makes writers give up
+        assert!(!git_dir.join("refs/stash.lock").exists());
+        let _held = RefLock::acquire(&git_dir, "refs/stash")?;
+        let error = ref_store.push_stash("f".repeat(40).parse()?, "WIP").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_stash() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = RefStore::new(repo.git_dir.clone());
         
-        // Create objects directory for stash creation
-        fs::create_dir_all(temp_dir.path().join(".cobra/objects"))?;
+        // Set main branch to point to a commit
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
         
         // Create a stash
-        let stash_hash = ref_store.create_stash(Some("Test stash"))?;
+        let stash_hash = ref_store.create_stash(&repo, Some("Test stash"))?;
         
         // Get stash by reference
         let retrieved_hash = ref_store.get_stash("stash@{0}")?;
@@ -693,19 +1535,15 @@ mod tests {
 
     #[test]
     fn test_drop_stash() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let ref_store = RefStore::new(temp_dir.path().to_path_buf());
+        let (_temp_dir, repo) = temp_repo()?;
+        let ref_store = RefStore::new(repo.git_dir.clone());
         
-        // Initialize refs and create repository structure
-        ref_store.create_initial_refs()?;
-        ref_store.update_ref("refs/heads/main", "main_commit")?;
-        
-        // Create objects directory for stash creation
-        fs::create_dir_all(temp_dir.path().join(".cobra/objects"))?;
+        // Set main branch to point to a commit
+        ref_store.update_ref("refs/heads/main", MAIN_COMMIT.parse()?, "test")?;
         
         // Create stashes
-        ref_store.create_stash(Some("First stash"))?;
-        ref_store.create_stash(Some("Second stash"))?;
+        ref_store.create_stash(&repo, Some("First stash"))?;
+        ref_store.create_stash(&repo, Some("Second stash"))?;
         
         // Verify we have 2 stashes
         let stashes = ref_store.list_stashes()?;
@@ -718,6 +1556,13 @@ mod tests {
         let stashes_after = ref_store.list_stashes()?;
         assert_eq!(stashes_after.len(), 1);
         assert_eq!(stashes_after[0].0, "stash@{0}"); // Index should be updated
+        assert_eq!(stashes_after[0].1, stashes[1].1);
+        assert_eq!(ref_store.read_ref("refs/stash")?, Some(stashes[1].1));
+
+        // Dropping the last one removes the ref and its log
+        ref_store.drop_stash("stash@{0}")?;
+        assert_eq!(ref_store.read_ref("refs/stash")?, None);
+        assert!(reflog::read(&repo.git_dir, "refs/stash")?.is_empty());
         
         Ok(())
     }
@@ -744,4 +1589,135 @@ mod tests {
         
         Ok(())
     }
-} 
\ No newline at end of file
+
+    /// A valid hash made of one repeated digit
+    fn hash(digit: char) -> Oid {
+        digit.to_string().repeat(40).parse().unwrap()
+    }
+
+    fn update(name: &str, expected: Option<Oid>, new: Oid) -> RefUpdate {
+        RefUpdate { name: name.to_string(), expected, new, reason: "test".to_string() }
+    }
+
+    #[test]
+    fn test_update_refs_checks_every_ref_first() -> io::Result<()> {
+        let (fs, ref_store) = memory_store();
+        ref_store.create_initial_refs()?;
+        ref_store.update_ref("refs/heads/main", hash('a'), "test")?;
+        ref_store.update_ref("refs/heads/topic", hash('d'), "test")?;
+
+        // main matches, topic has moved on
+        let err = ref_store.update_refs(&[
+            update("refs/heads/main", Some(hash('a')), hash('b')),
+            update("refs/heads/topic", Some(hash('c')), hash('e')),
+        ]).unwrap_err();
+        assert!(err.to_string().contains(&format!("cannot lock ref 'refs/heads/topic': is at {} but expected {}", hash('d'), hash('c'))));
+        assert_eq!(ref_store.read_ref("refs/heads/main")?, Some(hash('a')));
+        assert!(!fs.exists(Path::new("/repo/.cobra/refs/heads/main.lock")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_refs_failed_write_moves_nothing() -> io::Result<()> {
+        let (fs, ref_store) = memory_store();
+        ref_store.create_initial_refs()?;
+        ref_store.update_ref("refs/heads/main", hash('a'), "test")?;
+
+        // Taking the lock on topic works, writing the new value into it does not
+        fs.fail_after(1, VfsOp::Write, "/repo/.cobra/refs/heads/topic.lock", io::ErrorKind::StorageFull);
+        let err = ref_store.update_refs(&[
+            update("refs/heads/main", Some(hash('a')), hash('b')),
+            update("refs/heads/topic", None, hash('c')),
+        ]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        assert_eq!(ref_store.read_ref("refs/heads/main")?, Some(hash('a')));
+        assert_eq!(ref_store.read_ref("refs/heads/topic")?, None);
+        assert_eq!(ref_store.list_branches()?.len(), 1);
+        assert!(!fs.exists(Path::new("/repo/.cobra/refs/heads/main.lock")));
+        assert!(!fs.exists(Path::new("/repo/.cobra/refs/heads/topic.lock")));
+        // Only the update that happened is logged
+        assert_eq!(reflog::read_in(&*ref_store.vfs, &ref_store.git_dir, "refs/heads/main")?.len(), 1);
+        assert!(reflog::read_in(&*ref_store.vfs, &ref_store.git_dir, "refs/heads/topic")?.is_empty());
+
+        fs.clear_faults();
+        fs.fail(VfsOp::Rename, "/repo/.cobra/refs/heads/main.lock", io::ErrorKind::StorageFull);
+        let err = ref_store.update_ref("refs/heads/main", hash('b'), "test").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        assert_eq!(reflog::read_in(&*ref_store.vfs, &ref_store.git_dir, "refs/heads/main")?.len(), 1);
+        assert_eq!(reflog::read_in(&*ref_store.vfs, &ref_store.git_dir, "HEAD")?.len(), 1);
+
+        fs.clear_faults();
+        fs.set_read_only(true);
+        let err = ref_store.update_ref("refs/heads/main", hash('b'), "test").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(ref_store.read_ref("refs/heads/main")?, Some(hash('a')));
+        Ok(())
+    }
+
+    #[test]
+    fn test_locks_refuse_invalid_ref_names() -> io::Result<()> {
+        let (fs, ref_store) = memory_store();
+        ref_store.create_initial_refs()?;
+        for name in ["refs/heads/../../config", "/refs/heads/main", "refs/heads/main.lock", "refs/heads/a\tb"] {
+            let error = RefLock::acquire_on(fs.clone(), &ref_store.git_dir, name).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", name);
+            let error = ref_store.update_refs(&[update(name, None, hash('a'))]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", name);
+        }
+        assert!(!fs.exists(Path::new("/repo/config.lock")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_refs_are_normalized_on_read() -> io::Result<()> {
+        let (fs, ref_store) = memory_store();
+        ref_store.create_initial_refs()?;
+        let commit = hash('a');
+        for content in [
+            commit.to_string(),
+            format!("{}\n", commit),
+            format!("{}\r\n", commit),
+            format!("  {}\t\n\n", commit.to_string().to_uppercase()),
+        ] {
+            fs.write_atomic(Path::new("/repo/.cobra/refs/heads/topic"), content.as_bytes())?;
+            assert_eq!(ref_store.read_ref("refs/heads/topic")?, Some(commit));
+            assert_eq!(ref_store.read_ref_raw("refs/heads/topic")?, Some(content.into_bytes()));
+        }
+
+        // A hand-edited HEAD still names the current branch, which cannot be deleted
+        ref_store.update_ref("refs/heads/main", commit, "test")?;
+        fs.write_atomic(Path::new("/repo/.cobra/HEAD"), b"ref:  refs/heads/topic \r\n")?;
+        assert_eq!(ref_store.head_branch()?, Some("refs/heads/topic".to_string()));
+        let err = ref_store.delete_branch("topic").unwrap_err();
+        assert!(err.to_string().contains("Cannot delete the current branch"));
+        assert_eq!(ref_store.delete_branch("main")?, Some(commit));
+        Ok(())
+    }
+
+    #[test]
+    fn test_refs_are_written_in_canonical_form() -> io::Result<()> {
+        let (fs, ref_store) = memory_store();
+        ref_store.create_initial_refs()?;
+        assert_eq!(fs.read(Path::new("/repo/.cobra/refs/heads/main"))?, b"\n");
+        assert_eq!(fs.read(Path::new("/repo/.cobra/HEAD"))?, b"ref: refs/heads/main\n");
+
+        ref_store.write_ref("refs/heads/main", &format!(" {}\r\n", hash('b').to_string().to_uppercase()), Some("test"))?;
+        assert_eq!(fs.read(Path::new("/repo/.cobra/refs/heads/main"))?, format!("{}\n", hash('b')).into_bytes());
+        ref_store.write_ref("HEAD", "ref:refs/heads/main", Some("test"))?;
+        assert_eq!(fs.read(Path::new("/repo/.cobra/HEAD"))?, b"ref: refs/heads/main\n");
+
+        let err = ref_store.write_ref("refs/heads/main", "main_commit", Some("test")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let inner = err.into_inner().unwrap().downcast::<RefValueError>().unwrap();
+        assert_eq!(*inner, RefValueError::Malformed { name: "refs/heads/main".to_string(), value: "main_commit".to_string() });
+        for target in ["../outside", "refs/../..", "refs/heads/a..b", "refs/heads/main.lock"] {
+            assert!(matches!(
+                ref_store.attach_head(target, "test").unwrap_err().into_inner().unwrap().downcast::<RefValueError>().as_deref(),
+                Ok(RefValueError::InvalidTarget { .. }),
+            ), "{}", target);
+        }
+        assert_eq!(ref_store.read_ref("refs/heads/main")?, Some(hash('b')));
+        assert!(!fs.exists(Path::new("/repo/.cobra/refs/heads/main.lock")));
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+// Reference logs (.cobra/logs/<refname>)
+
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::cobra::core::signature::Signature;
+use crate::cobra::utils::fs::{walk_files, RealFs, Vfs};
+
+/// Placeholder hash for "no value", used when a ref is created or deleted
+pub const ZERO_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// One movement of a ref, in git's reflog line format:
+/// `<old> <new> <name> <<email>> <timestamp> <tz>\t<message>`
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub committer: Signature,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    /// An entry made now by the committer identity of the repository at
+    /// `git_dir`; no old value is recorded as ZERO_HASH
+    pub fn new(git_dir: &Path, old: Option<&str>, new: &str, message: &str) -> ReflogEntry {
+        // A missing identity or malformed COBRA_COMMITTER_DATE must not stop
+        // a ref update that already happened
+        let committer = Signature::committer(git_dir).unwrap_or_else(|_| {
+            Signature::new("unknown".to_string(), "unknown".to_string())
+        });
+        ReflogEntry {
+            old: old.unwrap_or(ZERO_HASH).to_string(),
+            new: new.to_string(),
+            committer,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn parse(line: &str) -> io::Result<ReflogEntry> {
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut parts = header.splitn(3, ' ');
+        let old = parts.next().unwrap_or_default();
+        let new = parts.next().unwrap_or_default();
+        let committer = Signature::parse(parts.next().unwrap_or_default())?;
+
+        Ok(ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            committer,
+            message: message.to_string(),
+        })
+    }
+
+    pub fn format(&self) -> String {
+        format!("{} {} {}\t{}", self.old, self.new, self.committer.format(), self.message)
+    }
+}
+
+fn log_path(git_dir: &Path, ref_name: &str) -> PathBuf {
+    git_dir.join("logs").join(ref_name)
+}
+
+/// Appends an entry to the log of `ref_name`
+pub fn append(git_dir: &Path, ref_name: &str, old: Option<&str>, new: &str, message: &str) -> io::Result<()> {
+    append_in(&RealFs::new(git_dir), git_dir, ref_name, old, new, message)
+}
+
+pub fn append_in(vfs: &dyn Vfs, git_dir: &Path, ref_name: &str, old: Option<&str>, new: &str, message: &str) -> io::Result<()> {
+    let entry = ReflogEntry::new(git_dir, old, new, message);
+
+    let path = log_path(git_dir, ref_name);
+    if let Some(parent) = path.parent() {
+        vfs.create_dir_all(parent)?;
+    }
+    vfs.append(&path, format!("{}\n", entry.format()).as_bytes())
+}
+
+/// Reads the log of `ref_name`, oldest entry first
+pub fn read(git_dir: &Path, ref_name: &str) -> io::Result<Vec<ReflogEntry>> {
+    read_in(&RealFs::new(git_dir), git_dir, ref_name)
+}
+
+pub fn read_in(vfs: &dyn Vfs, git_dir: &Path, ref_name: &str) -> io::Result<Vec<ReflogEntry>> {
+    let content = match vfs.read(&log_path(git_dir, ref_name)) {
+        Ok(content) => String::from_utf8(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    content.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(ReflogEntry::parse)
+        .collect()
+}
+
+/// Replaces the log of `ref_name`. An empty list removes the log file.
+pub fn write(git_dir: &Path, ref_name: &str, entries: &[ReflogEntry]) -> io::Result<()> {
+    write_in(&RealFs::new(git_dir), git_dir, ref_name, entries)
+}
+
+pub fn write_in(vfs: &dyn Vfs, git_dir: &Path, ref_name: &str, entries: &[ReflogEntry]) -> io::Result<()> {
+    let path = log_path(git_dir, ref_name);
+    if entries.is_empty() {
+        return match vfs.remove(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&entry.format());
+        content.push('\n');
+    }
+    if let Some(parent) = path.parent() {
+        vfs.create_dir_all(parent)?;
+    }
+    vfs.write_atomic(&path, content.as_bytes())
+}
+
+/// Lists the names of all refs that have a log, including deleted ones
+pub fn list(git_dir: &Path) -> io::Result<Vec<String>> {
+    list_in(&RealFs::new(git_dir), git_dir)
+}
+
+pub fn list_in(vfs: &dyn Vfs, git_dir: &Path) -> io::Result<Vec<String>> {
+    match walk_files(vfs, &git_dir.join("logs")) {
+        Ok(files) => Ok(files.iter().map(|file| file.to_string_lossy().replace('\\', "/")).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_append_and_read() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let git_dir = temp_dir.path();
+
+        append(git_dir, "refs/heads/main", None, "a".repeat(40).as_str(), "commit (initial): first")?;
+        append(git_dir, "refs/heads/main", Some(&"a".repeat(40)), ZERO_HASH, "branch: deleted")?;
+
+        let entries = read(git_dir, "refs/heads/main")?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].old, ZERO_HASH);
+        assert_eq!(entries[0].message, "commit (initial): first");
+        assert_eq!(entries[1].new, ZERO_HASH);
+        assert_eq!(list(git_dir)?, vec!["refs/heads/main".to_string()]);
+
+        write(git_dir, "refs/heads/main", &[])?;
+        assert!(read(git_dir, "refs/heads/main")?.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,15 +1,42 @@
 // Repository management 
 
+use std::env;
+use walkdir::WalkDir;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::io;
-use crate::cobra::core::ref_store::RefStore;
+use crate::cobra::core::ref_store::{RefLock, RefStore};
+use crate::cobra::core::case_fold::{self, PathFolding};
+use crate::cobra::core::compression::CompressionSettings;
+use crate::cobra::core::config::{Config, ConfigDocument};
+use crate::cobra::core::diff::Algorithm;
 use crate::cobra::core::index::Index;
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::events::{EventBus, RepositoryEvent, RepositoryObserver};
+use crate::cobra::core::transaction::ObjectTransaction;
+use crate::cobra::utils::fs::{RealFs, VfsHandle};
+use crate::cobra::utils::tempfiles::tmp_dir;
+use crate::cobra::utils::trace::{self, Stream};
+
+/// Length of abbreviated object names unless `core.abbrev` says otherwise
+pub const DEFAULT_ABBREV: usize = 7;
 
 pub struct Repository {
     pub root_path: PathBuf,
     pub git_dir: PathBuf,
     pub index: Index,
+    pub events: EventBus,
+    /// How the work tree's filesystem compares file names
+    pub folding: PathFolding,
+    /// Where refs, the index and objects are read from and written to
+    pub vfs: VfsHandle,
+    /// How new objects are compressed, from core.compression
+    pub compression: CompressionSettings,
+    /// Minimum length of abbreviated object names, from core.abbrev
+    pub abbrev: usize,
+    /// Config values that were ignored while opening, for the caller to report
+    pub warnings: Vec<String>,
 }
 
 impl Repository {
@@ -28,15 +55,23 @@ impl Repository {
             "ref: refs/heads/main\n",
         )?;
 
+        let folding = Repository::detect_folding(&git_dir)?;
+        Repository::record_folding(&git_dir, folding)?;
+        let (compression, warnings) = CompressionSettings::load(&git_dir)?;
         let repo = Repository {
+            compression,
+            abbrev: abbrev_len(&Config::load(&git_dir)?),
+            warnings,
+            vfs: RealFs::handle(&git_dir),
             root_path,
             git_dir,
             index: Index::new(),
+            events: EventBus::new(),
+            folding,
         };
 
         // Initialize refs
-        let ref_store = RefStore::new(repo.git_dir.clone());
-        ref_store.create_initial_refs()?;
+        repo.ref_store().create_initial_refs()?;
         
         // Save empty index
         repo.save_index()?;
@@ -44,6 +79,32 @@ impl Repository {
         Ok(repo)
     }
 
+    /// Copies the contents of a template directory (hooks, info/exclude, ...)
+    /// into the repository directory. Files that already exist, such as HEAD
+    /// and refs written by init, are never overwritten. Permissions, including
+    /// the executable bit on hooks, are preserved.
+    pub fn apply_template(&self, template_dir: &Path) -> io::Result<()> {
+        if !template_dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Templates not found in {}", template_dir.display()),
+            ));
+        }
+
+        for entry in WalkDir::new(template_dir).min_depth(1) {
+            let entry = entry.map_err(io::Error::other)?;
+            let relative = entry.path().strip_prefix(template_dir).map_err(io::Error::other)?;
+            let target = self.git_dir.join(relative);
+
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(&target)?;
+            } else if !target.exists() {
+                fs::copy(entry.path(), &target)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Checks if a repository exists at the given path
     #[allow(dead_code)]
     pub fn exists(path: &str) -> bool {
@@ -51,38 +112,343 @@ impl Repository {
         cobra_dir.exists() && cobra_dir.is_dir()
     }
 
+    /// Opens the repository at `path`. `path` is either a directory containing
+    /// `.cobra` or a bare repository directory. The work tree defaults to `path`
+    /// and can be moved elsewhere with `COBRA_WORK_TREE` (relative to the current
+    /// directory) or `core.worktree` (relative to the repository directory).
     pub fn open(path: &str) -> io::Result<Repository> {
+        Repository::open_with_work_tree(path, env::var_os("COBRA_WORK_TREE").map(PathBuf::from))
+    }
+
+    /// Like `open`, with an explicit work tree taking the place of `COBRA_WORK_TREE`
+    pub fn open_with_work_tree(path: &str, work_tree: Option<PathBuf>) -> io::Result<Repository> {
         let root_path = PathBuf::from(path);
-        let git_dir = root_path.join(".cobra");
+        let git_dir = Repository::find_git_dir(&root_path)?;
 
+        let work_tree = match work_tree {
+            Some(work_tree) => work_tree,
+            None => match Config::load(&git_dir)?.get("core.worktree") {
+                Some(work_tree) => git_dir.join(work_tree),
+                None => return Repository::open_at(git_dir, root_path),
+            },
+        };
+
+        // Resolve "../" so walking the work tree never mistakes it for the metadata directory
+        let work_tree = fs::canonicalize(&work_tree).map_err(|_| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Work tree '{}' does not exist", work_tree.display()),
+        ))?;
+
+        Repository::open_at(fs::canonicalize(&git_dir)?, work_tree)
+    }
+
+    /// The metadata directory of the repository at `path`, either its
+    /// `.cobra` or `path` itself for a bare repository. Nothing in it is
+    /// read, so this works even when the config would stop an open.
+    pub fn find_git_dir(path: &Path) -> io::Result<PathBuf> {
+        if path.join(".cobra").is_dir() {
+            Ok(path.join(".cobra"))
+        } else if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+            Ok(path.to_path_buf())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Not a cobra repository (or any of the parent directories)",
+            ))
+        }
+    }
+
+    /// Opens a repository whose metadata lives in `git_dir` and whose
+    /// working files live in `work_tree`, wherever they are
+    pub fn open_at(git_dir: PathBuf, work_tree: PathBuf) -> io::Result<Repository> {
         if !git_dir.is_dir() {
             return Err(io::Error::new(
                 io::ErrorKind::NotFound,
-                "Not a cobra repository (or any of the parent directories)",
+                format!("Not a cobra repository: {}", git_dir.display()),
+            ));
+        }
+        if !work_tree.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Work tree '{}' does not exist", work_tree.display()),
             ));
         }
 
         // Try to load existing index
-        let index = Index::load(&Repository {
-            root_path: root_path.clone(),
-            git_dir: git_dir.clone(),
-            index: Index::new(),
-        })?;
+        let vfs = RealFs::handle(&git_dir);
+        let index = Index::load_from(&*vfs, &git_dir)?;
 
+        let folding = Repository::detect_folding(&git_dir)?;
+        let (compression, warnings) = CompressionSettings::load(&git_dir)?;
         Ok(Repository {
-            root_path,
+            compression,
+            abbrev: abbrev_len(&Config::load(&git_dir)?),
+            warnings,
+            root_path: work_tree,
             git_dir,
             index,
+            events: EventBus::new(),
+            folding,
+            vfs,
         })
     }
 
+    /// Creates a repository whose storage lives entirely on `vfs`, such as a
+    /// `MemoryFs` in tests. Nothing touches the real disk, so the work tree
+    /// is only a name and file names are compared exactly.
+    pub fn init_on(vfs: VfsHandle, root_path: PathBuf) -> io::Result<Repository> {
+        let git_dir = root_path.join(".cobra");
+        vfs.create_dir_all(&git_dir.join("objects"))?;
+        let repo = Repository {
+            root_path,
+            git_dir,
+            index: Index::new(),
+            events: EventBus::new(),
+            folding: PathFolding::EXACT,
+            vfs,
+            compression: CompressionSettings::default(),
+            abbrev: DEFAULT_ABBREV,
+            warnings: Vec::new(),
+        };
+        repo.ref_store().create_initial_refs()?;
+        repo.save_index()?;
+        Ok(repo)
+    }
+
+    /// Returns the recorded core.ignoreCase and core.precomposeUnicode, or
+    /// probes the filesystem when they are not set. Opening a repository
+    /// never writes to it; only `init` records what was probed.
+    fn detect_folding(git_dir: &Path) -> io::Result<PathFolding> {
+        if let Some(folding) = PathFolding::from_config(&Config::load(git_dir)?) {
+            return Ok(folding);
+        }
+        Ok(case_fold::probe(&tmp_dir(git_dir)).unwrap_or_default())
+    }
+
+    /// Saves `folding` as core.ignoreCase and core.precomposeUnicode
+    fn record_folding(git_dir: &Path, folding: PathFolding) -> io::Result<()> {
+        let path = git_dir.join("config");
+        let mut config = ConfigDocument::load(&path)?;
+        config.set("core.ignoreCase", &folding.ignore_case.to_string())?;
+        config.set("core.precomposeUnicode", &folding.precompose_unicode.to_string())?;
+        config.save(&path)
+    }
+
+    /// Registers an observer that is notified after every state change
+    pub fn subscribe(&self, observer: Box<dyn RepositoryObserver>) {
+        self.events.subscribe(observer);
+    }
+
+    /// Returns a RefStore for this repository that reports to its observers
+    pub fn ref_store(&self) -> RefStore {
+        RefStore::with_vfs(self.git_dir.clone(), self.events.clone(), self.vfs.clone())
+    }
+
+    /// Starts collecting objects and ref updates to be written together
+    pub fn transaction(&self) -> ObjectTransaction {
+        ObjectTransaction::new(self.git_dir.clone(), self.events.clone())
+            .with_vfs(self.vfs.clone())
+            .with_compression(self.compression)
+    }
+
+    /// Writes an object to the object store and returns its id
+    pub fn write_object(&self, object: &Object) -> io::Result<Oid> {
+        let hash = object.hash();
+        if object.write_to_store(&*self.vfs, &self.git_dir, self.compression)? {
+            self.events.emit(RepositoryEvent::ObjectWritten {
+                hash,
+                kind: object.type_str(),
+            });
+        }
+        Ok(hash)
+    }
+
+    /// Reads an object from the object store
+    pub fn read_object(&self, hash: Oid) -> io::Result<Object> {
+        Object::read_from_store(&*self.vfs, &self.git_dir, hash)
+    }
+
+    /// Line diff algorithm from `diff.algorithm`, Myers when it is not set
+    pub fn diff_algorithm(&self) -> io::Result<Algorithm> {
+        match Config::load(&self.git_dir)?.get("diff.algorithm") {
+            Some(name) => Algorithm::parse(name),
+            None => Ok(Algorithm::default()),
+        }
+    }
+
+    /// Abbreviates an object name to `core.abbrev` characters, or more if
+    /// another object in the store shares that prefix
+    pub fn short_id(&self, hash: Oid) -> String {
+        let min_len = self.abbrev;
+        let others: Vec<String> = Object::find_by_prefix_in_store(self.vfs.as_ref(), &self.git_dir, &hash.abbrev(min_len))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&other| other != hash)
+            .map(|other| other.to_string())
+            .collect();
+
+        let hex_len = hash.algorithm().hex_len();
+        let mut len = min_len;
+        while len < hex_len && others.iter().any(|other| other.starts_with(&hash.abbrev(len))) {
+            len += 1;
+        }
+        hash.abbrev(len)
+    }
+
     pub fn add_to_index(&mut self, entry: crate::cobra::core::index::IndexEntry) -> io::Result<()> {
         self.index.add_entry(entry);
         self.save_index()
     }
 
-    fn save_index(&self) -> io::Result<()> {
-        let index_path = Path::new(&self.git_dir).join("index");
-        self.index.write_to_file(&index_path)
+    /// Takes index.lock, which every index write holds. Keep it across a
+    /// load and `save_index_locked` so no other writer slips in between.
+    pub fn lock_index(&self) -> io::Result<RefLock> {
+        RefLock::acquire_on(self.vfs.clone(), &self.git_dir, "index")
+    }
+
+    pub fn save_index(&self) -> io::Result<()> {
+        let lock = self.lock_index()?;
+        self.save_index_locked(lock)
+    }
+
+    /// Writes the index through `lock`, from `lock_index`, and releases it
+    pub fn save_index_locked(&self, lock: RefLock) -> io::Result<()> {
+        if self.index.is_foreign() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The index was written by git and cannot be updated by cobra",
+            ));
+        }
+
+        // A failed write never leaves a truncated index behind
+        lock.commit_raw(&self.index.to_bytes()?)?;
+        trace::event(Stream::Main, || format!("index write: {} entries", self.index.entries().count()));
+        self.events.emit(RepositoryEvent::IndexChanged);
+        Ok(())
     }
-} 
\ No newline at end of file
+}
+
+/// Minimum length of abbreviated object names, from `core.abbrev` ("auto"
+/// for the default, "no" for full names, or a number from 4 to 40)
+fn abbrev_len(config: &Config) -> usize {
+    match config.get("core.abbrev") {
+        Some("no") | Some("false") => 40,
+        Some(value) => value.parse().map_or(DEFAULT_ABBREV, |len: usize| len.clamp(4, 40)),
+        None => DEFAULT_ABBREV,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::core::index::IndexEntry;
+    use crate::cobra::utils::fs::{MemoryFs, VfsOp};
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_short_id_extends_ambiguous_prefix() -> io::Result<()> {
+        let repo = Repository::init_on(MemoryFs::new(), PathBuf::from("/repo"))?;
+        let oid = repo.write_object(&Object::Blob(b"content\n".to_vec()))?;
+        let hash = oid.to_string();
+        assert_eq!(repo.short_id(oid), &hash[..7]);
+
+        // Another object agreeing on the first 9 characters
+        let mut other = hash[..9].to_string();
+        other.push(if hash.as_bytes()[9] == b'0' { '1' } else { '0' });
+        other.push_str(&hash[10..]);
+        let fan_out = repo.git_dir.join("objects").join(&hash[..2]);
+        repo.vfs.write_atomic(&fan_out.join(&other[2..]), &repo.vfs.read(&fan_out.join(&hash[2..]))?)?;
+        assert_eq!(repo.short_id(oid), &hash[..10]);
+        assert_eq!(repo.short_id(other.parse()?), &other[..10]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_id_honors_core_abbrev() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let oid = repo.write_object(&Object::Blob(b"content\n".to_vec()))?;
+        let hash = oid.to_string();
+        let reopen = |config: &str| -> io::Result<Repository> {
+            fs::write(repo.git_dir.join("config"), config)?;
+            Repository::open(temp_dir.path().to_str().unwrap())
+        };
+
+        assert_eq!(reopen("[core]\n\tabbrev = 12\n")?.short_id(oid), &hash[..12]);
+        assert_eq!(reopen("[core]\n\tabbrev = no\n")?.short_id(oid), hash);
+        assert_eq!(reopen("[core]\n\tabbrev = auto\n")?.abbrev, DEFAULT_ABBREV);
+
+        // The setting is read once, when the repository is opened
+        fs::write(repo.git_dir.join("config"), "[core]\n\tabbrev = 12\n")?;
+        assert_eq!(repo.short_id(oid), &hash[..DEFAULT_ABBREV]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_git_dir_ignores_the_config() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        fs::write(repo.git_dir.join("config"), "[core]\n\tcompression = fastest\n")?;
+        assert_eq!(Repository::find_git_dir(temp_dir.path())?, repo.git_dir);
+        assert_eq!(Repository::find_git_dir(&repo.git_dir)?, repo.git_dir);
+        let error = Repository::find_git_dir(&repo.git_dir.join("objects")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_does_not_record_folding() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let recorded = Config::load(&repo.git_dir)?;
+        assert_eq!(PathFolding::from_config(&recorded), Some(repo.folding));
+
+        fs::write(repo.git_dir.join("config"), "")?;
+        let reopened = Repository::open(temp_dir.path().to_str().unwrap())?;
+        assert_eq!(reopened.folding, repo.folding);
+        assert_eq!(fs::read_to_string(repo.git_dir.join("config"))?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_writes_take_the_index_lock() -> io::Result<()> {
+        let mut repo = Repository::init_on(MemoryFs::new(), PathBuf::from("/repo"))?;
+        let lock = repo.lock_index()?;
+        let err = repo.add_to_index(entry("one.txt")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        repo.save_index_locked(lock)?;
+        assert!(Index::load(&repo)?.contains(Path::new("one.txt")));
+        assert!(!repo.vfs.exists(&repo.git_dir.join("index.lock")));
+        repo.save_index()
+    }
+
+    fn entry(name: &str) -> IndexEntry {
+        IndexEntry {
+            ctime: 0, mtime: 0, dev: 0, ino: 0, mode: 0o100644, uid: 0, gid: 0, size: 0,
+            hash: "a".repeat(40).parse().unwrap(),
+            path: PathBuf::from(name),
+        }
+    }
+
+    #[test]
+    fn test_failed_index_write_keeps_previous_index() -> io::Result<()> {
+        let fs = MemoryFs::new();
+        let mut repo = Repository::init_on(fs.clone(), PathBuf::from("/repo"))?;
+        repo.add_to_index(entry("one.txt"))?;
+
+        fs.fail(VfsOp::Write, "/repo/.cobra/index.lock", io::ErrorKind::StorageFull);
+        let err = repo.add_to_index(entry("two.txt")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        let stored = Index::load_from(&*fs, &repo.git_dir)?;
+        assert_eq!(stored.entries().count(), 1);
+
+        fs.clear_faults();
+        fs.set_read_only(true);
+        let err = repo.add_to_index(entry("three.txt")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(Index::load_from(&*fs, &repo.git_dir)?.contains(Path::new("one.txt")));
+
+        fs.set_read_only(false);
+        repo.save_index()?;
+        assert_eq!(Index::load_from(&*fs, &repo.git_dir)?.entries().count(), 3);
+        Ok(())
+    }
+}
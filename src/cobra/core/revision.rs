@@ -0,0 +1,213 @@
+// Revisions and the commit walk over their history
+//
+// Any revision may end in ancestry suffixes: `~<n>` follows first parents n
+// times and `^<n>` takes the nth parent, a bare `~` or `^` meaning 1.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io;
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::ref_store::RefStore;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::core::revparse;
+use crate::cobra::messages;
+
+/// Finds and reads the object `spec` names, of any type (see `revparse`)
+pub fn resolve_object(refs: &RefStore, spec: &str) -> io::Result<(Oid, Object)> {
+    let hash = revparse::resolve_in(refs, spec)?;
+    let object = refs.read_object(hash)?;
+    Ok((hash, object))
+}
+
+/// Resolves a revision with any ancestry suffixes, e.g. `HEAD~2` or
+/// `main^2`. `^0` is the commit itself.
+pub fn resolve_revision(refs: &RefStore, spec: &str) -> io::Result<Oid> {
+    let base_len = spec.find(['~', '^']).unwrap_or(spec.len());
+    let mut commit = refs.resolve_commit(&spec[..base_len])?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid revision '{}'", spec));
+    let mut chars = spec[base_len..].chars().peekable();
+    while let Some(op) = chars.next() {
+        if op != '~' && op != '^' {
+            return Err(invalid());
+        }
+        let mut digits = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            digits.push(digit);
+        }
+        let count = match digits.as_str() {
+            "" => 1,
+            number => number.parse::<usize>().map_err(|_| invalid())?,
+        };
+        let (steps, nth) = if op == '~' { (count, 1) } else { (count.min(1), count) };
+        for _ in 0..steps {
+            let parents = match refs.read_object(commit)? {
+                Object::Commit { parents, .. } => parents,
+                _ => Vec::new(),
+            };
+            commit = parents.get(nth - 1).copied().ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Revision '{}' goes beyond the history it names", spec),
+            ))?;
+        }
+    }
+    Ok(commit)
+}
+
+/// Commits reachable from a set of starting points, each yielded once,
+/// ordered by committer date (newest first, ties in the order they were
+/// reached). Commits in `hidden`, and therefore their history, are skipped.
+/// Every commit is queued at most once, so even a corrupt parent chain that
+/// loops back on itself ends. A commit whose object is missing ends its line
+/// of history without failing the walk; see `missing`.
+pub struct RevWalk<'a> {
+    repo: &'a Repository,
+    queue: BinaryHeap<(u64, Reverse<usize>, Oid)>,
+    commits: HashMap<Oid, Object>,
+    seen: HashSet<Oid>,
+    missing: Vec<Oid>,
+}
+
+impl<'a> RevWalk<'a> {
+    pub fn new(repo: &'a Repository, starts: impl IntoIterator<Item = Oid>, hidden: HashSet<Oid>) -> io::Result<RevWalk<'a>> {
+        let mut walk = RevWalk { repo, queue: BinaryHeap::new(), commits: HashMap::new(), seen: hidden, missing: Vec::new() };
+        for oid in starts {
+            walk.enqueue(oid)?;
+        }
+        Ok(walk)
+    }
+
+    fn enqueue(&mut self, oid: Oid) -> io::Result<()> {
+        if !self.seen.insert(oid) {
+            return Ok(());
+        }
+        let commit = match Object::read_from_objects_dir(&self.repo.git_dir, oid) {
+            Ok(commit) => commit,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.missing.push(oid);
+                return Ok(());
+            }
+            Err(e) => return Err(io::Error::new(e.kind(), messages::corrupt_object(&oid.to_string(), e))),
+        };
+        // Refs such as tags may name trees or blobs, which have no history
+        if let Object::Commit { committer, .. } = &commit {
+            self.queue.push((committer.timestamp, Reverse(self.seen.len()), oid));
+            self.commits.insert(oid, commit);
+        }
+        Ok(())
+    }
+
+    /// The commits reached so far whose objects do not exist, where the
+    /// history is cut short
+    pub fn missing(&self) -> &[Oid] {
+        &self.missing
+    }
+
+    fn next_commit(&mut self) -> io::Result<Option<(Oid, Object)>> {
+        let Some((_, _, oid)) = self.queue.pop() else {
+            return Ok(None);
+        };
+        let commit = self.commits.remove(&oid).expect("queued commits are loaded");
+        if let Object::Commit { parents, .. } = &commit {
+            for &parent in parents {
+                self.enqueue(parent)?;
+            }
+        }
+        Ok(Some((oid, commit)))
+    }
+}
+
+/// Reorders walked commits so that every commit comes before its parents.
+/// Like git's --topo-order, a line of history is kept in one piece: once
+/// a commit is out, the last of its parents with no children left comes
+/// next. Commits caught in a parent cycle keep their walk order at the end.
+pub fn topo_sort(commits: Vec<(Oid, Object)>) -> Vec<(Oid, Object)> {
+    let position: HashMap<Oid, usize> = commits.iter()
+        .enumerate()
+        .map(|(i, (hash, _))| (*hash, i))
+        .collect();
+    let parents: Vec<Vec<usize>> = commits.iter()
+        .map(|(_, commit)| match commit {
+            Object::Commit { parents, .. } => parents.iter().filter_map(|parent| position.get(parent).copied()).collect(),
+            _ => Vec::new(),
+        })
+        .collect();
+
+    let mut children = vec![0usize; commits.len()];
+    for &parent in parents.iter().flatten() {
+        children[parent] += 1;
+    }
+    let mut ready: Vec<usize> = (0..commits.len()).rev().filter(|&i| children[i] == 0).collect();
+    let mut order = Vec::with_capacity(commits.len());
+    let mut placed = vec![false; commits.len()];
+    while let Some(i) = ready.pop() {
+        order.push(i);
+        placed[i] = true;
+        for &parent in &parents[i] {
+            children[parent] -= 1;
+            if children[parent] == 0 {
+                ready.push(parent);
+            }
+        }
+    }
+    order.extend((0..commits.len()).filter(|&i| !placed[i]));
+
+    let mut commits: Vec<Option<(Oid, Object)>> = commits.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| commits[i].take()).collect()
+}
+
+impl Iterator for RevWalk<'_> {
+    type Item = io::Result<(Oid, Object)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_commit().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::test_support::{commit_at, forked_history, temp_repo};
+
+    #[test]
+    fn test_topo_sort_keeps_branches_together() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        // The side branch is older than main's last commit, and f1 claims to
+        // be older than its parent
+        let base = commit_at(&repo, &[], "base", 1000)?;
+        let f1 = commit_at(&repo, &[base], "f1", 500)?;
+        let f2 = commit_at(&repo, &[f1], "f2", 1500)?;
+        let m1 = commit_at(&repo, &[base], "m1", 3000)?;
+        let merge = commit_at(&repo, &[m1, f2], "merge", 4000)?;
+
+        let walked: Vec<(Oid, Object)> = RevWalk::new(&repo, [merge], HashSet::new())?
+            .collect::<io::Result<_>>()?;
+        let by_date: Vec<Oid> = walked.iter().map(|(hash, _)| *hash).collect();
+        assert_eq!(by_date, [merge, m1, f2, base, f1]);
+
+        let sorted: Vec<Oid> = topo_sort(walked).into_iter().map(|(hash, _)| hash).collect();
+        assert_eq!(sorted, [merge, f2, f1, m1, base]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ancestry_suffixes() -> io::Result<()> {
+        let (_temp_dir, repo, [base, m1, f1, f2]) = forked_history()?;
+        let merge = commit_at(&repo, &[m1, f2], "merge", 5000)?;
+        let refs = repo.ref_store();
+        refs.update_ref("refs/heads/main", merge, "test")?;
+
+        for (spec, expected) in [
+            ("feature~", &f1), ("feature~2", &base), ("feature^^", &base), ("feature~1^1", &base),
+            ("main^2", &f2), ("main^2~1", &f1), ("main^0", &merge), ("HEAD~1", &m1),
+        ] {
+            assert_eq!(&resolve_revision(&refs, spec)?, expected, "{}", spec);
+        }
+        assert_eq!(resolve_revision(&refs, "feature~3").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(resolve_revision(&refs, "main^3").unwrap_err().kind(), io::ErrorKind::NotFound);
+        for spec in ["main~é", "main~1x", "main^2.", "main~99999999999999999999999"] {
+            assert_eq!(resolve_revision(&refs, spec).unwrap_err().kind(), io::ErrorKind::InvalidInput, "{}", spec);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+// Turning a revision argument into an object name
+//
+// A spec is tried as HEAD or a pseudo-ref, a full ref name, a branch, a
+// tag, a remote-tracking branch and finally an object name, which may be
+// abbreviated to as few as MIN_PREFIX_LEN digits. Ancestry suffixes
+// (`~<n>`, `^<n>`) walk from the commit the rest names. Unlike
+// RefStore::resolve_commit, the result may be any kind of object.
+
+use std::io;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::ref_store::RefStore;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::core::revision::resolve_revision;
+
+/// Resolves `spec` to the full name of the object it refers to
+pub fn resolve(repo: &Repository, spec: &str) -> io::Result<Oid> {
+    resolve_in(&repo.ref_store(), spec)
+}
+
+/// `resolve` against a ref store
+pub fn resolve_in(refs: &RefStore, spec: &str) -> io::Result<Oid> {
+    if spec.contains(['~', '^']) {
+        return resolve_revision(refs, spec);
+    }
+    if spec == "HEAD" || (spec.ends_with("_HEAD") && spec.chars().all(|c| c.is_ascii_uppercase() || c == '_')) {
+        return refs.resolve_commit(spec);
+    }
+
+    let full_name = spec.starts_with("refs/").then(|| spec.to_string());
+    let names = full_name.into_iter()
+        .chain(["refs/heads/", "refs/tags/", "refs/remotes/"].map(|namespace| format!("{}{}", namespace, spec)));
+    for name in names {
+        if let Some(hash) = refs.read_ref(&name)? {
+            return Ok(hash);
+        }
+    }
+
+    if !spec.is_empty() && spec.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return refs.expand_prefix(spec);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("unknown revision '{}'", spec),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::core::object::Object;
+    use crate::cobra::core::ref_store::MIN_PREFIX_LEN;
+    use crate::cobra::utils::fs::MemoryFs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_resolve_refs_and_abbreviations() -> io::Result<()> {
+        let repo = Repository::init_on(MemoryFs::new(), PathBuf::from("/repo"))?;
+        let blob = repo.write_object(&Object::new_blob(b"content\n".to_vec()))?;
+        let refs = repo.ref_store();
+        refs.update_ref("refs/heads/main", blob, "test")?;
+        refs.update_ref("refs/tags/v1", blob, "test")?;
+
+        assert_eq!(resolve(&repo, "main")?, blob);
+        assert_eq!(resolve(&repo, "refs/tags/v1")?, blob);
+        assert_eq!(resolve(&repo, "v1")?, blob);
+        assert_eq!(resolve(&repo, &blob.to_string())?, blob);
+        assert_eq!(resolve(&repo, &blob.abbrev(MIN_PREFIX_LEN))?, blob);
+        assert_eq!(resolve(&repo, &blob.abbrev(10).to_uppercase())?, blob);
+
+        let error = resolve(&repo, &blob.abbrev(3)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("too short"), "{}", error);
+        assert_eq!(resolve(&repo, "nothing").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(resolve(&repo, "0000000").unwrap_err().kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ambiguous_prefix_lists_candidates() -> io::Result<()> {
+        let repo = Repository::init_on(MemoryFs::new(), PathBuf::from("/repo"))?;
+        let id = repo.write_object(&Object::new_blob(b"content\n".to_vec()))?;
+        let hash = id.to_string();
+
+        // A second object sharing the first six digits
+        let mut other = hash[..6].to_string();
+        other.push(if hash.as_bytes()[6] == b'0' { '1' } else { '0' });
+        other.push_str(&hash[7..]);
+        let fan_out = repo.git_dir.join("objects").join(&hash[..2]);
+        repo.vfs.write_atomic(&fan_out.join(&other[2..]), &repo.vfs.read(&fan_out.join(&hash[2..]))?)?;
+
+        let error = resolve(&repo, &hash[..6]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let message = error.to_string();
+        assert!(message.contains("is ambiguous"), "{}", message);
+        assert!(message.contains(&format!("\n  {} blob", hash)), "{}", message);
+        assert!(message.contains(&format!("\n  {} ", other)), "{}", message);
+        // One more digit settles it
+        assert_eq!(resolve(&repo, &hash[..7])?, id);
+        Ok(())
+    }
+}
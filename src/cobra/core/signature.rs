@@ -1,5 +1,33 @@
+use std::env;
 use std::io;
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
+use crate::cobra::core::config::Config;
+use crate::cobra::messages;
+
+/// Which identity of a commit is being looked up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    /// Prefix of the environment variables overriding this identity
+    fn env_prefix(self) -> &'static str {
+        match self {
+            Role::Author => "COBRA_AUTHOR",
+            Role::Committer => "COBRA_COMMITTER",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Role::Author => "Author",
+            Role::Committer => "Committer",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Signature {
@@ -24,6 +52,67 @@ impl Signature {
         }
     }
 
+    /// The author of new commits in the repository at `git_dir`
+    pub fn author(git_dir: &Path) -> io::Result<Signature> {
+        Signature::default_from_config(git_dir, Role::Author)
+    }
+
+    /// The committer of new commits and reflog entries in the repository
+    /// at `git_dir`
+    pub fn committer(git_dir: &Path) -> io::Result<Signature> {
+        Signature::default_from_config(git_dir, Role::Committer)
+    }
+
+    /// The identity for `role`: user.name and user.email from the
+    /// configuration. COBRA_AUTHOR_NAME and
+    /// COBRA_AUTHOR_EMAIL (COBRA_COMMITTER_* for the committer) override
+    /// them, and COBRA_AUTHOR_DATE the current time. Fails with git's
+    /// "Please tell me who you are" guidance when no name or email is set.
+    pub fn default_from_config(git_dir: &Path, role: Role) -> io::Result<Signature> {
+        let config = Config::load(git_dir)?;
+        Signature::resolve(role, |name| env::var(name).ok(), |key| config.get(key).map(str::to_string))
+    }
+
+    /// Builds the identity for `role` from environment variables and
+    /// config values, each looked up by name
+    fn resolve(
+        role: Role,
+        env_var: impl Fn(&str) -> Option<String>,
+        configured: impl Fn(&str) -> Option<String>,
+    ) -> io::Result<Signature> {
+        let var = |suffix: &str| env_var(&format!("{}_{}", role.env_prefix(), suffix)).filter(|value| !value.is_empty());
+        let configured = |key: &str| configured(key).filter(|value| !value.trim().is_empty());
+        let (Some(name), Some(email)) = (
+            var("NAME").or_else(|| configured("user.name")),
+            var("EMAIL").or_else(|| configured("user.email")),
+        ) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, messages::identity_unknown(role.label())));
+        };
+        let mut signature = Signature::new(name, email);
+        if let Some(date) = var("DATE") {
+            let (timestamp, timezone) = Signature::parse_date(&date)?;
+            signature.timestamp = timestamp;
+            signature.timezone = timezone;
+        }
+        Ok(signature)
+    }
+
+    /// Parses a date in git's internal format, `<seconds> <+HHMM>`, with an
+    /// optional leading `@`. Without a timezone the date is taken as UTC.
+    pub fn parse_date(value: &str) -> io::Result<(u64, String)> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid date '{}'", value));
+        let mut parts = value.trim().trim_start_matches('@').split_whitespace();
+        let timestamp = parts.next().and_then(|seconds| seconds.parse().ok()).ok_or_else(invalid)?;
+        let timezone = parts.next().unwrap_or("+0000");
+        let valid_timezone = timezone.len() == 5
+            && matches!(timezone.as_bytes()[0], b'+' | b'-')
+            && timezone[1..].bytes().all(|b| b.is_ascii_digit());
+        if !valid_timezone || parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok((timestamp, timezone.to_string()))
+    }
+
     pub fn parse(input: &str) -> io::Result<Signature> {
         // Format: "Name <email> timestamp timezone"
         let mut parts = input.rsplitn(3, ' ');
@@ -60,9 +149,75 @@ impl Signature {
         })
     }
 
+    /// Applies git's identity cleanup: drops `<`, `>` and newlines anywhere,
+    /// and trims punctuation and whitespace from both ends of name and email
+    pub fn sanitized(&self) -> Signature {
+        fn clean(value: &str) -> String {
+            let kept: String = value.chars()
+                .filter(|c| !matches!(c, '<' | '>' | '\n' | '\r' | '\0'))
+                .collect();
+            kept.trim_matches(|c: char| c.is_whitespace() || ".,:;\"'\\".contains(c))
+                .to_string()
+        }
+
+        Signature {
+            name: clean(&self.name),
+            email: clean(&self.email),
+            timestamp: self.timestamp,
+            timezone: self.timezone.clone(),
+        }
+    }
+
+    /// Returns why this signature cannot be written into an object header, if it can't
+    pub fn check(&self) -> Result<(), &'static str> {
+        let forbidden = |value: &str| value.contains(['<', '>', '\n', '\r', '\0']);
+        if self.name.trim().is_empty() {
+            return Err("empty ident name not allowed");
+        }
+        if forbidden(&self.name) {
+            return Err("name contains '<', '>' or a line break");
+        }
+        if forbidden(&self.email) {
+            return Err("email contains '<', '>' or a line break");
+        }
+        let timezone = self.timezone.as_bytes();
+        if timezone.len() != 5
+            || !matches!(timezone[0], b'+' | b'-')
+            || !timezone[1..].iter().all(u8::is_ascii_digit)
+        {
+            return Err("timezone must look like +HHMM");
+        }
+        Ok(())
+    }
+
     pub fn format(&self) -> String {
         format!("{} <{}> {} {}", self.name, self.email, self.timestamp, self.timezone)
     }
+
+    /// The date in the signature's own timezone as YYYY-MM-DD
+    pub fn short_date(&self) -> String {
+        // A malformed timezone is treated as UTC
+        let offset = match (self.timezone.get(..1), self.timezone.get(1..3), self.timezone.get(3..5)) {
+            (Some(sign), Some(hours), Some(minutes)) if self.timezone.len() == 5 => {
+                let seconds = hours.parse::<i64>().unwrap_or(0) * 3600 + minutes.parse::<i64>().unwrap_or(0) * 60;
+                if sign == "-" { -seconds } else { seconds }
+            }
+            _ => 0,
+        };
+        let days = (self.timestamp as i64 + offset).div_euclid(86400);
+
+        // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
 }
 
 #[cfg(test)]
@@ -89,4 +244,54 @@ mod tests {
         };
         assert_eq!(sig.format(), "John Doe <john@example.com> 1234567890 +0000");
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(Signature::parse_date("1234567890 +0100").unwrap(), (1234567890, "+0100".to_string()));
+        assert_eq!(Signature::parse_date("@1234567890").unwrap(), (1234567890, "+0000".to_string()));
+        assert!(Signature::parse_date("yesterday").is_err());
+        assert!(Signature::parse_date("1234567890 CET").is_err());
+    }
+
+    #[test]
+    fn test_signature_short_date() {
+        let mut sig = Signature::parse("John Doe <john@example.com> 1234567890 +0000").unwrap();
+        assert_eq!(sig.short_date(), "2009-02-13");
+        // 23:31:30 UTC is already the next day in UTC+01:00
+        sig.timezone = "+0100".to_string();
+        assert_eq!(sig.short_date(), "2009-02-14");
+        sig.timestamp = 951782400;
+        sig.timezone = "+0000".to_string();
+        assert_eq!(sig.short_date(), "2000-02-29");
+    }
+
+    #[test]
+    fn test_identity_precedence() -> io::Result<()> {
+        let env = |vars: &'static [(&'static str, &'static str)]| move |name: &str| {
+            vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+        };
+        let config = |key: &str| match key {
+            "user.name" => Some("Configured".to_string()),
+            "user.email" => Some("configured@example.com".to_string()),
+            _ => None,
+        };
+
+        let author = Signature::resolve(Role::Author, env(&[]), config)?;
+        assert_eq!((author.name.as_str(), author.email.as_str()), ("Configured", "configured@example.com"));
+
+        // The environment wins field by field, and only for its own role
+        let vars = &[("COBRA_AUTHOR_NAME", "From Env"), ("COBRA_COMMITTER_EMAIL", "committer@example.com")];
+        let author = Signature::resolve(Role::Author, env(vars), config)?;
+        assert_eq!((author.name.as_str(), author.email.as_str()), ("From Env", "configured@example.com"));
+        let committer = Signature::resolve(Role::Committer, env(vars), config)?;
+        assert_eq!((committer.name.as_str(), committer.email.as_str()), ("Configured", "committer@example.com"));
+
+        // The environment alone is enough; nothing at all is an error
+        let vars = &[("COBRA_AUTHOR_NAME", "Env"), ("COBRA_AUTHOR_EMAIL", "env@example.com"), ("COBRA_AUTHOR_DATE", "100 +0200")];
+        let author = Signature::resolve(Role::Author, env(vars), |_| None)?;
+        assert_eq!(author.format(), "Env <env@example.com> 100 +0200");
+        let error = Signature::resolve(Role::Committer, env(&[("COBRA_COMMITTER_NAME", "Env")]), |_| None).unwrap_err();
+        assert!(error.to_string().starts_with("Committer identity unknown\n\n*** Please tell me who you are."), "{}", error);
+        Ok(())
+    }
+}
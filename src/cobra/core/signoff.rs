@@ -0,0 +1,141 @@
+// Signed-off-by trailers and the commit.requireSignoff policy
+//
+// A sign-off is a "Signed-off-by: Name <email>" line in the trailer block,
+// the message's last paragraph. Projects that follow the Developer
+// Certificate of Origin set commit.requireSignoff so that every commit
+// cobra creates must carry one from its committer. ObjectTransaction::add
+// runs the check on every commit it stages, before anything is written.
+
+use std::io;
+use std::path::Path;
+use crate::cobra::core::config::Config;
+use crate::cobra::core::signature::Signature;
+
+const TRAILER_KEY: &str = "Signed-off-by";
+
+/// The sign-off line for `signature`
+pub fn trailer(signature: &Signature) -> String {
+    format!("{}: {} <{}>", TRAILER_KEY, signature.name, signature.email)
+}
+
+/// The lines of the message's trailer block: its last paragraph, when
+/// every line in it looks like "Key: value"
+fn trailer_lines(message: &str) -> Vec<&str> {
+    let body = message.trim_end();
+    let Some(last_paragraph) = body.rsplit("\n\n").next().filter(|_| body.contains("\n\n")) else {
+        return Vec::new();
+    };
+    let lines: Vec<&str> = last_paragraph.lines().collect();
+    let is_trailer = |line: &&str| line.split_once(": ")
+        .is_some_and(|(key, _)| !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+    if lines.iter().all(is_trailer) { lines } else { Vec::new() }
+}
+
+/// The identities that signed off on the message
+pub fn signoffs(message: &str) -> Vec<&str> {
+    trailer_lines(message).into_iter()
+        .filter_map(|line| line.strip_prefix(TRAILER_KEY)?.strip_prefix(": "))
+        .collect()
+}
+
+/// Appends `signature`'s sign-off to the trailer block, starting one if
+/// there is none. A message that already ends with it is left alone.
+pub fn add_signoff(message: &str, signature: &Signature) -> String {
+    let line = trailer(signature);
+    let body = message.trim_end();
+    if trailer_lines(body).last() == Some(&line.as_str()) {
+        return format!("{}\n", body);
+    }
+    let separator = if trailer_lines(body).is_empty() { "\n\n" } else { "\n" };
+    format!("{}{}{}\n", body, separator, line)
+}
+
+/// True when the config of the repository at `git_dir` sets commit.requireSignoff
+pub fn is_required(git_dir: &Path) -> io::Result<bool> {
+    let config = Config::load(git_dir)?;
+    Ok(config.get_bool("commit.requireSignoff") == Some(true))
+}
+
+/// Fails when commit.requireSignoff is set and `message` has no sign-off
+/// from `committer`
+pub fn check(git_dir: &Path, message: &str, committer: &Signature) -> io::Result<()> {
+    if !is_required(git_dir)? {
+        return Ok(());
+    }
+    let expected = format!("{} <{}>", committer.name, committer.email);
+    let found = signoffs(message);
+    if found.contains(&expected.as_str()) {
+        return Ok(());
+    }
+    let reason = match found.as_slice() {
+        [] => "the message has no Signed-off-by trailer".to_string(),
+        others => format!("it is signed off by {}, not by the committer", others.join(", ")),
+    };
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "commit.requireSignoff is set, but {}.\n\
+             Add '{}: {}' to the message, or commit with -s/--signoff to add it.",
+            reason, TRAILER_KEY, expected,
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use crate::cobra::commands::add::add_file;
+    use crate::cobra::commands::commit::{commit_with_options, CommitOptions};
+    use crate::cobra::core::object::Object;
+    use crate::cobra::test_support::temp_repo;
+
+    fn someone(name: &str) -> Signature {
+        Signature::new(name.to_string(), format!("{}@example.com", name.to_lowercase()))
+    }
+
+    #[test]
+    fn test_add_signoff_extends_the_trailer_block() {
+        let alice = someone("Alice");
+        assert_eq!(add_signoff("Fix it", &alice), "Fix it\n\nSigned-off-by: Alice <alice@example.com>\n");
+
+        let reviewed = "Fix it\n\nReviewed-by: Bob <bob@example.com>\n";
+        let signed = add_signoff(reviewed, &alice);
+        assert_eq!(signed, "Fix it\n\nReviewed-by: Bob <bob@example.com>\nSigned-off-by: Alice <alice@example.com>\n");
+        assert_eq!(add_signoff(&signed, &alice), signed);
+        assert_eq!(signoffs(&signed), vec!["Alice <alice@example.com>"]);
+
+        // A closing paragraph of prose is not a trailer block
+        assert!(signoffs("Fix it\n\nSigned-off-by: Alice <alice@example.com> and more\nprose").is_empty());
+    }
+
+    #[test]
+    fn test_required_signoff_is_enforced_before_writing() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        fs::write(temp_dir.path().join("a.txt"), "a\n")?;
+        add_file(&mut repo, Path::new("a.txt"))?;
+
+        let config = repo.git_dir.join("config");
+        let mut text = fs::read_to_string(&config)?;
+        text.push_str("[commit]\n\trequireSignoff = true\n");
+        fs::write(&config, text)?;
+
+        let error = commit_with_options(&repo, "Unsigned", &CommitOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(error.to_string().contains("--signoff"), "{}", error);
+        assert!(repo.ref_store().resolve_commit("HEAD").is_err());
+
+        let stranger = format!("Signed by someone else\n\n{}\n", trailer(&someone("Mallory")));
+        let error = commit_with_options(&repo, &stranger, &CommitOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("signed off by Mallory <mallory@example.com>, not by the committer"), "{}", error);
+
+        let options = CommitOptions { signoff: true, ..Default::default() };
+        let hash = commit_with_options(&repo, "Signed", &options)?;
+        let Object::Commit { message, committer, .. } = repo.read_object(hash)? else {
+            panic!("not a commit");
+        };
+        assert_eq!(message, format!("Signed\n\n{}\n", trailer(&committer)));
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+// How much two versions of a file have in common
+//
+// Rename and copy detection need a cheap score rather than a full diff. The
+// score counts the lines the two versions share (as multisets, so moving
+// lines around inside the file does not lower it) relative to their
+// combined size, giving 100 for identical content and 0 for nothing shared.
+
+use std::collections::HashMap;
+use crate::cobra::core::diff::split_lines;
+
+/// Score from which a deleted and an added file are taken to be one file
+/// that was renamed, as with git's default `-M50%`
+pub const RENAME_THRESHOLD: usize = 50;
+
+/// Percentage of lines the two contents share
+pub fn similarity(a: &[u8], b: &[u8]) -> usize {
+    let a_lines = split_lines(a);
+    let b_lines = split_lines(b);
+    if a_lines.is_empty() && b_lines.is_empty() {
+        return 100;
+    }
+
+    let mut counts: HashMap<&[u8], usize> = HashMap::new();
+    for line in &a_lines {
+        *counts.entry(line).or_default() += 1;
+    }
+    let mut shared = 0;
+    for line in &b_lines {
+        if let Some(count) = counts.get_mut(line).filter(|count| **count > 0) {
+            *count -= 1;
+            shared += 1;
+        }
+    }
+    200 * shared / (a_lines.len() + b_lines.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity(b"a\nb\nc\nd\n", b"a\nb\nc\nd\n"), 100);
+        assert_eq!(similarity(b"a\nb\nc\nd\n", b"d\nc\nb\na\n"), 100);
+        assert_eq!(similarity(b"a\nb\nc\nd\n", b"a\nb\nx\ny\n"), 50);
+        assert_eq!(similarity(b"a\na\n", b"a\n"), 66);
+        assert_eq!(similarity(b"a\n", b"b\n"), 0);
+        assert_eq!(similarity(b"", b""), 100);
+    }
+}
@@ -0,0 +1,464 @@
+// Writing related objects and the refs that point at them as one unit
+//
+// Commands such as commit and merge create several objects and then move a
+// ref. An ObjectTransaction collects the objects first and writes nothing
+// until `commit`, which makes every object durable (written through a temp
+// file, fsynced and renamed into place) before any ref moves. Ref updates
+// are compare-and-swap, so a ref that changed in the meantime fails the
+// transaction instead of losing someone else's work. A failure before the
+// refs leaves at most unreferenced objects behind, which gc prunes. Objects
+// and refs alike go through the repository's Vfs.
+
+use std::collections::{BTreeSet, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::cobra::core::compression::CompressionSettings;
+use crate::cobra::core::events::{EventBus, RepositoryEvent};
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::pack::RawObject;
+use crate::cobra::core::ref_store::{RefStore, RefUpdate};
+use crate::cobra::core::signoff;
+use crate::cobra::utils::fs::{RealFs, VfsHandle};
+use crate::cobra::utils::tempfiles::{tmp_dir, unique_name};
+use crate::cobra::utils::trace::{self, Counter, Stream};
+
+/// Blobs larger than this are compressed into a staging file when they are
+/// added instead of being held in memory until the commit
+const LARGE_OBJECT_SIZE: usize = 1 << 20;
+
+/// An object waiting to be written
+enum Staged {
+    Memory(Object),
+    /// Already compressed into a file under .cobra/tmp
+    File { kind: &'static str, file: StagedFile },
+}
+
+/// A compressed object under .cobra/tmp, removed when dropped unless it
+/// was moved into the object store
+struct StagedFile {
+    vfs: VfsHandle,
+    path: PathBuf,
+    done: bool,
+}
+
+impl StagedFile {
+    fn create(vfs: &VfsHandle, git_dir: &Path, content: &[u8]) -> io::Result<StagedFile> {
+        let dir = tmp_dir(git_dir);
+        vfs.create_dir_all(&dir)?;
+        let path = dir.join(unique_name("object"));
+        vfs.write_atomic(&path, content)?;
+        Ok(StagedFile { vfs: vfs.clone(), path, done: false })
+    }
+
+    /// Flushes the file to the disk and renames it to `destination`
+    fn persist(mut self, destination: &Path) -> io::Result<()> {
+        self.vfs.sync(&self.path)?;
+        self.vfs.rename(&self.path, destination)?;
+        self.done = true;
+        Ok(())
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.vfs.remove(&self.path);
+        }
+    }
+}
+
+/// Objects and ref updates that are written together by `commit`. Dropping
+/// the transaction without committing writes nothing.
+pub struct ObjectTransaction {
+    git_dir: PathBuf,
+    events: EventBus,
+    vfs: VfsHandle,
+    objects: Vec<(Oid, Staged)>,
+    hashes: HashSet<Oid>,
+    refs: Vec<RefUpdate>,
+    compression: CompressionSettings,
+    validate: bool,
+    signoff_policy: bool,
+}
+
+impl ObjectTransaction {
+    pub fn new(git_dir: PathBuf, events: EventBus) -> ObjectTransaction {
+        ObjectTransaction {
+            vfs: RealFs::handle(&git_dir),
+            git_dir,
+            events,
+            objects: Vec::new(),
+            hashes: HashSet::new(),
+            refs: Vec::new(),
+            compression: CompressionSettings::default(),
+            validate: true,
+            signoff_policy: true,
+        }
+    }
+
+    /// Writes objects and refs through `vfs` instead of the real filesystem
+    pub fn with_vfs(mut self, vfs: VfsHandle) -> ObjectTransaction {
+        self.vfs = vfs;
+        self
+    }
+
+    /// Compresses objects as `settings` says instead of at zlib's default
+    pub fn with_compression(mut self, settings: CompressionSettings) -> ObjectTransaction {
+        self.compression = settings;
+        self
+    }
+
+    /// Whether `add` checks commits with `Object::validate`, which it does
+    /// unless told otherwise (commit --no-validate)
+    pub fn with_validation(mut self, validate: bool) -> ObjectTransaction {
+        self.validate = validate;
+        self
+    }
+
+    /// Whether `add` holds commits to commit.requireSignoff. Stashes and
+    /// other bookkeeping commits that never become part of a branch turn
+    /// it off.
+    pub fn with_signoff_policy(mut self, enforce: bool) -> ObjectTransaction {
+        self.signoff_policy = enforce;
+        self
+    }
+
+    /// Stages an object and returns its hash. A commit whose tree names an
+    /// object that exists but is not a tree is refused, and so is one with
+    /// malformed headers unless validation is off, or one commit.requireSignoff
+    /// rejects. Every command that creates commits goes through here, so none
+    /// can skip the checks.
+    pub fn add(&mut self, object: Object) -> io::Result<Oid> {
+        if let Object::Commit { tree, committer, message, .. } = &object {
+            self.check_tree(*tree)?;
+            if self.validate {
+                object.validate()?;
+            }
+            if self.signoff_policy {
+                signoff::check(&self.git_dir, message, committer)?;
+            }
+        }
+        let hash = object.hash();
+        if !self.hashes.insert(hash) {
+            return Ok(hash);
+        }
+        let staged = match object {
+            Object::Blob(ref content) if content.len() > LARGE_OBJECT_SIZE => {
+                let mut compressed = Vec::new();
+                object.write_loose_to(&mut compressed, self.compression)?;
+                Staged::File { kind: object.type_str(), file: StagedFile::create(&self.vfs, &self.git_dir, &compressed)? }
+            }
+            object => Staged::Memory(object),
+        };
+        self.objects.push((hash, staged));
+        Ok(hash)
+    }
+
+    /// Fails if `tree` is staged or stored as anything other than a tree.
+    /// An object that is not available locally is not checked.
+    fn check_tree(&self, tree: Oid) -> io::Result<()> {
+        let kind = match self.objects.iter().find(|(hash, _)| *hash == tree) {
+            Some((_, Staged::Memory(object))) => object.type_str(),
+            Some((_, Staged::File { kind, .. })) => kind,
+            None => match Object::read_from_store(&*self.vfs, &self.git_dir, tree) {
+                Ok(object) => object.type_str(),
+                Err(_) => return Ok(()),
+            },
+        };
+        if kind != "tree" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Commit tree {} is a {}, not a tree", tree, kind),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Stages an object exactly as received from another repository
+    pub fn add_raw(&mut self, object: &RawObject) -> io::Result<Oid> {
+        let hash = object.hash();
+        if !self.hashes.insert(hash) {
+            return Ok(hash);
+        }
+        let mut compressed = Vec::new();
+        object.write_loose_to(&mut compressed, self.compression)?;
+        let file = StagedFile::create(&self.vfs, &self.git_dir, &compressed)?;
+        self.objects.push((hash, Staged::File { kind: object.kind, file }));
+        Ok(hash)
+    }
+
+    /// Number of objects staged so far
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// The repository the objects are written into
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// Moves `name` to `new` on commit, provided it still holds `expected`
+    /// (None for a ref that does not point anywhere yet). `reason` goes
+    /// into the reflog.
+    pub fn update_ref(&mut self, name: &str, expected: Option<Oid>, new: Oid, reason: &str) {
+        self.refs.push(RefUpdate {
+            name: name.to_string(),
+            expected,
+            new,
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Moves the checked out branch, or HEAD itself when it is detached
+    pub fn update_head(&mut self, expected: Option<Oid>, new: Oid, reason: &str) -> io::Result<()> {
+        let head = self.ref_store().read_head()?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HEAD reference not found"))?;
+        match head.strip_prefix("ref: ") {
+            Some(branch_ref) => self.update_ref(branch_ref.trim(), expected, new, reason),
+            None => self.update_ref("HEAD", expected, new, reason),
+        }
+        Ok(())
+    }
+
+    fn ref_store(&self) -> RefStore {
+        RefStore::with_vfs(self.git_dir.clone(), self.events.clone(), self.vfs.clone())
+    }
+
+    /// Writes every staged object, then applies the ref updates
+    pub fn commit(self) -> io::Result<()> {
+        let ref_store = self.ref_store();
+        let ObjectTransaction { git_dir, events, vfs, objects, refs, compression, .. } = self;
+        let written = write_objects(&vfs, &git_dir, &events, objects, compression)?;
+
+        ref_store.update_refs(&refs)?;
+        events.emit(RepositoryEvent::TransactionCommitted {
+            objects: written,
+            refs: refs.into_iter().map(|update| update.name).collect(),
+        });
+        Ok(())
+    }
+}
+
+/// Stores the objects as fsynced loose files and returns the hashes that
+/// were new. This is the one place to switch to writing a small pack.
+fn write_objects(
+    vfs: &VfsHandle,
+    git_dir: &Path,
+    events: &EventBus,
+    objects: Vec<(Oid, Staged)>,
+    compression: CompressionSettings,
+) -> io::Result<Vec<Oid>> {
+    let mut written = Vec::new();
+    let mut dirs = BTreeSet::new();
+    for (hash, staged) in objects {
+        let path = hash.loose_path(git_dir);
+        if vfs.exists(&path) {
+            // Bump the mtime so gc treats the object as recently used
+            vfs.touch(&path)?;
+            continue;
+        }
+
+        let (kind, file) = match staged {
+            Staged::File { kind, file } => (kind, file),
+            Staged::Memory(object) => {
+                let mut compressed = Vec::new();
+                object.write_loose_to(&mut compressed, compression)?;
+                (object.type_str(), StagedFile::create(vfs, git_dir, &compressed)?)
+            }
+        };
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        vfs.create_dir_all(&dir)?;
+        file.persist(&path)?;
+        dirs.insert(dir);
+
+        trace::increment(Counter::ObjectsWritten);
+        trace::event(Stream::Perf, || format!("transaction object {} {}", kind, hash));
+        events.emit(RepositoryEvent::ObjectWritten { hash, kind });
+        written.push(hash);
+    }
+
+    // The renames themselves must reach the disk before a ref names the objects
+    for dir in dirs {
+        vfs.sync(&dir)?;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::core::signature::Signature;
+    use crate::cobra::core::ref_store::RefLock;
+    use crate::cobra::core::repository::Repository;
+    use crate::cobra::utils::fs::{MemoryFs, Vfs, VfsOp};
+    use crate::cobra::test_support::temp_repo;
+    use tempfile::TempDir;
+
+    fn repo_with_commit() -> io::Result<(TempDir, Repository, Oid)> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        fs::write(temp_dir.path().join("file.txt"), "one\n")?;
+        add_file(&mut repo, Path::new("file.txt"))?;
+        let first = commit(&repo, "First")?;
+        Ok((temp_dir, repo, first))
+    }
+
+    fn staging_files(repo: &Repository) -> io::Result<usize> {
+        match fs::read_dir(tmp_dir(&repo.git_dir)) {
+            Ok(entries) => Ok(entries.count()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    #[test]
+    fn test_failure_after_objects_moves_no_refs() -> io::Result<()> {
+        let (temp_dir, mut repo, first) = repo_with_commit()?;
+        fs::write(temp_dir.path().join("file.txt"), "two\n")?;
+        add_file(&mut repo, Path::new("file.txt"))?;
+
+        // Another process holds the branch, so the commit fails once its objects are written
+        let lock = RefLock::acquire(&repo.git_dir, "refs/heads/main")?;
+        let error = commit(&repo, "Second").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        drop(lock);
+
+        let refs = repo.ref_store();
+        assert_eq!(refs.read_ref("refs/heads/main")?, Some(first));
+        assert!(!repo.git_dir.join("refs/heads/main.lock").exists());
+        assert_eq!(staging_files(&repo)?, 0);
+
+        // Running it again reuses the objects and completes
+        let second = commit(&repo, "Second")?;
+        assert_eq!(refs.read_ref("refs/heads/main")?, Some(second));
+        match Object::read_from_objects_dir(&repo.git_dir, second)? {
+            Object::Commit { parents, .. } => assert_eq!(parents, vec![first]),
+            _ => panic!("expected a commit"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_refs_move_all_or_nothing() -> io::Result<()> {
+        let (_temp_dir, repo, first) = repo_with_commit()?;
+        let refs = repo.ref_store();
+        refs.update_ref("refs/heads/stale", first, "test")?;
+
+        let mut transaction = repo.transaction();
+        let blob = transaction.add(Object::new_blob(b"payload\n".to_vec()))?;
+        transaction.update_ref("refs/heads/fresh", None, first, "test");
+        transaction.update_ref("refs/heads/stale", Some("1".repeat(40).parse()?), first, "test");
+        let error = transaction.commit().unwrap_err();
+        assert!(error.to_string().starts_with("cannot lock ref 'refs/heads/stale': is at "), "{}", error);
+
+        // The objects are durable but neither ref moved
+        assert!(Object::read_from_objects_dir(&repo.git_dir, blob).is_ok());
+        assert_eq!(refs.read_ref("refs/heads/fresh")?, None);
+        assert_eq!(refs.read_ref("refs/heads/stale")?, Some(first));
+        assert!(!repo.git_dir.join("refs/heads/fresh.lock").exists());
+
+        // A ref that moved after it was read fails the compare-and-swap
+        let mut transaction = repo.transaction();
+        transaction.update_head(Some(first), blob, "test")?;
+        let moved: Oid = "2".repeat(40).parse()?;
+        refs.update_ref("refs/heads/main", moved, "test")?;
+        assert!(transaction.commit().is_err());
+        assert_eq!(refs.read_ref("refs/heads/main")?, Some(moved));
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_blobs_are_staged_on_disk() -> io::Result<()> {
+        let (_temp_dir, repo, _first) = repo_with_commit()?;
+        let large = || Object::new_blob(vec![b'x'; LARGE_OBJECT_SIZE + 1]);
+
+        let mut transaction = repo.transaction();
+        let hash = transaction.add(large())?;
+        assert_eq!(transaction.add(large())?, hash);
+        assert_eq!(transaction.len(), 1);
+        assert_eq!(staging_files(&repo)?, 1);
+        drop(transaction);
+        assert_eq!(staging_files(&repo)?, 0);
+        assert!(Object::read_from_objects_dir(&repo.git_dir, hash).is_err());
+
+        let mut transaction = repo.transaction();
+        transaction.add(large())?;
+        transaction.commit()?;
+        assert_eq!(staging_files(&repo)?, 0);
+        match Object::read_from_objects_dir(&repo.git_dir, hash)? {
+            Object::Blob(content) => assert_eq!(content.len(), LARGE_OBJECT_SIZE + 1),
+            _ => panic!("expected a blob"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_tree_must_be_a_tree() -> io::Result<()> {
+        let (_temp_dir, repo, first) = repo_with_commit()?;
+        let Object::Commit { author, committer, .. } = repo.read_object(first)? else {
+            panic!("not a commit");
+        };
+        let mut transaction = repo.transaction();
+        let error = transaction.add(Object::new_commit(first, vec![first], author, committer, "Bad".into())).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("is a commit, not a tree"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_commits_are_validated() -> io::Result<()> {
+        let (_temp_dir, repo, first) = repo_with_commit()?;
+        let Object::Commit { tree, mut author, committer, .. } = repo.read_object(first)? else {
+            panic!("not a commit");
+        };
+        author.email = "evil>\ncommitter forged".to_string();
+        let forged = || Object::new_commit(tree, vec![first], author.clone(), committer.clone(), "Forged".into());
+        let error = repo.transaction().add(forged()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let mut transaction = repo.transaction().with_validation(false);
+        assert_eq!(transaction.add(forged())?, forged().hash());
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_goes_through_the_repository_filesystem() -> io::Result<()> {
+        let fs = MemoryFs::new();
+        let repo = Repository::init_on(fs.clone(), PathBuf::from("/repo"))?;
+        let signature = Signature::new("Tester".to_string(), "tester@example.com".to_string());
+        let stage = |transaction: &mut ObjectTransaction| -> io::Result<(Oid, Oid)> {
+            let large = transaction.add(Object::new_blob(vec![b'x'; LARGE_OBJECT_SIZE + 1]))?;
+            let tree = transaction.add(Object::new_tree_from_entries(vec![("large".to_string(), 0o100644, large)]))?;
+            let commit = Object::new_commit(tree, Vec::new(), signature.clone(), signature.clone(), "Initial\n".into());
+            Ok((large, transaction.add(commit)?))
+        };
+
+        // A full disk while moving objects into place leaves no staging
+        // files and no ref behind
+        fs.fail(VfsOp::Rename, "/repo/.cobra/tmp", io::ErrorKind::StorageFull);
+        let mut transaction = repo.transaction();
+        let (large, commit) = stage(&mut transaction)?;
+        transaction.update_head(None, commit, "commit (initial)")?;
+        assert_eq!(transaction.commit().unwrap_err().kind(), io::ErrorKind::StorageFull);
+        assert!(fs.read_dir(&tmp_dir(&repo.git_dir))?.is_empty());
+        assert_eq!(repo.ref_store().read_ref_value("refs/heads/main")?, Some(String::new()));
+
+        fs.clear_faults();
+        let mut transaction = repo.transaction();
+        stage(&mut transaction)?;
+        transaction.update_head(None, commit, "commit (initial)")?;
+        transaction.commit()?;
+        assert_eq!(repo.ref_store().read_ref("refs/heads/main")?, Some(commit));
+        assert!(matches!(repo.read_object(commit)?, Object::Commit { .. }));
+        assert!(matches!(repo.read_object(large)?, Object::Blob(content) if content.len() == LARGE_OBJECT_SIZE + 1));
+        assert!(fs.read_dir(&tmp_dir(&repo.git_dir))?.is_empty());
+        assert!(!Path::new("/repo").exists());
+        Ok(())
+    }
+}
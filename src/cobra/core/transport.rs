@@ -0,0 +1,364 @@
+// Fetching objects and refs from another repository
+//
+// A Transport knows how to reach a remote: it lists the remote's refs and
+// returns a pack of the objects asked for. `fetch` does the rest the same
+// way for every transport: it decides what is missing, stores the received
+// objects and moves the remote-tracking refs in one transaction.
+
+use std::collections::HashSet;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use crate::cobra::core::daemon::DEFAULT_PORT;
+use crate::cobra::core::pack::read_pack;
+use crate::cobra::core::protocol::{agent, read_pkt_line, write_flush, write_pkt, RemoteRefs};
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::ref_store::check_ref_format;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::utils::trace::{self, Stream};
+
+pub trait Transport {
+    fn list_refs(&mut self) -> io::Result<RemoteRefs>;
+
+    /// Returns a pack holding everything reachable from `wants` that is not
+    /// reachable from `haves`
+    fn fetch_pack(&mut self, wants: &[Oid], haves: &[Oid]) -> io::Result<Vec<u8>>;
+}
+
+/// Speaks the git protocol over TCP to a `cobra daemon` or `git daemon`,
+/// for URLs such as tcp://host:port/path/to/repo (git:// is accepted too)
+pub struct TcpTransport {
+    host: String,
+    port: u16,
+    path: String,
+    connection: Option<Connection>,
+}
+
+/// A conversation that has got as far as the ref advertisement
+struct Connection {
+    input: BufReader<TcpStream>,
+    out: BufWriter<TcpStream>,
+    refs: RemoteRefs,
+}
+
+impl TcpTransport {
+    pub fn new(url: &str) -> io::Result<TcpTransport> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid URL '{}'", url));
+        let rest = url.strip_prefix("tcp://")
+            .or_else(|| url.strip_prefix("git://"))
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unsupported URL '{}'; expected tcp://host[:port]/path", url),
+            ))?;
+        let (authority, path) = rest.find('/')
+            .map(|slash| (&rest[..slash], &rest[slash..]))
+            .ok_or_else(invalid)?;
+
+        // IPv6 addresses are bracketed so their colons are not taken for the port
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, rest) = bracketed.split_once(']').ok_or_else(invalid)?;
+                (host, rest.strip_prefix(':'))
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid())?,
+            None => DEFAULT_PORT,
+        };
+        if host.is_empty() || path.len() < 2 {
+            return Err(invalid());
+        }
+        Ok(TcpTransport { host: host.to_string(), port, path: path.to_string(), connection: None })
+    }
+
+    fn connect(&mut self) -> io::Result<&mut Connection> {
+        if self.connection.is_none() {
+            let stream = TcpStream::connect((self.host.as_str(), self.port)).map_err(|e| io::Error::new(
+                e.kind(),
+                format!("Cannot connect to {}:{}: {}", self.host, self.port, e),
+            ))?;
+            let mut input = BufReader::new(stream.try_clone()?);
+            let mut out = BufWriter::new(stream);
+            write_pkt(&mut out, format!("git-upload-pack {}\0host={}\0", self.path, self.host).as_bytes())?;
+            out.flush()?;
+
+            let refs = read_advertisement(&mut input)?;
+            self.connection = Some(Connection { input, out, refs });
+        }
+        Ok(self.connection.as_mut().unwrap())
+    }
+}
+
+fn read_advertisement(input: &mut BufReader<TcpStream>) -> io::Result<RemoteRefs> {
+    let mut advertised = RemoteRefs::default();
+    while let Some(line) = read_pkt_line(input)? {
+        let (line, capabilities) = line.split_once('\0').unwrap_or((&line, ""));
+        for capability in capabilities.split(' ') {
+            if let Some(target) = capability.strip_prefix("symref=HEAD:") {
+                advertised.head = Some(target.to_string());
+            }
+        }
+        let (hash, name) = line.split_once(' ')
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid ref advertisement '{}'", line)))?;
+        // Peeled tags and the placeholder an empty repository sends
+        if name.ends_with("^{}") {
+            continue;
+        }
+        let hash = Oid::from_hex(hash).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        advertised.refs.push((name.to_string(), hash));
+    }
+    Ok(advertised)
+}
+
+impl Transport for TcpTransport {
+    fn list_refs(&mut self) -> io::Result<RemoteRefs> {
+        Ok(self.connect()?.refs.clone())
+    }
+
+    fn fetch_pack(&mut self, wants: &[Oid], haves: &[Oid]) -> io::Result<Vec<u8>> {
+        self.connect()?;
+        // The protocol allows one fetch per connection
+        let Connection { mut input, mut out, .. } = self.connection.take().unwrap();
+
+        for (i, want) in wants.iter().enumerate() {
+            match i {
+                0 => write_pkt(&mut out, format!("want {} agent={}\n", want, agent()).as_bytes())?,
+                _ => write_pkt(&mut out, format!("want {}\n", want).as_bytes())?,
+            }
+        }
+        write_flush(&mut out)?;
+        if wants.is_empty() {
+            out.flush()?;
+            return Ok(Vec::new());
+        }
+        for have in haves {
+            write_pkt(&mut out, format!("have {}\n", have).as_bytes())?;
+        }
+        write_pkt(&mut out, b"done\n")?;
+        out.flush()?;
+
+        // Without multi_ack the server answers with one ACK for the first
+        // common commit, or a NAK if there was none
+        let answer = read_pkt_line(&mut input)?.unwrap_or_default();
+        if answer != "NAK" && !answer.starts_with("ACK ") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Expected ACK or NAK, got '{}'", answer)));
+        }
+
+        let mut pack = Vec::new();
+        input.read_to_end(&mut pack)?;
+        trace::event(Stream::Pack, || format!("fetch: {} ({} bytes)", answer, pack.len()));
+        Ok(pack)
+    }
+}
+
+/// A ref that `fetch` created or moved
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefChange {
+    pub name: String,
+    pub old: Option<Oid>,
+    pub new: Oid,
+}
+
+#[derive(Debug, Default)]
+pub struct FetchReport {
+    pub objects: usize,
+    pub updated: Vec<RefChange>,
+}
+
+/// Fetches every branch and tag the remote has. Branches land under
+/// refs/remotes/<remote>/ and tags under refs/tags/ unless a tag of that
+/// name already exists. Without a remote name nothing but FETCH_HEAD is
+/// written, as when fetching straight from a URL.
+pub fn fetch(repo: &Repository, transport: &mut dyn Transport, url: &str, remote: Option<&str>) -> io::Result<FetchReport> {
+    if let Some(remote) = remote {
+        if remote.is_empty() || remote.contains('/') || remote.starts_with('.') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid remote name '{}'", remote)));
+        }
+    }
+
+    let advertised = transport.list_refs()?;
+    // Names come from the remote, so none may reach outside the refs
+    for (name, _) in &advertised.refs {
+        check_ref_format(name).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Remote advertised {}", e),
+        ))?;
+    }
+    let store = repo.ref_store();
+    let has_object = |hash: Oid| repo.vfs.exists(&hash.loose_path(&repo.git_dir));
+
+    let mut wants = Vec::new();
+    for &(ref name, hash) in &advertised.refs {
+        if (name.starts_with("refs/heads/") || name.starts_with("refs/tags/")) && !has_object(hash) && !wants.contains(&hash) {
+            wants.push(hash);
+        }
+    }
+
+    let mut haves = Vec::new();
+    for namespace in ["refs/heads", "refs/remotes", "refs/tags"] {
+        for hash in store.list_refs(namespace)?.into_iter().filter_map(|(_, hash)| hash) {
+            if has_object(hash) && !haves.contains(&hash) {
+                haves.push(hash);
+            }
+        }
+    }
+
+    let mut tx = repo.transaction();
+    let mut report = FetchReport::default();
+    if !wants.is_empty() {
+        let received: HashSet<Oid> = read_pack(&transport.fetch_pack(&wants, &haves)?)?
+            .iter()
+            .map(|object| tx.add_raw(object))
+            .collect::<io::Result<_>>()?;
+        if let Some(missing) = wants.iter().find(|want| !received.contains(*want)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Remote did not send {}", missing)));
+        }
+        report.objects = received.len();
+    }
+
+    let mut fetch_head = String::new();
+    for &(ref name, hash) in &advertised.refs {
+        let local = if let Some(branch) = name.strip_prefix("refs/heads/") {
+            let merge = match advertised.head.as_deref() {
+                Some(head) if head == name => "",
+                _ => "not-for-merge",
+            };
+            let line = format!("{}\t{}\tbranch '{}' of {}\n", hash, merge, branch, url);
+            // The branch to merge goes first, as `merge FETCH_HEAD` expects
+            if merge.is_empty() {
+                fetch_head.insert_str(0, &line);
+            } else {
+                fetch_head.push_str(&line);
+            }
+            remote.map(|remote| format!("refs/remotes/{}/{}", remote, branch))
+        } else if name.starts_with("refs/tags/") {
+            remote.map(|_| name.clone()).filter(|tag| matches!(store.read_ref(tag), Ok(None)))
+        } else {
+            None
+        };
+
+        if let Some(local) = local {
+            let old = store.read_ref(&local)?;
+            if old != Some(hash) {
+                let reason = match &old {
+                    Some(_) => format!("fetch {}: updating", url),
+                    None => format!("fetch {}: storing head", url),
+                };
+                tx.update_ref(&local, old, hash, &reason);
+                report.updated.push(RefChange { name: local, old, new: hash });
+            }
+        }
+    }
+    tx.commit()?;
+    repo.vfs.write_atomic(&repo.git_dir.join("FETCH_HEAD"), fetch_head.as_bytes())?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use crate::cobra::commands::{add::add_file, commit::commit};
+    use crate::cobra::core::compression::DEFAULT_LEVEL;
+    use crate::cobra::core::pack::{objects_between, write_pack};
+    use crate::cobra::core::protocol::advertised_refs;
+    use crate::cobra::test_support::init_repo;
+    use tempfile::TempDir;
+
+    /// Serves another repository on disk without a network in between
+    struct LocalTransport {
+        git_dir: PathBuf,
+        haves_seen: Vec<Oid>,
+    }
+
+    impl Transport for LocalTransport {
+        fn list_refs(&mut self) -> io::Result<RemoteRefs> {
+            advertised_refs(&self.git_dir)
+        }
+
+        fn fetch_pack(&mut self, wants: &[Oid], haves: &[Oid]) -> io::Result<Vec<u8>> {
+            self.haves_seen = haves.to_vec();
+            let mut pack = Vec::new();
+            let objects = objects_between(&self.git_dir, wants, haves)?;
+            write_pack(&self.git_dir, &objects, DEFAULT_LEVEL, &mut pack)?;
+            Ok(pack)
+        }
+    }
+
+    #[test]
+    fn test_fetch_updates_tracking_refs_and_sends_only_new_objects() -> io::Result<()> {
+        let upstream_dir = TempDir::new()?;
+        let mut upstream = init_repo(upstream_dir.path())?;
+        fs::write(upstream_dir.path().join("a.txt"), "a\n")?;
+        add_file(&mut upstream, Path::new("a.txt"))?;
+        let first = commit(&upstream, "First")?;
+
+        let local_dir = TempDir::new()?;
+        let local = init_repo(local_dir.path())?;
+        let mut transport = LocalTransport { git_dir: upstream.git_dir.clone(), haves_seen: Vec::new() };
+
+        let report = fetch(&local, &mut transport, "upstream-url", Some("origin"))?;
+        assert_eq!(report.objects, 3);
+        assert_eq!(report.updated, vec![RefChange { name: "refs/remotes/origin/main".to_string(), old: None, new: first }]);
+        assert!(local.read_object(first).is_ok());
+        let fetch_head = fs::read_to_string(local.git_dir.join("FETCH_HEAD"))?;
+        assert_eq!(fetch_head, format!("{}\t\tbranch 'main' of upstream-url\n", first));
+
+        fs::write(upstream_dir.path().join("b.txt"), "b\n")?;
+        add_file(&mut upstream, Path::new("b.txt"))?;
+        let second = commit(&upstream, "Second")?;
+        let report = fetch(&local, &mut transport, "upstream-url", Some("origin"))?;
+        assert_eq!(transport.haves_seen, vec![first]);
+        // New commit, its tree and the new blob
+        assert_eq!(report.objects, 3);
+        assert_eq!(report.updated[0].old, Some(first));
+        assert_eq!(local.ref_store().read_ref("refs/remotes/origin/main")?, Some(second));
+
+        // Nothing left to fetch
+        assert!(fetch(&local, &mut transport, "upstream-url", Some("origin"))?.updated.is_empty());
+        Ok(())
+    }
+
+    /// Advertises a fixed set of refs and has no objects to send
+    struct AdvertisingTransport(Vec<(String, Oid)>);
+
+    impl Transport for AdvertisingTransport {
+        fn list_refs(&mut self) -> io::Result<RemoteRefs> {
+            Ok(RemoteRefs { refs: self.0.clone(), head: None })
+        }
+
+        fn fetch_pack(&mut self, _wants: &[Oid], _haves: &[Oid]) -> io::Result<Vec<u8>> {
+            panic!("nothing should be fetched for invalid refs")
+        }
+    }
+
+    #[test]
+    fn test_fetch_rejects_invalid_ref_names() -> io::Result<()> {
+        let local_dir = TempDir::new()?;
+        let local = init_repo(local_dir.path())?;
+        for name in ["refs/heads/../../config", "/etc/passwd", "refs/tags/v1.lock", "refs/heads/a\x01"] {
+            let mut transport = AdvertisingTransport(vec![(name.to_string(), "a".repeat(40).parse()?)]);
+            let error = fetch(&local, &mut transport, "url", Some("origin")).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+        assert!(local.ref_store().list_refs("refs/remotes")?.is_empty());
+        assert!(!local.git_dir.join("FETCH_HEAD").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_tcp_urls() -> io::Result<()> {
+        let transport = TcpTransport::new("tcp://127.0.0.1:4000/srv/repo")?;
+        assert_eq!((transport.host.as_str(), transport.port, transport.path.as_str()), ("127.0.0.1", 4000, "/srv/repo"));
+        let transport = TcpTransport::new("git://[::1]/repo")?;
+        assert_eq!((transport.host.as_str(), transport.port), ("::1", DEFAULT_PORT));
+        assert_eq!(TcpTransport::new("https://example.com/repo").err().unwrap().kind(), io::ErrorKind::Unsupported);
+        assert!(TcpTransport::new("tcp://host:port/repo").is_err());
+        assert!(TcpTransport::new("tcp://host").is_err());
+        Ok(())
+    }
+}
@@ -1,17 +1,26 @@
+use std::cmp::Ordering;
 use std::io;
 use std::collections::BTreeMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use crate::cobra::core::{
     object::Object,
     object::TreeEntry,
+    oid::Oid,
     repository::Repository,
+    transaction::ObjectTransaction,
 };
-use crate::cobra::core::index::IndexEntry;
+use crate::cobra::messages;
 
 pub struct Tree {
     entries: Vec<TreeEntry>,
 }
 
+impl Default for Tree {
+    fn default() -> Self {
+        Tree::new()
+    }
+}
+
 impl Tree {
     pub fn new() -> Tree {
         Tree {
@@ -19,7 +28,7 @@ impl Tree {
         }
     }
 
-    pub fn add_entry(&mut self, name: String, mode: u32, hash: String) {
+    pub fn add_entry(&mut self, name: String, mode: u32, hash: Oid) {
         self.entries.push(TreeEntry { mode, name, hash });
     }
 
@@ -28,83 +37,278 @@ impl Tree {
     }
 }
 
+/// Orders tree entries the way git does: by name, with directories compared
+/// as if their name ended in '/' (so "src.txt" sorts before the "src" directory)
+pub fn entry_order(a: &TreeEntry, b: &TreeEntry) -> Ordering {
+    let key = |entry: &TreeEntry| {
+        let mut name = entry.name.as_bytes().to_vec();
+        if entry.mode == 0o040000 {
+            name.push(b'/');
+        }
+        name
+    };
+    key(a).cmp(&key(b))
+}
+
 /// Builds a tree object from the index
 pub fn build_tree_from_index(repo: &Repository) -> io::Result<Object> {
-    let mut trees: BTreeMap<String, Tree> = BTreeMap::new();
-    trees.insert("".to_string(), Tree::new());
-
-    // First pass: create tree objects for each directory
-    for entry in repo.index.entries() {
-        let path = Path::new(&entry.path);
-        let parent_path = path.parent()
-            .map(|p| p.to_string_lossy().into_owned())
-            .unwrap_or_else(|| "".to_string());
-
-        // Ensure parent directory tree exists
-        if !trees.contains_key(&parent_path) {
-            trees.insert(parent_path.clone(), Tree::new());
-        }
+    let mut transaction = repo.transaction();
+    let tree = build_tree_from_index_in(repo, &mut transaction)?;
+    transaction.commit()?;
+    Ok(tree)
+}
 
-        // Add entry to parent tree
-        let filename = path.file_name()
+/// Like `build_tree_from_index`, but stages the nested subtrees in `transaction`
+pub fn build_tree_from_index_in(repo: &Repository, transaction: &mut ObjectTransaction) -> io::Result<Object> {
+    let entries: Vec<(PathBuf, u32, Oid)> = repo.index.entries()
+        .map(|entry| (entry.path.clone(), entry.mode, entry.hash))
+        .collect();
+    build_tree_in(transaction, &entries)
+}
+
+/// Builds a tree object from a flat list of (path, mode, hash) entries,
+/// writing every nested subtree to the object store along the way
+pub fn build_tree(repo: &Repository, entries: &[(PathBuf, u32, Oid)]) -> io::Result<Object> {
+    let mut transaction = repo.transaction();
+    let tree = build_tree_in(&mut transaction, entries)?;
+    transaction.commit()?;
+    Ok(tree)
+}
+
+/// Like `build_tree`, but stages the nested subtrees in `transaction`
+pub fn build_tree_in(transaction: &mut ObjectTransaction, entries: &[(PathBuf, u32, Oid)]) -> io::Result<Object> {
+    let mut tree = Tree::new();
+    let mut subdirs: BTreeMap<String, Vec<(PathBuf, u32, Oid)>> = BTreeMap::new();
+
+    for (path, mode, hash) in entries {
+        let mut components = path.components();
+        let first = components.next()
             .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?
+            .as_os_str()
             .to_string_lossy()
             .into_owned();
+        let rest = components.as_path();
 
-        let tree = trees.get_mut(&parent_path).unwrap();
-        tree.add_entry(filename, entry.mode, entry.hash.clone());
+        if rest.as_os_str().is_empty() {
+            tree.add_entry(first, *mode, *hash);
+        } else {
+            subdirs.entry(first)
+                .or_default()
+                .push((rest.to_path_buf(), *mode, *hash));
+        }
     }
 
-    // Second pass: build tree objects from bottom up
-    let mut root_tree = None;
-    let mut tree_hashes = BTreeMap::new();
+    // Write subtrees first so their hashes are final before the parent references them
+    for (name, sub_entries) in subdirs {
+        let subtree = build_tree_in(transaction, &sub_entries)?;
+        let subtree_hash = transaction.add(subtree)?;
+        tree.add_entry(name, 0o040000, subtree_hash);
+    }
 
-    // First, create all tree objects and store their hashes
-    for (path, tree) in &trees {
-        let tree_object = tree.to_object();
-        let tree_hash = tree_object.hash();
-        tree_object.write_to_objects_dir(&repo.git_dir)?;
-        tree_hashes.insert(path.clone(), tree_hash);
+    // Sort entries for consistent tree creation
+    tree.entries.sort_by(entry_order);
+    Ok(tree.to_object())
+}
+
+/// Returns the hash of the tree a commit points to
+pub fn commit_tree(git_dir: &Path, commit_hash: Oid) -> io::Result<Oid> {
+    match Object::read_from_objects_dir(git_dir, commit_hash)? {
+        Object::Commit { tree, .. } => Ok(tree),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            messages::not_a_commit(&commit_hash.to_string()),
+        )),
     }
+}
+
+/// Recursively flattens a tree object into a map of file paths to entries.
+/// The returned entries keep their base name; the key holds the full path.
+pub fn flatten_tree(git_dir: &Path, tree_hash: Oid) -> io::Result<BTreeMap<PathBuf, TreeEntry>> {
+    let mut files = BTreeMap::new();
+    flatten_tree_into(git_dir, tree_hash, Path::new(""), &mut files)?;
+    Ok(files)
+}
 
-    // Then, update parent trees with the hashes
-    let mut paths: Vec<String> = trees.keys().cloned().collect();
-    paths.sort_by(|a, b| b.len().cmp(&a.len())); // Sort by length descending
+/// Looks up a single file or directory by its path inside a tree
+pub fn tree_entry(git_dir: &Path, tree_hash: Oid, path: &Path) -> io::Result<Option<TreeEntry>> {
+    let mut tree_hash = tree_hash;
+    let mut components = path.components().peekable();
+    while let Some(component) = components.next() {
+        let entries = match Object::read_from_objects_dir(git_dir, tree_hash)? {
+            Object::Tree(entries) => entries,
+            _ => return Ok(None),
+        };
+        let name = component.as_os_str().to_string_lossy();
+        let entry = match entries.into_iter().find(|entry| entry.name == name) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        if components.peek().is_none() {
+            return Ok(Some(entry));
+        }
+        tree_hash = entry.hash;
+    }
+    Ok(None)
+}
 
-    for path in paths {
-        if path.is_empty() {
-            root_tree = Some(trees[&path].to_object());
+fn flatten_tree_into(
+    git_dir: &Path,
+    tree_hash: Oid,
+    prefix: &Path,
+    files: &mut BTreeMap<PathBuf, TreeEntry>,
+) -> io::Result<()> {
+    let entries = match Object::read_from_objects_dir(git_dir, tree_hash)? {
+        Object::Tree(entries) => entries,
+        _ => return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Object {} is not a tree", tree_hash),
+        )),
+    };
+
+    for entry in entries {
+        let path = prefix.join(&entry.name);
+        if entry.mode == 0o040000 {
+            flatten_tree_into(git_dir, entry.hash, &path, files)?;
         } else {
-            let parent_path = Path::new(&path)
-                .parent()
-                .map(|p| p.to_string_lossy().into_owned())
-                .unwrap_or_else(|| "".to_string());
-
-            let name = Path::new(&path)
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                .into_owned();
-
-            let tree_hash = tree_hashes[&path].clone();
-            let parent_tree = trees.get_mut(&parent_path).unwrap();
-            parent_tree.add_entry(name, 0o040000, tree_hash);
+            files.insert(path, entry);
         }
     }
 
-    Ok(root_tree.unwrap())
+    Ok(())
+}
+
+/// A file whose entry differs between two trees
+#[derive(Debug, Clone)]
+pub struct TreeChange {
+    pub path: PathBuf,
+    /// Entry on the old side, None if the file was added
+    pub old: Option<TreeEntry>,
+    /// Entry on the new side, None if the file was deleted
+    pub new: Option<TreeEntry>,
+}
+
+impl TreeChange {
+    /// git's status letter: Added, Deleted, Modified, or T when the file
+    /// changed type (say from a regular file to a symlink)
+    pub fn status(&self) -> char {
+        match (&self.old, &self.new) {
+            (None, _) => 'A',
+            (_, None) => 'D',
+            (Some(old), Some(new)) if old.mode & 0o170000 != new.mode & 0o170000 => 'T',
+            _ => 'M',
+        }
+    }
+}
+
+/// True if `path` is one of `limits` or lies below one of them. No limits
+/// matches everything, as does ".".
+pub fn matches_paths(path: &Path, limits: &[PathBuf]) -> bool {
+    limits.is_empty() || limits.iter().any(|limit| {
+        let limit = limit.strip_prefix(".").unwrap_or(limit);
+        limit.as_os_str().is_empty() || path.starts_with(limit)
+    })
+}
+
+/// Compares two trees file by file. A missing tree is treated as empty,
+/// so diffing a root commit against `None` reports every file as added.
+pub fn diff_trees(git_dir: &Path, old_tree: Option<Oid>, new_tree: Option<Oid>) -> io::Result<Vec<TreeChange>> {
+    diff_trees_within(git_dir, old_tree, new_tree, &[])
+}
+
+/// Like `diff_trees`, but only reports files at or below `limits` (see
+/// `matches_paths`). Only the subtrees leading to the limits are read, and
+/// subtrees with the same hash on both sides are skipped without reading.
+pub fn diff_trees_within(
+    git_dir: &Path,
+    old_tree: Option<Oid>,
+    new_tree: Option<Oid>,
+    limits: &[PathBuf],
+) -> io::Result<Vec<TreeChange>> {
+    let limits: Vec<PathBuf> = limits.iter()
+        .map(|limit| limit.strip_prefix(".").unwrap_or(limit).to_path_buf())
+        .collect();
+    let mut changes = Vec::new();
+    diff_subtrees(git_dir, old_tree, new_tree, Path::new(""), &limits, &mut changes)?;
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+fn diff_subtrees(
+    git_dir: &Path,
+    old_tree: Option<Oid>,
+    new_tree: Option<Oid>,
+    prefix: &Path,
+    limits: &[PathBuf],
+    changes: &mut Vec<TreeChange>,
+) -> io::Result<()> {
+    if old_tree == new_tree {
+        return Ok(());
+    }
+    let mut old_entries = tree_entries_by_name(git_dir, old_tree)?;
+    let mut entries: Vec<(String, Option<TreeEntry>, Option<TreeEntry>)> = tree_entries_by_name(git_dir, new_tree)?
+        .into_iter()
+        .map(|(name, new)| {
+            let old = old_entries.remove(&name);
+            (name, old, Some(new))
+        })
+        .collect();
+    entries.extend(old_entries.into_iter().map(|(name, old)| (name, Some(old), None)));
+
+    for (name, old, new) in entries {
+        let path = prefix.join(&name);
+        let selected = matches_paths(&path, limits);
+        // A directory above a limit is entered, but its own files are not reported
+        let leads_to_limit = limits.iter().any(|limit| limit.starts_with(&path));
+        if !selected && !leads_to_limit {
+            continue;
+        }
+        if let (Some(old), Some(new)) = (&old, &new) {
+            if old.hash == new.hash && old.mode == new.mode {
+                continue;
+            }
+        }
+
+        let is_dir = |entry: &Option<TreeEntry>| entry.as_ref().is_some_and(|entry| entry.mode == 0o040000);
+        let (old_dir, new_dir) = (is_dir(&old), is_dir(&new));
+        if old_dir || new_dir {
+            let hash = |entry: &Option<TreeEntry>, dir: bool| entry.as_ref().filter(|_| dir).map(|entry| entry.hash);
+            diff_subtrees(git_dir, hash(&old, old_dir), hash(&new, new_dir), &path, limits, changes)?;
+        }
+
+        // A file replaced by a directory (or the reverse) is a deletion or addition
+        let old_file = old.filter(|_| !old_dir);
+        let new_file = new.filter(|_| !new_dir);
+        if selected && (old_file.is_some() || new_file.is_some()) {
+            changes.push(TreeChange { path, old: old_file, new: new_file });
+        }
+    }
+    Ok(())
+}
+
+/// The entries of a tree keyed by name, none for a missing tree
+fn tree_entries_by_name(git_dir: &Path, tree_hash: Option<Oid>) -> io::Result<BTreeMap<String, TreeEntry>> {
+    let Some(tree_hash) = tree_hash else {
+        return Ok(BTreeMap::new());
+    };
+    match Object::read_from_objects_dir(git_dir, tree_hash)? {
+        Object::Tree(entries) => Ok(entries.into_iter().map(|entry| (entry.name.clone(), entry)).collect()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Object {} is not a tree", tree_hash),
+        )),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cobra::core::index::IndexEntry;
+    use crate::cobra::test_support::temp_repo;
     use std::fs;
-    use tempfile::TempDir;
 
     #[test]
     fn test_build_tree_single_file() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let mut repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (temp_dir, mut repo) = temp_repo()?;
 
         // Create a test file
         let test_file = temp_dir.path().join("test.txt");
@@ -114,7 +318,7 @@ mod tests {
         let metadata = fs::metadata(&test_file)?;
         let entry = IndexEntry::new(
             "test.txt".into(),
-            "1234567890123456789012345678901234567890".to_string(),
+            "1234567890123456789012345678901234567890".parse()?,
             metadata,
         );
         repo.add_to_index(entry)?;
@@ -137,8 +341,7 @@ mod tests {
 
     #[test]
     fn test_build_tree_nested() -> io::Result<()> {
-        let temp_dir = TempDir::new()?;
-        let mut repo = Repository::init(temp_dir.path().to_str().unwrap())?;
+        let (temp_dir, mut repo) = temp_repo()?;
 
         // Create test files
         fs::create_dir_all(temp_dir.path().join("src"))?;
@@ -152,12 +355,12 @@ mod tests {
         let metadata2 = fs::metadata(&test_file2)?;
         let entry1 = IndexEntry::new(
             "src/main.rs".into(),
-            "1111111111111111111111111111111111111111".to_string(),
+            "1111111111111111111111111111111111111111".parse()?,
             metadata1,
         );
         let entry2 = IndexEntry::new(
             "src/lib.rs".into(),
-            "2222222222222222222222222222222222222222".to_string(),
+            "2222222222222222222222222222222222222222".parse()?,
             metadata2,
         );
         repo.add_to_index(entry1)?;
@@ -173,7 +376,7 @@ mod tests {
                 assert_eq!(src_entry.mode, 0o040000);
 
                 // Read src tree
-                let src_tree = Object::read_from_objects_dir(&repo.git_dir, &src_entry.hash)?;
+                let src_tree = Object::read_from_objects_dir(&repo.git_dir, src_entry.hash)?;
                 match src_tree {
                     Object::Tree(entries) => {
                         assert_eq!(entries.len(), 2);
@@ -198,4 +401,39 @@ mod tests {
 
         Ok(())
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_diff_within_limits_reads_only_what_it_needs() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        let blob = |n: u8| Oid::from_bytes(&[n; 20]).unwrap();
+        let file = |path: &str, n: u8| (PathBuf::from(path), 0o100644, blob(n));
+
+        let old = repo.write_object(&build_tree(&repo, &[
+            file("README", 1), file("src/lib.rs", 2), file("src/cli/args.rs", 3), file("vendor/big/a.rs", 4), file("gone.txt", 5),
+        ])?)?;
+        let new = repo.write_object(&build_tree(&repo, &[
+            file("README", 10), file("src/lib.rs", 2), file("src/cli/args.rs", 30), file("vendor/big/a.rs", 4), file("gone.txt/now_a_dir", 6),
+        ])?)?;
+
+        // The unchanged vendor subtree is never read, so losing it does not matter
+        let vendor = tree_entry(&repo.git_dir, old, Path::new("vendor"))?.unwrap().hash;
+        fs::remove_file(vendor.loose_path(&repo.git_dir))?;
+
+        let summary = |changes: Vec<TreeChange>| -> Vec<String> {
+            changes.iter().map(|change| format!("{} {}", change.status(), change.path.display())).collect()
+        };
+        assert_eq!(summary(diff_trees(&repo.git_dir, Some(old), Some(new))?), [
+            "M README", "D gone.txt", "A gone.txt/now_a_dir", "M src/cli/args.rs",
+        ]);
+        assert_eq!(summary(diff_trees_within(&repo.git_dir, Some(old), Some(new), &[PathBuf::from("src")])?), ["M src/cli/args.rs"]);
+        assert_eq!(summary(diff_trees_within(&repo.git_dir, Some(old), Some(new), &[PathBuf::from("./src/cli/args.rs")])?), ["M src/cli/args.rs"]);
+        assert!(diff_trees_within(&repo.git_dir, Some(old), Some(new), &[PathBuf::from("src/lib.rs")])?.is_empty());
+        assert!(diff_trees_within(&repo.git_dir, Some(old), Some(new), &[PathBuf::from("src/li")])?.is_empty());
+
+        // A root commit adds everything under the limit
+        assert_eq!(summary(diff_trees_within(&repo.git_dir, None, Some(new), &[PathBuf::from("src/")])?), [
+            "A src/cli/args.rs", "A src/lib.rs",
+        ]);
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+// Checking the index's stat data against the work tree
+//
+// Every index entry keeps the stat data its file had when it was staged, so
+// status can trust a file that still looks the same without reading it.
+// Restoring a backup or copying the repository to another machine makes
+// that data wrong for every file at once: the device and inode numbers
+// belong to the old filesystem, and status ends up hashing the whole work
+// tree on every run. `verify` reports such drift, and `realign` records the
+// current stat data of the files whose content still matches the index,
+// the same way status refreshes the few files it had to hash.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::cobra::core::attributes::Attributes;
+use crate::cobra::core::index::{normalize_mode, Index, IndexEntry};
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::utils::trace::{self, Stream};
+
+/// Fields that editing files never changes for all of them at once, so a
+/// mismatch on every entry means the index came from another filesystem
+const WHOLESALE_FIELDS: [&str; 4] = ["dev", "ino", "uid", "gid"];
+
+/// A file whose content matches its index entry but whose stat data does not
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaleEntry {
+    pub path: PathBuf,
+    /// The stat fields that differ, e.g. ["dev", "ino"]
+    pub fields: Vec<&'static str>,
+}
+
+/// A tracked file whose stat data no longer matches its index entry but
+/// whose content and mode still do
+#[derive(Debug, Clone)]
+pub struct StaleFile {
+    pub path: PathBuf,
+    /// The index hash the content was found to match
+    pub hash: Oid,
+    /// The stat data the file had when it was read
+    pub metadata: fs::Metadata,
+}
+
+#[derive(Debug, Default)]
+pub struct IndexReport {
+    /// Entries whose file is in the work tree
+    pub present: usize,
+    /// Entries that only need their stat data refreshed, sorted by path
+    pub stale: Vec<StaleEntry>,
+    /// Entries whose file now has other content or another mode, sorted
+    pub modified: Vec<PathBuf>,
+    /// Entries whose file is gone, sorted
+    pub missing: Vec<PathBuf>,
+    /// Identity fields that differ for every present entry. Non-empty means
+    /// the stat data as a whole is from elsewhere and worth realigning.
+    pub wholesale: Vec<&'static str>,
+}
+
+/// Stats every tracked file and reports the identity fields that disagree
+/// with the index for all of them. Nothing is read, so this is cheap enough
+/// to run whenever status had to hash files.
+pub fn wholesale_drift(repo: &Repository) -> io::Result<Vec<&'static str>> {
+    let mut present = 0;
+    let mut counts: HashMap<&'static str, usize> = HashMap::new();
+    for entry in repo.index.entries() {
+        let Some(metadata) = stat(repo, entry)? else {
+            continue;
+        };
+        present += 1;
+        for field in entry.stat_differences(&metadata) {
+            *counts.entry(field).or_default() += 1;
+        }
+    }
+    Ok(WHOLESALE_FIELDS.into_iter()
+        .filter(|field| present > 0 && counts.get(field) == Some(&present))
+        .collect())
+}
+
+/// Compares every index entry with its file, hashing the files whose stat
+/// data differs to tell stale entries from modified ones
+pub fn verify(repo: &Repository) -> io::Result<IndexReport> {
+    Ok(scan(repo)?.0)
+}
+
+/// Records the current stat data of every stale entry, as status does for
+/// the files it hashed, and returns the paths that were refreshed
+pub fn realign(repo: &mut Repository) -> io::Result<Vec<PathBuf>> {
+    let (_, stale) = scan(repo)?;
+    let refreshed = refresh_stat_data(repo, &stale)?;
+    trace::event(Stream::Main, || format!("index realign: {} entries refreshed", refreshed.len()));
+    Ok(refreshed)
+}
+
+/// Stores the stat data each file had when it was hashed in its index
+/// entry, writing the index once under its lock. A file that changed since
+/// it was read, or whose entry was restaged, is left alone, and so is one
+/// modified within the current second: another write in that second could
+/// keep its size and mtime. Returns the paths refreshed.
+pub fn refresh_stat_data(repo: &mut Repository, stale: &[StaleFile]) -> io::Result<Vec<PathBuf>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let lock = repo.lock_index()?;
+    // Start from what is on disk now that nobody else can change it
+    repo.index = Index::load(repo)?;
+
+    let mut refreshed = Vec::new();
+    for file in stale.iter().filter(|file| file.metadata.mtime() < now) {
+        let entry = IndexEntry::new(file.path.clone(), file.hash, file.metadata.clone());
+        let unchanged = fs::metadata(repo.root_path.join(&file.path))
+            .is_ok_and(|metadata| entry.stat_differences(&metadata).is_empty());
+        match repo.index.get_entry_mut(&file.path) {
+            Some(current) if unchanged && current.hash == file.hash => {
+                *current = entry;
+                refreshed.push(file.path.clone());
+            }
+            _ => {}
+        }
+    }
+    if !refreshed.is_empty() {
+        repo.save_index_locked(lock)?;
+    }
+    trace::event(Stream::Main, || format!("refreshed stat data of {} index entries", refreshed.len()));
+    Ok(refreshed)
+}
+
+/// The report, along with the stat data each stale file had when hashed
+fn scan(repo: &Repository) -> io::Result<(IndexReport, Vec<StaleFile>)> {
+    let attributes = Attributes::load(repo)?;
+    let mut report = IndexReport { wholesale: wholesale_drift(repo)?, ..Default::default() };
+    let mut stale = Vec::new();
+    for entry in repo.index.entries() {
+        let Some(metadata) = stat(repo, entry)? else {
+            report.missing.push(entry.path.clone());
+            continue;
+        };
+        report.present += 1;
+        let fields = entry.stat_differences(&metadata);
+        if fields.is_empty() {
+            continue;
+        }
+        let content = attributes.to_index(&entry.path, fs::read(repo.root_path.join(&entry.path))?);
+        let hash = Object::new_blob(content).hash();
+        if hash == entry.hash && normalize_mode(metadata.mode()) == normalize_mode(entry.mode) {
+            report.stale.push(StaleEntry { path: entry.path.clone(), fields });
+            stale.push(StaleFile { path: entry.path.clone(), hash, metadata });
+        } else {
+            report.modified.push(entry.path.clone());
+        }
+    }
+    report.stale.sort_by(|a, b| a.path.cmp(&b.path));
+    report.modified.sort();
+    report.missing.sort();
+    Ok((report, stale))
+}
+
+fn stat(repo: &Repository, entry: &IndexEntry) -> io::Result<Option<fs::Metadata>> {
+    match fs::metadata(repo.root_path.join(&entry.path)) {
+        Ok(metadata) if metadata.is_file() => Ok(Some(metadata)),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::time::Duration;
+    use crate::cobra::commands::add::add_file;
+    use crate::cobra::commands::status::collect;
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_realign_after_index_moved_between_filesystems() -> io::Result<()> {
+        let (temp_dir, mut repo) = temp_repo()?;
+        // Only recent.txt was written within the current second
+        for name in ["a.txt", "b.txt", "c.txt", "recent.txt"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, format!("{}\n", name))?;
+            if name != "recent.txt" {
+                fs::File::options().write(true).open(&path)?.set_modified(SystemTime::now() - Duration::from_secs(100))?;
+            }
+            add_file(&mut repo, Path::new(name))?;
+        }
+        let report = verify(&repo)?;
+        assert!(report.stale.is_empty() && report.wholesale.is_empty());
+        assert_eq!(collect(&repo)?.stale.len(), 0);
+
+        // As if the index had been written on another machine
+        let paths: Vec<PathBuf> = repo.index.entries().map(|entry| entry.path.clone()).collect();
+        for path in &paths {
+            let entry = repo.index.get_entry_mut(path).unwrap();
+            entry.dev = entry.dev.wrapping_add(1);
+            entry.ino = entry.ino.wrapping_add(1000);
+        }
+        repo.save_index()?;
+        fs::write(temp_dir.path().join("c.txt"), "changed\n")?;
+        fs::remove_file(temp_dir.path().join("b.txt"))?;
+
+        assert_eq!(collect(&repo)?.stale.len(), 2);
+        let report = verify(&repo)?;
+        assert_eq!(report.wholesale, vec!["dev", "ino"]);
+        assert_eq!(report.stale, vec![
+            StaleEntry { path: PathBuf::from("a.txt"), fields: vec!["dev", "ino"] },
+            StaleEntry { path: PathBuf::from("recent.txt"), fields: vec!["dev", "ino"] },
+        ]);
+        assert_eq!(report.modified, vec![PathBuf::from("c.txt")]);
+        assert_eq!(report.missing, vec![PathBuf::from("b.txt")]);
+
+        assert_eq!(realign(&mut repo)?, vec![PathBuf::from("a.txt")]);
+        assert!(!repo.git_dir.join("index.lock").exists());
+        let reopened = Repository::open(temp_dir.path().to_str().unwrap())?;
+        // A rewrite later in this second could go unnoticed, so the recent
+        // file is left to be hashed again
+        let stale: Vec<_> = collect(&reopened)?.stale.into_iter().map(|file| file.path).collect();
+        assert_eq!(stale, vec![PathBuf::from("recent.txt")]);
+        assert_eq!(verify(&reopened)?.stale.len(), 1);
+        // The modified file keeps its staged content
+        assert_eq!(reopened.index.get_entry(Path::new("c.txt")).unwrap().hash, Object::new_blob(b"c.txt\n".to_vec()).hash());
+        Ok(())
+    }
+}
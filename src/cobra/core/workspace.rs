@@ -2,100 +2,185 @@
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use walkdir::WalkDir;
 use crate::cobra::core::{
     repository::Repository,
     object::Object,
-    index::IndexEntry,
+    oid::Oid,
+    index::{normalize_mode, IndexEntry},
+    events::RepositoryEvent,
+    ignore::IgnoreRules,
+    transaction::ObjectTransaction,
+    tree::{build_tree_in, flatten_tree},
 };
+use crate::cobra::utils::progress::{NoProgress, Progress};
+use crate::cobra::utils::trace::{self, Stream};
+
+/// Empty file standing in for an empty directory, which a tree cannot hold.
+/// Hidden files are never part of a workspace state, so a real file of this
+/// name is never mistaken for one.
+pub const KEEP_FILE: &str = ".cobra-keep";
+
+/// File at the top of a saved state listing each file's modification time
+pub const MTIMES_FILE: &str = ".cobra-mtimes";
+
+/// What is kept about a file besides its content
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileMetadata {
+    pub mode: u32,
+    /// None for states saved before modification times were recorded
+    pub mtime: Option<SystemTime>,
+}
+
+impl From<&fs::Metadata> for FileMetadata {
+    fn from(metadata: &fs::Metadata) -> FileMetadata {
+        FileMetadata {
+            mode: metadata.mode(),
+            mtime: metadata.modified().ok(),
+        }
+    }
+}
+
+/// How `apply_to_workspace` writes files back
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApplyOptions {
+    /// Give each file back its recorded modification time
+    pub preserve_mtime: bool,
+    /// Only replace the files the index tracks, leaving untracked files
+    /// (and the directories holding them) in place
+    pub keep_untracked: bool,
+}
 
 /// Represents the state of the working directory
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct WorkspaceState {
     /// Map of file paths to their content hashes
-    pub files: HashMap<PathBuf, String>,
+    pub files: HashMap<PathBuf, Oid>,
     /// Map of file paths to their metadata
-    pub metadata: HashMap<PathBuf, fs::Metadata>,
+    pub metadata: HashMap<PathBuf, FileMetadata>,
+    /// Directories without any entries
+    pub empty_dirs: BTreeSet<PathBuf>,
+}
+
+/// Filter for work tree walks: false for .cobra, hidden files and
+/// untracked files or directories the ignore rules leave out
+pub fn is_visible(repo: &Repository, ignore: &IgnoreRules, entry: &walkdir::DirEntry) -> bool {
+    !entry.path().starts_with(&repo.git_dir) &&
+    !entry.path().file_name().is_some_and(|n| n.to_string_lossy().starts_with(".")) &&
+    !entry.path().strip_prefix(&repo.root_path)
+        .is_ok_and(|path| ignore.excludes(&repo.index, path, entry.file_type().is_dir()))
 }
 
 impl WorkspaceState {
     /// Creates a new workspace state by scanning the working directory
     pub fn from_workspace(repo: &Repository) -> io::Result<WorkspaceState> {
-        let mut files = HashMap::new();
-        let mut metadata = HashMap::new();
-        let cobra_dir = repo.root_path.join(".cobra");
+        trace::timed(Stream::Main, "worktree walk", || WorkspaceState::scan(repo), |state| match state {
+            Ok(state) => format!("{} files", state.files.len()),
+            Err(e) => format!("failed: {}", e),
+        })
+    }
+
+    fn scan(repo: &Repository) -> io::Result<WorkspaceState> {
+        let mut state = WorkspaceState::default();
+        let ignore = IgnoreRules::load(repo)?;
 
         for entry in WalkDir::new(&repo.root_path)
             .min_depth(1)  // Skip root directory
             .into_iter()
-            .filter_entry(|e| {
-                // Skip .cobra directory and hidden files
-                !e.path().starts_with(&cobra_dir) && 
-                !e.path().to_string_lossy().contains("/.") &&
-                !e.path().file_name().map_or(false, |n| n.to_string_lossy().starts_with("."))
-            })
+            .filter_entry(|e| is_visible(repo, &ignore, e))
         {
             let entry = entry?;
+            let Ok(relative_path) = entry.path().strip_prefix(&repo.root_path) else {
+                continue;
+            };
+            let relative_path = relative_path.to_path_buf();
+
             if entry.file_type().is_file() {
-                if let Ok(relative_path) = entry.path().strip_prefix(&repo.root_path) {
-                    let relative_path = relative_path.to_path_buf();
-                    
-                    // Read file content and create blob
-                    let content = fs::read(entry.path())?;
-                    let blob = Object::new_blob(content);
-                    let hash = blob.hash();
-                    
-                    // Store blob in objects directory
-                    blob.write_to_objects_dir(&repo.git_dir)?;
-                    
-                    // Store file info
-                    files.insert(relative_path.clone(), hash);
-                    metadata.insert(relative_path, fs::metadata(entry.path())?);
-                }
+                // Read file content and create blob
+                let content = fs::read(entry.path())?;
+                let blob = Object::new_blob(content);
+                let hash = repo.write_object(&blob)?;
+
+                // Store file info
+                state.files.insert(relative_path.clone(), hash);
+                state.metadata.insert(relative_path, FileMetadata::from(&fs::metadata(entry.path())?));
+            } else if entry.file_type().is_dir() && fs::read_dir(entry.path())?.next().is_none() {
+                state.empty_dirs.insert(relative_path);
             }
         }
 
-        Ok(WorkspaceState { files, metadata })
+        Ok(state)
     }
 
-    /// Creates a tree object from the workspace state
-    pub fn create_tree(&self, repo: &Repository) -> io::Result<String> {
-        let mut tree_entries = Vec::new();
-        
-        for (path, hash) in &self.files {
-            if let Some(metadata) = self.metadata.get(path) {
-                let mode = metadata.mode() as u32;
-                let name = path.file_name()
-                    .ok_or_else(|| io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        "Invalid file path",
-                    ))?
-                    .to_string_lossy()
-                    .to_string();
-                
-                tree_entries.push((name, mode, hash.clone()));
+    /// Reads a state saved by `create_tree`
+    pub fn from_tree(repo: &Repository, tree_hash: Oid) -> io::Result<WorkspaceState> {
+        let mut state = WorkspaceState::default();
+        let mut mtimes = HashMap::new();
+        for (path, entry) in flatten_tree(&repo.git_dir, tree_hash)? {
+            if path == Path::new(MTIMES_FILE) {
+                mtimes = match repo.read_object(entry.hash)? {
+                    Object::Blob(content) => parse_mtimes(&content),
+                    _ => HashMap::new(),
+                };
+            } else if path.file_name().is_some_and(|name| name == KEEP_FILE) {
+                state.empty_dirs.insert(path.parent().unwrap_or(Path::new("")).to_path_buf());
+            } else {
+                state.metadata.insert(path.clone(), FileMetadata { mode: entry.mode, mtime: None });
+                state.files.insert(path, entry.hash);
             }
         }
-        
-        // Sort entries for consistent tree creation
-        tree_entries.sort_by(|a, b| a.0.cmp(&b.0));
-        
-        // Create tree object
-        let tree = Object::new_tree_from_entries(tree_entries);
-        let tree_hash = tree.hash();
-        tree.write_to_objects_dir(&repo.git_dir)?;
-        
-        Ok(tree_hash)
+        for (path, mtime) in mtimes {
+            if let Some(metadata) = state.metadata.get_mut(&path) {
+                metadata.mtime = Some(mtime);
+            }
+        }
+        Ok(state)
+    }
+
+    /// Creates a tree object from the workspace state. Empty directories
+    /// hold a KEEP_FILE and the modification times go into MTIMES_FILE.
+    pub fn create_tree(&self, transaction: &mut ObjectTransaction) -> io::Result<Oid> {
+        let mut entries = Vec::new();
+        let mut mtimes = String::new();
+
+        let mut paths: Vec<&PathBuf> = self.files.keys().collect();
+        paths.sort();
+        for path in paths {
+            let metadata = self.metadata.get(path);
+            let mode = metadata.map_or(0o100644, |metadata| normalize_mode(metadata.mode));
+            entries.push((path.clone(), mode, self.files[path]));
+
+            if let Some(since_epoch) = metadata.and_then(|metadata| metadata.mtime?.duration_since(UNIX_EPOCH).ok()) {
+                mtimes.push_str(&format!("{}.{:09} {}\n", since_epoch.as_secs(), since_epoch.subsec_nanos(), path.display()));
+            }
+        }
+
+        if !self.empty_dirs.is_empty() {
+            let keep = transaction.add(Object::new_blob(Vec::new()))?;
+            for dir in &self.empty_dirs {
+                entries.push((dir.join(KEEP_FILE), 0o100644, keep));
+            }
+        }
+        if !mtimes.is_empty() {
+            let hash = transaction.add(Object::new_blob(mtimes.into_bytes()))?;
+            entries.push((PathBuf::from(MTIMES_FILE), 0o100644, hash));
+        }
+
+        let tree = build_tree_in(transaction, &entries)?;
+        transaction.add(tree)
     }
 
-    /// Applies the workspace state to the working directory
-    pub fn apply_to_workspace(&self, repo: &Repository) -> io::Result<()> {
+    /// Applies the workspace state to the working directory,
+    /// reporting each written file to `progress`
+    pub fn apply_to_workspace(&self, repo: &Repository, options: &ApplyOptions, progress: &mut dyn Progress) -> io::Result<()> {
         // First, remove all existing files (except .cobra directory)
-        self.clean_workspace(repo)?;
+        self.clean_workspace(repo, options.keep_untracked)?;
         
         // Then create all files from the state
+        progress.start(self.files.len());
         for (path, hash) in &self.files {
             let full_path = repo.root_path.join(path);
             
@@ -105,7 +190,7 @@ impl WorkspaceState {
             }
             
             // Read blob and write to file
-            let blob = Object::read_from_objects_dir(&repo.git_dir, hash)?;
+            let blob = Object::read_from_objects_dir(&repo.git_dir, *hash)?;
             match blob {
                 Object::Blob(content) => {
                     fs::write(&full_path, content)?;
@@ -113,8 +198,16 @@ impl WorkspaceState {
                     // Restore file permissions if we have metadata
                     if let Some(metadata) = self.metadata.get(path) {
                         let mut perms = fs::metadata(&full_path)?.permissions();
-                        perms.set_mode(metadata.mode());
+                        perms.set_mode(metadata.mode);
                         fs::set_permissions(&full_path, perms)?;
+
+                        // The content is the saved blob verbatim, so its old
+                        // mtime is still accurate. Anything that merges content
+                        // must leave the fresh mtime alone. The mode may have
+                        // just made the file read-only, so open it for reading.
+                        if let (true, Some(mtime)) = (options.preserve_mtime, metadata.mtime) {
+                            fs::File::open(&full_path)?.set_times(fs::FileTimes::new().set_modified(mtime))?;
+                        }
                     }
                 }
                 _ => return Err(io::Error::new(
@@ -122,23 +215,48 @@ impl WorkspaceState {
                     "Workspace state contains non-blob object",
                 )),
             }
+            progress.tick(path);
+        }
+        progress.finish();
+
+        for dir in &self.empty_dirs {
+            fs::create_dir_all(repo.root_path.join(dir))?;
         }
+
+        let mut paths: Vec<PathBuf> = self.files.keys().cloned().collect();
+        paths.sort();
+        repo.events.emit(RepositoryEvent::WorktreeFilesChanged { paths });
         
         Ok(())
     }
 
-    /// Cleans the working directory (removes all files except .cobra)
-    fn clean_workspace(&self, repo: &Repository) -> io::Result<()> {
-        let cobra_dir = repo.root_path.join(".cobra");
+    /// Cleans the working directory (removes all files except .cobra, or
+    /// only the tracked ones and the directories they leave empty)
+    fn clean_workspace(&self, repo: &Repository, keep_untracked: bool) -> io::Result<()> {
+        if keep_untracked {
+            for entry in repo.index.entries() {
+                match fs::remove_file(repo.root_path.join(&entry.path)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+                let mut dir = entry.path.parent();
+                while let Some(parent) = dir.filter(|parent| !parent.as_os_str().is_empty()) {
+                    if fs::remove_dir(repo.root_path.join(parent)).is_err() {
+                        break;
+                    }
+                    dir = parent.parent();
+                }
+            }
+            return Ok(());
+        }
+
+        // Ignored files were never captured, so they are left in place
+        let ignore = IgnoreRules::load(repo)?;
         
         for entry in WalkDir::new(&repo.root_path)
             .min_depth(1)
             .into_iter()
-            .filter_entry(|e| {
-                !e.path().starts_with(&cobra_dir) && 
-                !e.path().to_string_lossy().contains("/.") &&
-                !e.path().file_name().map_or(false, |n| n.to_string_lossy().starts_with("."))
-            })
+            .filter_entry(|e| is_visible(repo, &ignore, e))
         {
             let entry = entry?;
             if entry.file_type().is_file() {
@@ -178,8 +296,8 @@ pub struct StashState {
     pub workspace: WorkspaceState,
     /// Index state (staged changes)
     pub index: HashMap<PathBuf, IndexEntry>,
-    /// Parent commit hash
-    pub parent: String,
+    /// Parent commit
+    pub parent: Oid,
     /// Stash message
     pub message: String,
 }
@@ -196,22 +314,21 @@ impl StashState {
         }
         
         // Get current HEAD commit
-        let ref_store = crate::cobra::core::ref_store::RefStore::new(repo.git_dir.clone());
+        let ref_store = repo.ref_store();
         let head_content = ref_store.read_head()?
             .ok_or_else(|| io::Error::new(
                 io::ErrorKind::NotFound,
                 "HEAD reference not found",
             ))?;
 
-        let parent = if head_content.starts_with("ref: ") {
-            let current_branch_ref = &head_content[5..];
+        let parent = if let Some(current_branch_ref) = head_content.strip_prefix("ref: ") {
             ref_store.read_ref(current_branch_ref)?
                 .ok_or_else(|| io::Error::new(
                     io::ErrorKind::NotFound,
                     "Current branch reference not found",
                 ))?
         } else {
-            head_content
+            head_content.parse()?
         };
 
         Ok(StashState {
@@ -222,30 +339,24 @@ impl StashState {
         })
     }
 
-    /// Creates a commit object from the stash state
-    pub fn create_commit(&self, repo: &Repository) -> io::Result<String> {
+    /// Stages the stash commit and its tree in `transaction`
+    pub fn create_commit(&self, transaction: &mut ObjectTransaction) -> io::Result<Oid> {
         // Create tree from workspace state
-        let tree_hash = self.workspace.create_tree(repo)?;
+        let tree_hash = self.workspace.create_tree(transaction)?;
         
         // Create commit
-        let author = crate::cobra::core::signature::Signature::new(
-            "Your Name".to_string(),
-            "you@example.com".to_string(),
-        );
-        let committer = author.clone();
+        let author = crate::cobra::core::signature::Signature::author(transaction.git_dir())?;
+        let committer = crate::cobra::core::signature::Signature::committer(transaction.git_dir())?;
 
         let commit = Object::new_commit(
             tree_hash,
-            vec![self.parent.clone()],
+            vec![self.parent],
             author,
             committer,
             self.message.clone(),
         );
 
-        let commit_hash = commit.hash();
-        commit.write_to_objects_dir(&repo.git_dir)?;
-        
-        Ok(commit_hash)
+        transaction.add(commit)
     }
 
     /// Applies the stash state to the working directory and index
@@ -253,18 +364,30 @@ impl StashState {
         // Check for conflicts
         let conflicts = self.workspace.check_conflicts(repo)?;
         if !conflicts.is_empty() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::other(
                 format!("Conflicts detected: {:?}", conflicts),
             ));
         }
         
         // Apply workspace state
-        self.workspace.apply_to_workspace(repo)?;
+        self.workspace.apply_to_workspace(repo, &ApplyOptions::default(), &mut NoProgress)?;
         
         // Apply index state (this would require updating the repository's index)
         // For now, we'll just note that this needs to be implemented
         
         Ok(())
     }
-} 
\ No newline at end of file
+}
+
+/// Reads MTIMES_FILE: one `<seconds>.<nanoseconds> <path>` line per file
+fn parse_mtimes(content: &[u8]) -> HashMap<PathBuf, SystemTime> {
+    String::from_utf8_lossy(content)
+        .lines()
+        .filter_map(|line| {
+            let (time, path) = line.split_once(' ')?;
+            let (secs, nanos) = time.split_once('.')?;
+            let since_epoch = Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+            Some((PathBuf::from(path), UNIX_EPOCH + since_epoch))
+        })
+        .collect()
+}
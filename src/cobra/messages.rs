@@ -0,0 +1,770 @@
+// User-facing message catalog
+//
+// Human text is built here, one function per message, from the template
+// constants below. `{}` marks where each argument goes, in order, so a
+// translation only has to replace the templates. Every template is listed in
+// TRANSLATABLE.
+//
+// Machine formats (porcelain records, plumbing output, diff headers) are
+// frozen: scripts parse them, so they are never translated or reworded. The
+// keywords they share live in `machine`.
+
+use std::fmt::{self, Write};
+
+/// Keywords of frozen output formats. Changing one breaks scripts.
+pub mod machine {
+    /// `# branch.oid` before the first commit (status --porcelain=v2)
+    pub const INITIAL_OID: &str = "(initial)";
+    /// `# branch.head` when HEAD is detached (status --porcelain=v2)
+    pub const DETACHED_HEAD: &str = "(detached)";
+    /// `## ...` header on an unborn branch (status --short -b, --porcelain=v1 -b)
+    pub const NO_COMMITS_YET_ON: &str = "No commits yet on";
+    /// `## ...` header when HEAD is detached (status --short -b, --porcelain=v1 -b)
+    pub const NO_BRANCH: &str = "HEAD (no branch)";
+    /// Record kinds printed by verify-index
+    pub const STALE: &str = "stale";
+    pub const MODIFIED: &str = "modified";
+    pub const MISSING: &str = "missing";
+    /// Record kinds printed by fsck
+    pub const MISPLACED: &str = "misplaced";
+    pub const DANGLING: &str = "dangling";
+}
+
+const MISSING_SUBCOMMAND: &str = "No subcommand given, see '{} --help'";
+const INITIALIZED: &str = "Initialized empty Cobra repository in {}";
+const COMMITTED: &str = "[{}] {}";
+const REMOVED_FROM_INDEX: &str = "rm '{}'";
+const RENAMED: &str = "Renamed '{}' to '{}'";
+const HEAD_NOW_AT: &str = "HEAD is now at {} {}";
+const CONFIG_UPDATED: &str = "Updated {}";
+const CONFIG_KEY_NOT_SET: &str = "{} is not set";
+const CONFIG_VALUE_IGNORED: &str = "warning: {}; using the default instead";
+const UNKNOWN_REF: &str = "Unknown ref '{}'";
+const FETCHING_FROM: &str = "From {}";
+const DAEMON_LISTENING: &str = "Listening on {}";
+const DAEMON_EXPORTING: &str = "Exporting {}";
+
+const STATUS_ON_BRANCH: &str = "On branch {}";
+const STATUS_DETACHED_AT: &str = "HEAD detached at {}";
+const STATUS_NO_COMMITS: &str = "No commits yet";
+const STATUS_STAGED: &str = "Changes to be committed:";
+const STATUS_STAGED_HINT: &str = "  (use \"cobra restore --staged <file>...\" to unstage)";
+const STATUS_NEW_FILE: &str = "\tnew file:   {}";
+const STATUS_DELETED: &str = "\tdeleted:    {}";
+const STATUS_NOT_STAGED: &str = "Changes not staged for commit:";
+const STATUS_NOT_STAGED_HINT: &str = "  (use \"cobra add <file>...\" to update what will be committed)";
+const STATUS_MODIFIED: &str = "\tmodified:   {}";
+const STATUS_UNTRACKED: &str = "Untracked files:";
+const STATUS_UNTRACKED_HINT: &str = "  (use \"cobra add <file>...\" to include in what will be committed)";
+const STATUS_CLEAN: &str = "nothing to commit, working tree clean";
+const STATUS_UNMERGED: &str = "Unmerged paths:";
+const STATUS_UNMERGED_HINT: &str = "  (use \"cobra add <file>...\" to mark resolution)";
+const STATUS_BOTH_MODIFIED: &str = "\tboth modified:   {}";
+const STATUS_BOTH_ADDED: &str = "\tboth added:      {}";
+const STATUS_DELETED_BY_US: &str = "\tdeleted by us:   {}";
+const STATUS_DELETED_BY_THEM: &str = "\tdeleted by them: {}";
+const STATUS_MERGING_UNMERGED: &str = "You have unmerged paths.\n  (fix conflicts and run \"cobra commit\")\n  (use \"cobra merge --abort\" to abort the merge)";
+const STATUS_MERGING_FIXED: &str = "All conflicts fixed but you are still merging.\n  (use \"cobra commit\" to conclude merge)";
+const STATUS_REALIGN_HINT: &str = "hint: the index's {} data does not match any file ({} had to be reread);\n\
+                                   hint: run \"cobra verify-index --realign\" or set index.autoRealign to refresh it";
+const INDEX_FOREIGN: &str = "{} differs for all {} files: the index was written on another filesystem";
+const INDEX_REFRESHED: &str = "Refreshed stat data for {} entries";
+
+const NO_BRANCHES: &str = "No branches found";
+const BRANCH_NO_COMMITS: &str = "(no commits)";
+const BRANCH_CREATED: &str = "Created branch '{}'";
+const BRANCH_DELETED: &str = "Deleted branch {} (was {}).";
+const BRANCH_DELETED_UNBORN: &str = "Deleted branch {}.";
+const SWITCHED_TO_BRANCH: &str = "Switched to branch '{}'";
+const SWITCHED_TO_NEW_BRANCH: &str = "Switched to a new branch '{}'";
+const NOT_BRANCH_OR_COMMIT: &str = "'{}' is neither a branch nor a commit";
+const BRANCH_LIST_DETACHED: &str = "(HEAD detached at {})";
+const BRANCH_RENAMED: &str = "Renamed branch '{}' to '{}'";
+const RENAME_DETACHED: &str = "HEAD is detached; name the branch to rename";
+const NO_PREVIOUS_CHECKOUT: &str = "There is no previous branch to switch back to";
+const BRANCH_EXISTS: &str = "A branch named '{}' already exists";
+const SWITCHED_TO_ORPHAN: &str = "Switched to a new branch '{}' with no history";
+const CHECKOUT_REMOVED_FILE: &str = "Removed '{}'";
+const CHECKOUT_REMOVED_DIR: &str = "Removed empty directory '{}/'";
+const CHECKOUT_DISCARDED: &str = "Discarded local changes to '{}'";
+const ALREADY_UP_TO_DATE: &str = "Already up to date.";
+const MERGE_CONFLICTS: &str = "Automatic merge failed; nothing was changed. Conflicting paths: {}";
+const MERGE_COMMIT_MADE: &str = "Merge made by creating a merge commit.";
+const MERGE_CONFLICT_IN: &str = "CONFLICT: Merge conflict in {}";
+const MERGE_STOPPED: &str = "Automatic merge failed; fix conflicts and then commit the result.";
+const MERGE_IN_PROGRESS: &str = "You have not concluded your merge (MERGE_HEAD exists). Commit it or run 'cobra merge --abort'.";
+const NO_MERGE_IN_PROGRESS: &str = "There is no merge to abort (MERGE_HEAD missing).";
+const MERGE_WOULD_OVERWRITE: &str = "Your local changes to the following files would be overwritten by merge:\n{}\nPlease commit your changes or stash them before you merge.";
+const IDENTITY_UNKNOWN: &str = "{} identity unknown\n\n\
+*** Please tell me who you are.\n\n\
+Run\n\n  cobra config --global user.email \"you@example.com\"\n  \
+cobra config --global user.name \"Your Name\"\n\n\
+to set your account's default identity.\n\
+Omit --global to set the identity only in this repository.";
+const NOTHING_TO_AMEND: &str = "You have nothing to amend: the current branch has no commits yet";
+const AMEND_DURING_MERGE: &str = "You are in the middle of a merge -- cannot amend";
+const UNMERGED_FILES: &str = "Committing is not possible because you have unmerged files: {}\nFix them up in the work tree, then mark them resolved with 'cobra add <file>'";
+const FAST_FORWARD_RANGE: &str = "Updating {}..{}";
+const FAST_FORWARD: &str = "Fast-forward";
+const REBASED_ONTO: &str = "Rebased current branch onto '{}'";
+const REBASE_TODO_HELP: &str = "Rebase {} onto {} ({} commands)\n\n\
+Commands:\n\
+p, pick <commit> = use commit\n\
+r, reword <commit> = use commit, but edit the commit message\n\
+s, squash <commit> = use commit, but meld into previous commit\n\
+f, fixup <commit> = like \"squash\", but discard this commit's log message\n\
+d, drop <commit> = remove commit\n\n\
+These lines can be re-ordered; they are executed from top to bottom.\n\
+If you remove everything, the rebase will be aborted.";
+const REBASE_IN_PROGRESS: &str = "A rebase is already in progress; if none is running, remove {}";
+const REBASE_LOCAL_CHANGES: &str = "Cannot rebase: you have uncommitted changes.\nPlease commit or stash them.";
+const REBASE_NOTHING_TO_DO: &str = "Nothing to do";
+const REBASE_BAD_TODO_LINE: &str = "Invalid todo list, line {}: {}; nothing was rewritten";
+const REBASE_COULD_NOT_APPLY: &str = "Could not apply {}... {}: conflicts in {}; nothing was rewritten";
+const REBASE_SUCCEEDED: &str = "Successfully rebased and updated {}.";
+const COMMIT_SCISSORS_HELP: &str = "Do not modify or remove the line above.\nEverything below it will be ignored.";
+const COMMIT_MESSAGE_HELP: &str = "Please enter the commit message for your changes. Lines starting\nwith '#' will be ignored, and an empty message aborts the commit.";
+const EMPTY_COMMIT_MESSAGE: &str = "Aborting commit due to empty commit message.";
+const COMPARED_WITH: &str = "Compared with {}:";
+
+const TAG_DELETED: &str = "Deleted tag '{}' (was {})";
+const IMPORT_SKIPPED: &str = "Skipped '{}': only regular files can be imported";
+
+const NO_STASHES: &str = "No stashes found";
+const STASH_SAVED: &str = "Saved working directory and index state WIP on current branch: {}";
+const STASH_APPLIED: &str = "Applied stash '{}'";
+const STASH_DROPPED: &str = "Dropped {} ({})";
+const STASH_CONFLICTS: &str = "Conflicts detected when applying stash:";
+const STASH_KEPT: &str = "The stash entry is kept in case you need it again.";
+const NO_STASH_ENTRIES: &str = "No stash entries found";
+
+const STAT_FILE_CHANGED: &str = "{} file changed";
+const STAT_FILES_CHANGED: &str = "{} files changed";
+const STAT_INSERTION: &str = "{} insertion(+)";
+const STAT_INSERTIONS: &str = "{} insertions(+)";
+const STAT_DELETION: &str = "{} deletion(-)";
+const STAT_DELETIONS: &str = "{} deletions(-)";
+
+const GC_EXPIRED: &str = "Expired {} reflog entries";
+const GC_REMOVED: &str = "Removed {} unreachable objects";
+const GC_KEPT_RECENT: &str = "Kept {} recent unreachable objects";
+const GC_WROTE_BITMAPS: &str = "Wrote reachability bitmaps for {} commits";
+
+const FSCK_MOVED: &str = "Moved {} {} (was stored as {})";
+const FSCK_NOTHING_LOST: &str = "No lost history to recover";
+const FSCK_RECOVERED: &str = "Recovered {} refs";
+
+const PULL_SINCE: &str = "The following changes since commit {}:";
+const PULL_AVAILABLE: &str = "are available in the Git repository at:";
+const PULL_UP_TO: &str = "for you to fetch changes up to {}:";
+
+const HEAD_UNBORN: &str = "HEAD does not point to a commit yet";
+const BRANCH_NOT_FOUND: &str = "Branch '{}' does not exist";
+const STASH_NOT_FOUND: &str = "Stash '{}' does not exist";
+const NOT_A_BLOB: &str = "Object {} is not a blob";
+const NOT_A_COMMIT: &str = "Object {} is not a commit";
+const NOT_A_TREE: &str = "Object {} is not a tree or commit";
+const NO_SUCH_OBJECT: &str = "No object named '{}'";
+const PREFIX_TOO_SHORT: &str = "'{}' is too short to name an object; use at least {} characters";
+const AMBIGUOUS_PREFIX: &str = "Short object name '{}' is ambiguous; it could be:";
+const CORRUPT_OBJECT: &str = "Object {} is corrupt: {}";
+const HISTORY_TRUNCATED: &str = "error: commit {} not found, history truncated";
+const INVALID_DATE: &str = "Invalid date '{}'; use YYYY-MM-DD, YYYY-MM-DD HH:MM[:SS] [+HHMM], @<seconds> or a relative date such as '2 weeks ago'";
+const PATHSPEC_NO_MATCH: &str = "pathspec '{}' did not match any file known to cobra";
+const PATH_NOT_IN_COMMIT: &str = "path '{}' does not exist in '{}'";
+
+/// Every human template, for translators and for the tests that keep them
+/// out of machine output
+pub const TRANSLATABLE: &[&str] = &[
+    MISSING_SUBCOMMAND, INITIALIZED, COMMITTED, REMOVED_FROM_INDEX, RENAMED, HEAD_NOW_AT,
+    CONFIG_UPDATED, CONFIG_KEY_NOT_SET, CONFIG_VALUE_IGNORED, UNKNOWN_REF, FETCHING_FROM, DAEMON_LISTENING, DAEMON_EXPORTING,
+    STATUS_ON_BRANCH, STATUS_DETACHED_AT, STATUS_NO_COMMITS,
+    STATUS_STAGED, STATUS_STAGED_HINT, STATUS_NEW_FILE, STATUS_DELETED,
+    STATUS_NOT_STAGED, STATUS_NOT_STAGED_HINT, STATUS_MODIFIED, STATUS_UNTRACKED,
+    STATUS_UNTRACKED_HINT, STATUS_CLEAN, STATUS_UNMERGED, STATUS_UNMERGED_HINT, STATUS_BOTH_MODIFIED,
+    STATUS_BOTH_ADDED, STATUS_DELETED_BY_US, STATUS_DELETED_BY_THEM, STATUS_MERGING_UNMERGED,
+    STATUS_MERGING_FIXED, STATUS_REALIGN_HINT, INDEX_FOREIGN, INDEX_REFRESHED,
+    NO_BRANCHES, BRANCH_NO_COMMITS, BRANCH_CREATED, BRANCH_DELETED, BRANCH_DELETED_UNBORN, SWITCHED_TO_BRANCH,
+    SWITCHED_TO_NEW_BRANCH, NOT_BRANCH_OR_COMMIT, BRANCH_LIST_DETACHED, BRANCH_RENAMED, RENAME_DETACHED,
+    NO_PREVIOUS_CHECKOUT, BRANCH_EXISTS, SWITCHED_TO_ORPHAN, CHECKOUT_REMOVED_FILE, CHECKOUT_REMOVED_DIR, CHECKOUT_DISCARDED,
+    ALREADY_UP_TO_DATE, MERGE_CONFLICTS, MERGE_COMMIT_MADE,
+    MERGE_CONFLICT_IN, MERGE_STOPPED, MERGE_IN_PROGRESS, NO_MERGE_IN_PROGRESS, MERGE_WOULD_OVERWRITE,
+    IDENTITY_UNKNOWN, NOTHING_TO_AMEND, AMEND_DURING_MERGE, UNMERGED_FILES, FAST_FORWARD_RANGE, FAST_FORWARD,
+    REBASED_ONTO, REBASE_TODO_HELP, REBASE_IN_PROGRESS, REBASE_LOCAL_CHANGES, REBASE_NOTHING_TO_DO,
+    REBASE_BAD_TODO_LINE, REBASE_COULD_NOT_APPLY, REBASE_SUCCEEDED, COMMIT_MESSAGE_HELP, COMMIT_SCISSORS_HELP, EMPTY_COMMIT_MESSAGE,
+    COMPARED_WITH, TAG_DELETED, IMPORT_SKIPPED,
+    NO_STASHES, STASH_SAVED, STASH_APPLIED, STASH_DROPPED, STASH_CONFLICTS, STASH_KEPT, NO_STASH_ENTRIES,
+    STAT_FILE_CHANGED, STAT_FILES_CHANGED, STAT_INSERTION, STAT_INSERTIONS, STAT_DELETION, STAT_DELETIONS,
+    GC_EXPIRED, GC_REMOVED, GC_KEPT_RECENT, GC_WROTE_BITMAPS,
+    FSCK_MOVED, FSCK_NOTHING_LOST, FSCK_RECOVERED,
+    PULL_SINCE, PULL_AVAILABLE, PULL_UP_TO,
+    HEAD_UNBORN, BRANCH_NOT_FOUND, STASH_NOT_FOUND, NOT_A_BLOB, NOT_A_COMMIT, NOT_A_TREE,
+    NO_SUCH_OBJECT, PREFIX_TOO_SHORT, AMBIGUOUS_PREFIX, CORRUPT_OBJECT, HISTORY_TRUNCATED, INVALID_DATE, PATHSPEC_NO_MATCH,
+    PATH_NOT_IN_COMMIT,
+];
+
+/// Replaces each `{}` in `template` with the next argument
+fn fill(template: &str, args: &[&dyn fmt::Display]) -> String {
+    let mut parts = template.split("{}");
+    let mut text = parts.next().unwrap_or_default().to_string();
+    for (part, arg) in parts.zip(args) {
+        let _ = write!(text, "{}", arg);
+        text.push_str(part);
+    }
+    text
+}
+
+pub fn missing_subcommand(command: &str) -> String {
+    fill(MISSING_SUBCOMMAND, &[&command])
+}
+
+pub fn initialized(path: impl fmt::Display) -> String {
+    fill(INITIALIZED, &[&path])
+}
+
+pub fn committed(short_id: &str, message: &str) -> String {
+    fill(COMMITTED, &[&short_id, &message])
+}
+
+pub fn removed_from_index(path: impl fmt::Display) -> String {
+    fill(REMOVED_FROM_INDEX, &[&path])
+}
+
+pub fn renamed(from: impl fmt::Display, to: impl fmt::Display) -> String {
+    fill(RENAMED, &[&from, &to])
+}
+
+pub fn head_now_at(short_id: &str, subject: &str) -> String {
+    fill(HEAD_NOW_AT, &[&short_id, &subject])
+}
+
+pub fn config_updated(path: impl fmt::Display) -> String {
+    fill(CONFIG_UPDATED, &[&path])
+}
+
+pub fn config_key_not_set(key: &str) -> String {
+    fill(CONFIG_KEY_NOT_SET, &[&key])
+}
+
+pub fn config_value_ignored(error: impl fmt::Display) -> String {
+    fill(CONFIG_VALUE_IGNORED, &[&error])
+}
+
+pub fn unknown_ref(name: &str) -> String {
+    fill(UNKNOWN_REF, &[&name])
+}
+
+pub fn fetching_from(url: &str) -> String {
+    fill(FETCHING_FROM, &[&url])
+}
+
+pub fn daemon_listening(address: impl fmt::Display) -> String {
+    fill(DAEMON_LISTENING, &[&address])
+}
+
+pub fn daemon_exporting(path: impl fmt::Display) -> String {
+    fill(DAEMON_EXPORTING, &[&path])
+}
+
+pub fn status_on_branch(branch: impl fmt::Display) -> String {
+    fill(STATUS_ON_BRANCH, &[&branch])
+}
+
+pub fn status_detached_at(commit: impl fmt::Display) -> String {
+    fill(STATUS_DETACHED_AT, &[&commit])
+}
+
+pub fn status_no_commits() -> String {
+    fill(STATUS_NO_COMMITS, &[])
+}
+
+pub fn status_staged() -> String {
+    fill(STATUS_STAGED, &[])
+}
+
+pub fn status_staged_hint() -> String {
+    fill(STATUS_STAGED_HINT, &[])
+}
+
+pub fn status_new_file(path: impl fmt::Display) -> String {
+    fill(STATUS_NEW_FILE, &[&path])
+}
+
+pub fn status_deleted(path: impl fmt::Display) -> String {
+    fill(STATUS_DELETED, &[&path])
+}
+
+pub fn status_not_staged() -> String {
+    fill(STATUS_NOT_STAGED, &[])
+}
+
+pub fn status_not_staged_hint() -> String {
+    fill(STATUS_NOT_STAGED_HINT, &[])
+}
+
+pub fn status_modified(path: impl fmt::Display) -> String {
+    fill(STATUS_MODIFIED, &[&path])
+}
+
+pub fn status_untracked() -> String {
+    fill(STATUS_UNTRACKED, &[])
+}
+
+pub fn status_untracked_hint() -> String {
+    fill(STATUS_UNTRACKED_HINT, &[])
+}
+
+pub fn status_clean() -> String {
+    fill(STATUS_CLEAN, &[])
+}
+
+pub fn status_unmerged() -> String {
+    fill(STATUS_UNMERGED, &[])
+}
+
+pub fn status_unmerged_hint() -> String {
+    fill(STATUS_UNMERGED_HINT, &[])
+}
+
+pub fn status_both_modified(path: impl fmt::Display) -> String {
+    fill(STATUS_BOTH_MODIFIED, &[&path])
+}
+
+pub fn status_both_added(path: impl fmt::Display) -> String {
+    fill(STATUS_BOTH_ADDED, &[&path])
+}
+
+pub fn status_deleted_by_us(path: impl fmt::Display) -> String {
+    fill(STATUS_DELETED_BY_US, &[&path])
+}
+
+pub fn status_deleted_by_them(path: impl fmt::Display) -> String {
+    fill(STATUS_DELETED_BY_THEM, &[&path])
+}
+
+pub fn status_merging_unmerged() -> String {
+    fill(STATUS_MERGING_UNMERGED, &[])
+}
+
+pub fn status_merging_fixed() -> String {
+    fill(STATUS_MERGING_FIXED, &[])
+}
+
+/// `fields` is the stat data that drifted, such as "mtime/ino"
+pub fn status_realign_hint(fields: &str, reread: usize) -> String {
+    fill(STATUS_REALIGN_HINT, &[&fields, &reread])
+}
+
+pub fn index_foreign(fields: &str, files: usize) -> String {
+    fill(INDEX_FOREIGN, &[&fields, &files])
+}
+
+pub fn index_refreshed(entries: usize) -> String {
+    fill(INDEX_REFRESHED, &[&entries])
+}
+
+pub fn no_branches() -> String {
+    fill(NO_BRANCHES, &[])
+}
+
+pub fn branch_no_commits() -> String {
+    fill(BRANCH_NO_COMMITS, &[])
+}
+
+pub fn branch_created(name: &str) -> String {
+    fill(BRANCH_CREATED, &[&name])
+}
+
+/// `short_tip` is empty for a branch without commits
+pub fn branch_deleted(name: &str, short_tip: &str) -> String {
+    if short_tip.is_empty() {
+        fill(BRANCH_DELETED_UNBORN, &[&name])
+    } else {
+        fill(BRANCH_DELETED, &[&name, &short_tip])
+    }
+}
+
+pub fn switched_to_branch(name: &str) -> String {
+    fill(SWITCHED_TO_BRANCH, &[&name])
+}
+
+pub fn switched_to_new_branch(name: &str) -> String {
+    fill(SWITCHED_TO_NEW_BRANCH, &[&name])
+}
+
+pub fn not_branch_or_commit(spec: &str) -> String {
+    fill(NOT_BRANCH_OR_COMMIT, &[&spec])
+}
+
+pub fn branch_list_detached(short_id: &str) -> String {
+    fill(BRANCH_LIST_DETACHED, &[&short_id])
+}
+
+pub fn branch_renamed(old: &str, new: &str) -> String {
+    fill(BRANCH_RENAMED, &[&old, &new])
+}
+
+pub fn rename_detached() -> String {
+    fill(RENAME_DETACHED, &[])
+}
+
+pub fn no_previous_checkout() -> String {
+    fill(NO_PREVIOUS_CHECKOUT, &[])
+}
+
+pub fn branch_exists(name: &str) -> String {
+    fill(BRANCH_EXISTS, &[&name])
+}
+
+pub fn switched_to_orphan(name: &str) -> String {
+    fill(SWITCHED_TO_ORPHAN, &[&name])
+}
+
+pub fn checkout_removed_file(path: impl fmt::Display) -> String {
+    fill(CHECKOUT_REMOVED_FILE, &[&path])
+}
+
+pub fn checkout_removed_dir(path: impl fmt::Display) -> String {
+    fill(CHECKOUT_REMOVED_DIR, &[&path])
+}
+
+pub fn checkout_discarded(path: impl fmt::Display) -> String {
+    fill(CHECKOUT_DISCARDED, &[&path])
+}
+
+pub fn already_up_to_date() -> String {
+    fill(ALREADY_UP_TO_DATE, &[])
+}
+
+pub fn merge_conflicts(paths: impl fmt::Display) -> String {
+    fill(MERGE_CONFLICTS, &[&paths])
+}
+
+pub fn merge_conflict_in(path: impl fmt::Display) -> String {
+    fill(MERGE_CONFLICT_IN, &[&path])
+}
+
+pub fn merge_stopped() -> String {
+    fill(MERGE_STOPPED, &[])
+}
+
+pub fn merge_in_progress() -> String {
+    fill(MERGE_IN_PROGRESS, &[])
+}
+
+pub fn no_merge_in_progress() -> String {
+    fill(NO_MERGE_IN_PROGRESS, &[])
+}
+
+pub fn merge_would_overwrite(paths: impl fmt::Display) -> String {
+    fill(MERGE_WOULD_OVERWRITE, &[&paths])
+}
+
+/// `role` is "Author" or "Committer"
+pub fn identity_unknown(role: &str) -> String {
+    fill(IDENTITY_UNKNOWN, &[&role])
+}
+
+pub fn nothing_to_amend() -> String {
+    fill(NOTHING_TO_AMEND, &[])
+}
+
+pub fn amend_during_merge() -> String {
+    fill(AMEND_DURING_MERGE, &[])
+}
+
+pub fn unmerged_files(paths: impl fmt::Display) -> String {
+    fill(UNMERGED_FILES, &[&paths])
+}
+
+pub fn merge_commit_made() -> String {
+    fill(MERGE_COMMIT_MADE, &[])
+}
+
+pub fn fast_forward_range(from: &str, to: &str) -> String {
+    fill(FAST_FORWARD_RANGE, &[&from, &to])
+}
+
+pub fn fast_forward() -> String {
+    fill(FAST_FORWARD, &[])
+}
+
+pub fn rebased_onto(branch: &str) -> String {
+    fill(REBASED_ONTO, &[&branch])
+}
+
+/// The comment block under an interactive rebase's todo list
+pub fn rebase_todo_help(range: impl fmt::Display, onto: impl fmt::Display, count: usize) -> String {
+    fill(REBASE_TODO_HELP, &[&range, &onto, &count])
+}
+
+pub fn rebase_in_progress(state_dir: impl fmt::Display) -> String {
+    fill(REBASE_IN_PROGRESS, &[&state_dir])
+}
+
+pub fn rebase_local_changes() -> String {
+    fill(REBASE_LOCAL_CHANGES, &[])
+}
+
+pub fn rebase_nothing_to_do() -> String {
+    fill(REBASE_NOTHING_TO_DO, &[])
+}
+
+pub fn rebase_bad_todo_line(line: usize, reason: impl fmt::Display) -> String {
+    fill(REBASE_BAD_TODO_LINE, &[&line, &reason])
+}
+
+pub fn rebase_could_not_apply(commit: impl fmt::Display, subject: impl fmt::Display, paths: impl fmt::Display) -> String {
+    fill(REBASE_COULD_NOT_APPLY, &[&commit, &subject, &paths])
+}
+
+pub fn rebase_succeeded(reference: impl fmt::Display) -> String {
+    fill(REBASE_SUCCEEDED, &[&reference])
+}
+
+/// The comment block under a commit message opened in the editor
+pub fn commit_message_help() -> String {
+    fill(COMMIT_MESSAGE_HELP, &[])
+}
+
+/// Explains the scissors line above the diff of `commit -v`
+pub fn commit_scissors_help() -> String {
+    fill(COMMIT_SCISSORS_HELP, &[])
+}
+
+pub fn empty_commit_message() -> String {
+    fill(EMPTY_COMMIT_MESSAGE, &[])
+}
+
+pub fn compared_with(branch: &str) -> String {
+    fill(COMPARED_WITH, &[&branch])
+}
+
+pub fn tag_deleted(name: &str, short_target: &str) -> String {
+    fill(TAG_DELETED, &[&name, &short_target])
+}
+
+pub fn import_skipped(path: impl fmt::Display) -> String {
+    fill(IMPORT_SKIPPED, &[&path])
+}
+
+pub fn no_stashes() -> String {
+    fill(NO_STASHES, &[])
+}
+
+pub fn stash_saved(short_id: &str) -> String {
+    fill(STASH_SAVED, &[&short_id])
+}
+
+pub fn stash_applied(stash: &str) -> String {
+    fill(STASH_APPLIED, &[&stash])
+}
+
+pub fn stash_dropped(stash: &str, short_id: &str) -> String {
+    fill(STASH_DROPPED, &[&stash, &short_id])
+}
+
+pub fn stash_conflicts() -> String {
+    fill(STASH_CONFLICTS, &[])
+}
+
+pub fn stash_kept() -> String {
+    fill(STASH_KEPT, &[])
+}
+
+pub fn no_stash_entries() -> String {
+    fill(NO_STASH_ENTRIES, &[])
+}
+
+/// The last line of a diffstat. A side without changes is left out unless
+/// both are.
+pub fn diffstat_summary(files: usize, insertions: usize, deletions: usize) -> String {
+    let plural = |count: usize, one: &str, many: &str| fill(if count == 1 { one } else { many }, &[&count]);
+    let mut parts = vec![plural(files, STAT_FILE_CHANGED, STAT_FILES_CHANGED)];
+    if insertions > 0 || deletions == 0 {
+        parts.push(plural(insertions, STAT_INSERTION, STAT_INSERTIONS));
+    }
+    if deletions > 0 || insertions == 0 {
+        parts.push(plural(deletions, STAT_DELETION, STAT_DELETIONS));
+    }
+    parts.join(", ")
+}
+
+pub fn gc_expired(entries: usize) -> String {
+    fill(GC_EXPIRED, &[&entries])
+}
+
+pub fn gc_removed(objects: usize) -> String {
+    fill(GC_REMOVED, &[&objects])
+}
+
+pub fn gc_kept_recent(objects: usize) -> String {
+    fill(GC_KEPT_RECENT, &[&objects])
+}
+
+pub fn gc_wrote_bitmaps(commits: usize) -> String {
+    fill(GC_WROTE_BITMAPS, &[&commits])
+}
+
+pub fn fsck_moved(kind: &str, hash: &str, stored_as: &str) -> String {
+    fill(FSCK_MOVED, &[&kind, &hash, &stored_as])
+}
+
+pub fn fsck_nothing_lost() -> String {
+    fill(FSCK_NOTHING_LOST, &[])
+}
+
+pub fn fsck_recovered(refs: usize) -> String {
+    fill(FSCK_RECOVERED, &[&refs])
+}
+
+pub fn pull_since(commit: &str) -> String {
+    fill(PULL_SINCE, &[&commit])
+}
+
+pub fn pull_available() -> String {
+    fill(PULL_AVAILABLE, &[])
+}
+
+pub fn pull_up_to(commit: &str) -> String {
+    fill(PULL_UP_TO, &[&commit])
+}
+
+pub fn head_unborn() -> String {
+    fill(HEAD_UNBORN, &[])
+}
+
+pub fn branch_not_found(name: &str) -> String {
+    fill(BRANCH_NOT_FOUND, &[&name])
+}
+
+pub fn stash_not_found(stash: &str) -> String {
+    fill(STASH_NOT_FOUND, &[&stash])
+}
+
+pub fn not_a_blob(hash: &str) -> String {
+    fill(NOT_A_BLOB, &[&hash])
+}
+
+pub fn not_a_commit(hash: &str) -> String {
+    fill(NOT_A_COMMIT, &[&hash])
+}
+
+pub fn not_a_tree(hash: &str) -> String {
+    fill(NOT_A_TREE, &[&hash])
+}
+
+pub fn no_such_object(name: &str) -> String {
+    fill(NO_SUCH_OBJECT, &[&name])
+}
+
+pub fn prefix_too_short(prefix: &str, min_len: usize) -> String {
+    fill(PREFIX_TOO_SHORT, &[&prefix, &min_len])
+}
+
+pub fn corrupt_object(hash: &str, error: impl fmt::Display) -> String {
+    fill(CORRUPT_OBJECT, &[&hash, &error])
+}
+
+pub fn history_truncated(hash: &str) -> String {
+    fill(HISTORY_TRUNCATED, &[&hash])
+}
+
+pub fn invalid_date(value: &str) -> String {
+    fill(INVALID_DATE, &[&value])
+}
+
+pub fn ambiguous_prefix(prefix: &str) -> String {
+    fill(AMBIGUOUS_PREFIX, &[&prefix])
+}
+
+pub fn pathspec_no_match(path: impl fmt::Display) -> String {
+    fill(PATHSPEC_NO_MATCH, &[&path])
+}
+
+pub fn path_not_in_commit(path: impl fmt::Display, commit: &str) -> String {
+    fill(PATH_NOT_IN_COMMIT, &[&path, &commit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io;
+    use crate::cobra::commands::{add, commit::commit, fsck, ls_files, status, verify_index};
+    use crate::cobra::core::object::Object;
+    use crate::cobra::state::{CommandContext, GlobalOpts, OutputHandle};
+    use crate::cobra::test_support::temp_repo;
+
+    /// The literal words of every template, the parts a translation changes
+    fn translatable_fragments() -> Vec<&'static str> {
+        TRANSLATABLE.iter()
+            .flat_map(|template| template.split("{}"))
+            .map(str::trim)
+            .filter(|fragment| fragment.len() >= 4)
+            .collect()
+    }
+
+    fn assert_untranslated(command: &str, output: &str) {
+        for fragment in translatable_fragments() {
+            assert!(!output.contains(fragment), "{} output contains {:?}:\n{}", command, fragment, output);
+        }
+    }
+
+    #[test]
+    fn test_templates_are_filled_in_order() {
+        assert_eq!(renamed("a.txt", "b.txt"), "Renamed 'a.txt' to 'b.txt'");
+        assert_eq!(branch_deleted("topic", "1234567"), "Deleted branch topic (was 1234567).");
+        assert_eq!(branch_deleted("topic", ""), "Deleted branch topic.");
+        assert_eq!(status_modified("src/lib.rs"), "\tmodified:   src/lib.rs");
+        // Missing arguments leave the rest of the template out rather than panicking
+        assert_eq!(fill("{} and {}", &[&1]), "1 and ");
+
+        let unique: HashSet<_> = TRANSLATABLE.iter().collect();
+        assert_eq!(unique.len(), TRANSLATABLE.len());
+        for keyword in [machine::INITIAL_OID, machine::DETACHED_HEAD] {
+            assert!(!TRANSLATABLE.iter().any(|template| template.contains(keyword)), "{}", keyword);
+        }
+    }
+
+    #[test]
+    fn test_machine_output_has_no_translatable_text() -> io::Result<()> {
+        let (temp_dir, repo) = temp_repo()?;
+        let root = temp_dir.path();
+        fs::write(root.join("tracked.txt"), "one\n")?;
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        add::run(&mut ctx, &["tracked.txt".to_string()], &add::AddOptions::default())?;
+        commit(&ctx.repo, "Initial commit")?;
+        fs::write(root.join("tracked.txt"), "one\ntwo\n")?;
+        fs::write(root.join("untracked.txt"), "new\n")?;
+        ctx.repo.write_object(&Object::new_blob(b"lost\n".to_vec()))?;
+
+        let mut capture = |command: &str, run: &dyn Fn(&mut CommandContext) -> io::Result<()>| -> io::Result<String> {
+            ctx.out = OutputHandle::buffer(false);
+            run(&mut ctx)?;
+            let output = ctx.out.captured();
+            assert!(!output.is_empty(), "{} printed nothing", command);
+            Ok(output)
+        };
+
+        // The long format is where the human text goes, so the check can see it
+        let long = capture("status", &|ctx| status::run(ctx, &status::StatusOptions::default()))?;
+        assert!(translatable_fragments().iter().any(|fragment| long.contains(fragment)), "{}", long);
+
+        let porcelain = status::StatusOptions {
+            format: status::StatusFormat::PorcelainV2,
+            branch: true,
+            null_terminated: false,
+        };
+        let output = capture("status --porcelain=v2", &|ctx| status::run(ctx, &porcelain))?;
+        assert_untranslated("status --porcelain=v2", &output);
+        let output = capture("ls-files --eol", &|ctx| ls_files::run(ctx, &ls_files::LsFilesOptions { eol: true, ..Default::default() }))?;
+        assert_untranslated("ls-files --eol", &output);
+        let output = capture("verify-index", &|ctx| verify_index::run(ctx, false))?;
+        assert_untranslated("verify-index", &output);
+        let output = capture("fsck", &|ctx| fsck::run(ctx, &fsck::FsckOptions::default()))?;
+        assert_untranslated("fsck", &output);
+        Ok(())
+    }
+}
@@ -2,4 +2,7 @@ pub mod cli;
 pub mod commands;
 pub mod core;
 pub mod utils;
-pub mod state; 
\ No newline at end of file
+pub mod state;
+pub mod messages;
+#[cfg(test)]
+pub mod test_support;
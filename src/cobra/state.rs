@@ -1 +1,132 @@
-// Global state management 
\ No newline at end of file
+// Global state management
+//
+// The CLI parses the global flags and opens the repository once, then hands
+// the resulting CommandContext to whichever command runs.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::core::ref_store::RefStore;
+
+/// Flags accepted by every command
+#[derive(Debug, Clone, Default)]
+pub struct GlobalOpts {
+    /// Suppress informational messages (-q)
+    pub quiet: bool,
+    /// Run as if started in this directory (-C)
+    pub directory: Option<PathBuf>,
+    /// Use this directory as the work tree (--work-tree)
+    pub work_tree: Option<PathBuf>,
+}
+
+enum Sink {
+    Stdout(io::Stdout),
+    Buffer(Vec<u8>),
+}
+
+/// Where commands write their output. Regular output goes through the
+/// `Write` impl; progress notes such as "Created branch" go through `note`
+/// and are dropped in quiet mode.
+pub struct OutputHandle {
+    sink: Sink,
+    quiet: bool,
+}
+
+impl OutputHandle {
+    pub fn stdout(quiet: bool) -> OutputHandle {
+        OutputHandle { sink: Sink::Stdout(io::stdout()), quiet }
+    }
+
+    /// An output that collects everything in memory, for tests
+    pub fn buffer(quiet: bool) -> OutputHandle {
+        OutputHandle { sink: Sink::Buffer(Vec::new()), quiet }
+    }
+
+    /// Writes an informational line unless quiet mode is on
+    pub fn note(&mut self, message: fmt::Arguments) -> io::Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
+        self.write_fmt(message)?;
+        self.write_all(b"\n")
+    }
+
+    /// Returns what has been written to a buffer output
+    pub fn captured(&self) -> String {
+        match &self.sink {
+            Sink::Buffer(buffer) => String::from_utf8_lossy(buffer).into_owned(),
+            Sink::Stdout(_) => String::new(),
+        }
+    }
+}
+
+impl Write for OutputHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.sink {
+            Sink::Stdout(stdout) => stdout.write(buf),
+            Sink::Buffer(buffer) => buffer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Stdout(stdout) => stdout.flush(),
+            Sink::Buffer(_) => Ok(()),
+        }
+    }
+}
+
+/// Everything a command needs: the open repository, its refs, the output
+/// and the global flags
+pub struct CommandContext {
+    pub repo: Repository,
+    pub refs: RefStore,
+    pub out: OutputHandle,
+    pub opts: GlobalOpts,
+}
+
+impl CommandContext {
+    /// Opens the repository in the current directory, honouring `--work-tree`
+    pub fn open(opts: GlobalOpts) -> io::Result<CommandContext> {
+        let repo = Repository::open_with_work_tree(".", opts.work_tree.clone())?;
+        let out = OutputHandle::stdout(opts.quiet);
+        Ok(CommandContext::new(repo, out, opts))
+    }
+
+    pub fn new(repo: Repository, out: OutputHandle, opts: GlobalOpts) -> CommandContext {
+        let refs = repo.ref_store();
+        CommandContext { repo, refs, out, opts }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cobra::commands::{branch, commit::commit};
+    use crate::cobra::test_support::temp_repo;
+
+    #[test]
+    fn test_commands_write_to_context_output() -> io::Result<()> {
+        let (_temp_dir, repo) = temp_repo()?;
+        commit(&repo, "Initial commit")?;
+
+        let mut ctx = CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default());
+        branch::create(&mut ctx, "feature", None)?;
+        branch::list(&mut ctx, &branch::ListOptions::default())?;
+        let output = ctx.out.captured();
+        assert!(output.starts_with("Created branch 'feature'\n"));
+        assert!(output.contains("\n* main "));
+
+        // Quiet mode drops the notes but keeps the listing
+        let opts = GlobalOpts { quiet: true, ..GlobalOpts::default() };
+        let mut ctx = CommandContext::new(ctx.repo, OutputHandle::buffer(true), opts);
+        branch::create(&mut ctx, "other", None)?;
+        branch::list(&mut ctx, &branch::ListOptions::default())?;
+        let output = ctx.out.captured();
+        assert!(!output.contains("Created branch"));
+        assert!(output.contains("other "));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+// Fixtures shared by the unit tests
+use std::fs;
+use std::io;
+use std::path::Path;
+use tempfile::TempDir;
+use crate::cobra::commands::add::add_file;
+use crate::cobra::commands::commit::commit;
+use crate::cobra::core::config::ConfigDocument;
+use crate::cobra::core::object::Object;
+use crate::cobra::core::oid::Oid;
+use crate::cobra::core::repository::Repository;
+use crate::cobra::core::signature::Signature;
+use crate::cobra::core::tree::Tree;
+use crate::cobra::state::{CommandContext, GlobalOpts, OutputHandle};
+
+/// Stand-in commit hashes for tests that only move refs
+pub const MAIN_COMMIT: &str = "1111111111111111111111111111111111111111";
+pub const FEATURE_COMMIT: &str = "2222222222222222222222222222222222222222";
+
+/// Initializes a repository at `path` whose config names the author and
+/// committer, so that tests can commit in it
+pub fn init_repo(path: &Path) -> io::Result<Repository> {
+    let repo = Repository::init(path.to_str().unwrap())?;
+    let config_path = repo.git_dir.join("config");
+    let mut config = ConfigDocument::load(&config_path)?;
+    config.set("user.name", "Cobra Tests")?;
+    config.set("user.email", "tests@example.com")?;
+    config.save(&config_path)?;
+    Ok(repo)
+}
+
+/// A new, empty repository in a temporary directory
+pub fn temp_repo() -> io::Result<(TempDir, Repository)> {
+    let temp_dir = TempDir::new()?;
+    let repo = init_repo(temp_dir.path())?;
+    Ok((temp_dir, repo))
+}
+
+/// A context for `repo` whose output is captured in memory
+pub fn context(repo: Repository) -> CommandContext {
+    CommandContext::new(repo, OutputHandle::buffer(false), GlobalOpts::default())
+}
+
+/// A repository holding `files`, each a path and its content, committed
+/// together as "Initial commit"
+pub fn repo_with_files(files: &[(&str, &str)]) -> io::Result<(TempDir, CommandContext)> {
+    let (temp_dir, mut repo) = temp_repo()?;
+    for (name, content) in files {
+        let path = temp_dir.path().join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+        add_file(&mut repo, Path::new(name))?;
+    }
+    commit(&repo, "Initial commit")?;
+    Ok((temp_dir, context(repo)))
+}
+
+/// Writes `content` to `name`, stages it and commits it with `message`
+pub fn commit_file(ctx: &mut CommandContext, name: &str, content: &str, message: &str) -> io::Result<Oid> {
+    fs::write(ctx.repo.root_path.join(name), content)?;
+    add_file(&mut ctx.repo, Path::new(name))?;
+    commit(&ctx.repo, message)
+}
+
+/// Writes a commit of the empty tree with a fixed timestamp, so that tests
+/// ordering commits by date are predictable
+pub fn commit_at(repo: &Repository, parents: &[Oid], message: &str, timestamp: u64) -> io::Result<Oid> {
+    let tree = repo.write_object(&Tree::new().to_object())?;
+    let mut signature = Signature::new("Tester".to_string(), "tester@example.com".to_string());
+    signature.timestamp = timestamp;
+    repo.write_object(&Object::new_commit(tree, parents.to_vec(), signature.clone(), signature, message.to_string()))
+}
+
+/// base - m1 (main, HEAD), base - f1 - f2 (feature), with the commits in
+/// that order
+pub fn forked_history() -> io::Result<(TempDir, Repository, [Oid; 4])> {
+    let (temp_dir, repo) = temp_repo()?;
+    let base = commit_at(&repo, &[], "base", 1000)?;
+    let m1 = commit_at(&repo, &[base], "m1", 2000)?;
+    let f1 = commit_at(&repo, &[base], "f1", 3000)?;
+    let f2 = commit_at(&repo, &[f1], "f2", 4000)?;
+    let refs = repo.ref_store();
+    refs.update_ref("refs/heads/main", m1, "test")?;
+    refs.update_ref("refs/heads/feature", f2, "test")?;
+    Ok((temp_dir, repo, [base, m1, f1, f2]))
+}
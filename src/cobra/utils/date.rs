@@ -0,0 +1,183 @@
+// Parsing of user-supplied dates such as `--since=2024-01-31`
+
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::cobra::messages;
+
+/// Seconds in each unit a relative date may use. Months and years are
+/// approximated as 30 and 365 days.
+const UNITS: &[(&str, u64)] = &[
+    ("second", 1),
+    ("minute", 60),
+    ("hour", 3600),
+    ("day", 86400),
+    ("week", 7 * 86400),
+    ("month", 30 * 86400),
+    ("year", 365 * 86400),
+];
+
+/// Parses a date into seconds since the Unix epoch, relative dates
+/// counting back from now. See `parse_at` for the accepted formats.
+pub fn parse(value: &str) -> io::Result<u64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    parse_at(value, now)
+}
+
+/// Parses a date into seconds since the Unix epoch. Accepted are
+/// `YYYY-MM-DD` (midnight UTC), `YYYY-MM-DD HH:MM[:SS]` with a `T` or a
+/// space before the time and an optional `Z`, `+HHMM` or `+HH:MM` offset,
+/// `@<seconds>`, `now`, `yesterday`, and `<n> <unit>[s] ago` (also written
+/// `<n>.<unit>s.ago`), measured back from `now`.
+pub fn parse_at(value: &str, now: u64) -> io::Result<u64> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, messages::invalid_date(value));
+    let value = value.trim();
+    let lower = value.to_ascii_lowercase();
+    match lower.as_str() {
+        "now" => return Ok(now),
+        "yesterday" => return Ok(now.saturating_sub(86400)),
+        _ => {}
+    }
+    if let Some(seconds) = value.strip_prefix('@') {
+        return seconds.parse().map_err(|_| invalid());
+    }
+    if let Some(ago) = lower.replace('.', " ").strip_suffix(" ago") {
+        return relative(ago).map(|seconds| now.saturating_sub(seconds)).ok_or_else(invalid);
+    }
+    absolute(value).ok_or_else(invalid)
+}
+
+/// The length of a `<n> <unit>[s]` span, in seconds
+fn relative(span: &str) -> Option<u64> {
+    let mut words = span.split_whitespace();
+    let count: u64 = words.next()?.parse().ok()?;
+    let unit = words.next()?;
+    if words.next().is_some() {
+        return None;
+    }
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+    let (_, seconds) = UNITS.iter().find(|(name, _)| *name == unit)?;
+    count.checked_mul(*seconds)
+}
+
+/// An ISO 8601 style date with optional time and offset
+fn absolute(value: &str) -> Option<u64> {
+    let (date, rest) = value.split_at(value.find(['T', ' ']).unwrap_or(value.len()));
+    let days = days_from_date(date)?;
+    let rest = rest.get(1..).unwrap_or_default().trim();
+
+    let time_end = rest.find(['Z', '+', '-', ' ']).unwrap_or(rest.len());
+    let (time, offset) = rest.split_at(time_end);
+    let seconds = match time {
+        "" => 0,
+        time => seconds_from_time(time)?,
+    };
+    let offset = match offset.trim() {
+        "" | "Z" => 0,
+        offset => offset_seconds(offset)?,
+    };
+    u64::try_from(days * 86400 + seconds - offset).ok()
+}
+
+/// Days since 1970-01-01 for `YYYY-MM-DD` (Howard Hinnant's algorithm)
+fn days_from_date(date: &str) -> Option<i64> {
+    let mut fields = date.split('-');
+    let (year, month, day) = (fields.next()?, fields.next()?, fields.next()?);
+    if fields.next().is_some() || year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (year, month, day): (i64, i64, i64) = (number(year)?, number(month)?, number(day)?);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let month_days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if !(1..=month_days).contains(&day) {
+        return None;
+    }
+
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146097 + day_of_era - 719468)
+}
+
+/// Seconds since midnight for `HH:MM` or `HH:MM:SS`
+fn seconds_from_time(time: &str) -> Option<i64> {
+    let fields: Vec<&str> = time.split(':').collect();
+    if !(2..=3).contains(&fields.len()) || fields.iter().any(|field| field.len() != 2) {
+        return None;
+    }
+    let hours: i64 = number(fields[0])?;
+    let minutes: i64 = number(fields[1])?;
+    let seconds: i64 = fields.get(2).map_or(Some(0), |field| number(field))?;
+    (hours < 24 && minutes < 60 && seconds < 60).then_some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Seconds east of UTC for `+HHMM` or `+HH:MM`
+fn offset_seconds(offset: &str) -> Option<i64> {
+    let sign = match offset.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits = offset[1..].replacen(':', "", 1);
+    // Checked before slicing, which would split a multi-byte character
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i64 = number(&digits[..2])?;
+    let minutes: i64 = number(&digits[2..])?;
+    (minutes < 60).then_some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Parses a field made of ASCII digits only
+fn number(digits: &str) -> Option<i64> {
+    digits.bytes().all(|b| b.is_ascii_digit()).then(|| digits.parse().ok()).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_absolute_dates() -> io::Result<()> {
+        assert_eq!(parse_at("1970-01-01", 0)?, 0);
+        assert_eq!(parse_at("2024-01-31", 0)?, 1706659200);
+        assert_eq!(parse_at("2024-02-29 13:45:10", 0)?, 1709214310);
+        assert_eq!(parse_at("2024-02-29T13:45:10Z", 0)?, 1709214310);
+        assert_eq!(parse_at("2024-02-29T13:45", 0)?, 1709214300);
+        assert_eq!(parse_at("2024-02-29 15:45:10 +0200", 0)?, 1709214310);
+        assert_eq!(parse_at("2024-02-29T08:15:10-05:30", 0)?, 1709214310);
+        assert_eq!(parse_at("@1709214310", 0)?, 1709214310);
+
+        for bad in ["", "2023-02-29", "2024-13-01", "2024-1-31", "24-01-31", "2024-01-31 25:00", "2024-01-31 12", "2024-01-31 12:00 0200", "2024-01-31 12:00 +é12", "2024-01-31T12:00+1é2", "1969-12-31", "@-5", "tomorrow"] {
+            let error = parse_at(bad, 0).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{:?}", bad);
+            assert!(error.to_string().contains("YYYY-MM-DD"), "{}", error);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_dates() -> io::Result<()> {
+        let now = 1_000_000_000;
+        assert_eq!(parse_at("now", now)?, now);
+        assert_eq!(parse_at("Yesterday", now)?, now - 86400);
+        assert_eq!(parse_at("2 weeks ago", now)?, now - 14 * 86400);
+        assert_eq!(parse_at("1 day ago", now)?, now - 86400);
+        assert_eq!(parse_at("3.hours.ago", now)?, now - 3 * 3600);
+        assert_eq!(parse_at("1 year ago", now)?, now - 365 * 86400);
+        // Far enough back saturates at the epoch
+        assert_eq!(parse_at("100 years ago", 0)?, 0);
+
+        for bad in ["weeks ago", "2 fortnights ago", "2 weeks", "two weeks ago", "2 weeks from now ago"] {
+            assert_eq!(parse_at(bad, now).unwrap_err().kind(), io::ErrorKind::InvalidInput, "{:?}", bad);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+// Launching the user's text editor
+//
+// The editor is taken from COBRA_EDITOR, then core.editor, then VISUAL and
+// EDITOR, falling back to vi. Like git, the command is run through the shell
+// so that values such as "code --wait" work.
+
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use crate::cobra::core::config::Config;
+
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Picks the editor command, `config` supplying core.editor
+pub fn editor_command(config: &Config) -> String {
+    let from_env = |name: &str| env::var(name).ok().filter(|value| !value.trim().is_empty());
+    from_env("COBRA_EDITOR")
+        .or_else(|| config.get("core.editor").map(str::to_string))
+        .or_else(|| from_env("VISUAL"))
+        .or_else(|| from_env("EDITOR"))
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string())
+}
+
+/// Opens `path` in `editor` and waits for it to exit
+pub fn edit_file(editor: &str, path: &Path) -> io::Result<()> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(editor)
+        .arg(path)
+        .status()
+        .map_err(|e| io::Error::new(e.kind(), format!("Could not launch editor '{}': {}", editor, e)))?;
+    if !status.success() {
+        return Err(io::Error::other(format!("Editor '{}' exited with {}", editor, status)));
+    }
+    Ok(())
+}
@@ -1 +1,503 @@
-// Filesystem utilities 
\ No newline at end of file
+// Filesystem access behind a trait
+//
+// Ref, index and object storage reach the disk through a `Vfs` instead of
+// std::fs, so the same code runs against `RealFs` in production and against
+// `MemoryFs` in tests. The in-memory implementation needs no temporary
+// directories and can be told to fail specific operations, which is the only
+// practical way to exercise paths like a full disk halfway through a ref
+// transaction. Errors are plain io::Errors whose kind carries the meaning;
+// callers never look at the (platform and locale dependent) message text.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+use crate::cobra::utils::tempfiles::{tmp_dir, TempFile};
+
+/// Shared handle to the filesystem a repository lives on
+pub type VfsHandle = Arc<dyn Vfs>;
+
+/// What `metadata` reports about a path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileStat {
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+/// The filesystem operations repository storage is built from. Paths are
+/// absolute (or relative to the process, for `RealFs`); no operation
+/// creates missing parent directories unless it says so.
+pub trait Vfs: Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Replaces the file at `path` so readers see either the old or the new
+    /// content, never a mix
+    fn write_atomic(&self, path: &Path, content: &[u8]) -> io::Result<()>;
+
+    /// Adds `content` to the end of the file, creating it if needed
+    fn append(&self, path: &Path, content: &[u8]) -> io::Result<()>;
+
+    /// Creates an empty file, failing with AlreadyExists if there is one.
+    /// Lock files rely on this being atomic.
+    fn create_new(&self, path: &Path) -> io::Result<()>;
+
+    /// Creates `path` and any missing parents
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn metadata(&self, path: &Path) -> io::Result<FileStat>;
+
+    /// Names of the entries of a directory, sorted
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>>;
+
+    /// Moves a file, replacing whatever is at `to`
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Removes a file
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes an empty directory
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Sets a file's modification time to now
+    fn touch(&self, path: &Path) -> io::Result<()>;
+
+    /// Waits until a file, or the entries of a directory, are on the disk
+    fn sync(&self, path: &Path) -> io::Result<()>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+}
+
+/// Every file below `dir`, relative to it and sorted
+pub fn walk_files(vfs: &dyn Vfs, dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        for name in vfs.read_dir(&dir.join(&relative))? {
+            let child = relative.join(name);
+            if vfs.metadata(&dir.join(&child))?.is_dir {
+                pending.push(child);
+            } else {
+                files.push(child);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The operating system's filesystem. Atomic writes go through a temporary
+/// file in the repository's tmp directory, which is on the same filesystem as
+/// everything they replace.
+pub struct RealFs {
+    tmp: PathBuf,
+}
+
+impl RealFs {
+    pub fn new(git_dir: &Path) -> RealFs {
+        RealFs { tmp: tmp_dir(git_dir) }
+    }
+
+    /// A shareable handle for the repository at `git_dir`
+    pub fn handle(git_dir: &Path) -> VfsHandle {
+        Arc::new(RealFs::new(git_dir))
+    }
+}
+
+impl Vfs for RealFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write_atomic(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        let prefix = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let staged = TempFile::in_dir(&self.tmp, &prefix)?;
+        fs::write(staged.path(), content)?;
+        staged.persist(path)
+    }
+
+    fn append(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        OpenOptions::new().create(true).append(true).open(path)?.write_all(content)
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<()> {
+        OpenOptions::new().write(true).create_new(true).open(path).map(|_| ())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileStat> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileStat {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let mut names = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn touch(&self, path: &Path) -> io::Result<()> {
+        // Read-only files can still have their times set by their owner
+        fs::File::open(path)?.set_times(fs::FileTimes::new().set_modified(SystemTime::now()))
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        fs::File::open(path)?.sync_all()
+    }
+}
+
+/// Kinds of operation a `MemoryFs` fault can target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VfsOp {
+    Read,
+    /// write_atomic, append, create_new, create_dir_all, touch and sync
+    Write,
+    Metadata,
+    ReadDir,
+    Rename,
+    Remove,
+}
+
+struct Fault {
+    op: VfsOp,
+    /// Fails operations on this path or anything below it
+    path: PathBuf,
+    kind: io::ErrorKind,
+    /// Matching operations still allowed to succeed first
+    skip: usize,
+}
+
+struct MemoryFile {
+    content: Vec<u8>,
+    modified: SystemTime,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    files: BTreeMap<PathBuf, MemoryFile>,
+    dirs: BTreeSet<PathBuf>,
+    faults: Vec<Fault>,
+    read_only: bool,
+}
+
+/// A filesystem held in memory, for tests. Directories have to be created
+/// before files go into them, as on disk, and injected faults make chosen
+/// operations fail with a given error kind.
+#[derive(Default)]
+pub struct MemoryFs {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Arc<MemoryFs> {
+        Arc::new(MemoryFs::default())
+    }
+
+    /// Makes every `op` on `path` or below it fail with `kind`
+    pub fn fail(&self, op: VfsOp, path: impl Into<PathBuf>, kind: io::ErrorKind) {
+        self.fail_after(0, op, path, kind);
+    }
+
+    /// Like `fail`, but lets the first `skip` matching operations succeed
+    pub fn fail_after(&self, skip: usize, op: VfsOp, path: impl Into<PathBuf>, kind: io::ErrorKind) {
+        self.lock().faults.push(Fault { op, path: path.into(), kind, skip });
+    }
+
+    /// Removes all injected faults
+    pub fn clear_faults(&self) {
+        self.lock().faults.clear();
+    }
+
+    /// Rejects every change with PermissionDenied while set, like a read-only mount
+    pub fn set_read_only(&self, read_only: bool) {
+        self.lock().read_only = read_only;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryState> {
+        // A test that panicked while holding the lock leaves consistent state behind
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Locks the state after checking `op` on `path` against the faults
+    fn begin(&self, op: VfsOp, path: &Path) -> io::Result<MutexGuard<'_, MemoryState>> {
+        let mut state = self.lock();
+        if state.read_only && matches!(op, VfsOp::Write | VfsOp::Rename | VfsOp::Remove) {
+            return Err(error(io::ErrorKind::PermissionDenied, path, "read-only filesystem"));
+        }
+        let fault = state.faults.iter_mut().find(|fault| fault.op == op && path.starts_with(&fault.path));
+        if let Some(fault) = fault {
+            if fault.skip == 0 {
+                return Err(error(fault.kind, path, "injected fault"));
+            }
+            fault.skip -= 1;
+        }
+        Ok(state)
+    }
+}
+
+fn error(kind: io::ErrorKind, path: &Path, what: &str) -> io::Error {
+    io::Error::new(kind, format!("{}: {}", path.display(), what))
+}
+
+impl MemoryState {
+    fn has_dir(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty() || path.parent().is_none() || self.dirs.contains(path)
+    }
+
+    /// Fails unless the directory `path` would go into exists
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        if self.dirs.contains(path) {
+            return Err(error(io::ErrorKind::IsADirectory, path, "is a directory"));
+        }
+        match path.parent() {
+            Some(parent) if !self.has_dir(parent) => Err(error(io::ErrorKind::NotFound, parent, "no such directory")),
+            _ => Ok(()),
+        }
+    }
+
+    fn put(&mut self, path: &Path, content: Vec<u8>) {
+        self.files.insert(path.to_path_buf(), MemoryFile { content, modified: SystemTime::now() });
+    }
+
+    fn file(&mut self, path: &Path) -> io::Result<&mut MemoryFile> {
+        self.files.get_mut(path).ok_or_else(|| error(io::ErrorKind::NotFound, path, "no such file"))
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut state = self.begin(VfsOp::Read, path)?;
+        if state.dirs.contains(path) {
+            return Err(error(io::ErrorKind::IsADirectory, path, "is a directory"));
+        }
+        Ok(state.file(path)?.content.clone())
+    }
+
+    fn write_atomic(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        let mut state = self.begin(VfsOp::Write, path)?;
+        state.check_parent(path)?;
+        state.put(path, content.to_vec());
+        Ok(())
+    }
+
+    fn append(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        let mut state = self.begin(VfsOp::Write, path)?;
+        state.check_parent(path)?;
+        match state.files.get_mut(path) {
+            Some(file) => {
+                file.content.extend_from_slice(content);
+                file.modified = SystemTime::now();
+            }
+            None => state.put(path, content.to_vec()),
+        }
+        Ok(())
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.begin(VfsOp::Write, path)?;
+        state.check_parent(path)?;
+        if state.files.contains_key(path) {
+            return Err(error(io::ErrorKind::AlreadyExists, path, "file exists"));
+        }
+        state.put(path, Vec::new());
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.begin(VfsOp::Write, path)?;
+        for dir in path.ancestors().filter(|dir| !state.has_dir(dir)) {
+            if state.files.contains_key(dir) {
+                return Err(error(io::ErrorKind::AlreadyExists, dir, "file exists"));
+            }
+        }
+        let missing: Vec<PathBuf> = path.ancestors()
+            .filter(|dir| !state.has_dir(dir))
+            .map(Path::to_path_buf)
+            .collect();
+        state.dirs.extend(missing);
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileStat> {
+        let mut state = self.begin(VfsOp::Metadata, path)?;
+        if state.has_dir(path) {
+            return Ok(FileStat { is_dir: true, len: 0, modified: SystemTime::UNIX_EPOCH });
+        }
+        let file = state.file(path)?;
+        Ok(FileStat { is_dir: false, len: file.content.len() as u64, modified: file.modified })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let state = self.begin(VfsOp::ReadDir, path)?;
+        if !state.has_dir(path) {
+            return Err(error(io::ErrorKind::NotFound, path, "no such directory"));
+        }
+        let mut names: Vec<OsString> = state.files.keys()
+            .chain(state.dirs.iter())
+            .filter(|child| child.parent() == Some(path))
+            .filter_map(|child| child.file_name().map(|name| name.to_os_string()))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.begin(VfsOp::Rename, from)?;
+        state.check_parent(to)?;
+        let file = state.files.remove(from).ok_or_else(|| error(io::ErrorKind::NotFound, from, "no such file"))?;
+        state.files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.begin(VfsOp::Remove, path)?;
+        state.files.remove(path).map(|_| ()).ok_or_else(|| error(io::ErrorKind::NotFound, path, "no such file"))
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.begin(VfsOp::Remove, path)?;
+        if !state.dirs.contains(path) {
+            return Err(error(io::ErrorKind::NotFound, path, "no such directory"));
+        }
+        if state.files.keys().chain(state.dirs.iter()).any(|child| child.parent() == Some(path)) {
+            return Err(error(io::ErrorKind::DirectoryNotEmpty, path, "directory not empty"));
+        }
+        state.dirs.remove(path);
+        Ok(())
+    }
+
+    fn touch(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.begin(VfsOp::Write, path)?;
+        state.file(path)?.modified = SystemTime::now();
+        Ok(())
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.begin(VfsOp::Write, path)?;
+        if !state.has_dir(path) {
+            state.file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Runs the same checks against both implementations
+    fn exercise(vfs: &dyn Vfs, root: &Path) -> io::Result<()> {
+        let dir = root.join("refs/heads");
+        let file = dir.join("main");
+        assert_eq!(vfs.write_atomic(&file, b"x").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        vfs.create_dir_all(&dir)?;
+        vfs.write_atomic(&file, b"one\n")?;
+        vfs.append(&file, b"two\n")?;
+        assert_eq!(vfs.read(&file)?, b"one\ntwo\n");
+        assert_eq!(vfs.metadata(&file)?.len, 8);
+        assert!(vfs.metadata(&dir)?.is_dir);
+
+        let lock = dir.join("main.lock");
+        vfs.create_new(&lock)?;
+        assert_eq!(vfs.create_new(&lock).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(vfs.read_dir(&dir)?, vec![OsString::from("main"), OsString::from("main.lock")]);
+        vfs.rename(&lock, &file)?;
+        assert_eq!(vfs.read(&file)?, b"");
+
+        vfs.create_dir_all(&dir.join("topic"))?;
+        vfs.write_atomic(&dir.join("topic/one"), b"")?;
+        assert_eq!(walk_files(vfs, &root.join("refs"))?, vec![
+            PathBuf::from("heads/main"),
+            PathBuf::from("heads/topic/one"),
+        ]);
+
+        vfs.sync(&file)?;
+        vfs.sync(&dir)?;
+        vfs.remove(&file)?;
+        assert!(!vfs.exists(&file));
+        assert_eq!(vfs.remove(&file).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(vfs.sync(&file).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let topic = dir.join("topic");
+        assert_eq!(vfs.remove_dir(&topic).unwrap_err().kind(), io::ErrorKind::DirectoryNotEmpty);
+        vfs.remove(&topic.join("one"))?;
+        vfs.remove_dir(&topic)?;
+        assert!(!vfs.exists(&topic));
+        Ok(())
+    }
+
+    #[test]
+    fn test_real_and_memory_agree() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        exercise(&RealFs::new(temp_dir.path()), temp_dir.path())?;
+        exercise(&*MemoryFs::new(), Path::new("/repo"))
+    }
+
+    #[test]
+    fn test_touch_read_only_file() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::Duration;
+
+        let temp_dir = TempDir::new()?;
+        let file = temp_dir.path().join("object");
+        fs::write(&file, "content")?;
+        let old = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+        fs::File::open(&file)?.set_modified(old)?;
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o444))?;
+
+        let vfs = RealFs::new(temp_dir.path());
+        vfs.touch(&file)?;
+        assert!(vfs.metadata(&file)?.modified > old);
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_faults() -> io::Result<()> {
+        let fs = MemoryFs::new();
+        let dir = Path::new("/repo/refs");
+        fs.create_dir_all(dir)?;
+
+        fs.fail_after(1, VfsOp::Write, dir, io::ErrorKind::StorageFull);
+        fs.write_atomic(&dir.join("a"), b"a")?;
+        let err = fs.write_atomic(&dir.join("b"), b"b").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        assert!(!fs.exists(&dir.join("b")));
+        // Other operations and paths are unaffected
+        assert_eq!(fs.read(&dir.join("a"))?, b"a");
+        fs.write_atomic(Path::new("/repo/HEAD"), b"")?;
+
+        fs.clear_faults();
+        fs.set_read_only(true);
+        assert_eq!(fs.remove(&dir.join("a")).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(fs.read(&dir.join("a"))?, b"a");
+        Ok(())
+    }
+}
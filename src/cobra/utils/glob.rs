@@ -0,0 +1,99 @@
+// Shell-style wildcard matching for ref and path patterns
+
+/// Matches `text` against a pattern where `*` matches any run of
+/// characters, `?` matches a single character and `[...]` matches a
+/// character class (`[!...]` or `[^...]` negates it). A backslash makes the
+/// next character literal.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the most recent `*`: (pattern index, text index)
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match_class(&pattern[p..], text[t]),
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(2),
+            Some(&c) => (c == text[t]).then_some(1),
+            None => None,
+        };
+
+        match step {
+            Some(width) => {
+                p += width;
+                t += 1;
+            }
+            None => match backtrack {
+                // Let the last `*` swallow one more character
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the class at the start of `pattern` and returns the
+/// class's length if it matches. An unterminated `[` is a literal.
+fn match_class(pattern: &[char], c: char) -> Option<usize> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() && (pattern[i] != ']' || first) {
+        first = false;
+        let low = pattern[i];
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&high| high != ']') {
+            matched |= low <= c && c <= pattern[i + 2];
+            i += 3;
+        } else {
+            matched |= low == c;
+            i += 1;
+        }
+    }
+
+    if i >= pattern.len() {
+        return (c == '[').then_some(1);
+    }
+    (matched != negated).then_some(i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("v1.*", "v1.2.3"));
+        assert!(!glob_match("v1.*", "v2.0"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("v?.0", "v2.0"));
+        assert!(glob_match("*-rc*", "v1.0-rc2"));
+        assert!(glob_match("v[0-9].[!0]", "v3.5"));
+        assert!(!glob_match("v[0-9].[!0]", "v3.0"));
+        assert!(glob_match("release\\*", "release*"));
+        assert!(!glob_match("release\\*", "release1"));
+        assert!(glob_match("a*b*c", "aXXbYYbc"));
+        assert!(glob_match("[", "["));
+    }
+}
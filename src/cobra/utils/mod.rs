@@ -1,2 +1,10 @@
 pub mod hash;
-pub mod fs; 
\ No newline at end of file
+pub mod fs;
+pub mod progress;
+pub mod regex;
+pub mod trace;
+pub mod glob;
+pub mod natsort;
+pub mod tempfiles;
+pub mod editor;
+pub mod date;
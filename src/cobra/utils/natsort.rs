@@ -0,0 +1,73 @@
+// Natural ("version") ordering of names such as v1.2.10 and v1.2.9
+
+use std::cmp::Ordering;
+
+/// Compares two names so that runs of digits compare by numeric value and
+/// everything else compares character by character. `v1.2.9` sorts before
+/// `v1.2.10`, and `v1.10` before `v1.10a`.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a = a.as_bytes();
+    let mut b = b.as_bytes();
+
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (a_digits, a_rest) = split_digits(a);
+                let (b_digits, b_rest) = split_digits(b);
+                let ordering = compare_numbers(a_digits, b_digits);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = a_rest;
+                b = b_rest;
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+fn split_digits(s: &[u8]) -> (&[u8], &[u8]) {
+    let end = s.iter().position(|c| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// Compares two digit strings by value without parsing, so any length works.
+/// Equal values with more leading zeros sort first.
+fn compare_numbers(a: &[u8], b: &[u8]) -> Ordering {
+    let strip = |s: &[u8]| -> usize { s.iter().take_while(|&&c| c == b'0').count() };
+    let (a_zeros, b_zeros) = (strip(a), strip(b));
+    let (a_value, b_value) = (&a[a_zeros..], &b[b_zeros..]);
+    a_value.len().cmp(&b_value.len())
+        .then_with(|| a_value.cmp(b_value))
+        .then_with(|| b_zeros.cmp(&a_zeros))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("v1.2.9", "v1.2.10"), Ordering::Less);
+        assert_eq!(compare_versions("v1.10", "v1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("v1.10", "v1.10a"), Ordering::Less);
+        assert_eq!(compare_versions("v1.0-beta2", "v1.0-beta10"), Ordering::Less);
+        assert_eq!(compare_versions("v2", "v2"), Ordering::Equal);
+        assert_eq!(compare_versions("v007", "v7"), Ordering::Less);
+        assert_eq!(compare_versions("v99999999999999999999999", "v100000000000000000000000"), Ordering::Less);
+        assert_eq!(compare_versions("alpha", "beta"), Ordering::Less);
+
+        let mut tags = vec!["v1.2.10", "v1.10.0", "v1.2.9", "v1.2", "v0.9"];
+        tags.sort_by(|a, b| compare_versions(a, b));
+        assert_eq!(tags, vec!["v0.9", "v1.2", "v1.2.9", "v1.2.10", "v1.10.0"]);
+    }
+}
@@ -0,0 +1,77 @@
+// Progress reporting for long-running working tree operations
+
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+
+/// Receives per-file notifications while a batch of files is processed
+pub trait Progress {
+    /// Called once before processing starts with the number of files to handle
+    fn start(&mut self, total: usize);
+    /// Called after each file has been handled
+    fn tick(&mut self, path: &Path);
+    /// Called once after the last file
+    fn finish(&mut self);
+}
+
+/// A progress sink that ignores all notifications
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn start(&mut self, _total: usize) {}
+    fn tick(&mut self, _path: &Path) {}
+    fn finish(&mut self) {}
+}
+
+/// Prints a "label: 42% (21/50)" file counter to stderr.
+/// Only renders when stderr is a terminal and the batch is large enough to matter.
+pub struct CounterProgress {
+    label: String,
+    total: usize,
+    current: usize,
+    enabled: bool,
+}
+
+impl CounterProgress {
+    /// Minimum number of files before the counter is shown
+    const THRESHOLD: usize = 100;
+
+    pub fn new(label: &str) -> CounterProgress {
+        CounterProgress {
+            label: label.to_string(),
+            total: 0,
+            current: 0,
+            enabled: false,
+        }
+    }
+
+    fn render(&self, done: bool) {
+        let percent = (self.current * 100).checked_div(self.total).unwrap_or(100);
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\r{}: {}% ({}/{})", self.label, percent, self.current, self.total);
+        if done {
+            let _ = writeln!(stderr, ", done.");
+        }
+        let _ = stderr.flush();
+    }
+}
+
+impl Progress for CounterProgress {
+    fn start(&mut self, total: usize) {
+        self.total = total;
+        self.current = 0;
+        self.enabled = total >= Self::THRESHOLD && io::stderr().is_terminal();
+    }
+
+    fn tick(&mut self, _path: &Path) {
+        self.current += 1;
+        if self.enabled {
+            self.render(false);
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.enabled {
+            self.render(true);
+        }
+    }
+}
@@ -0,0 +1,454 @@
+// Minimal regular expressions for content searches
+//
+// Patterns are compiled to a Thompson NFA and run by following every state
+// at once, so matching time grows with the text times the pattern and never
+// blows up on user patterns like `(a*)*b` the way backtracking does.
+
+use std::io;
+
+/// A compiled regular expression supporting the common POSIX extended subset:
+/// literals, `.`, `[...]` classes, `^`, `$`, groups, `|`, and the `*`, `+`,
+/// `?` and `{m,n}` quantifiers, plus the `\d`, `\w` and `\s` escapes.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+}
+
+/// Patterns compiling to more instructions than this are refused, as
+/// `{m,n}` copies its atom and nested counts multiply
+const MAX_PROGRAM: usize = 100_000;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class { items: Vec<ClassItem>, negated: bool },
+    Start,
+    End,
+    Group(Vec<Vec<Node>>),
+    Repeat { node: Box<Node>, min: usize, max: Option<usize> },
+}
+
+/// One NFA state. Split, Jmp, Start and End move on without consuming a
+/// character.
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class { items: Vec<ClassItem>, negated: bool },
+    Start,
+    End,
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Digit,
+    Word,
+    Space,
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Range(lo, hi) => *lo <= c && c <= *hi,
+            ClassItem::Digit => c.is_ascii_digit(),
+            ClassItem::Word => c.is_alphanumeric() || c == '_',
+            ClassItem::Space => c.is_whitespace(),
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    pattern: &'a str,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid regex '{}': {}", self.pattern, message),
+        )
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn parse_alternation(&mut self) -> io::Result<Vec<Vec<Node>>> {
+        let mut branches = vec![self.parse_sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.parse_sequence()?);
+        }
+        Ok(branches)
+    }
+
+    fn parse_sequence(&mut self) -> io::Result<Vec<Node>> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> io::Result<Node> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => return self.parse_braces(atom),
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        Ok(Node::Repeat { node: Box::new(atom), min, max })
+    }
+
+    fn parse_braces(&mut self, atom: Node) -> io::Result<Node> {
+        let close = self.chars[self.pos..].iter()
+            .position(|&c| c == '}')
+            .ok_or_else(|| self.error("unterminated '{'"))?;
+        let body: String = self.chars[self.pos + 1..self.pos + close].iter().collect();
+        let parse = |s: &str| s.trim().parse::<usize>().map_err(|_| self.error("invalid repetition count"));
+        let (min, max) = match body.split_once(',') {
+            None => {
+                let n = parse(&body)?;
+                (n, Some(n))
+            }
+            Some((lo, "")) => (parse(lo)?, None),
+            Some((lo, hi)) => (parse(lo)?, Some(parse(hi)?)),
+        };
+        self.pos += close + 1;
+        Ok(Node::Repeat { node: Box::new(atom), min, max })
+    }
+
+    fn parse_atom(&mut self) -> io::Result<Node> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end"))?;
+        self.pos += 1;
+        match c {
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '(' => {
+                let branches = self.parse_alternation()?;
+                if self.peek() != Some(')') {
+                    return Err(self.error("missing ')'"));
+                }
+                self.pos += 1;
+                Ok(Node::Group(branches))
+            }
+            '[' => self.parse_class(),
+            '\\' => {
+                let escaped = self.peek().ok_or_else(|| self.error("trailing backslash"))?;
+                self.pos += 1;
+                Ok(match escape_class(escaped) {
+                    Some((item, negated)) => Node::Class { items: vec![item], negated },
+                    None => Node::Char(escaped),
+                })
+            }
+            '*' | '+' | '?' => Err(self.error("nothing to repeat")),
+            _ => Ok(Node::Char(c)),
+        }
+    }
+
+    fn parse_class(&mut self) -> io::Result<Node> {
+        let mut items = Vec::new();
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("unterminated '['"))?;
+            self.pos += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = if c == '\\' {
+                let escaped = self.peek().ok_or_else(|| self.error("trailing backslash"))?;
+                self.pos += 1;
+                if let Some((item, _)) = escape_class(escaped) {
+                    items.push(item);
+                    continue;
+                }
+                escaped
+            } else {
+                c
+            };
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                let hi = self.chars[self.pos + 1];
+                self.pos += 2;
+                items.push(ClassItem::Range(lo, hi));
+            } else {
+                items.push(ClassItem::Range(lo, lo));
+            }
+        }
+        Ok(Node::Class { items, negated })
+    }
+}
+
+fn escape_class(c: char) -> Option<(ClassItem, bool)> {
+    match c {
+        'd' => Some((ClassItem::Digit, false)),
+        'D' => Some((ClassItem::Digit, true)),
+        'w' => Some((ClassItem::Word, false)),
+        'W' => Some((ClassItem::Word, true)),
+        's' => Some((ClassItem::Space, false)),
+        'S' => Some((ClassItem::Space, true)),
+        _ => None,
+    }
+}
+
+/// Turns the parsed nodes into a program
+struct Compiler<'a> {
+    program: Vec<Inst>,
+    pattern: &'a str,
+}
+
+impl Compiler<'_> {
+    fn push(&mut self, inst: Inst) -> io::Result<usize> {
+        if self.program.len() >= MAX_PROGRAM {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid regex '{}': pattern too large", self.pattern),
+            ));
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    fn alternation(&mut self, branches: &[Vec<Node>]) -> io::Result<()> {
+        let mut exits = Vec::new();
+        for (i, branch) in branches.iter().enumerate() {
+            if i + 1 == branches.len() {
+                self.sequence(branch)?;
+                break;
+            }
+            let split = self.push(Inst::Split(0, 0))?;
+            self.sequence(branch)?;
+            exits.push(self.push(Inst::Jmp(0))?);
+            self.program[split] = Inst::Split(split + 1, self.program.len());
+        }
+        let end = self.program.len();
+        for exit in exits {
+            self.program[exit] = Inst::Jmp(end);
+        }
+        Ok(())
+    }
+
+    fn sequence(&mut self, nodes: &[Node]) -> io::Result<()> {
+        nodes.iter().try_for_each(|node| self.node(node))
+    }
+
+    fn node(&mut self, node: &Node) -> io::Result<()> {
+        match node {
+            Node::Char(c) => self.push(Inst::Char(*c)).map(drop),
+            Node::Any => self.push(Inst::Any).map(drop),
+            Node::Class { items, negated } => self.push(Inst::Class { items: items.clone(), negated: *negated }).map(drop),
+            Node::Start => self.push(Inst::Start).map(drop),
+            Node::End => self.push(Inst::End).map(drop),
+            Node::Group(branches) => self.alternation(branches),
+            Node::Repeat { node, min, max } => self.repeat(node, *min, *max),
+        }
+    }
+
+    fn repeat(&mut self, node: &Node, min: usize, max: Option<usize>) -> io::Result<()> {
+        for _ in 0..min {
+            self.node(node)?;
+        }
+        match max {
+            None => {
+                let split = self.push(Inst::Split(0, 0))?;
+                self.node(node)?;
+                self.push(Inst::Jmp(split))?;
+                self.program[split] = Inst::Split(split + 1, self.program.len());
+            }
+            Some(max) => {
+                // Each optional copy may skip straight past the rest
+                let mut splits = Vec::new();
+                for _ in min..max {
+                    splits.push(self.push(Inst::Split(0, 0))?);
+                    self.node(node)?;
+                }
+                let end = self.program.len();
+                for split in splits {
+                    self.program[split] = Inst::Split(split + 1, end);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The states reached at one position, each listed once
+struct Threads {
+    list: Vec<usize>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads { list: Vec::with_capacity(size), seen: vec![false; size] }
+    }
+
+    fn clear(&mut self) {
+        for &pc in &self.list {
+            self.seen[pc] = false;
+        }
+        self.list.clear();
+    }
+
+    /// Adds `pc` and every state it reaches without consuming a character.
+    /// Returns true once Match is reached.
+    fn add(&mut self, program: &[Inst], pc: usize, pos: usize, len: usize) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if self.seen[pc] {
+                continue;
+            }
+            self.seen[pc] = true;
+            self.list.push(pc);
+            match &program[pc] {
+                Inst::Match => return true,
+                Inst::Jmp(target) => stack.push(*target),
+                Inst::Split(first, second) => {
+                    stack.push(*second);
+                    stack.push(*first);
+                }
+                Inst::Start if pos == 0 => stack.push(pc + 1),
+                Inst::End if pos == len => stack.push(pc + 1),
+                _ => {}
+            }
+        }
+        false
+    }
+}
+
+impl Regex {
+    /// Compiles a pattern, returning an InvalidInput error on bad syntax
+    pub fn new(pattern: &str) -> io::Result<Regex> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            pattern,
+        };
+        let branches = parser.parse_alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("unmatched ')'"));
+        }
+        let mut compiler = Compiler { program: Vec::new(), pattern };
+        compiler.alternation(&branches)?;
+        compiler.push(Inst::Match)?;
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            program: compiler.program,
+        })
+    }
+
+    /// Returns the source pattern
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns true if the pattern matches anywhere in `text`
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let program = &self.program;
+        let mut current = Threads::new(program.len());
+        let mut next = Threads::new(program.len());
+        for pos in 0..=chars.len() {
+            // A match may start anywhere, so every position starts a thread
+            if current.add(program, 0, pos, chars.len()) {
+                return true;
+            }
+            let Some(&c) = chars.get(pos) else {
+                break;
+            };
+            for i in 0..current.list.len() {
+                let pc = current.list[i];
+                let consumes = match &program[pc] {
+                    Inst::Char(expected) => *expected == c,
+                    Inst::Any => c != '\n',
+                    Inst::Class { items, negated } => items.iter().any(|item| item.matches(c)) != *negated,
+                    _ => false,
+                };
+                if consumes && next.add(program, pc + 1, pos + 1, chars.len()) {
+                    return true;
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literals_and_anchors() -> io::Result<()> {
+        assert!(Regex::new("needle")?.is_match("haystack with needle inside"));
+        assert!(!Regex::new("needle")?.is_match("haystack"));
+        assert!(Regex::new("^fn ")?.is_match("fn main() {"));
+        assert!(!Regex::new("^fn ")?.is_match("pub fn main() {"));
+        assert!(Regex::new("}$")?.is_match("fn main() {}"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_quantifiers_and_classes() -> io::Result<()> {
+        assert!(Regex::new("a[0-9]+b")?.is_match("xa123b"));
+        assert!(!Regex::new("a[0-9]+b")?.is_match("xab"));
+        assert!(Regex::new("colou?r")?.is_match("color"));
+        assert!(Regex::new("\\d{3}-\\d{2,}")?.is_match("call 555-1234"));
+        assert!(Regex::new("[^a-z]x")?.is_match("Ax"));
+        assert!(!Regex::new("^[^a-z]x")?.is_match("ax"));
+        assert!(Regex::new("a.*z")?.is_match("abcz"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_groups_and_alternation() -> io::Result<()> {
+        let re = Regex::new("^(foo|bar)+baz$")?;
+        assert!(re.is_match("foobarfoobaz"));
+        assert!(!re.is_match("foobaz!"));
+        assert!(Regex::new("cat|dog")?.is_match("hotdog"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!(Regex::new("(unclosed").is_err());
+        assert!(Regex::new("*start").is_err());
+        assert!(Regex::new("[abc").is_err());
+        assert!(Regex::new("(a{1000}){1000}").is_err());
+    }
+
+    #[test]
+    fn test_nested_quantifiers_stay_linear() -> io::Result<()> {
+        // Exponential for a backtracking matcher
+        let text = "a".repeat(5000);
+        assert!(!Regex::new("^(a*)*b$")?.is_match(&text));
+        assert!(!Regex::new("(a|a)+(a|aa)*c")?.is_match(&text));
+        assert!(Regex::new("(a+)+$")?.is_match(&text));
+        assert!(Regex::new("^(x?){3}xxx$")?.is_match("xxx"));
+        assert!(Regex::new("a{2,3}$")?.is_match("caaa"));
+        assert!(!Regex::new("^a{2,3}$")?.is_match("aaaa"));
+        assert!(Regex::new("^$")?.is_match(""));
+        assert!(Regex::new("(^|,)b")?.is_match("a,b"));
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+// Temporary files under .cobra/tmp, removed automatically
+//
+// Every temporary file lives in the repository's own tmp directory, so it is
+// on the same filesystem as its final destination (renames stay atomic) and
+// never litters the work tree or /tmp. A TempFile deletes itself when it is
+// dropped without having been persisted, which covers early returns through
+// `?` as well as panics. Setting COBRA_KEEP_TMP=1 keeps those leftovers for
+// debugging instead.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns true if COBRA_KEEP_TMP asks to keep temporary files of failed operations
+pub fn keep_from_env(value: Option<&str>) -> bool {
+    matches!(value.map(str::trim), Some("1") | Some("true") | Some("yes"))
+}
+
+/// The directory holding a repository's temporary files
+pub fn tmp_dir(git_dir: &Path) -> PathBuf {
+    git_dir.join("tmp")
+}
+
+/// A file name starting with `prefix` that no other temporary file of any
+/// process uses
+pub fn unique_name(prefix: &str) -> String {
+    format!("{}-{}-{}", prefix, process::id(), COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+pub struct TempFile {
+    path: PathBuf,
+    keep_on_failure: bool,
+    done: bool,
+}
+
+impl TempFile {
+    /// Creates an empty file named after `prefix` in `<git_dir>/tmp`
+    pub fn new(git_dir: &Path, prefix: &str) -> io::Result<TempFile> {
+        TempFile::in_dir(&tmp_dir(git_dir), prefix)
+    }
+
+    /// Creates an empty file named after `prefix` in `dir`, for files that
+    /// belong to no repository (such as the per-user config)
+    pub fn in_dir(dir: &Path, prefix: &str) -> io::Result<TempFile> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(unique_name(prefix));
+        fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+        Ok(TempFile {
+            path,
+            keep_on_failure: keep_from_env(env::var("COBRA_KEEP_TMP").ok().as_deref()),
+            done: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Overrides COBRA_KEEP_TMP for this file
+    pub fn keep_on_failure(mut self, keep: bool) -> TempFile {
+        self.keep_on_failure = keep;
+        self
+    }
+
+    /// Moves the file to `destination`, replacing whatever is there
+    pub fn persist(mut self, destination: &Path) -> io::Result<()> {
+        fs::rename(&self.path, destination)?;
+        self.done = true;
+        Ok(())
+    }
+
+    /// Deletes the file now that the operation using it has succeeded
+    pub fn finish(mut self) -> io::Result<()> {
+        self.done = true;
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Dropped without persist or finish: the operation failed
+        if self.done || self.keep_on_failure {
+            return;
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A directory under `<git_dir>/tmp` for files handed to other programs,
+/// removed with everything in it when dropped
+pub struct TempDirectory {
+    path: PathBuf,
+}
+
+impl TempDirectory {
+    pub fn new(git_dir: &Path, prefix: &str) -> io::Result<TempDirectory> {
+        let dir = tmp_dir(git_dir);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(unique_name(prefix));
+        fs::create_dir(&path)?;
+        Ok(TempDirectory { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Stands in for an operation that stages data in a temporary file and then fails
+    fn failing_apply(git_dir: &Path, keep: bool) -> io::Result<PathBuf> {
+        let staged = TempFile::new(git_dir, "apply")?.keep_on_failure(keep);
+        fs::write(staged.path(), "half applied\n")?;
+        let path = staged.path().to_path_buf();
+        Err(io::Error::other(format!("patch does not apply: {}", path.display())))
+    }
+
+    #[test]
+    fn test_failed_operation_leaves_no_files() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        assert!(failing_apply(temp_dir.path(), false).is_err());
+        assert_eq!(fs::read_dir(tmp_dir(temp_dir.path()))?.count(), 0);
+
+        // A panic unwinds through the guard as well
+        let git_dir = temp_dir.path().to_path_buf();
+        let result = std::panic::catch_unwind(move || {
+            let _staged = TempFile::new(&git_dir, "panic").unwrap().keep_on_failure(false);
+            panic!("simulated crash");
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_dir(tmp_dir(temp_dir.path()))?.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_on_failure_preserves_files() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        assert!(failing_apply(temp_dir.path(), true).is_err());
+        let kept: Vec<_> = fs::read_dir(tmp_dir(temp_dir.path()))?.collect::<io::Result<_>>()?;
+        assert_eq!(kept.len(), 1);
+        assert_eq!(fs::read_to_string(kept[0].path())?, "half applied\n");
+
+        assert!(keep_from_env(Some("1")));
+        assert!(!keep_from_env(Some("0")));
+        assert!(!keep_from_env(None));
+        Ok(())
+    }
+
+    #[test]
+    fn test_persist_and_finish() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let destination = temp_dir.path().join("index");
+
+        let staged = TempFile::new(temp_dir.path(), "index")?.keep_on_failure(false);
+        fs::write(staged.path(), "new index")?;
+        staged.persist(&destination)?;
+        assert_eq!(fs::read_to_string(&destination)?, "new index");
+
+        let scratch = TempFile::new(temp_dir.path(), "scratch")?.keep_on_failure(true);
+        scratch.finish()?;
+        assert_eq!(fs::read_dir(tmp_dir(temp_dir.path()))?.count(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+// Diagnostic tracing controlled by COBRA_TRACE* environment variables
+//
+// COBRA_TRACE       high-level operations: index loads, worktree walks, ref updates
+// COBRA_TRACE_PERF  per-object reads and writes with timings
+// COBRA_TRACE_PACK  pack file operations
+//
+// Each variable accepts "1"/"true" (write to stderr) or an absolute file path
+// (append to that file). Unset, empty, "0" and "false" disable the stream.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, Once};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Main,
+    Perf,
+    Pack,
+}
+
+impl Stream {
+    const ALL: [Stream; 3] = [Stream::Main, Stream::Perf, Stream::Pack];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn env_var(self) -> &'static str {
+        match self {
+            Stream::Main => "COBRA_TRACE",
+            Stream::Perf => "COBRA_TRACE_PERF",
+            Stream::Pack => "COBRA_TRACE_PACK",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Stream::Main => "trace",
+            Stream::Perf => "perf",
+            Stream::Pack => "pack",
+        }
+    }
+}
+
+/// Running totals reported by `summary`
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    ObjectsRead,
+    ObjectsWritten,
+}
+
+type Sink = Box<dyn Write + Send>;
+
+static INIT: Once = Once::new();
+static ENABLED: AtomicU8 = AtomicU8::new(0);
+static SINKS: Mutex<[Option<Sink>; 3]> = Mutex::new([None, None, None]);
+static COUNTERS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+/// Re-reads the COBRA_TRACE* variables. Streams are otherwise configured
+/// once, on first use.
+pub fn load_from_env() {
+    load_from(|var| std::env::var(var).ok());
+}
+
+/// Configures every stream from `lookup`, which maps a COBRA_TRACE* name to
+/// its value the way the environment would
+pub fn load_from<F: Fn(&str) -> Option<String>>(lookup: F) {
+    let mut sinks = SINKS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut enabled = 0;
+    for stream in Stream::ALL {
+        let sink = lookup(stream.env_var()).and_then(|value| open_sink(&value));
+        if sink.is_some() {
+            enabled |= 1 << stream.index();
+        }
+        sinks[stream.index()] = sink;
+    }
+    ENABLED.store(enabled, Ordering::Release);
+}
+
+fn open_sink(value: &str) -> Option<Sink> {
+    match value.trim() {
+        "" | "0" | "false" => None,
+        path if path.starts_with('/') => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .ok()
+            .map(|file| Box::new(file) as Sink),
+        _ => Some(Box::new(io::stderr())),
+    }
+}
+
+/// Returns true if `stream` is being recorded. This is a single atomic load
+/// once the environment has been read.
+pub fn enabled(stream: Stream) -> bool {
+    INIT.call_once(load_from_env);
+    ENABLED.load(Ordering::Acquire) & (1 << stream.index()) != 0
+}
+
+/// Writes one line to `stream`. The message is only built when the stream is on.
+pub fn event<F: FnOnce() -> String>(stream: Stream, message: F) {
+    if !enabled(stream) {
+        return;
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let line = format!("{}.{:06} {}: {}\n", now.as_secs(), now.subsec_micros(), stream.label(), message());
+    let mut sinks = SINKS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(sink) = sinks[stream.index()].as_mut() {
+        let _ = sink.write_all(line.as_bytes());
+        let _ = sink.flush();
+    }
+}
+
+/// Runs `f` and, when `stream` is on, logs `name` with the elapsed time.
+/// `describe` can add details taken from the result.
+pub fn timed<T, F, D>(stream: Stream, name: &str, f: F, describe: D) -> T
+where
+    F: FnOnce() -> T,
+    D: FnOnce(&T) -> String,
+{
+    if !enabled(stream) {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    event(stream, || format!("{}: {} ({:.3} ms)", name, describe(&result), elapsed.as_secs_f64() * 1000.0));
+    result
+}
+
+/// Bumps a counter. Counting is skipped entirely when tracing is off.
+pub fn increment(counter: Counter) {
+    if enabled(Stream::Main) {
+        COUNTERS[counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Logs the counters accumulated so far
+pub fn summary() {
+    event(Stream::Main, || format!(
+        "objects: {} read, {} written",
+        COUNTERS[Counter::ObjectsRead as usize].load(Ordering::Relaxed),
+        COUNTERS[Counter::ObjectsWritten as usize].load(Ordering::Relaxed),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::cobra::commands::commit::commit;
+    use crate::cobra::core::reflog::ZERO_HASH;
+    use crate::cobra::core::repository::Repository;
+    use crate::cobra::test_support::init_repo;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_trace_commit_to_file() -> io::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let trace_file = temp_dir.path().join("trace.log");
+        let repo_dir = temp_dir.path().join("repo");
+        fs::create_dir(&repo_dir)?;
+
+        let value = trace_file.to_string_lossy().into_owned();
+        load_from(|var| (var == "COBRA_TRACE").then(|| value.clone()));
+
+        init_repo(&repo_dir)?;
+        let repo = Repository::open(repo_dir.to_str().unwrap())?;
+        let hash = commit(&repo, "Initial commit")?;
+        summary();
+
+        load_from_env();
+
+        // Other tests may trace into the same file concurrently, so only look for our lines
+        let log = fs::read_to_string(&trace_file)?;
+        assert!(log.lines().any(|line| line.contains("trace: index load: 0 entries")));
+        assert!(log.lines().any(|line| line.contains(&format!("trace: ref update refs/heads/main: {} -> {}", ZERO_HASH, hash))));
+        assert!(log.lines().any(|line| line.contains("trace: objects: ")));
+        assert!(log.lines().all(|line| !line.contains("perf: ")));
+
+        Ok(())
+    }
+}
@@ -1,8 +1,8 @@
-mod cobra;
 use std::process;
+use cobra::cobra::cli;
 
 fn main() {
-    if let Err(e) = cobra::cli::run() {
+    if let Err(e) = cli::run() {
         eprintln!("Error: {}", e);
         process::exit(1);
     }
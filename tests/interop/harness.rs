@@ -0,0 +1,162 @@
+// Helpers for running the same scenario through cobra and git
+//
+// Both tools run with a fixed identity and clock, no user or system git
+// config, and git's default branch set to main, so identical operations
+// have to produce identical objects.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+/// Author and committer used by both tools
+const NAME: &str = "Interop Tester";
+const EMAIL: &str = "interop@example.com";
+const DATE: &str = "1700000000 +0100";
+
+/// Returns true if a git binary can be run
+pub fn git_available() -> bool {
+    Command::new("git").arg("--version").output().is_ok_and(|output| output.status.success())
+}
+
+/// Two empty work trees, one for each tool, plus a home directory without any config
+pub struct Sandbox {
+    root: TempDir,
+}
+
+impl Sandbox {
+    pub fn new() -> Sandbox {
+        let root = TempDir::new().expect("create sandbox");
+        for dir in ["cobra", "git", "home"] {
+            fs::create_dir(root.path().join(dir)).expect("create sandbox directory");
+        }
+        Sandbox { root }
+    }
+
+    /// Work tree of the repository cobra manages
+    pub fn cobra_dir(&self) -> PathBuf {
+        self.root.path().join("cobra")
+    }
+
+    /// Work tree of the repository git manages
+    pub fn git_dir(&self) -> PathBuf {
+        self.root.path().join("git")
+    }
+
+    /// A further empty directory, such as a second repository to fetch into
+    pub fn extra_dir(&self, name: &str) -> PathBuf {
+        let dir = self.root.path().join(name);
+        fs::create_dir_all(&dir).expect("create sandbox directory");
+        dir
+    }
+
+    fn command(&self, program: &str, dir: &Path) -> Command {
+        let mut command = Command::new(program);
+        command.current_dir(dir)
+            .env_remove("GIT_DIR")
+            .env_remove("GIT_WORK_TREE")
+            .env_remove("GIT_INDEX_FILE")
+            .env_remove("COBRA_WORK_TREE")
+            .env("HOME", self.root.path().join("home"))
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null");
+        for role in ["AUTHOR", "COMMITTER"] {
+            for tool in ["GIT", "COBRA"] {
+                command.env(format!("{}_{}_NAME", tool, role), NAME)
+                    .env(format!("{}_{}_EMAIL", tool, role), EMAIL)
+                    .env(format!("{}_{}_DATE", tool, role), DATE);
+            }
+        }
+        command
+    }
+
+    fn run(mut command: Command) -> String {
+        let output = command.output().expect("spawn command");
+        assert!(
+            output.status.success(),
+            "{:?} failed:\n{}{}",
+            command,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        );
+        String::from_utf8(output.stdout).expect("utf-8 output")
+    }
+
+    /// Runs cobra in `dir` and returns its standard output
+    pub fn cobra(&self, dir: &Path, args: &[&str]) -> String {
+        let mut command = self.command(env!("CARGO_BIN_EXE_cobra"), dir);
+        command.args(args);
+        Sandbox::run(command)
+    }
+
+    /// Runs git in `dir` and returns its standard output
+    pub fn git(&self, dir: &Path, args: &[&str]) -> String {
+        let mut command = self.command("git", dir);
+        command.args(args);
+        Sandbox::run(command)
+    }
+
+    /// Runs git against cobra's metadata directory. Cobra's index uses its
+    /// own format, so git is pointed at an index that does not exist.
+    pub fn git_on_cobra(&self, args: &[&str]) -> String {
+        let mut command = self.command("git", &self.cobra_dir());
+        command.env("GIT_DIR", self.cobra_dir().join(".cobra"))
+            .env("GIT_INDEX_FILE", self.root.path().join("no-index"))
+            .args(args);
+        Sandbox::run(command)
+    }
+
+    /// Writes the same file into both work trees
+    pub fn write_both(&self, path: &str, content: &str) {
+        for dir in [self.cobra_dir(), self.git_dir()] {
+            write_file(&dir, path, content);
+        }
+    }
+}
+
+pub fn write_file(dir: &Path, path: &str, content: &str) {
+    let path = dir.join(path);
+    fs::create_dir_all(path.parent().unwrap()).expect("create parent directory");
+    fs::write(path, content).expect("write file");
+}
+
+/// What a repository holds: every branch and tag with its commit, and the
+/// objects reachable from them with the path they were found under
+#[derive(Debug, PartialEq)]
+pub struct RepoState {
+    pub refs: BTreeMap<String, String>,
+    pub objects: Vec<String>,
+}
+
+/// Names of the loose objects in a metadata directory, sorted
+pub fn loose_objects(git_dir: &Path) -> Vec<String> {
+    let mut objects = Vec::new();
+    for dir in fs::read_dir(git_dir.join("objects")).expect("read objects directory") {
+        let dir = dir.expect("read objects directory").path();
+        let Some(prefix) = dir.file_name().and_then(|name| name.to_str()).filter(|name| name.len() == 2) else {
+            continue;
+        };
+        for file in fs::read_dir(&dir).expect("read object directory") {
+            objects.push(format!("{}{}", prefix, file.expect("read object directory").file_name().to_string_lossy()));
+        }
+    }
+    objects.sort();
+    objects
+}
+
+/// Reads the state of a repository through git, so both sides are described
+/// by the same tool
+pub fn repo_state(git: impl Fn(&[&str]) -> String) -> RepoState {
+    let refs = git(&["for-each-ref", "--format=%(refname) %(objectname)", "refs/heads", "refs/tags"])
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, hash)| (name.to_string(), hash.to_string()))
+        .collect();
+    let mut objects: Vec<String> = git(&["rev-list", "--objects", "--branches", "--tags"])
+        .lines()
+        .map(str::to_string)
+        .collect();
+    objects.sort();
+    RepoState { refs, objects }
+}
@@ -0,0 +1,216 @@
+// Compatibility tests running the same scenarios through cobra and git
+//
+// These tests need a git binary and are skipped (with a note on stderr)
+// when none is installed.
+
+mod harness;
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use cobra::cobra::core::daemon::Daemon;
+use harness::{git_available, loose_objects, repo_state, write_file, Sandbox};
+
+macro_rules! require_git {
+    () => {
+        if !git_available() {
+            eprintln!("git is not installed; skipping interop test");
+            return;
+        }
+    };
+}
+
+/// Files chosen to exercise tree ordering ("src.txt" sorts before the
+/// "src" directory in git) and modes
+fn write_initial_files(sandbox: &Sandbox) {
+    sandbox.write_both("README.md", "# Interop\n");
+    sandbox.write_both("src/main.rs", "fn main() {}\n");
+    sandbox.write_both("src/lib/mod.rs", "pub mod util;\n");
+    sandbox.write_both("src.txt", "sources live in src/\n");
+    sandbox.write_both("a-b.txt", "dash\n");
+}
+
+const INITIAL_FILES: &[&str] = &["README.md", "src/main.rs", "src/lib/mod.rs", "src.txt", "a-b.txt"];
+
+/// Commits on main, then on a feature branch, with the same files and
+/// messages in both repositories
+fn build_history(sandbox: &Sandbox) {
+    let (cobra, git) = (sandbox.cobra_dir(), sandbox.git_dir());
+    sandbox.cobra(&cobra, &["init", "."]);
+    sandbox.git(&git, &["init", "--quiet", "--initial-branch=main", "."]);
+
+    write_initial_files(sandbox);
+    for path in INITIAL_FILES {
+        sandbox.cobra(&cobra, &["add", path]);
+    }
+    sandbox.git(&git, &["add", "."]);
+    sandbox.cobra(&cobra, &["commit", "-m", "Initial commit"]);
+    sandbox.git(&git, &["commit", "--quiet", "-m", "Initial commit"]);
+
+    sandbox.cobra(&cobra, &["branch", "create", "feature"]);
+    sandbox.cobra(&cobra, &["branch", "checkout", "feature"]);
+    sandbox.git(&git, &["checkout", "--quiet", "-b", "feature"]);
+
+    sandbox.write_both("src/main.rs", "fn main() {\n    println!(\"hi\");\n}\n");
+    sandbox.write_both("docs/guide.md", "Read the source.\n");
+    for path in ["src/main.rs", "docs/guide.md"] {
+        sandbox.cobra(&cobra, &["add", path]);
+        sandbox.git(&git, &["add", path]);
+    }
+    sandbox.cobra(&cobra, &["commit", "-m", "Greet and document"]);
+    sandbox.git(&git, &["commit", "--quiet", "-m", "Greet and document"]);
+}
+
+#[test]
+fn test_same_operations_produce_same_objects() {
+    require_git!();
+    let sandbox = Sandbox::new();
+    build_history(&sandbox);
+
+    let git_state = repo_state(|args| sandbox.git(&sandbox.git_dir(), args));
+    let cobra_state = repo_state(|args| sandbox.git_on_cobra(args));
+    assert_eq!(cobra_state, git_state);
+    assert_eq!(cobra_state.refs.len(), 2);
+}
+
+#[test]
+fn test_git_fsck_accepts_cobra_repository() {
+    require_git!();
+    let sandbox = Sandbox::new();
+    build_history(&sandbox);
+
+    let git_report = sandbox.git(&sandbox.git_dir(), &["fsck", "--strict", "--no-progress"]);
+    let cobra_report = sandbox.git_on_cobra(&["fsck", "--strict", "--no-progress"]);
+    assert_eq!(cobra_report, git_report);
+}
+
+#[test]
+fn test_merge_commits_match() {
+    require_git!();
+    let sandbox = Sandbox::new();
+    build_history(&sandbox);
+
+    sandbox.cobra(&sandbox.cobra_dir(), &["branch", "checkout", "main"]);
+    sandbox.cobra(&sandbox.cobra_dir(), &["branch", "merge", "--no-ff", "feature"]);
+    sandbox.git(&sandbox.git_dir(), &["checkout", "--quiet", "main"]);
+    sandbox.git(&sandbox.git_dir(), &["merge", "--quiet", "--no-ff", "-m", "Merge branch 'feature'", "feature"]);
+
+    let git_state = repo_state(|args| sandbox.git(&sandbox.git_dir(), args));
+    let cobra_state = repo_state(|args| sandbox.git_on_cobra(args));
+    assert_eq!(cobra_state, git_state);
+}
+
+#[test]
+fn test_cobra_reads_git_repository() {
+    require_git!();
+    let sandbox = Sandbox::new();
+    let git = sandbox.git_dir();
+    sandbox.git(&git, &["init", "--quiet", "--initial-branch=main", "."]);
+    write_file(&git, "notes.txt", "one\ntwo\nthree\n");
+    sandbox.git(&git, &["add", "notes.txt"]);
+    sandbox.git(&git, &["commit", "--quiet", "-m", "Start notes"]);
+    sandbox.git(&git, &["tag", "v1.0"]);
+    write_file(&git, "notes.txt", "one\n2\nthree\nfour\n");
+    sandbox.git(&git, &["commit", "--quiet", "-am", "Revise notes"]);
+    sandbox.git(&git, &["tag", "v1.1"]);
+
+    // Cobra opens git's metadata directory directly
+    let metadata = git.join(".git");
+    let metadata = metadata.to_str().unwrap();
+
+    let git_log = sandbox.git(&git, &["log", "--format=%H"]);
+    let cobra_log: Vec<String> = sandbox.cobra(&git, &["-C", metadata, "log"])
+        .lines()
+        .filter_map(|line| line.strip_prefix("commit "))
+        .map(str::to_string)
+        .collect();
+    assert_eq!(cobra_log, git_log.lines().collect::<Vec<_>>());
+
+    assert_eq!(sandbox.cobra(&git, &["-C", metadata, "tag"]), sandbox.git(&git, &["tag", "--list"]));
+
+    // Incremental blame uses git's record format, so the group headers must agree
+    let headers = |output: &str| {
+        let mut headers: Vec<String> = output.lines()
+            .filter(|line| line.split(' ').count() == 4 && line.len() > 40 && line.as_bytes()[40] == b' ')
+            .map(str::to_string)
+            .collect();
+        headers.sort();
+        headers
+    };
+    let cobra_blame = sandbox.cobra(&git, &["-C", metadata, "blame", "--incremental", "notes.txt"]);
+    let git_blame = sandbox.git(&git, &["blame", "--incremental", "notes.txt"]);
+    assert_eq!(headers(&cobra_blame), headers(&git_blame));
+}
+
+/// Serves `repo` from a daemon on a free port and returns its URL
+fn start_daemon(repo: &Path) -> String {
+    let daemon = Daemon::bind("127.0.0.1:0", &[repo.to_path_buf()]).expect("start daemon");
+    let addr = daemon.local_addr().expect("daemon address");
+    thread::spawn(move || daemon.serve());
+    format!("tcp://{}{}", addr, fs::canonicalize(repo).unwrap().display())
+}
+
+#[test]
+fn test_fetch_from_daemon() {
+    let sandbox = Sandbox::new();
+    let upstream = sandbox.cobra_dir();
+    sandbox.cobra(&upstream, &["init", "."]);
+    write_file(&upstream, "src/main.rs", "fn main() {}\n");
+    sandbox.cobra(&upstream, &["add", "src/main.rs"]);
+    sandbox.cobra(&upstream, &["commit", "-m", "Initial commit"]);
+    sandbox.cobra(&upstream, &["branch", "create", "feature"]);
+    sandbox.cobra(&upstream, &["branch", "checkout", "feature"]);
+    write_file(&upstream, "README.md", "# Fetch me\n");
+    sandbox.cobra(&upstream, &["add", "README.md"]);
+    sandbox.cobra(&upstream, &["commit", "-m", "Add readme"]);
+    let url = start_daemon(&upstream);
+
+    let clone = sandbox.extra_dir("clone");
+    sandbox.cobra(&clone, &["init", "."]);
+    let config = clone.join(".cobra/config");
+    let mut contents = fs::read_to_string(&config).unwrap_or_default();
+    contents.push_str(&format!("[remote \"origin\"]\n\turl = {}\n", url));
+    fs::write(&config, contents).unwrap();
+    let output = sandbox.cobra(&clone, &["fetch", "origin"]);
+    assert!(output.contains("[new branch]"), "{}", output);
+
+    for branch in ["main", "feature"] {
+        let read_ref = |path: &Path| fs::read_to_string(path).unwrap().trim().to_string();
+        assert_eq!(
+            read_ref(&clone.join(".cobra/refs/remotes/origin").join(branch)),
+            read_ref(&upstream.join(".cobra/refs/heads").join(branch)),
+        );
+    }
+    assert_eq!(loose_objects(&clone.join(".cobra")), loose_objects(&upstream.join(".cobra")));
+
+    // Up to date, so a second fetch changes nothing
+    assert_eq!(sandbox.cobra(&clone, &["fetch", "origin"]), "");
+
+    // Paths that were not exported are refused
+    let hidden = format!("{}/clone", url.strip_suffix("/cobra").unwrap());
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_cobra"));
+    let output = command.current_dir(&clone).args(["fetch", &hidden]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not exported"));
+}
+
+#[test]
+fn test_git_clones_from_daemon() {
+    require_git!();
+    let sandbox = Sandbox::new();
+    build_history(&sandbox);
+    let url = start_daemon(&sandbox.cobra_dir()).replace("tcp://", "git://");
+
+    let clone = sandbox.extra_dir("clone");
+    sandbox.git(&clone, &["clone", "--quiet", &url, "."]);
+    sandbox.git(&clone, &["fsck", "--strict", "--no-progress"]);
+
+    let mut cloned: Vec<String> = sandbox.git(&clone, &["rev-list", "--objects", "--all"])
+        .lines()
+        .map(str::to_string)
+        .collect();
+    cloned.sort();
+    assert_eq!(cloned, repo_state(|args| sandbox.git_on_cobra(args)).objects);
+    // HEAD follows the symref the daemon advertised
+    assert_eq!(sandbox.git(&clone, &["symbolic-ref", "HEAD"]), "refs/heads/feature\n");
+}